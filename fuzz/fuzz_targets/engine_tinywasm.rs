@@ -0,0 +1,17 @@
+//! Untrusted module bytes handed to `TinywasmEngine::load`.
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use runtime::abi::Imports;
+use runtime::builder::{ConfigurableEngine, EngineConfig};
+use runtime::engines::tinywasm::TinywasmEngine;
+use runtime::Engine;
+
+fuzz_target!(|data: &[u8]| {
+    let mut engine: TinywasmEngine =
+        ConfigurableEngine::from_config(&EngineConfig::default(), Imports::new())
+            .expect("default config");
+    if engine.load(1, data).is_ok() {
+        engine.unload(1);
+    }
+});
@@ -0,0 +1,17 @@
+//! Untrusted module bytes handed to `WasmiEngine::load`.
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use runtime::abi::Imports;
+use runtime::builder::{ConfigurableEngine, EngineConfig};
+use runtime::engines::wasmi::WasmiEngine;
+use runtime::Engine;
+
+fuzz_target!(|data: &[u8]| {
+    let mut engine: WasmiEngine =
+        ConfigurableEngine::from_config(&EngineConfig::default(), Imports::new())
+            .expect("default config");
+    if engine.load(1, data).is_ok() {
+        engine.unload(1);
+    }
+});
@@ -0,0 +1,38 @@
+//! Manifest blobs as they arrive over the radio: SMNY (whole and in place),
+//! SUIT envelopes and the boot-time audit over both.
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use runtime::audit::{self, Keyring};
+use runtime::manifest::{Manifest, ManifestFormat};
+use runtime::suit::SuitManifest;
+
+fuzz_target!(|data: &[u8]| {
+    if let Ok((manifest, module)) = Manifest::parse(data) {
+        assert_eq!(module.len(), manifest.module_len as usize);
+        assert_eq!(manifest.blob_len(), data.len());
+        let _ = manifest.payload();
+        let _ = manifest.allowed_states();
+        let _ = manifest.schedule();
+        let _ = manifest.name();
+        manifest.dependencies().for_each(drop);
+        let _ = audit::check(manifest.module_id, data, &Keyring::new());
+        let _ = audit::check(
+            manifest.module_id,
+            data,
+            &Keyring::new().with_key([0; 32]).require_signatures(),
+        );
+    }
+    for offset in [0, 1, data.len() / 2] {
+        if let Ok((manifest, module)) = Manifest::parse_at(data, offset) {
+            assert_eq!(module.len(), manifest.module_len as usize);
+            assert!(offset + manifest.blob_len() <= data.len());
+        }
+    }
+    if ManifestFormat::detect(data) == Some(ManifestFormat::Suit) {
+        if let Ok((suit, _)) = SuitManifest::parse(data) {
+            let _ = suit.manifest();
+            let _ = suit.verify_ed25519(&[0; 32]);
+        }
+    }
+});
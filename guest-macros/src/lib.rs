@@ -0,0 +1,77 @@
+//! `#[slimmy_export]`, re-exported by guest-wasm's `rpc` feature.
+
+use proc_macro::TokenStream;
+use proc_macro2::Span;
+use quote::{format_ident, quote};
+use syn::{parse_macro_input, FnArg, ItemFn, LitStr};
+
+/// Exports a function taking and returning serde types as an entry point
+/// for `Runtime::call_typed`.
+///
+/// The function stays as written. Its arguments arrive as one postcard
+/// tuple (`()` for none, `(a,)` for one) and its result goes back as
+/// postcard; the export returns `E_INVALID` when the request does not
+/// decode (see `guest_wasm::rpc::serve`). It is exported under the
+/// function's name, or `#[slimmy_export(name = "...")]`.
+#[proc_macro_attribute]
+pub fn slimmy_export(attr: TokenStream, item: TokenStream) -> TokenStream {
+    let mut name: Option<LitStr> = None;
+    let parser = syn::meta::parser(|meta| {
+        if meta.path.is_ident("name") {
+            name = Some(meta.value()?.parse()?);
+            Ok(())
+        } else {
+            Err(meta.error("expected `name = \"...\"`"))
+        }
+    });
+    parse_macro_input!(attr with parser);
+    let func = parse_macro_input!(item as ItemFn);
+    expand(name, func)
+        .unwrap_or_else(syn::Error::into_compile_error)
+        .into()
+}
+
+fn expand(name: Option<LitStr>, func: ItemFn) -> syn::Result<proc_macro2::TokenStream> {
+    let sig = &func.sig;
+    if !sig.generics.params.is_empty() {
+        return Err(syn::Error::new_spanned(
+            &sig.generics,
+            "#[slimmy_export] functions cannot be generic",
+        ));
+    }
+    if let Some(asyncness) = &sig.asyncness {
+        return Err(syn::Error::new_spanned(
+            asyncness,
+            "#[slimmy_export] functions cannot be async",
+        ));
+    }
+    let mut args = Vec::new();
+    let mut types = Vec::new();
+    for (i, input) in sig.inputs.iter().enumerate() {
+        match input {
+            FnArg::Typed(arg) => {
+                args.push(format_ident!("arg{i}"));
+                types.push(&arg.ty);
+            }
+            FnArg::Receiver(receiver) => {
+                return Err(syn::Error::new_spanned(
+                    receiver,
+                    "#[slimmy_export] functions cannot take `self`",
+                ))
+            }
+        }
+    }
+    let ident = &sig.ident;
+    let export = name.unwrap_or_else(|| LitStr::new(&ident.to_string(), Span::call_site()));
+    Ok(quote! {
+        #func
+
+        const _: () = {
+            #[cfg(target_arch = "wasm32")]
+            #[export_name = #export]
+            extern "C" fn __slimmy_export() -> i32 {
+                ::guest_wasm::rpc::serve(|(#(#args,)*): (#(#types,)*)| #ident(#(#args),*))
+            }
+        };
+    })
+}
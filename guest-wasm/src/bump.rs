@@ -0,0 +1,180 @@
+//! Bump allocator for guests (`bump-alloc` feature).
+//!
+//! Allocations are carved upwards from `__heap_base`, growing linear memory
+//! a page at a time. Freeing only gives memory back when it is the most
+//! recent allocation, and growing that one extends it in place, so a `Vec`
+//! being pushed to does not leave copies behind. Everything else is
+//! reclaimed at once by `reset`, which an entry point calls first thing:
+//! the heap then holds one invocation's data, never fragments, and the
+//! allocator adds a few hundred bytes of code.
+//!
+//! The feature also exports `slimmy_alloc(len) -> ptr` (0 when out of
+//! memory) and `slimmy_free(ptr, len)` (`runtime::abi::ALLOC_EXPORT` /
+//! `FREE_EXPORT`), through which the host places payloads in guest memory.
+
+#[cfg(target_arch = "wasm32")]
+use core::alloc::GlobalAlloc;
+use core::alloc::Layout;
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+/// Alignment of buffers handed out by `slimmy_alloc`.
+pub const EXPORT_ALIGN: usize = 8;
+
+/// A bump allocator over the rest of linear memory.
+pub struct BumpAlloc {
+    /// Next free byte; 0 until the first allocation after a reset.
+    next: AtomicUsize,
+}
+
+impl BumpAlloc {
+    pub const fn new() -> Self {
+        Self {
+            next: AtomicUsize::new(0),
+        }
+    }
+
+    /// Frees every allocation at once.
+    ///
+    /// # Safety
+    /// Nothing allocated before may be used afterwards, including values
+    /// kept in statics across invocations.
+    pub unsafe fn reset(&self) {
+        self.next.store(0, Ordering::Relaxed);
+    }
+}
+
+impl Default for BumpAlloc {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Start and end of `layout` placed at or after `next`.
+#[cfg_attr(not(target_arch = "wasm32"), allow(dead_code))]
+fn place(next: usize, layout: Layout) -> Option<(usize, usize)> {
+    let start = next.checked_add(layout.align() - 1)? & !(layout.align() - 1);
+    Some((start, start.checked_add(layout.size())?))
+}
+
+#[cfg(target_arch = "wasm32")]
+mod memory {
+    use core::arch::wasm32::{memory_grow, memory_size};
+
+    const PAGE: usize = 64 * 1024;
+
+    extern "C" {
+        static __heap_base: u8;
+    }
+
+    pub fn heap_base() -> usize {
+        // SAFETY: only the address of the linker-provided symbol is taken.
+        unsafe { core::ptr::addr_of!(__heap_base) as usize }
+    }
+
+    /// Grows memory until `end` fits; false when it cannot.
+    pub fn reserve(end: usize) -> bool {
+        let size = memory_size(0) * PAGE;
+        end <= size || memory_grow(0, (end - size).div_ceil(PAGE)) != usize::MAX
+    }
+}
+
+#[cfg(target_arch = "wasm32")]
+unsafe impl GlobalAlloc for BumpAlloc {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        let next = match self.next.load(Ordering::Relaxed) {
+            0 => memory::heap_base(),
+            next => next,
+        };
+        match place(next, layout) {
+            Some((start, end)) if memory::reserve(end) => {
+                self.next.store(end, Ordering::Relaxed);
+                start as *mut u8
+            }
+            _ => core::ptr::null_mut(),
+        }
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        let start = ptr as usize;
+        if self.next.load(Ordering::Relaxed) == start + layout.size() {
+            self.next.store(start, Ordering::Relaxed);
+        }
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        let start = ptr as usize;
+        if self.next.load(Ordering::Relaxed) == start + layout.size() {
+            let end = start + new_size;
+            if end <= start + layout.size() || memory::reserve(end) {
+                self.next.store(end, Ordering::Relaxed);
+                return ptr;
+            }
+            return core::ptr::null_mut();
+        }
+        let Ok(new_layout) = Layout::from_size_align(new_size, layout.align()) else {
+            return core::ptr::null_mut();
+        };
+        let new = self.alloc(new_layout);
+        if !new.is_null() {
+            core::ptr::copy_nonoverlapping(ptr, new, layout.size().min(new_size));
+        }
+        new
+    }
+}
+
+#[cfg(target_arch = "wasm32")]
+#[global_allocator]
+static ALLOCATOR: BumpAlloc = BumpAlloc::new();
+
+/// Frees everything the global allocator handed out; call it at the start
+/// of each entry point.
+///
+/// # Safety
+/// See `BumpAlloc::reset`.
+#[cfg(target_arch = "wasm32")]
+pub unsafe fn reset() {
+    ALLOCATOR.reset();
+}
+
+/// Buffer of `len` bytes for the host to write into; 0 when out of memory.
+#[cfg(target_arch = "wasm32")]
+#[no_mangle]
+pub extern "C" fn slimmy_alloc(len: i32) -> i32 {
+    if len < 0 {
+        return 0;
+    }
+    let Ok(layout) = Layout::from_size_align(len.max(1) as usize, EXPORT_ALIGN) else {
+        return 0;
+    };
+    // SAFETY: `layout` has a non-zero size.
+    unsafe { ALLOCATOR.alloc(layout) as i32 }
+}
+
+/// Returns a buffer from `slimmy_alloc`.
+#[cfg(target_arch = "wasm32")]
+#[no_mangle]
+pub extern "C" fn slimmy_free(ptr: i32, len: i32) {
+    if ptr == 0 || len < 0 {
+        return;
+    }
+    if let Ok(layout) = Layout::from_size_align(len.max(1) as usize, EXPORT_ALIGN) {
+        // SAFETY: the host passes back what `slimmy_alloc` returned; a bump
+        // allocator ignores anything but its latest allocation anyway.
+        unsafe { ALLOCATOR.dealloc(ptr as *mut u8, layout) }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn places_aligned_and_refuses_overflow() {
+        let layout = |size, align| Layout::from_size_align(size, align).unwrap();
+        assert_eq!(place(1024, layout(3, 1)), Some((1024, 1027)));
+        assert_eq!(place(1027, layout(8, 8)), Some((1032, 1040)));
+        assert_eq!(place(1040, layout(0, 16)), Some((1040, 1040)));
+        assert_eq!(place(usize::MAX - 2, layout(1, 8)), None);
+        assert_eq!(place(usize::MAX - 2, layout(4, 1)), None);
+    }
+}
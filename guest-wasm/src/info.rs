@@ -0,0 +1,84 @@
+//! `__slimmy_info`: the module describes itself to the host.
+//!
+//! `slimmy_info!` defines the export, which sends the ABI version this
+//! crate targets and the listed imports and entries through the
+//! `describe_module` import (`runtime::describe` has the layout). The host
+//! checks them against the manifest and the capability grants at install:
+//!
+//! ```ignore
+//! guest_wasm::slimmy_info!(imports: ["log", "i2c_read"], entries: ["main"]);
+//! ```
+
+use crate::ABI_VERSION;
+
+/// Descriptor layout version (`runtime::describe::DESCRIPTOR_VERSION`).
+pub const DESCRIPTOR_VERSION: u16 = 1;
+/// Largest descriptor `describe` sends.
+pub const MAX_DESCRIPTOR_LEN: usize = 512;
+
+/// Encodes a descriptor into `buf`; `None` when it does not fit, or a
+/// count or name is over 255.
+pub fn encode(imports: &[&str], entries: &[&str], buf: &mut [u8]) -> Option<usize> {
+    let mut len = 0;
+    let mut put = |bytes: &[u8]| {
+        buf.get_mut(len..len + bytes.len())?.copy_from_slice(bytes);
+        len += bytes.len();
+        Some(())
+    };
+    put(&DESCRIPTOR_VERSION.to_le_bytes())?;
+    put(&ABI_VERSION.to_le_bytes())?;
+    put(&[
+        u8::try_from(imports.len()).ok()?,
+        u8::try_from(entries.len()).ok()?,
+    ])?;
+    for name in imports.iter().chain(entries) {
+        put(&[u8::try_from(name.len()).ok()?])?;
+        put(name.as_bytes())?;
+    }
+    Some(len)
+}
+
+/// Body of `__slimmy_info`: sends the descriptor and returns the import's
+/// status, or `E_INVALID` (-1) when it does not fit `MAX_DESCRIPTOR_LEN`.
+#[cfg(target_arch = "wasm32")]
+pub fn describe(imports: &[&str], entries: &[&str]) -> i32 {
+    #[link(wasm_import_module = "env")]
+    extern "C" {
+        fn describe_module(ptr: i32, len: i32) -> i32;
+    }
+
+    let mut buf = [0u8; MAX_DESCRIPTOR_LEN];
+    match encode(imports, entries, &mut buf) {
+        // SAFETY: the host only reads the `len` bytes at `ptr`.
+        Some(len) => unsafe { describe_module(buf.as_ptr() as i32, len as i32) },
+        None => -1,
+    }
+}
+
+/// Defines the `__slimmy_info` export from lists of string literals.
+#[macro_export]
+macro_rules! slimmy_info {
+    (imports: [$($import:expr),* $(,)?], entries: [$($entry:expr),* $(,)?] $(,)?) => {
+        #[cfg(target_arch = "wasm32")]
+        #[export_name = "__slimmy_info"]
+        extern "C" fn __slimmy_info() -> i32 {
+            $crate::info::describe(&[$($import),*], &[$($entry),*])
+        }
+    };
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn encodes_the_host_layout() {
+        let mut buf = [0u8; 32];
+        let len = encode(&["log"], &["main", "health"], &mut buf).unwrap();
+        assert_eq!(
+            &buf[..len],
+            b"\x01\x00\x01\x00\x01\x02\x03log\x04main\x06health"
+        );
+        assert_eq!(encode(&["log"], &["main"], &mut buf[..8]), None);
+    }
+}
@@ -1,12 +1,50 @@
+//! Guest-side support for slimmy modules, and the demo module itself.
+//!
+//! Guests depend on this crate with `default-features = false` and the
+//! features they need; `template/` is a crate to copy. Build them with
+//! `slimmy-guest-build` (in `packer`), which picks the target and a
+//! size-tuned profile and strips the result ready for packing.
+
 #![no_std]
 
-/// Minimal entry point for wasm3 demo: no args, no return, no imports.
+// Workspace builds compile the cdylib for the host too; std brings the
+// panic runtime unwinding needs there.
+#[cfg(not(target_arch = "wasm32"))]
+extern crate std;
+
+#[cfg(feature = "rpc")]
+extern crate alloc;
+
+#[cfg(feature = "bump-alloc")]
+pub mod bump;
+pub mod info;
+#[cfg(feature = "rpc")]
+pub mod rpc;
+pub mod status;
+
+#[cfg(feature = "rpc")]
+pub use guest_macros::slimmy_export;
+
+/// Host ABI version this crate is written against (`runtime::abi::ABI_VERSION`).
+pub const ABI_VERSION: u16 = 1;
+
+/// Declares `ABI_VERSION` to the host, which refuses modules built for one
+/// it does not speak (`runtime::abi::version`).
 #[cfg(target_arch = "wasm32")]
+#[link_section = "slimmy.abi"]
+#[used]
+static ABI_SECTION: [u8; 2] = ABI_VERSION.to_le_bytes();
+
+/// Minimal entry point for wasm3 demo: no args, no return, no imports.
+#[cfg(all(feature = "demo", target_arch = "wasm32"))]
 #[no_mangle]
 pub extern "C" fn main() {}
 
 /// Abort-on-panic for no_std wasm builds.
-#[cfg_attr(not(test), panic_handler)]
+#[cfg(all(feature = "panic-handler", target_arch = "wasm32"))]
+#[panic_handler]
 fn panic(_: &core::panic::PanicInfo) -> ! {
-    loop {}
+    loop {
+        core::hint::spin_loop();
+    }
 }
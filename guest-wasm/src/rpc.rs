@@ -0,0 +1,142 @@
+//! Typed exports (`rpc` feature).
+//!
+//! `#[slimmy_export]` turns a function taking and returning serde types into
+//! an entry point for `Runtime::call_typed` (see `runtime::rpc`): its body is
+//! `serve`, which reads the request through the `rpc_request` import, decodes
+//! it as the argument tuple, calls the function and hands the encoded result
+//! to `rpc_response`. Payloads use `runtime::codec::postcard`.
+//!
+//! Decoding needs a heap, so a guest also needs a global allocator; with
+//! `bump-alloc`, `serve` resets it first thing, as entry points should.
+
+use alloc::vec::Vec;
+use runtime::abi::{E_DEVICE, E_INVALID};
+use runtime::codec::postcard;
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+
+/// The host side of a typed call.
+trait Host {
+    /// Copies the request into `buf` when it fits; its length or a status.
+    fn request(&mut self, buf: &mut [u8]) -> i32;
+    fn respond(&mut self, response: &[u8]) -> i32;
+}
+
+#[cfg(target_arch = "wasm32")]
+struct Imports;
+
+#[cfg(target_arch = "wasm32")]
+#[link(wasm_import_module = "env")]
+extern "C" {
+    fn rpc_request(ptr: i32, cap: i32) -> i32;
+    fn rpc_response(ptr: i32, len: i32) -> i32;
+}
+
+#[cfg(target_arch = "wasm32")]
+impl Host for Imports {
+    fn request(&mut self, buf: &mut [u8]) -> i32 {
+        // SAFETY: the host writes at most `cap` bytes at `ptr`.
+        unsafe { rpc_request(buf.as_mut_ptr() as i32, buf.len() as i32) }
+    }
+
+    fn respond(&mut self, response: &[u8]) -> i32 {
+        // SAFETY: the host only reads the `len` bytes at `ptr`.
+        unsafe { rpc_response(response.as_ptr() as i32, response.len() as i32) }
+    }
+}
+
+/// Body of a `#[slimmy_export]` entry point: runs `f` on the decoded
+/// request and responds with its result. Returns 0, `E_INVALID` when the
+/// request does not decode, `E_DEVICE` when the heap runs out, or the
+/// status of a failed import (`E_EMPTY` outside a typed call).
+#[cfg(target_arch = "wasm32")]
+pub fn serve<Req, Resp>(f: impl FnOnce(Req) -> Resp) -> i32
+where
+    Req: DeserializeOwned,
+    Resp: Serialize,
+{
+    // SAFETY: this is the start of an entry point; `bump::reset` documents
+    // that bump-alloc guests keep no heap data across invocations.
+    #[cfg(feature = "bump-alloc")]
+    unsafe {
+        crate::bump::reset()
+    };
+    handle(&mut Imports, f)
+}
+
+fn handle<Req, Resp>(host: &mut impl Host, f: impl FnOnce(Req) -> Resp) -> i32
+where
+    Req: DeserializeOwned,
+    Resp: Serialize,
+{
+    let len = host.request(&mut []);
+    let Ok(size) = usize::try_from(len) else {
+        return len;
+    };
+    let mut request = Vec::new();
+    if request.try_reserve_exact(size).is_err() {
+        return E_DEVICE;
+    }
+    request.resize(size, 0);
+    if host.request(&mut request) != len {
+        return E_INVALID;
+    }
+    let Ok(request) = postcard::from_bytes(&request) else {
+        return E_INVALID;
+    };
+    match postcard::to_vec(&f(request)) {
+        Ok(response) => host.respond(&response),
+        Err(_) => E_DEVICE,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloc::string::String;
+    use runtime::abi::{E_EMPTY, OK};
+
+    struct Fake {
+        request: Option<Vec<u8>>,
+        response: Vec<u8>,
+    }
+
+    impl Host for Fake {
+        fn request(&mut self, buf: &mut [u8]) -> i32 {
+            let Some(request) = &self.request else {
+                return E_EMPTY;
+            };
+            if request.len() <= buf.len() {
+                buf[..request.len()].copy_from_slice(request);
+            }
+            request.len() as i32
+        }
+
+        fn respond(&mut self, response: &[u8]) -> i32 {
+            self.response = response.to_vec();
+            OK
+        }
+    }
+
+    fn repeat((word, times): (String, u8)) -> Vec<String> {
+        (0..times).map(|_| word.clone()).collect()
+    }
+
+    #[test]
+    fn serves_decoded_requests_and_encodes_results() {
+        let mut host = Fake {
+            request: Some(postcard::to_vec(&("ab", 2u8)).unwrap()),
+            response: Vec::new(),
+        };
+        assert_eq!(handle(&mut host, repeat), OK);
+        assert_eq!(
+            postcard::from_bytes::<Vec<String>>(&host.response).unwrap(),
+            ["ab", "ab"]
+        );
+
+        host.request = Some(alloc::vec![1, b'a']);
+        assert_eq!(handle(&mut host, repeat), E_INVALID);
+        host.request = None;
+        assert_eq!(handle(&mut host, repeat), E_EMPTY);
+    }
+}
@@ -0,0 +1,79 @@
+//! Entry point statuses (`runtime::ExitCode` on the host).
+//!
+//! An entry point returning `i32` succeeds with 0 or a positive exit code
+//! and reports a failure it handled itself with a negative status, which
+//! the host's `Runtime::execute_exit` returns as `Error::GuestError(code)`
+//! rather than treating it as a trap. `status` turns an entry's `Result`
+//! into that value:
+//!
+//! ```ignore
+//! const NO_SENSOR: Failure = Failure::new(-10);
+//!
+//! #[no_mangle]
+//! pub extern "C" fn sample() -> i32 {
+//!     status(read_sensor().ok_or(NO_SENSOR).map(|_| ()))
+//! }
+//! ```
+
+/// Status of an entry point that succeeded without a code.
+pub const SUCCESS: i32 = 0;
+
+/// A failure the module reports, carried as its negative status.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Failure(i32);
+
+impl Failure {
+    /// Panics unless `code` is negative (at compile time in a `const`).
+    pub const fn new(code: i32) -> Self {
+        assert!(code < 0, "failure statuses are negative");
+        Self(code)
+    }
+
+    pub const fn code(self) -> i32 {
+        self.0
+    }
+}
+
+/// Success values an entry point can return as its exit code.
+pub trait ExitStatus {
+    fn exit_code(self) -> i32;
+}
+
+impl ExitStatus for () {
+    fn exit_code(self) -> i32 {
+        SUCCESS
+    }
+}
+
+impl ExitStatus for u8 {
+    fn exit_code(self) -> i32 {
+        self.into()
+    }
+}
+
+impl ExitStatus for u16 {
+    fn exit_code(self) -> i32 {
+        self.into()
+    }
+}
+
+/// The status an entry point returns for `result`.
+pub fn status<T: ExitStatus>(result: Result<T, Failure>) -> i32 {
+    match result {
+        Ok(value) => value.exit_code(),
+        Err(failure) => failure.code(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn results_map_to_signed_statuses() {
+        const BUSY: Failure = Failure::new(-4);
+        assert_eq!(status(Ok(())), SUCCESS);
+        assert_eq!(status(Ok(300u16)), 300);
+        assert_eq!(status::<u8>(Err(BUSY)), -4);
+    }
+}
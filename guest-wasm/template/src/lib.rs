@@ -0,0 +1,8 @@
+#![no_std]
+
+// Links guest-wasm's panic handler.
+use guest_wasm as _;
+
+/// Entry point named in the manifest (`packer --entry main`).
+#[no_mangle]
+pub extern "C" fn main() {}
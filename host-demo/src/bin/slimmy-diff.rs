@@ -0,0 +1,146 @@
+//! Differential checker: runs one module on wasm3 and wasmtime with the same
+//! inputs (message ABI, see `runtime::diff`) and reports any divergence in
+//! host calls, guest-memory effects, outputs or outcome.
+//!
+//! Needs the `diff` feature (wasm3 + wasmtime-lite). Exits non-zero when the
+//! engines disagree.
+
+use clap::Parser;
+use runtime::abi::Imports;
+use runtime::diff::{Differential, Probe, Run, MAX_MESSAGE_LEN};
+use runtime::engines::wasm3::{Wasm3Engine, DEFAULT_STACK_SLOTS};
+use runtime::engines::wasmtime_lite::WasmtimeLiteEngine;
+use std::fs;
+use std::path::PathBuf;
+
+#[derive(Parser, Debug)]
+#[command(
+    name = "slimmy-diff",
+    about = "Run a module on wasm3 and wasmtime and report divergences."
+)]
+struct Args {
+    /// Path to the .wasm module
+    module: PathBuf,
+
+    /// Entry invoked for every input
+    #[arg(short, long, default_value = "main")]
+    entry: String,
+
+    /// Hex-encoded input passed to `msg_input` (repeatable)
+    #[arg(long, value_name = "HEX")]
+    input: Vec<String>,
+
+    /// File whose bytes are an input (repeatable)
+    #[arg(long, value_name = "PATH")]
+    input_file: Vec<PathBuf>,
+
+    /// Additionally generate this many pseudo-random inputs
+    #[arg(long, default_value_t = 0)]
+    random: usize,
+
+    /// Longest generated input in bytes
+    #[arg(long, default_value_t = 64)]
+    max_len: usize,
+
+    /// Seed for generated inputs
+    #[arg(long, default_value_t = 1)]
+    seed: u64,
+}
+
+fn main() -> Result<(), Box<dyn std::error::Error>> {
+    let args = Args::parse();
+    let module = fs::read(&args.module)?;
+    let inputs = collect_inputs(&args)?;
+
+    let left_probe = Probe::new();
+    let mut left = Wasm3Engine::new(DEFAULT_STACK_SLOTS).map_err(to_io_error)?;
+    left.set_imports(Imports::new().with(left_probe.imports()));
+    let right_probe = Probe::new();
+    let mut right = WasmtimeLiteEngine::new().map_err(to_io_error)?;
+    right.set_imports(Imports::new().with(right_probe.imports()));
+
+    let mut diff = Differential::new(left, left_probe, right, right_probe);
+    let report = diff.check(&module, &args.entry, &inputs);
+
+    for divergence in &report.divergences {
+        println!("❌ {divergence}");
+        println!("   input : {}", hex::encode(&inputs[divergence.input]));
+        print_run("wasm3", &divergence.left);
+        print_run("wasmtime", &divergence.right);
+    }
+    if !report.is_clean() {
+        return Err(format!(
+            "{} of {} inputs diverged",
+            report.divergences.len(),
+            report.runs
+        )
+        .into());
+    }
+    println!(
+        "✅ wasm3 and wasmtime agree on {} input(s) for entry `{}`",
+        report.runs, args.entry
+    );
+    Ok(())
+}
+
+fn print_run(engine: &str, run: &Run) {
+    println!(
+        "   {engine:<9}: {:?}, {} host call(s), output {}",
+        run.outcome,
+        run.calls.len(),
+        hex::encode(run.output())
+    );
+}
+
+fn collect_inputs(args: &Args) -> Result<Vec<Vec<u8>>, Box<dyn std::error::Error>> {
+    let mut inputs = Vec::new();
+    for hex in &args.input {
+        inputs.push(hex::decode(hex.trim()).map_err(|_| format!("--input {hex}: not valid hex"))?);
+    }
+    for path in &args.input_file {
+        inputs.push(fs::read(path)?);
+    }
+    inputs.extend(random_inputs(
+        args.random,
+        args.max_len.min(MAX_MESSAGE_LEN),
+        args.seed,
+    ));
+    if inputs.is_empty() {
+        inputs.push(Vec::new());
+    }
+    Ok(inputs)
+}
+
+/// xorshift64-generated inputs of length 0..=max_len.
+fn random_inputs(count: usize, max_len: usize, seed: u64) -> Vec<Vec<u8>> {
+    let mut state = seed.max(1);
+    let mut next = move || {
+        state ^= state << 13;
+        state ^= state >> 7;
+        state ^= state << 17;
+        state
+    };
+    (0..count)
+        .map(|_| {
+            let len = (next() % (max_len as u64 + 1)) as usize;
+            (0..len).map(|_| next() as u8).collect()
+        })
+        .collect()
+}
+
+fn to_io_error(err: runtime::Error) -> std::io::Error {
+    std::io::Error::other(format!("runtime error: {err}"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::random_inputs;
+
+    #[test]
+    fn random_inputs_are_bounded_and_repeatable() {
+        let inputs = random_inputs(20, 8, 3);
+        assert_eq!(inputs.len(), 20);
+        assert!(inputs.iter().all(|input| input.len() <= 8));
+        assert_eq!(inputs, random_inputs(20, 8, 3));
+    }
+}
@@ -0,0 +1,619 @@
+//! Soak/stress harness: runs a weighted mix of installs, invocations, cache
+//! churn and injected faults against the enabled engine and a chosen store for
+//! a fixed duration, reporting throughput, error rates and heap growth.
+//!
+//! Exits non-zero when clean operations fail more often than `--max-error-rate`
+//! or the heap grows by more than `--max-growth` bytes after warm-up.
+
+use clap::Parser;
+use runtime::storage::{FileFlash, FlashIo, MemoryFlash};
+use runtime::{CachedEngine, Engine, MemoryStore, ModuleId, ModuleSource, Runtime};
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::fs;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::{Duration, Instant};
+
+#[cfg(all(feature = "wasm3", feature = "wasmtime", not(feature = "diff")))]
+compile_error!("Select only one engine feature at a time: wasm3 or wasmtime-lite/-winch.");
+
+/// Heap accounting so the soak can spot leaks without external tooling.
+struct CountingAlloc;
+
+static LIVE_BYTES: AtomicUsize = AtomicUsize::new(0);
+static PEAK_BYTES: AtomicUsize = AtomicUsize::new(0);
+
+unsafe impl GlobalAlloc for CountingAlloc {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        let ptr = System.alloc(layout);
+        if !ptr.is_null() {
+            let live = LIVE_BYTES.fetch_add(layout.size(), Ordering::Relaxed) + layout.size();
+            PEAK_BYTES.fetch_max(live, Ordering::Relaxed);
+        }
+        ptr
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout);
+        LIVE_BYTES.fetch_sub(layout.size(), Ordering::Relaxed);
+    }
+}
+
+#[global_allocator]
+static ALLOC: CountingAlloc = CountingAlloc;
+
+#[derive(Parser, Debug)]
+#[command(
+    name = "slimmy-soak",
+    about = "Long-running install/invoke/fault soak for slimmy engines and stores."
+)]
+struct Args {
+    /// .wasm modules to cycle through (required for real engines)
+    #[arg(value_name = "MODULE")]
+    modules: Vec<PathBuf>,
+
+    /// Entry invoked on every module
+    #[arg(short, long, default_value = "main")]
+    entry: String,
+
+    /// How long to run, in seconds
+    #[arg(long, default_value_t = 60)]
+    duration: u64,
+
+    /// Stop after this many operations (default: run for --duration)
+    #[arg(long)]
+    iterations: Option<u64>,
+
+    /// Number of module slots (ids 1..=N) kept installed
+    #[arg(long, default_value_t = 4)]
+    slots: u32,
+
+    /// Operation weights, e.g. `install=1,invoke=8,churn=1,fault=1`
+    #[arg(long, default_value = "install=1,invoke=8,churn=1,fault=1")]
+    mix: String,
+
+    /// Store backend: `memory`, `flash` (RAM-emulated) or `file:PATH` (file-emulated flash)
+    #[arg(long, default_value = "memory")]
+    store: String,
+
+    /// Seed for the operation sequence (same seed = same sequence)
+    #[arg(long, default_value_t = 1)]
+    seed: u64,
+
+    /// Print a progress line every N seconds
+    #[arg(long, default_value_t = 10)]
+    report_every: u64,
+
+    /// Operations to run before taking the heap baseline
+    #[arg(long, default_value_t = 1000)]
+    warmup: u64,
+
+    /// Fail when the live heap grows by more than this many bytes after warm-up
+    #[arg(long)]
+    max_growth: Option<usize>,
+
+    /// Fail when clean operations fail more often than this fraction
+    #[arg(long, default_value_t = 0.0)]
+    max_error_rate: f64,
+}
+
+/// Relative weights of each operation kind.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Mix {
+    install: u32,
+    invoke: u32,
+    churn: u32,
+    fault: u32,
+}
+
+impl Mix {
+    fn parse(spec: &str) -> Result<Self, String> {
+        let mut mix = Mix {
+            install: 0,
+            invoke: 0,
+            churn: 0,
+            fault: 0,
+        };
+        for part in spec.split(',').map(str::trim).filter(|p| !p.is_empty()) {
+            let (name, weight) = part
+                .split_once('=')
+                .ok_or_else(|| format!("invalid --mix entry `{part}` (want name=weight)"))?;
+            let weight = weight
+                .parse::<u32>()
+                .map_err(|_| format!("invalid --mix weight in `{part}`"))?;
+            match name {
+                "install" => mix.install = weight,
+                "invoke" => mix.invoke = weight,
+                "churn" => mix.churn = weight,
+                "fault" => mix.fault = weight,
+                _ => return Err(format!("unknown --mix operation `{name}`")),
+            }
+        }
+        if mix.total() == 0 {
+            return Err("--mix weights sum to zero".into());
+        }
+        Ok(mix)
+    }
+
+    fn total(&self) -> u32 {
+        self.install + self.invoke + self.churn + self.fault
+    }
+
+    fn pick(&self, roll: u32) -> Op {
+        let mut roll = roll % self.total();
+        for (weight, op) in [
+            (self.install, Op::Install),
+            (self.invoke, Op::Invoke),
+            (self.churn, Op::Churn),
+        ] {
+            if roll < weight {
+                return op;
+            }
+            roll -= weight;
+        }
+        Op::Fault
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Op {
+    Install,
+    Invoke,
+    Churn,
+    Fault,
+}
+
+/// xorshift64*: deterministic and dependency-free.
+struct Rng(u64);
+
+impl Rng {
+    fn new(seed: u64) -> Self {
+        Self(seed.max(1))
+    }
+
+    fn next(&mut self) -> u64 {
+        self.0 ^= self.0 >> 12;
+        self.0 ^= self.0 << 25;
+        self.0 ^= self.0 >> 27;
+        self.0.wrapping_mul(0x2545_F491_4F6C_DD1D)
+    }
+
+    fn below(&mut self, n: u32) -> u32 {
+        (self.next() >> 32) as u32 % n.max(1)
+    }
+}
+
+/// A store the soak can install into.
+trait SoakStore: ModuleSource {
+    fn install(&mut self, id: ModuleId, bytes: &[u8]) -> runtime::Result<()>;
+}
+
+impl SoakStore for MemoryStore {
+    fn install(&mut self, id: ModuleId, bytes: &[u8]) -> runtime::Result<()> {
+        self.upsert(id, bytes);
+        Ok(())
+    }
+}
+
+/// Fixed-size slots on emulated flash; installs write through and read back
+/// into a RAM copy, exercising the flash path on every install.
+struct FlashSlots<IO> {
+    io: IO,
+    slot_len: usize,
+    cache: Vec<(ModuleId, Vec<u8>)>,
+}
+
+impl<IO: FlashIo> FlashSlots<IO> {
+    fn new(io: IO, slots: u32) -> Self {
+        let slot_len = io.capacity() / (slots as usize + 1).max(1);
+        Self {
+            io,
+            slot_len,
+            cache: Vec::new(),
+        }
+    }
+}
+
+impl<IO: FlashIo> ModuleSource for FlashSlots<IO> {
+    fn fetch(&self, id: ModuleId) -> Option<&[u8]> {
+        self.cache
+            .iter()
+            .find(|(cached, _)| *cached == id)
+            .map(|(_, bytes)| bytes.as_slice())
+    }
+}
+
+impl<IO: FlashIo> SoakStore for FlashSlots<IO> {
+    fn install(&mut self, id: ModuleId, bytes: &[u8]) -> runtime::Result<()> {
+        if bytes.len() > self.slot_len {
+            return Err(runtime::Error::Engine("flash slot too small"));
+        }
+        let slot = (id as usize) % (self.io.capacity() / self.slot_len);
+        self.io.erase_write(slot * self.slot_len, bytes)?;
+        let mut readback = vec![0u8; bytes.len()];
+        self.io.read(slot * self.slot_len, &mut readback)?;
+        match self.cache.iter_mut().find(|(cached, _)| *cached == id) {
+            Some((_, existing)) => *existing = readback,
+            None => self.cache.push((id, readback)),
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+struct Counts {
+    ops: u64,
+    installs: u64,
+    invokes: u64,
+    churns: u64,
+    faults: u64,
+    /// Clean operations that failed.
+    errors: u64,
+    /// Injected faults the runtime rejected with an error.
+    faults_rejected: u64,
+    /// Injected faults that ran anyway (e.g. a bit flip in a data section).
+    faults_tolerated: u64,
+}
+
+impl Counts {
+    fn clean_ops(&self) -> u64 {
+        self.ops - self.faults
+    }
+
+    fn error_rate(&self) -> f64 {
+        if self.clean_ops() == 0 {
+            0.0
+        } else {
+            self.errors as f64 / self.clean_ops() as f64
+        }
+    }
+}
+
+struct Soak<'a, E, S>
+where
+    E: Engine<Context = ()>,
+    E::ModuleHandle: PartialEq,
+{
+    runtime: Runtime<CachedEngine<E>, S>,
+    pool: &'a [Vec<u8>],
+    entry: &'a str,
+    slots: u32,
+    mix: Mix,
+    rng: Rng,
+    counts: Counts,
+}
+
+impl<'a, E, S> Soak<'a, E, S>
+where
+    E: Engine<Context = ()>,
+    E::ModuleHandle: PartialEq,
+    S: SoakStore,
+{
+    fn new(engine: E, store: S, pool: &'a [Vec<u8>], mix: Mix, args: &'a Args) -> Self {
+        Self {
+            runtime: Runtime::new(CachedEngine::new(engine), store),
+            pool,
+            entry: &args.entry,
+            slots: args.slots.max(1),
+            mix,
+            rng: Rng::new(args.seed),
+            counts: Counts::default(),
+        }
+    }
+
+    /// Installs every slot so invocations always have a target.
+    fn prime(&mut self) -> runtime::Result<()> {
+        for id in 1..=self.slots {
+            let bytes = &self.pool[id as usize % self.pool.len()];
+            self.runtime.source_mut().install(id, bytes)?;
+        }
+        Ok(())
+    }
+
+    fn random_slot(&mut self) -> ModuleId {
+        1 + self.rng.below(self.slots)
+    }
+
+    fn step(&mut self) {
+        self.counts.ops += 1;
+        let op = self.mix.pick(self.rng.below(u32::MAX));
+        let result = match op {
+            Op::Install => {
+                self.counts.installs += 1;
+                let id = self.random_slot();
+                let bytes = &self.pool[self.rng.below(self.pool.len() as u32) as usize];
+                self.runtime.engine().evict(id);
+                self.runtime.source_mut().install(id, bytes)
+            }
+            Op::Invoke => {
+                self.counts.invokes += 1;
+                let id = self.random_slot();
+                self.runtime.execute(id, self.entry, &mut ())
+            }
+            Op::Churn => {
+                self.counts.churns += 1;
+                let id = self.random_slot();
+                self.runtime.engine().evict(id);
+                Ok(())
+            }
+            Op::Fault => {
+                self.counts.faults += 1;
+                if self.inject_fault().is_err() {
+                    self.counts.faults_rejected += 1;
+                } else {
+                    self.counts.faults_tolerated += 1;
+                }
+                Ok(())
+            }
+        };
+        if result.is_err() {
+            self.counts.errors += 1;
+        }
+    }
+
+    /// Runs one fault: a corrupted module (in a dedicated slot), a missing
+    /// entry, or a missing module.
+    fn inject_fault(&mut self) -> runtime::Result<()> {
+        let fault_slot = self.slots + 1;
+        match self.rng.below(3) {
+            0 => {
+                let mut bytes = self.pool[self.rng.below(self.pool.len() as u32) as usize].clone();
+                if !bytes.is_empty() {
+                    let at = self.rng.below(bytes.len() as u32) as usize;
+                    bytes[at] ^= 1 << self.rng.below(8);
+                    let keep = self.rng.below(bytes.len() as u32 + 1) as usize;
+                    bytes.truncate(keep.max(at + 1));
+                }
+                self.runtime.engine().evict(fault_slot);
+                self.runtime.source_mut().install(fault_slot, &bytes)?;
+                self.runtime.execute(fault_slot, self.entry, &mut ())
+            }
+            1 => {
+                let id = self.random_slot();
+                self.runtime.execute(id, "__slimmy_soak_missing__", &mut ())
+            }
+            _ => self.runtime.execute(ModuleId::MAX, self.entry, &mut ()),
+        }
+    }
+}
+
+fn run<E, S>(engine: E, store: S, pool: &[Vec<u8>], args: &Args) -> Result<Counts, String>
+where
+    E: Engine<Context = ()>,
+    E::ModuleHandle: PartialEq,
+    S: SoakStore,
+{
+    let mix = Mix::parse(&args.mix)?;
+    let mut soak = Soak::new(engine, store, pool, mix, args);
+    soak.prime()
+        .map_err(|e| format!("priming slots failed: {e}"))?;
+
+    let started = Instant::now();
+    let deadline = Duration::from_secs(args.duration);
+    let report_every = Duration::from_secs(args.report_every.max(1));
+    let mut next_report = report_every;
+    let mut baseline = None;
+
+    while started.elapsed() < deadline
+        && args.iterations.is_none_or(|limit| soak.counts.ops < limit)
+    {
+        soak.step();
+        if soak.counts.ops == args.warmup {
+            baseline = Some(LIVE_BYTES.load(Ordering::Relaxed));
+        }
+        if started.elapsed() >= next_report {
+            next_report += report_every;
+            report(
+                &soak.counts,
+                started.elapsed(),
+                baseline,
+                soak.runtime.engine().cached_len(),
+            );
+        }
+    }
+    report(
+        &soak.counts,
+        started.elapsed(),
+        baseline,
+        soak.runtime.engine().cached_len(),
+    );
+
+    let counts = soak.counts;
+    if counts.error_rate() > args.max_error_rate {
+        return Err(format!(
+            "error rate {:.4} exceeds --max-error-rate {}",
+            counts.error_rate(),
+            args.max_error_rate
+        ));
+    }
+    if let (Some(limit), Some(baseline)) = (args.max_growth, baseline) {
+        let growth = LIVE_BYTES.load(Ordering::Relaxed).saturating_sub(baseline);
+        if growth > limit {
+            return Err(format!(
+                "heap grew by {growth} bytes after warm-up (limit {limit})"
+            ));
+        }
+    }
+    Ok(counts)
+}
+
+fn report(counts: &Counts, elapsed: Duration, baseline: Option<usize>, cached: usize) {
+    let live = LIVE_BYTES.load(Ordering::Relaxed);
+    let growth = baseline.map_or_else(
+        || "n/a".to_string(),
+        |base| format!("{:+}", live as i64 - base as i64),
+    );
+    println!(
+        "⏱  {:>6.0}s ops={} ({:.0}/s) install={} invoke={} churn={} fault={} (rejected={} tolerated={}) errors={} ({:.4}) cached={} heap={}B peak={}B growth={}",
+        elapsed.as_secs_f64(),
+        counts.ops,
+        counts.ops as f64 / elapsed.as_secs_f64().max(1e-9),
+        counts.installs,
+        counts.invokes,
+        counts.churns,
+        counts.faults,
+        counts.faults_rejected,
+        counts.faults_tolerated,
+        counts.errors,
+        counts.error_rate(),
+        cached,
+        live,
+        PEAK_BYTES.load(Ordering::Relaxed),
+        growth
+    );
+}
+
+fn run_with_store<E>(engine: E, pool: &[Vec<u8>], args: &Args) -> Result<Counts, String>
+where
+    E: Engine<Context = ()>,
+    E::ModuleHandle: PartialEq,
+{
+    let largest = pool.iter().map(Vec::len).max().unwrap_or(0);
+    let capacity = (largest * 2).max(4096) * (args.slots as usize + 2);
+    match args.store.as_str() {
+        "memory" => run(engine, MemoryStore::new(), pool, args),
+        "flash" => run(
+            engine,
+            FlashSlots::new(MemoryFlash::new(capacity), args.slots + 1),
+            pool,
+            args,
+        ),
+        other => match other.strip_prefix("file:") {
+            Some(path) => {
+                let flash = FileFlash::new(PathBuf::from(path), capacity)
+                    .map_err(|e| format!("opening {path}: {e}"))?;
+                run(engine, FlashSlots::new(flash, args.slots + 1), pool, args)
+            }
+            None => Err(format!(
+                "unknown --store `{other}` (memory, flash, file:PATH)"
+            )),
+        },
+    }
+}
+
+#[cfg(feature = "wasm3")]
+fn run_engine(pool: &[Vec<u8>], args: &Args) -> Result<Counts, String> {
+    use runtime::engines::wasm3::{Wasm3Engine, DEFAULT_STACK_SLOTS};
+
+    let engine = Wasm3Engine::new(DEFAULT_STACK_SLOTS).map_err(|e| e.to_string())?;
+    run_with_store(engine, pool, args)
+}
+
+#[cfg(all(feature = "wasmtime", not(feature = "wasm3")))]
+fn run_engine(pool: &[Vec<u8>], args: &Args) -> Result<Counts, String> {
+    use runtime::engines::wasmtime_lite::WasmtimeLiteEngine;
+
+    let engine = WasmtimeLiteEngine::new().map_err(|e| e.to_string())?;
+    run_with_store(engine, pool, args)
+}
+
+#[cfg(not(any(feature = "wasm3", feature = "wasmtime")))]
+fn run_engine(pool: &[Vec<u8>], args: &Args) -> Result<Counts, String> {
+    run_with_store(NoopEngine, pool, args)
+}
+
+/// Stand-in engine so the harness itself (stores, cache, accounting) can be
+/// soaked without a WASM backend; rejects empty modules only.
+#[cfg(not(any(feature = "wasm3", feature = "wasmtime")))]
+struct NoopEngine;
+
+#[cfg(not(any(feature = "wasm3", feature = "wasmtime")))]
+impl Engine for NoopEngine {
+    type ModuleHandle = ModuleId;
+    type Context = ();
+
+    fn load(&mut self, id: ModuleId, module: &[u8]) -> runtime::Result<ModuleId> {
+        if module.is_empty() {
+            return Err(runtime::Error::Engine("module is empty"));
+        }
+        Ok(id)
+    }
+
+    fn invoke(&mut self, _handle: ModuleId, _entry: &str, _ctx: &mut ()) -> runtime::Result<()> {
+        Ok(())
+    }
+}
+
+fn load_pool(args: &Args) -> Result<Vec<Vec<u8>>, Box<dyn std::error::Error>> {
+    if args.modules.is_empty() {
+        if cfg!(any(feature = "wasm3", feature = "wasmtime")) {
+            return Err("pass at least one .wasm module to soak a real engine".into());
+        }
+        return Ok(vec![vec![0u8; 256], vec![1u8; 1024]]);
+    }
+    Ok(args
+        .modules
+        .iter()
+        .map(fs::read)
+        .collect::<Result<_, _>>()?)
+}
+
+fn main() -> Result<(), Box<dyn std::error::Error>> {
+    let args = Args::parse();
+    Mix::parse(&args.mix)?;
+    let pool = load_pool(&args)?;
+
+    let counts = run_engine(&pool, &args)?;
+    println!(
+        "✅ soak passed: ops={} errors={} faults={}",
+        counts.ops, counts.errors, counts.faults
+    );
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn mix_parses_and_picks_by_weight() {
+        let mix = Mix::parse("invoke=2, fault=1").unwrap();
+        assert_eq!(
+            mix,
+            Mix {
+                install: 0,
+                invoke: 2,
+                churn: 0,
+                fault: 1
+            }
+        );
+        assert_eq!(mix.pick(0), Op::Invoke);
+        assert_eq!(mix.pick(1), Op::Invoke);
+        assert_eq!(mix.pick(2), Op::Fault);
+        assert!(Mix::parse("invoke=0").is_err());
+        assert!(Mix::parse("sleep=1").is_err());
+    }
+
+    #[test]
+    fn flash_slots_round_trip_installs() {
+        let mut slots = FlashSlots::new(MemoryFlash::new(4096), 3);
+        slots.install(1, b"abc").unwrap();
+        slots.install(2, b"defg").unwrap();
+        slots.install(1, b"xy").unwrap();
+        assert_eq!(slots.fetch(1), Some(&b"xy"[..]));
+        assert_eq!(slots.fetch(2), Some(&b"defg"[..]));
+        assert!(slots.install(3, &[0u8; 2048]).is_err());
+    }
+
+    #[cfg(not(any(feature = "wasm3", feature = "wasmtime")))]
+    #[test]
+    fn short_soak_is_deterministic() {
+        let args = Args::parse_from([
+            "slimmy-soak",
+            "--iterations",
+            "500",
+            "--warmup",
+            "100",
+            "--store",
+            "flash",
+        ]);
+        let pool = load_pool(&args).unwrap();
+        let first = run_engine(&pool, &args).unwrap();
+        let second = run_engine(&pool, &args).unwrap();
+        assert_eq!(first, second);
+        assert_eq!(first.ops, 500);
+        assert_eq!(first.errors, 0);
+        assert!(first.faults > 0);
+        assert_eq!(first.faults, first.faults_rejected + first.faults_tolerated);
+    }
+}
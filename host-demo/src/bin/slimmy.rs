@@ -0,0 +1,274 @@
+//! Device-side inspection tools.
+//!
+//! `slimmy flashmap` reads an on-flash module index (`runtime::storage::index`)
+//! from a dump file or a serial port and renders slot usage, fragmentation,
+//! per-module sizes and erase-count heat.
+
+use clap::{Parser, Subcommand};
+use runtime::storage::index::{Extent, FlashIndex, SlotState, ENTRY_LEN, HEADER_LEN};
+use std::fs::{self, File};
+use std::io::{self, Read};
+use std::path::PathBuf;
+use std::process::Command;
+
+#[derive(Parser, Debug)]
+#[command(name = "slimmy", about = "Inspect slimmy devices and images.")]
+struct Args {
+    #[command(subcommand)]
+    command: Cmd,
+}
+
+#[derive(Subcommand, Debug)]
+enum Cmd {
+    /// Render the module index of a flash region
+    Flashmap {
+        /// Index dump (raw bytes starting at the index magic)
+        #[arg(required_unless_present = "serial", conflicts_with = "serial")]
+        dump: Option<PathBuf>,
+
+        /// Read the index from this serial port instead (device streams it on boot/request)
+        #[arg(long, value_name = "TTY")]
+        serial: Option<PathBuf>,
+
+        /// Serial baud rate
+        #[arg(long, default_value_t = 115_200)]
+        baud: u32,
+
+        /// Characters in the block map and heat rows
+        #[arg(long, default_value_t = 64)]
+        width: usize,
+    },
+}
+
+fn main() -> Result<(), Box<dyn std::error::Error>> {
+    match Args::parse().command {
+        Cmd::Flashmap {
+            dump,
+            serial,
+            baud,
+            width,
+        } => {
+            let bytes = match (dump, serial) {
+                (Some(path), _) => fs::read(path)?,
+                (None, Some(tty)) => read_serial(&tty, baud)?,
+                (None, None) => unreachable!("clap requires one source"),
+            };
+            let index = FlashIndex::parse(&bytes).map_err(|e| format!("index: {e}"))?;
+            print!("{}", render(&index, width.max(8)));
+            Ok(())
+        }
+    }
+}
+
+/// Reads exactly one index from a tty: the header tells how many entries and
+/// erase counters follow.
+fn read_serial(tty: &PathBuf, baud: u32) -> Result<Vec<u8>, Box<dyn std::error::Error>> {
+    let status = Command::new("stty")
+        .arg("-F")
+        .arg(tty)
+        .args(["raw", "-echo", &baud.to_string()])
+        .status()?;
+    if !status.success() {
+        return Err(format!("stty failed for {}", tty.display()).into());
+    }
+    let mut port = File::open(tty)?;
+    let mut bytes = vec![0; HEADER_LEN];
+    port.read_exact(&mut bytes)?;
+    let entries = u16::from_le_bytes([bytes[6], bytes[7]]) as usize;
+    read_more(&mut port, &mut bytes, entries * ENTRY_LEN + 4)?;
+    let blocks = u32::from_le_bytes(bytes[bytes.len() - 4..].try_into().unwrap()) as usize;
+    read_more(&mut port, &mut bytes, blocks * 2)?;
+    Ok(bytes)
+}
+
+fn read_more(port: &mut File, bytes: &mut Vec<u8>, len: usize) -> io::Result<()> {
+    let start = bytes.len();
+    bytes.resize(start + len, 0);
+    port.read_exact(&mut bytes[start..])
+}
+
+/// Usage figures derived from the extents.
+#[derive(Debug, Default, PartialEq)]
+struct Usage {
+    live: u64,
+    superseded: u64,
+    free: u64,
+    largest_free: u64,
+    free_extents: usize,
+}
+
+impl Usage {
+    fn of(extents: &[Extent]) -> Self {
+        let mut usage = Usage::default();
+        for extent in extents {
+            let len = extent.len as u64;
+            match extent.record.map(|r| r.state) {
+                Some(SlotState::Live) => usage.live += len,
+                Some(SlotState::Superseded) => usage.superseded += len,
+                None => {
+                    usage.free += len;
+                    usage.largest_free = usage.largest_free.max(len);
+                    usage.free_extents += 1;
+                }
+            }
+        }
+        usage
+    }
+
+    /// 0 when all free space is one extent, approaching 1 as it splinters.
+    fn fragmentation(&self) -> f64 {
+        if self.free == 0 {
+            0.0
+        } else {
+            1.0 - self.largest_free as f64 / self.free as f64
+        }
+    }
+}
+
+fn render(index: &FlashIndex, width: usize) -> String {
+    use std::fmt::Write;
+
+    let extents = index.extents();
+    let usage = Usage::of(&extents);
+    let region = index.region_len as u64;
+    let percent = |n: u64| 100.0 * n as f64 / region.max(1) as f64;
+    let mut out = String::new();
+
+    let _ = writeln!(
+        out,
+        "region {} bytes, {} erase blocks of {} bytes, {} slot(s)",
+        region,
+        index.block_count(),
+        index.erase_block,
+        index.len()
+    );
+    let _ = writeln!(
+        out,
+        "live {} ({:.1}%), superseded {} ({:.1}%), free {} ({:.1}%)",
+        usage.live,
+        percent(usage.live),
+        usage.superseded,
+        percent(usage.superseded),
+        usage.free,
+        percent(usage.free)
+    );
+    let _ = writeln!(
+        out,
+        "free extents {}, largest {} bytes, fragmentation {:.0}%",
+        usage.free_extents,
+        usage.largest_free,
+        usage.fragmentation() * 100.0
+    );
+
+    let _ = writeln!(
+        out,
+        "\n{:>8} {:>8} {:>10} {:>10}  state",
+        "module", "version", "offset", "size"
+    );
+    let mut records: Vec<_> = index.entries().collect();
+    records.sort_by_key(|r| (r.module_id, r.offset));
+    for record in &records {
+        let state = match record.state {
+            SlotState::Live => "live",
+            SlotState::Superseded => "superseded",
+        };
+        let _ = writeln!(
+            out,
+            "{:>8} {:>8} {:>#10x} {:>10}  {state}",
+            record.module_id, record.version, record.offset, record.len
+        );
+    }
+
+    let _ = writeln!(out, "\nmap  |{}|", block_map(&extents, region, width));
+    let _ = writeln!(out, "      # live  + superseded  . free");
+    let counts: Vec<u16> = index.erase_counts().collect();
+    if !counts.is_empty() {
+        let max = counts.iter().copied().max().unwrap_or(0);
+        let _ = writeln!(out, "heat |{}|", heat_row(&counts, width));
+        let _ = writeln!(out, "      ░ low  █ high (max {max} erases)");
+    }
+    out
+}
+
+/// One character per `region / width` bytes; the dominant extent kind wins.
+fn block_map(extents: &[Extent], region: u64, width: usize) -> String {
+    let cells = width.min(region.max(1) as usize);
+    (0..cells)
+        .map(|cell| {
+            let start = region * cell as u64 / cells as u64;
+            let end = region * (cell as u64 + 1) / cells as u64;
+            let mut weights = [0u64; 3];
+            for extent in extents {
+                let lo = (extent.offset as u64).max(start);
+                let hi = (extent.offset as u64 + extent.len as u64).min(end);
+                if hi > lo {
+                    let kind = match extent.record.map(|r| r.state) {
+                        Some(SlotState::Live) => 0,
+                        Some(SlotState::Superseded) => 1,
+                        None => 2,
+                    };
+                    weights[kind] += hi - lo;
+                }
+            }
+            let kind = (0..3).max_by_key(|k| (weights[*k], 2 - *k)).unwrap_or(2);
+            ['#', '+', '.'][kind]
+        })
+        .collect()
+}
+
+/// Erase counters bucketed to `width` cells, shaded relative to the maximum.
+fn heat_row(counts: &[u16], width: usize) -> String {
+    const SHADES: [char; 4] = ['░', '▒', '▓', '█'];
+    let max = counts.iter().copied().max().unwrap_or(0).max(1) as usize;
+    let cells = width.min(counts.len());
+    (0..cells)
+        .map(|cell| {
+            let start = counts.len() * cell / cells;
+            let end = (counts.len() * (cell + 1) / cells).max(start + 1);
+            let hottest = counts[start..end].iter().copied().max().unwrap_or(0) as usize;
+            SHADES[(hottest * (SHADES.len() - 1) + max / 2) / max]
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use runtime::storage::index::{encode, IndexRecord};
+
+    #[test]
+    fn renders_usage_map_and_heat() {
+        let records = [
+            IndexRecord {
+                module_id: 1,
+                offset: 0,
+                len: 4096,
+                version: 1,
+                state: SlotState::Superseded,
+            },
+            IndexRecord {
+                module_id: 1,
+                offset: 8192,
+                len: 4096,
+                version: 2,
+                state: SlotState::Live,
+            },
+        ];
+        let bytes = encode(16384, 4096, &records, &[8, 0, 4, 0]).unwrap();
+        let index = FlashIndex::parse(&bytes).unwrap();
+        let extents = index.extents();
+
+        let usage = Usage::of(&extents);
+        assert_eq!(
+            (usage.live, usage.superseded, usage.free),
+            (4096, 4096, 8192)
+        );
+        assert_eq!(usage.fragmentation(), 0.5);
+        assert_eq!(block_map(&extents, 16384, 4), "+.#.");
+        assert_eq!(heat_row(&[8, 0, 4, 0], 4), "█░▓░");
+
+        let text = render(&index, 8);
+        assert!(text.contains("fragmentation 50%"));
+        assert!(text.contains("map  |++..##..|"));
+    }
+}
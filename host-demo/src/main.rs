@@ -1,20 +1,42 @@
 use clap::Parser;
-use runtime::{manifest::Manifest, CachedEngine, MemoryStore, ModuleSource, Runtime};
-#[cfg(all(feature = "wasm3", feature = "wasmtime-lite"))]
-compile_error!("Select only one engine feature at a time: wasm3 or wasmtime-lite.");
-#[cfg(not(any(feature = "wasm3", feature = "wasmtime-lite")))]
+use runtime::builder::RuntimeBuilder;
+use runtime::update::{AbStore, HealthOutcome};
+use runtime::{manifest::Manifest, MemoryStore, ModuleSource};
+use scenario::{Outcome, Scenario};
+#[cfg(all(feature = "wasm3", feature = "wasmtime", not(feature = "diff")))]
+compile_error!("Select only one engine feature at a time: wasm3 or wasmtime-lite/-winch.");
+#[cfg(not(any(feature = "wasm3", feature = "wasmtime")))]
 use runtime::{Engine, Error, ModuleId};
-#[cfg(not(any(feature = "wasm3", feature = "wasmtime-lite")))]
+#[cfg(not(any(feature = "wasm3", feature = "wasmtime")))]
 use std::collections::HashMap;
 use std::fs;
 use std::io;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use watch::Watcher;
+
+mod scenario;
+mod watch;
 
 #[derive(Parser, Debug)]
 #[command(name = "host-demo", about = "Tiny host runner for slimmy modules.")]
 struct Args {
     /// Path to .wasm or .smny blob
-    path: PathBuf,
+    #[arg(required_unless_present = "scenario")]
+    path: Option<PathBuf>,
+
+    /// Run a multi-module scenario file instead (see `scenario.rs` for the format)
+    #[arg(long, value_name = "FILE", conflicts_with_all = ["path", "manifest"])]
+    scenario: Option<PathBuf>,
+
+    /// Keep running: re-pack, reinstall (health-checked, like an OTA update)
+    /// and re-run the .wasm at PATH whenever it changes
+    #[arg(long, conflicts_with = "manifest")]
+    watch: bool,
+
+    /// How often `--watch` checks the file, in milliseconds
+    #[arg(long, value_name = "MS", default_value_t = 250, requires = "watch")]
+    poll_ms: u64,
 
     /// Override entry (defaults to manifest entry when using --manifest)
     #[arg(short, long)]
@@ -35,8 +57,20 @@ struct Args {
 
 fn main() -> Result<(), Box<dyn std::error::Error>> {
     let args = Args::parse();
+    if let Some(path) = &args.scenario {
+        return scenario_main(path);
+    }
 
-    let blob = fs::read(&args.path)?;
+    if args.watch {
+        let path = args.path.ok_or("missing path")?;
+        let entry = args.entry.unwrap_or_else(|| "main".to_string());
+        println!("👀 watching {} (entry `{entry}`)", path.display());
+        let mut watcher = Watcher::new(path, entry);
+        return watch_module(&mut watcher, Duration::from_millis(args.poll_ms))
+            .map_err(|err| to_io_error(err).into());
+    }
+
+    let blob = fs::read(args.path.as_ref().ok_or("missing path")?)?;
     let (module_bytes, entry, info) = if args.manifest {
         load_manifest_blob(&args, &blob)?
     } else {
@@ -72,8 +106,77 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
     Ok(())
 }
 
+fn scenario_main(path: &Path) -> Result<(), Box<dyn std::error::Error>> {
+    let (scenario, store) = Scenario::load(path)?;
+    let outcomes = run_scenario(&scenario, store).map_err(to_io_error)?;
+    for outcome in &outcomes {
+        match &outcome.failure {
+            None => println!(
+                "✅ step {} (pass {}): module={} entry=`{}` x{}",
+                outcome.step, outcome.pass, outcome.module, outcome.entry, outcome.invocations
+            ),
+            Some(why) => println!(
+                "❌ step {} (pass {}): module={} entry=`{}` invocation {}: {why}",
+                outcome.step, outcome.pass, outcome.module, outcome.entry, outcome.invocations
+            ),
+        }
+    }
+    let failed = outcomes.iter().filter(|o| o.failure.is_some()).count();
+    println!(
+        "scenario: {}/{} steps passed",
+        outcomes.len() - failed,
+        outcomes.len()
+    );
+    if failed > 0 {
+        return Err(format!("{failed} scenario steps failed").into());
+    }
+    Ok(())
+}
+
+/// Redeploys every change to the watched file; returns only on a runtime
+/// error outside a deploy.
+fn watch_loop<E: runtime::Engine>(
+    watcher: &mut Watcher,
+    runtime: &mut runtime::Runtime<E, AbStore>,
+    ctx: &mut E::Context,
+    poll: Duration,
+) -> runtime::Result<()> {
+    loop {
+        if let Some(module) = watcher.changed() {
+            match watcher.deploy(runtime, &module, ctx) {
+                Ok(deploy) => {
+                    let health = match deploy.health {
+                        HealthOutcome::Unchecked => "no health export".to_string(),
+                        HealthOutcome::Healthy { attempts } => {
+                            format!("healthy after {attempts} attempt(s)")
+                        }
+                        HealthOutcome::RolledBack { failed, restored } => {
+                            format!("unhealthy, rolled back v{failed} -> v{restored}")
+                        }
+                        HealthOutcome::Unhealthy { version } => {
+                            format!("unhealthy, nothing to roll back to from v{version}")
+                        }
+                        HealthOutcome::Rejected(rejection) => format!("rejected: {rejection:?}"),
+                    };
+                    println!(
+                        "🔁 installed v{} ({} bytes): {health}",
+                        deploy.version, deploy.bytes
+                    );
+                    match deploy.run {
+                        Some(Ok(())) => println!("✅ call finished: entry=`{}`", watcher.entry()),
+                        Some(Err(err)) => println!("❌ call failed: {err}"),
+                        None => {}
+                    }
+                }
+                Err(err) => println!("❌ pack failed: {err}"),
+            }
+        }
+        std::thread::sleep(poll);
+    }
+}
+
 fn to_io_error(err: runtime::Error) -> io::Error {
-    io::Error::new(io::ErrorKind::Other, format!("runtime error: {err}"))
+    io::Error::other(format!("runtime error: {err}"))
 }
 
 #[derive(Default)]
@@ -82,7 +185,7 @@ struct HostStats {
     last_size: usize,
 }
 
-#[cfg(not(any(feature = "wasm3", feature = "wasmtime-lite")))]
+#[cfg(not(any(feature = "wasm3", feature = "wasmtime")))]
 #[derive(Default)]
 struct NoopEngine {
     module_sizes: HashMap<ModuleId, usize>,
@@ -90,10 +193,9 @@ struct NoopEngine {
 
 #[cfg(feature = "wasm3")]
 fn run_module(store: MemoryStore, entry: &str, module_size: usize) -> runtime::Result<HostStats> {
-    use runtime::engines::wasm3::{Wasm3Engine, DEFAULT_STACK_SLOTS};
+    use runtime::engines::wasm3::Wasm3Engine;
 
-    let engine = CachedEngine::new(Wasm3Engine::new(DEFAULT_STACK_SLOTS)?);
-    let mut runtime = Runtime::new(engine, store);
+    let mut runtime = RuntimeBuilder::new(store).build::<Wasm3Engine>()?;
 
     runtime.execute(1, entry, &mut ())?;
     Ok(HostStats {
@@ -102,12 +204,27 @@ fn run_module(store: MemoryStore, entry: &str, module_size: usize) -> runtime::R
     })
 }
 
-#[cfg(feature = "wasmtime-lite")]
+#[cfg(feature = "wasm3")]
+fn watch_module(watcher: &mut Watcher, poll: Duration) -> runtime::Result<()> {
+    use runtime::engines::wasm3::Wasm3Engine;
+
+    let mut runtime = RuntimeBuilder::new(AbStore::new()).build::<Wasm3Engine>()?;
+    watch_loop(watcher, &mut runtime, &mut (), poll)
+}
+
+#[cfg(feature = "wasm3")]
+fn run_scenario(scenario: &Scenario, store: MemoryStore) -> runtime::Result<Vec<Outcome>> {
+    use runtime::engines::wasm3::Wasm3Engine;
+
+    let mut runtime = RuntimeBuilder::new(store).build::<Wasm3Engine>()?;
+    Ok(scenario.run(&mut runtime, &mut ()))
+}
+
+#[cfg(all(feature = "wasmtime", not(feature = "wasm3")))]
 fn run_module(store: MemoryStore, entry: &str, module_size: usize) -> runtime::Result<HostStats> {
     use runtime::engines::wasmtime_lite::WasmtimeLiteEngine;
 
-    let engine = CachedEngine::new(WasmtimeLiteEngine::new()?);
-    let mut runtime = Runtime::new(engine, store);
+    let mut runtime = RuntimeBuilder::new(store).build::<WasmtimeLiteEngine>()?;
 
     runtime.execute(1, entry, &mut ())?;
     Ok(HostStats {
@@ -116,17 +233,44 @@ fn run_module(store: MemoryStore, entry: &str, module_size: usize) -> runtime::R
     })
 }
 
-#[cfg(not(any(feature = "wasm3", feature = "wasmtime-lite")))]
+#[cfg(all(feature = "wasmtime", not(feature = "wasm3")))]
+fn watch_module(watcher: &mut Watcher, poll: Duration) -> runtime::Result<()> {
+    use runtime::engines::wasmtime_lite::WasmtimeLiteEngine;
+
+    let mut runtime = RuntimeBuilder::new(AbStore::new()).build::<WasmtimeLiteEngine>()?;
+    watch_loop(watcher, &mut runtime, &mut (), poll)
+}
+
+#[cfg(all(feature = "wasmtime", not(feature = "wasm3")))]
+fn run_scenario(scenario: &Scenario, store: MemoryStore) -> runtime::Result<Vec<Outcome>> {
+    use runtime::engines::wasmtime_lite::WasmtimeLiteEngine;
+
+    let mut runtime = RuntimeBuilder::new(store).build::<WasmtimeLiteEngine>()?;
+    Ok(scenario.run(&mut runtime, &mut ()))
+}
+
+#[cfg(not(any(feature = "wasm3", feature = "wasmtime")))]
 fn run_module(store: MemoryStore, entry: &str, _module_size: usize) -> runtime::Result<HostStats> {
-    let engine = CachedEngine::new(NoopEngine::default());
-    let mut runtime = Runtime::new(engine, store);
+    let mut runtime = RuntimeBuilder::new(store).build_with(NoopEngine::default());
 
     let mut ctx = HostStats::default();
     runtime.execute(1, entry, &mut ctx)?;
     Ok(ctx)
 }
 
-#[cfg(not(any(feature = "wasm3", feature = "wasmtime-lite")))]
+#[cfg(not(any(feature = "wasm3", feature = "wasmtime")))]
+fn watch_module(watcher: &mut Watcher, poll: Duration) -> runtime::Result<()> {
+    let mut runtime = RuntimeBuilder::new(AbStore::new()).build_with(NoopEngine::default());
+    watch_loop(watcher, &mut runtime, &mut HostStats::default(), poll)
+}
+
+#[cfg(not(any(feature = "wasm3", feature = "wasmtime")))]
+fn run_scenario(scenario: &Scenario, store: MemoryStore) -> runtime::Result<Vec<Outcome>> {
+    let mut runtime = RuntimeBuilder::new(store).build_with(NoopEngine::default());
+    Ok(scenario.run(&mut runtime, &mut HostStats::default()))
+}
+
+#[cfg(not(any(feature = "wasm3", feature = "wasmtime")))]
 impl Engine for NoopEngine {
     type ModuleHandle = ModuleId;
     type Context = HostStats;
@@ -164,10 +308,13 @@ impl Engine for NoopEngine {
     }
 }
 
+/// Module bytes, resolved entry, and the parsed manifest (when present).
+type LoadedBlob<'a> = (Vec<u8>, String, Option<(Manifest<'a>, Vec<u8>)>);
+
 fn load_manifest_blob<'a>(
     args: &'a Args,
     blob: &'a [u8],
-) -> Result<(Vec<u8>, String, Option<(Manifest<'a>, Vec<u8>)>), Box<dyn std::error::Error>> {
+) -> Result<LoadedBlob<'a>, Box<dyn std::error::Error>> {
     let (manifest, module) = Manifest::parse(blob).map_err(to_io_error)?;
     let module_vec = module.to_vec();
 
@@ -221,7 +368,10 @@ mod tests {
         let entry = "main";
         let blob = encode(1, entry, module, 0, 0, None).unwrap();
         let args = Args {
-            path: PathBuf::from("module.smny"),
+            path: Some(PathBuf::from("module.smny")),
+            scenario: None,
+            watch: false,
+            poll_ms: 250,
             entry: None,
             manifest: true,
             pubkey_hex: None,
@@ -241,7 +391,10 @@ mod tests {
         let sig = [0u8; 64];
         let blob = encode(1, "main", module, 0, 0, Some(sig)).unwrap();
         let args = Args {
-            path: PathBuf::from("module.smny"),
+            path: Some(PathBuf::from("module.smny")),
+            scenario: None,
+            watch: false,
+            poll_ms: 250,
             entry: None,
             manifest: true,
             pubkey_hex: None,
@@ -0,0 +1,282 @@
+//! Multi-module scenarios (`host-demo --scenario FILE`): install several
+//! modules, invoke them in order, and check each outcome.
+//!
+//! ```toml
+//! repeat = 2                 # run the whole step list this many times
+//!
+//! [[module]]
+//! id = 1
+//! path = "sensor.wasm"       # relative to the scenario; .smny blobs are unpacked
+//!
+//! [[module]]
+//! id = 2
+//! path = "report.smny"       # entry defaults to the manifest's
+//!
+//! [[step]]
+//! module = 1
+//! repeat = 10                # invocations; the first unexpected outcome fails the step
+//!
+//! [[step]]
+//! module = 2
+//! entry = "health"
+//! status = 0                 # expect this i32 status
+//!
+//! [[step]]
+//! module = 1
+//! entry = "crash"
+//! error = "unreachable"      # expect an error containing this ("" = any error)
+//! ```
+
+use runtime::manifest::{Manifest, MANIFEST_MAGIC};
+use runtime::{Engine, MemoryStore, ModuleId, Runtime};
+use serde::Deserialize;
+use std::fs;
+use std::path::Path;
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Scenario {
+    #[serde(default = "one")]
+    pub repeat: u32,
+    #[serde(rename = "module")]
+    pub modules: Vec<ModuleSpec>,
+    #[serde(rename = "step")]
+    pub steps: Vec<Step>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ModuleSpec {
+    pub id: ModuleId,
+    pub path: String,
+    pub entry: Option<String>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Step {
+    pub module: ModuleId,
+    pub entry: Option<String>,
+    #[serde(default = "one")]
+    pub repeat: u32,
+    /// Expected status (runs the entry with `execute_status`).
+    pub status: Option<i32>,
+    /// Expected error substring; `""` accepts any error.
+    pub error: Option<String>,
+}
+
+fn one() -> u32 {
+    1
+}
+
+/// How one step went.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Outcome {
+    /// Step number (1-based) and pass of the step list it ran in.
+    pub step: usize,
+    pub pass: u32,
+    pub module: ModuleId,
+    pub entry: String,
+    /// Invocations made, including the failing one.
+    pub invocations: u32,
+    /// Why the step failed.
+    pub failure: Option<String>,
+}
+
+impl Scenario {
+    pub fn parse(text: &str) -> Result<Self, String> {
+        let scenario: Scenario = toml::from_str(text).map_err(|err| err.to_string())?;
+        for (i, step) in scenario.steps.iter().enumerate() {
+            if step.status.is_some() && step.error.is_some() {
+                return Err(format!("step {}: set status or error, not both", i + 1));
+            }
+            if !scenario.modules.iter().any(|m| m.id == step.module) {
+                return Err(format!(
+                    "step {}: module {} not declared",
+                    i + 1,
+                    step.module
+                ));
+            }
+        }
+        Ok(scenario)
+    }
+
+    /// Reads the scenario at `path` and its modules into a store; module
+    /// entries default to their manifest's.
+    pub fn load(path: &Path) -> Result<(Self, MemoryStore), String> {
+        let text = fs::read_to_string(path)
+            .map_err(|err| format!("cannot read {}: {err}", path.display()))?;
+        let mut scenario =
+            Self::parse(&text).map_err(|err| format!("{}: {err}", path.display()))?;
+        let base = path.parent().unwrap_or(Path::new(""));
+        let mut store = MemoryStore::new();
+        for module in &mut scenario.modules {
+            let file = base.join(&module.path);
+            let bytes =
+                fs::read(&file).map_err(|err| format!("cannot read {}: {err}", file.display()))?;
+            let bytes = if bytes.starts_with(MANIFEST_MAGIC) {
+                let (manifest, wasm) =
+                    Manifest::parse(&bytes).map_err(|err| format!("{}: {err}", file.display()))?;
+                module
+                    .entry
+                    .get_or_insert_with(|| manifest.entry.to_string());
+                wasm.to_vec()
+            } else {
+                bytes
+            };
+            store.upsert(module.id, bytes);
+        }
+        Ok((scenario, store))
+    }
+
+    /// Runs every step `repeat` times against `runtime`.
+    pub fn run<E: Engine>(
+        &self,
+        runtime: &mut Runtime<E, MemoryStore>,
+        ctx: &mut E::Context,
+    ) -> Vec<Outcome> {
+        let mut outcomes = Vec::new();
+        for pass in 1..=self.repeat {
+            for (i, step) in self.steps.iter().enumerate() {
+                let entry = step
+                    .entry
+                    .clone()
+                    .or_else(|| self.module_entry(step.module))
+                    .unwrap_or_else(|| "main".into());
+                let mut outcome = Outcome {
+                    step: i + 1,
+                    pass,
+                    module: step.module,
+                    entry,
+                    invocations: 0,
+                    failure: None,
+                };
+                while outcome.invocations < step.repeat && outcome.failure.is_none() {
+                    outcome.invocations += 1;
+                    outcome.failure = check(step, runtime, step.module, &outcome.entry, ctx);
+                }
+                outcomes.push(outcome);
+            }
+        }
+        outcomes
+    }
+
+    fn module_entry(&self, id: ModuleId) -> Option<String> {
+        self.modules
+            .iter()
+            .find(|m| m.id == id)
+            .and_then(|m| m.entry.clone())
+    }
+}
+
+/// Invokes once; returns why the outcome is not the expected one.
+fn check<E: Engine>(
+    step: &Step,
+    runtime: &mut Runtime<E, MemoryStore>,
+    module: ModuleId,
+    entry: &str,
+    ctx: &mut E::Context,
+) -> Option<String> {
+    let result = match step.status {
+        Some(_) => runtime.execute_status(module, entry, ctx),
+        None => runtime.execute(module, entry, ctx).map(|()| 0),
+    };
+    match (result, &step.error, step.status) {
+        (Ok(status), None, Some(want)) if status != want => {
+            Some(format!("expected status {want}, got {status}"))
+        }
+        (Ok(_), None, _) => None,
+        (Ok(_), Some(_), _) => Some("expected an error, call succeeded".into()),
+        (Err(err), Some(want), _) if err.to_string().contains(want.as_str()) => None,
+        (Err(err), Some(want), _) => Some(format!("expected error `{want}`, got `{err}`")),
+        (Err(err), None, _) => Some(format!("unexpected error: {err}")),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use runtime::{Error, Result};
+
+    /// `fail` errors, `status` returns 7, anything else succeeds.
+    struct Scripted;
+
+    impl Engine for Scripted {
+        type ModuleHandle = ModuleId;
+        type Context = u32;
+
+        fn load(&mut self, id: ModuleId, _module: &[u8]) -> Result<ModuleId> {
+            Ok(id)
+        }
+
+        fn invoke(&mut self, _handle: ModuleId, entry: &str, calls: &mut u32) -> Result<()> {
+            *calls += 1;
+            match entry {
+                "fail" => Err(Error::Engine("boom")),
+                _ => Ok(()),
+            }
+        }
+
+        fn invoke_status(&mut self, handle: ModuleId, entry: &str, calls: &mut u32) -> Result<i32> {
+            self.invoke(handle, entry, calls)
+                .map(|()| if entry == "status" { 7 } else { 0 })
+        }
+    }
+
+    #[test]
+    fn runs_steps_in_order_and_checks_outcomes() {
+        let scenario = Scenario::parse(
+            r#"
+            repeat = 2
+
+            [[module]]
+            id = 1
+            path = "a.wasm"
+            entry = "tick"
+
+            [[step]]
+            module = 1
+            repeat = 3
+
+            [[step]]
+            module = 1
+            entry = "status"
+            status = 7
+
+            [[step]]
+            module = 1
+            entry = "fail"
+            error = "boom"
+
+            [[step]]
+            module = 1
+            entry = "status"
+            status = 0
+            repeat = 5
+            "#,
+        )
+        .unwrap();
+
+        let mut store = MemoryStore::new();
+        store.upsert(1, b"\0asm".to_vec());
+        let mut runtime = Runtime::new(Scripted, store);
+        let mut calls = 0;
+        let outcomes = scenario.run(&mut runtime, &mut calls);
+
+        assert_eq!(outcomes.len(), 8);
+        assert_eq!(calls, 2 * (3 + 1 + 1 + 1));
+        assert_eq!(
+            (outcomes[0].entry.as_str(), outcomes[0].invocations),
+            ("tick", 3)
+        );
+        assert!(outcomes[..3].iter().all(|o| o.failure.is_none()));
+        assert_eq!(
+            outcomes[3].failure.as_deref(),
+            Some("expected status 0, got 7")
+        );
+        assert_eq!((outcomes[7].pass, outcomes[7].invocations), (2, 1));
+
+        let bad = "[[module]]\nid = 1\npath = \"a\"\n[[step]]\nmodule = 2\n";
+        assert!(Scenario::parse(bad).unwrap_err().contains("module 2"));
+    }
+}
@@ -0,0 +1,163 @@
+//! Watch mode (`host-demo --watch FILE`): every time the `.wasm` changes it
+//! is re-packed, installed like an OTA update (health check, rollback to the
+//! previous build on failure) and its entry run again.
+
+use packer::{pack, PackOptions};
+use runtime::manifest::Manifest;
+use runtime::update::{AbStore, HealthOutcome, DEFAULT_HEALTH_ATTEMPTS};
+use runtime::{Engine, ModuleId, Runtime};
+use std::fs;
+use std::path::PathBuf;
+use std::time::SystemTime;
+
+/// Module id watched builds are installed under.
+pub const WATCH_MODULE_ID: ModuleId = 1;
+
+/// Polls one module file and redeploys it when it changes.
+pub struct Watcher {
+    path: PathBuf,
+    entry: String,
+    /// Modification time and length of the last build seen.
+    stamp: Option<(SystemTime, u64)>,
+    version: u32,
+}
+
+/// What one redeploy did.
+#[derive(Debug)]
+pub struct Deploy {
+    /// Manifest sequence the build was packed with.
+    pub version: u32,
+    pub bytes: usize,
+    pub health: HealthOutcome,
+    /// The entry call after install; skipped when the build was rolled back.
+    pub run: Option<runtime::Result<()>>,
+}
+
+impl Watcher {
+    pub fn new(path: PathBuf, entry: String) -> Self {
+        Self {
+            path,
+            entry,
+            stamp: None,
+            version: 0,
+        }
+    }
+
+    pub fn entry(&self) -> &str {
+        &self.entry
+    }
+
+    /// The file's contents if it changed since the last call (always on the
+    /// first); `None` while unchanged or unreadable, e.g. mid-rebuild.
+    pub fn changed(&mut self) -> Option<Vec<u8>> {
+        let meta = fs::metadata(&self.path).ok()?;
+        let stamp = (meta.modified().ok()?, meta.len());
+        if self.stamp == Some(stamp) {
+            return None;
+        }
+        let bytes = fs::read(&self.path).ok()?;
+        self.stamp = Some(stamp);
+        Some(bytes)
+    }
+
+    /// Packs `module` as the next version, installs it and runs the entry.
+    pub fn deploy<E: Engine>(
+        &mut self,
+        runtime: &mut Runtime<E, AbStore>,
+        module: &[u8],
+        ctx: &mut E::Context,
+    ) -> Result<Deploy, String> {
+        let opts = PackOptions {
+            module_id: WATCH_MODULE_ID,
+            entry: self.entry.clone(),
+            sequence: self.version + 1,
+            ..PackOptions::default()
+        };
+        let blob = pack(module, &opts)?.blob;
+        // Install from the blob, as a device receiving it over the air would.
+        let (manifest, wasm) = Manifest::parse(&blob).map_err(|err| err.to_string())?;
+        let health = runtime
+            .install_update(
+                manifest.module_id,
+                manifest.sequence,
+                wasm,
+                DEFAULT_HEALTH_ATTEMPTS,
+                ctx,
+            )
+            .map_err(|err| err.to_string())?;
+        self.version = manifest.sequence;
+        let run = match health {
+            HealthOutcome::RolledBack { .. } => None,
+            _ => Some(runtime.execute(manifest.module_id, &self.entry, ctx)),
+        };
+        Ok(Deploy {
+            version: manifest.sequence,
+            bytes: wasm.len(),
+            health,
+            run,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use runtime::{Error, Result};
+
+    /// Modules whose first byte is 0 fail `health`; calls are counted.
+    #[derive(Default)]
+    struct Scripted {
+        healthy: bool,
+    }
+
+    impl Engine for Scripted {
+        type ModuleHandle = ModuleId;
+        type Context = u32;
+
+        fn load(&mut self, id: ModuleId, module: &[u8]) -> Result<ModuleId> {
+            self.healthy = module.first() != Some(&0);
+            Ok(id)
+        }
+
+        fn invoke(&mut self, _handle: ModuleId, entry: &str, calls: &mut u32) -> Result<()> {
+            *calls += 1;
+            match entry {
+                "health" if !self.healthy => Err(Error::Engine("unhealthy")),
+                _ => Ok(()),
+            }
+        }
+    }
+
+    #[test]
+    fn redeploys_changed_builds_and_rolls_back_bad_ones() {
+        let path = std::env::temp_dir().join(format!("host-demo-watch-{}", std::process::id()));
+        fs::write(&path, b"good").unwrap();
+        let mut watcher = Watcher::new(path.clone(), "main".into());
+        let mut runtime = Runtime::new(Scripted::default(), AbStore::new());
+        let mut calls = 0;
+
+        let first = watcher.changed().unwrap();
+        assert!(watcher.changed().is_none());
+        let deploy = watcher.deploy(&mut runtime, &first, &mut calls).unwrap();
+        assert_eq!(deploy.version, 1);
+        assert_eq!(deploy.health, HealthOutcome::Healthy { attempts: 1 });
+        assert!(matches!(deploy.run, Some(Ok(()))));
+
+        fs::write(&path, b"\0bad build").unwrap();
+        let second = watcher.changed().unwrap();
+        let deploy = watcher.deploy(&mut runtime, &second, &mut calls).unwrap();
+        assert_eq!(
+            deploy.health,
+            HealthOutcome::RolledBack {
+                failed: 2,
+                restored: 1
+            }
+        );
+        assert!(deploy.run.is_none());
+        assert_eq!(
+            runtime.source().active(WATCH_MODULE_ID).unwrap().bytes,
+            b"good"
+        );
+        fs::remove_file(&path).unwrap();
+    }
+}
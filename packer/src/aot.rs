@@ -0,0 +1,118 @@
+//! Ahead-of-time compilation with WAMR's `wamrc` (`packer --aot`).
+//!
+//! The wasm module is compiled before packing and the manifest is marked
+//! `FLAG_AOT`, so only engines that load AOT artifacts run it. Import
+//! allowlists are checked on the wasm; the size budget on the artifact.
+
+use runtime::manifest::{PayloadKind, AOT_MAGIC};
+use std::fs;
+use std::path::PathBuf;
+use std::process::Command;
+use std::sync::atomic::{AtomicU32, Ordering};
+
+/// Compiler invoked when `AotOptions::wamrc` is not set.
+pub const DEFAULT_WAMRC: &str = "wamrc";
+
+/// How to run `wamrc`.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct AotOptions {
+    /// Compiler path; default `DEFAULT_WAMRC` from `PATH`.
+    pub wamrc: Option<PathBuf>,
+    /// `--target=` for the device, e.g. `thumbv7em`, `xtensa`, `riscv32`.
+    pub target: Option<String>,
+    /// Further arguments passed as is, e.g. `--cpu=cortex-m4` or `--size-level=3`.
+    pub args: Vec<String>,
+}
+
+/// Compiles `module` and returns the AOT artifact.
+pub fn compile(module: &[u8], opts: &AotOptions) -> Result<Vec<u8>, String> {
+    if PayloadKind::detect(module) != Some(PayloadKind::Wasm) {
+        return Err("aot: input is not a wasm module".into());
+    }
+    static NEXT: AtomicU32 = AtomicU32::new(0);
+    let dir = std::env::temp_dir().join(format!(
+        "packer-aot-{}-{}",
+        std::process::id(),
+        NEXT.fetch_add(1, Ordering::Relaxed)
+    ));
+    fs::create_dir_all(&dir).map_err(|err| format!("aot: {err}"))?;
+    let result = run(module, opts, &dir);
+    let _ = fs::remove_dir_all(&dir);
+    result
+}
+
+fn run(module: &[u8], opts: &AotOptions, dir: &std::path::Path) -> Result<Vec<u8>, String> {
+    let (input, output) = (dir.join("module.wasm"), dir.join("module.aot"));
+    fs::write(&input, module).map_err(|err| format!("aot: {err}"))?;
+
+    let wamrc = opts.wamrc.clone().unwrap_or(PathBuf::from(DEFAULT_WAMRC));
+    let mut command = Command::new(&wamrc);
+    if let Some(target) = &opts.target {
+        command.arg(format!("--target={target}"));
+    }
+    command.args(&opts.args).arg("-o").arg(&output).arg(&input);
+    let out = command
+        .output()
+        .map_err(|err| format!("aot: cannot run {}: {err}", wamrc.display()))?;
+    if !out.status.success() {
+        let stderr = String::from_utf8_lossy(&out.stderr);
+        return Err(format!(
+            "aot: wamrc failed ({}): {}",
+            out.status,
+            stderr.trim()
+        ));
+    }
+
+    let artifact = fs::read(&output).map_err(|err| format!("aot: no output: {err}"))?;
+    if !artifact.starts_with(AOT_MAGIC) {
+        return Err("aot: wamrc output is not an AOT artifact".into());
+    }
+    Ok(artifact)
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+    use std::os::unix::fs::PermissionsExt;
+
+    /// Writes a stand-in `wamrc` that records its arguments and emits `output`.
+    fn fake_wamrc(dir: &std::path::Path, output: &str) -> PathBuf {
+        let path = dir.join("wamrc");
+        let script = format!(
+            "#!/bin/sh\necho \"$@\" > {}/args\nwhile [ \"$1\" != -o ]; do shift; done\nprintf '{output}' > \"$2\"\n",
+            dir.display()
+        );
+        fs::write(&path, script).unwrap();
+        fs::set_permissions(&path, fs::Permissions::from_mode(0o755)).unwrap();
+        path
+    }
+
+    #[test]
+    fn runs_wamrc_and_checks_its_output() {
+        let dir = std::env::temp_dir().join(format!("packer-aot-test-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let wasm = b"\0asm\x01\0\0\0";
+        let opts = AotOptions {
+            wamrc: Some(fake_wamrc(&dir, "\\0aot\\3\\0\\0\\0")),
+            target: Some("thumbv7em".into()),
+            args: vec!["--size-level=3".into()],
+        };
+
+        assert_eq!(compile(wasm, &opts).unwrap(), b"\0aot\x03\0\0\0");
+        let args = fs::read_to_string(dir.join("args")).unwrap();
+        assert!(args.starts_with("--target=thumbv7em --size-level=3 -o "));
+        assert!(compile(b"\0aot", &opts).is_err());
+
+        let bogus = AotOptions {
+            wamrc: Some(fake_wamrc(&dir, "garbage")),
+            ..AotOptions::default()
+        };
+        assert!(compile(wasm, &bogus).unwrap_err().contains("not an AOT"));
+        let missing = AotOptions {
+            wamrc: Some(dir.join("missing")),
+            ..AotOptions::default()
+        };
+        assert!(compile(wasm, &missing).unwrap_err().contains("cannot run"));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,103 @@
+//! Builds a guest crate into a small, ready-to-pack wasm module: the right
+//! target and a size-tuned profile without editing the guest's `Cargo.toml`,
+//! then custom sections stripped (see `packer::guest`).
+
+use clap::Parser;
+use packer::guest::{GuestBuild, DEFAULT_PROFILE, DEFAULT_TARGET};
+use std::fs;
+use std::path::PathBuf;
+
+#[derive(Parser, Debug)]
+#[command(
+    name = "slimmy-guest-build",
+    about = "Compile a guest crate to an optimized, stripped wasm module."
+)]
+struct Args {
+    /// Cargo.toml of the guest crate or workspace (default: current directory)
+    #[arg(long, value_name = "PATH")]
+    manifest_path: Option<PathBuf>,
+
+    /// Package to build in a workspace
+    #[arg(short, long)]
+    package: Option<String>,
+
+    /// Features to enable (comma separated or repeatable)
+    #[arg(short = 'F', long, value_delimiter = ',')]
+    features: Vec<String>,
+
+    /// Do not enable the default features
+    #[arg(long, default_value_t = false)]
+    no_default_features: bool,
+
+    #[arg(long, default_value = DEFAULT_TARGET)]
+    target: String,
+
+    /// Cargo profile the size settings are applied to
+    #[arg(long, default_value = DEFAULT_PROFILE)]
+    profile: String,
+
+    /// `-C target-cpu`, e.g. `mvp` for engines without post-MVP wasm features
+    #[arg(long, value_name = "CPU")]
+    target_cpu: Option<String>,
+
+    /// Entry point the module must export
+    #[arg(long, default_value = "main")]
+    entry: String,
+
+    /// Also drop exports other than the entry, the runtime's (`memory`, `health`, `slimmy_alloc`, `slimmy_free`, `__slimmy_info`) and --keep-export
+    #[arg(long, default_value_t = false)]
+    strip_exports: bool,
+
+    /// Export kept by --strip-exports (repeatable)
+    #[arg(long = "keep-export", value_name = "NAME")]
+    keep_exports: Vec<String>,
+
+    /// Output file path (default: <crate>.wasm in the current directory)
+    #[arg(short, long)]
+    out: Option<PathBuf>,
+}
+
+fn main() -> Result<(), Box<dyn std::error::Error>> {
+    let args = Args::parse();
+    let build = GuestBuild {
+        manifest_path: args.manifest_path,
+        package: args.package,
+        features: args.features,
+        no_default_features: args.no_default_features,
+        target: args.target,
+        profile: args.profile,
+        target_cpu: args.target_cpu,
+        entry: args.entry,
+        strip_exports: args.strip_exports,
+        keep_exports: args.keep_exports,
+        cargo: None,
+    };
+    let built = build.run()?;
+    let out = match args.out {
+        Some(out) => out,
+        None => PathBuf::from(
+            built
+                .artifact
+                .file_name()
+                .ok_or("artifact has no file name")?,
+        ),
+    };
+    fs::write(&out, &built.module)?;
+    let report = built.report;
+    println!(
+        "✅ guest: {} -> {} bytes (-{}): {} custom sections, {} exports, {} dead functions -> {}",
+        report.before,
+        report.after,
+        report.saved(),
+        report.custom_sections,
+        report.exports,
+        report.functions,
+        out.display()
+    );
+    println!(
+        "   pack it with: packer {} --entry {}",
+        out.display(),
+        build.entry
+    );
+    Ok(())
+}
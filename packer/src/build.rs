@@ -0,0 +1,353 @@
+//! `packer build --config fleet.toml`: pack many modules in one run.
+//!
+//! ```toml
+//! out_dir = "dist"          # relative to the config file; default "."
+//! index = "bundle.json"     # bundle index written into out_dir
+//!
+//! [defaults]                # any module key except path, id, out and name
+//! sign_key_file = "keys/fleet.pem"
+//! strip = true
+//!
+//! [[module]]
+//! path = "sensor.wasm"
+//! id = 1
+//! name = "sensor"
+//!
+//! [[module]]
+//! path = "report.wasm"
+//! id = 2
+//! entry = "run"
+//! depends = ["sensor:1"]
+//! ```
+//!
+//! Module keys mirror the CLI flags. Every blob is written before the index,
+//! and the first failing module stops the run.
+
+use crate::aot::AotOptions;
+use crate::strip::{strip, StripOptions};
+use crate::{
+    default_extension, pack, parse_hex_key, parse_signing_key, public_key, ManifestInfo,
+    PackOptions,
+};
+use runtime::gate::DeviceState;
+use runtime::manifest::ManifestFormat;
+use runtime::verify::Algorithm;
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Default bundle index file name.
+pub const DEFAULT_INDEX: &str = "bundle.json";
+
+/// A build description.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct BuildConfig {
+    pub out_dir: Option<PathBuf>,
+    pub index: Option<String>,
+    pub defaults: ModuleConfig,
+    #[serde(rename = "module")]
+    pub modules: Vec<ModuleConfig>,
+}
+
+/// One module; unset keys fall back to `[defaults]`, then to the CLI defaults.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ModuleConfig {
+    pub path: Option<PathBuf>,
+    pub id: Option<u32>,
+    /// Blob file name in `out_dir`; default `<stem>.smny`, `.smny.sig` or `.suit`.
+    pub out: Option<String>,
+    pub name: Option<String>,
+    pub entry: Option<String>,
+    pub format: Option<String>,
+    pub sign_key_hex: Option<String>,
+    /// PKCS#8 PEM/DER or hex key file, relative to the config file.
+    pub sign_key_file: Option<PathBuf>,
+    /// `ed25519` (default) or `p256`.
+    pub sign_alg: Option<String>,
+    pub require_signature: Option<bool>,
+    pub sequence: Option<u32>,
+    pub pad_to: Option<usize>,
+    pub allowed_states: Option<Vec<DeviceState>>,
+    pub schedule: Option<String>,
+    pub depends: Option<Vec<String>>,
+    /// Signer certificate files, leaf first, relative to the config file.
+    pub certs: Option<Vec<PathBuf>>,
+    /// Certificates the device holds, named by digest.
+    pub cert_refs: Option<Vec<PathBuf>>,
+    pub strip: Option<bool>,
+    pub keep_exports: Option<Vec<String>>,
+    pub max_size: Option<usize>,
+    pub allowed_imports: Option<Vec<String>>,
+    pub aot: Option<bool>,
+    pub aot_target: Option<String>,
+    /// `wamrc` to run; default `wamrc` from `PATH`.
+    pub wamrc: Option<PathBuf>,
+    pub wamrc_args: Option<Vec<String>>,
+}
+
+impl ModuleConfig {
+    /// Fills unset keys from `defaults`.
+    fn or(self, defaults: &ModuleConfig) -> ModuleConfig {
+        let d = defaults.clone();
+        // A module's own key replaces the default key, whichever form either uses.
+        let (sign_key_hex, sign_key_file) =
+            if self.sign_key_hex.is_some() || self.sign_key_file.is_some() {
+                (self.sign_key_hex, self.sign_key_file)
+            } else {
+                (d.sign_key_hex, d.sign_key_file)
+            };
+        ModuleConfig {
+            path: self.path,
+            id: self.id,
+            out: self.out,
+            name: self.name,
+            entry: self.entry.or(d.entry),
+            format: self.format.or(d.format),
+            sign_key_hex,
+            sign_key_file,
+            sign_alg: self.sign_alg.or(d.sign_alg),
+            require_signature: self.require_signature.or(d.require_signature),
+            sequence: self.sequence.or(d.sequence),
+            pad_to: self.pad_to.or(d.pad_to),
+            allowed_states: self.allowed_states.or(d.allowed_states),
+            schedule: self.schedule.or(d.schedule),
+            depends: self.depends.or(d.depends),
+            certs: self.certs.or(d.certs),
+            cert_refs: self.cert_refs.or(d.cert_refs),
+            strip: self.strip.or(d.strip),
+            keep_exports: self.keep_exports.or(d.keep_exports),
+            max_size: self.max_size.or(d.max_size),
+            allowed_imports: self.allowed_imports.or(d.allowed_imports),
+            aot: self.aot.or(d.aot),
+            aot_target: self.aot_target.or(d.aot_target),
+            wamrc: self.wamrc.or(d.wamrc),
+            wamrc_args: self.wamrc_args.or(d.wamrc_args),
+        }
+    }
+}
+
+/// A bundle index entry: the blob's file name plus its `ManifestInfo`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct BundleEntry {
+    pub file: String,
+    #[serde(flatten)]
+    pub info: ManifestInfo,
+}
+
+/// The bundle index written next to the blobs.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct BundleIndex {
+    pub modules: Vec<BundleEntry>,
+}
+
+/// Reads `config`, packs every module into its `out_dir` and writes the
+/// bundle index; returns the index and where it was written.
+pub fn build(config: &Path) -> Result<(BundleIndex, PathBuf), String> {
+    let text = fs::read_to_string(config)
+        .map_err(|err| format!("cannot read {}: {err}", config.display()))?;
+    let parsed: BuildConfig =
+        toml::from_str(&text).map_err(|err| format!("{}: {err}", config.display()))?;
+    let base = config.parent().unwrap_or(Path::new(""));
+    build_from(&parsed, base)
+}
+
+/// `build` for an already parsed config; relative paths resolve against `base`.
+pub fn build_from(config: &BuildConfig, base: &Path) -> Result<(BundleIndex, PathBuf), String> {
+    let d = &config.defaults;
+    if d.path.is_some() || d.id.is_some() || d.out.is_some() || d.name.is_some() {
+        return Err("[defaults] cannot set path, id, out or name".into());
+    }
+    if config.modules.is_empty() {
+        return Err("no [[module]] entries".into());
+    }
+    let out_dir = base.join(config.out_dir.as_deref().unwrap_or(Path::new(".")));
+    fs::create_dir_all(&out_dir)
+        .map_err(|err| format!("cannot create {}: {err}", out_dir.display()))?;
+
+    let mut modules: Vec<BundleEntry> = Vec::new();
+    for module in &config.modules {
+        let module = module.clone().or(d);
+        let entry = pack_module(&module, base, &out_dir)
+            .map_err(|err| format!("module {}: {err}", describe(&module)))?;
+        if modules.iter().any(|other| other.file == entry.file) {
+            return Err(format!("two modules write {}", entry.file));
+        }
+        modules.push(entry);
+    }
+
+    let index = BundleIndex { modules };
+    let index_path = out_dir.join(config.index.as_deref().unwrap_or(DEFAULT_INDEX));
+    let json = serde_json::to_string_pretty(&index).map_err(|err| err.to_string())? + "\n";
+    fs::write(&index_path, json)
+        .map_err(|err| format!("cannot write {}: {err}", index_path.display()))?;
+    Ok((index, index_path))
+}
+
+fn pack_module(module: &ModuleConfig, base: &Path, out_dir: &Path) -> Result<BundleEntry, String> {
+    let path = module.path.as_deref().ok_or("missing path")?;
+    let module_id = module.id.ok_or("missing id")?;
+    let input = base.join(path);
+    let mut bytes =
+        fs::read(&input).map_err(|err| format!("cannot read {}: {err}", input.display()))?;
+
+    let format = match module.format.as_deref() {
+        Some(format) => format
+            .parse::<ManifestFormat>()
+            .map_err(|err| err.to_string())?,
+        None => ManifestFormat::Smny,
+    };
+    let sign_alg = match module.sign_alg.as_deref() {
+        Some(alg) => alg.parse::<Algorithm>().map_err(|err| err.to_string())?,
+        None => Algorithm::Ed25519,
+    };
+    let sign_key = match (&module.sign_key_hex, &module.sign_key_file) {
+        (Some(_), Some(_)) => return Err("set sign_key_hex or sign_key_file, not both".into()),
+        (Some(hex), None) => Some(parse_hex_key(hex)?),
+        (None, Some(file)) => {
+            let file = base.join(file);
+            let bytes =
+                fs::read(&file).map_err(|err| format!("cannot read {}: {err}", file.display()))?;
+            Some(parse_signing_key(&bytes, sign_alg)?)
+        }
+        (None, None) => None,
+    };
+    let read_all = |files: &Option<Vec<PathBuf>>| -> Result<Vec<Vec<u8>>, String> {
+        let files = files.as_deref().unwrap_or_default();
+        files
+            .iter()
+            .map(|file| {
+                let file = base.join(file);
+                fs::read(&file).map_err(|err| format!("cannot read {}: {err}", file.display()))
+            })
+            .collect()
+    };
+    let opts = PackOptions {
+        format,
+        module_id,
+        entry: module.entry.clone().unwrap_or_else(|| "main".into()),
+        sign_key,
+        sign_alg,
+        require_signature: module.require_signature.unwrap_or(false),
+        sequence: module.sequence.unwrap_or(0),
+        pad_to: module.pad_to,
+        allowed_states: module.allowed_states.clone().unwrap_or_default(),
+        schedule: module.schedule.clone(),
+        name: module.name.clone(),
+        depends: module.depends.clone().unwrap_or_default(),
+        certs: read_all(&module.certs)?,
+        cert_refs: read_all(&module.cert_refs)?,
+        max_size: module.max_size,
+        allowed_imports: module.allowed_imports.clone(),
+        aot: module.aot.unwrap_or(false).then(|| AotOptions {
+            wamrc: module.wamrc.clone(),
+            target: module.aot_target.clone(),
+            args: module.wamrc_args.clone().unwrap_or_default(),
+        }),
+    };
+
+    if module.strip.unwrap_or(false) {
+        let strip_opts = StripOptions {
+            keep_exports: module.keep_exports.clone().unwrap_or_default(),
+            ..StripOptions::default()
+        };
+        bytes = strip(&bytes, &opts.entry, &strip_opts)?.0;
+    }
+    let packed = pack(&bytes, &opts)?;
+
+    let file = match &module.out {
+        Some(out) => out.clone(),
+        None => {
+            let stem = path.file_stem().unwrap_or(path.as_os_str());
+            format!(
+                "{}.{}",
+                stem.to_string_lossy(),
+                default_extension(format, packed.signed)
+            )
+        }
+    };
+    let out = out_dir.join(&file);
+    fs::write(&out, &packed.blob)
+        .map_err(|err| format!("cannot write {}: {err}", out.display()))?;
+
+    let signer = sign_key.map(|key| public_key(sign_alg, &key)).transpose()?;
+    let info = ManifestInfo::from_blob(&packed.blob, signer.as_deref())?;
+    Ok(BundleEntry { file, info })
+}
+
+fn describe(module: &ModuleConfig) -> String {
+    match (&module.path, module.id) {
+        (Some(path), _) => path.display().to_string(),
+        (None, Some(id)) => format!("#{id}"),
+        (None, None) => "<unnamed>".into(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn builds_every_module_and_the_index() {
+        let dir = std::env::temp_dir().join(format!("packer-build-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join("a.wasm"), b"\0asm\x01\0\0\0").unwrap();
+        fs::write(dir.join("a.key"), hex::encode([3u8; 32]) + "\n").unwrap();
+        let exports = wat::parse_str(r#"(module (func (export "run")) (func (export "dbg")))"#);
+        fs::write(dir.join("b.wasm"), exports.unwrap()).unwrap();
+        let config = dir.join("fleet.toml");
+        fs::write(
+            &config,
+            r#"
+            out_dir = "dist"
+
+            [defaults]
+            sign_key_hex = "0909090909090909090909090909090909090909090909090909090909090909"
+            sequence = 4
+
+            [[module]]
+            path = "a.wasm"
+            id = 1
+            name = "a"
+            sign_key_file = "a.key"
+
+            [[module]]
+            path = "b.wasm"
+            id = 2
+            entry = "run"
+            strip = true
+            sign_key_hex = ""
+            sequence = 5
+            "#,
+        )
+        .unwrap();
+
+        // An empty key is not a key: the override fails rather than going unsigned.
+        assert!(build(&config).unwrap_err().contains("b.wasm"));
+
+        let text = fs::read_to_string(&config).unwrap();
+        fs::write(&config, text.replace("sign_key_hex = \"\"\n", "")).unwrap();
+        let (index, index_path) = build(&config).unwrap();
+        assert_eq!(index_path, dir.join("dist").join(DEFAULT_INDEX));
+        let files: Vec<&str> = index.modules.iter().map(|m| m.file.as_str()).collect();
+        assert_eq!(files, ["a.smny.sig", "b.smny.sig"]);
+        let b = &index.modules[1].info;
+        assert_eq!((b.module_id, b.entry.as_str(), b.sequence), (2, "run", 5));
+        assert!(b.signer_key_id.is_some());
+        assert_ne!(index.modules[0].info.signer_key_id, b.signer_key_id);
+        assert!((b.size as usize) < fs::read(dir.join("b.wasm")).unwrap().len());
+
+        let json = fs::read_to_string(&index_path).unwrap();
+        assert!(json.contains("\"file\": \"a.smny.sig\""));
+        assert!(json.contains("\"module_id\": 1"));
+
+        fs::write(
+            &config,
+            "[[module]]\npath = \"a.wasm\"\nid = 1\ncompress = true\n",
+        )
+        .unwrap();
+        assert!(build(&config).unwrap_err().contains("compress"));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
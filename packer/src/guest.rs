@@ -0,0 +1,248 @@
+//! Building guest crates into ready-to-pack modules (`slimmy-guest-build`).
+//!
+//! Runs `cargo build` for `wasm32-unknown-unknown` with the profile a tiny
+//! guest needs – `panic = "abort"`, fat LTO, `opt-level = "z"`, one codegen
+//! unit, no debug info – passed as `--config` overrides, so the guest's own
+//! `Cargo.toml` (see `guest-wasm/template`) needs none of it. The cdylib
+//! cargo reports is then stripped of custom sections (and, on request, of
+//! exports nothing on the device calls, see `strip`) and can go straight to
+//! `packer`.
+
+use crate::strip::{strip, StripOptions, StripReport};
+use std::fs;
+use std::path::PathBuf;
+use std::process::{Command, Stdio};
+
+/// Target guests are built for.
+pub const DEFAULT_TARGET: &str = "wasm32-unknown-unknown";
+/// Cargo profile the overrides are applied to.
+pub const DEFAULT_PROFILE: &str = "release";
+
+/// `profile.<name>.<key> = <value>` overrides for every build.
+const PROFILE: &[(&str, &str)] = &[
+    ("panic", "\"abort\""),
+    ("lto", "true"),
+    ("opt-level", "\"z\""),
+    ("codegen-units", "1"),
+    ("debug", "false"),
+    ("strip", "true"),
+];
+
+/// How to build a guest.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct GuestBuild {
+    /// `Cargo.toml` of the guest or its workspace; default: cargo's lookup
+    /// from the current directory.
+    pub manifest_path: Option<PathBuf>,
+    /// Package to build in a workspace.
+    pub package: Option<String>,
+    pub features: Vec<String>,
+    pub no_default_features: bool,
+    pub target: String,
+    pub profile: String,
+    /// `-C target-cpu`, e.g. `mvp` for engines without post-MVP features
+    /// (bulk memory, reference types, sign extension).
+    pub target_cpu: Option<String>,
+    /// Export the module must keep; also what `strip_exports` keeps.
+    pub entry: String,
+    /// Also drop exports other than `entry`, `RUNTIME_EXPORTS` and
+    /// `keep_exports`, and stub what only they reached.
+    pub strip_exports: bool,
+    pub keep_exports: Vec<String>,
+    /// `cargo` to run; default `$CARGO`, then `cargo` from `PATH`.
+    pub cargo: Option<PathBuf>,
+}
+
+impl Default for GuestBuild {
+    fn default() -> Self {
+        Self {
+            manifest_path: None,
+            package: None,
+            features: Vec::new(),
+            no_default_features: false,
+            target: DEFAULT_TARGET.into(),
+            profile: DEFAULT_PROFILE.into(),
+            target_cpu: None,
+            entry: "main".into(),
+            strip_exports: false,
+            keep_exports: Vec::new(),
+            cargo: None,
+        }
+    }
+}
+
+/// A built and stripped guest.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BuiltGuest {
+    /// The cdylib as cargo wrote it.
+    pub artifact: PathBuf,
+    /// The module to pack.
+    pub module: Vec<u8>,
+    pub report: StripReport,
+}
+
+impl GuestBuild {
+    /// Arguments passed to `cargo`.
+    pub fn cargo_args(&self) -> Vec<String> {
+        let mut args: Vec<String> = ["build", "--lib", "--message-format=json-render-diagnostics"]
+            .map(String::from)
+            .into();
+        args.extend(["--target".into(), self.target.clone()]);
+        args.extend(["--profile".into(), self.profile.clone()]);
+        if let Some(path) = &self.manifest_path {
+            args.extend(["--manifest-path".into(), path.display().to_string()]);
+        }
+        if let Some(package) = &self.package {
+            args.extend(["--package".into(), package.clone()]);
+        }
+        if self.no_default_features {
+            args.push("--no-default-features".into());
+        }
+        if !self.features.is_empty() {
+            args.extend(["--features".into(), self.features.join(",")]);
+        }
+        for (key, value) in PROFILE {
+            args.extend([
+                "--config".into(),
+                format!("profile.{}.{key}={value}", self.profile),
+            ]);
+        }
+        if let Some(cpu) = &self.target_cpu {
+            args.extend([
+                "--config".into(),
+                format!(
+                    "target.{}.rustflags=[\"-C\", \"target-cpu={cpu}\"]",
+                    self.target
+                ),
+            ]);
+        }
+        args
+    }
+
+    /// Builds the guest and strips the module cargo produced.
+    pub fn run(&self) -> Result<BuiltGuest, String> {
+        let cargo = self
+            .cargo
+            .clone()
+            .or_else(|| std::env::var_os("CARGO").map(PathBuf::from))
+            .unwrap_or(PathBuf::from("cargo"));
+        // Diagnostics go to stderr as rendered text; stdout carries the JSON.
+        let out = Command::new(&cargo)
+            .args(self.cargo_args())
+            .stderr(Stdio::inherit())
+            .output()
+            .map_err(|err| format!("guest: cannot run {}: {err}", cargo.display()))?;
+        if !out.status.success() {
+            return Err(format!("guest: cargo build failed ({})", out.status));
+        }
+
+        let artifact = wasm_artifact(&String::from_utf8_lossy(&out.stdout))?;
+        let bytes = fs::read(&artifact)
+            .map_err(|err| format!("guest: cannot read {}: {err}", artifact.display()))?;
+        let opts = StripOptions {
+            custom_sections: true,
+            dead_exports: self.strip_exports,
+            keep_exports: self.keep_exports.clone(),
+        };
+        let (module, report) =
+            strip(&bytes, &self.entry, &opts).map_err(|err| format!("guest: {err}"))?;
+        Ok(BuiltGuest {
+            artifact,
+            module,
+            report,
+        })
+    }
+}
+
+/// The one cdylib `.wasm` among cargo's `compiler-artifact` messages.
+fn wasm_artifact(messages: &str) -> Result<PathBuf, String> {
+    let mut found: Vec<PathBuf> = Vec::new();
+    for line in messages.lines() {
+        let Ok(message) = serde_json::from_str::<serde_json::Value>(line) else {
+            continue;
+        };
+        let cdylib = message["target"]["crate_types"]
+            .as_array()
+            .is_some_and(|types| types.iter().any(|t| t == "cdylib"));
+        if message["reason"] != "compiler-artifact" || !cdylib {
+            continue;
+        }
+        let files = message["filenames"].as_array().into_iter().flatten();
+        found.extend(
+            files
+                .filter_map(|file| file.as_str())
+                .filter(|file| file.ends_with(".wasm"))
+                .map(PathBuf::from),
+        );
+    }
+    match found.len() {
+        1 => Ok(found.remove(0)),
+        0 => Err("guest: cargo built no cdylib (crate-type = [\"cdylib\"])".into()),
+        n => Err(format!(
+            "guest: cargo built {n} cdylibs; pick one with --package"
+        )),
+    }
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+    use std::os::unix::fs::PermissionsExt;
+
+    #[test]
+    fn builds_with_tiny_profile_and_strips_the_artifact() {
+        let dir = std::env::temp_dir().join(format!("packer-guest-test-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let wasm = dir.join("guest.wasm");
+        let module = wat::parse_str(
+            r#"(module (func (export "main")) (func (export "debug")) (@custom "note" "x"))"#,
+        )
+        .unwrap();
+        fs::write(&wasm, &module).unwrap();
+        // Stand-in cargo: records its arguments and reports the module as a cdylib.
+        let cargo = dir.join("cargo");
+        let script = format!(
+            "#!/bin/sh\necho \"$@\" > {dir}/args\n\
+             echo '{{\"reason\":\"compiler-artifact\",\"target\":{{\"crate_types\":[\"lib\"]}},\"filenames\":[\"{dir}/dep.rlib\"]}}'\n\
+             echo '{{\"reason\":\"compiler-artifact\",\"target\":{{\"crate_types\":[\"cdylib\",\"rlib\"]}},\"filenames\":[\"{dir}/libguest.rlib\",\"{wasm}\"]}}'\n\
+             echo '{{\"reason\":\"build-finished\",\"success\":true}}'\n",
+            dir = dir.display(),
+            wasm = wasm.display()
+        );
+        fs::write(&cargo, script).unwrap();
+        fs::set_permissions(&cargo, fs::Permissions::from_mode(0o755)).unwrap();
+
+        let build = GuestBuild {
+            package: Some("sensor".into()),
+            target_cpu: Some("mvp".into()),
+            cargo: Some(cargo),
+            ..GuestBuild::default()
+        };
+        let built = build.run().unwrap();
+        assert_eq!(built.artifact, wasm);
+        assert_eq!(built.report.custom_sections, 1);
+        assert_eq!(built.report.exports, 0);
+        assert!(built.module.len() < module.len());
+
+        let args = fs::read_to_string(dir.join("args")).unwrap();
+        assert!(args.starts_with("build --lib"));
+        assert!(args.contains("--target wasm32-unknown-unknown --profile release"));
+        assert!(args.contains("--package sensor"));
+        assert!(args.contains("profile.release.panic=\"abort\""));
+        assert!(args.contains("profile.release.lto=true"));
+        assert!(args.contains("target-cpu=mvp"));
+
+        let exports = GuestBuild {
+            strip_exports: true,
+            ..build.clone()
+        };
+        assert_eq!(exports.run().unwrap().report.exports, 1);
+        let entry = GuestBuild {
+            entry: "run".into(),
+            ..build
+        };
+        assert!(entry.run().unwrap_err().contains("`run`"));
+        assert!(wasm_artifact("").unwrap_err().contains("no cdylib"));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
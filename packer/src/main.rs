@@ -1,21 +1,66 @@
-use clap::Parser;
-use ed25519_dalek::Signer;
-use runtime::manifest::{
-    encode, signing_preimage, FLAG_REQUIRE_SIGNATURE, FLAG_ROLLBACK_PROTECTED,
+use clap::{Parser, Subcommand};
+use packer::aot::AotOptions;
+use packer::strip::{strip, StripOptions};
+#[cfg(feature = "serde")]
+use packer::ManifestInfo;
+use packer::{
+    attach_signature, default_extension, issue_certificate, issue_revocation_list, pack,
+    parse_hex_key, parse_module_ids, parse_public_key, parse_signature, parse_signing_key, presign,
+    public_key, sign_key_from_env, PackOptions, Packed,
 };
+use runtime::gate::DeviceState;
+use runtime::manifest::ManifestFormat;
+use runtime::verify::Algorithm;
 use std::fs;
-use std::io;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 
 #[derive(Parser, Debug)]
 #[command(
     name = "packer",
-    about = "Bundle a WASM module into a signed manifest blob."
+    about = "Bundle a WASM module into a signed manifest blob.",
+    args_conflicts_with_subcommands = true,
+    subcommand_negates_reqs = true
 )]
 struct Args {
+    #[command(subcommand)]
+    command: Option<Command>,
+
+    #[command(flatten)]
+    pack: PackArgs,
+
+    /// Output file path
+    #[arg(short, long)]
+    out: Option<PathBuf>,
+
+    /// Optional hex-encoded 32-byte secret key (`--sign-alg`) to sign the blob
+    #[arg(long, value_name = "HEX32")]
+    sign_key_hex: Option<String>,
+
+    /// Secret key file: PKCS#8 PEM or DER, SEC1 `EC PRIVATE KEY` PEM (P-256), or hex.
+    /// Without a key flag the key is read from `SLIMMY_SIGN_KEY` (PEM or hex), if set
+    #[arg(long, value_name = "PATH", conflicts_with = "sign_key_hex")]
+    sign_key_file: Option<PathBuf>,
+
+    /// Also write a BLAKE3 outboard tree (<out>.outboard) for verified streaming of the blob
+    #[arg(long, default_value_t = false)]
+    emit_outboard: bool,
+
+    /// Also write a JSON sidecar (<out>.json): id, entry, digest, size, signature, signer key id
+    #[cfg(feature = "serde")]
+    #[arg(long, default_value_t = false)]
+    emit_json: bool,
+}
+
+/// The module and the manifest it is packed into; shared by every way of packing.
+#[derive(clap::Args, Debug)]
+struct PackArgs {
     /// Path to the input .wasm module
-    #[arg(value_name = "MODULE")]
-    module: PathBuf,
+    #[arg(value_name = "MODULE", required = true)]
+    module: Option<PathBuf>,
+
+    /// Manifest encoding: `smny` (compact) or `suit` (CBOR SUIT envelope, COSE signature)
+    #[arg(long, default_value = "smny")]
+    format: ManifestFormat,
 
     /// Module id to embed in the manifest
     #[arg(long, default_value_t = 1)]
@@ -25,13 +70,9 @@ struct Args {
     #[arg(long, default_value = "main")]
     entry: String,
 
-    /// Output file path
-    #[arg(short, long)]
-    out: Option<PathBuf>,
-
-    /// Optional hex-encoded 32-byte Ed25519 secret key to sign the blob
-    #[arg(long, value_name = "HEX32")]
-    sign_key_hex: Option<String>,
+    /// Signature algorithm: `ed25519` or `p256` (ECDSA P-256/SHA-256, SMNY only)
+    #[arg(long, default_value = "ed25519")]
+    sign_alg: Algorithm,
 
     /// Require signature flag in manifest (fails if no signing key provided)
     #[arg(long, default_value_t = false)]
@@ -44,117 +85,427 @@ struct Args {
     /// Pad module to the next multiple of this many bytes (useful for flash erase blocks)
     #[arg(long, value_name = "N")]
     pad_to: Option<usize>,
+
+    /// Strip custom sections and unused exports and stub unreachable functions before packing
+    #[arg(long, default_value_t = false)]
+    strip: bool,
+
+    /// Export kept by `--strip` besides the entry, `memory`, `health`, `slimmy_alloc`, `slimmy_free` and `__slimmy_info` (repeatable)
+    #[arg(long = "keep-export", value_name = "NAME", requires = "strip")]
+    keep_exports: Vec<String>,
+
+    /// Fail if the module (after `--strip`, before padding) is larger than this many bytes
+    #[arg(long, value_name = "BYTES")]
+    max_size: Option<usize>,
+
+    /// Function import the module may use: `NAME` (in `env`), `MODULE.NAME` or `MODULE.*`;
+    /// when given, any other import fails packing (repeatable, comma-separated)
+    #[arg(long = "allow-import", value_name = "IMPORT", value_delimiter = ',')]
+    allowed_imports: Vec<String>,
+
+    /// Compile the module with WAMR's `wamrc` and mark the manifest as an AOT payload
+    #[arg(long, default_value_t = false)]
+    aot: bool,
+
+    /// `wamrc --target` for the device, e.g. `thumbv7em`, `xtensa`, `riscv32`
+    #[arg(long, value_name = "TARGET", requires = "aot")]
+    aot_target: Option<String>,
+
+    /// `wamrc` to run (default: `wamrc` from PATH)
+    #[arg(long, value_name = "PATH", requires = "aot")]
+    wamrc: Option<PathBuf>,
+
+    /// Extra `wamrc` argument, e.g. `--wamrc-arg=--cpu=cortex-m4` (repeatable)
+    #[arg(
+        long = "wamrc-arg",
+        value_name = "ARG",
+        requires = "aot",
+        allow_hyphen_values = true
+    )]
+    wamrc_args: Vec<String>,
+
+    /// Device states the module may run in, e.g. `0,2` (emits a v3 manifest; default: any)
+    #[arg(long, value_name = "STATES", value_delimiter = ',')]
+    allowed_states: Vec<DeviceState>,
+
+    /// Schedule carried in the manifest, e.g. `"0 2 * * * report"` or `"@every 30s"`
+    #[arg(long, value_name = "SPEC")]
+    schedule: Option<String>,
+
+    /// Module name other manifests can depend on (emits a v3 manifest)
+    #[arg(long, value_name = "NAME")]
+    name: Option<String>,
+
+    /// Dependency as module id or name with optional minimum version, e.g. `7:3` or `net:2` (repeatable)
+    #[arg(long = "depends", value_name = "TARGET[:MINVER]")]
+    depends: Vec<String>,
+
+    /// Signer certificate to embed, leaf first then its issuers; the blob then verifies
+    /// against a root key instead of the signing key (repeatable)
+    #[arg(long = "cert", value_name = "PATH")]
+    certs: Vec<PathBuf>,
+
+    /// Certificate the device already holds, named by its SHA-256 after the embedded ones
+    /// (repeatable)
+    #[arg(long = "cert-ref", value_name = "PATH")]
+    cert_refs: Vec<PathBuf>,
+}
+
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Pack every module described in a TOML build file and write a bundle index
+    #[cfg(feature = "serde")]
+    Build {
+        /// Build description (see `packer::build`)
+        #[arg(long, value_name = "FILE")]
+        config: PathBuf,
+    },
+    /// Write the message an external signer (HSM, KMS) signs with `--sign-alg`
+    Presign {
+        #[command(flatten)]
+        pack: PackArgs,
+
+        /// Output file path (default: <MODULE>.preimage)
+        #[arg(short, long)]
+        out: Option<PathBuf>,
+    },
+    /// Pack with a signature made elsewhere over `presign`'s output; pass the same options
+    AttachSig {
+        #[command(flatten)]
+        pack: PackArgs,
+
+        /// Signature as returned by the signer: raw 64 bytes or hex, or DER for P-256
+        #[arg(long, value_name = "PATH")]
+        signature: PathBuf,
+
+        /// Hex-encoded public key the signature must verify against: 32 bytes for
+        /// Ed25519, a SEC1 point for P-256
+        #[arg(long, value_name = "HEX")]
+        pubkey: String,
+
+        /// Output file path
+        #[arg(short, long)]
+        out: Option<PathBuf>,
+    },
+    /// Issue a signer certificate for a public key (see `runtime::cert`)
+    IssueCert {
+        /// Issuer secret key file: PKCS#8 PEM or DER, SEC1 PEM (P-256), or hex
+        #[arg(long, value_name = "PATH")]
+        issuer_key_file: PathBuf,
+
+        /// Issuer key algorithm: `ed25519` or `p256`
+        #[arg(long, default_value = "ed25519")]
+        issuer_alg: Algorithm,
+
+        /// Hex-encoded subject public key: 32 bytes for Ed25519, a SEC1 point for P-256
+        #[arg(long, value_name = "HEX")]
+        subject_pubkey: String,
+
+        /// Serial number, for revocation
+        #[arg(long, default_value_t = 0)]
+        serial: u32,
+
+        /// Module ids the subject may sign, `MIN-MAX` or one id (default: all)
+        #[arg(long, value_name = "MIN-MAX", default_value = "0-4294967295")]
+        module_ids: String,
+
+        /// Let the subject issue certificates (an intermediate)
+        #[arg(long, default_value_t = false)]
+        ca: bool,
+
+        /// Output file path
+        #[arg(short, long)]
+        out: PathBuf,
+    },
+    /// Issue a signed revocation list (see `runtime::revoke`)
+    Revoke {
+        /// List signer secret key file: PKCS#8 PEM or DER, SEC1 PEM (P-256), or hex
+        #[arg(long, value_name = "PATH")]
+        key_file: PathBuf,
+
+        /// Signer key algorithm: `ed25519` or `p256`
+        #[arg(long, default_value = "ed25519")]
+        sign_alg: Algorithm,
+
+        /// Sequence number; must exceed the one of the list devices hold
+        #[arg(long)]
+        sequence: u32,
+
+        /// Module file or packed blob to revoke (repeatable)
+        #[arg(long = "module", value_name = "PATH")]
+        modules: Vec<PathBuf>,
+
+        /// Hex-encoded signing public key to revoke (repeatable)
+        #[arg(long = "pubkey", value_name = "HEX")]
+        pubkeys: Vec<String>,
+
+        /// Output file path
+        #[arg(short, long)]
+        out: PathBuf,
+    },
 }
 
 fn main() -> Result<(), Box<dyn std::error::Error>> {
     let args = Args::parse();
-
-    let mut module_bytes = fs::read(&args.module)?;
-    if let Some(block) = args.pad_to {
-        if block == 0 {
-            return Err("pad_to must be > 0".into());
+    match args.command {
+        #[cfg(feature = "serde")]
+        Some(Command::Build { config }) => return build(&config),
+        Some(Command::Presign { pack, out }) => {
+            let (module_path, module_bytes, opts) = pack.load(None)?;
+            let preimage = presign(&module_bytes, &opts)?;
+            let out_path = out.unwrap_or_else(|| module_path.with_extension("preimage"));
+            fs::write(&out_path, &preimage)?;
+            println!(
+                "✍️  preimage: len={} blake3={} -> {}",
+                preimage.len(),
+                hex::encode(blake3::hash(&preimage).as_bytes()),
+                out_path.display()
+            );
+            return Ok(());
         }
-        let padded = pad_to(module_bytes.len(), block);
-        if padded > module_bytes.len() {
-            module_bytes.resize(padded, 0xFF);
+        Some(Command::AttachSig {
+            pack,
+            signature,
+            pubkey,
+            out,
+        }) => {
+            let (module_path, module_bytes, opts) = pack.load(None)?;
+            let signature = parse_signature(&fs::read(&signature)?)
+                .map_err(|err| format!("{}: {err}", signature.display()))?;
+            let pubkey = hex::decode(pubkey.trim()).map_err(|_| "--pubkey must be hex")?;
+            let packed = attach_signature(&module_bytes, &opts, &signature, &pubkey)?;
+            let out_path = out.unwrap_or_else(|| default_out_path(&module_path, opts.format, true));
+            fs::write(&out_path, &packed.blob)?;
+            report(&opts, &packed, &out_path);
+            return Ok(());
         }
+        Some(Command::IssueCert {
+            issuer_key_file,
+            issuer_alg,
+            subject_pubkey,
+            serial,
+            module_ids,
+            ca,
+            out,
+        }) => {
+            let issuer_key = parse_signing_key(&fs::read(&issuer_key_file)?, issuer_alg)
+                .map_err(|err| format!("{}: {err}", issuer_key_file.display()))?;
+            let subject =
+                hex::decode(subject_pubkey.trim()).map_err(|_| "--subject-pubkey must be hex")?;
+            let subject = parse_public_key(&subject)?;
+            let module_ids = parse_module_ids(&module_ids)?;
+            let cert = issue_certificate(
+                issuer_alg,
+                &issuer_key,
+                &subject,
+                serial,
+                module_ids.clone(),
+                ca,
+            )?;
+            fs::write(&out, &cert)?;
+            println!(
+                "📜 certificate: serial={serial} modules={}-{} ca={ca} sha256={} -> {}",
+                module_ids.start(),
+                module_ids.end(),
+                hex::encode(runtime::cert::digest(&cert)),
+                out.display()
+            );
+            return Ok(());
+        }
+        Some(Command::Revoke {
+            key_file,
+            sign_alg,
+            sequence,
+            modules,
+            pubkeys,
+            out,
+        }) => {
+            let key = parse_signing_key(&fs::read(&key_file)?, sign_alg)
+                .map_err(|err| format!("{}: {err}", key_file.display()))?;
+            let modules = modules
+                .iter()
+                .map(fs::read)
+                .collect::<Result<Vec<_>, _>>()?;
+            let pubkeys = pubkeys
+                .iter()
+                .map(|key| hex::decode(key.trim()).map_err(|_| "--pubkey must be hex"))
+                .collect::<Result<Vec<_>, _>>()?;
+            let list = issue_revocation_list(sign_alg, &key, sequence, &modules, &pubkeys)?;
+            fs::write(&out, &list)?;
+            println!(
+                "🚫 revocation list: sequence={sequence} modules={} keys={} -> {}",
+                modules.len(),
+                pubkeys.len(),
+                out.display()
+            );
+            return Ok(());
+        }
+        None => {}
     }
 
-    if args.require_signature && args.sign_key_hex.is_none() {
-        return Err("require_signature set but no signing key provided".into());
-    }
-
-    let mut flags = 0u8;
-    if args.require_signature || args.sign_key_hex.is_some() {
-        flags |= FLAG_REQUIRE_SIGNATURE;
-    }
-    if args.sequence > 0 {
-        flags |= FLAG_ROLLBACK_PROTECTED;
-    }
-
-    let signature = if let Some(hex_key) = args.sign_key_hex.as_deref() {
-        let key_bytes = parse_hex_key(hex_key)?;
-        let signing = ed25519_dalek::SigningKey::from_bytes(&key_bytes);
-
-        let preimage = signing_preimage(
-            args.module_id,
-            &args.entry,
-            &module_bytes,
-            flags,
-            args.sequence,
-        )
-        .map_err(to_io_error)?;
-        let sig = signing.sign(&preimage).to_bytes();
-        Some(sig)
-    } else {
-        None
+    let sign_alg = args.pack.sign_alg;
+    let sign_key = match (&args.sign_key_hex, &args.sign_key_file) {
+        (Some(hex), _) => Some(parse_hex_key(hex)?),
+        (None, Some(path)) => Some(
+            parse_signing_key(&fs::read(path)?, sign_alg)
+                .map_err(|err| format!("{}: {err}", path.display()))?,
+        ),
+        (None, None) => sign_key_from_env(sign_alg)?,
     };
-
-    let blob = encode(
-        args.module_id,
-        &args.entry,
-        &module_bytes,
-        flags,
-        args.sequence,
-        signature,
-    )
-    .map_err(to_io_error)?;
+    let (module_path, module_bytes, opts) = args.pack.load(sign_key)?;
+    let packed = pack(&module_bytes, &opts)?;
+    let blob = &packed.blob;
 
     let out_path = args
         .out
-        .unwrap_or_else(|| default_out_path(&args.module, signature.is_some()));
+        .unwrap_or_else(|| default_out_path(&module_path, opts.format, packed.signed));
     fs::write(&out_path, blob)?;
 
-    println!(
-        "✅ packed module: id={} entry={} signed={} seq={} flags=0x{:02x} len={} -> {}",
-        args.module_id,
-        args.entry,
-        signature.is_some(),
-        args.sequence,
-        flags,
-        module_bytes.len(),
-        out_path.display()
-    );
+    if args.emit_outboard {
+        let (root, tree) = runtime::stream::outboard(blob);
+        let tree_path = sidecar_path(&out_path, "outboard");
+        fs::write(&tree_path, tree)?;
+        println!(
+            "🌳 outboard: root={} len={} -> {}",
+            hex::encode(root),
+            blob.len(),
+            tree_path.display()
+        );
+    }
 
+    #[cfg(feature = "serde")]
+    if args.emit_json {
+        let signer = sign_key.map(|key| public_key(sign_alg, &key)).transpose()?;
+        let info = ManifestInfo::from_blob(blob, signer.as_deref())?;
+        let json_path = sidecar_path(&out_path, "json");
+        fs::write(&json_path, serde_json::to_string_pretty(&info)? + "\n")?;
+        println!("🧾 json: digest={} -> {}", info.digest, json_path.display());
+    }
+
+    report(&opts, &packed, &out_path);
     Ok(())
 }
 
-fn parse_hex_key(hex: &str) -> Result<[u8; 32], io::Error> {
-    let bytes = hex::decode(hex.trim())
-        .map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "sign_key_hex not valid hex"))?;
-    let arr: [u8; 32] = bytes.try_into().map_err(|_| {
-        io::Error::new(io::ErrorKind::InvalidInput, "sign_key_hex must be 32 bytes")
-    })?;
-    Ok(arr)
+impl PackArgs {
+    /// Reads (and optionally strips) the module and collects the pack options.
+    fn load(
+        self,
+        sign_key: Option<[u8; 32]>,
+    ) -> Result<(PathBuf, Vec<u8>, PackOptions), Box<dyn std::error::Error>> {
+        let module_path = self.module.ok_or("missing MODULE")?;
+        let mut module_bytes = fs::read(&module_path)?;
+        if self.strip {
+            let opts = StripOptions {
+                keep_exports: self.keep_exports,
+                ..StripOptions::default()
+            };
+            let (stripped, report) = strip(&module_bytes, &self.entry, &opts)?;
+            println!(
+                "✂️  stripped: {} -> {} bytes (-{}, {:.1}%): {} custom sections, {} exports, {} dead functions",
+                report.before,
+                report.after,
+                report.saved(),
+                report.saved() as f64 * 100.0 / report.before.max(1) as f64,
+                report.custom_sections,
+                report.exports,
+                report.functions
+            );
+            module_bytes = stripped;
+        }
+        let read_all = |paths: Vec<PathBuf>| -> std::io::Result<Vec<Vec<u8>>> {
+            paths.iter().map(fs::read).collect()
+        };
+        let opts = PackOptions {
+            format: self.format,
+            module_id: self.module_id,
+            entry: self.entry,
+            sign_key,
+            sign_alg: self.sign_alg,
+            require_signature: self.require_signature,
+            sequence: self.sequence,
+            pad_to: self.pad_to,
+            allowed_states: self.allowed_states,
+            schedule: self.schedule,
+            name: self.name,
+            depends: self.depends,
+            certs: read_all(self.certs)?,
+            cert_refs: read_all(self.cert_refs)?,
+            max_size: self.max_size,
+            allowed_imports: (!self.allowed_imports.is_empty()).then_some(self.allowed_imports),
+            aot: self.aot.then_some(AotOptions {
+                wamrc: self.wamrc,
+                target: self.aot_target,
+                args: self.wamrc_args,
+            }),
+        };
+        Ok((module_path, module_bytes, opts))
+    }
 }
 
-fn default_out_path(input: &PathBuf, signed: bool) -> PathBuf {
-    let mut out = input.clone();
-    out.set_extension(if signed { "smny.sig" } else { "smny" });
-    out
+fn report(opts: &PackOptions, packed: &Packed, out_path: &Path) {
+    println!(
+        "✅ packed module: format={:?} id={} entry={} signed={} seq={} flags=0x{:02x} len={} -> {}",
+        opts.format,
+        opts.module_id,
+        opts.entry,
+        packed.signed,
+        opts.sequence,
+        packed.flags,
+        packed.module_len,
+        out_path.display()
+    );
+}
+
+#[cfg(feature = "serde")]
+fn build(config: &Path) -> Result<(), Box<dyn std::error::Error>> {
+    let (index, index_path) = packer::build::build(config)?;
+    for module in &index.modules {
+        println!(
+            "✅ packed module: id={} entry={} signed={} seq={} len={} -> {}",
+            module.info.module_id,
+            module.info.entry,
+            module.info.signature.is_some(),
+            module.info.sequence,
+            module.info.size,
+            module.file
+        );
+    }
+    println!(
+        "📦 bundle: {} modules -> {}",
+        index.modules.len(),
+        index_path.display()
+    );
+    Ok(())
 }
 
-fn to_io_error(err: runtime::Error) -> io::Error {
-    io::Error::new(io::ErrorKind::Other, format!("manifest error: {err}"))
+fn default_out_path(input: &Path, format: ManifestFormat, signed: bool) -> PathBuf {
+    let mut out = input.to_path_buf();
+    out.set_extension(default_extension(format, signed));
+    out
 }
 
-fn pad_to(len: usize, block: usize) -> usize {
-    if block == 0 {
-        len
-    } else {
-        ((len + block - 1) / block) * block
-    }
+/// `<out>.<suffix>`, next to the blob.
+fn sidecar_path(out: &Path, suffix: &str) -> PathBuf {
+    let mut name = out.as_os_str().to_owned();
+    name.push(".");
+    name.push(suffix);
+    PathBuf::from(name)
 }
 
 #[cfg(test)]
 mod tests {
-    use super::pad_to;
+    use super::sidecar_path;
+    use std::path::{Path, PathBuf};
 
     #[test]
-    fn pad_rounds_up() {
-        assert_eq!(pad_to(0, 4096), 0);
-        assert_eq!(pad_to(1, 4), 4);
-        assert_eq!(pad_to(4, 4), 4);
-        assert_eq!(pad_to(5, 4), 8);
+    fn sidecar_path_appends_suffix() {
+        assert_eq!(
+            sidecar_path(Path::new("out/module.smny.sig"), "outboard"),
+            PathBuf::from("out/module.smny.sig.outboard")
+        );
+        assert_eq!(
+            sidecar_path(Path::new("module.smny"), "json"),
+            PathBuf::from("module.smny.json")
+        );
     }
 }
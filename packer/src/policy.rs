@@ -0,0 +1,89 @@
+//! Artifact policy checked by `pack`: a module size budget and an allowlist
+//! of function imports, so CI rejects modules before they ship.
+
+use runtime::abi::IMPORT_MODULE;
+use wasmparser::{Parser, Payload, TypeRef};
+
+/// Fails when `module` is larger than `max_size` bytes.
+pub fn check_size(module: &[u8], max_size: usize) -> Result<(), String> {
+    if module.len() > max_size {
+        return Err(format!(
+            "module is {} bytes, over the {max_size}-byte budget",
+            module.len()
+        ));
+    }
+    Ok(())
+}
+
+/// `(module, name)` of every function `module` imports.
+pub fn function_imports(module: &[u8]) -> Result<Vec<(String, String)>, String> {
+    let mut imports = Vec::new();
+    for payload in Parser::new(0).parse_all(module) {
+        let payload = payload.map_err(|err| format!("invalid wasm: {err}"))?;
+        if let Payload::ImportSection(reader) = payload {
+            for import in reader {
+                let import = import.map_err(|err| format!("invalid wasm: {err}"))?;
+                if matches!(import.ty, TypeRef::Func(_) | TypeRef::FuncExact(_)) {
+                    imports.push((import.module.to_string(), import.name.to_string()));
+                }
+            }
+        }
+    }
+    Ok(imports)
+}
+
+/// Fails when `module` imports a function no `allowed` pattern matches.
+///
+/// Patterns are `NAME` (in the `env` host module), `MODULE.NAME` or
+/// `MODULE.*`.
+pub fn check_imports(module: &[u8], allowed: &[String]) -> Result<(), String> {
+    let denied: Vec<String> = function_imports(module)?
+        .into_iter()
+        .filter(|(module, name)| !allowed.iter().any(|pattern| matches(pattern, module, name)))
+        .map(|(module, name)| format!("{module}.{name}"))
+        .collect();
+    if denied.is_empty() {
+        Ok(())
+    } else {
+        Err(format!(
+            "imports outside the allowlist: {}",
+            denied.join(", ")
+        ))
+    }
+}
+
+fn matches(pattern: &str, module: &str, name: &str) -> bool {
+    let (want_module, want_name) = pattern.split_once('.').unwrap_or((IMPORT_MODULE, pattern));
+    want_module == module && (want_name == "*" || want_name == name)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn checks_size_and_import_allowlist() {
+        let module = wat::parse_str(
+            r#"(module
+                (import "env" "log" (func (param i32)))
+                (import "env" "kv_get" (func (param i32)))
+                (import "wasi" "fd_write" (func (param i32)))
+                (import "env" "mem" (memory 1)))"#,
+        )
+        .unwrap();
+
+        assert_eq!(check_size(&module, module.len()), Ok(()));
+        assert!(check_size(&module, module.len() - 1).is_err());
+
+        let allow = |patterns: &[&str]| {
+            let patterns: Vec<String> = patterns.iter().map(|p| p.to_string()).collect();
+            check_imports(&module, &patterns)
+        };
+        assert_eq!(allow(&["log", "kv_get", "wasi.*"]), Ok(()));
+        assert_eq!(
+            allow(&["env.log"]),
+            Err("imports outside the allowlist: env.kv_get, wasi.fd_write".into())
+        );
+        assert!(allow(&[]).is_err());
+    }
+}
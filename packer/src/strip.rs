@@ -0,0 +1,311 @@
+//! Size reduction before packing (`packer --strip`).
+//!
+//! Drops custom sections (names, producers, DWARF; `slimmy.abi` stays, see
+//! `runtime::abi::version`), exports nothing on the
+//! device calls, and replaces the bodies of functions no kept export, start
+//! function, table element or `ref.func` reaches with a bare `unreachable`.
+//! Function indices stay put, so nothing else needs re-encoding.
+
+use runtime::abi::{version, ALLOC_EXPORT, FREE_EXPORT, INFO_EXPORT};
+use wasm_encoder::{CodeSection, ExportKind, ExportSection, RawSection};
+use wasmparser::{ElementItems, ExternalKind, Operator, OperatorsReader, Parser, Payload, TypeRef};
+
+/// Exports the runtime looks up besides the entry point.
+pub const RUNTIME_EXPORTS: &[&str] = &["memory", "health", ALLOC_EXPORT, FREE_EXPORT, INFO_EXPORT];
+
+/// Body of a stubbed function: no locals, `unreachable`, `end`.
+const STUB_BODY: &[u8] = &[0x00, 0x00, 0x0b];
+
+/// What `strip` removes.
+#[derive(Debug, Clone)]
+pub struct StripOptions {
+    /// Drop every custom section but `slimmy.abi`, the name section included.
+    pub custom_sections: bool,
+    /// Drop exports other than the entry, `RUNTIME_EXPORTS` and `keep_exports`,
+    /// and stub functions left unreachable.
+    pub dead_exports: bool,
+    pub keep_exports: Vec<String>,
+}
+
+impl Default for StripOptions {
+    fn default() -> Self {
+        Self {
+            custom_sections: true,
+            dead_exports: true,
+            keep_exports: Vec::new(),
+        }
+    }
+}
+
+/// Sizes before and after, and what went.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct StripReport {
+    pub before: usize,
+    pub after: usize,
+    pub custom_sections: usize,
+    pub exports: usize,
+    /// Function bodies replaced with `unreachable`.
+    pub functions: usize,
+}
+
+impl StripReport {
+    pub fn saved(&self) -> usize {
+        self.before.saturating_sub(self.after)
+    }
+}
+
+/// Strips `module`, keeping `entry` exported; fails when `entry` is not an
+/// exported function or the module does not parse.
+pub fn strip(
+    module: &[u8],
+    entry: &str,
+    opts: &StripOptions,
+) -> Result<(Vec<u8>, StripReport), String> {
+    let scan = Scan::new(module)?;
+    let keep = |name: &str| {
+        !opts.dead_exports
+            || name == entry
+            || RUNTIME_EXPORTS.contains(&name)
+            || opts.keep_exports.iter().any(|kept| kept == name)
+    };
+    if !scan
+        .exports
+        .iter()
+        .any(|(name, kind, _)| name == entry && is_func(*kind))
+    {
+        return Err(format!("entry `{entry}` is not an exported function"));
+    }
+
+    let mut live = vec![!opts.dead_exports; scan.imported_funcs as usize + scan.bodies.len()];
+    let mut pending: Vec<u32> = scan.roots.clone();
+    pending.extend(
+        scan.exports
+            .iter()
+            .filter(|(name, kind, _)| is_func(*kind) && keep(name))
+            .map(|(_, _, index)| *index),
+    );
+    while let Some(func) = pending.pop() {
+        let Some(seen) = live.get_mut(func as usize) else {
+            return Err(format!("function index {func} out of range"));
+        };
+        if *seen {
+            continue;
+        }
+        *seen = true;
+        if let Some(local) = func.checked_sub(scan.imported_funcs) {
+            pending.extend(&scan.bodies[local as usize].1);
+        }
+    }
+
+    let mut report = StripReport {
+        before: module.len(),
+        ..StripReport::default()
+    };
+    let mut out = wasm_encoder::Module::new();
+    for payload in Parser::new(0).parse_all(module) {
+        let payload = payload.map_err(parse_error)?;
+        match &payload {
+            Payload::CustomSection(section)
+                if opts.custom_sections && section.name() != version::SECTION =>
+            {
+                report.custom_sections += 1;
+            }
+            Payload::ExportSection(_) => {
+                let mut exports = ExportSection::new();
+                for (name, kind, index) in &scan.exports {
+                    if keep(name) {
+                        exports.export(name, export_kind(*kind), *index);
+                    } else {
+                        report.exports += 1;
+                    }
+                }
+                out.section(&exports);
+            }
+            Payload::CodeSectionStart { .. } => {
+                let mut code = CodeSection::new();
+                for (local, (range, _)) in scan.bodies.iter().enumerate() {
+                    if live[scan.imported_funcs as usize + local] {
+                        code.raw(&module[range.clone()]);
+                    } else {
+                        code.raw(STUB_BODY);
+                        report.functions += 1;
+                    }
+                }
+                out.section(&code);
+            }
+            _ => {
+                if let Some((id, range)) = payload.as_section() {
+                    out.section(&RawSection {
+                        id,
+                        data: &module[range],
+                    });
+                }
+            }
+        }
+    }
+    let bytes = out.finish();
+    report.after = bytes.len();
+    Ok((bytes, report))
+}
+
+/// Everything `strip` needs from a first pass over the module.
+#[derive(Default)]
+struct Scan {
+    imported_funcs: u32,
+    exports: Vec<(String, ExternalKind, u32)>,
+    /// Functions live regardless of exports: start, table elements, `ref.func` in globals.
+    roots: Vec<u32>,
+    /// Per defined function: its body and the functions it references.
+    bodies: Vec<(core::ops::Range<usize>, Vec<u32>)>,
+}
+
+impl Scan {
+    fn new(module: &[u8]) -> Result<Self, String> {
+        let mut scan = Scan::default();
+        for payload in Parser::new(0).parse_all(module) {
+            match payload.map_err(parse_error)? {
+                Payload::ImportSection(imports) => {
+                    for import in imports {
+                        if matches!(
+                            import.map_err(parse_error)?.ty,
+                            TypeRef::Func(_) | TypeRef::FuncExact(_)
+                        ) {
+                            scan.imported_funcs += 1;
+                        }
+                    }
+                }
+                Payload::ExportSection(exports) => {
+                    for export in exports {
+                        let export = export.map_err(parse_error)?;
+                        scan.exports
+                            .push((export.name.to_string(), export.kind, export.index));
+                    }
+                }
+                Payload::StartSection { func, .. } => scan.roots.push(func),
+                Payload::GlobalSection(globals) => {
+                    for global in globals {
+                        let reader = global
+                            .map_err(parse_error)?
+                            .init_expr
+                            .get_operators_reader();
+                        referenced(reader, &mut scan.roots)?;
+                    }
+                }
+                Payload::ElementSection(elements) => {
+                    for element in elements {
+                        match element.map_err(parse_error)?.items {
+                            ElementItems::Functions(funcs) => {
+                                for func in funcs {
+                                    scan.roots.push(func.map_err(parse_error)?);
+                                }
+                            }
+                            ElementItems::Expressions(_, exprs) => {
+                                for expr in exprs {
+                                    let reader = expr.map_err(parse_error)?.get_operators_reader();
+                                    referenced(reader, &mut scan.roots)?;
+                                }
+                            }
+                        }
+                    }
+                }
+                Payload::CodeSectionEntry(body) => {
+                    let mut callees = Vec::new();
+                    referenced(
+                        body.get_operators_reader().map_err(parse_error)?,
+                        &mut callees,
+                    )?;
+                    scan.bodies.push((body.range(), callees));
+                }
+                _ => {}
+            }
+        }
+        Ok(scan)
+    }
+}
+
+/// Appends the functions `reader` calls or takes references to.
+fn referenced(reader: OperatorsReader<'_>, out: &mut Vec<u32>) -> Result<(), String> {
+    for op in reader {
+        match op.map_err(parse_error)? {
+            Operator::Call { function_index }
+            | Operator::ReturnCall { function_index }
+            | Operator::RefFunc { function_index } => out.push(function_index),
+            _ => {}
+        }
+    }
+    Ok(())
+}
+
+fn is_func(kind: ExternalKind) -> bool {
+    matches!(kind, ExternalKind::Func | ExternalKind::FuncExact)
+}
+
+fn export_kind(kind: ExternalKind) -> ExportKind {
+    match kind {
+        ExternalKind::Func | ExternalKind::FuncExact => ExportKind::Func,
+        ExternalKind::Table => ExportKind::Table,
+        ExternalKind::Memory => ExportKind::Memory,
+        ExternalKind::Global => ExportKind::Global,
+        ExternalKind::Tag => ExportKind::Tag,
+    }
+}
+
+fn parse_error(err: wasmparser::BinaryReaderError) -> String {
+    format!("invalid wasm: {err}")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn strips_custom_sections_exports_and_dead_code() {
+        let module = wat::parse_str(
+            r#"(module
+                (import "env" "log" (func $log (param i32) (result i32)))
+                (memory (export "memory") 1)
+                (table 1 funcref)
+                (elem (i32.const 0) $indirect)
+                (func $helper (drop (call $log (i32.const 1))))
+                (func $indirect)
+                (func $debug_only (drop (call $log (i32.const 2))) (drop (call $log (i32.const 3))))
+                (func (export "main") (call $helper))
+                (func (export "debug") (call $debug_only))
+                (func (export "extra"))
+                (@custom "slimmy.abi" "\01\00"))"#,
+        )
+        .unwrap();
+
+        let (stripped, report) = strip(&module, "main", &StripOptions::default()).unwrap();
+        assert_eq!(
+            (report.custom_sections, report.exports, report.functions),
+            (1, 2, 3)
+        );
+        assert_eq!(report.after, stripped.len());
+        assert!(report.saved() > 0);
+        wasmparser::Validator::new()
+            .validate_all(&stripped)
+            .unwrap();
+        let exports = Scan::new(&stripped).unwrap().exports;
+        let names: Vec<&str> = exports.iter().map(|(name, _, _)| name.as_str()).collect();
+        assert_eq!(names, ["memory", "main"]);
+        assert_eq!(version::guest_version(&stripped), Some(1));
+
+        let opts = StripOptions {
+            dead_exports: false,
+            ..StripOptions::default()
+        };
+        let (_, report) = strip(&module, "main", &opts).unwrap();
+        assert_eq!((report.exports, report.functions), (0, 0));
+
+        let opts = StripOptions {
+            keep_exports: vec!["debug".into()],
+            ..StripOptions::default()
+        };
+        let (_, report) = strip(&module, "main", &opts).unwrap();
+        assert_eq!((report.exports, report.functions), (1, 1));
+
+        assert!(strip(&module, "missing", &StripOptions::default()).is_err());
+        assert!(strip(b"\0asm", "main", &StripOptions::default()).is_err());
+    }
+}
@@ -0,0 +1,192 @@
+//! `slimmy` Python module: pack, parse and verify `.smny` blobs in CI without
+//! shelling out to the `packer` binary.
+//!
+//! ```python
+//! import slimmy
+//! blob = slimmy.pack(wasm, module_id=3, sign_key=secret, sequence=7)
+//! manifest, module = slimmy.parse(blob)
+//! assert slimmy.verify(blob, pubkey)
+//! ```
+
+use packer::PackOptions;
+use pyo3::exceptions::PyValueError;
+use pyo3::prelude::*;
+use pyo3::types::PyBytes;
+use runtime::manifest::{DependencyRef, Manifest};
+use runtime::verify::Algorithm;
+
+/// Parsed manifest header.
+#[pyclass(frozen, get_all, name = "Manifest", module = "slimmy")]
+struct PyManifest {
+    version: u8,
+    module_id: u32,
+    module_len: u32,
+    entry: String,
+    flags: u8,
+    sequence: u32,
+    signed: bool,
+    name: Option<String>,
+    schedule: Option<String>,
+    /// Bitmask of allowed device states; `None` means any.
+    allowed_states: Option<u32>,
+    /// Dependencies in packer `--depends` form (`"7:3"`, `"net:2"`).
+    depends: Vec<String>,
+}
+
+#[pymethods]
+impl PyManifest {
+    fn __repr__(&self) -> String {
+        format!(
+            "Manifest(module_id={}, entry={:?}, sequence={}, signed={}, module_len={})",
+            self.module_id,
+            self.entry,
+            self.sequence,
+            if self.signed { "True" } else { "False" },
+            self.module_len
+        )
+    }
+}
+
+impl PyManifest {
+    fn from_manifest(manifest: &Manifest<'_>) -> PyResult<Self> {
+        let text = |field: Option<runtime::Result<&str>>| -> PyResult<Option<String>> {
+            field
+                .map(|value| value.map(str::to_string).map_err(value_error))
+                .transpose()
+        };
+        let depends = manifest
+            .dependencies()
+            .map(|dep| match dep.map_err(value_error)? {
+                (DependencyRef::Id(id), min) => Ok(format!("{id}:{min}")),
+                (DependencyRef::Name(name), min) => Ok(format!("{name}:{min}")),
+            })
+            .collect::<PyResult<_>>()?;
+        Ok(Self {
+            version: manifest.version,
+            module_id: manifest.module_id,
+            module_len: manifest.module_len,
+            entry: manifest.entry.to_string(),
+            flags: manifest.flags,
+            sequence: manifest.sequence,
+            signed: manifest.signature.is_some(),
+            name: text(manifest.name())?,
+            schedule: text(manifest.schedule())?,
+            allowed_states: manifest.allowed_states(),
+            depends,
+        })
+    }
+}
+
+fn value_error(err: impl std::fmt::Display) -> PyErr {
+    PyValueError::new_err(err.to_string())
+}
+
+fn key32(key: &[u8], what: &str) -> PyResult<[u8; 32]> {
+    key.try_into()
+        .map_err(|_| PyValueError::new_err(format!("{what} must be 32 bytes")))
+}
+
+/// Wraps a wasm module into a manifest blob; arguments mirror the packer CLI
+/// (`format="suit"` emits a SUIT envelope).
+#[pyfunction]
+#[pyo3(signature = (
+    module,
+    module_id = 1,
+    entry = "main",
+    sign_key = None,
+    require_signature = false,
+    sequence = 0,
+    pad_to = None,
+    allowed_states = Vec::new(),
+    schedule = None,
+    name = None,
+    depends = Vec::new(),
+    format = "smny",
+    max_size = None,
+    allowed_imports = None,
+    sign_alg = "ed25519",
+))]
+#[allow(clippy::too_many_arguments)]
+fn pack<'py>(
+    py: Python<'py>,
+    module: &[u8],
+    module_id: u32,
+    entry: &str,
+    sign_key: Option<&[u8]>,
+    require_signature: bool,
+    sequence: u32,
+    pad_to: Option<usize>,
+    allowed_states: Vec<u8>,
+    schedule: Option<String>,
+    name: Option<String>,
+    depends: Vec<String>,
+    format: &str,
+    max_size: Option<usize>,
+    allowed_imports: Option<Vec<String>>,
+    sign_alg: &str,
+) -> PyResult<Bound<'py, PyBytes>> {
+    let opts = PackOptions {
+        format: format.parse().map_err(value_error)?,
+        module_id,
+        entry: entry.to_string(),
+        sign_key: sign_key.map(|key| key32(key, "sign_key")).transpose()?,
+        sign_alg: sign_alg.parse().map_err(value_error)?,
+        require_signature,
+        sequence,
+        pad_to,
+        allowed_states,
+        schedule,
+        name,
+        depends,
+        certs: Vec::new(),
+        cert_refs: Vec::new(),
+        max_size,
+        allowed_imports,
+        aot: None,
+    };
+    let packed = packer::pack(module, &opts).map_err(PyValueError::new_err)?;
+    Ok(PyBytes::new_bound(py, &packed.blob))
+}
+
+/// Parses a blob into `(Manifest, module_bytes)`.
+#[pyfunction]
+fn parse<'py>(py: Python<'py>, blob: &[u8]) -> PyResult<(PyManifest, Bound<'py, PyBytes>)> {
+    let (manifest, module) = Manifest::parse(blob).map_err(value_error)?;
+    if manifest.module_len as usize != module.len() {
+        return Err(PyValueError::new_err("module length differs from manifest"));
+    }
+    Ok((
+        PyManifest::from_manifest(&manifest)?,
+        PyBytes::new_bound(py, module),
+    ))
+}
+
+/// True when the blob carries a valid signature from `pubkey` (32-byte
+/// Ed25519, or a SEC1 point for P-256 manifests); raises `ValueError` for
+/// blobs that do not parse.
+#[pyfunction]
+fn verify(blob: &[u8], pubkey: &[u8]) -> PyResult<bool> {
+    let (manifest, _) = Manifest::parse(blob).map_err(value_error)?;
+    if manifest.signature_algorithm() != Ok(Algorithm::P256) {
+        key32(pubkey, "pubkey")?;
+    }
+    Ok(packer::verify(blob, pubkey).is_ok())
+}
+
+/// BLAKE3 outboard tree for verified streaming: `(root, tree)`, as written
+/// by `packer --emit-outboard`.
+#[pyfunction]
+fn outboard<'py>(py: Python<'py>, blob: &[u8]) -> (Bound<'py, PyBytes>, Bound<'py, PyBytes>) {
+    let (root, tree) = runtime::stream::outboard(blob);
+    (PyBytes::new_bound(py, &root), PyBytes::new_bound(py, &tree))
+}
+
+#[pymodule]
+fn slimmy(m: &Bound<'_, PyModule>) -> PyResult<()> {
+    m.add_class::<PyManifest>()?;
+    m.add_function(wrap_pyfunction!(pack, m)?)?;
+    m.add_function(wrap_pyfunction!(parse, m)?)?;
+    m.add_function(wrap_pyfunction!(verify, m)?)?;
+    m.add_function(wrap_pyfunction!(outboard, m)?)?;
+    Ok(())
+}
@@ -0,0 +1,32 @@
+import pytest
+import slimmy
+
+WASM = b"\0asm\x01\0\0\0"
+SECRET = bytes([9] * 32)
+# Ed25519 public key for SECRET.
+PUBKEY = bytes.fromhex("fd1724385aa0c75b64fb78cd602fa1d991fdebf76b13c58ed702eac835e9f618")
+
+
+def test_pack_parse_roundtrip():
+    blob = slimmy.pack(WASM, module_id=3, sequence=7, name="net", depends=["2:1"])
+    manifest, module = slimmy.parse(blob)
+    assert (manifest.module_id, manifest.sequence, manifest.name) == (3, 7, "net")
+    assert manifest.depends == ["2:1"]
+    assert not manifest.signed
+    assert module == WASM
+
+
+def test_signed_blobs_verify_against_their_key_only():
+    blob = slimmy.pack(WASM, sign_key=SECRET)
+    assert slimmy.parse(blob)[0].signed
+    assert slimmy.verify(blob, PUBKEY)
+    assert not slimmy.verify(blob[:-1] + b"\1", PUBKEY)
+
+
+def test_invalid_input_raises_value_error():
+    with pytest.raises(ValueError):
+        slimmy.pack(WASM, require_signature=True)
+    with pytest.raises(ValueError):
+        slimmy.pack(WASM, max_size=4)
+    with pytest.raises(ValueError):
+        slimmy.parse(b"nope")
@@ -0,0 +1,76 @@
+/*
+ * slimmy C API (runtime crate, `slimmy-capi` feature).
+ *
+ * Mirrors runtime/src/capi.rs; a test in that file checks every exported
+ * function and error code appears here.
+ *
+ * Build: cargo rustc -p runtime --release \
+ *          --features slimmy-capi,engine-wasm3 --crate-type staticlib
+ *
+ * A handle is not thread-safe: serialize calls on it.
+ */
+#ifndef SLIMMY_H
+#define SLIMMY_H
+
+#include <stddef.h>
+#include <stdint.h>
+
+#ifdef __cplusplus
+extern "C" {
+#endif
+
+#define SLIMMY_OK (0)
+/* Null pointer, bad UTF-8 or malformed manifest passed in. */
+#define SLIMMY_ERR_INVALID (-1)
+#define SLIMMY_ERR_MODULE_NOT_FOUND (-2)
+#define SLIMMY_ERR_ENTRY_NOT_FOUND (-3)
+#define SLIMMY_ERR_ENGINE (-4)
+#define SLIMMY_ERR_UNSUPPORTED (-5)
+#define SLIMMY_ERR_STATE_DENIED (-6)
+/* Missing, too old or cyclic dependency. */
+#define SLIMMY_ERR_DEPENDENCY (-7)
+/* Invocation rate or fuel quota used up for the current window. */
+#define SLIMMY_ERR_QUOTA (-8)
+/* A nested invocation was refused. */
+#define SLIMMY_ERR_REENTRANCY (-9)
+/* The module does not fit the store or its storage quota. */
+#define SLIMMY_ERR_STORAGE_FULL (-10)
+/* The module is installed but disabled. */
+#define SLIMMY_ERR_DISABLED (-11)
+/* A buffer could not be allocated. */
+#define SLIMMY_ERR_OUT_OF_MEMORY (-12)
+/* The entry point returned a negative status. */
+#define SLIMMY_ERR_GUEST (-13)
+/* The module was built for an ABI version this firmware does not speak. */
+#define SLIMMY_ERR_ABI (-14)
+/* The module or its signer is revoked. */
+#define SLIMMY_ERR_REVOKED (-15)
+/* The runtime is suspended. */
+#define SLIMMY_ERR_SUSPENDED (-16)
+
+/* Opaque runtime handle. */
+typedef struct SlimmyRuntime slimmy_runtime_t;
+
+/* Creates a runtime with an empty RAM module store; NULL on engine failure. */
+slimmy_runtime_t *slimmy_init(void);
+
+/* Installs (or replaces) a module from raw wasm or a manifest blob naming
+ * module_id. The bytes are copied. */
+int32_t slimmy_install(slimmy_runtime_t *rt, uint32_t module_id, const uint8_t *bytes, size_t len);
+
+/* Runs a NUL-terminated entry point of an installed module; an entry
+ * returning a negative status fails with SLIMMY_ERR_GUEST. */
+int32_t slimmy_execute(slimmy_runtime_t *rt, uint32_t module_id, const char *entry);
+
+/* Message for the last failed call ("" after a success); valid until the
+ * next call on rt. */
+const char *slimmy_last_error(const slimmy_runtime_t *rt);
+
+/* Destroys a runtime and every module it holds. NULL is ignored. */
+void slimmy_free(slimmy_runtime_t *rt);
+
+#ifdef __cplusplus
+}
+#endif
+
+#endif /* SLIMMY_H */
@@ -0,0 +1,405 @@
+//! Audit log of the host calls modules make.
+//!
+//! Attach a `CallLog` to an engine's `Imports` (`Imports::with_call_log`) and
+//! every host call is recorded while the log is enabled: the calling module,
+//! the function, the bytes it read from and wrote into guest memory, how
+//! long it took and how it ended (`CallOutcome`), including calls a call
+//! budget refused before they ran. The log is a ring buffer: once full, the
+//! oldest calls make room and are counted as dropped. Clones share the
+//! buffer, so the firmware keeps one and reads it after the invocation,
+//! e.g. for a security review of what a third-party module touches.
+//!
+//! ```ignore
+//! let log = CallLog::new(256);
+//! engine.set_imports(Imports::new().with(sys).with_call_log(log.clone()));
+//! runtime.execute(id, "main", &mut ())?;
+//! for call in log.take() {
+//!     println!("{} {} r={} w={}", call.module_id, call.name, call.bytes_read, call.bytes_written);
+//! }
+//! ```
+//!
+//! Durations need a `Timer`; without one they are 0.
+//!
+//! Engines bracket each invocation with `Imports::enter` and `Imports::exit`,
+//! so a module invoked from inside another one's host call (e.g. `route`
+//! on an engine sharing the log) is attributed its own calls, and the
+//! caller's later calls are attributed to the caller again.
+
+use super::GuestMemory;
+use crate::{ModuleId, Result};
+use alloc::boxed::Box;
+use alloc::collections::VecDeque;
+use alloc::rc::Rc;
+use alloc::vec::Vec;
+use core::cell::{Cell, RefCell};
+
+/// Monotonic microsecond counter (a cycle counter or hardware timer).
+pub trait Timer {
+    fn now_us(&self) -> u64;
+}
+
+/// `std::time::Instant` since the timer was created.
+#[cfg(feature = "std")]
+#[derive(Debug, Clone, Copy)]
+pub struct StdTimer(std::time::Instant);
+
+#[cfg(feature = "std")]
+impl StdTimer {
+    pub fn new() -> Self {
+        Self(std::time::Instant::now())
+    }
+}
+
+#[cfg(feature = "std")]
+impl Default for StdTimer {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(feature = "std")]
+impl Timer for StdTimer {
+    fn now_us(&self) -> u64 {
+        self.0.elapsed().as_micros() as u64
+    }
+}
+
+/// One host call.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct HostCall {
+    /// Module that made the call.
+    pub module_id: ModuleId,
+    pub name: &'static str,
+    /// Bytes the host read from guest memory.
+    pub bytes_read: u32,
+    /// Bytes the host wrote into guest memory.
+    pub bytes_written: u32,
+    pub duration_us: u32,
+    pub outcome: CallOutcome,
+}
+
+/// How a recorded host call ended.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CallOutcome {
+    /// Ran and returned this status.
+    Returned(i32),
+    /// Ran and trapped.
+    Trapped,
+    /// Refused by the module's call budget without running; the guest got
+    /// `E_DENIED`.
+    Denied,
+}
+
+struct Log {
+    calls: VecDeque<HostCall>,
+    capacity: usize,
+    enabled: bool,
+    dropped: u64,
+    /// Modules of the invocations in progress, innermost last.
+    modules: Vec<ModuleId>,
+    timer: Option<Box<dyn Timer>>,
+}
+
+impl Log {
+    /// Module of the innermost invocation (0 outside any).
+    fn module_id(&self) -> ModuleId {
+        self.modules.last().copied().unwrap_or(0)
+    }
+
+    fn push(&mut self, call: HostCall) {
+        if self.calls.len() == self.capacity {
+            self.calls.pop_front();
+            self.dropped += 1;
+        }
+        self.calls.push_back(call);
+    }
+}
+
+/// Shared ring buffer of `HostCall`s.
+#[derive(Clone)]
+pub struct CallLog {
+    log: Rc<RefCell<Log>>,
+}
+
+impl core::fmt::Debug for CallLog {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        let log = self.log.borrow();
+        f.debug_struct("CallLog")
+            .field("len", &log.calls.len())
+            .field("capacity", &log.capacity)
+            .field("enabled", &log.enabled)
+            .field("dropped", &log.dropped)
+            .finish()
+    }
+}
+
+impl CallLog {
+    /// Enabled log keeping the last `capacity` calls (at least one).
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            log: Rc::new(RefCell::new(Log {
+                calls: VecDeque::new(),
+                capacity: capacity.max(1),
+                enabled: true,
+                dropped: 0,
+                modules: Vec::new(),
+                timer: None,
+            })),
+        }
+    }
+
+    /// Times calls with `timer`.
+    pub fn with_timer(self, timer: impl Timer + 'static) -> Self {
+        self.log.borrow_mut().timer = Some(Box::new(timer));
+        self
+    }
+
+    /// Turns audit mode on or off; recorded calls are kept either way.
+    pub fn set_enabled(&self, enabled: bool) {
+        self.log.borrow_mut().enabled = enabled;
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.log.borrow().enabled
+    }
+
+    /// Calls in the buffer.
+    pub fn len(&self) -> usize {
+        self.log.borrow().calls.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Calls pushed out of the full buffer since the last `take` or `clear`.
+    pub fn dropped(&self) -> u64 {
+        self.log.borrow().dropped
+    }
+
+    /// Copies of the buffered calls, oldest first.
+    pub fn calls(&self) -> Vec<HostCall> {
+        self.log.borrow().calls.iter().copied().collect()
+    }
+
+    /// Returns the buffered calls, oldest first, and empties the buffer.
+    pub fn take(&self) -> Vec<HostCall> {
+        let mut log = self.log.borrow_mut();
+        log.dropped = 0;
+        log.calls.drain(..).collect()
+    }
+
+    /// Empties the buffer (e.g. before each invocation).
+    pub fn clear(&self) {
+        let mut log = self.log.borrow_mut();
+        log.calls.clear();
+        log.dropped = 0;
+    }
+
+    pub(crate) fn enter(&self, module_id: ModuleId) {
+        self.log.borrow_mut().modules.push(module_id);
+    }
+
+    /// Ends the innermost invocation; calls go to its caller again.
+    pub(crate) fn exit(&self) {
+        self.log.borrow_mut().modules.pop();
+    }
+
+    /// Records a call of `name` the running module's budget refused.
+    pub(crate) fn deny(&self, name: &'static str) {
+        let mut log = self.log.borrow_mut();
+        if !log.enabled {
+            return;
+        }
+        let module_id = log.module_id();
+        log.push(HostCall {
+            module_id,
+            name,
+            bytes_read: 0,
+            bytes_written: 0,
+            duration_us: 0,
+            outcome: CallOutcome::Denied,
+        });
+    }
+
+    /// Runs `call` with `memory`, recording it under `name` when enabled.
+    pub(crate) fn record(
+        &self,
+        name: &'static str,
+        memory: &mut dyn GuestMemory,
+        call: impl FnOnce(&mut dyn GuestMemory) -> Result<i32>,
+    ) -> Result<i32> {
+        if !self.is_enabled() {
+            return call(memory);
+        }
+        // Read up front: a nested call (e.g. `route`) may enter another module.
+        let module_id = self.log.borrow().module_id();
+        let started = self.now_us();
+        let mut counted = Counted {
+            memory,
+            read: Cell::new(0),
+            written: 0,
+        };
+        let result = call(&mut counted);
+        let duration = self.now_us().saturating_sub(started);
+        self.log.borrow_mut().push(HostCall {
+            module_id,
+            name,
+            bytes_read: counted.read.get(),
+            bytes_written: counted.written,
+            duration_us: u32::try_from(duration).unwrap_or(u32::MAX),
+            outcome: match result {
+                Ok(status) => CallOutcome::Returned(status),
+                Err(_) => CallOutcome::Trapped,
+            },
+        });
+        result
+    }
+
+    fn now_us(&self) -> u64 {
+        let log = self.log.borrow();
+        log.timer.as_ref().map_or(0, |timer| timer.now_us())
+    }
+}
+
+/// Guest memory that counts the bytes moved through it.
+struct Counted<'a> {
+    memory: &'a mut dyn GuestMemory,
+    read: Cell<u32>,
+    written: u32,
+}
+
+impl GuestMemory for Counted<'_> {
+    fn read(&self, ptr: u32, buf: &mut [u8]) -> Result<()> {
+        self.memory.read(ptr, buf)?;
+        self.read
+            .set(self.read.get().saturating_add(buf.len() as u32));
+        Ok(())
+    }
+
+    fn write(&mut self, ptr: u32, data: &[u8]) -> Result<()> {
+        self.memory.write(ptr, data)?;
+        self.written = self.written.saturating_add(data.len() as u32);
+        Ok(())
+    }
+}
+
+#[cfg(all(test, feature = "std"))]
+mod tests {
+    use super::*;
+    use crate::abi::caps::CapabilityPolicy;
+    use crate::abi::linker::Linker;
+    use crate::abi::{HostFn, HostImports, Imports, E_DENIED, OK};
+    use crate::Error;
+
+    /// `copy(src, dst, len)` moves guest bytes; `fail()` traps.
+    struct Mover;
+
+    impl HostImports for Mover {
+        fn functions(&self) -> &[HostFn] {
+            const FNS: &[HostFn] = &[HostFn::new("copy", 3), HostFn::new("fail", 0)];
+            FNS
+        }
+
+        fn call(&mut self, name: &str, args: &[i32], memory: &mut dyn GuestMemory) -> Result<i32> {
+            if name == "fail" {
+                return Err(Error::Engine("host trap"));
+            }
+            let mut buf = alloc::vec![0; args[2] as usize];
+            memory.read(args[0] as u32, &mut buf)?;
+            memory.write(args[1] as u32, &buf)?;
+            Ok(OK)
+        }
+    }
+
+    struct Ticks(Cell<u64>);
+
+    impl Timer for Ticks {
+        fn now_us(&self) -> u64 {
+            self.0.set(self.0.get() + 5);
+            self.0.get()
+        }
+    }
+
+    #[test]
+    fn records_host_calls_into_a_ring() {
+        let log = CallLog::new(2).with_timer(Ticks(Cell::new(0)));
+        let mut imports = Imports::new().with(Mover).with_call_log(log.clone());
+        let mut memory = [1u8, 2, 3, 4, 0, 0, 0, 0];
+
+        imports.enter(7);
+        assert_eq!(imports.call("copy", &[0, 4, 3], &mut memory), Ok(OK));
+        assert!(imports.call("fail", &[], &mut memory).is_err());
+        assert_eq!(
+            log.calls(),
+            [
+                HostCall {
+                    module_id: 7,
+                    name: "copy",
+                    bytes_read: 3,
+                    bytes_written: 3,
+                    duration_us: 5,
+                    outcome: CallOutcome::Returned(OK),
+                },
+                HostCall {
+                    module_id: 7,
+                    name: "fail",
+                    bytes_read: 0,
+                    bytes_written: 0,
+                    duration_us: 5,
+                    outcome: CallOutcome::Trapped,
+                },
+            ]
+        );
+
+        // The oldest call makes room.
+        imports.exit();
+        imports.enter(8);
+        imports.call("copy", &[0, 4, 1], &mut memory).unwrap();
+        assert_eq!(log.dropped(), 1);
+        let calls = log.take();
+        assert_eq!((calls[1].module_id, calls[1].bytes_read), (8, 1));
+        assert!(log.is_empty() && log.dropped() == 0);
+
+        log.set_enabled(false);
+        imports.call("copy", &[0, 4, 1], &mut memory).unwrap();
+        assert!(log.is_empty());
+    }
+
+    #[test]
+    fn attributes_nested_invocations_and_denied_calls() {
+        let log = CallLog::new(8);
+        let policy = CapabilityPolicy::new().with_call_limit(1, "copy", 1);
+        let mut caller = Linker::from(
+            Imports::new()
+                .with(Mover)
+                .with_call_log(log.clone())
+                .with_call_budgets(policy),
+        );
+        // Another engine sharing the log, e.g. one a `route` call runs on.
+        let mut callee = Imports::new().with(Mover).with_call_log(log.clone());
+        let mut memory = [1u8, 2, 3, 4];
+
+        caller.enter(1);
+        caller.call("copy", [0, 2, 1], &mut memory).unwrap();
+        callee.enter(2);
+        callee.call("copy", &[0, 2, 2], &mut memory).unwrap();
+        callee.exit();
+        assert_eq!(caller.call("copy", [0, 2, 1], &mut memory), Ok(E_DENIED));
+        caller.exit();
+
+        let calls = log.take();
+        let summary: Vec<_> = calls
+            .iter()
+            .map(|call| (call.module_id, call.bytes_read, call.outcome))
+            .collect();
+        assert_eq!(
+            summary,
+            [
+                (1, 1, CallOutcome::Returned(OK)),
+                (2, 2, CallOutcome::Returned(OK)),
+                (1, 0, CallOutcome::Denied),
+            ]
+        );
+    }
+}
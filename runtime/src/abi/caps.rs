@@ -0,0 +1,183 @@
+//! Per-module capability grants for host functions that reach shared hardware.
+//!
+//! Import sets consult a `CapabilityPolicy` with the module that is currently
+//! running (see `HostImports::enter`) so an OTA module can only touch the buses
+//! and addresses the firmware granted it, and call only the modules it was
+//! granted. Anything not granted is denied.
+//!
+//! A policy can also cap how often a module calls a host function in one
+//! invocation (`limit_calls`), e.g. ten I2C transactions or a few log lines.
+//! `linker::Linker` enforces the limits of the policy attached with
+//! `Imports::with_call_budgets`: calls past the limit return `E_DENIED`
+//! without reaching the import set.
+
+use crate::ModuleId;
+use alloc::vec::Vec;
+
+/// A single hardware resource a module may use.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Capability {
+    /// One 7-bit device address on an I2C bus.
+    I2c { bus: u8, addr: u8 },
+    /// Every transfer on an SPI device (chip select is part of the device).
+    Spi { bus: u8 },
+    /// Exports of another module, through `route::Router`.
+    Call { callee: ModuleId },
+}
+
+impl Capability {
+    pub fn kind(&self) -> CapabilityKind {
+        match self {
+            Capability::I2c { .. } => CapabilityKind::I2c,
+            Capability::Spi { .. } => CapabilityKind::Spi,
+            Capability::Call { .. } => CapabilityKind::Call,
+        }
+    }
+}
+
+/// A `Capability` without its bus, address or callee.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CapabilityKind {
+    I2c,
+    Spi,
+    Call,
+}
+
+impl CapabilityKind {
+    /// The kind a host function is gated on (`abi::hal`, `route`), if any.
+    pub fn for_import(name: &str) -> Option<Self> {
+        match name {
+            "i2c_write" | "i2c_read" | "i2c_write_read" => Some(CapabilityKind::I2c),
+            "spi_transfer" => Some(CapabilityKind::Spi),
+            "call" => Some(CapabilityKind::Call),
+            _ => None,
+        }
+    }
+}
+
+/// Grants per module; deny by default.
+#[derive(Debug, Default, Clone)]
+pub struct CapabilityPolicy {
+    grants: Vec<(ModuleId, Capability)>,
+    /// Most calls per invocation, by module and host function.
+    budgets: Vec<(ModuleId, &'static str, u32)>,
+}
+
+impl CapabilityPolicy {
+    /// Creates a policy that grants nothing.
+    pub fn new() -> Self {
+        Self {
+            grants: Vec::new(),
+            budgets: Vec::new(),
+        }
+    }
+
+    /// Grants a capability (builder style).
+    pub fn with(mut self, module_id: ModuleId, capability: Capability) -> Self {
+        self.grant(module_id, capability);
+        self
+    }
+
+    /// Grants a capability to a module.
+    pub fn grant(&mut self, module_id: ModuleId, capability: Capability) {
+        if !self.allows(module_id, capability) {
+            self.grants.push((module_id, capability));
+        }
+    }
+
+    /// Removes every grant held by a module (e.g. before installing an update).
+    pub fn revoke_all(&mut self, module_id: ModuleId) {
+        self.grants.retain(|(id, _)| *id != module_id);
+    }
+
+    /// True when `module_id` holds `capability`.
+    pub fn allows(&self, module_id: ModuleId, capability: Capability) -> bool {
+        self.grants.contains(&(module_id, capability))
+    }
+
+    /// True when `module_id` holds any capability of `kind`.
+    pub fn allows_kind(&self, module_id: ModuleId, kind: CapabilityKind) -> bool {
+        self.grants_for(module_id)
+            .any(|capability| capability.kind() == kind)
+    }
+
+    /// Limits a module to `max` calls of host function `name` per invocation
+    /// (builder style).
+    pub fn with_call_limit(mut self, module_id: ModuleId, name: &'static str, max: u32) -> Self {
+        self.limit_calls(module_id, name, max);
+        self
+    }
+
+    /// Limits a module to `max` calls of host function `name` per
+    /// invocation, replacing an earlier limit.
+    pub fn limit_calls(&mut self, module_id: ModuleId, name: &'static str, max: u32) {
+        self.unlimit_calls(module_id, name);
+        self.budgets.push((module_id, name, max));
+    }
+
+    /// Lifts the limit on `name` for a module.
+    pub fn unlimit_calls(&mut self, module_id: ModuleId, name: &str) {
+        self.budgets
+            .retain(|(id, limited, _)| *id != module_id || *limited != name);
+    }
+
+    /// Most calls of `name` a module may make per invocation; `None` when
+    /// unlimited.
+    pub fn call_limit(&self, module_id: ModuleId, name: &str) -> Option<u32> {
+        self.budgets
+            .iter()
+            .find(|(id, limited, _)| *id == module_id && *limited == name)
+            .map(|(_, _, max)| *max)
+    }
+
+    /// Capabilities held by a module.
+    pub fn grants_for(&self, module_id: ModuleId) -> impl Iterator<Item = Capability> + '_ {
+        self.grants
+            .iter()
+            .filter(move |(id, _)| *id == module_id)
+            .map(|(_, capability)| *capability)
+    }
+}
+
+#[cfg(all(test, feature = "std"))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn grants_are_per_module() {
+        let sensor = Capability::I2c { bus: 0, addr: 0x48 };
+        let mut policy = CapabilityPolicy::new()
+            .with(1, sensor)
+            .with(1, Capability::Spi { bus: 2 });
+
+        assert!(policy.allows(1, sensor));
+        assert!(!policy.allows(2, sensor));
+        assert!(!policy.allows(1, Capability::I2c { bus: 0, addr: 0x49 }));
+        assert_eq!(policy.grants_for(1).count(), 2);
+        assert!(policy.allows_kind(1, CapabilityKind::Spi));
+        assert!(!policy.allows_kind(1, CapabilityKind::Call));
+        assert_eq!(
+            CapabilityKind::for_import("i2c_read"),
+            Some(CapabilityKind::I2c)
+        );
+
+        policy.revoke_all(1);
+        assert!(!policy.allows(1, sensor));
+    }
+
+    #[test]
+    fn call_limits_are_per_module_and_function() {
+        let mut policy = CapabilityPolicy::new()
+            .with_call_limit(1, "i2c_write", 10)
+            .with_call_limit(1, "log", 4);
+
+        assert_eq!(policy.call_limit(1, "i2c_write"), Some(10));
+        assert_eq!(policy.call_limit(2, "i2c_write"), None);
+        assert_eq!(policy.call_limit(1, "i2c_read"), None);
+
+        policy.limit_calls(1, "i2c_write", 2);
+        assert_eq!(policy.call_limit(1, "i2c_write"), Some(2));
+        policy.unlimit_calls(1, "log");
+        assert_eq!(policy.call_limit(1, "log"), None);
+    }
+}
@@ -0,0 +1,163 @@
+//! `yield_hint`: cooperative check-ins for long-running guests.
+//!
+//! Guest imports (module `env`, all `i32`):
+//! - `yield_hint() -> status` – `OK` to carry on; traps when the invocation
+//!   must stop.
+//!
+//! Single-threaded firmware has nothing to stop a guest that computes for a
+//! long time, and engines without epochs cannot be interrupted from outside.
+//! A guest that calls `yield_hint` every so often hands control back to the
+//! host instead, which feeds the hardware watchdog, then traps the call once
+//! its deadline (`YieldImports::set_deadline`) has passed or its interrupter
+//! fired (e.g. from a `schedule::PreemptHint`). `YieldImports` is a cheap
+//! handle: register a clone with the engine and keep another.
+
+use super::{GuestMemory, HostFn, HostImports, OK};
+use crate::schedule::{Clock, Interrupter};
+use crate::{Error, ModuleId, Result};
+use alloc::boxed::Box;
+use alloc::rc::Rc;
+use alloc::sync::Arc;
+use core::cell::{Cell, RefCell};
+use core::sync::atomic::{AtomicBool, Ordering};
+
+/// Host functions provided by `YieldImports`.
+pub const FUNCTIONS: &[HostFn] = &[HostFn::new("yield_hint", 0)];
+
+struct State<C> {
+    clock: C,
+    /// UTC ms after which calls trap.
+    deadline: Cell<Option<u64>>,
+    watchdog: RefCell<Option<Box<dyn FnMut()>>>,
+    interrupted: Arc<AtomicBool>,
+    yields: Cell<u32>,
+}
+
+/// Shared handle providing `yield_hint`.
+pub struct YieldImports<C> {
+    state: Rc<State<C>>,
+}
+
+impl<C> Clone for YieldImports<C> {
+    fn clone(&self) -> Self {
+        Self {
+            state: self.state.clone(),
+        }
+    }
+}
+
+impl<C: Clock> YieldImports<C> {
+    /// No deadline and no watchdog; deadlines are read from `clock`.
+    pub fn new(clock: C) -> Self {
+        Self {
+            state: Rc::new(State {
+                clock,
+                deadline: Cell::new(None),
+                watchdog: RefCell::new(None),
+                interrupted: Arc::new(AtomicBool::new(false)),
+                yields: Cell::new(0),
+            }),
+        }
+    }
+
+    /// Feeds the hardware watchdog on every `yield_hint` (builder style).
+    pub fn with_watchdog(self, feed: impl FnMut() + 'static) -> Self {
+        *self.state.watchdog.borrow_mut() = Some(Box::new(feed));
+        self
+    }
+
+    /// Traps calls yielding after `at_ms` (UTC ms); `None` = never.
+    pub fn set_deadline(&self, at_ms: Option<u64>) {
+        self.state.deadline.set(at_ms);
+    }
+
+    /// Traps the running call at its next `yield_hint`; calls started later
+    /// are unaffected. Safe to fire from another thread or an interrupt.
+    pub fn interrupter(&self) -> Interrupter {
+        let interrupted = self.state.interrupted.clone();
+        Interrupter::new(move || interrupted.store(true, Ordering::Release))
+    }
+
+    /// `yield_hint` calls so far, for tuning how often guests yield.
+    pub fn yields(&self) -> u32 {
+        self.state.yields.get()
+    }
+
+    fn yield_hint(&self) -> Result<i32> {
+        let state = &self.state;
+        state.yields.set(state.yields.get().wrapping_add(1));
+        if let Some(feed) = state.watchdog.borrow_mut().as_mut() {
+            feed();
+        }
+        if state.interrupted.swap(false, Ordering::Acquire) {
+            return Err(Error::Engine("yield: interrupted"));
+        }
+        match state.deadline.get() {
+            Some(deadline) if state.clock.now_ms() > deadline => {
+                Err(Error::Engine("yield: deadline passed"))
+            }
+            _ => Ok(OK),
+        }
+    }
+}
+
+impl<C: Clock> HostImports for YieldImports<C> {
+    fn functions(&self) -> &[HostFn] {
+        FUNCTIONS
+    }
+
+    fn call(&mut self, name: &str, args: &[i32], _memory: &mut dyn GuestMemory) -> Result<i32> {
+        match (name, args) {
+            ("yield_hint", []) => self.yield_hint(),
+            _ => Err(Error::Engine("yield: bad host call")),
+        }
+    }
+
+    fn enter(&mut self, _module_id: ModuleId) {
+        self.state.interrupted.store(false, Ordering::Release);
+    }
+}
+
+#[cfg(all(test, feature = "std"))]
+mod tests {
+    use super::*;
+    use crate::abi::Imports;
+
+    struct TestClock(Rc<Cell<u64>>);
+
+    impl Clock for TestClock {
+        fn now_ms(&self) -> u64 {
+            self.0.get()
+        }
+    }
+
+    #[test]
+    fn feeds_the_watchdog_and_stops_late_or_interrupted_calls() {
+        let now = Rc::new(Cell::new(1000));
+        let fed = Rc::new(Cell::new(0));
+        let feeds = fed.clone();
+        let coop = YieldImports::new(TestClock(now.clone()))
+            .with_watchdog(move || feeds.set(feeds.get() + 1));
+        let mut imports = Imports::new().with(coop.clone());
+        let mut yield_hint = || imports.call("yield_hint", &[], &mut [0u8; 0]);
+
+        assert_eq!(yield_hint(), Ok(OK));
+        coop.set_deadline(Some(1500));
+        now.set(1500);
+        assert_eq!(yield_hint(), Ok(OK));
+        now.set(1501);
+        assert!(yield_hint().is_err());
+        coop.set_deadline(None);
+
+        let interrupter = coop.interrupter();
+        interrupter.interrupt();
+        assert!(yield_hint().is_err());
+        assert_eq!(yield_hint(), Ok(OK));
+        assert_eq!((fed.get(), coop.yields()), (5, 5));
+
+        // A tick before the call starts does not carry over.
+        interrupter.interrupt();
+        imports.enter(1);
+        assert_eq!(imports.call("yield_hint", &[], &mut [0u8; 0]), Ok(OK));
+    }
+}
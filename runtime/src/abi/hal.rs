@@ -0,0 +1,547 @@
+//! GPIO / ADC / PWM / I2C / SPI host bindings over embedded-hal traits (`abi-hal` feature).
+//!
+//! Guest imports (module `env`, all `i32`):
+//! - `gpio_write(pin, level) -> status` – drive an output pin low (0) or high (!= 0).
+//! - `gpio_read(pin) -> level | status` – 0/1 for an input pin, negative on error.
+//! - `adc_read(channel) -> raw | status` – raw conversion result, negative on error.
+//! - `pwm_set(channel, permille) -> status` – duty cycle in 0..=1000.
+//! - `i2c_write(bus, addr, ptr, len) -> status` – write `len` bytes to a 7-bit address.
+//! - `i2c_read(bus, addr, ptr, len) -> status` – read `len` bytes into guest memory.
+//! - `i2c_write_read(bus, addr, wptr, wlen, rptr, rlen) -> status` – repeated-start transfer.
+//! - `spi_transfer(bus, ptr, len) -> status` – full-duplex, in place in guest memory.
+//!
+//! Pins and channels are small ids chosen by the firmware when registering
+//! peripherals, so the same module runs on boards with different pinouts.
+//! Bus transfers are bounded by `MAX_TRANSFER` and gated by a
+//! `CapabilityPolicy`: the running module needs an `I2c { bus, addr }` or
+//! `Spi { bus }` grant, otherwise the call returns `E_DENIED`.
+
+use super::caps::{Capability, CapabilityPolicy};
+use super::{GuestMemory, HostFn, HostImports, E_DENIED, E_DEVICE, E_INVALID, OK};
+use crate::{Error, ModuleId, Result};
+use alloc::{boxed::Box, vec::Vec};
+use embedded_hal::digital::{InputPin, OutputPin};
+use embedded_hal::i2c::I2c;
+use embedded_hal::pwm::SetDutyCycle;
+use embedded_hal::spi::SpiDevice;
+
+/// Host functions provided by `HalImports`.
+pub const FUNCTIONS: &[HostFn] = &[
+    HostFn::new("gpio_write", 2),
+    HostFn::new("gpio_read", 1),
+    HostFn::new("adc_read", 1),
+    HostFn::new("pwm_set", 2),
+    HostFn::new("i2c_write", 4),
+    HostFn::new("i2c_read", 4),
+    HostFn::new("i2c_write_read", 6),
+    HostFn::new("spi_transfer", 3),
+];
+
+/// Full-scale PWM duty value accepted by `pwm_set`.
+pub const PWM_FULL_SCALE: u16 = 1000;
+/// Largest single I2C/SPI transfer (bytes per direction); keeps host buffers on the stack.
+pub const MAX_TRANSFER: usize = 64;
+
+/// One-shot ADC channel.
+///
+/// embedded-hal 1.0 dropped its ADC trait, so channels implement this instead;
+/// adapting a HAL's one-shot reader is a one-line `impl`.
+pub trait AdcChannel {
+    /// Performs a blocking conversion and returns the raw value.
+    fn read(&mut self) -> Result<u16>;
+}
+
+// Object-safe shims so pins with different error types share one table.
+trait DynOutput {
+    fn set(&mut self, high: bool) -> bool;
+}
+
+trait DynInput {
+    fn get(&mut self) -> Option<bool>;
+}
+
+trait DynPwm {
+    fn set_permille(&mut self, permille: u16) -> bool;
+}
+
+trait DynI2c {
+    fn write(&mut self, addr: u8, data: &[u8]) -> bool;
+    fn read(&mut self, addr: u8, buf: &mut [u8]) -> bool;
+    fn write_read(&mut self, addr: u8, data: &[u8], buf: &mut [u8]) -> bool;
+}
+
+trait DynSpi {
+    fn transfer(&mut self, buf: &mut [u8]) -> bool;
+}
+
+struct Output<P>(P);
+struct Input<P>(P);
+struct Pwm<P>(P);
+struct I2cBus<B>(B);
+struct Spi<D>(D);
+
+impl<P: OutputPin> DynOutput for Output<P> {
+    fn set(&mut self, high: bool) -> bool {
+        if high {
+            self.0.set_high().is_ok()
+        } else {
+            self.0.set_low().is_ok()
+        }
+    }
+}
+
+impl<P: InputPin> DynInput for Input<P> {
+    fn get(&mut self) -> Option<bool> {
+        self.0.is_high().ok()
+    }
+}
+
+impl<P: SetDutyCycle> DynPwm for Pwm<P> {
+    fn set_permille(&mut self, permille: u16) -> bool {
+        self.0
+            .set_duty_cycle_fraction(permille, PWM_FULL_SCALE)
+            .is_ok()
+    }
+}
+
+impl<B: I2c> DynI2c for I2cBus<B> {
+    fn write(&mut self, addr: u8, data: &[u8]) -> bool {
+        self.0.write(addr, data).is_ok()
+    }
+
+    fn read(&mut self, addr: u8, buf: &mut [u8]) -> bool {
+        self.0.read(addr, buf).is_ok()
+    }
+
+    fn write_read(&mut self, addr: u8, data: &[u8], buf: &mut [u8]) -> bool {
+        self.0.write_read(addr, data, buf).is_ok()
+    }
+}
+
+impl<D: SpiDevice> DynSpi for Spi<D> {
+    fn transfer(&mut self, buf: &mut [u8]) -> bool {
+        self.0.transfer_in_place(buf).is_ok()
+    }
+}
+
+/// Peripheral table exposed to guests through the host ABI.
+#[derive(Default)]
+pub struct HalImports {
+    outputs: Vec<(u8, Box<dyn DynOutput>)>,
+    inputs: Vec<(u8, Box<dyn DynInput>)>,
+    adcs: Vec<(u8, Box<dyn AdcChannel>)>,
+    pwms: Vec<(u8, Box<dyn DynPwm>)>,
+    i2cs: Vec<(u8, Box<dyn DynI2c>)>,
+    spis: Vec<(u8, Box<dyn DynSpi>)>,
+    policy: CapabilityPolicy,
+    active: Option<ModuleId>,
+}
+
+impl HalImports {
+    /// Creates an empty table; register peripherals with the `with_*` methods.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Registers an output pin under `id`.
+    pub fn with_output<P: OutputPin + 'static>(mut self, id: u8, pin: P) -> Self {
+        self.outputs.push((id, Box::new(Output(pin))));
+        self
+    }
+
+    /// Registers an input pin under `id`.
+    pub fn with_input<P: InputPin + 'static>(mut self, id: u8, pin: P) -> Self {
+        self.inputs.push((id, Box::new(Input(pin))));
+        self
+    }
+
+    /// Registers an ADC channel under `id`.
+    pub fn with_adc<A: AdcChannel + 'static>(mut self, id: u8, channel: A) -> Self {
+        self.adcs.push((id, Box::new(channel)));
+        self
+    }
+
+    /// Registers a PWM channel under `id`.
+    pub fn with_pwm<P: SetDutyCycle + 'static>(mut self, id: u8, channel: P) -> Self {
+        self.pwms.push((id, Box::new(Pwm(channel))));
+        self
+    }
+
+    /// Registers an I2C bus under `id`.
+    pub fn with_i2c<B: I2c + 'static>(mut self, id: u8, bus: B) -> Self {
+        self.i2cs.push((id, Box::new(I2cBus(bus))));
+        self
+    }
+
+    /// Registers an SPI device under `id`.
+    pub fn with_spi<D: SpiDevice + 'static>(mut self, id: u8, device: D) -> Self {
+        self.spis.push((id, Box::new(Spi(device))));
+        self
+    }
+
+    /// Sets the capability policy gating bus access.
+    pub fn with_policy(mut self, policy: CapabilityPolicy) -> Self {
+        self.policy = policy;
+        self
+    }
+
+    /// Mutable access to the capability policy (grant/revoke at runtime).
+    pub fn policy_mut(&mut self) -> &mut CapabilityPolicy {
+        &mut self.policy
+    }
+
+    fn granted(&self, capability: Capability) -> bool {
+        self.active
+            .is_some_and(|module_id| self.policy.allows(module_id, capability))
+    }
+
+    fn gpio_write(&mut self, pin: i32, level: i32) -> i32 {
+        match lookup(&mut self.outputs, pin).map(|out| out.set(level != 0)) {
+            Some(true) => OK,
+            Some(false) => E_DEVICE,
+            None => E_INVALID,
+        }
+    }
+
+    fn gpio_read(&mut self, pin: i32) -> i32 {
+        match lookup(&mut self.inputs, pin).map(|input| input.get()) {
+            Some(Some(high)) => high as i32,
+            Some(None) => E_DEVICE,
+            None => E_INVALID,
+        }
+    }
+
+    fn adc_read(&mut self, channel: i32) -> i32 {
+        match lookup(&mut self.adcs, channel).map(|adc| adc.read()) {
+            Some(Ok(raw)) => raw as i32,
+            Some(Err(_)) => E_DEVICE,
+            None => E_INVALID,
+        }
+    }
+
+    fn pwm_set(&mut self, channel: i32, permille: i32) -> i32 {
+        if !(0..=PWM_FULL_SCALE as i32).contains(&permille) {
+            return E_INVALID;
+        }
+        match lookup(&mut self.pwms, channel).map(|pwm| pwm.set_permille(permille as u16)) {
+            Some(true) => OK,
+            Some(false) => E_DEVICE,
+            None => E_INVALID,
+        }
+    }
+
+    fn i2c_write(&mut self, args: &[i32; 4], memory: &dyn GuestMemory) -> i32 {
+        let [bus, addr, ptr, len] = *args;
+        let mut data = [0u8; MAX_TRANSFER];
+        let Some(data) = guest_read(memory, ptr, len, &mut data) else {
+            return E_INVALID;
+        };
+        self.with_i2c_device(bus, addr, |dev, addr| dev.write(addr, data))
+    }
+
+    fn i2c_read(&mut self, args: &[i32; 4], memory: &mut dyn GuestMemory) -> i32 {
+        let [bus, addr, ptr, len] = *args;
+        let Some(len) = transfer_len(len) else {
+            return E_INVALID;
+        };
+        let mut buf = [0u8; MAX_TRANSFER];
+        let buf = &mut buf[..len];
+        let status = self.with_i2c_device(bus, addr, |dev, addr| dev.read(addr, buf));
+        guest_write(memory, status, ptr, buf)
+    }
+
+    fn i2c_write_read(&mut self, args: &[i32; 6], memory: &mut dyn GuestMemory) -> i32 {
+        let [bus, addr, wptr, wlen, rptr, rlen] = *args;
+        let mut data = [0u8; MAX_TRANSFER];
+        let Some(data) = guest_read(memory, wptr, wlen, &mut data) else {
+            return E_INVALID;
+        };
+        let Some(rlen) = transfer_len(rlen) else {
+            return E_INVALID;
+        };
+        let mut buf = [0u8; MAX_TRANSFER];
+        let buf = &mut buf[..rlen];
+        let status = self.with_i2c_device(bus, addr, |dev, addr| dev.write_read(addr, data, buf));
+        guest_write(memory, status, rptr, buf)
+    }
+
+    fn spi_transfer(&mut self, args: &[i32; 3], memory: &mut dyn GuestMemory) -> i32 {
+        let [bus, ptr, len] = *args;
+        let mut buf = [0u8; MAX_TRANSFER];
+        let Some(buf) = guest_read_mut(memory, ptr, len, &mut buf) else {
+            return E_INVALID;
+        };
+        let Ok(bus) = u8::try_from(bus) else {
+            return E_INVALID;
+        };
+        if !self.granted(Capability::Spi { bus }) {
+            return E_DENIED;
+        }
+        let status = match lookup(&mut self.spis, bus as i32).map(|dev| dev.transfer(buf)) {
+            Some(true) => OK,
+            Some(false) => E_DEVICE,
+            None => E_INVALID,
+        };
+        guest_write(memory, status, ptr, buf)
+    }
+
+    /// Checks the grant and bus id, then runs `op` against the device.
+    fn with_i2c_device(
+        &mut self,
+        bus: i32,
+        addr: i32,
+        op: impl FnOnce(&mut dyn DynI2c, u8) -> bool,
+    ) -> i32 {
+        let (Ok(bus), Ok(addr @ 0..=0x7f)) = (u8::try_from(bus), u8::try_from(addr)) else {
+            return E_INVALID;
+        };
+        if !self.granted(Capability::I2c { bus, addr }) {
+            return E_DENIED;
+        }
+        match lookup(&mut self.i2cs, bus as i32).map(|dev| op(dev, addr)) {
+            Some(true) => OK,
+            Some(false) => E_DEVICE,
+            None => E_INVALID,
+        }
+    }
+}
+
+fn transfer_len(len: i32) -> Option<usize> {
+    usize::try_from(len)
+        .ok()
+        .filter(|len| (1..=MAX_TRANSFER).contains(len))
+}
+
+fn guest_read<'b>(
+    memory: &dyn GuestMemory,
+    ptr: i32,
+    len: i32,
+    buf: &'b mut [u8; MAX_TRANSFER],
+) -> Option<&'b [u8]> {
+    guest_read_mut(memory, ptr, len, buf).map(|buf| &*buf)
+}
+
+fn guest_read_mut<'b>(
+    memory: &dyn GuestMemory,
+    ptr: i32,
+    len: i32,
+    buf: &'b mut [u8; MAX_TRANSFER],
+) -> Option<&'b mut [u8]> {
+    let buf = &mut buf[..transfer_len(len)?];
+    memory.read(ptr as u32, buf).ok()?;
+    Some(buf)
+}
+
+/// Copies `data` back to the guest when the transfer succeeded.
+fn guest_write(memory: &mut dyn GuestMemory, status: i32, ptr: i32, data: &[u8]) -> i32 {
+    if status != OK {
+        return status;
+    }
+    match memory.write(ptr as u32, data) {
+        Ok(()) => OK,
+        Err(_) => E_INVALID,
+    }
+}
+
+fn lookup<T: ?Sized>(table: &mut [(u8, Box<T>)], id: i32) -> Option<&mut T> {
+    let id = u8::try_from(id).ok()?;
+    table
+        .iter_mut()
+        .find(|(slot, _)| *slot == id)
+        .map(|(_, dev)| dev.as_mut())
+}
+
+impl HostImports for HalImports {
+    fn functions(&self) -> &[HostFn] {
+        FUNCTIONS
+    }
+
+    fn call(&mut self, name: &str, args: &[i32], memory: &mut dyn GuestMemory) -> Result<i32> {
+        match (name, args) {
+            ("gpio_write", &[pin, level]) => Ok(self.gpio_write(pin, level)),
+            ("gpio_read", &[pin]) => Ok(self.gpio_read(pin)),
+            ("adc_read", &[channel]) => Ok(self.adc_read(channel)),
+            ("pwm_set", &[channel, permille]) => Ok(self.pwm_set(channel, permille)),
+            ("i2c_write", &[a, b, c, d]) => Ok(self.i2c_write(&[a, b, c, d], memory)),
+            ("i2c_read", &[a, b, c, d]) => Ok(self.i2c_read(&[a, b, c, d], memory)),
+            ("i2c_write_read", &[a, b, c, d, e, f]) => {
+                Ok(self.i2c_write_read(&[a, b, c, d, e, f], memory))
+            }
+            ("spi_transfer", &[a, b, c]) => Ok(self.spi_transfer(&[a, b, c], memory)),
+            _ => Err(Error::Engine("hal: bad host call")),
+        }
+    }
+
+    fn enter(&mut self, module_id: ModuleId) {
+        self.active = Some(module_id);
+    }
+}
+
+#[cfg(all(test, feature = "std"))]
+mod tests {
+    use super::*;
+    use core::convert::Infallible;
+    use embedded_hal::digital::ErrorType;
+    use std::cell::Cell;
+    use std::rc::Rc;
+
+    struct Led(Rc<Cell<bool>>);
+
+    impl ErrorType for Led {
+        type Error = Infallible;
+    }
+
+    impl OutputPin for Led {
+        fn set_low(&mut self) -> core::result::Result<(), Infallible> {
+            self.0.set(false);
+            Ok(())
+        }
+
+        fn set_high(&mut self) -> core::result::Result<(), Infallible> {
+            self.0.set(true);
+            Ok(())
+        }
+    }
+
+    struct Button;
+
+    impl ErrorType for Button {
+        type Error = Infallible;
+    }
+
+    impl InputPin for Button {
+        fn is_high(&mut self) -> core::result::Result<bool, Infallible> {
+            Ok(true)
+        }
+
+        fn is_low(&mut self) -> core::result::Result<bool, Infallible> {
+            Ok(false)
+        }
+    }
+
+    struct Dimmer(Rc<Cell<u16>>);
+
+    impl embedded_hal::pwm::ErrorType for Dimmer {
+        type Error = Infallible;
+    }
+
+    impl SetDutyCycle for Dimmer {
+        fn max_duty_cycle(&self) -> u16 {
+            255
+        }
+
+        fn set_duty_cycle(&mut self, duty: u16) -> core::result::Result<(), Infallible> {
+            self.0.set(duty);
+            Ok(())
+        }
+    }
+
+    struct Thermistor;
+
+    impl AdcChannel for Thermistor {
+        fn read(&mut self) -> Result<u16> {
+            Ok(2048)
+        }
+    }
+
+    #[test]
+    fn guest_calls_reach_peripherals() {
+        let led = Rc::new(Cell::new(false));
+        let duty = Rc::new(Cell::new(0));
+        let mut hal = HalImports::new()
+            .with_output(2, Led(led.clone()))
+            .with_input(0, Button)
+            .with_adc(1, Thermistor)
+            .with_pwm(3, Dimmer(duty.clone()));
+        let mut memory = [0u8; 0];
+
+        assert_eq!(hal.call("gpio_write", &[2, 1], &mut memory).unwrap(), OK);
+        assert!(led.get());
+        assert_eq!(hal.call("gpio_read", &[0], &mut memory).unwrap(), 1);
+        assert_eq!(hal.call("adc_read", &[1], &mut memory).unwrap(), 2048);
+        assert_eq!(hal.call("pwm_set", &[3, 500], &mut memory).unwrap(), OK);
+        assert_eq!(duty.get(), 127);
+    }
+
+    /// Register file at 0x48: reads return the last written byte + 1.
+    struct Sensor;
+
+    impl embedded_hal::i2c::ErrorType for Sensor {
+        type Error = Infallible;
+    }
+
+    impl I2c for Sensor {
+        fn transaction(
+            &mut self,
+            address: u8,
+            operations: &mut [embedded_hal::i2c::Operation<'_>],
+        ) -> core::result::Result<(), Infallible> {
+            assert_eq!(address, 0x48);
+            let mut last = 0u8;
+            for op in operations {
+                match op {
+                    embedded_hal::i2c::Operation::Write(data) => last = data[data.len() - 1],
+                    embedded_hal::i2c::Operation::Read(buf) => buf.fill(last + 1),
+                }
+            }
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn i2c_calls_respect_capabilities() {
+        let policy = CapabilityPolicy::new().with(1, Capability::I2c { bus: 0, addr: 0x48 });
+        let mut hal = HalImports::new().with_i2c(0, Sensor).with_policy(policy);
+        let mut memory = [0u8; 16];
+        memory[0] = 0x10;
+
+        hal.enter(1);
+        assert_eq!(
+            hal.call("i2c_write_read", &[0, 0x48, 0, 1, 4, 2], &mut memory)
+                .unwrap(),
+            OK
+        );
+        assert_eq!(&memory[4..6], &[0x11, 0x11]);
+        assert_eq!(
+            hal.call("i2c_write", &[0, 0x49, 0, 1], &mut memory)
+                .unwrap(),
+            E_DENIED
+        );
+        assert_eq!(
+            hal.call(
+                "i2c_read",
+                &[0, 0x48, 0, MAX_TRANSFER as i32 + 1],
+                &mut memory
+            )
+            .unwrap(),
+            E_INVALID
+        );
+
+        hal.enter(2);
+        assert_eq!(
+            hal.call("i2c_read", &[0, 0x48, 8, 2], &mut memory).unwrap(),
+            E_DENIED
+        );
+        assert_eq!(&memory[8..10], &[0, 0]);
+    }
+
+    #[test]
+    fn rejects_unknown_ids_and_ranges() {
+        let mut hal = HalImports::new().with_pwm(0, Dimmer(Rc::new(Cell::new(0))));
+        let mut memory = [0u8; 0];
+
+        assert_eq!(
+            hal.call("gpio_write", &[9, 1], &mut memory).unwrap(),
+            E_INVALID
+        );
+        assert_eq!(
+            hal.call("gpio_read", &[-1], &mut memory).unwrap(),
+            E_INVALID
+        );
+        assert_eq!(
+            hal.call("pwm_set", &[0, 1001], &mut memory).unwrap(),
+            E_INVALID
+        );
+        assert!(hal.call("gpio_write", &[1], &mut memory).is_err());
+    }
+}
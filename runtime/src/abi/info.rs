@@ -0,0 +1,100 @@
+//! `invocation_info`: tells a guest why and under which identity it runs.
+//!
+//! `invocation_info(ptr) -> len | status` writes a little-endian record and
+//! returns its length. The layout is append-only; guests check `version` (or
+//! the returned length) before reading fields added later.
+//!
+//! | offset | type | field                                              |
+//! |--------|------|----------------------------------------------------|
+//! | 0      | u16  | `version` (= `INFO_VERSION`)                       |
+//! | 2      | u8   | `reason` (0 direct, 1 scheduled, 2 event, 3 remote) |
+//! | 3      | u8   | reserved, 0                                        |
+//! | 4      | u32  | `module_id`                                        |
+//! | 8      | u32  | `module_version` (manifest sequence)               |
+//! | 12     | u32  | reserved, 0                                        |
+//! | 16     | u64  | `correlation` id                                   |
+
+use super::{GuestMemory, HostFn, HostImports, E_INVALID};
+use crate::trace::{Invocation, TraceContext};
+use crate::{Error, Result};
+
+/// Layout version written at offset 0.
+pub const INFO_VERSION: u16 = 1;
+/// Size of the version-1 record.
+pub const INFO_LEN: usize = 24;
+
+/// Host functions provided by `InfoImports`.
+pub const FUNCTIONS: &[HostFn] = &[HostFn::new("invocation_info", 1)];
+
+/// Serializes an invocation in the guest layout.
+pub fn encode(invocation: &Invocation) -> [u8; INFO_LEN] {
+    let mut out = [0u8; INFO_LEN];
+    out[0..2].copy_from_slice(&INFO_VERSION.to_le_bytes());
+    out[2] = invocation.reason as u8;
+    out[4..8].copy_from_slice(&invocation.module_id.to_le_bytes());
+    out[8..12].copy_from_slice(&invocation.version.to_le_bytes());
+    out[16..24].copy_from_slice(&invocation.correlation.to_le_bytes());
+    out
+}
+
+/// Provides `invocation_info` from the runtime's `TraceContext`.
+pub struct InfoImports {
+    trace: TraceContext,
+}
+
+impl InfoImports {
+    /// Pass `Runtime::trace()` so the record matches the running invocation.
+    pub fn new(trace: TraceContext) -> Self {
+        Self { trace }
+    }
+}
+
+impl HostImports for InfoImports {
+    fn functions(&self) -> &[HostFn] {
+        FUNCTIONS
+    }
+
+    fn call(&mut self, name: &str, args: &[i32], memory: &mut dyn GuestMemory) -> Result<i32> {
+        match (name, args) {
+            ("invocation_info", &[ptr]) => {
+                let record = encode(&self.trace.invocation());
+                Ok(memory
+                    .write(ptr as u32, &record)
+                    .map_or(E_INVALID, |_| INFO_LEN as i32))
+            }
+            _ => Err(Error::Engine("info: bad host call")),
+        }
+    }
+}
+
+#[cfg(all(test, feature = "std"))]
+mod tests {
+    use super::*;
+    use crate::trace::Reason;
+
+    #[test]
+    fn writes_versioned_record() {
+        let trace = TraceContext::new();
+        let mut imports = InfoImports::new(trace.clone());
+        let mut memory = [0xffu8; 32];
+
+        let _scope = trace.enter(Invocation {
+            module_id: 7,
+            version: 3,
+            reason: Reason::RemoteCommand,
+            correlation: 0x1122,
+        });
+        assert_eq!(
+            imports.call("invocation_info", &[4], &mut memory).unwrap(),
+            INFO_LEN as i32
+        );
+        assert_eq!(
+            &memory[4..28],
+            &[1, 0, 3, 0, 7, 0, 0, 0, 3, 0, 0, 0, 0, 0, 0, 0, 0x22, 0x11, 0, 0, 0, 0, 0, 0]
+        );
+        assert_eq!(
+            imports.call("invocation_info", &[16], &mut memory).unwrap(),
+            E_INVALID
+        );
+    }
+}
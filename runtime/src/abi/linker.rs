@@ -0,0 +1,369 @@
+//! `Linker`: the host side of instantiation, shared by every engine.
+//!
+//! Backends differ only in how they define a function on their own linker.
+//! Which imports resolve, which signatures match the ABI and how a call
+//! reaches `Imports` is decided here, so a host function registered once
+//! behaves the same on wasm3, wasmi, tinywasm, wasmtime and later backends.
+//!
+//! A backend that inspects a module's imports (wasmi, wasmtime) asks
+//! `resolve` for each one and defines what it gets back; one that cannot
+//! (wasm3, tinywasm) defines every entry of `functions`. Either way the
+//! defined function forwards its raw arguments to `call`.
+//!
+//! An engine binds the `&mut Context` it was invoked with for the length of
+//! the call (`bind_context` .. `unbind_context`); host calls reach it through
+//! `HostContext`, one call at a time.
+//!
+//! Call budgets (`caps::CapabilityPolicy::limit_calls`, attached with
+//! `Imports::with_call_budgets`) are counted here: `enter` starts a fresh
+//! count for the module about to run and `exit` drops it. A module that runs
+//! again while its outermost invocation is still in progress (say, routed
+//! back to by a module it called) spends that invocation's budget, so
+//! re-entering itself does not buy a module more calls. A call past its
+//! limit returns
+//! `E_DENIED` without reaching the import set (the call log still records
+//! it, as `CallOutcome::Denied`).
+
+use super::{GuestMemory, HostContext, HostFn, Imports, E_DENIED, IMPORT_MODULE, MAX_PARAMS};
+use crate::{Error, ModuleId, Result};
+use alloc::vec::Vec;
+use core::any::Any;
+use core::ptr::NonNull;
+
+/// Value type in a guest import's signature, as far as the ABI cares.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ValType {
+    I32,
+    /// Anything else; never part of an ABI signature.
+    Other,
+}
+
+/// Host imports ready to be linked into guests.
+pub struct Linker {
+    imports: Imports,
+    /// Context of the running invocation, between `bind_context` and
+    /// `unbind_context`.
+    ctx: Option<NonNull<dyn Any>>,
+    /// Invocations in progress, innermost last.
+    frames: Vec<Frame>,
+}
+
+/// One invocation: its module and the budgeted calls it made so far.
+struct Frame {
+    module_id: ModuleId,
+    spent: Vec<(&'static str, u32)>,
+}
+
+impl Linker {
+    pub fn new(imports: Imports) -> Self {
+        Self {
+            imports,
+            ctx: None,
+            frames: Vec::new(),
+        }
+    }
+
+    pub fn imports(&self) -> &Imports {
+        &self.imports
+    }
+
+    pub fn imports_mut(&mut self) -> &mut Imports {
+        &mut self.imports
+    }
+
+    pub fn into_imports(self) -> Imports {
+        self.imports
+    }
+
+    /// Every host function, for backends that define them all up front.
+    pub fn functions(&self) -> impl Iterator<Item = HostFn> + '_ {
+        self.imports.functions()
+    }
+
+    /// Resolves the guest import `module.name` of signature `params -> results`.
+    ///
+    /// `Ok(None)` when the host provides no such function (instantiation
+    /// then reports the import as unresolved); `Err` when it does, but the
+    /// guest expects another signature.
+    pub fn resolve(
+        &self,
+        module: &str,
+        name: &str,
+        mut params: impl ExactSizeIterator<Item = ValType>,
+        mut results: impl ExactSizeIterator<Item = ValType>,
+    ) -> Result<Option<HostFn>> {
+        if module != IMPORT_MODULE {
+            return Ok(None);
+        }
+        let Some(host_fn) = self.imports.find(name) else {
+            return Ok(None);
+        };
+        let abi_shaped = params.len() == host_fn.params as usize
+            && params.all(|ty| ty == ValType::I32)
+            && results.len() == 1
+            && results.all(|ty| ty == ValType::I32);
+        match abi_shaped {
+            true => Ok(Some(host_fn)),
+            false => Err(Error::Engine("host import signature mismatch")),
+        }
+    }
+
+    /// Tells the imports which module is about to run and starts its call
+    /// budgets afresh, unless an invocation of the same module is already
+    /// in progress, whose budgets it shares.
+    pub fn enter(&mut self, module_id: ModuleId) {
+        self.frames.push(Frame {
+            module_id,
+            spent: Vec::new(),
+        });
+        self.imports.enter(module_id);
+    }
+
+    /// Ends the invocation `enter` started; the one it interrupted, if any,
+    /// continues with what is left of its budgets.
+    pub fn exit(&mut self) {
+        self.frames.pop();
+        self.imports.exit();
+    }
+
+    /// Hands `ctx` to host calls until `unbind_context`.
+    ///
+    /// # Safety
+    ///
+    /// `ctx` must stay alive and must not be used other than through this
+    /// linker until `unbind_context` is called.
+    pub unsafe fn bind_context(&mut self, ctx: &mut dyn Any) {
+        self.ctx = Some(NonNull::from(ctx));
+    }
+
+    /// Ends the borrow `bind_context` started.
+    pub fn unbind_context(&mut self) {
+        self.ctx = None;
+    }
+
+    /// Calls host function `name` with the guest's arguments.
+    pub fn call(
+        &mut self,
+        name: &str,
+        args: impl IntoIterator<Item = i32>,
+        memory: &mut dyn GuestMemory,
+    ) -> Result<i32> {
+        let mut buf = [0i32; MAX_PARAMS];
+        let mut len = 0;
+        for arg in args {
+            let slot = buf
+                .get_mut(len)
+                .ok_or(Error::Engine("too many host params"))?;
+            *slot = arg;
+            len += 1;
+        }
+        if !self.charge(name) {
+            if let (Some(log), Some(host_fn)) = (self.imports.call_log(), self.imports.find(name)) {
+                log.deny(host_fn.name);
+            }
+            return Ok(E_DENIED);
+        }
+        let mut ctx = match self.ctx {
+            // SAFETY: `bind_context` callers keep the context alive and unused
+            // until `unbind_context`; the borrow ends with this call.
+            Some(mut ctx) => HostContext::new(unsafe { ctx.as_mut() }),
+            None => HostContext::none(),
+        };
+        self.imports.call_with(name, &buf[..len], memory, &mut ctx)
+    }
+
+    /// Counts a call of `name` against the running module's budget, kept by
+    /// its outermost frame; false once the budget is spent. Calls outside
+    /// `enter` .. `exit` are not counted.
+    fn charge(&mut self, name: &str) -> bool {
+        let Some(running) = self.frames.last().map(|frame| frame.module_id) else {
+            return true;
+        };
+        let Some(frame) = self.frames.iter_mut().find(|f| f.module_id == running) else {
+            return true;
+        };
+        let Some(limit) = self
+            .imports
+            .call_budgets()
+            .and_then(|policy| policy.call_limit(frame.module_id, name))
+        else {
+            return true;
+        };
+        let Some(host_fn) = self.imports.find(name) else {
+            // Unknown functions fail in dispatch.
+            return true;
+        };
+        let spent = match frame.spent.iter_mut().find(|(n, _)| *n == host_fn.name) {
+            Some((_, spent)) => spent,
+            None => {
+                frame.spent.push((host_fn.name, 0));
+                &mut frame.spent.last_mut().expect("just pushed").1
+            }
+        };
+        if *spent >= limit {
+            return false;
+        }
+        *spent += 1;
+        true
+    }
+}
+
+impl Default for Linker {
+    fn default() -> Self {
+        Self::new(Imports::new())
+    }
+}
+
+impl From<Imports> for Linker {
+    fn from(imports: Imports) -> Self {
+        Self::new(imports)
+    }
+}
+
+#[cfg(all(test, feature = "std"))]
+mod tests {
+    use super::*;
+    use crate::abi::caps::CapabilityPolicy;
+    use crate::abi::{HostImports, OK};
+
+    struct Sum;
+
+    impl HostImports for Sum {
+        fn functions(&self) -> &[HostFn] {
+            const FNS: &[HostFn] = &[HostFn::new("sum", 2)];
+            FNS
+        }
+
+        fn call(
+            &mut self,
+            _name: &str,
+            args: &[i32],
+            _memory: &mut dyn GuestMemory,
+        ) -> Result<i32> {
+            Ok(args.iter().sum())
+        }
+
+        /// Also adds the sum to a `u32` context.
+        fn call_with(
+            &mut self,
+            name: &str,
+            args: &[i32],
+            memory: &mut dyn GuestMemory,
+            ctx: &mut HostContext<'_>,
+        ) -> Result<i32> {
+            let sum = self.call(name, args, memory)?;
+            if let Some(total) = ctx.get::<u32>() {
+                *total += sum as u32;
+            }
+            Ok(sum)
+        }
+    }
+
+    #[test]
+    fn resolves_abi_signatures_only() {
+        use ValType::{Other, I32};
+        let mut linker = Linker::from(Imports::new().with(Sum));
+        let resolve = |module, name, params: &[ValType], results: &[ValType]| {
+            linker.resolve(
+                module,
+                name,
+                params.iter().copied(),
+                results.iter().copied(),
+            )
+        };
+
+        assert_eq!(
+            resolve("env", "sum", &[I32, I32], &[I32]),
+            Ok(Some(HostFn::new("sum", 2)))
+        );
+        assert_eq!(resolve("env", "missing", &[], &[I32]), Ok(None));
+        assert_eq!(resolve("wasi", "sum", &[I32, I32], &[I32]), Ok(None));
+        for (params, results) in [
+            (&[I32][..], &[I32][..]),
+            (&[I32, Other], &[I32]),
+            (&[I32, I32], &[]),
+            (&[I32, I32], &[Other]),
+        ] {
+            assert!(resolve("env", "sum", params, results).is_err());
+        }
+
+        assert_eq!(linker.call("sum", [2, 3], &mut [0u8; 0]), Ok(5));
+        assert!(linker
+            .call("sum", [1; MAX_PARAMS + 1], &mut [0u8; 0])
+            .is_err());
+        assert_eq!(linker.call("sum", [], &mut [0u8; 0]), Ok(OK));
+    }
+
+    #[test]
+    fn hands_the_bound_context_to_host_calls() {
+        let mut linker = Linker::from(Imports::new().with(Sum));
+        let mut total = 1u32;
+        // SAFETY: `total` is only read after `unbind_context`.
+        unsafe { linker.bind_context(&mut total) };
+        assert_eq!(linker.call("sum", [2, 3], &mut [0u8; 0]), Ok(5));
+        linker.unbind_context();
+        assert_eq!(linker.call("sum", [4], &mut [0u8; 0]), Ok(4));
+        assert_eq!(total, 6);
+
+        let mut other = "not a u32";
+        // SAFETY: as above.
+        unsafe { linker.bind_context(&mut other) };
+        assert_eq!(linker.call("sum", [4], &mut [0u8; 0]), Ok(4));
+        linker.unbind_context();
+    }
+
+    #[test]
+    fn enforces_call_budgets_per_invocation() {
+        let policy = CapabilityPolicy::new().with_call_limit(1, "sum", 2);
+        let mut linker = Linker::from(Imports::new().with(Sum).with_call_budgets(policy));
+        let mut total = 0u32;
+        // SAFETY: `total` is only read after `unbind_context`.
+        unsafe { linker.bind_context(&mut total) };
+
+        linker.enter(1);
+        assert_eq!(linker.call("sum", [1], &mut [0u8; 0]), Ok(1));
+        assert_eq!(linker.call("sum", [2], &mut [0u8; 0]), Ok(2));
+        assert_eq!(linker.call("sum", [4], &mut [0u8; 0]), Ok(E_DENIED));
+
+        linker.exit();
+
+        // Other modules are unlimited; the next invocation starts afresh.
+        linker.enter(2);
+        for _ in 0..3 {
+            assert_eq!(linker.call("sum", [8], &mut [0u8; 0]), Ok(8));
+        }
+        linker.exit();
+        linker.enter(1);
+        assert_eq!(linker.call("sum", [16], &mut [0u8; 0]), Ok(16));
+        linker.exit();
+        linker.unbind_context();
+        assert_eq!(total, 1 + 2 + 3 * 8 + 16);
+    }
+
+    #[test]
+    fn nested_invocations_keep_the_outer_budget() {
+        let policy = CapabilityPolicy::new()
+            .with_call_limit(1, "sum", 2)
+            .with_call_limit(2, "sum", 1);
+        let mut linker = Linker::from(Imports::new().with(Sum).with_call_budgets(policy));
+
+        linker.enter(1);
+        assert_eq!(linker.call("sum", [1], &mut [0u8; 0]), Ok(1));
+        // A host call of module 1 runs module 2, which routes back to module
+        // 1: the inner invocation of 1 spends the outer one's budget...
+        linker.enter(2);
+        assert_eq!(linker.call("sum", [2], &mut [0u8; 0]), Ok(2));
+        linker.enter(1);
+        assert_eq!(linker.call("sum", [3], &mut [0u8; 0]), Ok(3));
+        assert_eq!(linker.call("sum", [4], &mut [0u8; 0]), Ok(E_DENIED));
+        linker.exit();
+        assert_eq!(linker.call("sum", [5], &mut [0u8; 0]), Ok(E_DENIED));
+        linker.exit();
+        // ...which stays spent once it returns.
+        assert_eq!(linker.call("sum", [6], &mut [0u8; 0]), Ok(E_DENIED));
+        linker.exit();
+        linker.enter(1);
+        assert_eq!(linker.call("sum", [7], &mut [0u8; 0]), Ok(7));
+        linker.exit();
+    }
+}
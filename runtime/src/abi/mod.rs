@@ -0,0 +1,380 @@
+//! Host ABI: functions the runtime exposes to guests.
+//!
+//! Every host function lives in the `env` import module and uses only `i32`
+//! parameters plus a single `i32` result, so the same signature set links on
+//! wasm3, wasmtime, and future interpreters; engines link them through
+//! `linker::Linker`. Pointers are guest linear-memory offsets. Recoverable
+//! failures come back as negative status codes; returning `Err` from a host
+//! call traps the guest.
+
+use crate::{Error, ModuleId, Result};
+#[cfg(feature = "alloc")]
+use alloc::{boxed::Box, vec::Vec};
+use core::any::Any;
+
+#[cfg(feature = "alloc")]
+pub mod calllog;
+#[cfg(feature = "alloc")]
+pub mod caps;
+#[cfg(feature = "alloc")]
+pub mod coop;
+#[cfg(feature = "abi-hal")]
+pub mod hal;
+#[cfg(feature = "alloc")]
+pub mod info;
+#[cfg(feature = "alloc")]
+pub mod linker;
+pub mod sys;
+pub mod version;
+
+#[cfg(feature = "alloc")]
+pub use linker::Linker;
+
+/// Import module name guests use for host functions.
+pub const IMPORT_MODULE: &str = "env";
+/// Most parameters a host function may take.
+pub const MAX_PARAMS: usize = 8;
+
+/// Optional guest export `slimmy_alloc(len) -> ptr`: a buffer of `len` bytes
+/// in guest memory for the host to write a payload into, 0 when the guest is
+/// out of memory (guest-wasm's `bump-alloc` feature provides it).
+pub const ALLOC_EXPORT: &str = "slimmy_alloc";
+/// Optional guest export `slimmy_free(ptr, len)`, returning such a buffer.
+pub const FREE_EXPORT: &str = "slimmy_free";
+/// Optional guest export `__slimmy_info() -> status`: hands the host a
+/// description of the module (see `describe`).
+pub const INFO_EXPORT: &str = "__slimmy_info";
+/// Version of this ABI, as guests declare it (see `version`).
+pub const ABI_VERSION: u16 = 1;
+
+/// Success status returned to the guest.
+pub const OK: i32 = 0;
+/// Bad argument (unknown pin/channel, out-of-range value, bad pointer).
+pub const E_INVALID: i32 = -1;
+/// The underlying device reported an error.
+pub const E_DEVICE: i32 = -2;
+/// The calling module was not granted the resource.
+pub const E_DENIED: i32 = -3;
+/// Nothing to return (empty queue, missing entry).
+pub const E_EMPTY: i32 = -4;
+
+/// Signature of a host function: `params` `i32` arguments, one `i32` result.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct HostFn {
+    pub name: &'static str,
+    pub params: u8,
+}
+
+impl HostFn {
+    pub const fn new(name: &'static str, params: u8) -> Self {
+        Self { name, params }
+    }
+}
+
+/// Bounds-checked view of a guest's linear memory during a host call.
+pub trait GuestMemory {
+    /// Copies `buf.len()` bytes starting at guest offset `ptr`.
+    fn read(&self, ptr: u32, buf: &mut [u8]) -> Result<()>;
+    /// Writes `data` starting at guest offset `ptr`.
+    fn write(&mut self, ptr: u32, data: &[u8]) -> Result<()>;
+}
+
+/// Any byte buffer works as guest memory (engine slices, arrays in tests).
+impl<T: AsRef<[u8]> + AsMut<[u8]>> GuestMemory for T {
+    fn read(&self, ptr: u32, buf: &mut [u8]) -> Result<()> {
+        let start = ptr as usize;
+        let end = start
+            .checked_add(buf.len())
+            .ok_or(Error::Engine("guest memory overflow"))?;
+        let src = self
+            .as_ref()
+            .get(start..end)
+            .ok_or(Error::Engine("guest memory out of bounds"))?;
+        buf.copy_from_slice(src);
+        Ok(())
+    }
+
+    fn write(&mut self, ptr: u32, data: &[u8]) -> Result<()> {
+        let start = ptr as usize;
+        let end = start
+            .checked_add(data.len())
+            .ok_or(Error::Engine("guest memory overflow"))?;
+        let dst = self
+            .as_mut()
+            .get_mut(start..end)
+            .ok_or(Error::Engine("guest memory out of bounds"))?;
+        dst.copy_from_slice(data);
+        Ok(())
+    }
+}
+
+/// Reads a host call's `(ptr, len)` argument pair into `buf`: `None` when
+/// `len` is negative or longer than `buf`, or the range is outside guest
+/// memory, which host functions answer with `E_INVALID`.
+pub fn guest_bytes<'b>(
+    memory: &dyn GuestMemory,
+    ptr: i32,
+    len: i32,
+    buf: &'b mut [u8],
+) -> Option<&'b [u8]> {
+    let len = usize::try_from(len).ok().filter(|len| *len <= buf.len())?;
+    let buf = &mut buf[..len];
+    memory.read(ptr as u32, buf).ok()?;
+    Some(buf)
+}
+
+/// `guest_bytes` for a name: non-empty UTF-8.
+pub fn guest_str<'b>(
+    memory: &dyn GuestMemory,
+    ptr: i32,
+    len: i32,
+    buf: &'b mut [u8],
+) -> Option<&'b str> {
+    guest_bytes(memory, ptr, len, buf)
+        .filter(|s| !s.is_empty())
+        .and_then(|s| core::str::from_utf8(s).ok())
+}
+
+/// The `Engine::Context` of the running invocation, as seen by a host call.
+///
+/// Borrowed for the one call only; engines invoked without a context (or
+/// with one of another type) hand out nothing.
+pub struct HostContext<'a> {
+    ctx: Option<&'a mut dyn Any>,
+}
+
+impl<'a> HostContext<'a> {
+    pub fn new(ctx: &'a mut dyn Any) -> Self {
+        Self { ctx: Some(ctx) }
+    }
+
+    /// No context, e.g. for calls made outside an invocation.
+    pub fn none() -> Self {
+        Self { ctx: None }
+    }
+
+    /// The context as a `T`, if it is one.
+    pub fn get<T: Any>(&mut self) -> Option<&mut T> {
+        self.ctx.as_deref_mut()?.downcast_mut()
+    }
+}
+
+/// A set of host functions an engine can link into guests.
+pub trait HostImports {
+    /// Functions provided under `IMPORT_MODULE`.
+    fn functions(&self) -> &[HostFn];
+
+    /// Dispatches a call to one of `functions()`. `args.len()` matches its `params`.
+    fn call(&mut self, name: &str, args: &[i32], memory: &mut dyn GuestMemory) -> Result<i32>;
+
+    /// `call` with the invocation's context; sets that read or update
+    /// per-invocation state override this. Engines always call this one.
+    fn call_with(
+        &mut self,
+        name: &str,
+        args: &[i32],
+        memory: &mut dyn GuestMemory,
+        _ctx: &mut HostContext<'_>,
+    ) -> Result<i32> {
+        self.call(name, args, memory)
+    }
+
+    /// Called by the engine before it runs `module_id`, so calls can be attributed.
+    fn enter(&mut self, _module_id: ModuleId) {}
+
+    /// Called by the engine once the invocation `enter` started returns;
+    /// sets shared across engines attribute further calls to the module that
+    /// was running before it again.
+    fn exit(&mut self) {}
+}
+
+/// Ordered collection of import sets handed to an engine.
+///
+/// The first set providing a name wins, so more specific sets go first.
+#[cfg(feature = "alloc")]
+#[derive(Default)]
+pub struct Imports {
+    sets: Vec<Box<dyn HostImports>>,
+    /// Records every call while enabled (see `calllog`).
+    call_log: Option<calllog::CallLog>,
+    /// Call limits `linker::Linker` enforces per invocation.
+    call_budgets: Option<caps::CapabilityPolicy>,
+}
+
+#[cfg(feature = "alloc")]
+impl Imports {
+    /// Creates an empty import table.
+    pub fn new() -> Self {
+        Self {
+            sets: Vec::new(),
+            call_log: None,
+            call_budgets: None,
+        }
+    }
+
+    /// Adds an import set (builder style).
+    pub fn with(mut self, set: impl HostImports + 'static) -> Self {
+        self.push(set);
+        self
+    }
+
+    /// Adds an import set.
+    pub fn push(&mut self, set: impl HostImports + 'static) {
+        self.sets.push(Box::new(set));
+    }
+
+    /// Records every call into `log` (builder style).
+    pub fn with_call_log(mut self, log: calllog::CallLog) -> Self {
+        self.set_call_log(Some(log));
+        self
+    }
+
+    /// Records every call into `log`; `None` detaches it.
+    pub fn set_call_log(&mut self, log: Option<calllog::CallLog>) {
+        self.call_log = log;
+    }
+
+    pub fn call_log(&self) -> Option<&calllog::CallLog> {
+        self.call_log.as_ref()
+    }
+
+    /// Caps calls per invocation by `policy`'s call limits (builder style).
+    pub fn with_call_budgets(mut self, policy: caps::CapabilityPolicy) -> Self {
+        self.set_call_budgets(Some(policy));
+        self
+    }
+
+    /// Caps calls per invocation by `policy`'s call limits; `None` lifts them.
+    pub fn set_call_budgets(&mut self, policy: Option<caps::CapabilityPolicy>) {
+        self.call_budgets = policy;
+    }
+
+    pub fn call_budgets(&self) -> Option<&caps::CapabilityPolicy> {
+        self.call_budgets.as_ref()
+    }
+
+    /// True when no import sets are registered.
+    pub fn is_empty(&self) -> bool {
+        self.sets.is_empty()
+    }
+
+    /// Looks up a host function signature by name.
+    pub fn find(&self, name: &str) -> Option<HostFn> {
+        self.functions().find(|f| f.name == name)
+    }
+
+    /// Iterates every function from every set, in registration order.
+    pub fn functions(&self) -> impl Iterator<Item = HostFn> + '_ {
+        self.sets
+            .iter()
+            .flat_map(|set| set.functions().iter().copied())
+    }
+
+    /// Tells every set which module is about to run. Engines pair it with
+    /// `exit` once the invocation returns.
+    pub fn enter(&mut self, module_id: ModuleId) {
+        if let Some(log) = &self.call_log {
+            log.enter(module_id);
+        }
+        for set in &mut self.sets {
+            set.enter(module_id);
+        }
+    }
+
+    /// Ends the invocation `enter` started, so the call log and every set
+    /// attribute further calls to the module that was running before it.
+    pub fn exit(&mut self) {
+        if let Some(log) = &self.call_log {
+            log.exit();
+        }
+        for set in &mut self.sets {
+            set.exit();
+        }
+    }
+
+    /// Dispatches a call to the first set providing `name`, without a context.
+    pub fn call(&mut self, name: &str, args: &[i32], memory: &mut dyn GuestMemory) -> Result<i32> {
+        self.call_with(name, args, memory, &mut HostContext::none())
+    }
+
+    /// Dispatches a call to the first set providing `name`.
+    pub fn call_with(
+        &mut self,
+        name: &str,
+        args: &[i32],
+        memory: &mut dyn GuestMemory,
+        ctx: &mut HostContext<'_>,
+    ) -> Result<i32> {
+        let (set, host_fn) = self
+            .sets
+            .iter_mut()
+            .find_map(|set| {
+                let host_fn = set.functions().iter().find(|f| f.name == name).copied();
+                host_fn.map(|host_fn| (set, host_fn))
+            })
+            .ok_or(Error::Engine("host function not found"))?;
+        match &self.call_log {
+            Some(log) => log.record(host_fn.name, memory, |memory| {
+                set.call_with(name, args, memory, ctx)
+            }),
+            None => set.call_with(name, args, memory, ctx),
+        }
+    }
+}
+
+#[cfg(all(test, feature = "std"))]
+mod tests {
+    use super::*;
+
+    struct Echo;
+
+    impl HostImports for Echo {
+        fn functions(&self) -> &[HostFn] {
+            const FNS: &[HostFn] = &[HostFn::new("echo", 1), HostFn::new("poke", 2)];
+            FNS
+        }
+
+        fn call(&mut self, name: &str, args: &[i32], memory: &mut dyn GuestMemory) -> Result<i32> {
+            match name {
+                "echo" => Ok(args[0]),
+                "poke" => memory
+                    .write(args[0] as u32, &[args[1] as u8])
+                    .map(|_| OK)
+                    .or(Ok(E_INVALID)),
+                _ => Err(Error::Engine("host function not found")),
+            }
+        }
+    }
+
+    #[test]
+    fn guest_arguments_are_bounds_checked() {
+        let memory = *b"led\xff\0\0\0\0";
+        let mut buf = [0u8; 4];
+        assert_eq!(guest_bytes(&memory, 0, 3, &mut buf), Some(&b"led"[..]));
+        assert_eq!(guest_bytes(&memory, 0, 0, &mut buf), Some(&[][..]));
+        assert_eq!(guest_bytes(&memory, 0, -1, &mut buf), None);
+        assert_eq!(guest_bytes(&memory, 0, 5, &mut buf), None);
+        assert_eq!(guest_bytes(&memory, 6, 4, &mut buf), None);
+        assert_eq!(guest_bytes(&memory, -1, 1, &mut buf), None);
+
+        assert_eq!(guest_str(&memory, 0, 3, &mut buf), Some("led"));
+        assert_eq!(guest_str(&memory, 0, 0, &mut buf), None);
+        assert_eq!(guest_str(&memory, 0, 4, &mut buf), None);
+    }
+
+    #[test]
+    fn imports_dispatch_by_name() {
+        let mut imports = Imports::new().with(Echo);
+        let mut memory = [0u8; 4];
+
+        assert_eq!(imports.find("poke"), Some(HostFn::new("poke", 2)));
+        assert_eq!(imports.call("echo", &[41], &mut memory).unwrap(), 41);
+        assert_eq!(imports.call("poke", &[2, 9], &mut memory).unwrap(), OK);
+        assert_eq!(memory, [0, 0, 9, 0]);
+        assert_eq!(
+            imports.call("poke", &[4, 9], &mut memory).unwrap(),
+            E_INVALID
+        );
+        assert!(imports.call("missing", &[], &mut memory).is_err());
+    }
+}
@@ -0,0 +1,94 @@
+//! `time_ms` and `random`: the nondeterministic inputs most guests need.
+//!
+//! Guest imports (module `env`, all `i32`):
+//! - `time_ms(ptr) -> status` – writes the wall clock as a little-endian `u64`
+//!   of milliseconds since the Unix epoch.
+//! - `random(ptr, len) -> status` – fills `len` bytes with host entropy.
+//!
+//! Wrap `SysImports` in `replay::Recorder` to capture what a field invocation
+//! saw, and replay it bit-for-bit on a host build.
+
+use super::{GuestMemory, HostFn, HostImports, E_INVALID, OK};
+use crate::schedule::Clock;
+use crate::{Error, Result};
+
+/// Host functions provided by `SysImports`.
+pub const FUNCTIONS: &[HostFn] = &[HostFn::new("time_ms", 1), HostFn::new("random", 2)];
+
+/// Largest `random` request, in bytes.
+pub const MAX_RANDOM_LEN: usize = 256;
+
+/// Provides `time_ms` from a `Clock` and `random` from `fill`.
+pub struct SysImports<C, R> {
+    clock: C,
+    fill: R,
+}
+
+impl<C, R> SysImports<C, R>
+where
+    C: Clock,
+    R: FnMut(&mut [u8]),
+{
+    /// `fill` writes entropy into the whole buffer (hardware RNG, `getrandom`, ...).
+    pub fn new(clock: C, fill: R) -> Self {
+        Self { clock, fill }
+    }
+}
+
+impl<C, R> HostImports for SysImports<C, R>
+where
+    C: Clock,
+    R: FnMut(&mut [u8]),
+{
+    fn functions(&self) -> &[HostFn] {
+        FUNCTIONS
+    }
+
+    fn call(&mut self, name: &str, args: &[i32], memory: &mut dyn GuestMemory) -> Result<i32> {
+        match (name, args) {
+            ("time_ms", &[ptr]) => {
+                let now = self.clock.now_ms().to_le_bytes();
+                Ok(memory.write(ptr as u32, &now).map_or(E_INVALID, |_| OK))
+            }
+            ("random", &[ptr, len]) => {
+                let Ok(len) = usize::try_from(len) else {
+                    return Ok(E_INVALID);
+                };
+                if len > MAX_RANDOM_LEN {
+                    return Ok(E_INVALID);
+                }
+                let mut buf = [0u8; MAX_RANDOM_LEN];
+                (self.fill)(&mut buf[..len]);
+                Ok(memory
+                    .write(ptr as u32, &buf[..len])
+                    .map_or(E_INVALID, |_| OK))
+            }
+            _ => Err(Error::Engine("sys: bad host call")),
+        }
+    }
+}
+
+#[cfg(all(test, feature = "std"))]
+mod tests {
+    use super::*;
+
+    struct Fixed;
+
+    impl Clock for Fixed {
+        fn now_ms(&self) -> u64 {
+            0x0102_0304_0506
+        }
+    }
+
+    #[test]
+    fn writes_time_and_entropy() {
+        let mut imports = SysImports::new(Fixed, |buf: &mut [u8]| buf.fill(7));
+        let mut memory = [0u8; 16];
+        assert_eq!(imports.call("time_ms", &[0], &mut memory), Ok(OK));
+        assert_eq!(memory[..8], 0x0102_0304_0506u64.to_le_bytes());
+        assert_eq!(imports.call("random", &[8, 4], &mut memory), Ok(OK));
+        assert_eq!(memory[8..14], [7, 7, 7, 7, 0, 0]);
+        assert_eq!(imports.call("random", &[14, 4], &mut memory), Ok(E_INVALID));
+        assert_eq!(imports.call("random", &[0, -1], &mut memory), Ok(E_INVALID));
+    }
+}
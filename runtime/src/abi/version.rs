@@ -0,0 +1,105 @@
+//! ABI version negotiation.
+//!
+//! guest-wasm embeds the ABI version it was written against (its
+//! `ABI_VERSION`) as a two-byte little-endian custom section named
+//! `slimmy.abi`. `Runtime` reads it before every engine load and refuses a
+//! module built for a version outside `MIN_ABI_VERSION..=ABI_VERSION` with
+//! `Error::AbiMismatch`, so a fleet mixing firmware and module releases gets
+//! a clear error at load instead of a failed link or an odd status at the
+//! first host call. Modules without the section (hand-written wat, other
+//! toolchains) and non-wasm artifacts (AOT, native) are not checked.
+//! `packer --strip` keeps the section.
+
+use super::ABI_VERSION;
+use crate::{Error, Result};
+
+/// Name of the custom section holding the guest's ABI version.
+pub const SECTION: &str = "slimmy.abi";
+/// Oldest guest ABI this host still links.
+pub const MIN_ABI_VERSION: u16 = 1;
+
+/// Whether this host speaks ABI `guest`.
+pub fn compatible(guest: u16) -> bool {
+    (MIN_ABI_VERSION..=ABI_VERSION).contains(&guest)
+}
+
+/// The version in `module`'s `slimmy.abi` section; `None` for modules
+/// without one, non-wasm bytes and sections too short to hold it.
+pub fn guest_version(module: &[u8]) -> Option<u16> {
+    let mut rest = module.strip_prefix(b"\0asm")?.get(4..)?;
+    while let Some((&id, tail)) = rest.split_first() {
+        rest = tail;
+        let len = read_leb(&mut rest)? as usize;
+        let (mut section, tail) = rest.split_at_checked(len)?;
+        rest = tail;
+        if id != 0 {
+            continue;
+        }
+        let name_len = read_leb(&mut section)? as usize;
+        let (name, payload) = section.split_at_checked(name_len)?;
+        if name == SECTION.as_bytes() {
+            let [lo, hi, ..] = *payload else {
+                return None;
+            };
+            return Some(u16::from_le_bytes([lo, hi]));
+        }
+    }
+    None
+}
+
+/// Fails with `Error::AbiMismatch` when `module` declares an ABI version
+/// this host does not speak.
+pub fn check(module: &[u8]) -> Result<()> {
+    match guest_version(module) {
+        Some(guest) if !compatible(guest) => Err(Error::AbiMismatch {
+            host: ABI_VERSION,
+            guest,
+        }),
+        _ => Ok(()),
+    }
+}
+
+/// Reads an unsigned LEB128 `u32`.
+fn read_leb(bytes: &mut &[u8]) -> Option<u32> {
+    let mut value = 0u32;
+    for shift in (0..35).step_by(7) {
+        let (&byte, tail) = bytes.split_first()?;
+        *bytes = tail;
+        value |= u32::from(byte & 0x7f).checked_shl(shift)?;
+        if byte & 0x80 == 0 {
+            return Some(value);
+        }
+    }
+    None
+}
+
+#[cfg(all(test, feature = "std"))]
+mod tests {
+    use super::*;
+
+    /// A module with a type section and a `slimmy.abi` section for `version`.
+    fn module(version: &[u8]) -> Vec<u8> {
+        let mut bytes = b"\0asm\x01\0\0\0\x01\x04\x01\x60\0\0".to_vec();
+        bytes.extend([0, 11 + version.len() as u8, 10]);
+        bytes.extend(SECTION.as_bytes());
+        bytes.extend(version);
+        bytes
+    }
+
+    #[test]
+    fn reads_the_section_and_refuses_other_versions() {
+        assert_eq!(guest_version(&module(&[1, 0])), Some(1));
+        assert_eq!(check(&module(&ABI_VERSION.to_le_bytes())), Ok(()));
+        assert_eq!(
+            check(&module(&[9, 0])),
+            Err(Error::AbiMismatch {
+                host: ABI_VERSION,
+                guest: 9
+            })
+        );
+        // Nothing to check: no section, a short one, or not wasm at all.
+        assert_eq!(check(b"\0asm\x01\0\0\0"), Ok(()));
+        assert_eq!(guest_version(&module(&[1])), None);
+        assert_eq!(check(b"AOT artifact"), Ok(()));
+    }
+}
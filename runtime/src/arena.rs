@@ -0,0 +1,558 @@
+//! Static arena allocator.
+//!
+//! `Arena<N>` hands out memory from an `N`-byte region it owns, so a firmware
+//! that installs it as the global allocator gets every allocation of the
+//! runtime (`MemoryStore` images, `CachedEngine` handles, scheduler and bus
+//! queues, staged OTA images) from one static whose size is fixed at compile
+//! time and shows up in the linker map:
+//!
+//! ```ignore
+//! #[global_allocator]
+//! static HEAP: runtime::arena::Arena<{ 64 * 1024 }> = runtime::arena::Arena::new();
+//! ```
+//!
+//! Stable Rust collections cannot take a per-instance allocator, so the
+//! runtime types that hold the bulk of its memory also have `in_arena`
+//! constructors that take an arena directly, whether or not it is the global
+//! allocator: `MemoryStore::in_arena`, `CachedEngine::in_arena` and
+//! `bus::Bus::in_arena`. Each `claim`s fixed-capacity slices for its images,
+//! handles or queued messages once, up front, and reports running out of them
+//! as an error (`Error::StorageFull`, a full queue drops its oldest message)
+//! rather than growing:
+//!
+//! ```ignore
+//! static RUNTIME: runtime::arena::Arena<{ 96 * 1024 }> = runtime::arena::Arena::new();
+//!
+//! let store = MemoryStore::in_arena(&RUNTIME, 8, 64 * 1024)?;
+//! let engine = CachedEngine::in_arena(WasmiEngine::new(), &RUNTIME, 8)?;
+//! ```
+//!
+//! Allocation bumps a cursor. Freeing or resizing the most recent allocation
+//! moves the cursor back, and once nothing is live the whole region is
+//! reused; anything else freed stays taken until then. That suits the
+//! runtime's allocate-at-startup pattern. A full arena returns null, which the
+//! OTA path reports as `Error::OutOfMemory`. `peak` tells how much of the
+//! region a workload needed.
+
+use crate::{Error, ModuleId, Result};
+use core::alloc::{GlobalAlloc, Layout};
+use core::cell::UnsafeCell;
+use core::ptr::{self, NonNull};
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+/// Bump allocator over an owned `N`-byte region.
+pub struct Arena<const N: usize> {
+    region: UnsafeCell<[u8; N]>,
+    /// Offset of the first free byte.
+    next: AtomicUsize,
+    /// Allocations not freed yet.
+    live: AtomicUsize,
+    peak: AtomicUsize,
+}
+
+// The region is only handed out in disjoint pieces claimed through `next`.
+unsafe impl<const N: usize> Sync for Arena<N> {}
+
+impl<const N: usize> Arena<N> {
+    pub const fn new() -> Self {
+        Self {
+            region: UnsafeCell::new([0; N]),
+            next: AtomicUsize::new(0),
+            live: AtomicUsize::new(0),
+            peak: AtomicUsize::new(0),
+        }
+    }
+
+    pub const fn capacity(&self) -> usize {
+        N
+    }
+
+    /// Bytes up to the cursor, including what was freed out of order.
+    pub fn used(&self) -> usize {
+        self.next.load(Ordering::Relaxed)
+    }
+
+    /// Highest `used` so far.
+    pub fn peak(&self) -> usize {
+        self.peak.load(Ordering::Relaxed)
+    }
+
+    /// Allocations not freed yet.
+    pub fn live(&self) -> usize {
+        self.live.load(Ordering::Relaxed)
+    }
+
+    /// Claims `len` copies of `value` for good: the backing slice of an
+    /// `in_arena` constructor. Claims are never freed, so the region is not
+    /// rewound while one exists; `Error::OutOfMemory` once it is too full.
+    // Every claim is a fresh piece of the region, never handed out again.
+    #[allow(clippy::mut_from_ref)]
+    pub fn claim<T: Copy>(&'static self, len: usize, value: T) -> Result<&'static mut [T]> {
+        let layout = Layout::array::<T>(len).map_err(|_| Error::OutOfMemory)?;
+        let start = if layout.size() == 0 {
+            NonNull::dangling().as_ptr()
+        } else {
+            // SAFETY: the layout has a non-zero size.
+            unsafe { self.alloc(layout) }.cast::<T>()
+        };
+        if start.is_null() {
+            return Err(Error::OutOfMemory);
+        }
+        // SAFETY: `start` is aligned and valid for `len` values that nothing
+        // else is handed, as it is never deallocated.
+        unsafe {
+            for i in 0..len {
+                start.add(i).write(value);
+            }
+            Ok(core::slice::from_raw_parts_mut(start, len))
+        }
+    }
+
+    fn base(&self) -> *mut u8 {
+        self.region.get().cast()
+    }
+
+    /// Moves the cursor from `from` to `to`; fails if it is elsewhere.
+    fn bump(&self, from: usize, to: usize) -> bool {
+        let moved = self
+            .next
+            .compare_exchange(from, to, Ordering::AcqRel, Ordering::Relaxed)
+            .is_ok();
+        if moved {
+            self.peak.fetch_max(to, Ordering::Relaxed);
+        }
+        moved
+    }
+}
+
+impl<const N: usize> Default for Arena<N> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+unsafe impl<const N: usize> GlobalAlloc for Arena<N> {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        // Counted first, so a concurrent last `dealloc` cannot rewind the
+        // region under an allocation in progress.
+        self.live.fetch_add(1, Ordering::AcqRel);
+        let base = self.base();
+        let mut next = self.next.load(Ordering::Relaxed);
+        loop {
+            let start = base
+                .wrapping_add(next)
+                .align_offset(layout.align())
+                .checked_add(next);
+            let Some((start, end)) = start
+                .and_then(|start| Some((start, start.checked_add(layout.size())?)))
+                .filter(|(_, end)| *end <= N)
+            else {
+                self.live.fetch_sub(1, Ordering::AcqRel);
+                return ptr::null_mut();
+            };
+            if self.bump(next, end) {
+                return base.add(start);
+            }
+            next = self.next.load(Ordering::Relaxed);
+        }
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        let offset = ptr.offset_from(self.base()) as usize;
+        self.bump(offset + layout.size(), offset);
+        if self.live.fetch_sub(1, Ordering::AcqRel) == 1 {
+            let next = self.next.load(Ordering::Relaxed);
+            if self.live.load(Ordering::Acquire) == 0 {
+                self.bump(next, 0);
+            }
+        }
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        let offset = ptr.offset_from(self.base()) as usize;
+        let end = offset.checked_add(new_size).filter(|end| *end <= N);
+        if end.is_some_and(|end| self.bump(offset + layout.size(), end)) {
+            return ptr;
+        }
+        if new_size <= layout.size() {
+            return ptr;
+        }
+        let moved = self.alloc(Layout::from_size_align_unchecked(new_size, layout.align()));
+        if !moved.is_null() {
+            ptr::copy_nonoverlapping(ptr, moved, layout.size());
+            self.dealloc(ptr, layout);
+        }
+        moved
+    }
+}
+
+/// Fixed-capacity map from module id to `V` over a claimed slice: what
+/// `in_arena` constructors keep in place of an `idmap::IdMap`. Ascending by
+/// id; inserting a new id fails once every slot is taken.
+pub struct Slots<V> {
+    /// `&'static mut [Option<(ModuleId, V)>]`, the first `len` taken.
+    slots: NonNull<Option<(ModuleId, V)>>,
+    capacity: usize,
+    len: usize,
+}
+
+// `Slots` owns its slice like a `&'static mut` would.
+unsafe impl<V: Send> Send for Slots<V> {}
+unsafe impl<V: Sync> Sync for Slots<V> {}
+
+impl<V: Copy> Slots<V> {
+    /// Keeps at most `capacity` ids in `arena`.
+    pub fn in_arena<const N: usize>(arena: &'static Arena<N>, capacity: usize) -> Result<Self>
+    where
+        V: 'static,
+    {
+        Ok(Self::new(arena.claim(capacity, None)?))
+    }
+
+    /// Keeps ids in `slots`, one each.
+    pub fn new(slots: &'static mut [Option<(ModuleId, V)>]) -> Self
+    where
+        V: 'static,
+    {
+        slots.fill(None);
+        Self {
+            capacity: slots.len(),
+            slots: NonNull::from(slots).cast(),
+            len: 0,
+        }
+    }
+
+    fn taken(&self) -> &[Option<(ModuleId, V)>] {
+        // SAFETY: `slots` came from a `&'static mut` slice `self` owns.
+        unsafe { core::slice::from_raw_parts(self.slots.as_ptr(), self.len) }
+    }
+
+    fn all_mut(&mut self) -> &mut [Option<(ModuleId, V)>] {
+        // SAFETY: as in `taken`, and `&mut self` is unique.
+        unsafe { core::slice::from_raw_parts_mut(self.slots.as_ptr(), self.capacity) }
+    }
+
+    fn slot(&self, id: ModuleId) -> core::result::Result<usize, usize> {
+        self.taken()
+            .binary_search_by_key(&id, |slot| slot.map_or(ModuleId::MAX, |(id, _)| id))
+    }
+
+    /// Number of entries.
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    /// True when empty.
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Most entries it holds.
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    /// Value for `id`.
+    pub fn get(&self, id: ModuleId) -> Option<V> {
+        let at = self.slot(id).ok()?;
+        self.taken()[at].map(|(_, value)| value)
+    }
+
+    /// Inserts or replaces; returns the replaced value. Fails only for a new
+    /// id when every slot is taken.
+    pub fn insert(&mut self, id: ModuleId, value: V) -> Result<Option<V>> {
+        let len = self.len;
+        match self.slot(id) {
+            Ok(at) => {
+                let old = self.all_mut()[at].replace((id, value));
+                Ok(old.map(|(_, value)| value))
+            }
+            Err(_) if len == self.capacity => Err(Error::Engine("id map full")),
+            Err(at) => {
+                let slots = self.all_mut();
+                slots[at..=len].rotate_right(1);
+                slots[at] = Some((id, value));
+                self.len += 1;
+                Ok(None)
+            }
+        }
+    }
+
+    /// Removes and returns the value for `id`.
+    pub fn remove(&mut self, id: ModuleId) -> Option<V> {
+        let at = self.slot(id).ok()?;
+        let len = self.len;
+        let slots = self.all_mut();
+        let removed = slots[at].take();
+        slots[at..len].rotate_left(1);
+        self.len -= 1;
+        removed.map(|(_, value)| value)
+    }
+
+    /// Keeps only the entries for which `keep` returns true.
+    pub fn retain(&mut self, mut keep: impl FnMut(ModuleId, &V) -> bool) {
+        let mut at = 0;
+        while let Some(&Some((id, value))) = self.taken().get(at) {
+            if keep(id, &value) {
+                at += 1;
+            } else {
+                self.remove(id);
+            }
+        }
+    }
+
+    /// Removes everything.
+    pub fn clear(&mut self) {
+        self.all_mut().fill(None);
+        self.len = 0;
+    }
+
+    /// The `index`-th id in ascending order.
+    pub fn id_at(&self, index: usize) -> Option<ModuleId> {
+        self.taken().get(index)?.map(|(id, _)| id)
+    }
+
+    /// Entries in ascending id order.
+    pub fn iter(&self) -> impl Iterator<Item = (ModuleId, V)> + '_ {
+        self.taken().iter().flatten().copied()
+    }
+
+    /// Entries in ascending id order, values mutable.
+    pub fn iter_mut(&mut self) -> impl Iterator<Item = (ModuleId, &mut V)> + '_ {
+        let len = self.len;
+        self.all_mut()[..len]
+            .iter_mut()
+            .flatten()
+            .map(|(id, value)| (*id, value))
+    }
+}
+
+impl<V> core::fmt::Debug for Slots<V> {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.debug_struct("Slots")
+            .field("len", &self.len)
+            .field("capacity", &self.capacity)
+            .finish()
+    }
+}
+
+/// Module images packed into a claimed byte slice, with an index of `Slots`:
+/// the storage of `MemoryStore::in_arena`. Removing an image moves the ones
+/// after it down, so the free space stays in one piece at the end, where
+/// streamed images are staged until they commit.
+#[cfg(feature = "alloc")]
+#[derive(Debug)]
+pub(crate) struct Images {
+    /// Offset and length of each image in `bytes`.
+    index: Slots<(usize, usize)>,
+    bytes: &'static mut [u8],
+    /// Bytes taken by committed images.
+    used: usize,
+    /// Image being streamed in at `used`: id, announced and written length.
+    staged: Option<(ModuleId, usize, usize)>,
+}
+
+#[cfg(feature = "alloc")]
+impl Images {
+    pub(crate) fn in_arena<const N: usize>(
+        arena: &'static Arena<N>,
+        max_modules: usize,
+        capacity: usize,
+    ) -> Result<Self> {
+        Ok(Self {
+            index: Slots::in_arena(arena, max_modules)?,
+            bytes: arena.claim(capacity, 0)?,
+            used: 0,
+            staged: None,
+        })
+    }
+
+    pub(crate) fn index(&self) -> &Slots<(usize, usize)> {
+        &self.index
+    }
+
+    pub(crate) fn get(&self, id: ModuleId) -> Option<&[u8]> {
+        let (offset, len) = self.index.get(id)?;
+        Some(&self.bytes[offset..offset + len])
+    }
+
+    /// Fails with `StorageFull` when `len` more bytes (beyond the image `id`
+    /// replaces, when `replacing`) or another id do not fit.
+    fn reserve(&self, id: ModuleId, len: usize, replacing: bool) -> Result<()> {
+        let old = self.index.get(id).map(|(_, len)| len);
+        let freed = if replacing { old.unwrap_or(0) } else { 0 };
+        let available = self.bytes.len() - self.used + freed;
+        if len > available {
+            return Err(Error::StorageFull {
+                needed: len,
+                available,
+            });
+        }
+        if old.is_none() && self.index.len() == self.index.capacity() {
+            return Err(Error::Engine("id map full"));
+        }
+        Ok(())
+    }
+
+    /// Replaces the image of `id`; an image being streamed in is dropped.
+    pub(crate) fn insert(&mut self, id: ModuleId, image: &[u8]) -> Result<()> {
+        self.reserve(id, image.len(), true)?;
+        self.staged = None;
+        self.remove(id);
+        self.bytes[self.used..self.used + image.len()].copy_from_slice(image);
+        self.index.insert(id, (self.used, image.len()))?;
+        self.used += image.len();
+        Ok(())
+    }
+
+    pub(crate) fn remove(&mut self, id: ModuleId) -> bool {
+        let Some((offset, len)) = self.index.remove(id) else {
+            return false;
+        };
+        // Staged bytes move down with the images.
+        let end = self.used + self.staged.map_or(0, |(_, _, written)| written);
+        self.bytes.copy_within(offset + len..end, offset);
+        self.used -= len;
+        for (_, (start, _)) in self.index.iter_mut() {
+            if *start > offset {
+                *start -= len;
+            }
+        }
+        true
+    }
+
+    pub(crate) fn clear(&mut self) {
+        self.index.clear();
+        self.used = 0;
+        self.staged = None;
+    }
+
+    pub(crate) fn begin(&mut self, id: ModuleId, len: usize) -> Result<()> {
+        self.staged = None;
+        self.reserve(id, len, false)?;
+        self.staged = Some((id, len, 0));
+        Ok(())
+    }
+
+    pub(crate) fn write(&mut self, chunk: &[u8]) -> Result<()> {
+        let (_, len, written) = self
+            .staged
+            .as_mut()
+            .ok_or(Error::Engine("sink: no write in progress"))?;
+        if *written + chunk.len() > *len {
+            return Err(Error::Engine("sink: more bytes than announced"));
+        }
+        let at = self.used + *written;
+        self.bytes[at..at + chunk.len()].copy_from_slice(chunk);
+        *written += chunk.len();
+        Ok(())
+    }
+
+    pub(crate) fn commit(&mut self) -> Result<()> {
+        match self.staged {
+            Some((id, len, written)) if written == len => {
+                self.remove(id);
+                self.index.insert(id, (self.used, len))?;
+                self.used += len;
+                self.staged = None;
+                Ok(())
+            }
+            Some(_) => Err(Error::Engine("sink: image incomplete")),
+            None => Err(Error::Engine("sink: no write in progress")),
+        }
+    }
+
+    pub(crate) fn abort(&mut self) {
+        self.staged = None;
+    }
+}
+
+#[cfg(all(test, feature = "std"))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn bumps_rewinds_and_reuses_the_region() {
+        let arena = Arena::<256>::new();
+        let word = Layout::from_size_align(8, 8).unwrap();
+        let byte = Layout::from_size_align(1, 1).unwrap();
+        unsafe {
+            let a = arena.alloc(byte);
+            let b = arena.alloc(word);
+            assert_eq!(b as usize % 8, 0);
+            assert!(b > a);
+            let used = arena.used();
+
+            // The last allocation grows in place.
+            assert_eq!(arena.realloc(b, word, 64), b);
+            assert_eq!(arena.used(), used + 56);
+            let b_big = Layout::from_size_align(64, 8).unwrap();
+            assert!(arena
+                .alloc(Layout::from_size_align(512, 1).unwrap())
+                .is_null());
+
+            // Freeing it rewinds; freeing `a` out of order does not, until
+            // nothing is live.
+            arena.dealloc(b, b_big);
+            assert_eq!(arena.used(), used - 8);
+            let c = arena.alloc(byte);
+            arena.dealloc(a, byte);
+            assert!(arena.used() > 0);
+            arena.dealloc(c, byte);
+            assert_eq!((arena.used(), arena.live()), (0, 0));
+            assert_eq!(arena.peak(), used + 56);
+        }
+    }
+    #[test]
+    fn claims_are_kept_and_bounded() {
+        static ARENA: Arena<64> = Arena::new();
+        let words = ARENA.claim(4, 7u32).unwrap();
+        assert_eq!(words, &[7; 4]);
+        words[0] = 1;
+        assert!(ARENA.claim(64, 0u8).is_err());
+        assert_eq!(ARENA.claim(0, 0u64).unwrap().len(), 0);
+        assert_eq!((ARENA.used(), ARENA.live()), (16, 1));
+
+        let mut slots = Slots::new(ARENA.claim(2, None).unwrap());
+        assert_eq!(slots.insert(5, 'b').unwrap(), None);
+        assert_eq!(slots.insert(2, 'a').unwrap(), None);
+        assert_eq!(slots.insert(5, 'c').unwrap(), Some('b'));
+        assert!(slots.insert(9, 'd').is_err());
+        assert_eq!((slots.id_at(0), slots.get(5)), (Some(2), Some('c')));
+        assert_eq!(slots.remove(2), Some('a'));
+        assert_eq!((slots.len(), slots.capacity()), (1, 2));
+    }
+
+    #[test]
+    fn arena_stores_compact_and_stream_images() {
+        static ARENA: Arena<256> = Arena::new();
+        let mut images = Images::in_arena(&ARENA, 3, 16).unwrap();
+        images.insert(1, b"aaaa").unwrap();
+        images.insert(2, b"bbbbbb").unwrap();
+        assert!(matches!(
+            images.insert(3, b"ccccccccc"),
+            Err(Error::StorageFull {
+                needed: 9,
+                available: 6
+            })
+        ));
+
+        // Removing an image moves the ones after it down.
+        assert!(images.remove(1));
+        images.insert(1, b"cccccccccc").unwrap();
+        assert_eq!(images.get(2), Some(&b"bbbbbb"[..]));
+        assert_eq!(images.get(1), Some(&b"cccccccccc"[..]));
+
+        // A staged replacement sits next to the image it replaces.
+        images.remove(2);
+        assert!(images.begin(1, 7).is_err());
+        images.begin(1, 6).unwrap();
+        images.write(&[1; 4]).unwrap();
+        images.write(&[2; 2]).unwrap();
+        assert!(images.write(&[3]).is_err());
+        assert_eq!(images.get(1), Some(&b"cccccccccc"[..]));
+        images.commit().unwrap();
+        assert_eq!(images.get(1), Some(&[1, 1, 1, 1, 2, 2][..]));
+        assert_eq!(images.index().len(), 1);
+    }
+}
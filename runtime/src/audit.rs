@@ -0,0 +1,582 @@
+//! Boot-time integrity audit of stored modules.
+//!
+//! `Runtime::audit` re-checks every module the runtime knows about (its
+//! `deps::Registry`) against a `Keyring` before anything runs, so firmware can
+//! quarantine bad modules at startup instead of discovering them mid-flight.
+//!
+//! Stored bytes are either a raw wasm module or a manifest blob (`SMNY` header
+//! followed by the module). For blobs the manifest must parse, name the same
+//! module id and length, and carry a signature from a trusted key when one is
+//! required. Modules must start with the wasm magic, or the AOT magic when
+//! the manifest sets `FLAG_AOT`; with `verify-blake3`, pinned digests are
+//! compared too. Signatures are checked by the keyring's `Verifier`
+//! (`Keyring::with_verifier`, e.g. a hardware crypto driver) against the
+//! trusted keys of the algorithm the manifest names, or, with `cert-chain`,
+//! through the signer certificate chain it carries up to a trusted root
+//! (`cert::verify_manifest`). With `revocation`, modules and signing keys on
+//! the keyring's revocation list fail (`revoke`).
+
+use crate::manifest::{Manifest, PayloadKind, FLAG_REQUIRE_SIGNATURE, MANIFEST_MAGIC};
+use crate::verify::{self, Algorithm, Verifier, P256_PUBLIC_KEY_LEN};
+use crate::{Engine, ModuleCatalog, ModuleId, ModuleSource, Runtime};
+use alloc::rc::Rc;
+use alloc::vec::Vec;
+use core::fmt;
+
+/// Trust anchors and pins the audit checks against.
+#[derive(Clone, Default)]
+pub struct Keyring {
+    keys: Vec<[u8; 32]>,
+    p256_keys: Vec<[u8; P256_PUBLIC_KEY_LEN]>,
+    #[cfg(feature = "cert-chain")]
+    roots: Vec<verify::PublicKey>,
+    /// Certificates manifests may name by digest.
+    #[cfg(feature = "cert-chain")]
+    certificates: Vec<Vec<u8>>,
+    require_signature: bool,
+    #[cfg(feature = "verify-blake3")]
+    digests: Vec<(ModuleId, [u8; 32])>,
+    #[cfg(feature = "revocation")]
+    revocations: Option<Rc<crate::revoke::RevocationList>>,
+    /// `None` uses `verify::default_verifier`.
+    verifier: Option<Rc<dyn Verifier>>,
+}
+
+impl fmt::Debug for Keyring {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut keyring = f.debug_struct("Keyring");
+        keyring
+            .field("keys", &self.keys)
+            .field("p256_keys", &self.p256_keys);
+        #[cfg(feature = "cert-chain")]
+        keyring
+            .field("roots", &self.roots)
+            .field("certificates", &self.certificates.len());
+        keyring.field("require_signature", &self.require_signature);
+        #[cfg(feature = "verify-blake3")]
+        keyring.field("digests", &self.digests);
+        #[cfg(feature = "revocation")]
+        keyring.field("revocations", &self.revocations);
+        keyring
+            .field("custom_verifier", &self.verifier.is_some())
+            .finish()
+    }
+}
+
+impl Keyring {
+    /// Empty keyring: nothing needs a signature, nothing is pinned.
+    pub const fn new() -> Self {
+        Self {
+            keys: Vec::new(),
+            p256_keys: Vec::new(),
+            #[cfg(feature = "cert-chain")]
+            roots: Vec::new(),
+            #[cfg(feature = "cert-chain")]
+            certificates: Vec::new(),
+            require_signature: false,
+            #[cfg(feature = "verify-blake3")]
+            digests: Vec::new(),
+            #[cfg(feature = "revocation")]
+            revocations: None,
+            verifier: None,
+        }
+    }
+
+    /// Trusts an Ed25519 public key (checked by the keyring's `Verifier`).
+    pub fn with_key(mut self, pubkey: [u8; 32]) -> Self {
+        self.keys.push(pubkey);
+        self
+    }
+
+    /// Trusts an ECDSA P-256 public key (SEC1 compressed) for manifests
+    /// that name `manifest::SIG_ALG_P256`.
+    pub fn with_p256_key(mut self, pubkey: [u8; P256_PUBLIC_KEY_LEN]) -> Self {
+        self.p256_keys.push(pubkey);
+        self
+    }
+
+    /// Trusts signer certificate chains that end at `root`.
+    #[cfg(feature = "cert-chain")]
+    pub fn with_root(mut self, root: verify::PublicKey) -> Self {
+        self.roots.push(root);
+        self
+    }
+
+    /// Holds a certificate manifests may name by digest
+    /// (`manifest::EXT_SIGNER_CERT_HASH`), e.g. an intermediate received
+    /// over OTA. It is trusted only through a chain to a root.
+    #[cfg(feature = "cert-chain")]
+    pub fn with_certificate(mut self, certificate: Vec<u8>) -> Self {
+        self.certificates.push(certificate);
+        self
+    }
+
+    /// Requires every module to be a manifest blob signed by a trusted key.
+    pub fn require_signatures(mut self) -> Self {
+        self.require_signature = true;
+        self
+    }
+
+    /// Pins a module to the BLAKE3 digest of its wasm bytes.
+    #[cfg(feature = "verify-blake3")]
+    pub fn with_digest(mut self, module_id: ModuleId, digest: [u8; 32]) -> Self {
+        self.digests.push((module_id, digest));
+        self
+    }
+
+    /// Fails modules and distrusts keys on `list` (e.g.
+    /// `revoke::Revocations::current`).
+    #[cfg(feature = "revocation")]
+    pub fn with_revocations(mut self, list: Rc<crate::revoke::RevocationList>) -> Self {
+        self.revocations = Some(list);
+        self
+    }
+
+    /// Checks signatures with `verifier` instead of the software default.
+    pub fn with_verifier(mut self, verifier: impl Verifier + 'static) -> Self {
+        self.verifier = Some(Rc::new(verifier));
+        self
+    }
+
+    /// Trusted Ed25519 public keys.
+    pub fn keys(&self) -> &[[u8; 32]] {
+        &self.keys
+    }
+
+    /// Trusted P-256 public keys.
+    pub fn p256_keys(&self) -> &[[u8; P256_PUBLIC_KEY_LEN]] {
+        &self.p256_keys
+    }
+
+    /// Every trusted key, of either algorithm.
+    #[cfg(feature = "revocation")]
+    fn signers(&self) -> Vec<verify::PublicKey> {
+        let ed25519 = self.keys.iter().copied().map(verify::PublicKey::Ed25519);
+        let p256 = self.p256_keys.iter().copied().map(verify::PublicKey::P256);
+        ed25519.chain(p256).collect()
+    }
+
+    /// Roots signer certificate chains must end at.
+    #[cfg(feature = "cert-chain")]
+    pub fn roots(&self) -> &[verify::PublicKey] {
+        &self.roots
+    }
+
+    /// The verifier signatures are checked with; `None` when this build
+    /// cannot check them.
+    pub fn verifier(&self) -> Option<&dyn Verifier> {
+        match &self.verifier {
+            Some(verifier) => Some(&**verifier),
+            None => verify::default_verifier(),
+        }
+    }
+
+    /// BLAKE3 digest of everything that decides what this keyring accepts:
+    /// keys, roots, certificates, pins, the signature requirement and the
+    /// revocation list it carries. A custom verifier counts only as present.
+    #[cfg(feature = "verify-blake3")]
+    pub fn fingerprint(&self) -> [u8; 32] {
+        let mut hasher = blake3::Hasher::new();
+        hasher.update(&[u8::from(self.require_signature)]);
+        hasher.update(&[u8::from(self.verifier.is_some())]);
+        hasher.update(&(self.keys.len() as u32).to_le_bytes());
+        for key in &self.keys {
+            hasher.update(key);
+        }
+        hasher.update(&(self.p256_keys.len() as u32).to_le_bytes());
+        for key in &self.p256_keys {
+            hasher.update(key);
+        }
+        #[cfg(feature = "cert-chain")]
+        {
+            hasher.update(&(self.roots.len() as u32).to_le_bytes());
+            for root in &self.roots {
+                hasher.update(&[root.as_bytes().len() as u8]);
+                hasher.update(root.as_bytes());
+            }
+            hasher.update(&(self.certificates.len() as u32).to_le_bytes());
+            for certificate in &self.certificates {
+                hasher.update(blake3::hash(certificate).as_bytes());
+            }
+        }
+        hasher.update(&(self.digests.len() as u32).to_le_bytes());
+        for (module_id, digest) in &self.digests {
+            hasher.update(&module_id.to_le_bytes());
+            hasher.update(digest);
+        }
+        #[cfg(feature = "revocation")]
+        if let Some(list) = &self.revocations {
+            hasher.update(blake3::hash(list.as_bytes()).as_bytes());
+        }
+        *hasher.finalize().as_bytes()
+    }
+}
+
+/// Why a module failed the audit.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Issue {
+    /// Known to the runtime but absent from the source.
+    Missing,
+    /// Bytes are damaged: bad manifest, wrong length or id, not wasm.
+    Corrupted(&'static str),
+    /// No signature where one is required.
+    Unsigned,
+    /// Signature present but not from a trusted key, or it does not match.
+    Untrusted,
+    /// Wasm bytes differ from the pinned digest.
+    DigestMismatch,
+    /// The module is on the revocation list.
+    Revoked,
+}
+
+impl fmt::Display for Issue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Issue::Missing => f.write_str("missing from storage"),
+            Issue::Corrupted(reason) => write!(f, "corrupted: {reason}"),
+            Issue::Unsigned => f.write_str("signature required but absent"),
+            Issue::Untrusted => f.write_str("signature not from a trusted key"),
+            Issue::DigestMismatch => f.write_str("digest does not match pin"),
+            Issue::Revoked => f.write_str("revoked"),
+        }
+    }
+}
+
+/// One module that failed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Finding {
+    pub module_id: ModuleId,
+    pub issue: Issue,
+}
+
+/// Result of an audit.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct AuditReport {
+    /// Modules examined.
+    pub checked: usize,
+    pub findings: Vec<Finding>,
+}
+
+impl AuditReport {
+    /// True when every module passed.
+    pub fn is_clean(&self) -> bool {
+        self.findings.is_empty()
+    }
+
+    /// Ids of modules that failed, in audit order.
+    pub fn failed(&self) -> impl Iterator<Item = ModuleId> + '_ {
+        self.findings.iter().map(|f| f.module_id)
+    }
+}
+
+/// Audits one module's stored bytes.
+pub fn check(module_id: ModuleId, stored: &[u8], keyring: &Keyring) -> Result<(), Issue> {
+    let (module, payload) = if stored.starts_with(MANIFEST_MAGIC) {
+        let (manifest, module) = Manifest::parse(stored).map_err(|e| match e {
+            crate::Error::Engine(reason) => Issue::Corrupted(reason),
+            _ => Issue::Corrupted("manifest unreadable"),
+        })?;
+        if manifest.module_id != module_id {
+            return Err(Issue::Corrupted("manifest names another module"));
+        }
+        if manifest.module_len as usize != module.len() {
+            return Err(Issue::Corrupted("module length differs from manifest"));
+        }
+        check_signature(&manifest, module, keyring)?;
+        (module, manifest.payload())
+    } else if keyring.require_signature {
+        return Err(Issue::Unsigned);
+    } else {
+        (stored, PayloadKind::Wasm)
+    };
+
+    match (PayloadKind::detect(module), payload) {
+        (Some(found), declared) if found == declared => {}
+        (_, PayloadKind::Wasm) => return Err(Issue::Corrupted("not a wasm module")),
+        (_, PayloadKind::Aot) => return Err(Issue::Corrupted("not an AOT module")),
+    }
+    #[cfg(feature = "verify-blake3")]
+    if let Some((_, pin)) = keyring.digests.iter().find(|(id, _)| *id == module_id) {
+        if !crate::manifest::ct_eq(blake3::hash(module).as_bytes(), pin) {
+            return Err(Issue::DigestMismatch);
+        }
+    }
+    #[cfg(feature = "revocation")]
+    if let Some(list) = &keyring.revocations {
+        if list.is_module_revoked(module) {
+            return Err(Issue::Revoked);
+        }
+    }
+    Ok(())
+}
+
+fn check_signature(manifest: &Manifest<'_>, module: &[u8], keyring: &Keyring) -> Result<(), Issue> {
+    let required = keyring.require_signature || manifest.flags & FLAG_REQUIRE_SIGNATURE != 0;
+    if manifest.signature.is_none() {
+        return if required {
+            Err(Issue::Unsigned)
+        } else {
+            Ok(())
+        };
+    }
+    // Without a verifier signatures cannot be checked; only
+    // unsigned-tolerant policies pass.
+    let Some(verifier) = keyring.verifier() else {
+        return if required {
+            Err(Issue::Untrusted)
+        } else {
+            Ok(())
+        };
+    };
+    let algorithm = manifest
+        .signature_algorithm()
+        .map_err(|_| Issue::Corrupted("unknown signature algorithm"))?;
+    #[cfg(feature = "cert-chain")]
+    if crate::cert::has_chain(manifest) {
+        let anchored = crate::cert::verify_manifest(
+            verifier,
+            manifest,
+            module,
+            &keyring.roots,
+            &keyring.certificates,
+        )
+        .is_ok_and(|leaf| !keyring.is_revoked(&leaf.subject));
+        return if anchored || (!required && keyring.has_no_trust()) {
+            Ok(())
+        } else {
+            Err(Issue::Untrusted)
+        };
+    }
+    let trusted = match algorithm {
+        Algorithm::Ed25519 => keyring.keys.iter().any(|key| {
+            !keyring.is_revoked(&verify::PublicKey::Ed25519(*key))
+                && crate::manifest::verify_with(verifier, manifest, module, key).is_ok()
+        }),
+        Algorithm::P256 => keyring.p256_keys.iter().any(|key| {
+            !keyring.is_revoked(&verify::PublicKey::P256(*key))
+                && crate::manifest::verify_p256_with(verifier, manifest, module, key).is_ok()
+        }),
+    };
+    if trusted || (!required && keyring.has_no_trust()) {
+        Ok(())
+    } else {
+        Err(Issue::Untrusted)
+    }
+}
+
+impl Keyring {
+    /// No keys or roots to check signatures against.
+    fn has_no_trust(&self) -> bool {
+        #[cfg(feature = "cert-chain")]
+        if !self.roots.is_empty() {
+            return false;
+        }
+        self.keys.is_empty() && self.p256_keys.is_empty()
+    }
+
+    /// Whether `key` is on the revocation list.
+    fn is_revoked(&self, key: &verify::PublicKey) -> bool {
+        #[cfg(feature = "revocation")]
+        if let Some(list) = &self.revocations {
+            return list.is_key_revoked(key);
+        }
+        let _ = key;
+        false
+    }
+}
+
+impl<E: Engine, S: ModuleSource> Runtime<E, S> {
+    /// Trust anchors configured for this runtime (see `RuntimeBuilder::keyring`).
+    pub fn keyring(&self) -> &Keyring {
+        &self.keyring
+    }
+
+    /// Replaces the keyring; trust marks made under the old one no longer
+    /// hold (see `trust`).
+    pub fn set_keyring(&mut self, keyring: Keyring) {
+        self.keyring = keyring;
+        #[cfg(feature = "revocation")]
+        self.revocations.set_module_signers(self.keyring.signers());
+        self.refresh_trust_context();
+    }
+
+    /// Re-verifies every registered module (see `deps::Registry`) and reports
+    /// the ones that are missing, corrupted, untrusted or, with `revocation`,
+    /// revoked (by the runtime's list when `keyring` carries none). Nothing is
+    /// loaded or executed.
+    pub fn audit(&self, keyring: &Keyring) -> AuditReport {
+        self.audit_ids(self.registry.modules().iter().map(|m| m.module_id), keyring)
+    }
+
+    fn audit_ids(&self, ids: impl Iterator<Item = ModuleId>, keyring: &Keyring) -> AuditReport {
+        #[cfg(feature = "revocation")]
+        let with_list;
+        #[cfg(feature = "revocation")]
+        let keyring = match (&keyring.revocations, self.revocations.current()) {
+            (None, Some(list)) => {
+                with_list = keyring.clone().with_revocations(list.clone());
+                &with_list
+            }
+            _ => keyring,
+        };
+        let mut report = AuditReport::default();
+        for module_id in ids {
+            report.checked += 1;
+            let result = match self.source().fetch(module_id) {
+                Some(stored) => check(module_id, stored, keyring),
+                None => Err(Issue::Missing),
+            };
+            if let Err(issue) = result {
+                report.findings.push(Finding { module_id, issue });
+            }
+        }
+        report
+    }
+}
+
+impl<E: Engine, S: ModuleCatalog> Runtime<E, S> {
+    /// Like `audit`, but also covers stored modules the runtime was never
+    /// told about (registered ones first, then the rest in storage order).
+    pub fn audit_stored(&self, keyring: &Keyring) -> AuditReport {
+        let registry = &self.registry;
+        let unregistered = self
+            .source()
+            .iter_ids()
+            .filter(|id| registry.get(*id).is_none());
+        let registered = registry.modules().iter().map(|m| m.module_id);
+        self.audit_ids(registered.chain(unregistered), keyring)
+    }
+}
+
+#[cfg(all(test, feature = "std"))]
+mod tests {
+    use super::*;
+    use crate::manifest;
+    use crate::{MemoryStore, Result};
+
+    struct NoEngine;
+
+    impl Engine for NoEngine {
+        type ModuleHandle = ();
+        type Context = ();
+
+        fn load(&mut self, _id: ModuleId, _module: &[u8]) -> Result<()> {
+            Ok(())
+        }
+
+        fn invoke(&mut self, _handle: (), _entry: &str, _ctx: &mut ()) -> Result<()> {
+            Ok(())
+        }
+    }
+
+    const WASM: &[u8] = b"\0asm\x01\0\0\0";
+
+    fn audited(
+        modules: &[(ModuleId, Vec<u8>)],
+        known: &[ModuleId],
+    ) -> Runtime<NoEngine, MemoryStore> {
+        let mut store = MemoryStore::new();
+        for (id, bytes) in modules {
+            store.upsert(*id, bytes.clone());
+        }
+        let mut runtime = Runtime::new(NoEngine, store);
+        for &id in known {
+            runtime.set_module_version(id, 1);
+        }
+        runtime
+    }
+
+    #[test]
+    fn reports_missing_corrupted_and_unsigned_modules() {
+        let blob = manifest::encode(3, "main", WASM, 0, 1, None).unwrap();
+        let mut truncated = blob.clone();
+        truncated.pop();
+        let wrong_id = manifest::encode(9, "main", WASM, 0, 1, None).unwrap();
+        let runtime = audited(
+            &[
+                (1, WASM.to_vec()),
+                (2, b"garbage".to_vec()),
+                (3, blob),
+                (4, truncated),
+                (5, wrong_id),
+            ],
+            &[1, 2, 3, 4, 5, 6],
+        );
+
+        let report = runtime.audit(&Keyring::new());
+        assert_eq!(report.checked, 6);
+        assert_eq!(report.failed().collect::<Vec<_>>(), [2, 4, 5, 6]);
+        assert_eq!(report.findings[3].issue, Issue::Missing);
+        assert_eq!(
+            report.findings[0].issue,
+            Issue::Corrupted("not a wasm module")
+        );
+
+        let aot = b"\0aot\x03\0\0\0";
+        let aot_blob = manifest::encode(1, "main", aot, manifest::FLAG_AOT, 1, None).unwrap();
+        let undeclared = manifest::encode(2, "main", aot, 0, 1, None).unwrap();
+        let mislabelled = manifest::encode(3, "main", WASM, manifest::FLAG_AOT, 1, None).unwrap();
+        let runtime = audited(
+            &[(1, aot_blob), (2, undeclared), (3, mislabelled)],
+            &[1, 2, 3],
+        );
+        let report = runtime.audit(&Keyring::new());
+        assert_eq!(report.failed().collect::<Vec<_>>(), [2, 3]);
+        assert_eq!(
+            report.findings[1].issue,
+            Issue::Corrupted("not an AOT module")
+        );
+
+        let mut stored = audited(&[(1, WASM.to_vec()), (7, b"junk".to_vec())], &[2]);
+        let report = stored.audit_stored(&Keyring::new());
+        assert_eq!(report.checked, 3);
+        assert_eq!(report.failed().collect::<Vec<_>>(), [2, 7]);
+        stored.set_module_version(7, 1);
+        assert_eq!(stored.audit_stored(&Keyring::new()).checked, 3);
+
+        let report = runtime.audit(&Keyring::new().require_signatures());
+        assert!(report
+            .findings
+            .iter()
+            .any(|f| f.module_id == 1 && f.issue == Issue::Unsigned));
+        assert!(report
+            .findings
+            .iter()
+            .any(|f| f.module_id == 3 && f.issue == Issue::Unsigned));
+    }
+
+    #[cfg(feature = "verify-ed25519")]
+    #[test]
+    fn signatures_must_come_from_trusted_keys() {
+        use ed25519_dalek::{Signer, SigningKey};
+
+        let signing = SigningKey::from_bytes(&[5u8; 32]);
+        let preimage = manifest::signing_preimage(1, "main", WASM, 0, 1).unwrap();
+        let sig = signing.sign(&preimage).to_bytes();
+        let blob = manifest::encode(1, "main", WASM, 0, 1, Some(sig)).unwrap();
+        let runtime = audited(&[(1, blob)], &[1]);
+
+        let trusted = Keyring::new()
+            .with_key(signing.verifying_key().to_bytes())
+            .require_signatures();
+        assert!(runtime.audit(&trusted).is_clean());
+
+        let other = SigningKey::from_bytes(&[6u8; 32])
+            .verifying_key()
+            .to_bytes();
+        let report = runtime.audit(&Keyring::new().with_key(other));
+        assert_eq!(report.findings[0].issue, Issue::Untrusted);
+    }
+
+    #[cfg(feature = "verify-blake3")]
+    #[test]
+    fn pinned_digest_must_match() {
+        let runtime = audited(&[(1, WASM.to_vec())], &[1]);
+        let pin = *blake3::hash(WASM).as_bytes();
+        assert!(runtime
+            .audit(&Keyring::new().with_digest(1, pin))
+            .is_clean());
+        let report = runtime.audit(&Keyring::new().with_digest(1, [0; 32]));
+        assert_eq!(report.findings[0].issue, Issue::DigestMismatch);
+    }
+}
@@ -0,0 +1,344 @@
+//! Module updates over a BLE GATT service (`ble-ota` feature).
+//!
+//! The service is stack-agnostic: `BleOta` is the state machine behind three
+//! characteristics, and the firmware's GATT server (nrf-softdevice, TrouBLE,
+//! Embassy's HCI stacks) forwards writes to it and sends back the
+//! notifications it returns.
+//!
+//! | characteristic | UUID             | properties              | value |
+//! |----------------|------------------|-------------------------|-------|
+//! | control point  | `CONTROL_UUID`   | write, notify           | requests below; each answered by a notification `[RESPONSE, op, status, …]` |
+//! | data           | `DATA_UUID`      | write without response  | `seq u16, chunk` |
+//! | status         | `STATUS_UUID`    | notify                  | `[ACK, offset u32]` or `[NAK, offset u32, seq u16]` |
+//!
+//! A phone sends one `.smny` blob per transfer (little-endian):
+//! 1. `[OP_START, len u32]` on the control point.
+//! 2. Data packets, `seq` counting from 0 (wrapping). After every
+//!    `ack_interval` packets and after the last one the device notifies
+//!    `ACK` with the bytes received so far; the phone keeps at most that many
+//!    packets in flight. An out-of-order packet is dropped and answered with
+//!    `NAK`, the offset to resend from and the `seq` expected there; the
+//!    packets still in flight after it are dropped without another `NAK`.
+//! 3. `[OP_FINISH]`: the manifest is parsed and checked with the verification
+//!    hook, then the module is installed through the `ModuleSink`. Success is
+//!    answered with `module_id u32, sequence u32` after the status.
+//!
+//! `[OP_ABORT]` drops the transfer. Chunk size is up to the phone (ATT MTU
+//! minus 5); the link layer's CRC protects the packets and the verification
+//! hook (e.g. `manifest::verify_ed25519`) the image.
+
+use crate::manifest::Manifest;
+use crate::{write_module, ModuleId, ModuleSink, Result};
+use alloc::vec::Vec;
+
+/// OTA service.
+pub const SERVICE_UUID: u128 = 0x5e1f_0001_6c69_6d6d_7953_4f54_4100_0000;
+/// Control point characteristic.
+pub const CONTROL_UUID: u128 = 0x5e1f_0002_6c69_6d6d_7953_4f54_4100_0000;
+/// Data characteristic.
+pub const DATA_UUID: u128 = 0x5e1f_0003_6c69_6d6d_7953_4f54_4100_0000;
+/// Status characteristic.
+pub const STATUS_UUID: u128 = 0x5e1f_0004_6c69_6d6d_7953_4f54_4100_0000;
+
+pub const OP_START: u8 = 0x01;
+pub const OP_FINISH: u8 = 0x02;
+pub const OP_ABORT: u8 = 0x03;
+/// First byte of every control-point notification.
+pub const RESPONSE: u8 = 0x10;
+pub const ACK: u8 = 0x20;
+pub const NAK: u8 = 0x21;
+
+pub const STATUS_OK: u8 = 0;
+/// Malformed or unknown request.
+pub const STATUS_INVALID: u8 = 1;
+/// The announced length is above the limit, or more than the heap can
+/// buffer.
+pub const STATUS_TOO_LARGE: u8 = 2;
+/// `OP_FINISH` without a transfer, or before all bytes arrived.
+pub const STATUS_INCOMPLETE: u8 = 3;
+/// The image is not a valid manifest or failed verification.
+pub const STATUS_REJECTED: u8 = 4;
+/// The sink failed to store the module.
+pub const STATUS_STORAGE: u8 = 5;
+
+/// Packets between acknowledgments, for callers without a preference.
+pub const DEFAULT_ACK_INTERVAL: u16 = 8;
+
+/// Value to notify on a characteristic.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Notification {
+    buf: [u8; 11],
+    len: usize,
+}
+
+impl Notification {
+    fn new(head: &[u8], tail: &[u8]) -> Self {
+        let mut buf = [0u8; 11];
+        buf[..head.len()].copy_from_slice(head);
+        buf[head.len()..head.len() + tail.len()].copy_from_slice(tail);
+        Self {
+            buf,
+            len: head.len() + tail.len(),
+        }
+    }
+
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.buf[..self.len]
+    }
+}
+
+/// Module installed by the last successful transfer.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Installed {
+    pub module_id: ModuleId,
+    pub sequence: u32,
+}
+
+struct Transfer {
+    len: usize,
+    next_seq: u16,
+    unacked: u16,
+    /// A `NAK` was sent and no packet accepted since.
+    nacked: bool,
+}
+
+/// Receives `.smny` blobs over GATT writes and installs them into a
+/// `ModuleSink`.
+pub struct BleOta<K, V> {
+    sink: K,
+    verify: V,
+    max_len: usize,
+    ack_interval: u16,
+    transfer: Option<Transfer>,
+    image: Vec<u8>,
+    installed: Option<Installed>,
+}
+
+impl<K, V> BleOta<K, V>
+where
+    K: ModuleSink,
+    V: FnMut(&Manifest<'_>, &[u8]) -> Result<()>,
+{
+    /// Installs into `sink`, accepting blobs up to `max_len` bytes whose
+    /// manifest and module pass `verify`.
+    pub fn new(sink: K, max_len: usize, verify: V) -> Self {
+        Self {
+            sink,
+            verify,
+            max_len,
+            ack_interval: DEFAULT_ACK_INTERVAL,
+            transfer: None,
+            image: Vec::new(),
+            installed: None,
+        }
+    }
+
+    /// Acknowledges every `packets` data packets (builder style; at least 1).
+    pub fn with_ack_interval(mut self, packets: u16) -> Self {
+        self.ack_interval = packets.max(1);
+        self
+    }
+
+    /// Module installed by the last successful transfer.
+    pub fn installed(&self) -> Option<Installed> {
+        self.installed
+    }
+
+    /// Whether a transfer is in progress.
+    pub fn receiving(&self) -> bool {
+        self.transfer.is_some()
+    }
+
+    pub fn sink(&self) -> &K {
+        &self.sink
+    }
+
+    pub fn sink_mut(&mut self) -> &mut K {
+        &mut self.sink
+    }
+
+    /// Handles a control-point write; notify the result on the control point.
+    pub fn on_control_write(&mut self, value: &[u8]) -> Notification {
+        let (op, result) = match value {
+            [OP_START, len @ ..] => (OP_START, self.start(len)),
+            [OP_FINISH] => (OP_FINISH, self.finish()),
+            [OP_ABORT] => {
+                self.reset();
+                (OP_ABORT, Ok(()))
+            }
+            _ => (value.first().copied().unwrap_or(0), Err(STATUS_INVALID)),
+        };
+        let mut tail = [0u8; 8];
+        let tail = match (result, self.installed) {
+            (Err(status), _) => return Notification::new(&[RESPONSE, op, status], &[]),
+            (Ok(()), Some(installed)) if op == OP_FINISH => {
+                tail[..4].copy_from_slice(&installed.module_id.to_le_bytes());
+                tail[4..].copy_from_slice(&installed.sequence.to_le_bytes());
+                &tail[..]
+            }
+            (Ok(()), _) => &[],
+        };
+        Notification::new(&[RESPONSE, op, STATUS_OK], tail)
+    }
+
+    /// Handles a data write; notify the result, if any, on the status
+    /// characteristic.
+    pub fn on_data_write(&mut self, value: &[u8]) -> Option<Notification> {
+        let transfer = self.transfer.as_mut()?;
+        let [lo, hi, chunk @ ..] = value else {
+            return None;
+        };
+        let offset = self.image.len();
+        if u16::from_le_bytes([*lo, *hi]) != transfer.next_seq
+            || offset + chunk.len() > transfer.len
+        {
+            // Packets already in flight after the gap are dropped quietly.
+            if transfer.nacked {
+                return None;
+            }
+            transfer.nacked = true;
+            transfer.unacked = 0;
+            let mut tail = [0u8; 6];
+            tail[..4].copy_from_slice(&(offset as u32).to_le_bytes());
+            tail[4..].copy_from_slice(&transfer.next_seq.to_le_bytes());
+            return Some(Notification::new(&[NAK], &tail));
+        }
+        self.image.extend_from_slice(chunk);
+        transfer.next_seq = transfer.next_seq.wrapping_add(1);
+        transfer.nacked = false;
+        transfer.unacked += 1;
+        let done = self.image.len() == transfer.len;
+        if !done && transfer.unacked < self.ack_interval {
+            return None;
+        }
+        transfer.unacked = 0;
+        let received = self.image.len() as u32;
+        Some(Notification::new(&[ACK], &received.to_le_bytes()))
+    }
+
+    fn start(&mut self, len: &[u8]) -> core::result::Result<(), u8> {
+        self.reset();
+        let len = <[u8; 4]>::try_from(len).map_err(|_| STATUS_INVALID)?;
+        let len = u32::from_le_bytes(len) as usize;
+        if len == 0 || len > self.max_len {
+            return Err(STATUS_TOO_LARGE);
+        }
+        crate::fallible::reserve(&mut self.image, len).map_err(|_| STATUS_TOO_LARGE)?;
+        self.transfer = Some(Transfer {
+            len,
+            next_seq: 0,
+            unacked: 0,
+            nacked: false,
+        });
+        Ok(())
+    }
+
+    fn finish(&mut self) -> core::result::Result<(), u8> {
+        match &self.transfer {
+            Some(transfer) if transfer.len == self.image.len() => {}
+            _ => return Err(STATUS_INCOMPLETE),
+        }
+        let result = self.install();
+        self.reset();
+        self.installed = Some(result?);
+        Ok(())
+    }
+
+    fn install(&mut self) -> core::result::Result<Installed, u8> {
+        let (manifest, module) = Manifest::parse(&self.image).map_err(|_| STATUS_REJECTED)?;
+        if manifest.module_len as usize != module.len() {
+            return Err(STATUS_REJECTED);
+        }
+        (self.verify)(&manifest, module).map_err(|_| STATUS_REJECTED)?;
+        write_module(&mut self.sink, manifest.module_id, module).map_err(|_| STATUS_STORAGE)?;
+        Ok(Installed {
+            module_id: manifest.module_id,
+            sequence: manifest.sequence,
+        })
+    }
+
+    fn reset(&mut self) {
+        self.transfer = None;
+        self.image = Vec::new();
+    }
+}
+
+#[cfg(all(test, feature = "std"))]
+mod tests {
+    use super::*;
+    use crate::manifest::encode;
+    use crate::{Error, MemoryStore, ModuleSource};
+
+    const WASM: &[u8] = b"\0asm\x01\0\0\0";
+
+    fn packet(seq: u16, chunk: &[u8]) -> Vec<u8> {
+        [&seq.to_le_bytes()[..], chunk].concat()
+    }
+
+    #[test]
+    fn receives_acknowledges_and_installs() {
+        let blob = encode(7, "main", WASM, 0, 3, None).unwrap();
+        let mut ota = BleOta::new(MemoryStore::new(), 1024, |m: &Manifest<'_>, _: &[u8]| {
+            if m.sequence > 5 {
+                Err(Error::Engine("rollback"))
+            } else {
+                Ok(())
+            }
+        })
+        .with_ack_interval(2);
+
+        let len = (blob.len() as u32).to_le_bytes();
+        let start = [&[OP_START][..], &len].concat();
+        assert_eq!(
+            ota.on_control_write(&start).as_bytes(),
+            [RESPONSE, OP_START, STATUS_OK]
+        );
+        assert_eq!(
+            ota.on_control_write(&[OP_FINISH]).as_bytes(),
+            [RESPONSE, OP_FINISH, STATUS_INCOMPLETE]
+        );
+        assert!(ota.receiving());
+
+        let chunks: Vec<&[u8]> = blob.chunks(10).collect();
+        assert_eq!(ota.on_data_write(&packet(0, chunks[0])), None);
+        // Packet 1 is lost: 2 is refused with where to resume, 3 quietly.
+        let nak = ota.on_data_write(&packet(2, chunks[2])).unwrap();
+        assert_eq!(nak.as_bytes(), [NAK, 10, 0, 0, 0, 1, 0]);
+        assert_eq!(ota.on_data_write(&packet(3, chunks[3])), None);
+        assert_eq!(ota.on_data_write(&packet(1, chunks[1])), None);
+        let ack = ota.on_data_write(&packet(2, chunks[2])).unwrap();
+        assert_eq!(ack.as_bytes(), [ACK, 30, 0, 0, 0]);
+        let mut last = None;
+        for (seq, chunk) in chunks.iter().enumerate().skip(3) {
+            last = ota.on_data_write(&packet(seq as u16, chunk));
+        }
+        let total = (blob.len() as u32).to_le_bytes();
+        assert_eq!(last.unwrap().as_bytes(), [&[ACK][..], &total].concat());
+
+        let done = ota.on_control_write(&[OP_FINISH]);
+        assert_eq!(
+            done.as_bytes(),
+            [RESPONSE, OP_FINISH, STATUS_OK, 7, 0, 0, 0, 3, 0, 0, 0]
+        );
+        assert_eq!(ota.sink().fetch(7), Some(WASM));
+        assert!(!ota.receiving());
+
+        // Rejected by the hook, or too large for the device.
+        let newer = encode(7, "main", WASM, 0, 6, None).unwrap();
+        ota.on_control_write(&start);
+        ota.on_data_write(&packet(0, &newer));
+        assert_eq!(
+            ota.on_control_write(&[OP_FINISH]).as_bytes(),
+            [RESPONSE, OP_FINISH, STATUS_REJECTED]
+        );
+        let huge = [&[OP_START][..], &4096u32.to_le_bytes()].concat();
+        assert_eq!(
+            ota.on_control_write(&huge).as_bytes(),
+            [RESPONSE, OP_START, STATUS_TOO_LARGE]
+        );
+        assert_eq!(
+            ota.on_control_write(&[9]).as_bytes(),
+            [RESPONSE, 9, STATUS_INVALID]
+        );
+    }
+}
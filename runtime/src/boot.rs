@@ -0,0 +1,241 @@
+//! Boot descriptor: which modules run at startup, kept as data.
+//!
+//! The descriptor is a small text blob the firmware persists wherever it likes
+//! (a file, an NVS key, a flash slot) and can replace over the air, so what
+//! starts on boot is no longer compiled in. One module per line, `#` starts a
+//! comment:
+//!
+//! ```text
+//! # id  entry  on|off  [schedule]
+//! 2     init   on
+//! 7     start  on      @every 30s sample
+//! 9     -      off     0 2 * * * report
+//! ```
+//!
+//! `Runtime::boot` runs the start entry (`-` for none) of every enabled module
+//! in descriptor order; the optional schedule, in `schedule::Trigger` syntax,
+//! is installed with `BootDescriptor::schedule`. Disabled modules do neither.
+
+use crate::schedule::{Clock, Scheduler, Trigger};
+use crate::{Error, ModuleId, Result};
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::fmt::Write;
+
+/// One module's boot settings.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BootEntry {
+    pub module_id: ModuleId,
+    /// Entry run at boot; `None` = nothing to start.
+    pub entry: Option<String>,
+    pub enabled: bool,
+    /// Trigger text for the scheduler; without an entry name of its own it
+    /// calls `entry`.
+    pub schedule: Option<String>,
+}
+
+/// Modules to start and schedule at boot, in order.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct BootDescriptor {
+    entries: Vec<BootEntry>,
+}
+
+impl BootDescriptor {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Parses the text form; every schedule is checked.
+    pub fn parse(bytes: &[u8]) -> Result<Self> {
+        let text = core::str::from_utf8(bytes).map_err(|_| Error::Engine("boot: not utf-8"))?;
+        let mut descriptor = Self::new();
+        for line in text.lines() {
+            let line = line.split('#').next().unwrap_or("").trim();
+            if line.is_empty() {
+                continue;
+            }
+            let mut rest = line;
+            let mut field = || {
+                let end = rest.find(|c: char| c.is_ascii_whitespace());
+                let (field, tail) = rest.split_at(end.unwrap_or(rest.len()));
+                rest = tail.trim_start();
+                Some(field)
+                    .filter(|f| !f.is_empty())
+                    .ok_or(Error::Engine("boot: missing field"))
+            };
+            let module_id = field()?
+                .parse()
+                .map_err(|_| Error::Engine("boot: bad module id"))?;
+            let entry = match field()? {
+                "-" => None,
+                entry => Some(entry.into()),
+            };
+            let enabled = match field()? {
+                "on" => true,
+                "off" => false,
+                _ => return Err(Error::Engine("boot: expected on or off")),
+            };
+            let schedule = Some(rest).filter(|s| !s.is_empty());
+            descriptor.insert(BootEntry {
+                module_id,
+                entry,
+                enabled,
+                schedule: schedule.map(Into::into),
+            })?;
+        }
+        Ok(descriptor)
+    }
+
+    /// The text form `parse` reads.
+    pub fn encode(&self) -> String {
+        let mut out = String::new();
+        for e in &self.entries {
+            let entry = e.entry.as_deref().unwrap_or("-");
+            let enabled = if e.enabled { "on" } else { "off" };
+            // Writing to a `String` cannot fail.
+            let _ = write!(out, "{} {entry} {enabled}", e.module_id);
+            if let Some(schedule) = &e.schedule {
+                let _ = write!(out, " {schedule}");
+            }
+            out.push('\n');
+        }
+        out
+    }
+
+    /// Adds a module, or replaces its settings in place.
+    pub fn insert(&mut self, entry: BootEntry) -> Result<()> {
+        if let Some(schedule) = &entry.schedule {
+            let (_, name) = Trigger::parse(schedule)?;
+            if name.is_none() && entry.entry.is_none() {
+                return Err(Error::Engine("boot: schedule without an entry"));
+            }
+        }
+        match self
+            .entries
+            .iter_mut()
+            .find(|e| e.module_id == entry.module_id)
+        {
+            Some(existing) => *existing = entry,
+            None => self.entries.push(entry),
+        }
+        Ok(())
+    }
+
+    /// Drops a module; returns whether it was listed.
+    pub fn remove(&mut self, module_id: ModuleId) -> bool {
+        let before = self.entries.len();
+        self.entries.retain(|e| e.module_id != module_id);
+        self.entries.len() != before
+    }
+
+    /// Enables or disables a module; returns whether it was listed.
+    pub fn set_enabled(&mut self, module_id: ModuleId, enabled: bool) -> bool {
+        let entry = self.entries.iter_mut().find(|e| e.module_id == module_id);
+        entry.map(|e| e.enabled = enabled).is_some()
+    }
+
+    pub fn get(&self, module_id: ModuleId) -> Option<&BootEntry> {
+        self.entries.iter().find(|e| e.module_id == module_id)
+    }
+
+    /// Listed modules, in boot order.
+    pub fn entries(&self) -> &[BootEntry] {
+        &self.entries
+    }
+
+    /// Replaces the scheduler's jobs for every listed module with the
+    /// descriptor's schedule (none when disabled); returns the jobs added.
+    pub fn schedule<C: Clock>(&self, scheduler: &mut Scheduler<C>) -> Result<usize> {
+        let mut added = 0;
+        for e in &self.entries {
+            scheduler.remove(e.module_id);
+            if let (true, Some(spec)) = (e.enabled, &e.schedule) {
+                scheduler.add_spec(e.module_id, e.entry.as_deref().unwrap_or(""), spec)?;
+                added += 1;
+            }
+        }
+        Ok(added)
+    }
+}
+
+/// Outcome of `Runtime::boot`.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct BootReport {
+    /// Modules whose start entry ran, in order.
+    pub started: Vec<ModuleId>,
+    /// Modules whose start entry failed; booting carried on.
+    pub failed: Vec<(ModuleId, Error)>,
+}
+
+#[cfg(all(test, feature = "std"))]
+mod tests {
+    use super::*;
+    use crate::{Engine, MemoryStore, Runtime};
+
+    const DESCRIPTOR: &[u8] = b"# boot order\n\
+        2 init on\n\
+        7   start  on   @every 30s sample\n\
+        9 - off 0 2 * * * report\n\
+        4 init on # not installed\n";
+
+    struct Calls;
+
+    impl Engine for Calls {
+        type ModuleHandle = ModuleId;
+        type Context = Vec<(ModuleId, String)>;
+
+        fn load(&mut self, id: ModuleId, _module: &[u8]) -> Result<ModuleId> {
+            Ok(id)
+        }
+
+        fn invoke(&mut self, handle: ModuleId, entry: &str, ctx: &mut Self::Context) -> Result<()> {
+            ctx.push((handle, entry.into()));
+            Ok(())
+        }
+    }
+
+    struct Epoch;
+
+    impl Clock for Epoch {
+        fn now_ms(&self) -> u64 {
+            0
+        }
+    }
+
+    #[test]
+    fn boots_and_schedules_enabled_modules() {
+        let mut descriptor = BootDescriptor::parse(DESCRIPTOR).unwrap();
+        assert_eq!(
+            BootDescriptor::parse(descriptor.encode().as_bytes()),
+            Ok(descriptor.clone())
+        );
+        assert!(BootDescriptor::parse(b"3 - on @every 5s").is_err());
+        assert!(BootDescriptor::parse(b"3 main maybe").is_err());
+
+        let mut store = MemoryStore::new();
+        for id in [2, 7, 9] {
+            store.upsert(id, vec![0]);
+        }
+        let mut runtime = Runtime::new(Calls, store);
+        let mut calls = Vec::new();
+        let report = runtime.boot(&descriptor, &mut calls);
+        assert_eq!(report.started, [2, 7]);
+        assert_eq!(report.failed, [(4, Error::ModuleNotFound)]);
+        assert_eq!(calls, [(2, "init".into()), (7, "start".into())]);
+
+        let mut scheduler = Scheduler::new(Epoch);
+        scheduler.add(2, "stale", Trigger::Every(1000));
+        assert_eq!(descriptor.schedule(&mut scheduler).unwrap(), 1);
+        let jobs: Vec<_> = scheduler
+            .jobs()
+            .iter()
+            .map(|j| (j.module_id, j.entry.as_str()))
+            .collect();
+        assert_eq!(jobs, [(7, "sample")]);
+
+        assert!(descriptor.set_enabled(9, true));
+        assert!(descriptor.remove(4));
+        descriptor.schedule(&mut scheduler).unwrap();
+        assert_eq!(scheduler.jobs().len(), 2);
+    }
+}
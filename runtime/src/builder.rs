@@ -0,0 +1,370 @@
+//! `RuntimeBuilder`: the knobs a `Runtime` is assembled from, in one place.
+//!
+//! ```ignore
+//! let mut runtime = RuntimeBuilder::new(store)
+//!     .stack_size(8 * 1024)
+//!     .max_memory_pages(1)
+//!     .imports(Imports::new().with(hal))
+//!     .state_gate(gate)
+//!     .build::<Wasm3Engine>()?;
+//! ```
+//!
+//! Defaults suit small targets: a 4 KiB wasm stack, no memory cap beyond what
+//! modules declare, and handles for up to `DEFAULT_CACHED_MODULES` modules
+//! kept between calls.
+
+use crate::abi::Imports;
+use crate::quota::{Quota, Quotas};
+use crate::schedule::Clock;
+use crate::telemetry::{EventSink, Telemetry};
+use crate::trace::TraceContext;
+use crate::{gate, CachedEngine, Engine, Error, ModuleId, ModuleSource, Result, Runtime};
+use alloc::vec::Vec;
+
+/// Bytes in one wasm memory page.
+pub const WASM_PAGE_SIZE: usize = 64 * 1024;
+
+/// Default wasm stack size in bytes.
+pub const DEFAULT_STACK_SIZE: u32 = 4 * 1024;
+
+/// Module handles `InstanceMode::default()` keeps cached.
+pub const DEFAULT_CACHED_MODULES: usize = 4;
+
+/// Engine-level limits.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct EngineConfig {
+    /// Wasm stack size in bytes.
+    pub stack_size: u32,
+    /// Upper bound on a module's linear memory in 64 KiB pages; `None` leaves
+    /// it to the module.
+    pub max_memory_pages: Option<u32>,
+    /// Count fuel per invocation (`Engine::last_fuel`) for fuel quotas.
+    pub meter_fuel: bool,
+    /// Start each call from the state the module's previous call left
+    /// instead of a fresh instance; enables `Engine::snapshot`/`restore`.
+    pub keep_state: bool,
+}
+
+impl Default for EngineConfig {
+    fn default() -> Self {
+        Self {
+            stack_size: DEFAULT_STACK_SIZE,
+            max_memory_pages: None,
+            meter_fuel: false,
+            keep_state: false,
+        }
+    }
+}
+
+/// Engines `RuntimeBuilder::build` can construct.
+///
+/// Limits an engine cannot enforce are an error rather than silently ignored.
+pub trait ConfigurableEngine: Engine + Sized {
+    fn from_config(config: &EngineConfig, imports: Imports) -> Result<Self>;
+}
+
+/// How loaded modules are kept between calls.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum InstanceMode {
+    /// Load the module on every call; nothing stays resident.
+    Reload,
+    /// Keep handles for up to `max_modules` modules; others reload.
+    Cached { max_modules: usize },
+}
+
+impl Default for InstanceMode {
+    fn default() -> Self {
+        InstanceMode::Cached {
+            max_modules: DEFAULT_CACHED_MODULES,
+        }
+    }
+}
+
+/// Builds a `Runtime` over a `CachedEngine`.
+pub struct RuntimeBuilder<S> {
+    source: S,
+    engine: EngineConfig,
+    imports: Imports,
+    instances: InstanceMode,
+    states: gate::StatePolicy,
+    trace: Option<TraceContext>,
+    versions: Vec<(ModuleId, u32)>,
+    quotas: Quotas,
+    telemetry: Option<Telemetry>,
+    #[cfg(feature = "unstable")]
+    keyring: crate::audit::Keyring,
+    #[cfg(feature = "unstable")]
+    verification: crate::trust::VerificationPolicy,
+}
+
+impl<S: ModuleSource> RuntimeBuilder<S> {
+    /// Starts from the defaults, loading modules from `source`.
+    pub fn new(source: S) -> Self {
+        Self {
+            source,
+            engine: EngineConfig::default(),
+            imports: Imports::new(),
+            instances: InstanceMode::default(),
+            states: gate::StatePolicy::new(),
+            trace: None,
+            versions: Vec::new(),
+            quotas: Quotas::new(),
+            telemetry: None,
+            #[cfg(feature = "unstable")]
+            keyring: crate::audit::Keyring::new(),
+            #[cfg(feature = "unstable")]
+            verification: crate::trust::VerificationPolicy::Never,
+        }
+    }
+
+    /// Wasm stack size in bytes.
+    pub fn stack_size(mut self, bytes: u32) -> Self {
+        self.engine.stack_size = bytes;
+        self
+    }
+
+    /// Caps each module's linear memory at `pages` 64 KiB pages.
+    pub fn max_memory_pages(mut self, pages: u32) -> Self {
+        self.engine.max_memory_pages = Some(pages);
+        self
+    }
+
+    /// Replaces all engine limits at once.
+    pub fn engine_config(mut self, config: EngineConfig) -> Self {
+        self.engine = config;
+        self
+    }
+
+    /// Host functions linked into every module.
+    pub fn imports(mut self, imports: Imports) -> Self {
+        self.imports = imports;
+        self
+    }
+
+    pub fn instance_mode(mut self, mode: InstanceMode) -> Self {
+        self.instances = mode;
+        self
+    }
+
+    /// Gate reporting the current device state (see `Runtime::set_state_gate`).
+    pub fn state_gate(mut self, gate: impl gate::StateGate + 'static) -> Self {
+        self.states.set_gate(gate);
+        self
+    }
+
+    /// Restricts a module to the device states in `mask`.
+    pub fn restrict_states(mut self, module_id: ModuleId, mask: u32) -> Self {
+        self.states.restrict(module_id, mask);
+        self
+    }
+
+    /// Replaces the state policy (gate and restrictions) wholesale.
+    pub fn state_policy(mut self, policy: gate::StatePolicy) -> Self {
+        self.states = policy;
+        self
+    }
+
+    /// Enables tracing with `trace` as the correlation-id context.
+    pub fn trace(mut self, trace: TraceContext) -> Self {
+        self.trace = Some(trace);
+        self
+    }
+
+    /// Records a module version up front (see `Runtime::set_module_version`).
+    pub fn module_version(mut self, module_id: ModuleId, version: u32) -> Self {
+        self.versions.push((module_id, version));
+        self
+    }
+
+    /// Limits a module's invocation rate and fuel; fuel limits turn on
+    /// `EngineConfig::meter_fuel`.
+    pub fn quota(mut self, module_id: ModuleId, quota: Quota) -> Self {
+        self.engine.meter_fuel |= quota.max_fuel.is_some();
+        self.quotas.set(module_id, quota);
+        self
+    }
+
+    /// Clock quota windows are measured with.
+    pub fn quota_clock(mut self, clock: impl Clock + 'static) -> Self {
+        self.quotas.set_clock(clock);
+        self
+    }
+
+    /// Sink for OTA and execution events (see `Runtime::set_event_sink`).
+    pub fn event_sink(
+        mut self,
+        sink: impl EventSink + 'static,
+        clock: impl Clock + 'static,
+    ) -> Self {
+        self.telemetry = Some(Telemetry::new(sink, clock));
+        self
+    }
+
+    /// Trust anchors kept on the runtime for audits (`Runtime::keyring`).
+    #[cfg(feature = "unstable")]
+    pub fn keyring(mut self, keyring: crate::audit::Keyring) -> Self {
+        self.keyring = keyring;
+        self
+    }
+
+    /// When invocations verify modules against the keyring (see `trust`).
+    #[cfg(feature = "unstable")]
+    pub fn verification_policy(mut self, policy: crate::trust::VerificationPolicy) -> Self {
+        self.verification = policy;
+        self
+    }
+
+    /// Engine limits collected so far.
+    pub fn config(&self) -> &EngineConfig {
+        &self.engine
+    }
+
+    /// Constructs the engine from the collected limits and imports; fails
+    /// with `Error::Unsupported` when the engine cannot link imports, meter
+    /// fuel or keep state and those were asked for.
+    pub fn build<E>(mut self) -> Result<Runtime<CachedEngine<E>, S>>
+    where
+        E: ConfigurableEngine,
+        E::ModuleHandle: PartialEq,
+    {
+        let imports = core::mem::take(&mut self.imports);
+        let wants_imports = !imports.is_empty();
+        let engine = E::from_config(&self.engine, imports)?;
+        let caps = engine.capabilities();
+        if (wants_imports && !caps.imports)
+            || (self.engine.meter_fuel && !caps.fuel)
+            || (self.engine.keep_state && !caps.snapshots)
+        {
+            return Err(Error::Unsupported);
+        }
+        Ok(self.build_with(engine))
+    }
+
+    /// Uses an engine constructed by the caller; the engine limits and
+    /// imports set here are not applied to it.
+    pub fn build_with<E>(self, engine: E) -> Runtime<CachedEngine<E>, S>
+    where
+        E: Engine,
+        E::ModuleHandle: PartialEq,
+    {
+        let max_modules = match self.instances {
+            InstanceMode::Reload => 0,
+            InstanceMode::Cached { max_modules } => max_modules,
+        };
+        let mut runtime = Runtime::new(
+            CachedEngine::with_capacity_limit(engine, max_modules),
+            self.source,
+        );
+        runtime.states = self.states;
+        runtime.trace = self.trace;
+        runtime.quotas = self.quotas;
+        runtime.telemetry = self.telemetry;
+        for (module_id, version) in self.versions {
+            runtime.set_module_version(module_id, version);
+        }
+        #[cfg(feature = "unstable")]
+        runtime.set_keyring(self.keyring);
+        #[cfg(feature = "unstable")]
+        runtime.set_verification_policy(self.verification);
+        runtime
+    }
+}
+
+#[cfg(all(test, feature = "std"))]
+mod tests {
+    use super::*;
+    use crate::gate::{state_mask, DeviceState, StateGate};
+    use crate::{Error, MemoryStore};
+    use std::cell::Cell;
+    use std::rc::Rc;
+
+    #[derive(Default)]
+    struct Loads(Rc<Cell<u32>>);
+
+    impl Engine for Loads {
+        type ModuleHandle = ModuleId;
+        type Context = ();
+
+        fn load(&mut self, id: ModuleId, _module: &[u8]) -> Result<ModuleId> {
+            self.0.set(self.0.get() + 1);
+            Ok(id)
+        }
+
+        fn invoke(&mut self, _handle: ModuleId, _entry: &str, _ctx: &mut ()) -> Result<()> {
+            Ok(())
+        }
+    }
+
+    impl ConfigurableEngine for Loads {
+        fn from_config(config: &EngineConfig, _imports: Imports) -> Result<Self> {
+            match config.max_memory_pages {
+                Some(_) => Err(Error::Unsupported),
+                None => Ok(Self::default()),
+            }
+        }
+    }
+
+    struct Fixed(DeviceState);
+
+    impl StateGate for Fixed {
+        fn current_state(&self) -> DeviceState {
+            self.0
+        }
+    }
+
+    fn store() -> MemoryStore {
+        let mut store = MemoryStore::new();
+        store.upsert(1, b"\0asm".to_vec());
+        store.upsert(2, b"\0asm".to_vec());
+        store
+    }
+
+    #[test]
+    fn applies_settings_and_instance_mode() {
+        assert_eq!(
+            RuntimeBuilder::new(store())
+                .max_memory_pages(1)
+                .build::<Loads>()
+                .err(),
+            Some(Error::Unsupported)
+        );
+
+        let mut runtime = RuntimeBuilder::new(store())
+            .state_gate(Fixed(0))
+            .restrict_states(2, state_mask(&[1]))
+            .module_version(1, 9)
+            .build::<Loads>()
+            .unwrap();
+        runtime.execute(1, "main", &mut ()).unwrap();
+        runtime.execute(1, "main", &mut ()).unwrap();
+        assert_eq!(runtime.engine().cached_len(), 1);
+        assert_eq!(runtime.execute(2, "main", &mut ()), Err(Error::StateDenied));
+        assert_eq!(runtime.module_version(1), 9);
+
+        let loads = Rc::new(Cell::new(0));
+        let mut runtime = RuntimeBuilder::new(store())
+            .instance_mode(InstanceMode::Reload)
+            .build_with(Loads(loads.clone()));
+        runtime.execute(1, "main", &mut ()).unwrap();
+        runtime.execute(1, "main", &mut ()).unwrap();
+        assert_eq!((loads.get(), runtime.engine().cached_len()), (2, 0));
+    }
+
+    #[test]
+    fn rejects_imports_and_fuel_the_engine_lacks() {
+        let kv = crate::kv::KvImports::new(crate::kv::MemoryKv::new());
+        let with_imports = RuntimeBuilder::new(store()).imports(Imports::new().with(kv));
+        assert_eq!(
+            with_imports.build::<Loads>().err(),
+            Some(Error::Unsupported)
+        );
+        let metered = RuntimeBuilder::new(store()).engine_config(EngineConfig {
+            meter_fuel: true,
+            ..EngineConfig::default()
+        });
+        assert_eq!(metered.build::<Loads>().err(), Some(Error::Unsupported));
+
+        let runtime = RuntimeBuilder::new(store()).build::<Loads>().unwrap();
+        assert_eq!(runtime.capabilities(), crate::EngineCaps::NONE);
+    }
+}
@@ -0,0 +1,620 @@
+//! Inter-module publish/subscribe bus.
+//!
+//! Modules post byte messages to named topics and every other module subscribed
+//! to that topic finds them queued on its next invocation, so pipelines such as
+//! sensor → filter → uplink need no host glue per hop. `Bus` is a cheap handle:
+//! register a clone as a `HostImports` set and keep another to publish, subscribe
+//! or drain from firmware.
+//!
+//! Guest imports (module `env`, all `i32`):
+//! - `bus_publish(topic_ptr, topic_len, data_ptr, data_len) -> delivered | status`
+//! - `bus_subscribe(topic_ptr, topic_len) -> status`
+//! - `bus_recv(ptr, cap) -> len | status` – writes `topic_len: u8, topic, data`;
+//!   `E_EMPTY` when nothing is queued, `E_INVALID` (message kept) when `cap` is too small.
+//!
+//! Queues are bounded per subscriber; when full the oldest message is dropped
+//! and counted in `Bus::dropped`. `Bus::in_arena` claims every queue from an
+//! `arena::Arena` up front, for a fixed number of subscribers with up to
+//! `MAX_ARENA_TOPICS` topics each.
+
+use crate::abi::{guest_bytes, GuestMemory, HostFn, HostImports, E_EMPTY, E_INVALID, OK};
+use crate::arena::Arena;
+use crate::trace::{CorrelationId, TraceContext, NO_CORRELATION};
+use crate::{Error, ModuleId, Result};
+use alloc::collections::VecDeque;
+use alloc::rc::Rc;
+use alloc::vec::Vec;
+use core::cell::RefCell;
+
+/// Longest topic name in bytes.
+pub const MAX_TOPIC_LEN: usize = 32;
+/// Largest message payload in bytes.
+pub const MAX_MESSAGE_LEN: usize = 256;
+/// Default queue depth per subscriber.
+pub const DEFAULT_QUEUE_DEPTH: usize = 16;
+/// Most topics one module subscribes to on a bus made with `Bus::in_arena`.
+pub const MAX_ARENA_TOPICS: usize = 4;
+
+/// Host functions provided by `Bus`.
+pub const FUNCTIONS: &[HostFn] = &[
+    HostFn::new("bus_publish", 4),
+    HostFn::new("bus_subscribe", 2),
+    HostFn::new("bus_recv", 2),
+];
+
+/// A queued message.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Message {
+    /// Publishing module (`None` when posted by firmware).
+    pub from: Option<ModuleId>,
+    /// Trace id current when the message was published (see `Bus::set_trace`).
+    pub correlation: CorrelationId,
+    pub topic: Vec<u8>,
+    pub data: Vec<u8>,
+}
+
+struct Subscriber {
+    module_id: ModuleId,
+    topics: Vec<Vec<u8>>,
+    queue: VecDeque<Message>,
+}
+
+/// Subscriber of a bus made with `Bus::in_arena`; its queue is the `depth`
+/// records at its own index in `Mailboxes::records`.
+#[derive(Clone, Copy)]
+struct Mailbox {
+    module_id: ModuleId,
+    /// Length and bytes of each topic; unused ones are empty.
+    topics: [(u8, [u8; MAX_TOPIC_LEN]); MAX_ARENA_TOPICS],
+    /// Oldest queued record and number queued.
+    head: usize,
+    len: usize,
+}
+
+/// A queued message on a bus made with `Bus::in_arena`.
+#[derive(Clone, Copy)]
+struct Record {
+    from: Option<ModuleId>,
+    correlation: CorrelationId,
+    topic_len: u8,
+    topic: [u8; MAX_TOPIC_LEN],
+    data_len: u16,
+    data: [u8; MAX_MESSAGE_LEN],
+}
+
+impl Record {
+    fn topic(&self) -> &[u8] {
+        &self.topic[..self.topic_len as usize]
+    }
+
+    fn data(&self) -> &[u8] {
+        &self.data[..self.data_len as usize]
+    }
+}
+
+struct Mailboxes {
+    boxes: &'static mut [Option<Mailbox>],
+    records: &'static mut [Record],
+}
+
+/// Subscribers and their queues, on the heap or in an arena.
+enum Queues {
+    Heap(Vec<Subscriber>),
+    Arena(Mailboxes),
+}
+
+impl Queues {
+    fn subscribe(&mut self, module_id: ModuleId, topic: &[u8]) -> Result<()> {
+        match self {
+            Self::Heap(subscribers) => {
+                let pos = subscribers
+                    .iter()
+                    .position(|sub| sub.module_id == module_id);
+                let sub = match pos {
+                    Some(pos) => &mut subscribers[pos],
+                    None => {
+                        subscribers.push(Subscriber {
+                            module_id,
+                            topics: Vec::new(),
+                            queue: VecDeque::new(),
+                        });
+                        subscribers.last_mut().unwrap()
+                    }
+                };
+                if !sub.topics.iter().any(|t| t == topic) {
+                    sub.topics.push(topic.to_vec());
+                }
+                Ok(())
+            }
+            Self::Arena(mailboxes) => {
+                let boxes = &mut *mailboxes.boxes;
+                let at = match boxes.iter().position(|b| is_for(b, module_id)) {
+                    Some(at) => at,
+                    None => {
+                        let at = boxes
+                            .iter()
+                            .position(Option::is_none)
+                            .ok_or(Error::Engine("bus: too many subscribers"))?;
+                        boxes[at] = Some(Mailbox {
+                            module_id,
+                            topics: [(0, [0; MAX_TOPIC_LEN]); MAX_ARENA_TOPICS],
+                            head: 0,
+                            len: 0,
+                        });
+                        at
+                    }
+                };
+                let mailbox = boxes[at].as_mut().unwrap();
+                if mailbox.topics.iter().any(|t| t.1[..t.0 as usize] == *topic) {
+                    return Ok(());
+                }
+                let free = mailbox
+                    .topics
+                    .iter_mut()
+                    .find(|t| t.0 == 0)
+                    .ok_or(Error::Engine("bus: too many topics"))?;
+                free.0 = topic.len() as u8;
+                free.1[..topic.len()].copy_from_slice(topic);
+                Ok(())
+            }
+        }
+    }
+
+    fn unsubscribe_all(&mut self, module_id: ModuleId) {
+        match self {
+            Self::Heap(subscribers) => subscribers.retain(|sub| sub.module_id != module_id),
+            Self::Arena(mailboxes) => {
+                for mailbox in mailboxes.boxes.iter_mut() {
+                    if is_for(mailbox, module_id) {
+                        *mailbox = None;
+                    }
+                }
+            }
+        }
+    }
+
+    /// Queues a message for every subscriber of `topic` except `from`;
+    /// returns how many received it and how many older messages were dropped.
+    fn deliver(
+        &mut self,
+        depth: usize,
+        from: Option<ModuleId>,
+        correlation: CorrelationId,
+        topic: &[u8],
+        data: &[u8],
+    ) -> (usize, u32) {
+        let mut delivered = 0;
+        let mut dropped = 0;
+        match self {
+            Self::Heap(subscribers) => {
+                for sub in subscribers {
+                    if Some(sub.module_id) == from || !sub.topics.iter().any(|t| t == topic) {
+                        continue;
+                    }
+                    if sub.queue.len() >= depth {
+                        sub.queue.pop_front();
+                        dropped += 1;
+                    }
+                    sub.queue.push_back(Message {
+                        from,
+                        correlation,
+                        topic: topic.to_vec(),
+                        data: data.to_vec(),
+                    });
+                    delivered += 1;
+                }
+            }
+            Self::Arena(mailboxes) => {
+                for (at, mailbox) in mailboxes.boxes.iter_mut().enumerate() {
+                    let Some(mailbox) = mailbox else {
+                        continue;
+                    };
+                    let subscribed = mailbox.topics.iter().any(|t| t.1[..t.0 as usize] == *topic);
+                    if Some(mailbox.module_id) == from || !subscribed {
+                        continue;
+                    }
+                    if mailbox.len >= depth {
+                        mailbox.head = (mailbox.head + 1) % depth;
+                        mailbox.len -= 1;
+                        dropped += 1;
+                    }
+                    let record =
+                        &mut mailboxes.records[at * depth + (mailbox.head + mailbox.len) % depth];
+                    record.from = from;
+                    record.correlation = correlation;
+                    record.topic_len = topic.len() as u8;
+                    record.topic[..topic.len()].copy_from_slice(topic);
+                    record.data_len = data.len() as u16;
+                    record.data[..data.len()].copy_from_slice(data);
+                    mailbox.len += 1;
+                    delivered += 1;
+                }
+            }
+        }
+        (delivered, dropped)
+    }
+
+    /// Topic and data of the oldest message queued for `module_id`.
+    fn front(&self, depth: usize, module_id: ModuleId) -> Option<(&[u8], &[u8])> {
+        match self {
+            Self::Heap(subscribers) => {
+                let sub = subscribers.iter().find(|sub| sub.module_id == module_id)?;
+                let message = sub.queue.front()?;
+                Some((&message.topic, &message.data))
+            }
+            Self::Arena(mailboxes) => {
+                let record = mailboxes.front(depth, module_id)?;
+                Some((record.topic(), record.data()))
+            }
+        }
+    }
+
+    /// Takes the oldest message queued for `module_id`.
+    fn pop(&mut self, depth: usize, module_id: ModuleId) -> Option<Message> {
+        match self {
+            Self::Heap(subscribers) => subscribers
+                .iter_mut()
+                .find(|sub| sub.module_id == module_id)?
+                .queue
+                .pop_front(),
+            Self::Arena(mailboxes) => {
+                let record = *mailboxes.front(depth, module_id)?;
+                let mailbox = mailboxes
+                    .boxes
+                    .iter_mut()
+                    .flatten()
+                    .find(|b| b.module_id == module_id)?;
+                mailbox.head = (mailbox.head + 1) % depth;
+                mailbox.len -= 1;
+                Some(Message {
+                    from: record.from,
+                    correlation: record.correlation,
+                    topic: record.topic().to_vec(),
+                    data: record.data().to_vec(),
+                })
+            }
+        }
+    }
+
+    fn pending(&self, module_id: ModuleId) -> usize {
+        match self {
+            Self::Heap(subscribers) => subscribers
+                .iter()
+                .find(|sub| sub.module_id == module_id)
+                .map_or(0, |sub| sub.queue.len()),
+            Self::Arena(mailboxes) => mailboxes
+                .boxes
+                .iter()
+                .flatten()
+                .find(|b| b.module_id == module_id)
+                .map_or(0, |b| b.len),
+        }
+    }
+}
+
+impl Mailboxes {
+    fn front(&self, depth: usize, module_id: ModuleId) -> Option<&Record> {
+        let at = self.boxes.iter().position(|b| is_for(b, module_id))?;
+        let mailbox = self.boxes[at].as_ref()?;
+        (mailbox.len > 0).then(|| &self.records[at * depth + mailbox.head])
+    }
+}
+
+fn is_for(mailbox: &Option<Mailbox>, module_id: ModuleId) -> bool {
+    mailbox.is_some_and(|b| b.module_id == module_id)
+}
+
+struct State {
+    queues: Queues,
+    depth: usize,
+    dropped: u32,
+    /// Modules entered and not yet exited, innermost last.
+    active: Vec<ModuleId>,
+    trace: Option<TraceContext>,
+}
+
+/// Shared handle to the message bus.
+#[derive(Clone)]
+pub struct Bus {
+    state: Rc<RefCell<State>>,
+}
+
+impl Default for Bus {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Bus {
+    /// Creates a bus with `DEFAULT_QUEUE_DEPTH` messages per subscriber.
+    pub fn new() -> Self {
+        Self::with_depth(DEFAULT_QUEUE_DEPTH)
+    }
+
+    /// Creates a bus with a custom per-subscriber queue depth (at least 1).
+    pub fn with_depth(depth: usize) -> Self {
+        Self {
+            state: Rc::new(RefCell::new(State {
+                queues: Queues::Heap(Vec::new()),
+                depth: depth.max(1),
+                dropped: 0,
+                active: Vec::new(),
+                trace: None,
+            })),
+        }
+    }
+
+    /// Creates a bus for up to `max_subscribers` modules whose queues of
+    /// `depth` messages (at least 1) are claimed from `arena` now. Subscribing
+    /// another module, or a module to more than `MAX_ARENA_TOPICS` topics,
+    /// fails.
+    pub fn in_arena<const N: usize>(
+        arena: &'static Arena<N>,
+        max_subscribers: usize,
+        depth: usize,
+    ) -> Result<Self> {
+        let depth = depth.max(1);
+        let empty = Record {
+            from: None,
+            correlation: NO_CORRELATION,
+            topic_len: 0,
+            topic: [0; MAX_TOPIC_LEN],
+            data_len: 0,
+            data: [0; MAX_MESSAGE_LEN],
+        };
+        let records = max_subscribers
+            .checked_mul(depth)
+            .ok_or(Error::OutOfMemory)?;
+        let bus = Self::with_depth(depth);
+        bus.state.borrow_mut().queues = Queues::Arena(Mailboxes {
+            boxes: arena.claim(max_subscribers, None)?,
+            records: arena.claim(records, empty)?,
+        });
+        Ok(bus)
+    }
+
+    /// Stamps published messages with the current correlation id from `trace`.
+    pub fn set_trace(&self, trace: TraceContext) {
+        self.state.borrow_mut().trace = Some(trace);
+    }
+
+    /// Subscribes `module_id` to `topic`.
+    pub fn subscribe(&self, module_id: ModuleId, topic: &[u8]) -> Result<()> {
+        check_topic(topic)?;
+        self.state.borrow_mut().queues.subscribe(module_id, topic)
+    }
+
+    /// Removes a module's subscriptions and queued messages.
+    pub fn unsubscribe_all(&self, module_id: ModuleId) {
+        self.state.borrow_mut().queues.unsubscribe_all(module_id);
+    }
+
+    /// Queues `data` for every subscriber of `topic` except the publisher.
+    ///
+    /// Returns how many subscribers received it.
+    pub fn publish(&self, from: Option<ModuleId>, topic: &[u8], data: &[u8]) -> Result<usize> {
+        check_topic(topic)?;
+        if data.len() > MAX_MESSAGE_LEN {
+            return Err(Error::Engine("bus: message too long"));
+        }
+        let mut state = self.state.borrow_mut();
+        let depth = state.depth;
+        let correlation = state
+            .trace
+            .as_ref()
+            .map_or(NO_CORRELATION, |trace| trace.current());
+        let (delivered, dropped) = state.queues.deliver(depth, from, correlation, topic, data);
+        state.dropped = state.dropped.saturating_add(dropped);
+        Ok(delivered)
+    }
+
+    /// Takes the oldest message queued for `module_id`.
+    pub fn recv(&self, module_id: ModuleId) -> Option<Message> {
+        let mut state = self.state.borrow_mut();
+        let depth = state.depth;
+        state.queues.pop(depth, module_id)
+    }
+
+    /// Number of messages waiting for `module_id`.
+    pub fn pending(&self, module_id: ModuleId) -> usize {
+        self.state.borrow().queues.pending(module_id)
+    }
+
+    /// Messages dropped because a subscriber's queue was full.
+    pub fn dropped(&self) -> u32 {
+        self.state.borrow().dropped
+    }
+
+    fn active(&self) -> Result<ModuleId> {
+        self.state
+            .borrow()
+            .active
+            .last()
+            .copied()
+            .ok_or(Error::Engine("bus: no active module"))
+    }
+
+    fn guest_publish(&self, args: [i32; 4], memory: &dyn GuestMemory) -> Result<i32> {
+        let [topic_ptr, topic_len, data_ptr, data_len] = args;
+        let mut topic = [0u8; MAX_TOPIC_LEN];
+        let mut data = [0u8; MAX_MESSAGE_LEN];
+        let (Some(topic), Some(data)) = (
+            guest_bytes(memory, topic_ptr, topic_len, &mut topic),
+            guest_bytes(memory, data_ptr, data_len, &mut data),
+        ) else {
+            return Ok(E_INVALID);
+        };
+        match self.publish(Some(self.active()?), topic, data) {
+            Ok(delivered) => Ok(delivered as i32),
+            Err(_) => Ok(E_INVALID),
+        }
+    }
+
+    fn guest_subscribe(&self, args: [i32; 2], memory: &dyn GuestMemory) -> Result<i32> {
+        let [topic_ptr, topic_len] = args;
+        let mut topic = [0u8; MAX_TOPIC_LEN];
+        let Some(topic) = guest_bytes(memory, topic_ptr, topic_len, &mut topic) else {
+            return Ok(E_INVALID);
+        };
+        match self.subscribe(self.active()?, topic) {
+            Ok(()) => Ok(OK),
+            Err(_) => Ok(E_INVALID),
+        }
+    }
+
+    fn guest_recv(&self, args: [i32; 2], memory: &mut dyn GuestMemory) -> Result<i32> {
+        let [ptr, cap] = args;
+        let module_id = self.active()?;
+        let mut state = self.state.borrow_mut();
+        let depth = state.depth;
+        let Some((topic, data)) = state.queues.front(depth, module_id) else {
+            return Ok(E_EMPTY);
+        };
+        let len = 1 + topic.len() + data.len();
+        if cap < 0 || (cap as usize) < len {
+            return Ok(E_INVALID);
+        }
+        let ptr = ptr as u32;
+        let topic_end = ptr.wrapping_add(1 + topic.len() as u32);
+        let written = memory
+            .write(ptr, &[topic.len() as u8])
+            .and_then(|_| memory.write(ptr.wrapping_add(1), topic))
+            .and_then(|_| memory.write(topic_end, data));
+        if written.is_err() {
+            return Ok(E_INVALID);
+        }
+        state.queues.pop(depth, module_id);
+        Ok(len as i32)
+    }
+}
+
+fn check_topic(topic: &[u8]) -> Result<()> {
+    if topic.is_empty() || topic.len() > MAX_TOPIC_LEN {
+        return Err(Error::Engine("bus: bad topic"));
+    }
+    Ok(())
+}
+
+impl HostImports for Bus {
+    fn functions(&self) -> &[HostFn] {
+        FUNCTIONS
+    }
+
+    fn call(&mut self, name: &str, args: &[i32], memory: &mut dyn GuestMemory) -> Result<i32> {
+        match (name, args) {
+            ("bus_publish", &[a, b, c, d]) => self.guest_publish([a, b, c, d], memory),
+            ("bus_subscribe", &[a, b]) => self.guest_subscribe([a, b], memory),
+            ("bus_recv", &[a, b]) => self.guest_recv([a, b], memory),
+            _ => Err(Error::Engine("bus: bad host call")),
+        }
+    }
+
+    fn enter(&mut self, module_id: ModuleId) {
+        self.state.borrow_mut().active.push(module_id);
+    }
+
+    fn exit(&mut self) {
+        self.state.borrow_mut().active.pop();
+    }
+}
+
+#[cfg(all(test, feature = "std"))]
+mod tests {
+    use super::*;
+    use crate::abi::Imports;
+
+    #[test]
+    fn guest_pipeline_through_imports() {
+        let bus = Bus::new();
+        let trace = TraceContext::new();
+        bus.set_trace(trace.clone());
+        let mut imports = Imports::new().with(bus.clone());
+        let mut memory = [0u8; 64];
+        memory[..4].copy_from_slice(b"temp");
+        memory[8..10].copy_from_slice(&[21, 5]);
+
+        // Filter module (2) subscribes, sensor module (1) publishes.
+        imports.enter(2);
+        assert_eq!(
+            imports.call("bus_subscribe", &[0, 4], &mut memory).unwrap(),
+            OK
+        );
+        imports.enter(1);
+        let scope = trace.scope(9);
+        assert_eq!(
+            imports
+                .call("bus_publish", &[0, 4, 8, 2], &mut memory)
+                .unwrap(),
+            1
+        );
+        drop(scope);
+        assert_eq!(bus.pending(2), 1);
+
+        // The firmware can run the subscriber under the publisher's id.
+        if let Queues::Heap(subscribers) = &bus.state.borrow().queues {
+            assert_eq!(subscribers[0].queue[0].correlation, 9);
+        }
+        imports.enter(2);
+        assert_eq!(
+            imports.call("bus_recv", &[16, 4], &mut memory).unwrap(),
+            E_INVALID
+        );
+        assert_eq!(imports.call("bus_recv", &[16, 32], &mut memory).unwrap(), 7);
+        assert_eq!(&memory[16..23], b"\x04temp\x15\x05");
+        assert_eq!(
+            imports.call("bus_recv", &[16, 32], &mut memory).unwrap(),
+            E_EMPTY
+        );
+    }
+
+    #[test]
+    fn full_queues_drop_oldest() {
+        let bus = Bus::with_depth(2);
+        bus.subscribe(3, b"log").unwrap();
+        for n in 0..3u8 {
+            assert_eq!(bus.publish(None, b"log", &[n]).unwrap(), 1);
+        }
+        assert_eq!(bus.publish(Some(3), b"log", &[9]).unwrap(), 0);
+        assert_eq!(bus.dropped(), 1);
+        assert_eq!(bus.recv(3).unwrap().data, vec![1]);
+        assert_eq!(bus.recv(3).unwrap().data, vec![2]);
+        assert!(bus.recv(3).is_none());
+        assert!(bus.publish(None, b"", &[]).is_err());
+    }
+
+    #[test]
+    fn arena_queues_are_fixed_rings() {
+        static ARENA: Arena<4096> = Arena::new();
+        let bus = Bus::in_arena(&ARENA, 2, 2).unwrap();
+        let mut imports = Imports::new().with(bus.clone());
+        bus.subscribe(3, b"log").unwrap();
+        bus.subscribe(4, b"log").unwrap();
+        assert!(bus.subscribe(5, b"log").is_err());
+        for topic in [&b"a"[..], b"b", b"c"] {
+            bus.subscribe(3, topic).unwrap();
+        }
+        assert!(bus.subscribe(3, b"d").is_err());
+
+        for n in 0..3u8 {
+            assert_eq!(bus.publish(Some(4), b"log", &[n]).unwrap(), 1);
+        }
+        assert_eq!(bus.dropped(), 1);
+        assert_eq!(bus.pending(3), 2);
+        let message = bus.recv(3).unwrap();
+        assert_eq!((message.from, &message.topic[..]), (Some(4), &b"log"[..]));
+        assert_eq!(message.data, vec![1]);
+
+        let mut memory = [0u8; 16];
+        imports.enter(3);
+        assert_eq!(imports.call("bus_recv", &[0, 16], &mut memory).unwrap(), 5);
+        assert_eq!(&memory[..5], b"\x03log\x02");
+        assert!(bus.recv(3).is_none());
+
+        // A freed mailbox starts empty for the next subscriber.
+        bus.publish(None, b"log", &[7]).unwrap();
+        bus.unsubscribe_all(3);
+        bus.subscribe(5, b"log").unwrap();
+        assert_eq!(bus.pending(5), 0);
+        assert_eq!(bus.pending(4), 1);
+    }
+}
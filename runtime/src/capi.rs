@@ -0,0 +1,338 @@
+//! C ABI for linking slimmy into C/FreeRTOS firmware (`slimmy-capi` feature).
+//!
+//! Build a static library with
+//! `cargo rustc -p runtime --release --features slimmy-capi,engine-wasm3 --crate-type staticlib`
+//! and include `runtime/include/slimmy.h`. Without `std`, the final link also
+//! needs a `#[global_allocator]` and `#[panic_handler]` from a small wrapper
+//! crate, as for any `no_std` staticlib.
+//!
+//! Every call takes the opaque handle returned by `slimmy_init`. Calls
+//! returning `int32_t` give `SLIMMY_OK` (0) or a negative `SLIMMY_ERR_*` code;
+//! `slimmy_last_error` describes the most recent failure. A handle is not
+//! thread-safe: serialize calls on it (e.g. from one task).
+
+use crate::builder::{InstanceMode, RuntimeBuilder};
+use crate::manifest::{Manifest, MANIFEST_MAGIC};
+use crate::{CachedEngine, Error, MemoryStore, ModuleId, Result, Runtime};
+use alloc::boxed::Box;
+use core::ffi::{c_char, CStr};
+use core::fmt::Write;
+
+#[cfg(feature = "engine-wasm3")]
+type CEngine = crate::engines::wasm3::Wasm3Engine;
+#[cfg(all(
+    any(feature = "engine-wasmtime-lite", feature = "engine-wasmtime-winch"),
+    not(feature = "engine-wasm3")
+))]
+type CEngine = crate::engines::wasmtime_lite::WasmtimeLiteEngine;
+#[cfg(all(
+    feature = "engine-wasmi",
+    not(any(
+        feature = "engine-wasm3",
+        feature = "engine-wasmtime-lite",
+        feature = "engine-wasmtime-winch"
+    ))
+))]
+type CEngine = crate::engines::wasmi::WasmiEngine;
+#[cfg(not(any(
+    feature = "engine-wasm3",
+    feature = "engine-wasmi",
+    feature = "engine-wasmtime-lite",
+    feature = "engine-wasmtime-winch"
+)))]
+compile_error!(
+    "slimmy-capi needs an engine: enable engine-wasm3, engine-wasmi or engine-wasmtime-lite/-winch"
+);
+
+pub const SLIMMY_OK: i32 = 0;
+/// Null pointer, bad UTF-8 or malformed manifest passed in.
+pub const SLIMMY_ERR_INVALID: i32 = -1;
+pub const SLIMMY_ERR_MODULE_NOT_FOUND: i32 = -2;
+pub const SLIMMY_ERR_ENTRY_NOT_FOUND: i32 = -3;
+pub const SLIMMY_ERR_ENGINE: i32 = -4;
+pub const SLIMMY_ERR_UNSUPPORTED: i32 = -5;
+pub const SLIMMY_ERR_STATE_DENIED: i32 = -6;
+/// Missing, too old or cyclic dependency.
+pub const SLIMMY_ERR_DEPENDENCY: i32 = -7;
+/// Invocation rate or fuel quota used up for the current window.
+pub const SLIMMY_ERR_QUOTA: i32 = -8;
+/// A nested invocation was refused.
+pub const SLIMMY_ERR_REENTRANCY: i32 = -9;
+/// The module does not fit the store or its storage quota.
+pub const SLIMMY_ERR_STORAGE_FULL: i32 = -10;
+/// The module is installed but disabled.
+pub const SLIMMY_ERR_DISABLED: i32 = -11;
+/// A buffer could not be allocated.
+pub const SLIMMY_ERR_OUT_OF_MEMORY: i32 = -12;
+/// The entry point returned a negative status.
+pub const SLIMMY_ERR_GUEST: i32 = -13;
+/// The module was built for an ABI version this firmware does not speak.
+pub const SLIMMY_ERR_ABI: i32 = -14;
+/// The module or its signer is revoked.
+pub const SLIMMY_ERR_REVOKED: i32 = -15;
+/// The runtime is suspended.
+pub const SLIMMY_ERR_SUSPENDED: i32 = -16;
+
+/// Longest `slimmy_last_error` message, including the NUL.
+const ERROR_LEN: usize = 96;
+
+/// Opaque runtime handle (`slimmy_runtime_t` in C).
+pub struct SlimmyRuntime {
+    runtime: Runtime<CachedEngine<CEngine>, MemoryStore>,
+    last_error: [u8; ERROR_LEN],
+}
+
+impl SlimmyRuntime {
+    fn report(&mut self, code: i32, message: impl core::fmt::Display) -> i32 {
+        let mut buf = ErrorBuf {
+            buf: &mut self.last_error,
+            len: 0,
+        };
+        let _ = write!(buf, "{message}");
+        let len = buf.len;
+        self.last_error[len] = 0;
+        code
+    }
+
+    fn finish(&mut self, result: Result<()>) -> i32 {
+        match result {
+            Ok(()) => {
+                self.last_error[0] = 0;
+                SLIMMY_OK
+            }
+            Err(err) => self.report(code(&err), err),
+        }
+    }
+
+    fn install(&mut self, module_id: ModuleId, bytes: &[u8]) -> Result<()> {
+        let module = if bytes.starts_with(MANIFEST_MAGIC) {
+            let (manifest, module) = Manifest::parse(bytes)?;
+            if manifest.module_id != module_id || manifest.module_len as usize != module.len() {
+                return Err(Error::Engine("manifest does not match module"));
+            }
+            self.runtime.apply_manifest(&manifest)?;
+            module
+        } else {
+            bytes
+        };
+        self.runtime.engine().evict(module_id);
+        self.runtime.source_mut().upsert(module_id, module);
+        Ok(())
+    }
+}
+
+/// Truncating `fmt::Write` into the error buffer, leaving room for the NUL.
+struct ErrorBuf<'a> {
+    buf: &'a mut [u8; ERROR_LEN],
+    len: usize,
+}
+
+impl Write for ErrorBuf<'_> {
+    fn write_str(&mut self, s: &str) -> core::fmt::Result {
+        let room = ERROR_LEN - 1 - self.len;
+        let take = s.len().min(room);
+        self.buf[self.len..self.len + take].copy_from_slice(&s.as_bytes()[..take]);
+        self.len += take;
+        Ok(())
+    }
+}
+
+fn code(err: &Error) -> i32 {
+    match err {
+        Error::ModuleNotFound => SLIMMY_ERR_MODULE_NOT_FOUND,
+        Error::EntryNotFound => SLIMMY_ERR_ENTRY_NOT_FOUND,
+        Error::Engine(_) => SLIMMY_ERR_ENGINE,
+        Error::Unsupported => SLIMMY_ERR_UNSUPPORTED,
+        Error::StateDenied => SLIMMY_ERR_STATE_DENIED,
+        Error::DependencyMissing { .. }
+        | Error::DependencyTooOld { .. }
+        | Error::DependencyCycle { .. } => SLIMMY_ERR_DEPENDENCY,
+        Error::QuotaExceeded => SLIMMY_ERR_QUOTA,
+        Error::Reentrancy => SLIMMY_ERR_REENTRANCY,
+        Error::StorageFull { .. } => SLIMMY_ERR_STORAGE_FULL,
+        Error::ModuleDisabled => SLIMMY_ERR_DISABLED,
+        Error::OutOfMemory => SLIMMY_ERR_OUT_OF_MEMORY,
+        Error::GuestError(_) => SLIMMY_ERR_GUEST,
+        Error::AbiMismatch { .. } => SLIMMY_ERR_ABI,
+        Error::Revoked => SLIMMY_ERR_REVOKED,
+        Error::Suspended => SLIMMY_ERR_SUSPENDED,
+    }
+}
+
+/// Creates a runtime with an empty RAM module store; NULL if the engine
+/// fails to initialize. Release it with `slimmy_free`.
+#[no_mangle]
+pub extern "C" fn slimmy_init() -> *mut SlimmyRuntime {
+    let runtime = RuntimeBuilder::new(MemoryStore::new())
+        .instance_mode(InstanceMode::Cached {
+            max_modules: usize::MAX,
+        })
+        .build::<CEngine>();
+    match runtime {
+        Ok(runtime) => Box::into_raw(Box::new(SlimmyRuntime {
+            runtime,
+            last_error: [0; ERROR_LEN],
+        })),
+        Err(_) => core::ptr::null_mut(),
+    }
+}
+
+/// Installs (or replaces) a module from raw wasm or a manifest blob; a
+/// manifest must name `module_id` and is applied as by `Runtime::apply_manifest`.
+/// The bytes are copied.
+///
+/// # Safety
+/// `rt` comes from `slimmy_init` and `bytes` points to `len` readable bytes.
+#[no_mangle]
+pub unsafe extern "C" fn slimmy_install(
+    rt: *mut SlimmyRuntime,
+    module_id: ModuleId,
+    bytes: *const u8,
+    len: usize,
+) -> i32 {
+    let Some(rt) = rt.as_mut() else {
+        return SLIMMY_ERR_INVALID;
+    };
+    if bytes.is_null() {
+        return rt.report(SLIMMY_ERR_INVALID, "null module bytes");
+    }
+    let bytes = core::slice::from_raw_parts(bytes, len);
+    let result = rt.install(module_id, bytes);
+    if let Err(Error::Engine(reason)) = result {
+        if reason.starts_with("manifest") {
+            return rt.report(SLIMMY_ERR_INVALID, reason);
+        }
+    }
+    rt.finish(result)
+}
+
+/// Runs `entry` (NUL-terminated) of an installed module; an entry returning
+/// a negative status fails with `SLIMMY_ERR_GUEST` (see `ExitCode`).
+///
+/// # Safety
+/// `rt` comes from `slimmy_init` and `entry` is a valid C string.
+#[no_mangle]
+pub unsafe extern "C" fn slimmy_execute(
+    rt: *mut SlimmyRuntime,
+    module_id: ModuleId,
+    entry: *const c_char,
+) -> i32 {
+    let Some(rt) = rt.as_mut() else {
+        return SLIMMY_ERR_INVALID;
+    };
+    if entry.is_null() {
+        return rt.report(SLIMMY_ERR_INVALID, "null entry name");
+    }
+    let Ok(entry) = CStr::from_ptr(entry).to_str() else {
+        return rt.report(SLIMMY_ERR_INVALID, "entry name not utf-8");
+    };
+    let result = rt.runtime.execute_exit(module_id, entry, &mut ());
+    rt.finish(result.map(drop))
+}
+
+/// Message for the last failed call on `rt` ("" after a success). The string
+/// stays valid until the next call on `rt`.
+///
+/// # Safety
+/// `rt` is NULL or comes from `slimmy_init`.
+#[no_mangle]
+pub unsafe extern "C" fn slimmy_last_error(rt: *const SlimmyRuntime) -> *const c_char {
+    match rt.as_ref() {
+        Some(rt) => rt.last_error.as_ptr().cast(),
+        None => c"invalid runtime handle".as_ptr(),
+    }
+}
+
+/// Destroys a runtime and every module it holds.
+///
+/// # Safety
+/// `rt` is NULL or comes from `slimmy_init` and is not used afterwards.
+#[no_mangle]
+pub unsafe extern "C" fn slimmy_free(rt: *mut SlimmyRuntime) {
+    if !rt.is_null() {
+        drop(Box::from_raw(rt));
+    }
+}
+
+#[cfg(all(test, feature = "std", feature = "engine-wasmtime-lite"))]
+mod tests {
+    use super::*;
+    use crate::manifest;
+
+    fn last_error(rt: *const SlimmyRuntime) -> String {
+        unsafe { CStr::from_ptr(slimmy_last_error(rt)) }
+            .to_str()
+            .unwrap()
+            .to_string()
+    }
+
+    #[test]
+    fn installs_and_executes_through_the_c_abi() {
+        let wasm = wat::parse_str(
+            r#"(module
+                (func (export "main"))
+                (func (export "fail") (result i32) i32.const -5))"#,
+        )
+        .unwrap();
+        let rt = slimmy_init();
+        assert!(!rt.is_null());
+        unsafe {
+            assert_eq!(
+                slimmy_execute(rt, 1, c"main".as_ptr()),
+                SLIMMY_ERR_MODULE_NOT_FOUND
+            );
+            assert_eq!(last_error(rt), "module not found");
+
+            assert_eq!(slimmy_install(rt, 1, wasm.as_ptr(), wasm.len()), SLIMMY_OK);
+            assert_eq!(slimmy_execute(rt, 1, c"main".as_ptr()), SLIMMY_OK);
+            assert_eq!(last_error(rt), "");
+            assert_eq!(slimmy_execute(rt, 1, c"fail".as_ptr()), SLIMMY_ERR_GUEST);
+            assert_eq!(last_error(rt), "module reported error -5");
+            assert_eq!(
+                slimmy_execute(rt, 1, c"nope".as_ptr()),
+                SLIMMY_ERR_ENTRY_NOT_FOUND
+            );
+
+            let blob = manifest::encode(2, "main", &wasm, 0, 7, None).unwrap();
+            assert_eq!(slimmy_install(rt, 2, blob.as_ptr(), blob.len()), SLIMMY_OK);
+            assert_eq!((*rt).runtime.module_version(2), 7);
+            assert_eq!(
+                slimmy_install(rt, 3, blob.as_ptr(), blob.len()),
+                SLIMMY_ERR_INVALID
+            );
+            assert_eq!(last_error(rt), "manifest does not match module");
+
+            assert_eq!(
+                slimmy_execute(core::ptr::null_mut(), 1, c"main".as_ptr()),
+                SLIMMY_ERR_INVALID
+            );
+            slimmy_free(rt);
+        }
+    }
+
+    #[test]
+    fn header_declares_every_export() {
+        let header = include_str!("../include/slimmy.h");
+        let source = include_str!("capi.rs");
+        let exports: Vec<&str> = source
+            .split("extern \"C\" fn ")
+            .skip(1)
+            .filter_map(|rest| rest.split('(').next())
+            .filter(|name| name.starts_with("slimmy_"))
+            .collect();
+        assert_eq!(exports.len(), 5);
+        for name in exports {
+            assert!(
+                header.contains(&format!("{name}(")),
+                "{name} missing from slimmy.h"
+            );
+        }
+        for (name, value) in [
+            ("SLIMMY_ERR_INVALID", SLIMMY_ERR_INVALID),
+            ("SLIMMY_ERR_DEPENDENCY", SLIMMY_ERR_DEPENDENCY),
+            ("SLIMMY_ERR_QUOTA", SLIMMY_ERR_QUOTA),
+        ] {
+            assert!(header.contains(&format!("#define {name} ({value})")));
+        }
+    }
+}
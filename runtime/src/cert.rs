@@ -0,0 +1,314 @@
+//! Certificate-chain trust for manifest signers (`cert-chain` feature).
+//!
+//! Devices embed only root keys (`audit::Keyring::with_root`). A manifest
+//! names its signer with `EXT_SIGNER_CERT` records, a compact certificate
+//! for the per-product signing key followed by its issuers, or with
+//! `EXT_SIGNER_CERT_HASH` records for certificates the device already holds
+//! (`Keyring::with_certificate`). `verify_manifest` checks the signature
+//! with the leaf key and walks the chain up to a root, so new signing keys
+//! can be issued without reflashing the roots.
+//!
+//! Certificates are a fixed little-endian layout instead of X.509 DER, to
+//! fit in one extension record and parse without an ASN.1 decoder:
+//! - magic: 4 bytes = b"SMNC"
+//! - version: u8 = 1
+//! - flags: u8 (bit0 = CA, may issue certificates)
+//! - serial: u32
+//! - module_min, module_max: u32 each (module ids the key may sign)
+//! - key_alg: u8 (`manifest::SIG_ALG_*`), then the subject key (32 bytes for
+//!   Ed25519, 33 for P-256)
+//! - issuer_alg: u8
+//! - signature: [u8; 64] by the issuer over the bytes before it
+//!
+//! Every certificate in a chain must cover the module id, every issuer must
+//! be a CA, and chains are at most `MAX_CHAIN_LEN` certificates long.
+
+use crate::manifest::{Manifest, EXT_SIGNER_CERT, EXT_SIGNER_CERT_HASH};
+use crate::verify::{Algorithm, PublicKey, Verifier, SIGNATURE_LEN};
+use crate::{Error, ModuleId, Result};
+use core::ops::RangeInclusive;
+
+/// Certificate magic marker.
+pub const CERT_MAGIC: &[u8; 4] = b"SMNC";
+/// Certificate layout version.
+pub const CERT_VERSION: u8 = 1;
+/// The subject may issue certificates.
+pub const FLAG_CA: u8 = 0b0000_0001;
+/// Longest chain `verify_manifest` walks, leaf included.
+pub const MAX_CHAIN_LEN: usize = 4;
+
+/// SHA-256 of a certificate's bytes, as `EXT_SIGNER_CERT_HASH` names it.
+pub type CertDigest = [u8; 32];
+
+const FIXED_LEN: usize = 4 + 1 + 1 + 4 + 4 + 4 + 1;
+
+/// Parsed view into a certificate.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Certificate<'a> {
+    pub flags: u8,
+    pub serial: u32,
+    pub module_ids: RangeInclusive<ModuleId>,
+    pub subject: PublicKey,
+    pub issuer_algorithm: Algorithm,
+    pub signature: &'a [u8; SIGNATURE_LEN],
+    /// Bytes the signature covers.
+    signed: &'a [u8],
+}
+
+impl<'a> Certificate<'a> {
+    /// Parses a certificate of exactly `bytes`.
+    pub fn parse(bytes: &'a [u8]) -> Result<Self> {
+        let malformed = Error::Engine("certificate malformed");
+        let fixed = bytes.get(..FIXED_LEN).ok_or(malformed)?;
+        if &fixed[..4] != CERT_MAGIC {
+            return Err(Error::Engine("bad certificate magic"));
+        }
+        if fixed[4] != CERT_VERSION {
+            return Err(Error::Engine("unsupported certificate version"));
+        }
+        let u32_at = |at: usize| u32::from_le_bytes(fixed[at..at + 4].try_into().unwrap());
+        let (module_min, module_max) = (u32_at(10), u32_at(14));
+        let algorithm = Algorithm::from_id(fixed[18]).ok_or(malformed)?;
+        let key_end = FIXED_LEN + algorithm.public_key_len();
+        let subject =
+            PublicKey::from_bytes(algorithm, bytes.get(FIXED_LEN..key_end).ok_or(malformed)?)?;
+        let issuer_algorithm = bytes
+            .get(key_end)
+            .and_then(|&id| Algorithm::from_id(id))
+            .ok_or(malformed)?;
+        let (signed, signature) = bytes.split_at(key_end + 1);
+        let signature = signature.try_into().map_err(|_| malformed)?;
+        Ok(Self {
+            flags: fixed[5],
+            serial: u32_at(6),
+            module_ids: module_min..=module_max,
+            subject,
+            issuer_algorithm,
+            signature,
+            signed,
+        })
+    }
+
+    /// Whether the subject may issue certificates.
+    pub fn is_ca(&self) -> bool {
+        self.flags & FLAG_CA != 0
+    }
+
+    /// Whether the subject may sign module `module_id`.
+    pub fn covers(&self, module_id: ModuleId) -> bool {
+        self.module_ids.contains(&module_id)
+    }
+
+    /// Checks that `issuer` signed this certificate.
+    pub fn verify_issued_by(&self, verifier: &dyn Verifier, issuer: &PublicKey) -> Result<()> {
+        if issuer.algorithm() != self.issuer_algorithm {
+            return Err(Error::Engine("certificate signed with another algorithm"));
+        }
+        issuer.verify(verifier, &[self.signed], self.signature)
+    }
+}
+
+/// `CertDigest` of certificate `bytes`.
+pub fn digest(bytes: &[u8]) -> CertDigest {
+    use sha2::{Digest, Sha256};
+    Sha256::digest(bytes).into()
+}
+
+#[cfg(feature = "alloc")]
+/// Bytes an issuer signs for a certificate; append the signature to get the
+/// certificate.
+pub fn signing_preimage(
+    flags: u8,
+    serial: u32,
+    module_ids: RangeInclusive<ModuleId>,
+    subject: &PublicKey,
+    issuer_algorithm: Algorithm,
+) -> alloc::vec::Vec<u8> {
+    let mut out = alloc::vec::Vec::with_capacity(FIXED_LEN + 34 + SIGNATURE_LEN);
+    out.extend_from_slice(CERT_MAGIC);
+    out.push(CERT_VERSION);
+    out.push(flags);
+    out.extend_from_slice(&serial.to_le_bytes());
+    out.extend_from_slice(&module_ids.start().to_le_bytes());
+    out.extend_from_slice(&module_ids.end().to_le_bytes());
+    out.push(subject.algorithm().id());
+    out.extend_from_slice(subject.as_bytes());
+    out.push(issuer_algorithm.id());
+    out
+}
+
+/// Verifies the manifest signature with the signer certificate it names and
+/// that certificate's chain up to one of `roots`; returns the leaf.
+/// `known` holds the certificates `EXT_SIGNER_CERT_HASH` records may name.
+pub fn verify_manifest<'a, K: AsRef<[u8]>>(
+    verifier: &dyn Verifier,
+    manifest: &Manifest<'a>,
+    module: &[u8],
+    roots: &[PublicKey],
+    known: &'a [K],
+) -> Result<Certificate<'a>> {
+    let mut chain = manifest.extensions().filter_map(|(tag, value)| match tag {
+        EXT_SIGNER_CERT => Some(Certificate::parse(value)),
+        EXT_SIGNER_CERT_HASH => Some(lookup(value, known)),
+        _ => None,
+    });
+    let leaf = chain
+        .next()
+        .ok_or(Error::Engine("manifest names no signer certificate"))??;
+    crate::manifest::verify_key(verifier, manifest, module, &leaf.subject)?;
+
+    let module_id = manifest.module_id;
+    let mut subject = leaf.clone();
+    for len in 1.. {
+        if !subject.covers(module_id) {
+            return Err(Error::Engine("certificate does not cover module"));
+        }
+        let Some(issuer) = chain.next().transpose()? else {
+            break;
+        };
+        if len == MAX_CHAIN_LEN {
+            return Err(Error::Engine("certificate chain too long"));
+        }
+        if !issuer.is_ca() {
+            return Err(Error::Engine("certificate issuer is not a CA"));
+        }
+        subject.verify_issued_by(verifier, &issuer.subject)?;
+        subject = issuer;
+    }
+    let anchored = roots
+        .iter()
+        .any(|root| subject.verify_issued_by(verifier, root).is_ok());
+    if anchored {
+        Ok(leaf)
+    } else {
+        Err(Error::Engine("certificate chain not anchored in a root"))
+    }
+}
+
+/// Whether the manifest names a signer certificate.
+pub fn has_chain(manifest: &Manifest<'_>) -> bool {
+    manifest
+        .extensions()
+        .any(|(tag, _)| tag == EXT_SIGNER_CERT || tag == EXT_SIGNER_CERT_HASH)
+}
+
+fn lookup<'a, K: AsRef<[u8]>>(hash: &[u8], known: &'a [K]) -> Result<Certificate<'a>> {
+    let cert = known
+        .iter()
+        .map(AsRef::as_ref)
+        .find(|cert| crate::manifest::ct_eq(&digest(cert), hash))
+        .ok_or(Error::Engine("signer certificate unknown"))?;
+    Certificate::parse(cert)
+}
+
+#[cfg(all(test, feature = "std", feature = "verify-ed25519"))]
+mod tests {
+    use super::*;
+    use crate::manifest::{self, push_extension};
+    use crate::verify::SoftwareVerifier;
+    use ed25519_dalek::{Signer, SigningKey};
+
+    fn issue(
+        issuer: &SigningKey,
+        subject: &SigningKey,
+        ids: RangeInclusive<ModuleId>,
+        flags: u8,
+    ) -> Vec<u8> {
+        let subject = PublicKey::Ed25519(subject.verifying_key().to_bytes());
+        let mut cert = signing_preimage(flags, 7, ids, &subject, Algorithm::Ed25519);
+        cert.extend_from_slice(&issuer.sign(&cert).to_bytes());
+        cert
+    }
+
+    fn signed(signer: &SigningKey, module_id: ModuleId, ext: &[u8]) -> Vec<u8> {
+        let module = b"\0asm\x01\0\0\0";
+        let preimage =
+            manifest::signing_preimage_ext(module_id, "main", module, 0, 1, ext).unwrap();
+        let sig = signer.sign(&preimage).to_bytes();
+        manifest::encode_ext(module_id, "main", module, 0, 1, ext, Some(sig)).unwrap()
+    }
+
+    #[test]
+    fn chains_verify_up_to_a_root() {
+        let root = SigningKey::from_bytes(&[1; 32]);
+        let product = SigningKey::from_bytes(&[2; 32]);
+        let signer = SigningKey::from_bytes(&[3; 32]);
+        let roots = [PublicKey::Ed25519(root.verifying_key().to_bytes())];
+        let intermediate = issue(&root, &product, 0..=99, FLAG_CA);
+        let leaf = issue(&product, &signer, 10..=19, 0);
+        let check = |blob: &[u8], known: &[Vec<u8>]| {
+            let (parsed, module) = Manifest::parse(blob).unwrap();
+            verify_manifest(&SoftwareVerifier, &parsed, module, &roots, known)
+                .map(|leaf| leaf.serial)
+        };
+
+        let mut ext = Vec::new();
+        push_extension(&mut ext, manifest::EXT_SIGNER_CERT, &leaf).unwrap();
+        push_extension(&mut ext, manifest::EXT_SIGNER_CERT, &intermediate).unwrap();
+        assert_eq!(check(&signed(&signer, 12, &ext), &[]), Ok(7));
+        #[cfg(feature = "unstable")]
+        {
+            use crate::audit::{self, Issue, Keyring};
+            let keyring = Keyring::new().with_root(roots[0]).require_signatures();
+            assert_eq!(
+                audit::check(12, &signed(&signer, 12, &ext), &keyring),
+                Ok(())
+            );
+            assert_eq!(
+                audit::check(20, &signed(&signer, 20, &ext), &keyring),
+                Err(Issue::Untrusted)
+            );
+        }
+        // Outside the leaf's module ids, or signed by another key.
+        assert!(check(&signed(&signer, 20, &ext), &[]).is_err());
+        assert!(check(&signed(&product, 12, &ext), &[]).is_err());
+
+        // The intermediate by hash, once the device holds it.
+        let mut by_hash = Vec::new();
+        push_extension(&mut by_hash, manifest::EXT_SIGNER_CERT, &leaf).unwrap();
+        push_extension(
+            &mut by_hash,
+            manifest::EXT_SIGNER_CERT_HASH,
+            &digest(&intermediate),
+        )
+        .unwrap();
+        let blob = signed(&signer, 12, &by_hash);
+        assert_eq!(check(&blob, &[intermediate]), Ok(7));
+        assert_eq!(
+            check(&blob, &[]),
+            Err(Error::Engine("signer certificate unknown"))
+        );
+
+        // Issuers must be CAs, and the chain must end at a root.
+        let rogue = issue(&signer, &product, 0..=99, 0);
+        let mut ext = Vec::new();
+        push_extension(
+            &mut ext,
+            manifest::EXT_SIGNER_CERT,
+            &issue(&product, &signer, 10..=19, 0),
+        )
+        .unwrap();
+        push_extension(&mut ext, manifest::EXT_SIGNER_CERT, &rogue).unwrap();
+        assert_eq!(
+            check(&signed(&signer, 12, &ext), &[]),
+            Err(Error::Engine("certificate issuer is not a CA"))
+        );
+        let mut ext = Vec::new();
+        push_extension(&mut ext, manifest::EXT_SIGNER_CERT, &leaf).unwrap();
+        assert_eq!(
+            check(&signed(&signer, 12, &ext), &[]),
+            Err(Error::Engine("certificate chain not anchored in a root"))
+        );
+
+        let mut tampered = leaf.clone();
+        tampered[14] = 0xff;
+        assert!(Certificate::parse(&tampered)
+            .unwrap()
+            .verify_issued_by(
+                &SoftwareVerifier,
+                &PublicKey::Ed25519(product.verifying_key().to_bytes())
+            )
+            .is_err());
+        assert!(Certificate::parse(&leaf[..leaf.len() - 1]).is_err());
+    }
+}
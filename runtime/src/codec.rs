@@ -0,0 +1,204 @@
+//! Serde wire formats for payloads passed between host and guests
+//! (`codec` feature, no_std).
+//!
+//! - `postcard`: compact and type-driven; the one format guests see. Typed
+//!   exports (`rpc`) and the remote `Call` command (`remote`) carry it.
+//! - `cbor`: self-describing CBOR (RFC 8949) for backends and tools that
+//!   decode payloads without the Rust types, e.g. telemetry forwarded to a
+//!   fleet service.
+//!
+//! `Format` picks one at run time, named on the wire by its `id`.
+
+use crate::Error;
+#[cfg(feature = "alloc")]
+use alloc::vec::Vec;
+use core::fmt::{self, Write as _};
+use serde::de::Deserialize;
+use serde::ser::Serialize;
+
+pub mod cbor;
+pub mod postcard;
+
+/// Why a payload could not be encoded or decoded.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum CodecError {
+    /// The output buffer (or heap) has no room for the encoding.
+    BufferFull,
+    /// The payload ended inside a value.
+    UnexpectedEnd,
+    /// Bytes were left after the value.
+    TrailingBytes,
+    /// The bytes do not encode a value of the expected type.
+    Invalid(&'static str),
+    /// The type uses a serde feature the format cannot express.
+    Unsupported(&'static str),
+    /// A `Serialize` or `Deserialize` impl reported its own error.
+    Custom,
+}
+
+impl fmt::Display for CodecError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        Error::from(*self).write_to(f)
+    }
+}
+
+impl serde::ser::StdError for CodecError {}
+
+impl serde::ser::Error for CodecError {
+    fn custom<T: fmt::Display>(_msg: T) -> Self {
+        CodecError::Custom
+    }
+}
+
+impl serde::de::Error for CodecError {
+    fn custom<T: fmt::Display>(_msg: T) -> Self {
+        CodecError::Custom
+    }
+}
+
+impl From<CodecError> for Error {
+    fn from(err: CodecError) -> Self {
+        Error::Engine(match err {
+            CodecError::BufferFull => "codec: buffer full",
+            CodecError::UnexpectedEnd => "codec: payload truncated",
+            CodecError::TrailingBytes => "codec: trailing bytes",
+            CodecError::Invalid(msg) | CodecError::Unsupported(msg) => msg,
+            CodecError::Custom => "codec: rejected by the type",
+        })
+    }
+}
+
+/// A payload format chosen at run time.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+    Postcard,
+    Cbor,
+}
+
+impl Format {
+    /// The byte naming this format in frames and sidecars.
+    pub const fn id(self) -> u8 {
+        match self {
+            Format::Postcard => 0,
+            Format::Cbor => 1,
+        }
+    }
+
+    pub const fn from_id(id: u8) -> Option<Self> {
+        match id {
+            0 => Some(Format::Postcard),
+            1 => Some(Format::Cbor),
+            _ => None,
+        }
+    }
+
+    /// Encodes `value` into `buf`; returns the used prefix.
+    pub fn to_slice<'b, T: Serialize + ?Sized>(
+        self,
+        value: &T,
+        buf: &'b mut [u8],
+    ) -> Result<&'b mut [u8], CodecError> {
+        match self {
+            Format::Postcard => postcard::to_slice(value, buf),
+            Format::Cbor => cbor::to_slice(value, buf),
+        }
+    }
+
+    /// Encodes `value` into a new buffer.
+    #[cfg(feature = "alloc")]
+    pub fn to_vec<T: Serialize + ?Sized>(self, value: &T) -> Result<Vec<u8>, CodecError> {
+        match self {
+            Format::Postcard => postcard::to_vec(value),
+            Format::Cbor => cbor::to_vec(value),
+        }
+    }
+
+    /// Decodes a `T` that spans all of `bytes`.
+    pub fn from_bytes<'de, T: Deserialize<'de>>(self, bytes: &'de [u8]) -> Result<T, CodecError> {
+        match self {
+            Format::Postcard => postcard::from_bytes(bytes),
+            Format::Cbor => cbor::from_bytes(bytes),
+        }
+    }
+}
+
+/// Where encoded bytes go.
+trait Sink {
+    fn put(&mut self, bytes: &[u8]) -> Result<(), CodecError>;
+}
+
+struct SliceSink<'b> {
+    buf: &'b mut [u8],
+    len: usize,
+}
+
+impl Sink for SliceSink<'_> {
+    fn put(&mut self, bytes: &[u8]) -> Result<(), CodecError> {
+        let end = self.len + bytes.len();
+        self.buf
+            .get_mut(self.len..end)
+            .ok_or(CodecError::BufferFull)?
+            .copy_from_slice(bytes);
+        self.len = end;
+        Ok(())
+    }
+}
+
+#[cfg(feature = "alloc")]
+impl Sink for Vec<u8> {
+    fn put(&mut self, bytes: &[u8]) -> Result<(), CodecError> {
+        self.try_reserve(bytes.len())
+            .map_err(|_| CodecError::BufferFull)?;
+        self.extend_from_slice(bytes);
+        Ok(())
+    }
+}
+
+/// Counts or copies what `Display` writes, for `collect_str`.
+struct StrSink<'s, S> {
+    out: Option<&'s mut S>,
+    len: usize,
+    failed: Option<CodecError>,
+}
+
+impl<S: Sink> fmt::Write for StrSink<'_, S> {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        self.len += s.len();
+        if let Some(out) = &mut self.out {
+            if let Err(err) = out.put(s.as_bytes()) {
+                self.failed = Some(err);
+                return Err(fmt::Error);
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Writes `value` as a string whose length `head` puts first, without
+/// a heap: it is formatted once to count and once to copy.
+fn write_display<S: Sink, T: fmt::Display + ?Sized>(
+    out: &mut S,
+    value: &T,
+    head: impl FnOnce(&mut S, usize) -> Result<(), CodecError>,
+) -> Result<(), CodecError> {
+    let mut count = StrSink::<S> {
+        out: None,
+        len: 0,
+        failed: None,
+    };
+    write!(count, "{value}").map_err(|_| CodecError::Invalid("codec: Display failed"))?;
+    head(out, count.len)?;
+    let mut copy = StrSink {
+        out: Some(out),
+        len: 0,
+        failed: None,
+    };
+    match write!(copy, "{value}") {
+        Err(_) => Err(copy
+            .failed
+            .unwrap_or(CodecError::Invalid("codec: Display failed"))),
+        Ok(()) if copy.len != count.len => Err(CodecError::Invalid("codec: Display changed")),
+        Ok(()) => Ok(()),
+    }
+}
@@ -0,0 +1,822 @@
+//! CBOR (RFC 8949) for serde types.
+//!
+//! Self-describing, so payloads decode without the Rust types: other
+//! languages read them with any CBOR library and `deserialize_any` works.
+//! Follows the usual serde mapping: structs are maps keyed by field name,
+//! `None` and `()` are null, unit variants are their name as a text string
+//! and other variants a one-entry map from name to content. Integers take
+//! the shortest head and floats keep their width. Decoding also accepts
+//! indefinite-length arrays and maps, half floats and (skipped) tags, and
+//! refuses indefinite-length strings, integers beyond 64 bits, nesting past
+//! `MAX_DEPTH` and trailing bytes.
+
+use super::{write_display, CodecError, Sink, SliceSink};
+#[cfg(feature = "alloc")]
+use alloc::vec::Vec;
+use core::fmt;
+use serde::de::{self, DeserializeSeed, Visitor};
+use serde::ser::{self, Serialize};
+
+type Result<T> = core::result::Result<T, CodecError>;
+
+/// Deepest nesting of arrays, maps and tags `from_bytes` accepts.
+pub const MAX_DEPTH: u32 = 32;
+
+const MAJOR_UNSIGNED: u8 = 0;
+const MAJOR_NEGATIVE: u8 = 1;
+const MAJOR_BYTES: u8 = 2;
+const MAJOR_TEXT: u8 = 3;
+const MAJOR_ARRAY: u8 = 4;
+const MAJOR_MAP: u8 = 5;
+const MAJOR_TAG: u8 = 6;
+
+const FALSE: u8 = 0xf4;
+const TRUE: u8 = 0xf5;
+const NULL: u8 = 0xf6;
+const UNDEFINED: u8 = 0xf7;
+const BREAK: u8 = 0xff;
+
+/// Encodes `value` into `buf`; returns the used prefix.
+pub fn to_slice<'b, T: Serialize + ?Sized>(value: &T, buf: &'b mut [u8]) -> Result<&'b mut [u8]> {
+    let mut serializer = Serializer {
+        out: SliceSink { buf, len: 0 },
+    };
+    value.serialize(&mut serializer)?;
+    let SliceSink { buf, len } = serializer.out;
+    Ok(&mut buf[..len])
+}
+
+/// Encodes `value` into a new buffer.
+#[cfg(feature = "alloc")]
+pub fn to_vec<T: Serialize + ?Sized>(value: &T) -> Result<Vec<u8>> {
+    let mut serializer = Serializer { out: Vec::new() };
+    value.serialize(&mut serializer)?;
+    Ok(serializer.out)
+}
+
+/// Decodes a `T` that spans all of `bytes`.
+pub fn from_bytes<'de, T: de::Deserialize<'de>>(bytes: &'de [u8]) -> Result<T> {
+    let mut deserializer = Deserializer {
+        input: bytes,
+        depth: 0,
+    };
+    let value = T::deserialize(&mut deserializer)?;
+    if !deserializer.input.is_empty() {
+        return Err(CodecError::TrailingBytes);
+    }
+    Ok(value)
+}
+
+/// Writes an item head: the major type and its shortest argument.
+fn head(out: &mut impl Sink, major: u8, value: u64) -> Result<()> {
+    let mut buf = [0u8; 9];
+    let len = if value < 24 {
+        buf[0] = major << 5 | value as u8;
+        1
+    } else if let Ok(value) = u8::try_from(value) {
+        buf[..2].copy_from_slice(&[major << 5 | 24, value]);
+        2
+    } else if let Ok(value) = u16::try_from(value) {
+        buf[0] = major << 5 | 25;
+        buf[1..3].copy_from_slice(&value.to_be_bytes());
+        3
+    } else if let Ok(value) = u32::try_from(value) {
+        buf[0] = major << 5 | 26;
+        buf[1..5].copy_from_slice(&value.to_be_bytes());
+        5
+    } else {
+        buf[0] = major << 5 | 27;
+        buf[1..].copy_from_slice(&value.to_be_bytes());
+        9
+    };
+    out.put(&buf[..len])
+}
+
+struct Serializer<S> {
+    out: S,
+}
+
+impl<S: Sink> Serializer<S> {
+    fn head(&mut self, major: u8, value: u64) -> Result<()> {
+        head(&mut self.out, major, value)
+    }
+
+    fn integer(&mut self, value: i128) -> Result<()> {
+        let too_wide = |_| CodecError::Unsupported("codec: integer wider than 64 bits");
+        if value < 0 {
+            self.head(MAJOR_NEGATIVE, u64::try_from(!value).map_err(too_wide)?)
+        } else {
+            self.head(MAJOR_UNSIGNED, u64::try_from(value).map_err(too_wide)?)
+        }
+    }
+
+    fn len(&mut self, major: u8, len: usize) -> Result<()> {
+        self.head(major, len as u64)
+    }
+
+    /// Opens the one-entry map holding a variant's content.
+    fn variant(&mut self, variant: &str) -> Result<()> {
+        self.head(MAJOR_MAP, 1)?;
+        ser::Serializer::serialize_str(self, variant)
+    }
+
+    /// An array or map of `len` items, indefinite when the length is unknown.
+    fn open(&mut self, major: u8, len: Option<usize>) -> Result<Compound<'_, S>> {
+        match len {
+            Some(len) => self.len(major, len)?,
+            None => self.out.put(&[major << 5 | 31])?,
+        }
+        Ok(Compound {
+            ser: self,
+            indefinite: len.is_none(),
+        })
+    }
+}
+
+struct Compound<'a, S> {
+    ser: &'a mut Serializer<S>,
+    indefinite: bool,
+}
+
+impl<S: Sink> Compound<'_, S> {
+    fn end(self) -> Result<()> {
+        if self.indefinite {
+            self.ser.out.put(&[BREAK])?;
+        }
+        Ok(())
+    }
+}
+
+impl<'a, S: Sink> ser::Serializer for &'a mut Serializer<S> {
+    type Ok = ();
+    type Error = CodecError;
+    type SerializeSeq = Compound<'a, S>;
+    type SerializeTuple = Compound<'a, S>;
+    type SerializeTupleStruct = Compound<'a, S>;
+    type SerializeTupleVariant = Compound<'a, S>;
+    type SerializeMap = Compound<'a, S>;
+    type SerializeStruct = Compound<'a, S>;
+    type SerializeStructVariant = Compound<'a, S>;
+
+    fn is_human_readable(&self) -> bool {
+        false
+    }
+
+    fn serialize_bool(self, v: bool) -> Result<()> {
+        self.out.put(&[if v { TRUE } else { FALSE }])
+    }
+
+    fn serialize_i8(self, v: i8) -> Result<()> {
+        self.integer(v.into())
+    }
+
+    fn serialize_i16(self, v: i16) -> Result<()> {
+        self.integer(v.into())
+    }
+
+    fn serialize_i32(self, v: i32) -> Result<()> {
+        self.integer(v.into())
+    }
+
+    fn serialize_i64(self, v: i64) -> Result<()> {
+        self.integer(v.into())
+    }
+
+    fn serialize_i128(self, v: i128) -> Result<()> {
+        self.integer(v)
+    }
+
+    fn serialize_u8(self, v: u8) -> Result<()> {
+        self.integer(v.into())
+    }
+
+    fn serialize_u16(self, v: u16) -> Result<()> {
+        self.integer(v.into())
+    }
+
+    fn serialize_u32(self, v: u32) -> Result<()> {
+        self.integer(v.into())
+    }
+
+    fn serialize_u64(self, v: u64) -> Result<()> {
+        self.integer(v.into())
+    }
+
+    fn serialize_u128(self, v: u128) -> Result<()> {
+        let v = u64::try_from(v)
+            .map_err(|_| CodecError::Unsupported("codec: integer wider than 64 bits"))?;
+        self.integer(v.into())
+    }
+
+    fn serialize_f32(self, v: f32) -> Result<()> {
+        self.out.put(&[0xfa])?;
+        self.out.put(&v.to_be_bytes())
+    }
+
+    fn serialize_f64(self, v: f64) -> Result<()> {
+        self.out.put(&[0xfb])?;
+        self.out.put(&v.to_be_bytes())
+    }
+
+    fn serialize_char(self, v: char) -> Result<()> {
+        self.serialize_str(v.encode_utf8(&mut [0; 4]))
+    }
+
+    fn serialize_str(self, v: &str) -> Result<()> {
+        self.len(MAJOR_TEXT, v.len())?;
+        self.out.put(v.as_bytes())
+    }
+
+    fn serialize_bytes(self, v: &[u8]) -> Result<()> {
+        self.len(MAJOR_BYTES, v.len())?;
+        self.out.put(v)
+    }
+
+    fn serialize_none(self) -> Result<()> {
+        self.out.put(&[NULL])
+    }
+
+    fn serialize_some<T: Serialize + ?Sized>(self, value: &T) -> Result<()> {
+        value.serialize(self)
+    }
+
+    fn serialize_unit(self) -> Result<()> {
+        self.out.put(&[NULL])
+    }
+
+    fn serialize_unit_struct(self, _name: &'static str) -> Result<()> {
+        self.out.put(&[NULL])
+    }
+
+    fn serialize_unit_variant(
+        self,
+        _name: &'static str,
+        _index: u32,
+        variant: &'static str,
+    ) -> Result<()> {
+        self.serialize_str(variant)
+    }
+
+    fn serialize_newtype_struct<T: Serialize + ?Sized>(
+        self,
+        _name: &'static str,
+        value: &T,
+    ) -> Result<()> {
+        value.serialize(self)
+    }
+
+    fn serialize_newtype_variant<T: Serialize + ?Sized>(
+        self,
+        _name: &'static str,
+        _index: u32,
+        variant: &'static str,
+        value: &T,
+    ) -> Result<()> {
+        self.variant(variant)?;
+        value.serialize(self)
+    }
+
+    fn serialize_seq(self, len: Option<usize>) -> Result<Compound<'a, S>> {
+        self.open(MAJOR_ARRAY, len)
+    }
+
+    fn serialize_tuple(self, len: usize) -> Result<Compound<'a, S>> {
+        self.open(MAJOR_ARRAY, Some(len))
+    }
+
+    fn serialize_tuple_struct(self, _name: &'static str, len: usize) -> Result<Compound<'a, S>> {
+        self.open(MAJOR_ARRAY, Some(len))
+    }
+
+    fn serialize_tuple_variant(
+        self,
+        _name: &'static str,
+        _index: u32,
+        variant: &'static str,
+        len: usize,
+    ) -> Result<Compound<'a, S>> {
+        self.variant(variant)?;
+        self.open(MAJOR_ARRAY, Some(len))
+    }
+
+    fn serialize_map(self, len: Option<usize>) -> Result<Compound<'a, S>> {
+        self.open(MAJOR_MAP, len)
+    }
+
+    fn serialize_struct(self, _name: &'static str, len: usize) -> Result<Compound<'a, S>> {
+        self.open(MAJOR_MAP, Some(len))
+    }
+
+    fn serialize_struct_variant(
+        self,
+        _name: &'static str,
+        _index: u32,
+        variant: &'static str,
+        len: usize,
+    ) -> Result<Compound<'a, S>> {
+        self.variant(variant)?;
+        self.open(MAJOR_MAP, Some(len))
+    }
+
+    fn collect_str<T: fmt::Display + ?Sized>(self, value: &T) -> Result<()> {
+        write_display(&mut self.out, value, |out, len| {
+            head(out, MAJOR_TEXT, len as u64)
+        })
+    }
+}
+
+macro_rules! compound {
+    ($($trait:ident :: $method:ident),*) => {$(
+        impl<S: Sink> ser::$trait for Compound<'_, S> {
+            type Ok = ();
+            type Error = CodecError;
+
+            fn $method<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<()> {
+                value.serialize(&mut *self.ser)
+            }
+
+            fn end(self) -> Result<()> {
+                Compound::end(self)
+            }
+        }
+    )*};
+}
+
+compound!(
+    SerializeSeq::serialize_element,
+    SerializeTuple::serialize_element,
+    SerializeTupleStruct::serialize_field,
+    SerializeTupleVariant::serialize_field
+);
+
+impl<S: Sink> ser::SerializeMap for Compound<'_, S> {
+    type Ok = ();
+    type Error = CodecError;
+
+    fn serialize_key<T: Serialize + ?Sized>(&mut self, key: &T) -> Result<()> {
+        key.serialize(&mut *self.ser)
+    }
+
+    fn serialize_value<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<()> {
+        value.serialize(&mut *self.ser)
+    }
+
+    fn end(self) -> Result<()> {
+        Compound::end(self)
+    }
+}
+
+macro_rules! fields {
+    ($($trait:ident),*) => {$(
+        impl<S: Sink> ser::$trait for Compound<'_, S> {
+            type Ok = ();
+            type Error = CodecError;
+
+            fn serialize_field<T: Serialize + ?Sized>(
+                &mut self,
+                key: &'static str,
+                value: &T,
+            ) -> Result<()> {
+                ser::Serializer::serialize_str(&mut *self.ser, key)?;
+                value.serialize(&mut *self.ser)
+            }
+
+            fn end(self) -> Result<()> {
+                Compound::end(self)
+            }
+        }
+    )*};
+}
+
+fields!(SerializeStruct, SerializeStructVariant);
+
+/// Widens an IEEE 754 half-precision float; every one is exact in `f32`.
+fn half(bits: u16) -> f32 {
+    let sign = u32::from(bits & 0x8000) << 16;
+    let exp = u32::from(bits >> 10 & 0x1f);
+    let mantissa = u32::from(bits & 0x3ff);
+    match exp {
+        0 => {
+            // Subnormal: mantissa × 2^-24.
+            let magnitude = mantissa as f32 * f32::from_bits(0x3380_0000);
+            if sign == 0 {
+                magnitude
+            } else {
+                -magnitude
+            }
+        }
+        0x1f => f32::from_bits(sign | 0x7f80_0000 | mantissa << 13),
+        _ => f32::from_bits(sign | (exp + 112) << 23 | mantissa << 13),
+    }
+}
+
+/// The argument of a head that must not be indefinite.
+fn definite(arg: Option<u64>) -> Result<u64> {
+    arg.ok_or(CodecError::Invalid("codec: unexpected indefinite length"))
+}
+
+fn length(arg: Option<u64>) -> Result<Option<usize>> {
+    arg.map(|len| usize::try_from(len).map_err(|_| CodecError::Invalid("codec: length too large")))
+        .transpose()
+}
+
+struct Deserializer<'de> {
+    input: &'de [u8],
+    /// Arrays, maps and tags entered.
+    depth: u32,
+}
+
+impl<'de> Deserializer<'de> {
+    fn take(&mut self, len: usize) -> Result<&'de [u8]> {
+        if len > self.input.len() {
+            return Err(CodecError::UnexpectedEnd);
+        }
+        let (head, rest) = self.input.split_at(len);
+        self.input = rest;
+        Ok(head)
+    }
+
+    fn array<const N: usize>(&mut self) -> Result<[u8; N]> {
+        let mut out = [0; N];
+        out.copy_from_slice(self.take(N)?);
+        Ok(out)
+    }
+
+    fn peek(&self) -> Result<u8> {
+        self.input.first().copied().ok_or(CodecError::UnexpectedEnd)
+    }
+
+    /// The next head: major type, additional info and argument (`None` for
+    /// an indefinite length or a break).
+    fn head(&mut self) -> Result<(u8, u8, Option<u64>)> {
+        let [initial] = self.array()?;
+        let info = initial & 0x1f;
+        let arg = match info {
+            0..=23 => Some(info.into()),
+            24 => Some(u8::from_be_bytes(self.array()?).into()),
+            25 => Some(u16::from_be_bytes(self.array()?).into()),
+            26 => Some(u32::from_be_bytes(self.array()?).into()),
+            27 => Some(u64::from_be_bytes(self.array()?)),
+            31 => None,
+            _ => return Err(CodecError::Invalid("codec: reserved CBOR head")),
+        };
+        Ok((initial >> 5, info, arg))
+    }
+
+    /// The content of a byte or text string.
+    fn string(&mut self, arg: Option<u64>) -> Result<&'de [u8]> {
+        let len =
+            length(arg)?.ok_or(CodecError::Unsupported("codec: indefinite-length strings"))?;
+        self.take(len)
+    }
+
+    fn nested<T>(&mut self, f: impl FnOnce(&mut Self) -> Result<T>) -> Result<T> {
+        if self.depth >= MAX_DEPTH {
+            return Err(CodecError::Invalid("codec: nested too deep"));
+        }
+        self.depth += 1;
+        let result = f(self);
+        self.depth -= 1;
+        result
+    }
+}
+
+impl<'de> de::Deserializer<'de> for &mut Deserializer<'de> {
+    type Error = CodecError;
+
+    fn is_human_readable(&self) -> bool {
+        false
+    }
+
+    fn deserialize_any<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value> {
+        let (major, info, arg) = self.head()?;
+        match major {
+            MAJOR_UNSIGNED => visitor.visit_u64(definite(arg)?),
+            MAJOR_NEGATIVE => {
+                let arg = definite(arg)?;
+                match i64::try_from(arg) {
+                    Ok(arg) => visitor.visit_i64(!arg),
+                    Err(_) => visitor.visit_i128(!i128::from(arg)),
+                }
+            }
+            MAJOR_BYTES => visitor.visit_borrowed_bytes(self.string(arg)?),
+            MAJOR_TEXT => visitor.visit_borrowed_str(
+                core::str::from_utf8(self.string(arg)?)
+                    .map_err(|_| CodecError::Invalid("codec: invalid UTF-8"))?,
+            ),
+            MAJOR_ARRAY => {
+                let left = length(arg)?;
+                self.nested(|de| {
+                    let mut items = Items { de, left };
+                    let value = visitor.visit_seq(&mut items)?;
+                    items.end().map(|()| value)
+                })
+            }
+            MAJOR_MAP => {
+                let left = length(arg)?;
+                self.nested(|de| {
+                    let mut items = Items { de, left };
+                    let value = visitor.visit_map(&mut items)?;
+                    items.end().map(|()| value)
+                })
+            }
+            MAJOR_TAG => {
+                definite(arg)?;
+                self.nested(|de| de.deserialize_any(visitor))
+            }
+            _ => match (info, arg) {
+                (20, _) => visitor.visit_bool(false),
+                (21, _) => visitor.visit_bool(true),
+                (22 | 23, _) => visitor.visit_unit(),
+                (25, Some(bits)) => visitor.visit_f32(half(bits as u16)),
+                (26, Some(bits)) => visitor.visit_f32(f32::from_bits(bits as u32)),
+                (27, Some(bits)) => visitor.visit_f64(f64::from_bits(bits)),
+                (31, _) => Err(CodecError::Invalid("codec: unexpected break")),
+                _ => Err(CodecError::Unsupported("codec: CBOR simple value")),
+            },
+        }
+    }
+
+    fn deserialize_option<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value> {
+        match self.peek()? {
+            NULL | UNDEFINED => {
+                self.take(1)?;
+                visitor.visit_none()
+            }
+            _ => visitor.visit_some(self),
+        }
+    }
+
+    fn deserialize_newtype_struct<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        visitor: V,
+    ) -> Result<V::Value> {
+        visitor.visit_newtype_struct(self)
+    }
+
+    fn deserialize_enum<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        _variants: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value> {
+        match self.peek()? >> 5 {
+            MAJOR_TEXT => visitor.visit_enum(Variant {
+                de: self,
+                content: false,
+            }),
+            MAJOR_MAP => {
+                if self.head()?.2 != Some(1) {
+                    return Err(CodecError::Invalid("codec: variant map needs one entry"));
+                }
+                self.nested(|de| visitor.visit_enum(Variant { de, content: true }))
+            }
+            _ => Err(CodecError::Invalid("codec: expected an enum variant")),
+        }
+    }
+
+    serde::forward_to_deserialize_any! {
+        bool i8 i16 i32 i64 i128 u8 u16 u32 u64 u128 f32 f64 char str string
+        bytes byte_buf unit unit_struct seq tuple tuple_struct map struct
+        identifier ignored_any
+    }
+}
+
+/// The items of an array or map; `left` is `None` until the break of an
+/// indefinite one.
+struct Items<'a, 'de> {
+    de: &'a mut Deserializer<'de>,
+    left: Option<usize>,
+}
+
+impl Items<'_, '_> {
+    fn next(&mut self) -> Result<bool> {
+        match &mut self.left {
+            Some(0) => Ok(false),
+            Some(left) => {
+                *left -= 1;
+                Ok(true)
+            }
+            None if self.de.peek()? == BREAK => {
+                self.de.take(1)?;
+                self.left = Some(0);
+                Ok(false)
+            }
+            None => Ok(true),
+        }
+    }
+
+    /// Fails when the visitor stopped before the last item.
+    fn end(&mut self) -> Result<()> {
+        match self.next()? {
+            true => Err(CodecError::Invalid("codec: too many items")),
+            false => Ok(()),
+        }
+    }
+}
+
+impl<'de> de::SeqAccess<'de> for Items<'_, 'de> {
+    type Error = CodecError;
+
+    fn next_element_seed<T: DeserializeSeed<'de>>(&mut self, seed: T) -> Result<Option<T::Value>> {
+        if !self.next()? {
+            return Ok(None);
+        }
+        seed.deserialize(&mut *self.de).map(Some)
+    }
+
+    fn size_hint(&self) -> Option<usize> {
+        // A forged length must not make the caller preallocate it.
+        self.left.map(|left| left.min(self.de.input.len()))
+    }
+}
+
+impl<'de> de::MapAccess<'de> for Items<'_, 'de> {
+    type Error = CodecError;
+
+    fn next_key_seed<K: DeserializeSeed<'de>>(&mut self, seed: K) -> Result<Option<K::Value>> {
+        de::SeqAccess::next_element_seed(self, seed)
+    }
+
+    fn next_value_seed<V: DeserializeSeed<'de>>(&mut self, seed: V) -> Result<V::Value> {
+        seed.deserialize(&mut *self.de)
+    }
+
+    fn size_hint(&self) -> Option<usize> {
+        de::SeqAccess::size_hint(self)
+    }
+}
+
+/// An enum variant: its name, then (`content`) the map entry's value.
+struct Variant<'a, 'de> {
+    de: &'a mut Deserializer<'de>,
+    content: bool,
+}
+
+impl<'a, 'de> de::EnumAccess<'de> for Variant<'a, 'de> {
+    type Error = CodecError;
+    type Variant = Self;
+
+    fn variant_seed<V: DeserializeSeed<'de>>(self, seed: V) -> Result<(V::Value, Self)> {
+        let value = seed.deserialize(&mut *self.de)?;
+        Ok((value, self))
+    }
+}
+
+impl<'de> de::VariantAccess<'de> for Variant<'_, 'de> {
+    type Error = CodecError;
+
+    fn unit_variant(self) -> Result<()> {
+        if self.content {
+            de::Deserialize::deserialize(self.de)
+        } else {
+            Ok(())
+        }
+    }
+
+    fn newtype_variant_seed<T: DeserializeSeed<'de>>(self, seed: T) -> Result<T::Value> {
+        if !self.content {
+            return Err(CodecError::Invalid("codec: variant has no content"));
+        }
+        seed.deserialize(self.de)
+    }
+
+    fn tuple_variant<V: Visitor<'de>>(self, _len: usize, visitor: V) -> Result<V::Value> {
+        if !self.content {
+            return Err(CodecError::Invalid("codec: variant has no content"));
+        }
+        de::Deserializer::deserialize_any(self.de, visitor)
+    }
+
+    fn struct_variant<V: Visitor<'de>>(
+        self,
+        _fields: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value> {
+        self.tuple_variant(0, visitor)
+    }
+}
+
+#[cfg(all(test, feature = "std"))]
+mod tests {
+    use super::*;
+    use crate::codec::Format;
+    use alloc::string::String;
+    use alloc::vec;
+    use serde::de::IgnoredAny;
+    use serde::{Deserialize, Serialize};
+
+    #[derive(Debug, Serialize, Deserialize, PartialEq)]
+    enum Mode {
+        Idle,
+        Burst(u8),
+        Window { from: u32, to: u32 },
+    }
+
+    #[derive(Debug, Serialize, Deserialize, PartialEq)]
+    struct Reading {
+        id: u16,
+        temp: f32,
+        tags: Vec<i8>,
+        note: Option<String>,
+        mode: Mode,
+    }
+
+    #[test]
+    fn encodes_rfc8949_items_and_round_trips() {
+        let reading = Reading {
+            id: 500,
+            temp: 1.5,
+            tags: vec![-1, 24],
+            note: None,
+            mode: Mode::Burst(3),
+        };
+        let bytes = to_vec(&reading).unwrap();
+        let mut expected = vec![0xa5, 0x62, b'i', b'd', 0x19, 0x01, 0xf4];
+        expected.extend(b"\x64temp\xfa\x3f\xc0\x00\x00");
+        expected.extend(b"\x64tags\x82\x20\x18\x18");
+        expected.extend(b"\x64note\xf6");
+        expected.extend(b"\x64mode\xa1\x65Burst\x03");
+        assert_eq!(bytes, expected);
+        assert_eq!(from_bytes::<Reading>(&bytes), Ok(reading));
+        assert_eq!(Format::Cbor.to_vec(&Mode::Idle).unwrap(), b"\x64Idle");
+        let window = Mode::Window { from: 1, to: 2 };
+        let bytes = to_vec(&window).unwrap();
+        assert_eq!(bytes, b"\xa1\x66Window\xa2\x64from\x01\x62to\x02");
+        assert_eq!(Format::Cbor.from_bytes(&bytes), Ok(window));
+
+        let mut buf = [0u8; 8];
+        let used = to_slice(&format_args!("{}-{}", 4, "x"), &mut buf).unwrap();
+        assert_eq!(used, b"\x634-x");
+        assert_eq!(to_slice(&expected, &mut buf), Err(CodecError::BufferFull));
+        assert_eq!(
+            to_vec(&u128::MAX),
+            Err(CodecError::Unsupported("codec: integer wider than 64 bits"))
+        );
+    }
+
+    #[test]
+    fn decodes_what_other_encoders_emit_and_refuses_the_rest() {
+        // Indefinite arrays, tags (here: epoch time) and half floats.
+        assert_eq!(from_bytes::<Vec<u8>>(&[0x9f, 1, 2, 0xff]), Ok(vec![1, 2]));
+        assert_eq!(from_bytes::<u32>(&[0xc1, 0x1a, 0, 0, 0, 5]), Ok(5));
+        assert_eq!(from_bytes::<f32>(&[0xf9, 0x3e, 0x00]), Ok(1.5));
+        assert_eq!(
+            from_bytes::<f32>(&[0xf9, 0xfc, 0x00]),
+            Ok(f32::NEG_INFINITY)
+        );
+        assert_eq!(from_bytes::<f64>(&[0xf9, 0x00, 0x01]), Ok(2f64.powi(-24)));
+        assert_eq!(
+            from_bytes::<i128>(&[0x3b, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff]),
+            Ok(-(1i128 << 64))
+        );
+        // Fields in another order, and null for a missing option.
+        let bytes = b"\xa5\x64mode\x64Idle\x64note\xf7\x62id\x01\x64tags\x80\x64temp\xf9\x00\x00";
+        let reading = from_bytes::<Reading>(bytes).unwrap();
+        assert_eq!(
+            (reading.id, reading.note, reading.mode),
+            (1, None, Mode::Idle)
+        );
+
+        assert_eq!(
+            from_bytes::<u8>(&[0x19, 0x01]),
+            Err(CodecError::UnexpectedEnd)
+        );
+        assert_eq!(from_bytes::<u8>(&[1, 2]), Err(CodecError::TrailingBytes));
+        assert!(matches!(
+            from_bytes::<u8>(&[0x19, 0x01, 0x00]),
+            Err(CodecError::Custom)
+        ));
+        assert!(matches!(
+            from_bytes::<(u8,)>(&[0x82, 1, 2]),
+            Err(CodecError::Invalid("codec: too many items"))
+        ));
+        assert!(matches!(
+            from_bytes::<String>(&[0x7f, 0x61, b'a', 0xff]),
+            Err(CodecError::Unsupported(_))
+        ));
+        assert!(matches!(
+            from_bytes::<u8>(&[0x1c]),
+            Err(CodecError::Invalid(_))
+        ));
+        assert!(matches!(
+            from_bytes::<u8>(&[0xff]),
+            Err(CodecError::Invalid(_))
+        ));
+        // A forged length neither preallocates nor reads past the end.
+        assert_eq!(
+            from_bytes::<Vec<u8>>(&[0x9b, 0, 0, 0, 0, 0xff, 0xff, 0xff, 0xff]),
+            Err(CodecError::UnexpectedEnd)
+        );
+
+        let mut nested = vec![0x81; MAX_DEPTH as usize];
+        nested.push(0);
+        assert!(from_bytes::<IgnoredAny>(&nested).is_ok());
+        nested.insert(0, 0x81);
+        assert_eq!(
+            from_bytes::<IgnoredAny>(&nested),
+            Err(CodecError::Invalid("codec: nested too deep"))
+        );
+    }
+}
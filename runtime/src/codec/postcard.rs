@@ -0,0 +1,738 @@
+//! The postcard (1.x) wire format for serde types.
+//!
+//! Compact and not self-describing: integers wider than a byte are LEB128
+//! varints (signed ones zigzagged first), floats are little endian, strings,
+//! byte strings, sequences and maps carry a varint length, `Option` a 0/1
+//! tag, enum variants their varint index, and structs and tuples are their
+//! fields in order. Decoding therefore needs the type (`deserialize_any` is
+//! refused), and rejects overlong varints and trailing bytes.
+
+use super::{write_display, CodecError, Sink, SliceSink};
+#[cfg(feature = "alloc")]
+use alloc::vec::Vec;
+use core::fmt;
+use serde::de::{self, DeserializeSeed, IntoDeserializer, Visitor};
+use serde::ser::{self, Serialize};
+
+type Result<T> = core::result::Result<T, CodecError>;
+
+/// Encodes `value` into `buf`; returns the used prefix.
+pub fn to_slice<'b, T: Serialize + ?Sized>(value: &T, buf: &'b mut [u8]) -> Result<&'b mut [u8]> {
+    let mut serializer = Serializer {
+        out: SliceSink { buf, len: 0 },
+    };
+    value.serialize(&mut serializer)?;
+    let SliceSink { buf, len } = serializer.out;
+    Ok(&mut buf[..len])
+}
+
+/// Encodes `value` into a new buffer.
+#[cfg(feature = "alloc")]
+pub fn to_vec<T: Serialize + ?Sized>(value: &T) -> Result<Vec<u8>> {
+    let mut serializer = Serializer { out: Vec::new() };
+    value.serialize(&mut serializer)?;
+    Ok(serializer.out)
+}
+
+/// Decodes a `T` that spans all of `bytes`.
+pub fn from_bytes<'de, T: de::Deserialize<'de>>(bytes: &'de [u8]) -> Result<T> {
+    let mut deserializer = Deserializer { input: bytes };
+    let value = T::deserialize(&mut deserializer)?;
+    if !deserializer.input.is_empty() {
+        return Err(CodecError::TrailingBytes);
+    }
+    Ok(value)
+}
+
+fn zigzag(value: i128) -> u128 {
+    ((value << 1) ^ (value >> 127)) as u128
+}
+
+fn unzigzag(value: u128) -> i128 {
+    (value >> 1) as i128 ^ -((value & 1) as i128)
+}
+
+struct Serializer<S> {
+    out: S,
+}
+
+fn varint(out: &mut impl Sink, mut value: u128) -> Result<()> {
+    let mut buf = [0u8; 19];
+    let mut len = 0;
+    loop {
+        let byte = (value & 0x7f) as u8;
+        value >>= 7;
+        if value == 0 {
+            buf[len] = byte;
+            return out.put(&buf[..=len]);
+        }
+        buf[len] = byte | 0x80;
+        len += 1;
+    }
+}
+
+impl<S: Sink> Serializer<S> {
+    fn varint(&mut self, value: u128) -> Result<()> {
+        varint(&mut self.out, value)
+    }
+
+    fn len(&mut self, len: Option<usize>) -> Result<()> {
+        let len = len.ok_or(CodecError::Unsupported("codec: length not known up front"))?;
+        self.varint(len as u128)
+    }
+}
+
+impl<S: Sink> ser::Serializer for &mut Serializer<S> {
+    type Ok = ();
+    type Error = CodecError;
+    type SerializeSeq = Self;
+    type SerializeTuple = Self;
+    type SerializeTupleStruct = Self;
+    type SerializeTupleVariant = Self;
+    type SerializeMap = Self;
+    type SerializeStruct = Self;
+    type SerializeStructVariant = Self;
+
+    fn is_human_readable(&self) -> bool {
+        false
+    }
+
+    fn serialize_bool(self, v: bool) -> Result<()> {
+        self.out.put(&[v as u8])
+    }
+
+    fn serialize_i8(self, v: i8) -> Result<()> {
+        self.out.put(&v.to_le_bytes())
+    }
+
+    fn serialize_i16(self, v: i16) -> Result<()> {
+        self.varint(zigzag(v.into()))
+    }
+
+    fn serialize_i32(self, v: i32) -> Result<()> {
+        self.varint(zigzag(v.into()))
+    }
+
+    fn serialize_i64(self, v: i64) -> Result<()> {
+        self.varint(zigzag(v.into()))
+    }
+
+    fn serialize_i128(self, v: i128) -> Result<()> {
+        self.varint(zigzag(v))
+    }
+
+    fn serialize_u8(self, v: u8) -> Result<()> {
+        self.out.put(&[v])
+    }
+
+    fn serialize_u16(self, v: u16) -> Result<()> {
+        self.varint(v.into())
+    }
+
+    fn serialize_u32(self, v: u32) -> Result<()> {
+        self.varint(v.into())
+    }
+
+    fn serialize_u64(self, v: u64) -> Result<()> {
+        self.varint(v.into())
+    }
+
+    fn serialize_u128(self, v: u128) -> Result<()> {
+        self.varint(v)
+    }
+
+    fn serialize_f32(self, v: f32) -> Result<()> {
+        self.out.put(&v.to_le_bytes())
+    }
+
+    fn serialize_f64(self, v: f64) -> Result<()> {
+        self.out.put(&v.to_le_bytes())
+    }
+
+    fn serialize_char(self, v: char) -> Result<()> {
+        self.serialize_str(v.encode_utf8(&mut [0; 4]))
+    }
+
+    fn serialize_str(self, v: &str) -> Result<()> {
+        self.serialize_bytes(v.as_bytes())
+    }
+
+    fn serialize_bytes(self, v: &[u8]) -> Result<()> {
+        self.varint(v.len() as u128)?;
+        self.out.put(v)
+    }
+
+    fn serialize_none(self) -> Result<()> {
+        self.out.put(&[0])
+    }
+
+    fn serialize_some<T: Serialize + ?Sized>(self, value: &T) -> Result<()> {
+        self.out.put(&[1])?;
+        value.serialize(self)
+    }
+
+    fn serialize_unit(self) -> Result<()> {
+        Ok(())
+    }
+
+    fn serialize_unit_struct(self, _name: &'static str) -> Result<()> {
+        Ok(())
+    }
+
+    fn serialize_unit_variant(
+        self,
+        _name: &'static str,
+        index: u32,
+        _: &'static str,
+    ) -> Result<()> {
+        self.varint(index.into())
+    }
+
+    fn serialize_newtype_struct<T: Serialize + ?Sized>(
+        self,
+        _name: &'static str,
+        value: &T,
+    ) -> Result<()> {
+        value.serialize(self)
+    }
+
+    fn serialize_newtype_variant<T: Serialize + ?Sized>(
+        self,
+        _name: &'static str,
+        index: u32,
+        _variant: &'static str,
+        value: &T,
+    ) -> Result<()> {
+        self.varint(index.into())?;
+        value.serialize(self)
+    }
+
+    fn serialize_seq(self, len: Option<usize>) -> Result<Self> {
+        self.len(len)?;
+        Ok(self)
+    }
+
+    fn serialize_tuple(self, _len: usize) -> Result<Self> {
+        Ok(self)
+    }
+
+    fn serialize_tuple_struct(self, _name: &'static str, _len: usize) -> Result<Self> {
+        Ok(self)
+    }
+
+    fn serialize_tuple_variant(
+        self,
+        _name: &'static str,
+        index: u32,
+        _variant: &'static str,
+        _len: usize,
+    ) -> Result<Self> {
+        self.varint(index.into())?;
+        Ok(self)
+    }
+
+    fn serialize_map(self, len: Option<usize>) -> Result<Self> {
+        self.len(len)?;
+        Ok(self)
+    }
+
+    fn serialize_struct(self, _name: &'static str, _len: usize) -> Result<Self> {
+        Ok(self)
+    }
+
+    fn serialize_struct_variant(
+        self,
+        _name: &'static str,
+        index: u32,
+        _variant: &'static str,
+        _len: usize,
+    ) -> Result<Self> {
+        self.varint(index.into())?;
+        Ok(self)
+    }
+
+    fn collect_str<T: fmt::Display + ?Sized>(self, value: &T) -> Result<()> {
+        write_display(&mut self.out, value, |out, len| varint(out, len as u128))
+    }
+}
+
+macro_rules! compound {
+    ($($trait:ident :: $method:ident),*) => {$(
+        impl<S: Sink> ser::$trait for &mut Serializer<S> {
+            type Ok = ();
+            type Error = CodecError;
+
+            fn $method<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<()> {
+                value.serialize(&mut **self)
+            }
+
+            fn end(self) -> Result<()> {
+                Ok(())
+            }
+        }
+    )*};
+}
+
+compound!(
+    SerializeSeq::serialize_element,
+    SerializeTuple::serialize_element,
+    SerializeTupleStruct::serialize_field,
+    SerializeTupleVariant::serialize_field
+);
+
+impl<S: Sink> ser::SerializeMap for &mut Serializer<S> {
+    type Ok = ();
+    type Error = CodecError;
+
+    fn serialize_key<T: Serialize + ?Sized>(&mut self, key: &T) -> Result<()> {
+        key.serialize(&mut **self)
+    }
+
+    fn serialize_value<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<()> {
+        value.serialize(&mut **self)
+    }
+
+    fn end(self) -> Result<()> {
+        Ok(())
+    }
+}
+
+impl<S: Sink> ser::SerializeStruct for &mut Serializer<S> {
+    type Ok = ();
+    type Error = CodecError;
+
+    fn serialize_field<T: Serialize + ?Sized>(
+        &mut self,
+        _key: &'static str,
+        value: &T,
+    ) -> Result<()> {
+        value.serialize(&mut **self)
+    }
+
+    fn end(self) -> Result<()> {
+        Ok(())
+    }
+}
+
+impl<S: Sink> ser::SerializeStructVariant for &mut Serializer<S> {
+    type Ok = ();
+    type Error = CodecError;
+
+    fn serialize_field<T: Serialize + ?Sized>(
+        &mut self,
+        _key: &'static str,
+        value: &T,
+    ) -> Result<()> {
+        value.serialize(&mut **self)
+    }
+
+    fn end(self) -> Result<()> {
+        Ok(())
+    }
+}
+
+struct Deserializer<'de> {
+    input: &'de [u8],
+}
+
+impl<'de> Deserializer<'de> {
+    fn take(&mut self, len: usize) -> Result<&'de [u8]> {
+        if len > self.input.len() {
+            return Err(CodecError::UnexpectedEnd);
+        }
+        let (head, rest) = self.input.split_at(len);
+        self.input = rest;
+        Ok(head)
+    }
+
+    fn array<const N: usize>(&mut self) -> Result<[u8; N]> {
+        let mut out = [0; N];
+        out.copy_from_slice(self.take(N)?);
+        Ok(out)
+    }
+
+    /// A varint of at most `bits` bits, in its shortest form.
+    fn varint(&mut self, bits: u32) -> Result<u128> {
+        let mut value = 0u128;
+        for i in 0..bits.div_ceil(7) {
+            let [byte] = self.array()?;
+            let chunk = u128::from(byte & 0x7f);
+            let shift = i * 7;
+            if shift + (8 - chunk.leading_zeros().saturating_sub(120)) > bits {
+                return Err(CodecError::Invalid("codec: integer out of range"));
+            }
+            value |= chunk << shift;
+            if byte & 0x80 == 0 {
+                if byte == 0 && i > 0 {
+                    return Err(CodecError::Invalid("codec: overlong integer"));
+                }
+                return Ok(value);
+            }
+        }
+        Err(CodecError::Invalid("codec: integer out of range"))
+    }
+
+    fn signed(&mut self, bits: u32) -> Result<i128> {
+        self.varint(bits).map(unzigzag)
+    }
+
+    fn len(&mut self) -> Result<usize> {
+        usize::try_from(self.varint(64)?)
+            .map_err(|_| CodecError::Invalid("codec: length too large"))
+    }
+
+    fn bytes(&mut self) -> Result<&'de [u8]> {
+        let len = self.len()?;
+        self.take(len)
+    }
+
+    fn str(&mut self) -> Result<&'de str> {
+        core::str::from_utf8(self.bytes()?).map_err(|_| CodecError::Invalid("codec: invalid UTF-8"))
+    }
+}
+
+/// Narrows a decoded integer; the varint width already bounds it.
+fn narrow<T: TryFrom<V>, V>(value: V) -> Result<T> {
+    T::try_from(value).map_err(|_| CodecError::Invalid("codec: integer out of range"))
+}
+
+impl<'de> de::Deserializer<'de> for &mut Deserializer<'de> {
+    type Error = CodecError;
+
+    fn is_human_readable(&self) -> bool {
+        false
+    }
+
+    fn deserialize_any<V: Visitor<'de>>(self, _visitor: V) -> Result<V::Value> {
+        Err(CodecError::Unsupported(
+            "codec: format is not self-describing",
+        ))
+    }
+
+    fn deserialize_bool<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value> {
+        match self.array()? {
+            [0] => visitor.visit_bool(false),
+            [1] => visitor.visit_bool(true),
+            _ => Err(CodecError::Invalid("codec: invalid bool")),
+        }
+    }
+
+    fn deserialize_i8<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value> {
+        visitor.visit_i8(i8::from_le_bytes(self.array()?))
+    }
+
+    fn deserialize_i16<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value> {
+        visitor.visit_i16(narrow(self.signed(16)?)?)
+    }
+
+    fn deserialize_i32<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value> {
+        visitor.visit_i32(narrow(self.signed(32)?)?)
+    }
+
+    fn deserialize_i64<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value> {
+        visitor.visit_i64(narrow(self.signed(64)?)?)
+    }
+
+    fn deserialize_i128<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value> {
+        visitor.visit_i128(self.signed(128)?)
+    }
+
+    fn deserialize_u8<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value> {
+        let [byte] = self.array()?;
+        visitor.visit_u8(byte)
+    }
+
+    fn deserialize_u16<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value> {
+        visitor.visit_u16(narrow(self.varint(16)?)?)
+    }
+
+    fn deserialize_u32<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value> {
+        visitor.visit_u32(narrow(self.varint(32)?)?)
+    }
+
+    fn deserialize_u64<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value> {
+        visitor.visit_u64(narrow(self.varint(64)?)?)
+    }
+
+    fn deserialize_u128<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value> {
+        visitor.visit_u128(self.varint(128)?)
+    }
+
+    fn deserialize_f32<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value> {
+        visitor.visit_f32(f32::from_le_bytes(self.array()?))
+    }
+
+    fn deserialize_f64<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value> {
+        visitor.visit_f64(f64::from_le_bytes(self.array()?))
+    }
+
+    fn deserialize_char<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value> {
+        let mut chars = self.str()?.chars();
+        match (chars.next(), chars.next()) {
+            (Some(c), None) => visitor.visit_char(c),
+            _ => Err(CodecError::Invalid("codec: invalid char")),
+        }
+    }
+
+    fn deserialize_str<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value> {
+        visitor.visit_borrowed_str(self.str()?)
+    }
+
+    fn deserialize_string<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value> {
+        self.deserialize_str(visitor)
+    }
+
+    fn deserialize_bytes<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value> {
+        visitor.visit_borrowed_bytes(self.bytes()?)
+    }
+
+    fn deserialize_byte_buf<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value> {
+        self.deserialize_bytes(visitor)
+    }
+
+    fn deserialize_option<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value> {
+        match self.array()? {
+            [0] => visitor.visit_none(),
+            [1] => visitor.visit_some(self),
+            _ => Err(CodecError::Invalid("codec: invalid option tag")),
+        }
+    }
+
+    fn deserialize_unit<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value> {
+        visitor.visit_unit()
+    }
+
+    fn deserialize_unit_struct<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        visitor: V,
+    ) -> Result<V::Value> {
+        visitor.visit_unit()
+    }
+
+    fn deserialize_newtype_struct<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        visitor: V,
+    ) -> Result<V::Value> {
+        visitor.visit_newtype_struct(self)
+    }
+
+    fn deserialize_seq<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value> {
+        let len = self.len()?;
+        visitor.visit_seq(Fields { de: self, len })
+    }
+
+    fn deserialize_tuple<V: Visitor<'de>>(self, len: usize, visitor: V) -> Result<V::Value> {
+        visitor.visit_seq(Fields { de: self, len })
+    }
+
+    fn deserialize_tuple_struct<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        len: usize,
+        visitor: V,
+    ) -> Result<V::Value> {
+        self.deserialize_tuple(len, visitor)
+    }
+
+    fn deserialize_map<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value> {
+        let len = self.len()?;
+        visitor.visit_map(Fields { de: self, len })
+    }
+
+    fn deserialize_struct<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        fields: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value> {
+        self.deserialize_tuple(fields.len(), visitor)
+    }
+
+    fn deserialize_enum<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        _variants: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value> {
+        visitor.visit_enum(self)
+    }
+
+    fn deserialize_identifier<V: Visitor<'de>>(self, _visitor: V) -> Result<V::Value> {
+        Err(CodecError::Unsupported(
+            "codec: identifiers are not encoded",
+        ))
+    }
+
+    fn deserialize_ignored_any<V: Visitor<'de>>(self, _visitor: V) -> Result<V::Value> {
+        Err(CodecError::Unsupported(
+            "codec: format is not self-describing",
+        ))
+    }
+}
+
+/// The next `len` elements, map entries or fields.
+struct Fields<'a, 'de> {
+    de: &'a mut Deserializer<'de>,
+    len: usize,
+}
+
+impl<'de> de::SeqAccess<'de> for Fields<'_, 'de> {
+    type Error = CodecError;
+
+    fn next_element_seed<T: DeserializeSeed<'de>>(&mut self, seed: T) -> Result<Option<T::Value>> {
+        if self.len == 0 {
+            return Ok(None);
+        }
+        self.len -= 1;
+        seed.deserialize(&mut *self.de).map(Some)
+    }
+
+    fn size_hint(&self) -> Option<usize> {
+        // A forged length must not make the caller preallocate it.
+        Some(self.len.min(self.de.input.len()))
+    }
+}
+
+impl<'de> de::MapAccess<'de> for Fields<'_, 'de> {
+    type Error = CodecError;
+
+    fn next_key_seed<K: DeserializeSeed<'de>>(&mut self, seed: K) -> Result<Option<K::Value>> {
+        de::SeqAccess::next_element_seed(self, seed)
+    }
+
+    fn next_value_seed<V: DeserializeSeed<'de>>(&mut self, seed: V) -> Result<V::Value> {
+        seed.deserialize(&mut *self.de)
+    }
+
+    fn size_hint(&self) -> Option<usize> {
+        Some(self.len.min(self.de.input.len()))
+    }
+}
+
+impl<'de> de::EnumAccess<'de> for &mut Deserializer<'de> {
+    type Error = CodecError;
+    type Variant = Self;
+
+    fn variant_seed<V: DeserializeSeed<'de>>(self, seed: V) -> Result<(V::Value, Self)> {
+        let index: u32 = narrow(self.varint(32)?)?;
+        let value = seed.deserialize(index.into_deserializer())?;
+        Ok((value, self))
+    }
+}
+
+impl<'de> de::VariantAccess<'de> for &mut Deserializer<'de> {
+    type Error = CodecError;
+
+    fn unit_variant(self) -> Result<()> {
+        Ok(())
+    }
+
+    fn newtype_variant_seed<T: DeserializeSeed<'de>>(self, seed: T) -> Result<T::Value> {
+        seed.deserialize(self)
+    }
+
+    fn tuple_variant<V: Visitor<'de>>(self, len: usize, visitor: V) -> Result<V::Value> {
+        de::Deserializer::deserialize_tuple(self, len, visitor)
+    }
+
+    fn struct_variant<V: Visitor<'de>>(
+        self,
+        fields: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value> {
+        de::Deserializer::deserialize_tuple(self, fields.len(), visitor)
+    }
+}
+
+#[cfg(all(test, feature = "std"))]
+mod tests {
+    use super::*;
+    use alloc::string::String;
+    use alloc::vec;
+    use serde::{Deserialize, Serialize};
+
+    #[derive(Debug, Serialize, Deserialize, PartialEq)]
+    enum Command {
+        Stop,
+        Blink(u8),
+        Set { key: String, value: Option<i64> },
+    }
+
+    #[derive(Debug, Serialize, Deserialize, PartialEq)]
+    struct Frame<'a> {
+        seq: u32,
+        delta: i16,
+        ratio: f32,
+        tag: char,
+        #[serde(borrow)]
+        raw: &'a [u8],
+        flags: (bool, u64),
+        commands: Vec<Command>,
+    }
+
+    #[test]
+    fn encodes_postcard_bytes_and_round_trips() {
+        let frame = Frame {
+            seq: 300,
+            delta: -2,
+            ratio: 1.5,
+            tag: 'é',
+            raw: b"ab",
+            flags: (true, u64::MAX),
+            commands: vec![
+                Command::Stop,
+                Command::Blink(7),
+                Command::Set {
+                    key: "k".into(),
+                    value: Some(-1),
+                },
+            ],
+        };
+        let bytes = to_vec(&frame).unwrap();
+        let mut expected = vec![0xac, 0x02, 0x03];
+        expected.extend(1.5f32.to_le_bytes());
+        expected.extend([2, 0xc3, 0xa9, 2, b'a', b'b', 1]);
+        expected.extend([0xff; 9]);
+        expected.extend([0x01, 3, 0, 1, 7, 2, 1, b'k', 1, 1]);
+        assert_eq!(bytes, expected);
+        assert_eq!(from_bytes::<Frame>(&bytes).as_ref(), Ok(&frame));
+
+        // `collect_str` writes the same bytes as the string it formats.
+        let mut buf = [0u8; 8];
+        let used = to_slice(&format_args!("{}-{}", 4, "x"), &mut buf).unwrap();
+        assert_eq!(used, [3, b'4', b'-', b'x']);
+        assert_eq!(from_bytes::<String>(used).unwrap(), "4-x");
+
+        assert_eq!(to_slice(&frame, &mut buf), Err(CodecError::BufferFull));
+        assert_eq!(from_bytes::<u32>(&[0x80]), Err(CodecError::UnexpectedEnd));
+        assert_eq!(from_bytes::<u8>(&[1, 2]), Err(CodecError::TrailingBytes));
+        assert!(matches!(
+            from_bytes::<u16>(&[0xff, 0xff, 0x07]),
+            Err(CodecError::Invalid(_))
+        ));
+        assert!(matches!(
+            from_bytes::<u32>(&[0x81, 0x00]),
+            Err(CodecError::Invalid(_))
+        ));
+        assert!(matches!(
+            from_bytes::<bool>(&[2]),
+            Err(CodecError::Invalid(_))
+        ));
+        assert!(matches!(
+            from_bytes::<Command>(&[9]),
+            Err(CodecError::Custom)
+        ));
+        assert_eq!(from_bytes::<u16>(&[0xff, 0xff, 0x03]), Ok(u16::MAX));
+        assert_eq!(
+            from_bytes::<i32>(&[0xff, 0xff, 0xff, 0xff, 0x0f]),
+            Ok(i32::MIN)
+        );
+        // A forged length neither preallocates nor reads past the end.
+        assert_eq!(
+            from_bytes::<Vec<u8>>(&[0xff, 0xff, 0xff, 0xff, 0x0f]),
+            Err(CodecError::UnexpectedEnd)
+        );
+    }
+}
@@ -0,0 +1,392 @@
+//! Differential conformance across the wasm backends.
+//!
+//! Every enabled engine (wasm3, wasmtime-lite, wasmi, tinywasm) runs the same
+//! corpus of small modules through `diff::run` and is compared with the first
+//! one: entry statuses, trap and load-failure kinds (`Error` variants) and the
+//! full host-call transcripts must match. A backend that disagrees fails the
+//! test with the case name and the first divergence. Each case also states
+//! the outcome it expects, so a single enabled engine is still checked.
+
+use crate::abi::Imports;
+use crate::diff::{self, Outcome, Probe, Run};
+use crate::{Engine, Error};
+use std::boxed::Box;
+use std::string::String;
+use std::vec::Vec;
+
+/// Message ABI imports shared by the corpus.
+const IMPORTS: &str = r#"
+    (import "env" "msg_input" (func $input (param i32 i32) (result i32)))
+    (import "env" "msg_output" (func $output (param i32 i32) (result i32)))
+    (memory (export "memory") 1 2)"#;
+
+/// One entry of one module, run once per input.
+struct Case {
+    name: &'static str,
+    /// Module body after `IMPORTS`, or raw bytes when `raw` is set.
+    body: &'static str,
+    raw: Option<&'static [u8]>,
+    entry: &'static str,
+    inputs: &'static [&'static [u8]],
+    /// What every engine must agree on, so the reference is checked too.
+    expect: Expect,
+}
+
+#[derive(Clone, Copy)]
+enum Expect {
+    Status(i32),
+    /// Completes with a status that depends on the input.
+    Completes,
+    Trap,
+    Error(Error),
+    LoadError,
+}
+
+const NO_INPUT: &[&[u8]] = &[b""];
+const TEXT: &[&[u8]] = &[b"", b"a", b"slimmy", &[0xff; 64], &[7; 200]];
+
+const ARITH: &str = r#"
+    (func (export "main") (result i32)
+        (i32.xor
+            (i32.xor
+                (i32.mul (i32.const 0x7fffffff) (i32.const 3))
+                (i32.rotl (i32.const 0x80000001) (i32.const 5)))
+            (i32.add
+                (i32.add (i32.div_s (i32.const -7) (i32.const 2)) (i32.rem_s (i32.const -7) (i32.const 2)))
+                (i32.add (i32.clz (i32.const 1)) (i32.shr_s (i32.const -256) (i32.const 4))))))
+    (func (export "wide") (result i32)
+        (i32.wrap_i64
+            (i64.xor
+                (i64.mul (i64.const 0x123456789abcdef) (i64.const 31))
+                (i64.shr_u (i64.const -1) (i64.const 40)))))
+    (func (export "none"))"#;
+
+const ECHO: &str = r#"
+    (func (export "main") (result i32)
+        (call $output (i32.const 0) (call $input (i32.const 0) (i32.const 128))))"#;
+
+const REVERSE: &str = r#"
+    (func (export "main") (result i32) (local $len i32) (local $i i32) (local $t i32)
+        (local.set $len (call $input (i32.const 0) (i32.const 4096)))
+        (if (i32.gt_u (local.get $len) (i32.const 4096)) (then (return (i32.const -1))))
+        (block $done
+            (loop $next
+                (br_if $done (i32.ge_s (local.get $i) (i32.div_u (local.get $len) (i32.const 2))))
+                (local.set $t (i32.load8_u (local.get $i)))
+                (i32.store8 (local.get $i)
+                    (i32.load8_u (i32.sub (i32.sub (local.get $len) (i32.const 1)) (local.get $i))))
+                (i32.store8 (i32.sub (i32.sub (local.get $len) (i32.const 1)) (local.get $i)) (local.get $t))
+                (local.set $i (i32.add (local.get $i) (i32.const 1)))
+                (br $next)))
+        (drop (call $output (i32.const 0) (local.get $len)))
+        (local.get $len))"#;
+
+const CHECKSUM: &str = r#"
+    (func (export "main") (result i32) (local $len i32) (local $i i32) (local $h i64)
+        (local.set $len (call $input (i32.const 0) (i32.const 4096)))
+        (local.set $h (i64.const 0xcbf29ce484222325))
+        (block $done
+            (loop $next
+                (br_if $done (i32.ge_u (local.get $i) (local.get $len)))
+                (local.set $h (i64.mul
+                    (i64.xor (local.get $h) (i64.load8_u (local.get $i)))
+                    (i64.const 0x100000001b3)))
+                (local.set $i (i32.add (local.get $i) (i32.const 1)))
+                (br $next)))
+        (i64.store (i32.const 8192) (local.get $h))
+        (drop (call $output (i32.const 8192) (i32.const 8)))
+        (i32.wrap_i64 (i64.shr_u (local.get $h) (i64.const 32))))"#;
+
+const FLOAT: &str = r#"
+    (func (export "main") (result i32)
+        (f64.store (i32.const 0) (f64.sqrt (f64.const 2)))
+        (f64.store (i32.const 8) (f64.nearest (f64.const 2.5)))
+        (f64.store (i32.const 16) (f64.convert_i64_u (i64.const -1)))
+        (f32.store (i32.const 24) (f32.demote_f64 (f64.const 0.1)))
+        (f32.store (i32.const 28) (f32.min (f32.const -0) (f32.const 0)))
+        (i64.store (i32.const 32) (i64.trunc_f64_s (f64.const -1e18)))
+        (drop (call $output (i32.const 0) (i32.const 40)))
+        (i32.add
+            (i32.trunc_sat_f64_s (f64.const 1e10))
+            (i32.trunc_sat_f32_u (f32.const -3.5))))"#;
+
+const BRANCH: &str = r#"
+    (func (export "main") (result i32)
+        (drop (call $input (i32.const 0) (i32.const 16)))
+        (block $d (block $c (block $b (block $a
+            (br_table $a $b $c $d (i32.load8_u (i32.const 0))))
+            (return (i32.const 10)))
+            (return (i32.const 20)))
+            (return (i32.const 30)))
+        (i32.const 40))"#;
+
+const MEMORY: &str = r#"
+    (global $calls (mut i32) (i32.const 0))
+    (func (export "main") (result i32)
+        (global.set $calls (i32.add (global.get $calls) (i32.const 1)))
+        (i32.add
+            (i32.add
+                (i32.mul (memory.grow (i32.const 1)) (i32.const 1000))
+                (i32.mul (memory.grow (i32.const 1)) (i32.const 100)))
+            (i32.add (i32.mul (memory.size) (i32.const 10)) (global.get $calls))))
+    (func (export "bounds") (result i32)
+        (i32.add
+            (call $output (i32.const 65530) (i32.const 16))
+            (call $output (i32.const 0) (i32.const -1))))"#;
+
+const TRAPS: &str = r#"
+    (type $unit (func))
+    (table 2 funcref)
+    (elem (i32.const 0) $unit_fn)
+    (func $unit_fn)
+    (func $deep (param i32) (result i32)
+        (i32.add (call $deep (i32.add (local.get 0) (i32.const 1))) (i32.const 1)))
+    (func (export "unreachable") unreachable)
+    (func (export "div_zero") (result i32) (i32.div_u (i32.const 1) (i32.const 0)))
+    (func (export "div_overflow") (result i32) (i32.div_s (i32.const 0x80000000) (i32.const -1)))
+    (func (export "oob_load") (result i32) (i32.load (i32.const 131072)))
+    (func (export "oob_store") (i64.store (i32.const 131068) (i64.const 1)))
+    (func (export "null_call") (call_indirect (type $unit) (i32.const 1)))
+    (func (export "bad_signature") (result i32)
+        (call_indirect (type $unit) (i32.const 0))
+        (call_indirect (param i32) (i32.const 0) (i32.const 0))
+        (i32.const 0))
+    (func (export "nan_trunc") (result i32) (i32.trunc_f32_s (f32.const nan)))
+    (func (export "stack") (result i32) (call $deep (i32.const 0)))
+    (func (export "after_output") (result i32)
+        (drop (call $output (i32.const 0) (call $input (i32.const 0) (i32.const 64))))
+        unreachable)"#;
+
+const CORPUS: &[Case] = &[
+    case("i32 arithmetic", ARITH, "main", NO_INPUT, Expect::Completes),
+    case("i64 arithmetic", ARITH, "wide", NO_INPUT, Expect::Completes),
+    case(
+        "entry without result",
+        ARITH,
+        "none",
+        NO_INPUT,
+        Expect::Status(0),
+    ),
+    case(
+        "missing entry",
+        ARITH,
+        "absent",
+        NO_INPUT,
+        Expect::Error(Error::EntryNotFound),
+    ),
+    case("echo", ECHO, "main", TEXT, Expect::Completes),
+    case(
+        "reverse in memory",
+        REVERSE,
+        "main",
+        TEXT,
+        Expect::Completes,
+    ),
+    case("fnv-1a checksum", CHECKSUM, "main", TEXT, Expect::Completes),
+    case("float rounding", FLOAT, "main", NO_INPUT, Expect::Completes),
+    case(
+        "br_table",
+        BRANCH,
+        "main",
+        &[b"", b"\x01", b"\x02", b"\x03", b"\xff"],
+        Expect::Completes,
+    ),
+    case(
+        "memory.grow past max",
+        MEMORY,
+        "main",
+        NO_INPUT,
+        Expect::Status(1000 - 100 + 20 + 1),
+    ),
+    case(
+        "host call out of bounds",
+        MEMORY,
+        "bounds",
+        NO_INPUT,
+        Expect::Completes,
+    ),
+    case("unreachable", TRAPS, "unreachable", NO_INPUT, Expect::Trap),
+    case("divide by zero", TRAPS, "div_zero", NO_INPUT, Expect::Trap),
+    case(
+        "division overflow",
+        TRAPS,
+        "div_overflow",
+        NO_INPUT,
+        Expect::Trap,
+    ),
+    case(
+        "load out of bounds",
+        TRAPS,
+        "oob_load",
+        NO_INPUT,
+        Expect::Trap,
+    ),
+    case(
+        "store straddling the end",
+        TRAPS,
+        "oob_store",
+        NO_INPUT,
+        Expect::Trap,
+    ),
+    case(
+        "indirect call to null",
+        TRAPS,
+        "null_call",
+        NO_INPUT,
+        Expect::Trap,
+    ),
+    case(
+        "indirect call signature mismatch",
+        TRAPS,
+        "bad_signature",
+        NO_INPUT,
+        Expect::Trap,
+    ),
+    case("NaN to integer", TRAPS, "nan_trunc", NO_INPUT, Expect::Trap),
+    case("stack exhaustion", TRAPS, "stack", NO_INPUT, Expect::Trap),
+    case(
+        "trap after host call",
+        TRAPS,
+        "after_output",
+        TEXT,
+        Expect::Trap,
+    ),
+    Case {
+        name: "not a wasm module",
+        body: "",
+        raw: Some(b"\0asm\x01\0\0\0\x01\xff"),
+        entry: "main",
+        inputs: NO_INPUT,
+        expect: Expect::LoadError,
+    },
+];
+
+const fn case(
+    name: &'static str,
+    body: &'static str,
+    entry: &'static str,
+    inputs: &'static [&'static [u8]],
+    expect: Expect,
+) -> Case {
+    Case {
+        name,
+        body,
+        raw: None,
+        entry,
+        inputs,
+        expect,
+    }
+}
+
+impl Case {
+    fn module(&self) -> Vec<u8> {
+        match self.raw {
+            Some(bytes) => bytes.to_vec(),
+            None => wat::parse_str(format!("(module {IMPORTS} {})", self.body))
+                .unwrap_or_else(|err| panic!("{}: {err}", self.name)),
+        }
+    }
+
+    fn check(&self, run: &Run) -> bool {
+        match (self.expect, run.outcome) {
+            (Expect::Status(want), Outcome::Completed(status)) => status == want,
+            (Expect::Completes, Outcome::Completed(_)) => true,
+            (Expect::Trap, Outcome::Failed(Error::Engine(_))) => true,
+            (Expect::Error(want), Outcome::Failed(err)) => err == want,
+            (Expect::LoadError, Outcome::LoadFailed(_)) => true,
+            _ => false,
+        }
+    }
+}
+
+/// Runs an entry of a module for one input.
+type RunFn = dyn FnMut(&[u8], &str, &[u8]) -> Run;
+
+/// One backend with its probe.
+struct Lane {
+    name: &'static str,
+    run: Box<RunFn>,
+}
+
+impl Lane {
+    // Unused when no engine feature is enabled.
+    #[allow(dead_code)]
+    fn new<E: Engine<Context = ()> + 'static>(
+        name: &'static str,
+        make: impl FnOnce(Imports) -> E,
+    ) -> Self {
+        let probe = Probe::new();
+        let mut engine = make(Imports::new().with(probe.imports()));
+        Self {
+            name,
+            run: Box::new(move |module, entry, input| {
+                diff::run(&mut engine, &probe, module, entry, input)
+            }),
+        }
+    }
+}
+
+fn lanes() -> Vec<Lane> {
+    #[allow(unused_mut)]
+    let mut lanes = Vec::new();
+    #[cfg(feature = "engine-wasm3")]
+    lanes.push(Lane::new("wasm3", |imports| {
+        use crate::engines::wasm3::{Wasm3Engine, DEFAULT_STACK_SLOTS};
+        let mut engine = Wasm3Engine::new(DEFAULT_STACK_SLOTS).unwrap();
+        engine.set_imports(imports);
+        engine
+    }));
+    #[cfg(feature = "engine-wasmtime-lite")]
+    lanes.push(Lane::new("wasmtime-lite", |imports| {
+        let mut engine = crate::engines::wasmtime_lite::WasmtimeLiteEngine::new().unwrap();
+        engine.set_imports(imports);
+        engine
+    }));
+    #[cfg(feature = "engine-wasmi")]
+    lanes.push(Lane::new("wasmi", |imports| {
+        let mut engine =
+            crate::engines::wasmi::WasmiEngine::new(crate::builder::DEFAULT_STACK_SIZE).unwrap();
+        engine.set_imports(imports);
+        engine
+    }));
+    #[cfg(feature = "engine-tinywasm")]
+    lanes.push(Lane::new("tinywasm", |imports| {
+        let mut engine =
+            crate::engines::tinywasm::TinywasmEngine::new(crate::builder::DEFAULT_STACK_SIZE);
+        engine.set_imports(imports);
+        engine
+    }));
+    lanes
+}
+
+#[test]
+fn backends_agree_on_the_corpus() {
+    let mut lanes = lanes();
+    let mut failures = Vec::<String>::new();
+    for case in CORPUS {
+        let module = case.module();
+        for (index, input) in case.inputs.iter().enumerate() {
+            let mut runs = lanes
+                .iter_mut()
+                .map(|lane| (lane.name, (lane.run)(&module, case.entry, input)));
+            let Some((reference, expected)) = runs.next() else {
+                return;
+            };
+            if !case.check(&expected) {
+                failures.push(format!(
+                    "{}: {reference} input #{index}: unexpected {:?}",
+                    case.name, expected.outcome
+                ));
+            }
+            for (name, run) in runs {
+                if let Some(divergence) = diff::compare(index, expected.clone(), run) {
+                    failures.push(format!(
+                        "{}: {reference} vs {name}: {divergence}",
+                        case.name
+                    ));
+                }
+            }
+        }
+    }
+    assert!(failures.is_empty(), "{}", failures.join("\n"));
+}
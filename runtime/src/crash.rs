@@ -0,0 +1,258 @@
+//! Crash records for post-mortem analysis of trapping guests.
+//!
+//! Enable capture with `Runtime::set_crash_capture`. When an invocation traps,
+//! engines that support it snapshot the trap kind, a window of linear memory
+//! and the bottom of the value stack (`Engine::take_crash`); the runtime adds
+//! the module, version, entry and fuel and keeps the newest `CrashRecord`
+//! until `Runtime::take_crash`. `CrashRecord::write_to` packs it into a
+//! host-provided buffer (e.g. a retained-RAM or flash slot) for later upload.
+//!
+//! Encoded layout (little-endian), truncated to fit the buffer:
+//!
+//! | field                 | type           |
+//! |-----------------------|----------------|
+//! | magic `SCRS`          | 4 bytes        |
+//! | version               | u8 (= 1)       |
+//! | trap kind             | u8             |
+//! | flags (bit0: fuel)    | u8             |
+//! | entry length          | u8             |
+//! | module id             | u32            |
+//! | module version        | u32            |
+//! | fuel                  | u64            |
+//! | entry                 | entry length   |
+//! | stack slot count `n`  | u16            |
+//! | stack slots           | `n` × u64      |
+//! | memory offset         | u32            |
+//! | memory length `m`     | u32            |
+//! | memory                | `m` bytes      |
+
+use crate::ModuleId;
+use alloc::string::String;
+use alloc::vec::Vec;
+
+/// Leading bytes of an encoded `CrashRecord`.
+pub const CRASH_MAGIC: &[u8; 4] = b"SCRS";
+/// Encoding version.
+pub const CRASH_VERSION: u8 = 1;
+
+const FLAG_FUEL: u8 = 1;
+/// Fixed part of the encoding, before the entry name.
+const HEADER_LEN: usize = 24;
+
+/// Why the guest stopped.
+#[repr(u8)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TrapKind {
+    Unreachable = 1,
+    MemoryOutOfBounds = 2,
+    StackOverflow = 3,
+    DivisionByZero = 4,
+    IntegerOverflow = 5,
+    BadConversion = 6,
+    /// Bad table index, null entry or signature mismatch.
+    IndirectCall = 7,
+    OutOfFuel = 8,
+    /// A host import returned an error.
+    HostError = 9,
+    /// Stopped by `Engine::interrupter` (see `schedule::PreemptHint`).
+    Interrupted = 10,
+    Other = 255,
+}
+
+/// What engines snapshot when a call traps.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CrashCapture {
+    /// Start of the linear-memory window.
+    pub memory_offset: u32,
+    /// Bytes of linear memory to keep (clamped to the memory size).
+    pub memory_len: u32,
+    /// Value-stack slots to keep, from the bottom (engines without an
+    /// accessible value stack keep none).
+    pub stack_slots: u32,
+}
+
+impl Default for CrashCapture {
+    fn default() -> Self {
+        Self {
+            memory_offset: 0,
+            memory_len: 256,
+            stack_slots: 16,
+        }
+    }
+}
+
+/// Engine-side snapshot of a trap.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Crash {
+    pub trap: TrapKind,
+    pub memory_offset: u32,
+    pub memory: Vec<u8>,
+    pub stack: Vec<u64>,
+}
+
+/// A trapped invocation.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CrashRecord {
+    pub module_id: ModuleId,
+    /// Module version (manifest sequence; 0 when unknown).
+    pub version: u32,
+    pub entry: String,
+    /// Fuel consumed before the trap, for engines that meter it.
+    pub fuel: Option<u64>,
+    pub crash: Crash,
+}
+
+impl CrashRecord {
+    /// Encodes the record into `buf`, dropping stack slots and then memory
+    /// bytes that do not fit; returns the bytes written (0 when even the
+    /// header and entry do not fit).
+    pub fn write_to(&self, buf: &mut [u8]) -> usize {
+        let entry = &self.entry.as_bytes()[..self.entry.len().min(u8::MAX as usize)];
+        let fixed = HEADER_LEN + entry.len() + 2 + 8;
+        if buf.len() < fixed {
+            return 0;
+        }
+        let stack_room = (buf.len() - fixed) / 8;
+        let stack = &self.crash.stack[..self
+            .crash
+            .stack
+            .len()
+            .min(stack_room)
+            .min(u16::MAX as usize)];
+        let memory_room = buf.len() - fixed - stack.len() * 8;
+        let memory = &self.crash.memory[..self.crash.memory.len().min(memory_room)];
+
+        let mut out = Writer { buf, pos: 0 };
+        out.put(CRASH_MAGIC);
+        out.put(&[
+            CRASH_VERSION,
+            self.crash.trap as u8,
+            if self.fuel.is_some() { FLAG_FUEL } else { 0 },
+            entry.len() as u8,
+        ]);
+        out.put(&self.module_id.to_le_bytes());
+        out.put(&self.version.to_le_bytes());
+        out.put(&self.fuel.unwrap_or(0).to_le_bytes());
+        out.put(entry);
+        out.put(&(stack.len() as u16).to_le_bytes());
+        for slot in stack {
+            out.put(&slot.to_le_bytes());
+        }
+        out.put(&self.crash.memory_offset.to_le_bytes());
+        out.put(&(memory.len() as u32).to_le_bytes());
+        out.put(memory);
+        out.pos
+    }
+}
+
+struct Writer<'a> {
+    buf: &'a mut [u8],
+    pos: usize,
+}
+
+impl Writer<'_> {
+    fn put(&mut self, bytes: &[u8]) {
+        self.buf[self.pos..self.pos + bytes.len()].copy_from_slice(bytes);
+        self.pos += bytes.len();
+    }
+}
+
+/// Copies the `capture` window out of `memory`.
+#[cfg(any(
+    feature = "engine-tinywasm",
+    feature = "engine-wasm3",
+    feature = "engine-wasmi",
+    feature = "engine-wasmtime-lite"
+))]
+pub(crate) fn memory_window(memory: &[u8], capture: &CrashCapture) -> Vec<u8> {
+    let start = (capture.memory_offset as usize).min(memory.len());
+    let end = start
+        .saturating_add(capture.memory_len as usize)
+        .min(memory.len());
+    memory[start..end].to_vec()
+}
+
+#[cfg(all(test, feature = "std"))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn write_to_truncates_to_buffer() {
+        let record = CrashRecord {
+            module_id: 7,
+            version: 3,
+            entry: "main".into(),
+            fuel: Some(99),
+            crash: Crash {
+                trap: TrapKind::Unreachable,
+                memory_offset: 16,
+                memory: vec![0xAB; 40],
+                stack: vec![1, 2],
+            },
+        };
+        let mut buf = [0u8; 128];
+        let len = record.write_to(&mut buf);
+        assert_eq!(len, HEADER_LEN + 4 + 2 + 16 + 8 + 40);
+        assert_eq!(&buf[..8], b"SCRS\x01\x01\x01\x04");
+        assert_eq!(buf[8..12], 7u32.to_le_bytes());
+        assert_eq!(buf[16..24], 99u64.to_le_bytes());
+        assert_eq!(&buf[24..28], b"main");
+
+        let mut small = [0u8; HEADER_LEN + 4 + 2 + 8 + 8 + 5];
+        assert_eq!(record.write_to(&mut small), small.len());
+        assert_eq!(small[28..30], 1u16.to_le_bytes());
+        assert_eq!(small[small.len() - 9..small.len() - 5], 5u32.to_le_bytes());
+        assert_eq!(record.write_to(&mut [0u8; 20]), 0);
+    }
+
+    #[cfg(feature = "engine-wasmtime-lite")]
+    #[test]
+    fn runtime_captures_wasmtime_trap() {
+        use crate::engines::wasmtime_lite::WasmtimeLiteEngine;
+        use crate::{CachedEngine, Error, MemoryStore, Runtime};
+
+        let wasm = wat::parse_str(
+            r#"(module
+                (memory (export "memory") 1)
+                (func (export "main")
+                    (i32.store (i32.const 4) (i32.const 0x2a))
+                    unreachable)
+                (func (export "ok")))"#,
+        )
+        .unwrap();
+        let mut store = MemoryStore::new();
+        store.upsert(1, wasm);
+        let engine = CachedEngine::new(WasmtimeLiteEngine::new().unwrap());
+        let mut runtime = Runtime::new(engine, store);
+
+        assert!(runtime.execute(1, "main", &mut ()).is_err());
+        assert_eq!(runtime.take_crash(), None);
+
+        runtime.set_crash_capture(Some(CrashCapture {
+            memory_offset: 2,
+            memory_len: 4,
+            stack_slots: 0,
+        }));
+        runtime.set_module_version(1, 5);
+        assert_eq!(
+            runtime.execute(1, "main", &mut ()),
+            Err(Error::Engine("wasmtime call"))
+        );
+        runtime.execute(1, "ok", &mut ()).unwrap();
+        let record = runtime.take_crash().unwrap();
+        assert_eq!(
+            (record.module_id, record.version, record.entry.as_str()),
+            (1, 5, "main")
+        );
+        assert_eq!(record.crash.trap, TrapKind::Unreachable);
+        assert_eq!(record.crash.memory, [0, 0, 0x2a, 0]);
+        assert_eq!(runtime.take_crash(), None);
+
+        let capture = CrashCapture {
+            memory_offset: 30,
+            memory_len: 100,
+            stack_slots: 0,
+        };
+        assert_eq!(memory_window(&[1; 40], &capture), vec![1; 10]);
+    }
+}
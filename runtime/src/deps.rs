@@ -0,0 +1,286 @@
+//! Module dependencies and startup ordering.
+//!
+//! A manifest may name its module (`EXT_NAME`) and declare dependencies on
+//! other modules by id or by name, each with a minimum version (the
+//! dependency's manifest sequence). `Registry` collects what is installed and
+//! `start_order` returns an order in which every module comes after its
+//! dependencies, or the first problem found: a missing dependency, one that is
+//! too old, or a cycle.
+
+use crate::manifest::{DependencyRef, Manifest};
+use crate::{Error, ModuleId, Result};
+use alloc::string::{String, ToString};
+use alloc::vec::Vec;
+
+/// What a dependency points at.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Target {
+    Id(ModuleId),
+    Name(String),
+}
+
+/// One declared dependency.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Requirement {
+    pub target: Target,
+    /// Lowest acceptable version (manifest sequence) of the target.
+    pub min_version: u32,
+}
+
+/// What the registry knows about an installed module.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ModuleInfo {
+    pub module_id: ModuleId,
+    /// Manifest sequence (0 when unknown).
+    pub version: u32,
+    pub name: Option<String>,
+    /// Entry run by `Runtime::start_all`; `None` = nothing to start.
+    pub entry: Option<String>,
+    pub dependencies: Vec<Requirement>,
+}
+
+/// Installed modules, their versions and dependencies, in registration order.
+#[derive(Debug, Default)]
+pub struct Registry {
+    modules: Vec<ModuleInfo>,
+}
+
+impl Registry {
+    /// Creates an empty registry.
+    pub const fn new() -> Self {
+        Self {
+            modules: Vec::new(),
+        }
+    }
+
+    /// Registered modules in registration order.
+    pub fn modules(&self) -> &[ModuleInfo] {
+        &self.modules
+    }
+
+    /// Info for `module_id`, if registered.
+    pub fn get(&self, module_id: ModuleId) -> Option<&ModuleInfo> {
+        self.modules.iter().find(|m| m.module_id == module_id)
+    }
+
+    /// Info for the module registered under manifest name `name`.
+    pub fn find(&self, name: &str) -> Option<&ModuleInfo> {
+        self.modules
+            .iter()
+            .find(|m| m.name.as_deref() == Some(name))
+    }
+
+    fn entry_mut(&mut self, module_id: ModuleId) -> &mut ModuleInfo {
+        match self.modules.iter().position(|m| m.module_id == module_id) {
+            Some(pos) => &mut self.modules[pos],
+            None => {
+                self.modules.push(ModuleInfo {
+                    module_id,
+                    ..ModuleInfo::default()
+                });
+                self.modules.last_mut().unwrap()
+            }
+        }
+    }
+
+    /// Records a module's version, registering it if needed.
+    pub fn set_version(&mut self, module_id: ModuleId, version: u32) {
+        self.entry_mut(module_id).version = version;
+    }
+
+    /// Version recorded for a module (0 when unknown).
+    pub fn version(&self, module_id: ModuleId) -> u32 {
+        self.get(module_id).map_or(0, |m| m.version)
+    }
+
+    /// Inserts or replaces a module's info.
+    pub fn register(&mut self, info: ModuleInfo) {
+        let module_id = info.module_id;
+        *self.entry_mut(module_id) = info;
+    }
+
+    /// Registers a module from its manifest: sequence, name, entry and dependencies.
+    pub fn register_manifest(&mut self, manifest: &Manifest<'_>) -> Result<()> {
+        let name = manifest.name().transpose()?.map(ToString::to_string);
+        let dependencies = manifest
+            .dependencies()
+            .map(|dependency| {
+                let (target, min_version) = dependency?;
+                let target = match target {
+                    DependencyRef::Id(id) => Target::Id(id),
+                    DependencyRef::Name(name) => Target::Name(name.to_string()),
+                };
+                Ok(Requirement {
+                    target,
+                    min_version,
+                })
+            })
+            .collect::<Result<Vec<_>>>()?;
+        self.register(ModuleInfo {
+            module_id: manifest.module_id,
+            version: manifest.sequence,
+            name,
+            entry: Some(manifest.entry.to_string()).filter(|entry| !entry.is_empty()),
+            dependencies,
+        });
+        Ok(())
+    }
+
+    /// Forgets a module; returns whether it was registered.
+    pub fn remove(&mut self, module_id: ModuleId) -> bool {
+        let before = self.modules.len();
+        self.modules.retain(|m| m.module_id != module_id);
+        self.modules.len() != before
+    }
+
+    fn resolve(&self, target: &Target) -> Option<usize> {
+        self.modules.iter().position(|m| match target {
+            Target::Id(id) => m.module_id == *id,
+            Target::Name(name) => m.name.as_deref() == Some(name.as_str()),
+        })
+    }
+
+    /// Orders modules so each comes after its dependencies; ties keep
+    /// registration order.
+    ///
+    /// Fails with `DependencyMissing`, `DependencyTooOld` or `DependencyCycle`
+    /// naming the first offending module.
+    pub fn start_order(&self) -> Result<Vec<ModuleId>> {
+        let mut edges = Vec::with_capacity(self.modules.len());
+        for module in &self.modules {
+            let mut deps = Vec::with_capacity(module.dependencies.len());
+            for requirement in &module.dependencies {
+                let Some(dep) = self.resolve(&requirement.target) else {
+                    return Err(Error::DependencyMissing {
+                        module: module.module_id,
+                    });
+                };
+                let found = &self.modules[dep];
+                if found.version < requirement.min_version {
+                    return Err(Error::DependencyTooOld {
+                        module: module.module_id,
+                        dependency: found.module_id,
+                        required: requirement.min_version,
+                        found: found.version,
+                    });
+                }
+                deps.push(dep);
+            }
+            edges.push(deps);
+        }
+
+        let mut started = alloc::vec![false; self.modules.len()];
+        let mut order = Vec::with_capacity(self.modules.len());
+        while order.len() < self.modules.len() {
+            let next = (0..self.modules.len())
+                .find(|&i| !started[i] && edges[i].iter().all(|&dep| started[dep]));
+            let Some(next) = next else {
+                let stuck = started.iter().position(|done| !done).unwrap();
+                return Err(Error::DependencyCycle {
+                    module: self.modules[stuck].module_id,
+                });
+            };
+            started[next] = true;
+            order.push(self.modules[next].module_id);
+        }
+        Ok(order)
+    }
+}
+
+#[cfg(all(test, feature = "std"))]
+mod tests {
+    use super::*;
+    use crate::manifest::{self, EXT_DEPENDS_ID, EXT_DEPENDS_NAME, EXT_NAME};
+
+    fn requires(target: Target, min_version: u32) -> Requirement {
+        Requirement {
+            target,
+            min_version,
+        }
+    }
+
+    fn module(id: ModuleId, version: u32, name: &str, deps: Vec<Requirement>) -> ModuleInfo {
+        ModuleInfo {
+            module_id: id,
+            version,
+            name: Some(name.to_string()),
+            entry: Some("start".to_string()),
+            dependencies: deps,
+        }
+    }
+
+    #[test]
+    fn orders_dependencies_first_and_reports_problems() {
+        let mut registry = Registry::new();
+        registry.register(module(
+            1,
+            1,
+            "app",
+            vec![requires(Target::Name("net".into()), 2)],
+        ));
+        registry.register(module(2, 3, "net", vec![requires(Target::Id(3), 1)]));
+        registry.register(module(3, 1, "hal", Vec::new()));
+        registry.register(module(4, 1, "log", Vec::new()));
+        assert_eq!(registry.start_order().unwrap(), vec![3, 2, 1, 4]);
+
+        registry.set_version(2, 1);
+        assert_eq!(
+            registry.start_order(),
+            Err(Error::DependencyTooOld {
+                module: 1,
+                dependency: 2,
+                required: 2,
+                found: 1
+            })
+        );
+
+        registry.set_version(2, 2);
+        assert!(registry.remove(3));
+        assert_eq!(
+            registry.start_order(),
+            Err(Error::DependencyMissing { module: 2 })
+        );
+
+        registry.register(module(3, 1, "hal", vec![requires(Target::Id(1), 0)]));
+        assert_eq!(
+            registry.start_order(),
+            Err(Error::DependencyCycle { module: 1 })
+        );
+    }
+
+    #[test]
+    fn registers_from_manifest() {
+        let mut ext = Vec::new();
+        manifest::push_extension(&mut ext, EXT_NAME, b"app").unwrap();
+        let mut by_id = 9u32.to_le_bytes().to_vec();
+        by_id.extend_from_slice(&4u32.to_le_bytes());
+        manifest::push_extension(&mut ext, EXT_DEPENDS_ID, &by_id).unwrap();
+        let mut by_name = 2u32.to_le_bytes().to_vec();
+        by_name.extend_from_slice(b"net");
+        manifest::push_extension(&mut ext, EXT_DEPENDS_NAME, &by_name).unwrap();
+        let bytes = manifest::encode_ext(5, "main", &[0u8; 4], 0, 7, &ext, None).unwrap();
+        let (parsed, _) = Manifest::parse(&bytes).unwrap();
+
+        let mut registry = Registry::new();
+        registry.register_manifest(&parsed).unwrap();
+        assert_eq!(
+            registry.get(5),
+            Some(&ModuleInfo {
+                module_id: 5,
+                version: 7,
+                name: Some("app".to_string()),
+                entry: Some("main".to_string()),
+                dependencies: vec![
+                    requires(Target::Id(9), 4),
+                    requires(Target::Name("net".into()), 2)
+                ],
+            })
+        );
+
+        let mut broken = Vec::new();
+        manifest::push_extension(&mut broken, EXT_DEPENDS_ID, &[1, 2, 3]).unwrap();
+        let bytes = manifest::encode_ext(6, "main", &[0u8; 4], 0, 1, &broken, None).unwrap();
+        let (parsed, _) = Manifest::parse(&bytes).unwrap();
+        assert!(registry.register_manifest(&parsed).is_err());
+    }
+}
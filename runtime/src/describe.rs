@@ -0,0 +1,362 @@
+//! Module self-description (`__slimmy_info`).
+//!
+//! A guest may export `__slimmy_info() -> status` (`abi::INFO_EXPORT`),
+//! which hands the host a `Descriptor` through the `describe_module`
+//! import: the ABI version it was built against, the host functions it
+//! needs and the entries it exports. `Runtime::check_descriptor` loads a
+//! module, runs that export and checks the answer against the module's
+//! manifest and the `CapabilityPolicy`, so an artifact built for another
+//! manifest or for hardware the module was never granted is refused at
+//! install instead of failing at its first call.
+//!
+//! Guest import (module `env`, all `i32`):
+//! - `describe_module(ptr, len) -> status` – stores the encoded descriptor;
+//!   `E_INVALID` past `MAX_DESCRIPTOR_LEN` or out of bounds, `E_EMPTY`
+//!   outside `__slimmy_info`.
+//!
+//! Encoding (little endian, append-only like `abi::info`; decoders ignore
+//! bytes after the fields they know):
+//!
+//! | offset | type | field                                            |
+//! |--------|------|--------------------------------------------------|
+//! | 0      | u16  | layout version (= `DESCRIPTOR_VERSION`)          |
+//! | 2      | u16  | `abi_version` (the guest's `abi::ABI_VERSION`)   |
+//! | 4      | u8   | import count                                     |
+//! | 5      | u8   | entry count                                      |
+//! | 6      | …    | imports, then entries: each a u8 length and UTF-8 name |
+
+use crate::abi::caps::{CapabilityKind, CapabilityPolicy};
+use crate::abi::{
+    version, GuestMemory, HostFn, HostImports, ABI_VERSION, E_EMPTY, E_INVALID, INFO_EXPORT, OK,
+};
+use crate::manifest::Manifest;
+use crate::{Engine, Error, ModuleId, ModuleSource, Result, Runtime};
+use alloc::rc::Rc;
+use alloc::string::{String, ToString};
+use alloc::vec::Vec;
+use core::cell::RefCell;
+
+/// Layout version written at offset 0.
+pub const DESCRIPTOR_VERSION: u16 = 1;
+/// Largest encoded descriptor in bytes.
+pub const MAX_DESCRIPTOR_LEN: usize = 1024;
+
+/// Host functions provided by `Describe`.
+pub const FUNCTIONS: &[HostFn] = &[HostFn::new("describe_module", 2)];
+
+/// What a module says about itself.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Descriptor {
+    /// Host ABI version the module was built against.
+    pub abi_version: u16,
+    /// Host functions the module imports.
+    pub imports: Vec<String>,
+    /// Entry points the module exports.
+    pub entries: Vec<String>,
+}
+
+impl Descriptor {
+    pub fn decode(bytes: &[u8]) -> Result<Self> {
+        let [v0, v1, a0, a1, imports, entries, rest @ ..] = bytes else {
+            return Err(Error::Engine("describe: descriptor truncated"));
+        };
+        let mut rest = rest;
+        if u16::from_le_bytes([*v0, *v1]) == 0 {
+            return Err(Error::Engine("describe: bad descriptor version"));
+        }
+        let mut names = |count: u8| -> Result<Vec<String>> {
+            (0..count)
+                .map(|_| {
+                    let (len, tail) = rest
+                        .split_first()
+                        .ok_or(Error::Engine("describe: descriptor truncated"))?;
+                    let (name, tail) = tail
+                        .split_at_checked(usize::from(*len))
+                        .ok_or(Error::Engine("describe: descriptor truncated"))?;
+                    rest = tail;
+                    core::str::from_utf8(name)
+                        .map(ToString::to_string)
+                        .map_err(|_| Error::Engine("describe: name not UTF-8"))
+                })
+                .collect()
+        };
+        Ok(Self {
+            abi_version: u16::from_le_bytes([*a0, *a1]),
+            imports: names(*imports)?,
+            entries: names(*entries)?,
+        })
+    }
+
+    /// The encoding guests produce; `None` past 255 names, a name over 255
+    /// bytes or `MAX_DESCRIPTOR_LEN`.
+    pub fn encode(&self) -> Option<Vec<u8>> {
+        let mut out = Vec::new();
+        out.extend(DESCRIPTOR_VERSION.to_le_bytes());
+        out.extend(self.abi_version.to_le_bytes());
+        out.push(u8::try_from(self.imports.len()).ok()?);
+        out.push(u8::try_from(self.entries.len()).ok()?);
+        for name in self.imports.iter().chain(&self.entries) {
+            out.push(u8::try_from(name.len()).ok()?);
+            out.extend(name.as_bytes());
+        }
+        (out.len() <= MAX_DESCRIPTOR_LEN).then_some(out)
+    }
+
+    pub fn exports(&self, entry: &str) -> bool {
+        self.entries.iter().any(|name| name == entry)
+    }
+
+    /// Checks the descriptor against this host's ABI (`abi::version`), the
+    /// module's manifest (its entry must be exported) and `policy` (imports
+    /// gated on a capability need a grant of that kind for the module).
+    pub fn check(&self, manifest: &Manifest<'_>, policy: &CapabilityPolicy) -> Result<()> {
+        if !version::compatible(self.abi_version) {
+            return Err(Error::AbiMismatch {
+                host: ABI_VERSION,
+                guest: self.abi_version,
+            });
+        }
+        if !manifest.entry.is_empty() && !self.exports(manifest.entry) {
+            return Err(Error::Engine("describe: manifest entry not exported"));
+        }
+        let ungranted = self.imports.iter().any(|import| {
+            CapabilityKind::for_import(import)
+                .is_some_and(|kind| !policy.allows_kind(manifest.module_id, kind))
+        });
+        if ungranted {
+            return Err(Error::Engine(
+                "describe: import needs an ungranted capability",
+            ));
+        }
+        Ok(())
+    }
+}
+
+#[derive(Default)]
+struct State {
+    /// Set while `__slimmy_info` runs.
+    collecting: bool,
+    descriptor: Option<Vec<u8>>,
+}
+
+/// Shared handle receiving descriptors; register it with the engine.
+#[derive(Clone, Default)]
+pub struct Describe {
+    state: Rc<RefCell<State>>,
+}
+
+impl Describe {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    fn guest_describe(&self, ptr: i32, len: i32, memory: &dyn GuestMemory) -> i32 {
+        let mut state = self.state.borrow_mut();
+        if !state.collecting {
+            return E_EMPTY;
+        }
+        let Some(len) = usize::try_from(len)
+            .ok()
+            .filter(|len| *len <= MAX_DESCRIPTOR_LEN)
+        else {
+            return E_INVALID;
+        };
+        let mut descriptor = alloc::vec![0u8; len];
+        if memory.read(ptr as u32, &mut descriptor).is_err() {
+            return E_INVALID;
+        }
+        state.descriptor = Some(descriptor);
+        OK
+    }
+}
+
+impl HostImports for Describe {
+    fn functions(&self) -> &[HostFn] {
+        FUNCTIONS
+    }
+
+    fn call(&mut self, name: &str, args: &[i32], memory: &mut dyn GuestMemory) -> Result<i32> {
+        match (name, args) {
+            ("describe_module", &[ptr, len]) => Ok(self.guest_describe(ptr, len, memory)),
+            _ => Err(Error::Engine("describe: bad host call")),
+        }
+    }
+}
+
+impl<E: Engine, S: ModuleSource> Runtime<E, S> {
+    /// Loads `module_id` and runs its `__slimmy_info` export; `None` when it
+    /// has none. `describe` must be registered with the engine. Like
+    /// `preload`, this bypasses switches, quotas and tracing.
+    pub fn describe(
+        &mut self,
+        describe: &Describe,
+        module_id: ModuleId,
+        ctx: &mut E::Context,
+    ) -> Result<Option<Descriptor>> {
+        self.ensure_awake()?;
+        {
+            let mut state = describe.state.borrow_mut();
+            if state.collecting {
+                return Err(Error::Reentrancy);
+            }
+            state.collecting = true;
+            state.descriptor = None;
+        }
+        #[cfg(feature = "unstable")]
+        let verified = self.verify_stored(module_id);
+        #[cfg(not(feature = "unstable"))]
+        let verified = Ok(());
+        let status = verified
+            .and_then(|()| self.source.fetch(module_id).ok_or(Error::ModuleNotFound))
+            .and_then(|bytes| {
+                #[cfg(feature = "revocation")]
+                self.revocations.check(module_id, bytes)?;
+                crate::load_module(&mut self.engine, module_id, bytes)
+            })
+            .and_then(|handle| self.engine.invoke_status(handle, INFO_EXPORT, ctx));
+        let descriptor = {
+            let mut state = describe.state.borrow_mut();
+            state.collecting = false;
+            state.descriptor.take()
+        };
+        match status {
+            Ok(OK) => descriptor
+                .ok_or(Error::Engine("describe: no descriptor"))
+                .and_then(|bytes| Descriptor::decode(&bytes))
+                .map(Some),
+            Ok(_) => Err(Error::Engine("describe: __slimmy_info failed")),
+            Err(Error::EntryNotFound) => Ok(None),
+            Err(error) => Err(error),
+        }
+    }
+
+    /// `describe`s the module `manifest` installed and checks the answer
+    /// (`Descriptor::check`); modules without `__slimmy_info` pass.
+    pub fn check_descriptor(
+        &mut self,
+        describe: &Describe,
+        manifest: &Manifest<'_>,
+        policy: &CapabilityPolicy,
+        ctx: &mut E::Context,
+    ) -> Result<Option<Descriptor>> {
+        let descriptor = self.describe(describe, manifest.module_id, ctx)?;
+        if let Some(descriptor) = &descriptor {
+            descriptor.check(manifest, policy)?;
+        }
+        Ok(descriptor)
+    }
+}
+
+#[cfg(all(test, feature = "std"))]
+mod tests {
+    use super::*;
+    use crate::abi::caps::Capability;
+    use crate::abi::Imports;
+    use crate::manifest::encode;
+    use crate::MemoryStore;
+
+    /// Module 1 describes itself, 2 has no `__slimmy_info`, 3 sends garbage.
+    struct Guests {
+        imports: Imports,
+        descriptor: Vec<u8>,
+    }
+
+    impl Engine for Guests {
+        type ModuleHandle = ModuleId;
+        type Context = ();
+
+        fn load(&mut self, id: ModuleId, _module: &[u8]) -> Result<ModuleId> {
+            Ok(id)
+        }
+
+        fn invoke(&mut self, id: ModuleId, entry: &str, ctx: &mut ()) -> Result<()> {
+            self.invoke_status(id, entry, ctx).map(drop)
+        }
+
+        fn invoke_status(&mut self, id: ModuleId, entry: &str, _ctx: &mut ()) -> Result<i32> {
+            let mut memory = self.descriptor.clone();
+            let len = match (id, entry) {
+                (1, INFO_EXPORT) => memory.len(),
+                (3, INFO_EXPORT) => 3,
+                _ => return Err(Error::EntryNotFound),
+            };
+            self.imports
+                .call("describe_module", &[0, len as i32], &mut memory)
+        }
+    }
+
+    #[test]
+    fn reads_descriptors_and_checks_them_against_manifest_and_grants() {
+        let sensor = Descriptor {
+            abi_version: 1,
+            imports: alloc::vec!["log".into(), "i2c_read".into()],
+            entries: alloc::vec!["main".into(), "health".into()],
+        };
+        let describe = Describe::new();
+        let engine = Guests {
+            imports: Imports::new().with(describe.clone()),
+            descriptor: sensor.encode().unwrap(),
+        };
+        let mut store = MemoryStore::new();
+        for id in 1..=3 {
+            store.upsert(id, b"\0asm".to_vec());
+        }
+        let mut runtime = Runtime::new(engine, store);
+        assert_eq!(
+            runtime.describe(&describe, 1, &mut ()),
+            Ok(Some(sensor.clone()))
+        );
+        assert_eq!(runtime.describe(&describe, 2, &mut ()), Ok(None));
+        assert_eq!(
+            runtime.describe(&describe, 3, &mut ()),
+            Err(Error::Engine("describe: descriptor truncated"))
+        );
+        assert_eq!(
+            runtime.describe(&describe, 4, &mut ()),
+            Err(Error::ModuleNotFound)
+        );
+
+        let blob = |id, entry| encode(id, entry, b"\0asm", 0, 1, None).unwrap();
+        let (main, other) = (blob(1, "main"), blob(1, "other"));
+        let main = Manifest::parse(&main).unwrap().0;
+        let grants = CapabilityPolicy::new().with(1, Capability::I2c { bus: 0, addr: 0x48 });
+        assert!(runtime
+            .check_descriptor(&describe, &main, &grants, &mut ())
+            .is_ok());
+        assert_eq!(
+            runtime.check_descriptor(&describe, &main, &CapabilityPolicy::new(), &mut ()),
+            Err(Error::Engine(
+                "describe: import needs an ungranted capability"
+            ))
+        );
+        let other = Manifest::parse(&other).unwrap().0;
+        assert_eq!(
+            runtime.check_descriptor(&describe, &other, &grants, &mut ()),
+            Err(Error::Engine("describe: manifest entry not exported"))
+        );
+        let future = Descriptor {
+            abi_version: ABI_VERSION + 1,
+            ..sensor
+        };
+        assert_eq!(
+            future.check(&main, &grants),
+            Err(Error::AbiMismatch {
+                host: ABI_VERSION,
+                guest: ABI_VERSION + 1
+            })
+        );
+        let bare = blob(2, "main");
+        let bare = Manifest::parse(&bare).unwrap().0;
+        assert_eq!(
+            runtime.check_descriptor(&describe, &bare, &grants, &mut ()),
+            Ok(None)
+        );
+
+        // Outside `__slimmy_info` the import refuses.
+        let mut imports = Imports::new().with(describe);
+        assert_eq!(
+            imports.call("describe_module", &[0, 0], &mut [0u8; 4]),
+            Ok(E_EMPTY)
+        );
+    }
+}
@@ -0,0 +1,271 @@
+//! Module delivery over existing firmware-update (DFU) channels (`dfu` feature).
+//!
+//! `DfuTarget` is the device side of an update transfer with the same shape as
+//! `embedded_update::FirmwareDevice` (Drogue's `embedded-update`): report
+//! status, `start` a version, `write` chunks at offsets, then `update` with a
+//! SHA-256 checksum. It is synchronous so it works without an executor;
+//! plugging `ModuleDfu` into an `embedded-update` `FirmwareUpdater` takes a
+//! `FirmwareDevice` impl whose `async fn`s call straight through.
+//!
+//! `ModuleDfu` receives a manifest blob (`SMNY`, or SUIT with
+//! `manifest-suit`), checks the checksum, runs a verification hook on the
+//! parsed manifest and installs the module through any `ModuleSink` under the
+//! id the manifest names. Transfers resume: restarting the same version keeps
+//! what was already received.
+
+use crate::manifest::{ct_eq, Manifest, ManifestFormat};
+use crate::{write_module, Error, ModuleId, ModuleSink, Result};
+use alloc::vec::Vec;
+use sha2::{Digest as _, Sha256};
+
+/// Progress reported to the update service.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DfuStatus<'a> {
+    /// Version of the last installed image (empty before the first).
+    pub current_version: &'a [u8],
+    /// Offset the next `write` should start at.
+    pub next_offset: u32,
+    /// Version being received, if a transfer is in progress.
+    pub next_version: Option<&'a [u8]>,
+}
+
+/// Device side of a firmware-update transfer.
+pub trait DfuTarget {
+    /// Largest chunk `write` accepts.
+    const MTU: usize;
+
+    fn status(&mut self) -> Result<DfuStatus<'_>>;
+
+    /// Begins (or resumes) receiving `version`.
+    fn start(&mut self, version: &[u8]) -> Result<()>;
+
+    /// Stores `data` at `offset` of the image being received.
+    fn write(&mut self, offset: u32, data: &[u8]) -> Result<()>;
+
+    /// Completes the transfer: the image's SHA-256 must equal `checksum`.
+    fn update(&mut self, version: &[u8], checksum: &[u8]) -> Result<()>;
+
+    /// The service reports the device up to date.
+    fn synced(&mut self) -> Result<()>;
+}
+
+/// Module installed by the last successful `update`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Installed {
+    pub module_id: ModuleId,
+    pub sequence: u32,
+}
+
+/// `DfuTarget` that installs manifest blobs into a `ModuleSink`.
+pub struct ModuleDfu<K, V> {
+    sink: K,
+    verify: V,
+    max_len: usize,
+    current_version: Vec<u8>,
+    next_version: Option<Vec<u8>>,
+    image: Vec<u8>,
+    installed: Option<Installed>,
+}
+
+impl<K, V> ModuleDfu<K, V>
+where
+    K: ModuleSink,
+    V: FnMut(&Manifest<'_>, &[u8]) -> Result<()>,
+{
+    /// Installs into `sink`, accepting blobs up to `max_len` bytes whose
+    /// manifest and module pass `verify` (e.g. `manifest::verify_ed25519`).
+    pub fn new(sink: K, max_len: usize, verify: V) -> Self {
+        Self {
+            sink,
+            verify,
+            max_len,
+            current_version: Vec::new(),
+            next_version: None,
+            image: Vec::new(),
+            installed: None,
+        }
+    }
+
+    /// Sets the version reported before anything was installed over DFU.
+    pub fn with_current_version(mut self, version: &[u8]) -> Self {
+        self.current_version = version.to_vec();
+        self
+    }
+
+    /// Module installed by the last successful `update`.
+    pub fn installed(&self) -> Option<Installed> {
+        self.installed
+    }
+
+    pub fn sink(&self) -> &K {
+        &self.sink
+    }
+
+    pub fn sink_mut(&mut self) -> &mut K {
+        &mut self.sink
+    }
+
+    pub fn into_inner(self) -> K {
+        self.sink
+    }
+
+    fn install(&mut self) -> Result<Installed> {
+        let image = self.image.as_slice();
+        let (manifest, module) = match ManifestFormat::detect(image) {
+            Some(ManifestFormat::Smny) => Manifest::parse(image)?,
+            #[cfg(feature = "manifest-suit")]
+            Some(ManifestFormat::Suit) => {
+                let (suit, module) = crate::suit::SuitManifest::parse(image)?;
+                (suit.manifest(), module)
+            }
+            _ => return Err(Error::Engine("dfu: image is not a manifest")),
+        };
+        if manifest.module_len as usize != module.len() {
+            return Err(Error::Engine("manifest module_len mismatch"));
+        }
+        (self.verify)(&manifest, module)?;
+        write_module(&mut self.sink, manifest.module_id, module)?;
+        Ok(Installed {
+            module_id: manifest.module_id,
+            sequence: manifest.sequence,
+        })
+    }
+}
+
+impl<K, V> DfuTarget for ModuleDfu<K, V>
+where
+    K: ModuleSink,
+    V: FnMut(&Manifest<'_>, &[u8]) -> Result<()>,
+{
+    const MTU: usize = 256;
+
+    fn status(&mut self) -> Result<DfuStatus<'_>> {
+        Ok(DfuStatus {
+            current_version: &self.current_version,
+            next_offset: self.image.len() as u32,
+            next_version: self.next_version.as_deref(),
+        })
+    }
+
+    fn start(&mut self, version: &[u8]) -> Result<()> {
+        if self.next_version.as_deref() != Some(version) {
+            self.image.clear();
+            self.next_version = Some(version.to_vec());
+        }
+        Ok(())
+    }
+
+    fn write(&mut self, offset: u32, data: &[u8]) -> Result<()> {
+        if self.next_version.is_none() {
+            return Err(Error::Engine("dfu: no transfer started"));
+        }
+        if offset as usize != self.image.len() {
+            return Err(Error::Engine("dfu: unexpected offset"));
+        }
+        if data.len() > Self::MTU || self.image.len() + data.len() > self.max_len {
+            return Err(Error::Engine("dfu: image too large"));
+        }
+        crate::fallible::extend(&mut self.image, data)
+    }
+
+    fn update(&mut self, version: &[u8], checksum: &[u8]) -> Result<()> {
+        if self.next_version.as_deref() != Some(version) {
+            return Err(Error::Engine("dfu: version not being received"));
+        }
+        // A bad image is dropped so the service restarts from offset 0.
+        let result = if !ct_eq(&Sha256::digest(&self.image), checksum) {
+            Err(Error::Engine("dfu: checksum mismatch"))
+        } else {
+            self.install()
+        };
+        self.image = Vec::new();
+        self.next_version = None;
+        let installed = result?;
+        self.installed = Some(installed);
+        self.current_version = version.to_vec();
+        Ok(())
+    }
+
+    fn synced(&mut self) -> Result<()> {
+        Ok(())
+    }
+}
+
+#[cfg(all(test, feature = "std"))]
+mod tests {
+    use super::*;
+    use crate::manifest::encode;
+    use crate::{MemoryStore, ModuleSource};
+
+    const WASM: &[u8] = b"\0asm\x01\0\0\0";
+
+    fn send(dfu: &mut impl DfuTarget, version: &[u8], blob: &[u8]) -> Result<()> {
+        dfu.start(version)?;
+        let mut offset = dfu.status()?.next_offset as usize;
+        for chunk in blob[offset..].chunks(5) {
+            dfu.write(offset as u32, chunk)?;
+            offset += chunk.len();
+        }
+        dfu.update(version, &Sha256::digest(blob))
+    }
+
+    #[test]
+    fn installs_resumes_and_rejects() {
+        let blob = encode(7, "main", WASM, 0, 3, None).unwrap();
+        let mut dfu = ModuleDfu::new(
+            MemoryStore::new(),
+            1024,
+            |_: &Manifest<'_>, _: &[u8]| Ok(()),
+        )
+        .with_current_version(b"v0");
+
+        // Interrupted transfer, resumed from the reported offset.
+        dfu.start(b"v1").unwrap();
+        dfu.write(0, &blob[..10]).unwrap();
+        assert_eq!(
+            dfu.write(3, &blob[10..12]),
+            Err(Error::Engine("dfu: unexpected offset"))
+        );
+        assert_eq!(dfu.status().unwrap().next_offset, 10);
+        send(&mut dfu, b"v1", &blob).unwrap();
+
+        assert_eq!(dfu.sink().fetch(7), Some(WASM));
+        assert_eq!(
+            dfu.installed(),
+            Some(Installed {
+                module_id: 7,
+                sequence: 3
+            })
+        );
+        let status = dfu.status().unwrap();
+        assert_eq!(
+            (status.current_version, status.next_version),
+            (&b"v1"[..], None)
+        );
+
+        assert_eq!(
+            dfu.start(b"v2")
+                .and_then(|()| dfu.write(0, &blob))
+                .and_then(|()| dfu.update(b"v2", &[0; 32])),
+            Err(Error::Engine("dfu: checksum mismatch"))
+        );
+        assert_eq!(dfu.status().unwrap().next_offset, 0);
+        assert_eq!(
+            send(&mut dfu, b"v2", WASM),
+            Err(Error::Engine("dfu: image is not a manifest"))
+        );
+
+        let mut strict = ModuleDfu::new(MemoryStore::new(), 1024, |m: &Manifest<'_>, _: &[u8]| {
+            if m.sequence > 2 {
+                Err(Error::Engine("rollback"))
+            } else {
+                Ok(())
+            }
+        });
+        assert_eq!(
+            send(&mut strict, b"v1", &blob),
+            Err(Error::Engine("rollback"))
+        );
+        assert_eq!(strict.sink().fetch(7), None);
+    }
+}
@@ -0,0 +1,493 @@
+//! Differential execution: run one module on two engines and compare.
+//!
+//! Guests under test talk through the message ABI (module `env`, all `i32`):
+//! - `msg_input(ptr, cap) -> len | status` – returns the input's full length and
+//!   copies it when it fits in `cap`.
+//! - `msg_output(ptr, len) -> status` – emits `len` bytes of output.
+//!
+//! Each engine gets its own `Probe`. Every host call the guest makes through
+//! the probe's imports (or through import sets wrapped with `Probe::wrap`) is
+//! appended to a transcript with its arguments, result and the guest-memory
+//! bytes it read or wrote. `Differential::check` runs each input on both
+//! engines and reports the first transcript entry or outcome that differs, so
+//! interpreter bugs surface as concrete, replayable divergences.
+
+use crate::abi::{GuestMemory, HostContext, HostFn, HostImports, E_INVALID, OK};
+use crate::{Engine, Error, ModuleId, Result};
+use alloc::rc::Rc;
+use alloc::string::{String, ToString};
+use alloc::vec::Vec;
+use core::cell::RefCell;
+use core::fmt;
+
+/// Module id both engines load the module under.
+pub const DIFF_MODULE_ID: ModuleId = 1;
+/// Largest input or output message in bytes.
+pub const MAX_MESSAGE_LEN: usize = 4096;
+
+/// Host functions provided by `MessageImports`.
+pub const FUNCTIONS: &[HostFn] = &[HostFn::new("msg_input", 2), HostFn::new("msg_output", 2)];
+
+/// Guest-memory bytes moved by a host call.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Access {
+    pub write: bool,
+    pub ptr: u32,
+    pub bytes: Vec<u8>,
+}
+
+/// One host call made by the guest.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Call {
+    pub name: String,
+    pub args: Vec<i32>,
+    /// Status returned to the guest; `None` when the call trapped.
+    pub result: Option<i32>,
+    pub memory: Vec<Access>,
+}
+
+/// How a run ended. Engines word their errors differently, so failures are
+/// compared by `Error` variant only; the error itself is kept for the report.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Outcome {
+    /// The entry returned this status (0 for entries without a result).
+    Completed(i32),
+    LoadFailed(Error),
+    Failed(Error),
+}
+
+impl Outcome {
+    fn same_kind(&self, other: &Outcome) -> bool {
+        use core::mem::discriminant;
+        match (self, other) {
+            (Outcome::Completed(a), Outcome::Completed(b)) => a == b,
+            (Outcome::LoadFailed(a), Outcome::LoadFailed(b))
+            | (Outcome::Failed(a), Outcome::Failed(b)) => discriminant(a) == discriminant(b),
+            _ => false,
+        }
+    }
+}
+
+/// Transcript and outcome of one input on one engine.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Run {
+    pub outcome: Outcome,
+    pub calls: Vec<Call>,
+}
+
+impl Run {
+    /// Bytes the guest emitted with `msg_output`, in order.
+    pub fn output(&self) -> Vec<u8> {
+        self.calls
+            .iter()
+            .filter(|call| call.name == "msg_output" && call.result == Some(OK))
+            .flat_map(|call| call.memory.iter().filter(|a| !a.write))
+            .flat_map(|access| access.bytes.iter().copied())
+            .collect()
+    }
+}
+
+#[derive(Default)]
+struct State {
+    input: Vec<u8>,
+    calls: Vec<Call>,
+}
+
+/// Per-engine recorder; clone it into the engine's `Imports`.
+#[derive(Clone, Default)]
+pub struct Probe {
+    state: Rc<RefCell<State>>,
+}
+
+impl Probe {
+    /// Creates an empty probe.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Import set providing `msg_input` / `msg_output`.
+    pub fn imports(&self) -> MessageImports {
+        MessageImports {
+            probe: self.clone(),
+        }
+    }
+
+    /// Records calls made through another import set.
+    pub fn wrap<I: HostImports>(&self, inner: I) -> Recorded<I> {
+        Recorded {
+            inner,
+            probe: self.clone(),
+        }
+    }
+
+    pub(crate) fn start(&self, input: &[u8]) {
+        let mut state = self.state.borrow_mut();
+        state.input = input.to_vec();
+        state.calls.clear();
+    }
+
+    pub(crate) fn finish(&self) -> Vec<Call> {
+        core::mem::take(&mut self.state.borrow_mut().calls)
+    }
+
+    fn record(
+        &self,
+        name: &str,
+        args: &[i32],
+        memory: &mut dyn GuestMemory,
+        call: impl FnOnce(&mut dyn GuestMemory) -> Result<i32>,
+    ) -> Result<i32> {
+        let mut tap = Tap {
+            inner: memory,
+            accesses: RefCell::new(Vec::new()),
+        };
+        let result = call(&mut tap);
+        self.state.borrow_mut().calls.push(Call {
+            name: name.to_string(),
+            args: args.to_vec(),
+            result: result.as_ref().ok().copied(),
+            memory: tap.accesses.into_inner(),
+        });
+        result
+    }
+}
+
+/// Guest memory that logs every access.
+struct Tap<'m> {
+    inner: &'m mut dyn GuestMemory,
+    accesses: RefCell<Vec<Access>>,
+}
+
+impl Tap<'_> {
+    fn log(&self, write: bool, ptr: u32, bytes: &[u8]) {
+        self.accesses.borrow_mut().push(Access {
+            write,
+            ptr,
+            bytes: bytes.to_vec(),
+        });
+    }
+}
+
+impl GuestMemory for Tap<'_> {
+    fn read(&self, ptr: u32, buf: &mut [u8]) -> Result<()> {
+        self.inner.read(ptr, buf)?;
+        self.log(false, ptr, buf);
+        Ok(())
+    }
+
+    fn write(&mut self, ptr: u32, data: &[u8]) -> Result<()> {
+        self.inner.write(ptr, data)?;
+        self.log(true, ptr, data);
+        Ok(())
+    }
+}
+
+/// `msg_input` / `msg_output` for one probe.
+pub struct MessageImports {
+    probe: Probe,
+}
+
+impl MessageImports {
+    fn msg_input(input: &[u8], ptr: i32, cap: i32, memory: &mut dyn GuestMemory) -> i32 {
+        let Ok(cap) = usize::try_from(cap) else {
+            return E_INVALID;
+        };
+        if input.len() <= cap && memory.write(ptr as u32, input).is_err() {
+            return E_INVALID;
+        }
+        input.len() as i32
+    }
+
+    fn msg_output(ptr: i32, len: i32, memory: &mut dyn GuestMemory) -> i32 {
+        let Some(len) = usize::try_from(len)
+            .ok()
+            .filter(|len| *len <= MAX_MESSAGE_LEN)
+        else {
+            return E_INVALID;
+        };
+        let mut buf = alloc::vec![0u8; len];
+        memory.read(ptr as u32, &mut buf).map_or(E_INVALID, |_| OK)
+    }
+}
+
+impl HostImports for MessageImports {
+    fn functions(&self) -> &[HostFn] {
+        FUNCTIONS
+    }
+
+    fn call(&mut self, name: &str, args: &[i32], memory: &mut dyn GuestMemory) -> Result<i32> {
+        let input = self.probe.state.borrow().input.clone();
+        self.probe
+            .record(name, args, memory, |memory| match (name, args) {
+                ("msg_input", &[ptr, cap]) => Ok(Self::msg_input(&input, ptr, cap, memory)),
+                ("msg_output", &[ptr, len]) => Ok(Self::msg_output(ptr, len, memory)),
+                _ => Err(Error::Engine("diff: bad host call")),
+            })
+    }
+}
+
+/// Another import set whose calls (and memory accesses) land in the transcript.
+pub struct Recorded<I> {
+    inner: I,
+    probe: Probe,
+}
+
+impl<I: HostImports> HostImports for Recorded<I> {
+    fn functions(&self) -> &[HostFn] {
+        self.inner.functions()
+    }
+
+    fn call(&mut self, name: &str, args: &[i32], memory: &mut dyn GuestMemory) -> Result<i32> {
+        self.call_with(name, args, memory, &mut HostContext::none())
+    }
+
+    fn call_with(
+        &mut self,
+        name: &str,
+        args: &[i32],
+        memory: &mut dyn GuestMemory,
+        ctx: &mut HostContext<'_>,
+    ) -> Result<i32> {
+        let inner = &mut self.inner;
+        self.probe.record(name, args, memory, |memory| {
+            inner.call_with(name, args, memory, ctx)
+        })
+    }
+
+    fn enter(&mut self, module_id: ModuleId) {
+        self.inner.enter(module_id);
+    }
+
+    fn exit(&mut self) {
+        self.inner.exit();
+    }
+}
+
+/// First difference found for one input.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Divergence {
+    /// Index into the inputs passed to `check`.
+    pub input: usize,
+    /// First differing host call; `None` when only the outcome differs.
+    pub call: Option<usize>,
+    pub left: Run,
+    pub right: Run,
+}
+
+impl fmt::Display for Divergence {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.call {
+            Some(index) => write!(
+                f,
+                "input #{}: host call #{index} differs: {:?} vs {:?}",
+                self.input,
+                self.left.calls.get(index),
+                self.right.calls.get(index)
+            ),
+            None => write!(
+                f,
+                "input #{}: outcome differs: {:?} vs {:?}",
+                self.input, self.left.outcome, self.right.outcome
+            ),
+        }
+    }
+}
+
+/// Result of a differential check.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct Report {
+    pub runs: usize,
+    pub divergences: Vec<Divergence>,
+}
+
+impl Report {
+    /// True when every input behaved the same on both engines.
+    pub fn is_clean(&self) -> bool {
+        self.divergences.is_empty()
+    }
+}
+
+/// Two engines, each linked with its own `Probe`.
+pub struct Differential<A, B> {
+    left: (A, Probe),
+    right: (B, Probe),
+}
+
+impl<A, B> Differential<A, B>
+where
+    A: Engine<Context = ()>,
+    B: Engine<Context = ()>,
+{
+    /// `left_probe` / `right_probe` must be the probes whose imports were
+    /// handed to the respective engine.
+    pub fn new(left: A, left_probe: Probe, right: B, right_probe: Probe) -> Self {
+        Self {
+            left: (left, left_probe),
+            right: (right, right_probe),
+        }
+    }
+
+    /// Runs `entry` once per input on both engines and compares transcripts.
+    pub fn check(&mut self, module: &[u8], entry: &str, inputs: &[Vec<u8>]) -> Report {
+        let mut report = Report::default();
+        for (index, input) in inputs.iter().enumerate() {
+            let left = run(&mut self.left.0, &self.left.1, module, entry, input);
+            let right = run(&mut self.right.0, &self.right.1, module, entry, input);
+            report.runs += 1;
+            if let Some(divergence) = compare(index, left, right) {
+                report.divergences.push(divergence);
+            }
+        }
+        report
+    }
+
+    /// Returns both engines.
+    pub fn into_engines(self) -> (A, B) {
+        (self.left.0, self.right.0)
+    }
+}
+
+/// Runs `entry` on one engine for one input.
+pub(crate) fn run<E: Engine<Context = ()>>(
+    engine: &mut E,
+    probe: &Probe,
+    module: &[u8],
+    entry: &str,
+    input: &[u8],
+) -> Run {
+    probe.start(input);
+    let outcome = match engine.load(DIFF_MODULE_ID, module) {
+        Err(err) => Outcome::LoadFailed(err),
+        Ok(handle) => {
+            let outcome = match engine.invoke_status(handle, entry, &mut ()) {
+                Ok(status) => Outcome::Completed(status),
+                Err(err) => Outcome::Failed(err),
+            };
+            engine.drop_module(handle);
+            outcome
+        }
+    };
+    Run {
+        outcome,
+        calls: probe.finish(),
+    }
+}
+
+/// Compares two runs of the same input; `None` when they agree.
+pub fn compare(input: usize, left: Run, right: Run) -> Option<Divergence> {
+    let call = left
+        .calls
+        .iter()
+        .zip(&right.calls)
+        .position(|(l, r)| l != r)
+        .or_else(|| {
+            (left.calls.len() != right.calls.len()).then(|| left.calls.len().min(right.calls.len()))
+        });
+    if call.is_none() && left.outcome.same_kind(&right.outcome) {
+        return None;
+    }
+    Some(Divergence {
+        input,
+        call,
+        left,
+        right,
+    })
+}
+
+#[cfg(all(test, feature = "std"))]
+mod tests {
+    use super::*;
+    use crate::abi::Imports;
+
+    /// Echo engine: reads the input and emits it, optionally corrupting byte 0.
+    struct Echo {
+        imports: Imports,
+        corrupt: bool,
+    }
+
+    impl Engine for Echo {
+        type ModuleHandle = ModuleId;
+        type Context = ();
+
+        fn load(&mut self, id: ModuleId, module: &[u8]) -> Result<ModuleId> {
+            if module.is_empty() {
+                return Err(Error::Engine("empty"));
+            }
+            Ok(id)
+        }
+
+        fn invoke(&mut self, _handle: ModuleId, entry: &str, _ctx: &mut ()) -> Result<()> {
+            if entry != "main" {
+                return Err(Error::EntryNotFound);
+            }
+            let mut memory = [0u8; 64];
+            let len = self.imports.call("msg_input", &[0, 64], &mut memory)?;
+            if self.corrupt && len > 0 {
+                memory[0] ^= 0xff;
+            }
+            self.imports.call("msg_output", &[0, len], &mut memory)?;
+            Ok(())
+        }
+    }
+
+    fn echo(corrupt: bool) -> (Echo, Probe) {
+        let probe = Probe::new();
+        let engine = Echo {
+            imports: Imports::new().with(probe.imports()),
+            corrupt,
+        };
+        (engine, probe)
+    }
+
+    #[test]
+    fn identical_engines_agree() {
+        let (a, pa) = echo(false);
+        let (b, pb) = echo(false);
+        let mut diff = Differential::new(a, pa, b, pb);
+        let report = diff.check(&[1], "main", &[b"hi".to_vec(), Vec::new()]);
+        assert_eq!(report.runs, 2);
+        assert!(report.is_clean());
+        assert!(diff.check(&[1], "other", &[Vec::new()]).is_clean());
+    }
+
+    #[test]
+    fn reports_first_divergent_call() {
+        let (a, pa) = echo(false);
+        let (b, pb) = echo(true);
+        let mut diff = Differential::new(a, pa, b, pb);
+        let report = diff.check(&[1], "main", &[Vec::new(), b"ok".to_vec()]);
+        assert_eq!(report.divergences.len(), 1);
+        let divergence = &report.divergences[0];
+        assert_eq!((divergence.input, divergence.call), (1, Some(1)));
+        assert_eq!(divergence.left.output(), b"ok");
+        assert_eq!(divergence.right.output(), [b'o' ^ 0xff, b'k']);
+        assert!(divergence.to_string().starts_with("input #1: host call #1"));
+    }
+
+    #[cfg(feature = "engine-wasmtime-lite")]
+    #[test]
+    fn wasmtime_against_itself_is_clean() {
+        use crate::engines::wasmtime_lite::WasmtimeLiteEngine;
+
+        let module = wat::parse_str(
+            r#"(module
+                (import "env" "msg_input" (func $input (param i32 i32) (result i32)))
+                (import "env" "msg_output" (func $output (param i32 i32) (result i32)))
+                (memory (export "memory") 1)
+                (func (export "main")
+                    (drop (call $output (i32.const 0) (call $input (i32.const 0) (i32.const 64))))))"#,
+        )
+        .unwrap();
+        let lane = || {
+            let probe = Probe::new();
+            let mut engine = WasmtimeLiteEngine::new().unwrap();
+            engine.set_imports(Imports::new().with(probe.imports()));
+            (engine, probe)
+        };
+        let ((a, pa), (b, pb)) = (lane(), lane());
+        let mut diff = Differential::new(a, pa, b, pb);
+        let report = diff.check(&module, "main", &[b"abc".to_vec(), vec![7; 100]]);
+        assert!(report.is_clean(), "{:?}", report.divergences);
+        assert_eq!(report.runs, 2);
+    }
+}
@@ -0,0 +1,395 @@
+//! `FallbackEngine<A, B>`: run modules on `A` where it can, on `B` otherwise.
+//!
+//! Loading tries `A` first; a module `A` rejects with `Error::Unsupported` or
+//! an engine (compile) error is loaded on `B` instead. A module `A` loaded
+//! whose entry `A` then reports as `Unsupported` is moved to `B` for that and
+//! every later call, which needs its bytes: `new` keeps a copy of each module
+//! loaded on `A`, `load_only` keeps none and falls back at load only.
+//!
+//! Traps and other errors from a call are returned as they are; a call is
+//! never retried on `B` after it may have run on `A`.
+//!
+//! ```ignore
+//! let engine = FallbackEngine::new(WamrEngine::new(), Wasm3Engine::new(1024)?);
+//! ```
+
+use crate::crash::{Crash, CrashCapture};
+use crate::idmap::IdMap;
+use crate::schedule::Interrupter;
+use crate::{Engine, EngineCaps, Error, ModuleId, Result, StackStats};
+use alloc::vec::Vec;
+
+/// Which engine a module runs on.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FallbackHandle<HA, HB> {
+    Primary(ModuleId, HA),
+    Fallback(HB),
+}
+
+/// Engine `A` with `B` behind it; both share one context type.
+pub struct FallbackEngine<A: Engine, B: Engine> {
+    primary: A,
+    fallback: B,
+    /// Bytes of modules loaded on `A`, when invoke-time fallback is on.
+    bytes: Option<IdMap<Vec<u8>>>,
+    /// Modules moved to `B` after `A` could not run them.
+    moved: IdMap<B::ModuleHandle>,
+    /// Whether the last call ran on `B`, for `last_fuel` and `take_crash`.
+    last_on_fallback: bool,
+}
+
+impl<A, B> FallbackEngine<A, B>
+where
+    A: Engine,
+    B: Engine<Context = A::Context>,
+{
+    /// Falls back at load and at invoke; keeps a copy of each module on `A`.
+    pub fn new(primary: A, fallback: B) -> Self {
+        Self {
+            bytes: Some(IdMap::new()),
+            ..Self::load_only(primary, fallback)
+        }
+    }
+
+    /// Falls back at load only; an `Unsupported` call on `A` is returned.
+    pub fn load_only(primary: A, fallback: B) -> Self {
+        Self {
+            primary,
+            fallback,
+            bytes: None,
+            moved: IdMap::new(),
+            last_on_fallback: false,
+        }
+    }
+
+    pub fn primary(&mut self) -> &mut A {
+        &mut self.primary
+    }
+
+    pub fn fallback(&mut self) -> &mut B {
+        &mut self.fallback
+    }
+
+    /// Whether module `id` now runs on `B` after `A` could not run an entry.
+    pub fn moved(&self, id: ModuleId) -> bool {
+        self.moved.contains(id)
+    }
+
+    fn call<T>(
+        &mut self,
+        handle: FallbackHandle<A::ModuleHandle, B::ModuleHandle>,
+        ctx: &mut A::Context,
+        on_primary: impl FnOnce(&mut A, A::ModuleHandle, &mut A::Context) -> Result<T>,
+        on_fallback: impl FnOnce(&mut B, B::ModuleHandle, &mut A::Context) -> Result<T>,
+    ) -> Result<T> {
+        let (id, handle) = match handle {
+            FallbackHandle::Fallback(handle) => {
+                self.last_on_fallback = true;
+                return on_fallback(&mut self.fallback, handle, ctx);
+            }
+            FallbackHandle::Primary(id, handle) => (id, handle),
+        };
+        if let Some(&moved) = self.moved.get(id) {
+            self.last_on_fallback = true;
+            return on_fallback(&mut self.fallback, moved, ctx);
+        }
+        self.last_on_fallback = false;
+        match on_primary(&mut self.primary, handle, ctx) {
+            Err(Error::Unsupported) => {
+                let Some(bytes) = self.bytes.as_ref().and_then(|bytes| bytes.get(id)) else {
+                    return Err(Error::Unsupported);
+                };
+                let moved = self.fallback.load(id, bytes)?;
+                self.moved.insert(id, moved)?;
+                self.last_on_fallback = true;
+                on_fallback(&mut self.fallback, moved, ctx)
+            }
+            result => result,
+        }
+    }
+}
+
+impl<A, B> Engine for FallbackEngine<A, B>
+where
+    A: Engine,
+    B: Engine<Context = A::Context>,
+{
+    type ModuleHandle = FallbackHandle<A::ModuleHandle, B::ModuleHandle>;
+    type Context = A::Context;
+
+    fn load(&mut self, id: ModuleId, module: &[u8]) -> Result<Self::ModuleHandle> {
+        // New bytes get a new chance on `A`.
+        if let Some(moved) = self.moved.remove(id) {
+            self.fallback.drop_module(moved);
+        }
+        match self.primary.load(id, module) {
+            Ok(handle) => {
+                if let Some(bytes) = self.bytes.as_mut() {
+                    bytes.insert(id, module.to_vec())?;
+                }
+                Ok(FallbackHandle::Primary(id, handle))
+            }
+            Err(Error::Unsupported | Error::Engine(_)) => {
+                self.fallback.load(id, module).map(FallbackHandle::Fallback)
+            }
+            Err(err) => Err(err),
+        }
+    }
+
+    fn invoke(
+        &mut self,
+        handle: Self::ModuleHandle,
+        entry: &str,
+        ctx: &mut Self::Context,
+    ) -> Result<()> {
+        self.call(
+            handle,
+            ctx,
+            |a, handle, ctx| a.invoke(handle, entry, ctx),
+            |b, handle, ctx| b.invoke(handle, entry, ctx),
+        )
+    }
+
+    fn invoke_status(
+        &mut self,
+        handle: Self::ModuleHandle,
+        entry: &str,
+        ctx: &mut Self::Context,
+    ) -> Result<i32> {
+        self.call(
+            handle,
+            ctx,
+            |a, handle, ctx| a.invoke_status(handle, entry, ctx),
+            |b, handle, ctx| b.invoke_status(handle, entry, ctx),
+        )
+    }
+
+    /// What both engines support, since a module may run on either.
+    fn capabilities(&self) -> EngineCaps {
+        let (a, b) = (self.primary.capabilities(), self.fallback.capabilities());
+        EngineCaps {
+            typed_calls: a.typed_calls && b.typed_calls,
+            imports: a.imports && b.imports,
+            memory_access: a.memory_access && b.memory_access,
+            fuel: a.fuel && b.fuel,
+            xip: a.xip && b.xip,
+            multiple_instances: a.multiple_instances && b.multiple_instances,
+            aot: a.aot && b.aot,
+            snapshots: a.snapshots && b.snapshots,
+        }
+    }
+
+    fn last_fuel(&self) -> Option<u64> {
+        match self.last_on_fallback {
+            true => self.fallback.last_fuel(),
+            false => self.primary.last_fuel(),
+        }
+    }
+
+    fn stack_stats(&self, handle: Self::ModuleHandle) -> Option<StackStats> {
+        match handle {
+            FallbackHandle::Primary(id, _) if self.moved.contains(id) => {
+                self.fallback.stack_stats(*self.moved.get(id)?)
+            }
+            FallbackHandle::Primary(_, handle) => self.primary.stack_stats(handle),
+            FallbackHandle::Fallback(handle) => self.fallback.stack_stats(handle),
+        }
+    }
+
+    fn set_crash_capture(&mut self, capture: Option<CrashCapture>) {
+        self.primary.set_crash_capture(capture);
+        self.fallback.set_crash_capture(capture);
+    }
+
+    fn take_crash(&mut self) -> Option<Crash> {
+        match self.last_on_fallback {
+            true => self.fallback.take_crash(),
+            false => self.primary.take_crash(),
+        }
+    }
+
+    fn snapshot(&mut self, handle: Self::ModuleHandle) -> Result<Vec<u8>> {
+        match handle {
+            FallbackHandle::Primary(id, _) if self.moved.contains(id) => {
+                let moved = *self.moved.get(id).ok_or(Error::ModuleNotFound)?;
+                self.fallback.snapshot(moved)
+            }
+            FallbackHandle::Primary(_, handle) => self.primary.snapshot(handle),
+            FallbackHandle::Fallback(handle) => self.fallback.snapshot(handle),
+        }
+    }
+
+    fn restore(&mut self, handle: Self::ModuleHandle, state: &[u8]) -> Result<()> {
+        match handle {
+            FallbackHandle::Primary(id, _) if self.moved.contains(id) => {
+                let moved = *self.moved.get(id).ok_or(Error::ModuleNotFound)?;
+                self.fallback.restore(moved, state)
+            }
+            FallbackHandle::Primary(_, handle) => self.primary.restore(handle, state),
+            FallbackHandle::Fallback(handle) => self.fallback.restore(handle, state),
+        }
+    }
+
+    /// Interrupts whichever engine is running; `None` only when neither can
+    /// be interrupted.
+    fn interrupter(&self) -> Option<Interrupter> {
+        match (self.primary.interrupter(), self.fallback.interrupter()) {
+            (Some(a), Some(b)) => Some(Interrupter::new(move || {
+                a.interrupt();
+                b.interrupt();
+            })),
+            (a, b) => a.or(b),
+        }
+    }
+
+    fn drop_module(&mut self, handle: Self::ModuleHandle) {
+        match handle {
+            FallbackHandle::Primary(id, handle) => {
+                self.primary.drop_module(handle);
+                if let Some(moved) = self.moved.remove(id) {
+                    self.fallback.drop_module(moved);
+                }
+                if let Some(bytes) = self.bytes.as_mut() {
+                    bytes.remove(id);
+                }
+            }
+            FallbackHandle::Fallback(handle) => self.fallback.drop_module(handle),
+        }
+    }
+
+    fn unload(&mut self, id: ModuleId) {
+        self.primary.unload(id);
+        self.fallback.unload(id);
+        self.moved.remove(id);
+        if let Some(bytes) = self.bytes.as_mut() {
+            bytes.remove(id);
+        }
+    }
+
+    #[cfg(feature = "secure-erase")]
+    fn secure_unload(&mut self, id: ModuleId) {
+        use zeroize::Zeroize;
+        self.primary.secure_unload(id);
+        self.fallback.secure_unload(id);
+        self.moved.remove(id);
+        if let Some(mut bytes) = self.bytes.as_mut().and_then(|bytes| bytes.remove(id)) {
+            bytes.zeroize();
+        }
+    }
+
+    /// Moved modules move again on their next call; the copies of their
+    /// bytes that takes are kept.
+    fn suspend(&mut self) {
+        self.primary.suspend();
+        self.fallback.suspend();
+        self.moved.clear();
+    }
+}
+
+#[cfg(all(test, feature = "std"))]
+mod tests {
+    use super::*;
+    use crate::{CachedEngine, MemoryStore, Runtime};
+    use alloc::string::{String, ToString};
+
+    /// Rejects modules not starting with `accept` at load, and entries named
+    /// `unsupported` at invoke; logs what ran as `"<name>:<entry>"`.
+    struct Picky {
+        name: &'static str,
+        accept: u8,
+    }
+
+    impl Engine for Picky {
+        type ModuleHandle = ModuleId;
+        type Context = Vec<String>;
+
+        fn load(&mut self, id: ModuleId, module: &[u8]) -> Result<ModuleId> {
+            match module.first() == Some(&self.accept) {
+                true => Ok(id),
+                false => Err(Error::Engine("compile")),
+            }
+        }
+
+        fn invoke(&mut self, _handle: ModuleId, entry: &str, log: &mut Vec<String>) -> Result<()> {
+            match entry {
+                "unsupported" => Err(Error::Unsupported),
+                "trap" => Err(Error::Engine("trap")),
+                _ => {
+                    log.push(self.name.to_string() + ":" + entry);
+                    Ok(())
+                }
+            }
+        }
+    }
+
+    fn engines() -> (Picky, Picky) {
+        (
+            Picky {
+                name: "aot",
+                accept: b'a',
+            },
+            Picky {
+                name: "interp",
+                accept: b'a',
+            },
+        )
+    }
+
+    #[test]
+    fn falls_back_on_load_and_on_unsupported_calls() {
+        let (aot, mut interp) = engines();
+        interp.accept = b'i';
+        let mut store = MemoryStore::new();
+        store.upsert(1, b"aot".to_vec());
+        store.upsert(2, b"interp".to_vec());
+        let engine = CachedEngine::new(FallbackEngine::new(aot, interp));
+        let mut runtime = Runtime::new(engine, store);
+        let mut log = Vec::new();
+
+        runtime.execute(1, "main", &mut log).unwrap();
+        runtime.execute(2, "main", &mut log).unwrap();
+        assert_eq!(log, ["aot:main", "interp:main"]);
+
+        // `interp` only accepts `i…` modules, so moving module 1 fails to load.
+        assert_eq!(
+            runtime.execute(1, "unsupported", &mut log),
+            Err(Error::Engine("compile"))
+        );
+        assert_eq!(
+            runtime.execute(1, "trap", &mut log),
+            Err(Error::Engine("trap"))
+        );
+    }
+
+    #[test]
+    fn moved_modules_stay_on_the_fallback() {
+        let (aot, interp) = engines();
+        let mut engine = FallbackEngine::new(aot, interp);
+        let mut log = Vec::new();
+        let handle = engine.load(1, b"a").unwrap();
+        assert!(matches!(handle, FallbackHandle::Primary(1, _)));
+        assert_eq!(
+            engine.invoke(handle, "unsupported", &mut log),
+            Err(Error::Unsupported)
+        );
+        // Moved to `interp`, which does not support the entry either.
+        assert!(engine.moved(1));
+        engine.invoke(handle, "main", &mut log).unwrap();
+        assert_eq!(log, ["interp:main"]);
+
+        engine.unload(1);
+        let handle = engine.load(1, b"a").unwrap();
+        engine.invoke(handle, "main", &mut log).unwrap();
+        assert_eq!(log, ["interp:main", "aot:main"]);
+
+        let (aot, interp) = engines();
+        let mut engine = FallbackEngine::load_only(aot, interp);
+        let handle = engine.load(1, b"a").unwrap();
+        assert_eq!(
+            engine.invoke(handle, "unsupported", &mut log),
+            Err(Error::Unsupported)
+        );
+        assert!(!engine.moved(1));
+        assert!(engine.load(1, b"x").is_err());
+    }
+}
@@ -1,8 +1,15 @@
 //! Optional engine backends.
 
+#[cfg(feature = "alloc")]
+pub mod fallback;
+pub mod native;
+#[cfg(feature = "engine-tinywasm")]
+pub mod tinywasm;
 #[cfg(feature = "engine-wamr")]
 pub mod wamr;
 #[cfg(feature = "engine-wasm3")]
 pub mod wasm3;
-#[cfg(feature = "engine-wasmtime-lite")]
+#[cfg(feature = "engine-wasmi")]
+pub mod wasmi;
+#[cfg(any(feature = "engine-wasmtime-lite", feature = "engine-wasmtime-winch"))]
 pub mod wasmtime_lite;
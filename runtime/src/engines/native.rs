@@ -0,0 +1,219 @@
+//! `NativeEngine`: built-in modules compiled into the firmware.
+//!
+//! A const table maps module ids to Rust functions, so built-in logic runs
+//! through the same `Runtime` as OTA wasm: state gates, quotas, schedules,
+//! traces and metrics all see it as one more module. Combine it with a wasm
+//! engine through `FallbackEngine` (ids not in the table are `Unsupported`)
+//! and wrap the store in `NativeSource` so table ids resolve without bytes:
+//!
+//! ```ignore
+//! static BUILTINS: &[NativeModule<Ctx>] = &[NativeModule {
+//!     id: 1,
+//!     entries: &[("main", read_sensors), ("health", |_| Ok(0))],
+//! }];
+//!
+//! let engine = FallbackEngine::load_only(NativeEngine::new(BUILTINS), Wasm3Engine::new(1024)?);
+//! let runtime = Runtime::new(engine, NativeSource::new(BUILTINS, store));
+//! ```
+
+use crate::{Engine, EngineCaps, Error, ModuleCatalog, ModuleId, ModuleSource, Result};
+
+/// Image `NativeSource` returns for table ids; `NativeEngine` ignores it.
+pub const NATIVE_IMAGE: &[u8] = b"\0nat";
+
+/// A built-in entry: gets the invocation context, returns a status (0 = ok).
+pub type NativeFn<C> = fn(&mut C) -> Result<i32>;
+
+/// One built-in module.
+pub struct NativeModule<C: 'static> {
+    pub id: ModuleId,
+    /// `(export name, function)` pairs.
+    pub entries: &'static [(&'static str, NativeFn<C>)],
+}
+
+/// Runs the modules of a const table; loads of other ids are `Unsupported`.
+pub struct NativeEngine<C: 'static> {
+    table: &'static [NativeModule<C>],
+}
+
+impl<C> NativeEngine<C> {
+    pub const fn new(table: &'static [NativeModule<C>]) -> Self {
+        Self { table }
+    }
+
+    pub fn table(&self) -> &'static [NativeModule<C>] {
+        self.table
+    }
+}
+
+impl<C> Engine for NativeEngine<C> {
+    /// Index into the table.
+    type ModuleHandle = usize;
+    type Context = C;
+
+    fn load(&mut self, id: ModuleId, _module: &[u8]) -> Result<usize> {
+        self.table
+            .iter()
+            .position(|module| module.id == id)
+            .ok_or(Error::Unsupported)
+    }
+
+    fn invoke(&mut self, handle: usize, entry: &str, ctx: &mut C) -> Result<()> {
+        self.invoke_status(handle, entry, ctx).map(|_| ())
+    }
+
+    fn invoke_status(&mut self, handle: usize, entry: &str, ctx: &mut C) -> Result<i32> {
+        let module = self.table.get(handle).ok_or(Error::ModuleNotFound)?;
+        let (_, func) = module
+            .entries
+            .iter()
+            .find(|(name, _)| *name == entry)
+            .ok_or(Error::EntryNotFound)?;
+        func(ctx)
+    }
+
+    /// Built-ins run from flash and share nothing; there is no guest memory
+    /// or import table to speak of.
+    fn capabilities(&self) -> EngineCaps {
+        EngineCaps {
+            typed_calls: true,
+            xip: true,
+            multiple_instances: true,
+            ..EngineCaps::NONE
+        }
+    }
+}
+
+/// A `ModuleSource` that serves `NATIVE_IMAGE` for table ids and defers to
+/// `inner` for the rest. Table ids shadow stored modules with the same id.
+pub struct NativeSource<C: 'static, S> {
+    table: &'static [NativeModule<C>],
+    inner: S,
+}
+
+impl<C, S> NativeSource<C, S> {
+    pub fn new(table: &'static [NativeModule<C>], inner: S) -> Self {
+        Self { table, inner }
+    }
+
+    pub fn inner(&self) -> &S {
+        &self.inner
+    }
+
+    pub fn inner_mut(&mut self) -> &mut S {
+        &mut self.inner
+    }
+
+    pub fn into_inner(self) -> S {
+        self.inner
+    }
+
+    fn is_native(&self, id: ModuleId) -> bool {
+        self.table.iter().any(|module| module.id == id)
+    }
+}
+
+impl<C, S: ModuleSource> ModuleSource for NativeSource<C, S> {
+    fn fetch(&self, id: ModuleId) -> Option<&[u8]> {
+        match self.is_native(id) {
+            true => Some(NATIVE_IMAGE),
+            false => self.inner.fetch(id),
+        }
+    }
+}
+
+/// Lists table ids first, then the stored ones they do not shadow.
+impl<C, S: ModuleCatalog> ModuleCatalog for NativeSource<C, S> {
+    fn module_count(&self) -> usize {
+        self.table.len() + self.stored().count()
+    }
+
+    fn module_id_at(&self, index: usize) -> Option<ModuleId> {
+        match self.table.get(index) {
+            Some(module) => Some(module.id),
+            None => self.stored().nth(index - self.table.len()),
+        }
+    }
+}
+
+impl<C, S: ModuleCatalog> NativeSource<C, S> {
+    fn stored(&self) -> impl Iterator<Item = ModuleId> + '_ {
+        (0..self.inner.module_count())
+            .filter_map(|index| self.inner.module_id_at(index))
+            .filter(|id| !self.is_native(*id))
+    }
+}
+
+#[cfg(all(test, feature = "std"))]
+mod tests {
+    use super::*;
+    use crate::engines::fallback::FallbackEngine;
+    use crate::{MemoryStore, Runtime};
+    use alloc::vec::Vec;
+
+    fn count(calls: &mut Vec<u32>) -> Result<i32> {
+        calls.push(1);
+        Ok(0)
+    }
+
+    static BUILTINS: &[NativeModule<Vec<u32>>] = &[NativeModule {
+        id: 1,
+        entries: &[
+            ("main", count),
+            ("status", |calls| Ok(calls.len() as i32)),
+            ("fail", |_| Err(Error::Engine("sensor offline"))),
+        ],
+    }];
+
+    /// Stands in for a wasm engine: runs anything, logging the module id.
+    struct Wasm;
+
+    impl Engine for Wasm {
+        type ModuleHandle = ModuleId;
+        type Context = Vec<u32>;
+
+        fn load(&mut self, id: ModuleId, module: &[u8]) -> Result<ModuleId> {
+            match module.starts_with(b"\0asm") {
+                true => Ok(id),
+                false => Err(Error::Engine("not wasm")),
+            }
+        }
+
+        fn invoke(&mut self, id: ModuleId, _entry: &str, calls: &mut Vec<u32>) -> Result<()> {
+            calls.push(id);
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn runs_builtins_next_to_wasm_modules() {
+        let mut store = MemoryStore::new();
+        store.upsert(1, b"\0asm shadowed".to_vec());
+        store.upsert(2, b"\0asm".to_vec());
+        let engine = FallbackEngine::load_only(NativeEngine::new(BUILTINS), Wasm);
+        let mut runtime = Runtime::new(engine, NativeSource::new(BUILTINS, store));
+        let mut calls = Vec::new();
+
+        runtime.execute(1, "main", &mut calls).unwrap();
+        runtime.execute(2, "main", &mut calls).unwrap();
+        assert_eq!(calls, [1, 2]);
+        assert_eq!(runtime.execute_status(1, "status", &mut calls), Ok(2));
+        assert_eq!(
+            runtime.execute(1, "fail", &mut calls),
+            Err(Error::Engine("sensor offline"))
+        );
+        assert_eq!(
+            runtime.execute(1, "missing", &mut calls),
+            Err(Error::EntryNotFound)
+        );
+        assert_eq!(runtime.module_ids().collect::<Vec<_>>(), [1, 2]);
+    }
+
+    #[test]
+    fn rejects_ids_outside_the_table() {
+        let mut engine = NativeEngine::new(BUILTINS);
+        assert_eq!(engine.load(1, &[]), Ok(0));
+        assert_eq!(engine.load(2, NATIVE_IMAGE), Err(Error::Unsupported));
+        assert!(engine.capabilities().typed_calls);
+    }
+}
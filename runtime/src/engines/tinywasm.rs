@@ -0,0 +1,490 @@
+//! tinywasm-based engine for parts with well under 32 KiB of RAM (Cortex-M0
+//! class): a small pure-Rust interpreter whose stacks are allocated once at a
+//! fixed size, so a call never grows the heap behind the firmware's back.
+//!
+//! Footprint, per the `EngineConfig` stack budget (`stack_size` bytes, 4 KiB
+//! by default):
+//! - value stacks: half the budget for 32-bit slots, a quarter each for
+//!   64-bit and 128-bit slots, reserved up front for every call;
+//! - call stack: one frame per 64 bytes of budget (64 nested calls at 4 KiB);
+//! - the parsed module of every loaded id stays in RAM; with
+//!   `InstanceMode::Reload` only the module being run does;
+//! - linear memory comes in 64 KiB wasm pages, more than these parts have.
+//!   Guests either declare no memory or a small page size (the
+//!   custom-page-sizes proposal, e.g. `(memory 1 (pagesize 1))`). It is
+//!   allocated on first write, so a memory the guest never writes costs
+//!   nothing.
+//!
+//! Compared with wasm3 and wasmi it trades speed for size, and it neither
+//! meters fuel nor reports a stack high-water mark: `from_config` rejects
+//! `meter_fuel`, so fuel quotas need another engine.
+
+use alloc::rc::Rc;
+use alloc::vec::Vec;
+use core::cell::RefCell;
+use core::marker::PhantomData;
+use tinywasm::engine::Config;
+use tinywasm::types::{FuncType, MemoryType, WasmType, WasmValue};
+use tinywasm::{
+    Engine as TinyCore, FuncContext, HostFunction, Imports as TinyImports, LinearMemory, Memory,
+    MemoryBackend, Module, ModuleInstance, StackConfig, Store, Trap, VecMemory,
+};
+
+use crate::abi::{GuestMemory, Imports, Linker, IMPORT_MODULE, MAX_PARAMS};
+use crate::builder::{ConfigurableEngine, EngineConfig, DEFAULT_STACK_SIZE, WASM_PAGE_SIZE};
+use crate::crash::{self, Crash, CrashCapture, TrapKind};
+use crate::idmap::IdMap;
+use crate::manifest::PayloadKind;
+use crate::{Engine, EngineCaps, Error, ModuleId, Result, StackStats};
+
+/// Stack budget bytes per call frame.
+const FRAME_BUDGET: u32 = 64;
+
+/// tinywasm-backed engine; host imports see the `C` each call is invoked with.
+pub struct TinywasmEngine<C = ()> {
+    engine: TinyCore,
+    modules: IdMap<Module>,
+    imports: Rc<RefCell<Linker>>,
+    /// Linear memory cap in bytes.
+    max_memory: Option<usize>,
+    stats: IdMap<StackStats>,
+    crash_capture: Option<CrashCapture>,
+    crash: Option<Crash>,
+    _ctx: PhantomData<fn(&mut C)>,
+}
+
+impl<C: 'static> TinywasmEngine<C> {
+    /// Engine with a stack budget of `stack_size` bytes and no memory cap.
+    pub fn new(stack_size: u32) -> Self {
+        Self::with_memory_cap(stack_size, None)
+    }
+
+    fn with_memory_cap(stack_size: u32, max_memory: Option<usize>) -> Self {
+        let slots =
+            |share: u32, size: u32| StackConfig::fixed((stack_size / share / size) as usize);
+        let mut config = Config::new()
+            .with_value_stack_32(slots(2, 4))
+            .with_value_stack_64(slots(4, 8))
+            .with_value_stack_128(slots(4, 16))
+            .with_call_stack(StackConfig::fixed((stack_size / FRAME_BUDGET) as usize));
+        if let Some(max) = max_memory {
+            config = config.with_memory_backend(MemoryBackend::custom(move |ty: MemoryType| {
+                CappedMemory::new(ty, max)
+            }));
+        }
+        Self {
+            engine: TinyCore::new(config),
+            modules: IdMap::new(),
+            imports: Rc::new(RefCell::new(Linker::default())),
+            max_memory,
+            stats: IdMap::new(),
+            crash_capture: None,
+            crash: None,
+            _ctx: PhantomData,
+        }
+    }
+
+    /// Host functions linked into every module on invoke.
+    pub fn set_imports(&mut self, imports: Imports) {
+        *self.imports.borrow_mut() = Linker::new(imports);
+    }
+
+    /// Defines every host function with the ABI signature; tinywasm rejects
+    /// modules that import one with another signature at instantiation.
+    fn link(&self, store: &mut Store) -> TinyImports {
+        let mut linked = TinyImports::new();
+        let host_fns: Vec<_> = self.imports.borrow().functions().collect();
+        for host_fn in host_fns {
+            let params = [WasmType::I32; MAX_PARAMS];
+            let ty = FuncType::new(&params[..host_fn.params as usize], &[WasmType::I32]);
+            let imports = self.imports.clone();
+            let name = host_fn.name;
+            let func = HostFunction::from_untyped(
+                store,
+                &ty,
+                move |mut ctx: FuncContext<'_>, params: &[WasmValue]| {
+                    let args = params.iter().map(|val| match val {
+                        WasmValue::I32(val) => *val,
+                        _ => 0,
+                    });
+                    let memory = ctx.memory("memory").ok();
+                    let mut guest = Guest {
+                        memory,
+                        store: ctx.store_mut(),
+                    };
+                    let status = imports
+                        .borrow_mut()
+                        .call(name, args, &mut guest)
+                        .map_err(|_| Trap::HostFunction("host import failed".into()))?;
+                    Ok(alloc::vec![WasmValue::I32(status)])
+                },
+            );
+            linked.define(IMPORT_MODULE, name, func);
+        }
+        linked
+    }
+
+    /// Instantiates `handle` with the imports and runs `f` against it, then
+    /// records the page count of its exported `memory` and, on a trap, the
+    /// crash snapshot.
+    fn call<T>(
+        &mut self,
+        handle: ModuleId,
+        ctx: &mut C,
+        f: impl FnOnce(&mut Store, &ModuleInstance, &mut Option<TrapKind>) -> Result<T>,
+    ) -> Result<T> {
+        let module = self.modules.get(handle).ok_or(Error::ModuleNotFound)?;
+        // tinywasm allocates memories on first write, past where a backend
+        // error can fail instantiation, so oversized ones are refused here.
+        let oversized = |max: usize| {
+            module
+                .memory_types
+                .iter()
+                .any(|ty| ty.initial_size() > max as u64)
+        };
+        if self.max_memory.is_some_and(oversized) {
+            return Err(Error::Engine("tinywasm: instantiate"));
+        }
+        let mut store = Store::new(self.engine.clone());
+        let imports = self.link(&mut store);
+        self.imports.borrow_mut().enter(handle);
+
+        let mut trap = None;
+        // SAFETY: `ctx` stays borrowed until this returns, and is unbound
+        // right after the call.
+        unsafe { self.imports.borrow_mut().bind_context(ctx) };
+        let ran = ModuleInstance::instantiate(&mut store, module, Some(imports))
+            .map(|instance| (f(&mut store, &instance, &mut trap), instance));
+        self.imports.borrow_mut().unbind_context();
+        self.imports.borrow_mut().exit();
+        let (result, instance) = ran.map_err(|_| Error::Engine("tinywasm: instantiate"))?;
+        let memory = instance.memory("memory").ok();
+        let len = memory.and_then(|memory| memory.len(&store).ok());
+        if let Some(len) = len {
+            let mut stats = self.stats.get(handle).copied().unwrap_or_default();
+            stats.record(None, len.div_ceil(WASM_PAGE_SIZE) as u32);
+            self.stats.insert(handle, stats)?;
+        }
+        if let (Some(trap), Some(capture)) = (trap, self.crash_capture) {
+            let bytes = memory
+                .zip(len)
+                .and_then(|(memory, len)| memory.read_vec(&store, 0, len).ok())
+                .unwrap_or_default();
+            self.crash = Some(Crash {
+                trap,
+                memory_offset: capture.memory_offset,
+                memory: crash::memory_window(&bytes, &capture),
+                stack: Vec::new(),
+            });
+        }
+        result
+    }
+}
+
+impl<C: 'static> ConfigurableEngine for TinywasmEngine<C> {
+    /// tinywasm cannot meter fuel or keep state, so those settings are
+    /// rejected.
+    fn from_config(config: &EngineConfig, imports: Imports) -> Result<Self> {
+        if config.meter_fuel || config.keep_state {
+            return Err(Error::Unsupported);
+        }
+        let max_memory = config
+            .max_memory_pages
+            .map(|pages| pages as usize * WASM_PAGE_SIZE);
+        let mut engine = Self::with_memory_cap(config.stack_size, max_memory);
+        engine.set_imports(imports);
+        Ok(engine)
+    }
+}
+
+impl<C: 'static> Default for TinywasmEngine<C> {
+    fn default() -> Self {
+        Self::new(DEFAULT_STACK_SIZE)
+    }
+}
+
+impl<C: 'static> Engine for TinywasmEngine<C> {
+    type ModuleHandle = ModuleId;
+    type Context = C;
+
+    fn load(&mut self, id: ModuleId, module: &[u8]) -> Result<Self::ModuleHandle> {
+        if module.is_empty() {
+            return Err(Error::Engine("tinywasm: empty module"));
+        }
+        if PayloadKind::detect(module) == Some(PayloadKind::Aot) {
+            return Err(Error::Unsupported);
+        }
+        let parsed =
+            tinywasm::parse_bytes(module).map_err(|_| Error::Engine("tinywasm: compile"))?;
+        self.modules.insert(id, parsed)?;
+        Ok(id)
+    }
+
+    fn invoke(&mut self, handle: Self::ModuleHandle, entry: &str, ctx: &mut C) -> Result<()> {
+        self.call(handle, ctx, |store, instance, trap| {
+            let func = instance
+                .func::<(), ()>(store, entry)
+                .map_err(|_| Error::EntryNotFound)?;
+            func.call(store, ()).map_err(|err| trapped(err, trap))
+        })
+    }
+
+    fn invoke_status(
+        &mut self,
+        handle: Self::ModuleHandle,
+        entry: &str,
+        ctx: &mut C,
+    ) -> Result<i32> {
+        self.call(handle, ctx, |store, instance, trap| {
+            if let Ok(func) = instance.func::<(), i32>(store, entry) {
+                return func.call(store, ()).map_err(|err| trapped(err, trap));
+            }
+            let func = instance
+                .func::<(), ()>(store, entry)
+                .map_err(|_| Error::EntryNotFound)?;
+            func.call(store, ())
+                .map_err(|err| trapped(err, trap))
+                .map(|()| 0)
+        })
+    }
+
+    fn capabilities(&self) -> EngineCaps {
+        EngineCaps {
+            typed_calls: true,
+            imports: true,
+            memory_access: true,
+            multiple_instances: true,
+            ..EngineCaps::NONE
+        }
+    }
+
+    fn stack_stats(&self, handle: Self::ModuleHandle) -> Option<StackStats> {
+        self.stats.get(handle).copied()
+    }
+
+    fn set_crash_capture(&mut self, capture: Option<CrashCapture>) {
+        self.crash_capture = capture;
+    }
+
+    fn take_crash(&mut self) -> Option<Crash> {
+        self.crash.take()
+    }
+
+    fn unload(&mut self, id: ModuleId) {
+        self.modules.remove(id);
+        self.stats.remove(id);
+    }
+}
+
+/// Guest memory as seen from a host import.
+struct Guest<'a> {
+    memory: Option<Memory>,
+    store: &'a mut Store,
+}
+
+impl GuestMemory for Guest<'_> {
+    fn read(&self, ptr: u32, buf: &mut [u8]) -> Result<()> {
+        let memory = self
+            .memory
+            .ok_or(Error::Engine("guest memory out of bounds"))?;
+        memory
+            .read_exact(self.store, ptr as usize, buf)
+            .map_err(|_| Error::Engine("guest memory out of bounds"))
+    }
+
+    fn write(&mut self, ptr: u32, data: &[u8]) -> Result<()> {
+        let memory = self
+            .memory
+            .ok_or(Error::Engine("guest memory out of bounds"))?;
+        memory
+            .copy_from_slice(self.store, ptr as usize, data)
+            .map_err(|_| Error::Engine("guest memory out of bounds"))
+    }
+}
+
+/// `VecMemory` that refuses to grow past `max` bytes; `memory.grow` past it
+/// returns -1.
+struct CappedMemory {
+    inner: VecMemory,
+    max: usize,
+}
+
+impl CappedMemory {
+    fn new(ty: MemoryType, max: usize) -> tinywasm::Result<Self> {
+        let len = usize::try_from(ty.initial_size())
+            .ok()
+            .filter(|len| *len <= max)
+            .ok_or(tinywasm::Error::Trap(Trap::OutOfMemory))?;
+        Ok(Self {
+            inner: VecMemory::try_new(len)?,
+            max,
+        })
+    }
+}
+
+impl LinearMemory for CappedMemory {
+    fn len(&self) -> usize {
+        self.inner.len()
+    }
+
+    fn grow_to(&mut self, new_len: usize) -> core::result::Result<(), Trap> {
+        match new_len <= self.max {
+            true => self.inner.grow_to(new_len),
+            false => Err(Trap::OutOfMemory),
+        }
+    }
+
+    fn read(&self, addr: usize, dst: &mut [u8]) -> usize {
+        self.inner.read(addr, dst)
+    }
+
+    fn write(&mut self, addr: usize, src: &[u8]) -> usize {
+        self.inner.write(addr, src)
+    }
+}
+
+/// Maps a call error, noting in `trap` how the call trapped.
+fn trapped(err: tinywasm::Error, trap: &mut Option<TrapKind>) -> Error {
+    match err {
+        tinywasm::Error::Trap(cause) => {
+            *trap = Some(trap_kind(&cause));
+            Error::Engine(cause.message())
+        }
+        _ => Error::Engine("tinywasm: call"),
+    }
+}
+
+fn trap_kind(trap: &Trap) -> TrapKind {
+    match trap {
+        Trap::Unreachable => TrapKind::Unreachable,
+        Trap::HostFunction(_) => TrapKind::HostError,
+        Trap::MemoryOutOfBounds { .. } => TrapKind::MemoryOutOfBounds,
+        Trap::CallStackOverflow | Trap::ValueStackOverflow => TrapKind::StackOverflow,
+        Trap::DivisionByZero => TrapKind::DivisionByZero,
+        Trap::IntegerOverflow => TrapKind::IntegerOverflow,
+        Trap::InvalidConversionToInt => TrapKind::BadConversion,
+        Trap::TableOutOfBounds { .. }
+        | Trap::UndefinedElement { .. }
+        | Trap::UninitializedElement { .. }
+        | Trap::IndirectCallTypeMismatch { .. } => TrapKind::IndirectCall,
+        _ => TrapKind::Other,
+    }
+}
+
+#[cfg(all(test, feature = "std"))]
+mod tests {
+    use super::*;
+    use crate::abi::{HostFn, HostImports, OK};
+
+    struct Recorder {
+        seen: Rc<RefCell<Vec<(i32, u8)>>>,
+    }
+
+    impl HostImports for Recorder {
+        fn functions(&self) -> &[HostFn] {
+            const FNS: &[HostFn] = &[HostFn::new("record", 2)];
+            FNS
+        }
+
+        fn call(&mut self, _name: &str, args: &[i32], memory: &mut dyn GuestMemory) -> Result<i32> {
+            let mut byte = [0u8; 1];
+            memory.read(args[1] as u32, &mut byte)?;
+            memory.write(args[1] as u32, &[byte[0] + 1])?;
+            self.seen.borrow_mut().push((args[0], byte[0]));
+            Ok(OK)
+        }
+    }
+
+    #[test]
+    fn runs_entries_in_one_byte_pages() {
+        let wasm = wat::parse_str(
+            r#"(module
+                (import "env" "record" (func $record (param i32 i32) (result i32)))
+                (memory (export "memory") 64 (pagesize 1))
+                (data (i32.const 16) "\2a")
+                (func (export "main") (drop (call $record (i32.const 7) (i32.const 16))))
+                (func (export "health") (result i32) (i32.load8_u (i32.const 16)))
+                (func (export "trap") unreachable))"#,
+        )
+        .unwrap();
+
+        let seen = Rc::new(RefCell::new(Vec::new()));
+        let mut engine = TinywasmEngine::new(1024);
+        engine.set_imports(Imports::new().with(Recorder { seen: seen.clone() }));
+        engine.set_crash_capture(Some(CrashCapture::default()));
+
+        let handle = engine.load(1, &wasm).unwrap();
+        engine.invoke(handle, "main", &mut ()).unwrap();
+        assert_eq!(*seen.borrow(), vec![(7, 0x2a)]);
+        assert_eq!(engine.invoke_status(handle, "health", &mut ()), Ok(0x2a));
+        assert_eq!(engine.invoke_status(handle, "main", &mut ()), Ok(0));
+        assert_eq!(
+            engine.invoke(handle, "missing", &mut ()),
+            Err(Error::EntryNotFound)
+        );
+        assert_eq!(
+            engine.invoke(handle, "trap", &mut ()),
+            Err(Error::Engine("unreachable"))
+        );
+        assert_eq!(engine.take_crash().unwrap().trap, TrapKind::Unreachable);
+        assert_eq!(engine.stack_stats(handle).unwrap().memory_pages, 1);
+
+        assert_eq!(engine.load(2, b"\0aot\x03\0\0\0"), Err(Error::Unsupported));
+        assert_eq!(
+            engine.load(2, b"\0asm"),
+            Err(Error::Engine("tinywasm: compile"))
+        );
+        engine.unload(1);
+        assert_eq!(
+            engine.invoke(handle, "main", &mut ()),
+            Err(Error::ModuleNotFound)
+        );
+    }
+
+    #[test]
+    fn from_config_enforces_limits() {
+        let wasm = wat::parse_str(
+            r#"(module
+                (memory 2)
+                (func $deep (param i32)
+                    (br_if 0 (i32.eqz (local.get 0)))
+                    (call $deep (i32.sub (local.get 0) (i32.const 1))))
+                (func (export "shallow") (call $deep (i32.const 4)))
+                (func (export "deep") (call $deep (i32.const 100000)))
+                (func (export "grow") (result i32) (memory.grow (i32.const 1))))"#,
+        )
+        .unwrap();
+
+        let config = EngineConfig {
+            max_memory_pages: Some(1),
+            ..EngineConfig::default()
+        };
+        let mut engine = TinywasmEngine::from_config(&config, Imports::new()).unwrap();
+        let handle = engine.load(1, &wasm).unwrap();
+        assert_eq!(
+            engine.invoke(handle, "shallow", &mut ()),
+            Err(Error::Engine("tinywasm: instantiate"))
+        );
+
+        let config = EngineConfig {
+            max_memory_pages: Some(2),
+            ..EngineConfig::default()
+        };
+        let mut engine = TinywasmEngine::from_config(&config, Imports::new()).unwrap();
+        engine.set_crash_capture(Some(CrashCapture::default()));
+        let handle = engine.load(1, &wasm).unwrap();
+        engine.invoke(handle, "shallow", &mut ()).unwrap();
+        assert_eq!(engine.invoke_status(handle, "grow", &mut ()), Ok(-1));
+        assert!(engine.invoke(handle, "deep", &mut ()).is_err());
+        assert_eq!(engine.take_crash().unwrap().trap, TrapKind::StackOverflow);
+
+        let fuel = EngineConfig {
+            meter_fuel: true,
+            ..EngineConfig::default()
+        };
+        assert!(matches!(
+            TinywasmEngine::<()>::from_config(&fuel, Imports::new()),
+            Err(Error::Unsupported)
+        ));
+    }
+}
@@ -1,4 +1,11 @@
 //! WAMR engine placeholder (still unsupported). Replace with a real integration when stable bindings are available.
+//!
+//! Manifests mark `wamrc` output with `manifest::FLAG_AOT` (`packer --aot`);
+//! the integration should load those as AOT and everything else in the
+//! interpreter, and report `EngineCaps::aot`. Until then both kinds are
+//! `Unsupported`, so a `FallbackEngine` passes wasm payloads to its fallback.
+use crate::abi::Imports;
+use crate::builder::{ConfigurableEngine, EngineConfig};
 use crate::{Engine, Error, ModuleId, Result};
 
 pub struct WamrEngine;
@@ -9,6 +16,12 @@ impl WamrEngine {
     }
 }
 
+impl ConfigurableEngine for WamrEngine {
+    fn from_config(_config: &EngineConfig, _imports: Imports) -> Result<Self> {
+        Ok(Self::new())
+    }
+}
+
 impl Engine for WamrEngine {
     type ModuleHandle = ModuleId;
     type Context = ();
@@ -1,88 +1,311 @@
 //! Minimal wasm3-based engine implementation.
 //! Intended for host/tests and small targets that can link the interpreter.
 
+use alloc::rc::Rc;
 use alloc::vec::Vec;
-use wasm3::error::Error as Wasm3Error;
-use wasm3::{Environment, Runtime as M3Runtime};
+use core::cell::RefCell;
+use core::marker::PhantomData;
+use wasm3::error::{Error as Wasm3Error, Trap};
+use wasm3::{CallContext, Environment, Module as M3Module, Runtime as M3Runtime};
 
-use crate::{Engine, Error, ModuleId, Result};
+use crate::abi::{HostFn, Imports, Linker, IMPORT_MODULE};
+use crate::builder::{ConfigurableEngine, EngineConfig};
+use crate::crash::{self, Crash, CrashCapture, TrapKind};
+use crate::idmap::IdMap;
+use crate::manifest::PayloadKind;
+use crate::{Engine, EngineCaps, Error, ModuleId, Result, StackStats};
 
 /// Default stack size in "slots" (4 bytes each). 4 KiB is typically enough for tiny modules.
 pub const DEFAULT_STACK_SLOTS: u32 = 1024;
 
+/// Byte the value stack is painted with before each call; slots past the last
+/// byte that still differs from it were never used.
+const STACK_PAINT: u8 = 0xA5;
+
 /// wasm3-backed engine that reloads the module for each invocation.
 ///
 /// This keeps lifetimes simple and is still fast for small modules. Pair with
-/// `CachedEngine` to avoid repeated load costs when desired.
-pub struct Wasm3Engine {
+/// `CachedEngine` to avoid repeated load costs when desired. Host imports see
+/// the `C` each call is invoked with.
+pub struct Wasm3Engine<C = ()> {
     env: Environment,
     stack_slots: u32,
-    modules: Vec<(ModuleId, Vec<u8>)>,
+    modules: IdMap<Vec<u8>>,
+    imports: Rc<RefCell<Linker>>,
+    stats: IdMap<StackStats>,
+    crash_capture: Option<CrashCapture>,
+    crash: Option<Crash>,
+    _ctx: PhantomData<fn(&mut C)>,
 }
 
-impl Wasm3Engine {
+impl<C: 'static> Wasm3Engine<C> {
     /// Constructs a new engine with the provided stack size (in slots).
     pub fn new(stack_slots: u32) -> Result<Self> {
         let env = Environment::new().map_err(map_err)?;
         Ok(Self {
             env,
             stack_slots,
-            modules: Vec::new(),
+            modules: IdMap::new(),
+            imports: Rc::new(RefCell::new(Linker::default())),
+            stats: IdMap::new(),
+            crash_capture: None,
+            crash: None,
+            _ctx: PhantomData,
         })
     }
 
-    /// Replaces or inserts a module's bytes.
-    fn upsert_module(&mut self, id: ModuleId, bytes: Vec<u8>) {
-        if let Some((_, existing)) = self.modules.iter_mut().find(|(mid, _)| *mid == id) {
-            *existing = bytes;
-        } else {
-            self.modules.push((id, bytes));
-        }
+    /// Host functions linked into every module on invoke.
+    pub fn set_imports(&mut self, imports: Imports) {
+        *self.imports.borrow_mut() = Linker::new(imports);
     }
 
     fn module_bytes(&self, id: ModuleId) -> Result<&[u8]> {
         self.modules
-            .iter()
-            .find(|(mid, _)| *mid == id)
-            .map(|(_, bytes)| bytes.as_slice())
+            .get(id)
+            .map(Vec::as_slice)
             .ok_or(Error::ModuleNotFound)
     }
 }
 
-impl Engine for Wasm3Engine {
+impl<C: 'static> ConfigurableEngine for Wasm3Engine<C> {
+    /// The stack size is rounded down to whole slots. wasm3 can neither cap
+    /// linear memory, meter fuel nor keep state, so those settings are
+    /// rejected.
+    fn from_config(config: &EngineConfig, imports: Imports) -> Result<Self> {
+        if config.max_memory_pages.is_some() || config.meter_fuel || config.keep_state {
+            return Err(Error::Unsupported);
+        }
+        let mut engine = Self::new(config.stack_size / 4)?;
+        engine.set_imports(imports);
+        Ok(engine)
+    }
+}
+
+impl<C: 'static> Engine for Wasm3Engine<C> {
     type ModuleHandle = ModuleId;
-    type Context = ();
+    type Context = C;
 
     fn load(&mut self, id: ModuleId, module: &[u8]) -> Result<Self::ModuleHandle> {
         if module.is_empty() {
             return Err(Error::Engine("wasm3: empty module"));
         }
+        if PayloadKind::detect(module) == Some(PayloadKind::Aot) {
+            return Err(Error::Unsupported);
+        }
 
-        // wasm3 keeps a copy of the bytes, so store them for reloading on invoke.
-        self.upsert_module(id, module.to_vec());
+        // Parsed once here so malformed bytes fail the load, as on the other
+        // engines; wasm3 keeps a copy of the bytes, so store them for
+        // reloading on invoke.
+        M3Module::parse(&self.env, module).map_err(map_err)?;
+        self.modules.insert(id, module.to_vec())?;
         Ok(id)
     }
 
-    fn invoke(
+    fn invoke(&mut self, handle: Self::ModuleHandle, entry: &str, ctx: &mut C) -> Result<()> {
+        self.call(handle, ctx, |module| {
+            // Functions with no args/returns keep the footprint minimal for now.
+            let func: wasm3::Function<(), ()> = module.find_function(entry)?;
+            func.call()
+        })
+    }
+
+    fn invoke_status(
         &mut self,
         handle: Self::ModuleHandle,
         entry: &str,
-        _ctx: &mut Self::Context,
-    ) -> Result<()> {
-        let bytes = self.module_bytes(handle)?;
+        ctx: &mut C,
+    ) -> Result<i32> {
+        self.call(handle, ctx, |module| {
+            match module.find_function::<(), i32>(entry) {
+                Ok(func) => func.call(),
+                Err(Wasm3Error::InvalidFunctionSignature) => {
+                    let func: wasm3::Function<(), ()> = module.find_function(entry)?;
+                    func.call().map(|()| 0)
+                }
+                Err(err) => Err(err),
+            }
+        })
+    }
+
+    fn capabilities(&self) -> EngineCaps {
+        EngineCaps {
+            typed_calls: true,
+            imports: true,
+            memory_access: true,
+            multiple_instances: true,
+            ..EngineCaps::NONE
+        }
+    }
+
+    fn stack_stats(&self, handle: Self::ModuleHandle) -> Option<StackStats> {
+        self.stats.get(handle).copied()
+    }
+
+    fn set_crash_capture(&mut self, capture: Option<CrashCapture>) {
+        self.crash_capture = capture;
+    }
+
+    fn take_crash(&mut self) -> Option<Crash> {
+        self.crash.take()
+    }
+
+    fn unload(&mut self, id: ModuleId) {
+        self.modules.remove(id);
+        self.stats.remove(id);
+    }
+
+    #[cfg(feature = "secure-erase")]
+    fn secure_unload(&mut self, id: ModuleId) {
+        use zeroize::Zeroize;
+        if let Some(mut bytes) = self.modules.remove(id) {
+            bytes.zeroize();
+        }
+        self.stats.remove(id);
+    }
+}
+
+impl<C: 'static> Wasm3Engine<C> {
+    /// Parses `handle` into a fresh wasm3 runtime, links the imports and runs
+    /// `f`, recording the stack high-water mark and memory size afterwards
+    /// and, on a trap, the crash snapshot.
+    fn call<T>(
+        &mut self,
+        handle: ModuleId,
+        ctx: &mut C,
+        f: impl FnOnce(&M3Module<'_>) -> core::result::Result<T, Wasm3Error>,
+    ) -> Result<T> {
+        let bytes = self.module_bytes(handle)?.to_vec();
 
         let runtime = M3Runtime::new(&self.env, self.stack_slots).map_err(map_err)?;
-        let module = runtime
-            .parse_and_load_module(bytes.to_vec())
-            .map_err(map_err)?;
+        let mut module = runtime.parse_and_load_module(bytes).map_err(map_err)?;
+        let host_fns: Vec<HostFn> = self.imports.borrow().functions().collect();
+        for host_fn in host_fns {
+            link_host_fn(&mut module, host_fn, &self.imports)?;
+        }
+        self.imports.borrow_mut().enter(handle);
+
+        let stack = runtime.stack_mut();
+        // SAFETY: the stack belongs to `runtime` and nothing runs on it until
+        // `f`; slots are plain integers, so any byte pattern is valid.
+        unsafe { core::ptr::write_bytes(stack.cast::<u8>(), STACK_PAINT, size_of_slots(stack)) };
+
+        // SAFETY: `ctx` stays borrowed until this returns, and is unbound
+        // right after the call.
+        unsafe { self.imports.borrow_mut().bind_context(ctx) };
+        let result = f(&module);
+        self.imports.borrow_mut().unbind_context();
+        self.imports.borrow_mut().exit();
+
+        // SAFETY: the call has returned, so nothing writes the stack.
+        let bytes = unsafe {
+            core::slice::from_raw_parts(runtime.stack().cast::<u8>(), size_of_slots(stack))
+        };
+        let slot_size = size_of_slots(stack) / stack.len().max(1);
+        let used = bytes
+            .iter()
+            .rposition(|byte| *byte != STACK_PAINT)
+            .map_or(0, |last| last / slot_size + 1);
+        // SAFETY: loading the module allocated the memory header (even without
+        // a memory section), and only running wasm reallocates it.
+        let memory = unsafe { &*runtime.memory() };
+        let pages = memory.len() / crate::builder::WASM_PAGE_SIZE;
+        let mut stats = self.stats.get(handle).copied().unwrap_or_default();
+        stats.record(Some(used as u32), pages as u32);
+        self.stats.insert(handle, stats)?;
 
-        // Functions with no args/returns keep the footprint minimal for now.
-        let func: wasm3::Function<(), ()> = module.find_function(entry).map_err(map_err)?;
-        func.call().map_err(map_err)?;
-        Ok(())
+        if let (Err(err), Some(capture)) = (&result, self.crash_capture) {
+            if let Some(trap) = trap_kind(err) {
+                // SAFETY: as above.
+                let slots = unsafe { &*runtime.stack() };
+                self.crash = Some(Crash {
+                    trap,
+                    memory_offset: capture.memory_offset,
+                    memory: crash::memory_window(memory, &capture),
+                    stack: slots
+                        .iter()
+                        .take(capture.stack_slots as usize)
+                        .map(|slot| u64::from(*slot))
+                        .collect(),
+                });
+            }
+        }
+        result.map_err(map_err)
     }
 }
 
+/// Size in bytes of the slots `stack` points to.
+fn size_of_slots<T>(stack: *mut [T]) -> usize {
+    stack.len() * core::mem::size_of::<T>()
+}
+
+/// Links one host function; modules that do not import it are left untouched.
+fn link_host_fn(
+    module: &mut M3Module<'_>,
+    host_fn: HostFn,
+    imports: &Rc<RefCell<Linker>>,
+) -> Result<()> {
+    // wasm3 needs the arity at compile time, so expand one arm per supported count.
+    macro_rules! link {
+        ($args:ty, |$($arg:ident),*|) => {{
+            let imports = imports.clone();
+            let name = host_fn.name;
+            module.link_closure::<$args, i32, _>(
+                IMPORT_MODULE,
+                name,
+                move |cc: CallContext<'_>, ($($arg),*): $args| {
+                    // SAFETY: the runtime keeps linear memory alive for the duration of the call.
+                    let mut memory = unsafe { &mut *cc.memory_mut() };
+                    imports
+                        .borrow_mut()
+                        .call(name, [$($arg),*], &mut memory)
+                        .map_err(|_| Trap::Abort)
+                },
+            )
+        }};
+    }
+
+    let linked = match host_fn.params {
+        0 => link!((), ||),
+        1 => link!(i32, |a|),
+        2 => link!((i32, i32), |a, b|),
+        3 => link!((i32, i32, i32), |a, b, c|),
+        4 => link!((i32, i32, i32, i32), |a, b, c, d|),
+        5 => link!((i32, i32, i32, i32, i32), |a, b, c, d, e|),
+        6 => link!((i32, i32, i32, i32, i32, i32), |a, b, c, d, e, f|),
+        7 => link!((i32, i32, i32, i32, i32, i32, i32), |a, b, c, d, e, f, g|),
+        8 => link!((i32, i32, i32, i32, i32, i32, i32, i32), |a, b, c, d, e, f, g, h|),
+        _ => return Err(Error::Engine("wasm3: too many host params")),
+    };
+    match linked {
+        Ok(()) | Err(Wasm3Error::FunctionNotFound) => Ok(()),
+        Err(err) => Err(map_err(err)),
+    }
+}
+
+/// Trap behind `err`; `None` when the call did not trap (e.g. missing entry).
+fn trap_kind(err: &Wasm3Error) -> Option<TrapKind> {
+    const KINDS: [(Trap, TrapKind); 9] = [
+        (Trap::Unreachable, TrapKind::Unreachable),
+        (Trap::OutOfBoundsMemoryAccess, TrapKind::MemoryOutOfBounds),
+        (Trap::StackOverflow, TrapKind::StackOverflow),
+        (Trap::DivisionByZero, TrapKind::DivisionByZero),
+        (Trap::IntegerOverflow, TrapKind::IntegerOverflow),
+        (Trap::IntegerConversion, TrapKind::BadConversion),
+        (Trap::IndirectCallTypeMismatch, TrapKind::IndirectCall),
+        (Trap::TableIndexOutOfRange, TrapKind::IndirectCall),
+        // Host imports report failures as aborts (see `link_host_fn`).
+        (Trap::Abort, TrapKind::HostError),
+    ];
+    let Wasm3Error::Wasm3(inner) = err else {
+        return None;
+    };
+    let kind = KINDS
+        .iter()
+        .find(|(trap, _)| inner.is_trap(*trap))
+        .map_or(TrapKind::Other, |(_, kind)| *kind);
+    Some(kind)
+}
+
 fn map_err(err: Wasm3Error) -> Error {
     match err {
         Wasm3Error::FunctionNotFound => Error::EntryNotFound,
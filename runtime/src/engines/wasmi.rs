@@ -0,0 +1,640 @@
+//! wasmi-based engine: a pure-Rust interpreter, so `no_std` builds need no C
+//! toolchain (wasm3 and WAMR both compile C).
+//!
+//! Modules are validated and compiled once at load and instantiated in a
+//! fresh store for every call, like `WasmtimeLiteEngine`; with
+//! `EngineConfig::keep_state` each call then continues from the state the
+//! previous one left (see `snapshot`).
+
+use alloc::vec::Vec;
+use core::marker::PhantomData;
+use wasmi::core::{Pages, TrapCode, ValueType, F32, F64};
+use wasmi::{
+    Caller, Config, Engine as WasmiCore, Extern, ExternType, Instance, Linker, Module, Mutability,
+    StackLimits, Store, StoreLimits, StoreLimitsBuilder, TypedFunc, Value, WasmResults,
+};
+
+use crate::abi::linker::{Linker as HostLinker, ValType};
+use crate::abi::{Imports, IMPORT_MODULE};
+use crate::builder::{ConfigurableEngine, EngineConfig, WASM_PAGE_SIZE};
+use crate::crash::{self, Crash, CrashCapture, TrapKind};
+use crate::idmap::IdMap;
+use crate::manifest::PayloadKind;
+use crate::snapshot::{GlobalValue, InstanceState};
+use crate::{Engine, EngineCaps, Error, ModuleId, Result, StackStats};
+
+/// Bytes per value-stack slot.
+const SLOT_SIZE: u32 = 8;
+/// Nested wasm calls allowed before a stack-overflow trap.
+const MAX_RECURSION_DEPTH: usize = 1024;
+
+/// wasmi-backed engine; host imports see the `C` each call is invoked with.
+pub struct WasmiEngine<C = ()> {
+    engine: WasmiCore,
+    modules: IdMap<Module>,
+    imports: HostLinker,
+    limits: StoreLimits,
+    meter_fuel: bool,
+    last_fuel: Option<u64>,
+    stats: IdMap<StackStats>,
+    crash_capture: Option<CrashCapture>,
+    crash: Option<Crash>,
+    keep_state: bool,
+    /// State each module's next call starts from, with `keep_state`.
+    states: IdMap<InstanceState>,
+    _ctx: PhantomData<fn(&mut C)>,
+}
+
+/// Per-call store data: the imports, the memory limit and how the call trapped.
+struct Host {
+    imports: HostLinker,
+    limits: StoreLimits,
+    trap: Option<TrapKind>,
+}
+
+impl<C: 'static> WasmiEngine<C> {
+    /// Engine with a value stack of `stack_size` bytes and no other limits.
+    pub fn new(stack_size: u32) -> Result<Self> {
+        Self::with_limits(stack_size, StoreLimits::default(), false)
+    }
+
+    fn with_limits(stack_size: u32, limits: StoreLimits, meter_fuel: bool) -> Result<Self> {
+        let slots = (stack_size / SLOT_SIZE) as usize;
+        let stack = StackLimits::new(slots, slots, MAX_RECURSION_DEPTH)
+            .map_err(|_| Error::Engine("wasmi: bad stack size"))?;
+        let mut config = Config::default();
+        config.set_stack_limits(stack).consume_fuel(meter_fuel);
+        Ok(Self {
+            engine: WasmiCore::new(&config),
+            modules: IdMap::new(),
+            imports: HostLinker::default(),
+            limits,
+            meter_fuel,
+            last_fuel: None,
+            stats: IdMap::new(),
+            crash_capture: None,
+            crash: None,
+            keep_state: false,
+            states: IdMap::new(),
+            _ctx: PhantomData,
+        })
+    }
+
+    /// Host functions linked into every module on invoke.
+    pub fn set_imports(&mut self, imports: Imports) {
+        self.imports = HostLinker::new(imports);
+    }
+
+    fn linker(&self, module: &Module) -> Result<Linker<Host>> {
+        let mut linker = Linker::new(&self.engine);
+        for import in module.imports() {
+            let ExternType::Func(ty) = import.ty() else {
+                continue;
+            };
+            let host_fn = self
+                .imports
+                .resolve(
+                    import.module(),
+                    import.name(),
+                    ty.params().iter().map(abi_type),
+                    ty.results().iter().map(abi_type),
+                )
+                .map_err(|_| Error::Engine("wasmi: host import signature mismatch"))?;
+            let Some(host_fn) = host_fn else {
+                continue;
+            };
+
+            let name = host_fn.name;
+            linker
+                .func_new(
+                    IMPORT_MODULE,
+                    name,
+                    ty.clone(),
+                    move |mut caller: Caller<'_, Host>, params: &[Value], results: &mut [Value]| {
+                        let args = params.iter().map(|val| val.i32().unwrap_or_default());
+                        let ret = match caller.get_export("memory").and_then(Extern::into_memory) {
+                            Some(memory) => {
+                                let (mut data, host) = memory.data_and_store_mut(&mut caller);
+                                host.imports.call(name, args, &mut data)
+                            }
+                            None => caller.data_mut().imports.call(name, args, &mut [0u8; 0]),
+                        };
+                        let status =
+                            ret.map_err(|_| wasmi::core::Trap::new("host import failed"))?;
+                        results[0] = Value::I32(status);
+                        Ok(())
+                    },
+                )
+                .map_err(|_| Error::Engine("wasmi: link host import"))?;
+        }
+        Ok(linker)
+    }
+
+    /// Instantiates `handle` with the imports (and its kept state) and runs
+    /// `f` against it, then records the size of its exported `memory` (wasmi
+    /// exposes no stack high-water mark), its new state and, on a trap, the
+    /// crash snapshot.
+    fn call<T>(
+        &mut self,
+        handle: ModuleId,
+        ctx: &mut C,
+        f: impl FnOnce(&mut Store<Host>, &Instance) -> Result<T>,
+    ) -> Result<T> {
+        let module = self.modules.get(handle).ok_or(Error::ModuleNotFound)?;
+        let linker = self.linker(module)?;
+
+        self.imports.enter(handle);
+        // SAFETY: `ctx` stays borrowed until this returns, and is unbound
+        // below once the imports are back.
+        unsafe { self.imports.bind_context(ctx) };
+        // Imports move into the store for the call and come back afterwards.
+        let host = Host {
+            imports: core::mem::take(&mut self.imports),
+            limits: self.limits.clone(),
+            trap: None,
+        };
+        let mut store = Store::new(&self.engine, host);
+        store.limiter(|host| &mut host.limits);
+        if self.meter_fuel {
+            // Cannot fail: the engine consumes fuel whenever `meter_fuel` is set.
+            let _ = store.add_fuel(u64::MAX);
+        }
+        let mut pages = None;
+        let capture = self.crash_capture;
+        let mut crash = None;
+        let kept = self.states.get(handle).filter(|_| self.keep_state);
+        let mut saved = None;
+        let result = linker
+            .instantiate(&mut store, module)
+            .and_then(|pre| pre.start(&mut store))
+            .map_err(|_| Error::Engine("wasmi: instantiate"))
+            .and_then(|instance| {
+                if let Some(state) = kept {
+                    load_state(&mut store, &instance, state)?;
+                }
+                let result = f(&mut store, &instance);
+                if self.keep_state && result.is_ok() {
+                    saved = Some(save_state(&store, &instance));
+                }
+                let memory = instance.get_memory(&store, "memory");
+                pages = memory.map(|memory| u32::from(memory.current_pages(&store)));
+                if let (Some(trap), Some(capture)) = (store.data().trap, capture) {
+                    crash = Some(Crash {
+                        trap,
+                        memory_offset: capture.memory_offset,
+                        memory: memory.map_or_else(Vec::new, |memory| {
+                            crash::memory_window(memory.data(&store), &capture)
+                        }),
+                        stack: Vec::new(),
+                    });
+                }
+                result
+            });
+        if crash.is_some() {
+            self.crash = crash;
+        }
+        self.last_fuel = store.fuel_consumed();
+        self.imports = store.into_data().imports;
+        self.imports.unbind_context();
+        self.imports.exit();
+        if let Some(pages) = pages {
+            let mut stats = self.stats.get(handle).copied().unwrap_or_default();
+            stats.record(None, pages);
+            self.stats.insert(handle, stats)?;
+        }
+        if let Some(state) = saved {
+            self.states.insert(handle, state)?;
+        }
+        result
+    }
+}
+
+/// Exported `memory` and mutable globals of `instance`.
+fn save_state(store: &Store<Host>, instance: &Instance) -> InstanceState {
+    let mut state = InstanceState::default();
+    for export in instance.exports(store) {
+        let name = export.name();
+        match export.into_extern() {
+            Extern::Memory(memory) if name == "memory" => {
+                state.memory = memory.data(store).to_vec();
+            }
+            Extern::Global(global)
+                if global.ty(store).mutability() == Mutability::Var
+                    && name.len() <= u8::MAX as usize =>
+            {
+                let value = match global.get(store) {
+                    Value::I32(v) => GlobalValue::I32(v),
+                    Value::I64(v) => GlobalValue::I64(v),
+                    Value::F32(v) => GlobalValue::F32(v.to_bits()),
+                    Value::F64(v) => GlobalValue::F64(v.to_bits()),
+                    _ => continue,
+                };
+                state.globals.push((name.into(), value));
+            }
+            _ => {}
+        }
+    }
+    state
+}
+
+/// Overwrites a fresh instance with a kept state.
+fn load_state(store: &mut Store<Host>, instance: &Instance, state: &InstanceState) -> Result<()> {
+    const MISMATCH: Error = Error::Engine("wasmi: state does not fit module");
+    if let Some(memory) = instance.get_memory(&*store, "memory") {
+        let missing = state
+            .memory
+            .len()
+            .saturating_sub(memory.data(&*store).len());
+        if missing > 0 {
+            let pages = u32::try_from(missing.div_ceil(WASM_PAGE_SIZE))
+                .ok()
+                .and_then(Pages::new)
+                .ok_or(MISMATCH)?;
+            memory.grow(&mut *store, pages).map_err(|_| MISMATCH)?;
+        }
+        memory
+            .write(&mut *store, 0, &state.memory)
+            .map_err(|_| MISMATCH)?;
+    } else if !state.memory.is_empty() {
+        return Err(MISMATCH);
+    }
+    for (name, value) in &state.globals {
+        let value = match *value {
+            GlobalValue::I32(v) => Value::I32(v),
+            GlobalValue::I64(v) => Value::I64(v),
+            GlobalValue::F32(v) => Value::F32(F32::from_bits(v)),
+            GlobalValue::F64(v) => Value::F64(F64::from_bits(v)),
+        };
+        let global = instance.get_global(&*store, name).ok_or(MISMATCH)?;
+        global.set(&mut *store, value).map_err(|_| MISMATCH)?;
+    }
+    Ok(())
+}
+
+impl<C: 'static> ConfigurableEngine for WasmiEngine<C> {
+    fn from_config(config: &EngineConfig, imports: Imports) -> Result<Self> {
+        let mut limits = StoreLimitsBuilder::new();
+        if let Some(pages) = config.max_memory_pages {
+            limits = limits.memory_size(pages as usize * WASM_PAGE_SIZE);
+        }
+        let mut engine = Self::with_limits(config.stack_size, limits.build(), config.meter_fuel)?;
+        engine.keep_state = config.keep_state;
+        engine.set_imports(imports);
+        Ok(engine)
+    }
+}
+
+impl<C: 'static> Engine for WasmiEngine<C> {
+    type ModuleHandle = ModuleId;
+    type Context = C;
+
+    fn load(&mut self, id: ModuleId, module: &[u8]) -> Result<Self::ModuleHandle> {
+        if module.is_empty() {
+            return Err(Error::Engine("wasmi: empty module"));
+        }
+        if PayloadKind::detect(module) == Some(PayloadKind::Aot) {
+            return Err(Error::Unsupported);
+        }
+        let compiled =
+            Module::new(&self.engine, module).map_err(|_| Error::Engine("wasmi: compile"))?;
+        self.modules.insert(id, compiled)?;
+        Ok(id)
+    }
+
+    fn invoke(&mut self, handle: Self::ModuleHandle, entry: &str, ctx: &mut C) -> Result<()> {
+        self.call(handle, ctx, |store, instance| {
+            let func = instance
+                .get_typed_func::<(), ()>(&*store, entry)
+                .map_err(|_| Error::EntryNotFound)?;
+            run(store, func)
+        })
+    }
+
+    fn invoke_status(
+        &mut self,
+        handle: Self::ModuleHandle,
+        entry: &str,
+        ctx: &mut C,
+    ) -> Result<i32> {
+        self.call(handle, ctx, |store, instance| {
+            if let Ok(func) = instance.get_typed_func::<(), i32>(&*store, entry) {
+                return run(store, func);
+            }
+            let func = instance
+                .get_typed_func::<(), ()>(&*store, entry)
+                .map_err(|_| Error::EntryNotFound)?;
+            run(store, func).map(|()| 0)
+        })
+    }
+
+    fn capabilities(&self) -> EngineCaps {
+        EngineCaps {
+            typed_calls: true,
+            imports: true,
+            memory_access: true,
+            // Metering is chosen when the engine is built.
+            fuel: self.meter_fuel,
+            multiple_instances: true,
+            snapshots: self.keep_state,
+            ..EngineCaps::NONE
+        }
+    }
+
+    fn last_fuel(&self) -> Option<u64> {
+        self.last_fuel
+    }
+
+    fn stack_stats(&self, handle: Self::ModuleHandle) -> Option<StackStats> {
+        self.stats.get(handle).copied()
+    }
+
+    fn set_crash_capture(&mut self, capture: Option<CrashCapture>) {
+        self.crash_capture = capture;
+    }
+
+    fn take_crash(&mut self) -> Option<Crash> {
+        self.crash.take()
+    }
+
+    /// A module that has not run yet snapshots as an empty state, which
+    /// restores to a fresh instance.
+    fn snapshot(&mut self, handle: Self::ModuleHandle) -> Result<Vec<u8>> {
+        if !self.keep_state {
+            return Err(Error::Unsupported);
+        }
+        self.modules.get(handle).ok_or(Error::ModuleNotFound)?;
+        Ok(self
+            .states
+            .get(handle)
+            .map_or_else(|| InstanceState::default().encode(), InstanceState::encode))
+    }
+
+    /// A state that does not fit the module fails its next call.
+    fn restore(&mut self, handle: Self::ModuleHandle, state: &[u8]) -> Result<()> {
+        if !self.keep_state {
+            return Err(Error::Unsupported);
+        }
+        self.modules.get(handle).ok_or(Error::ModuleNotFound)?;
+        self.states.insert(handle, InstanceState::decode(state)?)?;
+        Ok(())
+    }
+
+    fn unload(&mut self, id: ModuleId) {
+        self.modules.remove(id);
+        self.stats.remove(id);
+        self.states.remove(id);
+    }
+
+    fn suspend(&mut self) {
+        self.modules.clear();
+    }
+}
+
+/// Calls `func`, noting in the store how it trapped.
+fn run<R: WasmResults>(store: &mut Store<Host>, func: TypedFunc<(), R>) -> Result<R> {
+    func.call(&mut *store, ()).map_err(|trap| {
+        let code = trap.trap_code();
+        store.data_mut().trap = Some(code.map_or(TrapKind::HostError, trap_kind));
+        // Spec messages, e.g. "call stack exhausted".
+        Error::Engine(code.map_or("wasmi: host import failed", |code| code.trap_message()))
+    })
+}
+
+fn trap_kind(code: TrapCode) -> TrapKind {
+    match code {
+        TrapCode::UnreachableCodeReached => TrapKind::Unreachable,
+        TrapCode::MemoryOutOfBounds => TrapKind::MemoryOutOfBounds,
+        TrapCode::StackOverflow => TrapKind::StackOverflow,
+        TrapCode::IntegerDivisionByZero => TrapKind::DivisionByZero,
+        TrapCode::IntegerOverflow => TrapKind::IntegerOverflow,
+        TrapCode::BadConversionToInteger => TrapKind::BadConversion,
+        TrapCode::TableOutOfBounds | TrapCode::IndirectCallToNull | TrapCode::BadSignature => {
+            TrapKind::IndirectCall
+        }
+        TrapCode::OutOfFuel => TrapKind::OutOfFuel,
+        TrapCode::GrowthOperationLimited => TrapKind::Other,
+    }
+}
+
+fn abi_type(ty: &ValueType) -> ValType {
+    match ty {
+        ValueType::I32 => ValType::I32,
+        _ => ValType::Other,
+    }
+}
+
+#[cfg(all(test, feature = "std"))]
+mod tests {
+    use super::*;
+    use crate::abi::{GuestMemory, HostFn, HostImports, OK};
+
+    struct Recorder {
+        seen: std::rc::Rc<std::cell::RefCell<Vec<(i32, u8)>>>,
+    }
+
+    impl HostImports for Recorder {
+        fn functions(&self) -> &[HostFn] {
+            const FNS: &[HostFn] = &[HostFn::new("record", 2)];
+            FNS
+        }
+
+        fn call(&mut self, _name: &str, args: &[i32], memory: &mut dyn GuestMemory) -> Result<i32> {
+            let mut byte = [0u8; 1];
+            memory.read(args[1] as u32, &mut byte)?;
+            self.seen.borrow_mut().push((args[0], byte[0]));
+            Ok(OK)
+        }
+    }
+
+    #[test]
+    fn runs_entries_with_host_imports() {
+        let wasm = wat::parse_str(
+            r#"(module
+                (import "env" "record" (func $record (param i32 i32) (result i32)))
+                (memory (export "memory") 1)
+                (data (i32.const 16) "\2a")
+                (func (export "main") (drop (call $record (i32.const 7) (i32.const 16))))
+                (func (export "health") (result i32) (i32.const 3))
+                (func (export "trap") unreachable))"#,
+        )
+        .unwrap();
+
+        let seen = std::rc::Rc::new(std::cell::RefCell::new(Vec::new()));
+        let mut engine = WasmiEngine::new(crate::builder::DEFAULT_STACK_SIZE).unwrap();
+        engine.set_imports(Imports::new().with(Recorder { seen: seen.clone() }));
+        engine.set_crash_capture(Some(CrashCapture::default()));
+
+        let handle = engine.load(1, &wasm).unwrap();
+        engine.invoke(handle, "main", &mut ()).unwrap();
+        assert_eq!(*seen.borrow(), vec![(7, 0x2a)]);
+        assert_eq!(engine.invoke_status(handle, "health", &mut ()), Ok(3));
+        assert_eq!(engine.invoke_status(handle, "main", &mut ()), Ok(0));
+        assert_eq!(
+            engine.invoke(handle, "missing", &mut ()),
+            Err(Error::EntryNotFound)
+        );
+        assert_eq!(
+            engine.invoke(handle, "trap", &mut ()),
+            Err(Error::Engine("wasm `unreachable` instruction executed"))
+        );
+        assert_eq!(engine.take_crash().unwrap().trap, TrapKind::Unreachable);
+        assert_eq!(engine.stack_stats(handle).unwrap().memory_pages, 1);
+
+        assert_eq!(engine.load(2, b"\0aot\x03\0\0\0"), Err(Error::Unsupported));
+        assert_eq!(
+            engine.load(2, b"\0asm"),
+            Err(Error::Engine("wasmi: compile"))
+        );
+        engine.unload(1);
+        assert_eq!(
+            engine.invoke(handle, "main", &mut ()),
+            Err(Error::ModuleNotFound)
+        );
+    }
+
+    /// `log(ptr, len)` appends the guest's bytes to a `Vec<u8>` context.
+    struct Log;
+
+    impl HostImports for Log {
+        fn functions(&self) -> &[HostFn] {
+            const FNS: &[HostFn] = &[HostFn::new("log", 2)];
+            FNS
+        }
+
+        fn call(
+            &mut self,
+            _name: &str,
+            _args: &[i32],
+            _memory: &mut dyn GuestMemory,
+        ) -> Result<i32> {
+            Ok(crate::abi::E_INVALID)
+        }
+
+        fn call_with(
+            &mut self,
+            _name: &str,
+            args: &[i32],
+            memory: &mut dyn GuestMemory,
+            ctx: &mut crate::abi::HostContext<'_>,
+        ) -> Result<i32> {
+            let buf = ctx.get::<Vec<u8>>().ok_or(Error::Engine("no log buffer"))?;
+            let mut bytes = [0u8; 16];
+            let bytes = bytes
+                .get_mut(..args[1] as usize)
+                .ok_or(Error::Engine("log too long"))?;
+            memory.read(args[0] as u32, bytes)?;
+            buf.extend_from_slice(bytes);
+            Ok(OK)
+        }
+    }
+
+    #[test]
+    fn host_imports_reach_the_invocation_context() {
+        let wasm = wat::parse_str(
+            r#"(module
+                (import "env" "log" (func $log (param i32 i32) (result i32)))
+                (memory (export "memory") 1)
+                (data (i32.const 0) "hi")
+                (func (export "main") (result i32) (call $log (i32.const 0) (i32.const 2))))"#,
+        )
+        .unwrap();
+
+        let mut engine = WasmiEngine::<Vec<u8>>::new(crate::builder::DEFAULT_STACK_SIZE).unwrap();
+        engine.set_imports(Imports::new().with(Log));
+        let handle = engine.load(1, &wasm).unwrap();
+        let mut buf = Vec::new();
+        assert_eq!(engine.invoke_status(handle, "main", &mut buf), Ok(OK));
+        assert_eq!(engine.invoke_status(handle, "main", &mut buf), Ok(OK));
+        assert_eq!(buf, b"hihi");
+    }
+
+    #[test]
+    fn from_config_enforces_limits_and_meters_fuel() {
+        let wasm = wat::parse_str(
+            r#"(module
+                (memory 2)
+                (func $deep (param i32)
+                    (br_if 0 (i32.eqz (local.get 0)))
+                    (call $deep (i32.sub (local.get 0) (i32.const 1))))
+                (func (export "shallow") (call $deep (i32.const 4)))
+                (func (export "deep") (call $deep (i32.const 100000))))"#,
+        )
+        .unwrap();
+
+        let config = EngineConfig {
+            max_memory_pages: Some(1),
+            ..EngineConfig::default()
+        };
+        let mut engine = WasmiEngine::from_config(&config, Imports::new()).unwrap();
+        let handle = engine.load(1, &wasm).unwrap();
+        assert_eq!(
+            engine.invoke(handle, "shallow", &mut ()),
+            Err(Error::Engine("wasmi: instantiate"))
+        );
+        assert_eq!(engine.last_fuel(), None);
+        assert!(!engine.capabilities().fuel);
+
+        let config = EngineConfig {
+            max_memory_pages: Some(2),
+            meter_fuel: true,
+            ..EngineConfig::default()
+        };
+        let mut engine = WasmiEngine::from_config(&config, Imports::new()).unwrap();
+        let handle = engine.load(1, &wasm).unwrap();
+        engine.invoke(handle, "shallow", &mut ()).unwrap();
+        assert!(engine.last_fuel().is_some_and(|fuel| fuel >= 4));
+        assert!(engine.capabilities().fuel);
+        assert_eq!(
+            engine.invoke(handle, "deep", &mut ()),
+            Err(Error::Engine("call stack exhausted"))
+        );
+    }
+
+    #[test]
+    fn keeps_and_restores_state_between_calls() {
+        let wasm = wat::parse_str(
+            r#"(module
+                (memory (export "memory") 1)
+                (global $count (export "count") (mut i32) (i32.const 0))
+                (func (export "tick") (result i32)
+                    (if (i32.eq (memory.size) (i32.const 1))
+                        (then (drop (memory.grow (i32.const 1)))))
+                    (global.set $count (i32.add (global.get $count) (i32.const 1)))
+                    (i32.store8 (i32.const 70000)
+                        (i32.add (i32.load8_u (i32.const 70000)) (i32.const 10)))
+                    (i32.add (global.get $count) (i32.load8_u (i32.const 70000))))
+                (func (export "fail")
+                    (global.set $count (i32.const 100))
+                    unreachable))"#,
+        )
+        .unwrap();
+
+        let mut fresh = WasmiEngine::<()>::new(4096).unwrap();
+        let handle = fresh.load(1, &wasm).unwrap();
+        assert_eq!(fresh.invoke_status(handle, "tick", &mut ()), Ok(11));
+        assert_eq!(fresh.invoke_status(handle, "tick", &mut ()), Ok(11));
+        assert_eq!(fresh.snapshot(handle), Err(Error::Unsupported));
+
+        let config = EngineConfig {
+            keep_state: true,
+            ..EngineConfig::default()
+        };
+        let mut engine = WasmiEngine::from_config(&config, Imports::new()).unwrap();
+        let handle = engine.load(1, &wasm).unwrap();
+        let empty = engine.snapshot(handle).unwrap();
+        assert_eq!(engine.invoke_status(handle, "tick", &mut ()), Ok(11));
+        assert_eq!(engine.invoke_status(handle, "tick", &mut ()), Ok(22));
+        assert!(engine.invoke(handle, "fail", &mut ()).is_err());
+        assert_eq!(engine.invoke_status(handle, "tick", &mut ()), Ok(33));
+        let state = engine.snapshot(handle).unwrap();
+
+        // After "deep sleep": a new engine resumes where the old one stopped.
+        let mut engine = WasmiEngine::from_config(&config, Imports::new()).unwrap();
+        let handle = engine.load(1, &wasm).unwrap();
+        engine.restore(handle, &state).unwrap();
+        assert_eq!(engine.invoke_status(handle, "tick", &mut ()), Ok(44));
+        engine.restore(handle, &empty).unwrap();
+        assert_eq!(engine.invoke_status(handle, "tick", &mut ()), Ok(11));
+        assert!(engine.restore(handle, b"SSNP").is_err());
+    }
+}
@@ -1,57 +1,1303 @@
 //! Minimal wasmtime-based engine for host testing (std only).
 //! Not intended for microcontrollers; enables a fast host path for integration.
+//!
+//! Only wasmtime's runtime and one compiler are built. `engine-wasmtime-lite`
+//! compiles guests with Cranelift; `engine-wasmtime-winch` swaps in the Winch
+//! baseline compiler, which cuts the binary by most of its size for small
+//! Linux devices at the cost of slower guest code (x86_64 only). With both
+//! features Cranelift is used. `COMPILER` names the one built in.
+//!
+//! Gateways can also run calls in parallel: `start_workers` gives the engine a
+//! pool of threads, and `Runtime::execute_async` hands an invocation to the
+//! next free worker and returns a `PendingCall` to wait on (`join`) or
+//! `.await`. Workers share the compiled modules but run each call in a store
+//! of their own, with imports built per worker; pooled calls see no context,
+//! keep no state and record no fuel, stack stats or crashes.
+//!
+//! Guest calls can be bounded: `Runtime::execute_with_deadline` traps a call
+//! still running at its deadline with `DEADLINE_EXCEEDED`, and
+//! `Runtime::execute_cancellable` lets another thread stop it through a
+//! `CancellationToken` (`CANCELLED`). Both use epoch interruption, like
+//! `Engine::interrupter`.
 
-use crate::{Engine, Error, ModuleId, Result};
-use std::collections::HashMap;
-use wasmtime::{Engine as HostEngine, Instance, Module, Store};
+use crate::abi::linker::{Linker as HostLinker, ValType as AbiType};
+use crate::abi::{Imports, IMPORT_MODULE};
+use crate::builder::{ConfigurableEngine, EngineConfig};
+use crate::crash::{self, Crash, CrashCapture, TrapKind};
+use crate::manifest::PayloadKind;
+use crate::schedule::Interrupter;
+use crate::snapshot::{GlobalValue, InstanceState};
+use crate::{Engine, EngineCaps, Error, ModuleId, ModuleSource, Result, Runtime, StackStats};
+use core::future::Future;
+use core::marker::PhantomData;
+use core::pin::Pin;
+use core::task::{Context, Poll, Waker};
+use std::collections::{HashMap, HashSet};
+use std::fmt::Write as _;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{mpsc, Arc, Condvar, Mutex, OnceLock};
+use std::thread::{self, JoinHandle};
+use std::time::Instant;
+use wasmtime::{
+    Caller, Engine as HostEngine, Extern, ExternType, Instance, Linker, Module, Mutability, Store,
+    StoreLimits, StoreLimitsBuilder, Trap, TypedFunc, UpdateDeadline, Val, ValType, WasmBacktrace,
+    WasmResults,
+};
 
-/// wasmtime-backed engine (host-only).
-pub struct WasmtimeLiteEngine {
+/// Frames named in a trap message before the rest is elided.
+const BACKTRACE_FRAMES: usize = 4;
+/// Distinct trap messages kept; later ones fall back to `"wasmtime call"`.
+const MAX_TRAP_MESSAGES: usize = 256;
+
+/// A call stopped through its `CancellationToken`.
+pub const CANCELLED: Error = Error::Engine("wasmtime: call cancelled");
+/// A call still running at its deadline.
+pub const DEADLINE_EXCEEDED: Error = Error::Engine("wasmtime: deadline exceeded");
+
+/// Compiler guests are compiled with.
+#[cfg(feature = "engine-wasmtime-lite")]
+pub const COMPILER: &str = "cranelift";
+#[cfg(not(feature = "engine-wasmtime-lite"))]
+pub const COMPILER: &str = "winch";
+
+/// wasmtime-backed engine (host-only); host imports see the `C` each call is
+/// invoked with.
+pub struct WasmtimeLiteEngine<C = ()> {
     engine: HostEngine,
     modules: HashMap<ModuleId, Module>,
+    imports: HostLinker,
+    limits: StoreLimits,
+    meter_fuel: bool,
+    last_fuel: Option<u64>,
+    stats: HashMap<ModuleId, StackStats>,
+    crash_capture: Option<CrashCapture>,
+    crash: Option<Crash>,
+    keep_state: bool,
+    /// State each module's next call starts from, with `keep_state`.
+    states: HashMap<ModuleId, InstanceState>,
+    pool: Option<Pool>,
+    /// Bumped by every `interrupter` tick.
+    interrupts: Arc<AtomicU64>,
+    /// Deadline and token of the next call only.
+    bounds: Option<Bounds>,
+    _ctx: PhantomData<fn(&mut C)>,
+}
+
+/// Per-call store data: the imports, the memory limit and how the call trapped.
+struct Host {
+    imports: HostLinker,
+    limits: StoreLimits,
+    trap: Option<TrapKind>,
 }
 
-impl WasmtimeLiteEngine {
+impl<C: 'static> WasmtimeLiteEngine<C> {
     pub fn new() -> Result<Self> {
-        let mut config = wasmtime::Config::new();
+        Self::with_config(wasmtime::Config::new(), StoreLimits::default(), false)
+    }
+
+    fn with_config(
+        mut config: wasmtime::Config,
+        limits: StoreLimits,
+        meter_fuel: bool,
+    ) -> Result<Self> {
+        #[cfg(feature = "engine-wasmtime-lite")]
         config.cranelift_opt_level(wasmtime::OptLevel::Speed);
+        #[cfg(not(feature = "engine-wasmtime-lite"))]
+        config.strategy(wasmtime::Strategy::Winch);
+        config.consume_fuel(meter_fuel).epoch_interruption(true);
         let engine = HostEngine::new(&config).map_err(|_| Error::Engine("wasmtime init"))?;
         Ok(Self {
             engine,
             modules: HashMap::new(),
+            imports: HostLinker::default(),
+            limits,
+            meter_fuel,
+            last_fuel: None,
+            stats: HashMap::new(),
+            crash_capture: None,
+            crash: None,
+            keep_state: false,
+            states: HashMap::new(),
+            pool: None,
+            interrupts: Arc::default(),
+            bounds: None,
+            _ctx: PhantomData,
         })
     }
+
+    /// Host functions linked into every module on invoke.
+    pub fn set_imports(&mut self, imports: Imports) {
+        self.imports = HostLinker::new(imports);
+    }
+
+    /// Instantiates `handle` with the imports (and its kept state) and runs
+    /// `f` against it, then records the size of its exported `memory`
+    /// (wasmtime exposes no stack usage), its new state and, on a trap, the
+    /// crash snapshot.
+    fn call<T>(
+        &mut self,
+        handle: ModuleId,
+        ctx: &mut C,
+        f: impl FnOnce(&mut Store<Host>, &Instance) -> Result<T>,
+    ) -> Result<T> {
+        let bounds = self.bounds.take().unwrap_or_default();
+        let module = self.modules.get(&handle).ok_or(Error::ModuleNotFound)?;
+        let linker = link(&self.engine, &self.imports, module)?;
+        let _armed = bounds.arm(&self.engine)?;
+
+        self.imports.enter(handle);
+        // SAFETY: `ctx` stays borrowed until this returns, and is unbound
+        // below once the imports are back.
+        unsafe { self.imports.bind_context(ctx) };
+        // Imports move into the store for the call and come back afterwards.
+        let host = Host {
+            imports: core::mem::take(&mut self.imports),
+            limits: self.limits.clone(),
+            trap: None,
+        };
+        let mut store = Store::new(&self.engine, host);
+        store.limiter(|host| &mut host.limits);
+        // Any `interrupter` tick from here on stops the call.
+        stop_on(&mut store, &self.interrupts, &bounds);
+        if self.meter_fuel {
+            // Cannot fail: the engine consumes fuel whenever `meter_fuel` is set.
+            let _ = store.set_fuel(u64::MAX);
+        }
+        let mut pages = None;
+        let capture = self.crash_capture;
+        let mut crash = None;
+        let kept = self.states.get(&handle).filter(|_| self.keep_state);
+        let mut saved = None;
+        let result = linker
+            .instantiate(&mut store, module)
+            .map_err(|_| Error::Engine("wasmtime instantiate"))
+            .and_then(|instance| {
+                if let Some(state) = kept {
+                    load_state(&mut store, &instance, state)?;
+                }
+                let result = f(&mut store, &instance);
+                if self.keep_state && result.is_ok() {
+                    saved = Some(save_state(&mut store, &instance));
+                }
+                let memory = instance.get_memory(&mut store, "memory");
+                pages = memory.map(|memory| memory.size(&store) as u32);
+                if let (Some(trap), Some(capture)) = (store.data().trap, capture) {
+                    crash = Some(Crash {
+                        trap,
+                        memory_offset: capture.memory_offset,
+                        memory: memory.map_or_else(Vec::new, |memory| {
+                            crash::memory_window(memory.data(&store), &capture)
+                        }),
+                        stack: Vec::new(),
+                    });
+                }
+                result
+            });
+        if crash.is_some() {
+            self.crash = crash;
+        }
+        if let Some(pages) = pages {
+            self.stats.entry(handle).or_default().record(None, pages);
+        }
+        if let Some(state) = saved {
+            self.states.insert(handle, state);
+        }
+        self.last_fuel = store.get_fuel().ok().map(|left| u64::MAX - left);
+        let trap = store.data().trap;
+        self.imports = store.into_data().imports;
+        self.imports.unbind_context();
+        self.imports.exit();
+        result.map_err(|error| bounds.explain(trap, error))
+    }
+}
+
+impl<C: 'static> WasmtimeLiteEngine<C> {
+    /// Starts `workers` threads for `Runtime::execute_async`, each with the
+    /// imports `imports` builds for it; replaces any previous pool once its
+    /// calls are done.
+    pub fn start_workers(
+        &mut self,
+        workers: usize,
+        imports: impl Fn() -> Imports + Send + Sync + 'static,
+    ) -> Result<()> {
+        if workers == 0 {
+            return Err(Error::Engine("wasmtime: no workers"));
+        }
+        self.pool = None;
+        let (queue, jobs) = mpsc::channel::<Job>();
+        let jobs = Arc::new(Mutex::new(jobs));
+        let imports = Arc::new(imports);
+        let mut pool = Pool {
+            queue: Some(queue),
+            workers: Vec::with_capacity(workers),
+        };
+        for i in 0..workers {
+            let worker = Worker {
+                engine: self.engine.clone(),
+                limits: self.limits.clone(),
+                meter_fuel: self.meter_fuel,
+                interrupts: self.interrupts.clone(),
+            };
+            let jobs = jobs.clone();
+            let imports = imports.clone();
+            let handle = thread::Builder::new()
+                .name(format!("slimmy-wasmtime-{i}"))
+                .spawn(move || {
+                    let mut linker = HostLinker::new(imports());
+                    loop {
+                        // Another worker panicking cannot corrupt the queue.
+                        let job = jobs.lock().unwrap_or_else(|p| p.into_inner()).recv();
+                        let Ok(job) = job else { break };
+                        let result = worker.call(&mut linker, &job);
+                        job.call.finish(result);
+                    }
+                })
+                .map_err(|_| Error::Engine("wasmtime: cannot spawn worker"))?;
+            pool.workers.push(handle);
+        }
+        self.pool = Some(pool);
+        Ok(())
+    }
+
+    /// Number of pool workers (0 without `start_workers`).
+    pub fn worker_count(&self) -> usize {
+        self.pool.as_ref().map_or(0, |pool| pool.workers.len())
+    }
+}
+
+impl<C: 'static, S: ModuleSource> Runtime<WasmtimeLiteEngine<C>, S> {
+    /// Queues `entry` of `module_id` on the engine's workers and returns
+    /// without waiting. Switches, quarantine, the state gate and quotas are
+    /// checked now; the module is compiled on first use. The outcome is only
+    /// seen through the returned call, not by telemetry or quarantine.
+    pub fn execute_async(&mut self, module_id: ModuleId, entry: &str) -> Result<PendingCall> {
+        self.admit(module_id, entry)?;
+        if !self.engine.modules.contains_key(&module_id) {
+            let bytes = self.source.fetch(module_id).ok_or(Error::ModuleNotFound)?;
+            self.engine.load(module_id, bytes)?;
+        }
+        let pool = self.engine.pool.as_ref().ok_or(Error::Unsupported)?;
+        let call = Arc::new(CallState::default());
+        let job = Job {
+            module_id,
+            module: self.engine.modules[&module_id].clone(),
+            entry: entry.into(),
+            call: call.clone(),
+        };
+        pool.queue
+            .as_ref()
+            .and_then(|queue| queue.send(job).ok())
+            .ok_or(Error::Engine("wasmtime: workers stopped"))?;
+        Ok(PendingCall { state: call })
+    }
+}
+
+/// Worker threads and the queue feeding them.
+struct Pool {
+    queue: Option<mpsc::Sender<Job>>,
+    workers: Vec<JoinHandle<()>>,
+}
+
+/// Closing the queue lets workers finish what was queued, then stop.
+impl Drop for Pool {
+    fn drop(&mut self) {
+        self.queue = None;
+        for worker in self.workers.drain(..) {
+            let _ = worker.join();
+        }
+    }
+}
+
+/// What a worker needs from the engine.
+struct Worker {
+    engine: HostEngine,
+    limits: StoreLimits,
+    meter_fuel: bool,
+    interrupts: Arc<AtomicU64>,
+}
+
+impl Worker {
+    /// `WasmtimeLiteEngine::call` without context, kept state or records.
+    fn call(&self, imports: &mut HostLinker, job: &Job) -> Result<()> {
+        let linker = link(&self.engine, imports, &job.module)?;
+        imports.enter(job.module_id);
+        let host = Host {
+            imports: core::mem::take(imports),
+            limits: self.limits.clone(),
+            trap: None,
+        };
+        let mut store = Store::new(&self.engine, host);
+        store.limiter(|host| &mut host.limits);
+        stop_on(&mut store, &self.interrupts, &Bounds::default());
+        if self.meter_fuel {
+            // Cannot fail: the engine consumes fuel whenever `meter_fuel` is set.
+            let _ = store.set_fuel(u64::MAX);
+        }
+        let result = linker
+            .instantiate(&mut store, &job.module)
+            .map_err(|_| Error::Engine("wasmtime instantiate"))
+            .and_then(|instance| {
+                let func = instance
+                    .get_typed_func::<(), ()>(&mut store, &job.entry)
+                    .map_err(|_| Error::EntryNotFound)?;
+                run(&mut store, func)
+            });
+        *imports = store.into_data().imports;
+        imports.exit();
+        result
+    }
+}
+
+struct Job {
+    module_id: ModuleId,
+    module: Module,
+    entry: String,
+    call: Arc<CallState>,
+}
+
+/// A job dropped unfinished (its worker panicked) still completes its call.
+impl Drop for Job {
+    fn drop(&mut self) {
+        self.call
+            .finish(Err(Error::Engine("wasmtime: worker stopped")));
+    }
+}
+
+#[derive(Default)]
+struct CallState {
+    slot: Mutex<CallSlot>,
+    done: Condvar,
+}
+
+#[derive(Default)]
+struct CallSlot {
+    result: Option<Result<()>>,
+    finished: bool,
+    waker: Option<Waker>,
+}
+
+impl CallState {
+    fn slot(&self) -> std::sync::MutexGuard<'_, CallSlot> {
+        self.slot.lock().unwrap_or_else(|p| p.into_inner())
+    }
+
+    /// Stores the first result only.
+    fn finish(&self, result: Result<()>) {
+        let mut slot = self.slot();
+        if slot.finished {
+            return;
+        }
+        slot.result = Some(result);
+        slot.finished = true;
+        if let Some(waker) = slot.waker.take() {
+            waker.wake();
+        }
+        self.done.notify_all();
+    }
+}
+
+/// An invocation queued by `Runtime::execute_async`.
+pub struct PendingCall {
+    state: Arc<CallState>,
+}
+
+impl PendingCall {
+    pub fn is_finished(&self) -> bool {
+        self.state.slot().finished
+    }
+
+    /// Blocks until the call is done.
+    pub fn join(self) -> Result<()> {
+        let mut slot = self.state.slot();
+        while !slot.finished {
+            slot = self
+                .state
+                .done
+                .wait(slot)
+                .unwrap_or_else(|p| p.into_inner());
+        }
+        slot.result
+            .take()
+            .unwrap_or(Err(Error::Engine("wasmtime: call already joined")))
+    }
+}
+
+impl Future for PendingCall {
+    type Output = Result<()>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<()>> {
+        let mut slot = self.state.slot();
+        match slot.result.take() {
+            Some(result) => Poll::Ready(result),
+            None if slot.finished => {
+                Poll::Ready(Err(Error::Engine("wasmtime: call already joined")))
+            }
+            None => {
+                slot.waker = Some(cx.waker().clone());
+                Poll::Pending
+            }
+        }
+    }
+}
+
+impl<C: 'static, S: ModuleSource> Runtime<WasmtimeLiteEngine<C>, S> {
+    /// `execute`, stopped with `DEADLINE_EXCEEDED` if the guest is still
+    /// running at `deadline`.
+    pub fn execute_with_deadline(
+        &mut self,
+        module_id: ModuleId,
+        entry: &str,
+        ctx: &mut C,
+        deadline: Instant,
+    ) -> Result<()> {
+        self.execute_bounded(module_id, entry, ctx, Some(deadline), None)
+    }
+
+    /// `execute` that `token.cancel()` stops from any thread with
+    /// `CANCELLED` (at once if it already was), optionally with a deadline
+    /// as in `execute_with_deadline`.
+    pub fn execute_cancellable(
+        &mut self,
+        module_id: ModuleId,
+        entry: &str,
+        ctx: &mut C,
+        token: &CancellationToken,
+        deadline: Option<Instant>,
+    ) -> Result<()> {
+        self.execute_bounded(module_id, entry, ctx, deadline, Some(token.clone()))
+    }
+
+    fn execute_bounded(
+        &mut self,
+        module_id: ModuleId,
+        entry: &str,
+        ctx: &mut C,
+        deadline: Option<Instant>,
+        token: Option<CancellationToken>,
+    ) -> Result<()> {
+        self.engine.bounds = Some(Bounds { deadline, token });
+        let result = self.execute(module_id, entry, ctx);
+        // Refused before reaching the engine: do not bound the next call.
+        self.engine.bounds = None;
+        result
+    }
+}
+
+/// Stops calls made with it (`Runtime::execute_cancellable`) from any
+/// thread; clones share the same state.
+#[derive(Clone, Default)]
+pub struct CancellationToken(Arc<TokenState>);
+
+#[derive(Default)]
+struct TokenState {
+    cancelled: AtomicBool,
+    /// Engines running a call with this token.
+    engines: Mutex<Vec<HostEngine>>,
+}
+
+impl CancellationToken {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Stops the calls running with this token and refuses later ones.
+    pub fn cancel(&self) {
+        self.0.cancelled.store(true, Ordering::SeqCst);
+        for engine in self.engines().iter() {
+            engine.increment_epoch();
+        }
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.0.cancelled.load(Ordering::SeqCst)
+    }
+
+    fn engines(&self) -> std::sync::MutexGuard<'_, Vec<HostEngine>> {
+        self.0.engines.lock().unwrap_or_else(|p| p.into_inner())
+    }
+}
+
+/// Deadline and token of one call.
+#[derive(Clone, Default)]
+struct Bounds {
+    deadline: Option<Instant>,
+    token: Option<CancellationToken>,
+}
+
+impl Bounds {
+    fn expired(&self) -> Option<Error> {
+        if self
+            .token
+            .as_ref()
+            .is_some_and(CancellationToken::is_cancelled)
+        {
+            Some(CANCELLED)
+        } else if self
+            .deadline
+            .is_some_and(|deadline| Instant::now() >= deadline)
+        {
+            Some(DEADLINE_EXCEEDED)
+        } else {
+            None
+        }
+    }
+
+    /// Makes the token and the deadline able to tick `engine` until the
+    /// returned guard is dropped.
+    fn arm(&self, engine: &HostEngine) -> Result<Armed> {
+        let token = self.token.clone();
+        if let Some(token) = &token {
+            // Registered before the check, so a `cancel` racing with it
+            // still ticks the engine.
+            token.engines().push(engine.clone());
+        }
+        let mut armed = Armed {
+            engine: engine.clone(),
+            token,
+            watchdog: None,
+        };
+        if let Some(error) = self.token.as_ref().and_then(|_| self.expired()) {
+            return Err(error);
+        }
+        if let Some(deadline) = self.deadline {
+            let done = Arc::new((Mutex::new(false), Condvar::new()));
+            let watched = done.clone();
+            let engine = engine.clone();
+            thread::Builder::new()
+                .name("slimmy-deadline".into())
+                .spawn(move || {
+                    let (lock, wake) = &*watched;
+                    let mut done = lock.lock().unwrap_or_else(|p| p.into_inner());
+                    while !*done {
+                        let left = deadline.saturating_duration_since(Instant::now());
+                        if left.is_zero() {
+                            engine.increment_epoch();
+                            return;
+                        }
+                        done = wake
+                            .wait_timeout(done, left)
+                            .unwrap_or_else(|p| p.into_inner())
+                            .0;
+                    }
+                })
+                .map_err(|_| Error::Engine("wasmtime: cannot spawn deadline watchdog"))?;
+            armed.watchdog = Some(done);
+        }
+        Ok(armed)
+    }
+
+    /// An interrupted call's error, naming the bound that stopped it.
+    fn explain(&self, trap: Option<TrapKind>, error: Error) -> Error {
+        match trap {
+            Some(TrapKind::Interrupted) => self.expired().unwrap_or(error),
+            _ => error,
+        }
+    }
+}
+
+/// Undoes `Bounds::arm`.
+struct Armed {
+    engine: HostEngine,
+    token: Option<CancellationToken>,
+    watchdog: Option<Arc<(Mutex<bool>, Condvar)>>,
+}
+
+impl Drop for Armed {
+    fn drop(&mut self) {
+        if let Some(token) = &self.token {
+            let mut engines = token.engines();
+            if let Some(at) = engines
+                .iter()
+                .position(|e| HostEngine::same(e, &self.engine))
+            {
+                engines.swap_remove(at);
+            }
+        }
+        if let Some(watchdog) = &self.watchdog {
+            let (lock, wake) = &**watchdog;
+            *lock.lock().unwrap_or_else(|p| p.into_inner()) = true;
+            wake.notify_one();
+        }
+    }
+}
+
+/// Traps the store's call on the next epoch tick if it came from an
+/// `interrupter` or one of `bounds` expired; other ticks (another call's
+/// deadline on the same engine) are ignored.
+fn stop_on(store: &mut Store<Host>, interrupts: &Arc<AtomicU64>, bounds: &Bounds) {
+    let interrupts = interrupts.clone();
+    let seen = interrupts.load(Ordering::SeqCst);
+    let bounds = bounds.clone();
+    store.set_epoch_deadline(1);
+    store.epoch_deadline_callback(move |_| {
+        if interrupts.load(Ordering::SeqCst) != seen || bounds.expired().is_some() {
+            Err(Trap::Interrupt.into())
+        } else {
+            Ok(UpdateDeadline::Continue(1))
+        }
+    });
+}
+
+/// Links the host imports `module` asks for.
+fn link(engine: &HostEngine, imports: &HostLinker, module: &Module) -> Result<Linker<Host>> {
+    let mut linker = Linker::new(engine);
+    for import in module.imports() {
+        let ExternType::Func(ty) = import.ty() else {
+            continue;
+        };
+        let host_fn = imports
+            .resolve(
+                import.module(),
+                import.name(),
+                ty.params().map(abi_type),
+                ty.results().map(abi_type),
+            )
+            .map_err(|_| Error::Engine("wasmtime: host import signature mismatch"))?;
+        let Some(host_fn) = host_fn else {
+            continue;
+        };
+
+        let name = host_fn.name;
+        linker
+            .func_new(
+                IMPORT_MODULE,
+                name,
+                ty,
+                move |mut caller: Caller<'_, Host>, params: &[Val], results: &mut [Val]| {
+                    let args = params.iter().map(Val::unwrap_i32);
+                    let ret = match caller.get_export("memory").and_then(|e| e.into_memory()) {
+                        Some(memory) => {
+                            let (mut data, host) = memory.data_and_store_mut(&mut caller);
+                            host.imports.call(name, args, &mut data)
+                        }
+                        None => caller.data_mut().imports.call(name, args, &mut [0u8; 0]),
+                    };
+                    results[0] = Val::I32(ret.map_err(wasmtime::Error::msg)?);
+                    Ok(())
+                },
+            )
+            .map_err(|_| Error::Engine("wasmtime: link host import"))?;
+    }
+    Ok(linker)
 }
 
-impl Engine for WasmtimeLiteEngine {
+/// Exported `memory` and mutable globals of `instance`.
+fn save_state(store: &mut Store<Host>, instance: &Instance) -> InstanceState {
+    let mut state = InstanceState::default();
+    let exports: Vec<(String, Extern)> = instance
+        .exports(&mut *store)
+        .map(|export| (export.name().to_string(), export.into_extern()))
+        .collect();
+    for (name, export) in exports {
+        match export {
+            Extern::Memory(memory) if name == "memory" => {
+                state.memory = memory.data(&*store).to_vec();
+            }
+            Extern::Global(global)
+                if global.ty(&*store).mutability() == Mutability::Var
+                    && name.len() <= u8::MAX as usize =>
+            {
+                let value = match global.get(&mut *store) {
+                    Val::I32(v) => GlobalValue::I32(v),
+                    Val::I64(v) => GlobalValue::I64(v),
+                    Val::F32(v) => GlobalValue::F32(v),
+                    Val::F64(v) => GlobalValue::F64(v),
+                    _ => continue,
+                };
+                state.globals.push((name, value));
+            }
+            _ => {}
+        }
+    }
+    state
+}
+
+/// Overwrites a fresh instance with a kept state.
+fn load_state(store: &mut Store<Host>, instance: &Instance, state: &InstanceState) -> Result<()> {
+    const MISMATCH: Error = Error::Engine("wasmtime: state does not fit module");
+    if let Some(memory) = instance.get_memory(&mut *store, "memory") {
+        let missing = state.memory.len().saturating_sub(memory.data_size(&*store));
+        if missing > 0 {
+            let pages = missing.div_ceil(crate::builder::WASM_PAGE_SIZE) as u64;
+            memory.grow(&mut *store, pages).map_err(|_| MISMATCH)?;
+        }
+        memory
+            .write(&mut *store, 0, &state.memory)
+            .map_err(|_| MISMATCH)?;
+    } else if !state.memory.is_empty() {
+        return Err(MISMATCH);
+    }
+    for (name, value) in &state.globals {
+        let value = match *value {
+            GlobalValue::I32(v) => Val::I32(v),
+            GlobalValue::I64(v) => Val::I64(v),
+            GlobalValue::F32(v) => Val::F32(v),
+            GlobalValue::F64(v) => Val::F64(v),
+        };
+        let global = instance.get_global(&mut *store, name).ok_or(MISMATCH)?;
+        global.set(&mut *store, value).map_err(|_| MISMATCH)?;
+    }
+    Ok(())
+}
+
+impl<C: 'static> ConfigurableEngine for WasmtimeLiteEngine<C> {
+    fn from_config(config: &EngineConfig, imports: Imports) -> Result<Self> {
+        let mut wasmtime_config = wasmtime::Config::new();
+        wasmtime_config.max_wasm_stack(config.stack_size as usize);
+        let mut limits = StoreLimitsBuilder::new();
+        if let Some(pages) = config.max_memory_pages {
+            limits = limits.memory_size(pages as usize * crate::builder::WASM_PAGE_SIZE);
+        }
+        let mut engine = Self::with_config(wasmtime_config, limits.build(), config.meter_fuel)?;
+        engine.keep_state = config.keep_state;
+        engine.set_imports(imports);
+        Ok(engine)
+    }
+}
+
+impl<C: 'static> Engine for WasmtimeLiteEngine<C> {
     type ModuleHandle = ModuleId;
-    type Context = ();
+    type Context = C;
 
     fn load(&mut self, id: ModuleId, module: &[u8]) -> Result<Self::ModuleHandle> {
         if module.is_empty() {
             return Err(Error::Engine("wasmtime: empty module"));
         }
+        if PayloadKind::detect(module) == Some(PayloadKind::Aot) {
+            return Err(Error::Unsupported);
+        }
         let compiled = Module::from_binary(&self.engine, module)
             .map_err(|_| Error::Engine("wasmtime compile"))?;
         self.modules.insert(id, compiled);
         Ok(id)
     }
 
-    fn invoke(
+    fn invoke(&mut self, handle: Self::ModuleHandle, entry: &str, ctx: &mut C) -> Result<()> {
+        self.call(handle, ctx, |store, instance| {
+            let func = instance
+                .get_typed_func::<(), ()>(&mut *store, entry)
+                .map_err(|_| Error::EntryNotFound)?;
+            run(store, func)
+        })
+    }
+
+    fn invoke_status(
         &mut self,
         handle: Self::ModuleHandle,
         entry: &str,
-        _ctx: &mut Self::Context,
-    ) -> Result<()> {
-        let module = self.modules.get(&handle).ok_or(Error::ModuleNotFound)?;
-        let mut store = Store::new(&self.engine, ());
-        let instance = Instance::new(&mut store, module, &[])
-            .map_err(|_| Error::Engine("wasmtime instantiate"))?;
-        let func = instance
-            .get_typed_func::<(), ()>(&mut store, entry)
-            .map_err(|_| Error::EntryNotFound)?;
-        func.call(&mut store, ())
-            .map_err(|_| Error::Engine("wasmtime call"))?;
+        ctx: &mut C,
+    ) -> Result<i32> {
+        self.call(handle, ctx, |store, instance| {
+            if let Ok(func) = instance.get_typed_func::<(), i32>(&mut *store, entry) {
+                return run(store, func);
+            }
+            let func = instance
+                .get_typed_func::<(), ()>(&mut *store, entry)
+                .map_err(|_| Error::EntryNotFound)?;
+            run(store, func).map(|()| 0)
+        })
+    }
+
+    fn capabilities(&self) -> EngineCaps {
+        EngineCaps {
+            typed_calls: true,
+            imports: true,
+            memory_access: true,
+            // Metering is chosen when the engine is built.
+            fuel: self.meter_fuel,
+            xip: false,
+            multiple_instances: true,
+            aot: false,
+            snapshots: self.keep_state,
+        }
+    }
+
+    fn last_fuel(&self) -> Option<u64> {
+        self.last_fuel
+    }
+
+    fn set_crash_capture(&mut self, capture: Option<CrashCapture>) {
+        self.crash_capture = capture;
+    }
+
+    fn take_crash(&mut self) -> Option<Crash> {
+        self.crash.take()
+    }
+
+    /// A module that has not run yet snapshots as an empty state, which
+    /// restores to a fresh instance.
+    fn snapshot(&mut self, handle: Self::ModuleHandle) -> Result<Vec<u8>> {
+        if !self.keep_state {
+            return Err(Error::Unsupported);
+        }
+        self.modules.get(&handle).ok_or(Error::ModuleNotFound)?;
+        Ok(self
+            .states
+            .get(&handle)
+            .map_or_else(|| InstanceState::default().encode(), InstanceState::encode))
+    }
+
+    /// A state that does not fit the module fails its next call.
+    fn restore(&mut self, handle: Self::ModuleHandle, state: &[u8]) -> Result<()> {
+        if !self.keep_state {
+            return Err(Error::Unsupported);
+        }
+        self.modules.get(&handle).ok_or(Error::ModuleNotFound)?;
+        self.states.insert(handle, InstanceState::decode(state)?);
         Ok(())
     }
+
+    /// Advances the engine's epoch, which traps the call in progress.
+    fn interrupter(&self) -> Option<Interrupter> {
+        let engine = self.engine.clone();
+        let interrupts = self.interrupts.clone();
+        Some(Interrupter::new(move || {
+            interrupts.fetch_add(1, Ordering::SeqCst);
+            engine.increment_epoch();
+        }))
+    }
+
+    fn stack_stats(&self, handle: Self::ModuleHandle) -> Option<StackStats> {
+        self.stats.get(&handle).copied()
+    }
+
+    fn unload(&mut self, id: ModuleId) {
+        self.modules.remove(&id);
+        self.stats.remove(&id);
+        self.states.remove(&id);
+    }
+
+    fn suspend(&mut self) {
+        self.modules.clear();
+    }
+}
+
+/// Calls `func`, noting in the store how it trapped.
+fn run<R: WasmResults>(store: &mut Store<Host>, func: TypedFunc<(), R>) -> Result<R> {
+    func.call(&mut *store, ()).map_err(|err| {
+        store.data_mut().trap = Some(trap_kind(&err));
+        Error::Engine(trap_message(&err).unwrap_or("wasmtime call"))
+    })
+}
+
+/// `"wasm trap: <reason> in inner <- outer <- ..."`, naming the innermost
+/// frames from the module's name section; `None` when no frame has a name.
+fn trap_message(err: &wasmtime::Error) -> Option<&'static str> {
+    let frames = err.downcast_ref::<WasmBacktrace>()?.frames();
+    if frames.iter().all(|frame| frame.func_name().is_none()) {
+        return None;
+    }
+    // `Trap` displays as "wasm trap: <reason>".
+    let mut msg = String::new();
+    match err.downcast_ref::<Trap>() {
+        Some(trap) => write!(msg, "{trap}").ok()?,
+        None => msg.push_str("wasm trap: host import failed"),
+    }
+    msg.push_str(" in ");
+    for (i, frame) in frames.iter().take(BACKTRACE_FRAMES).enumerate() {
+        if i > 0 {
+            msg.push_str(" <- ");
+        }
+        match frame.func_name() {
+            Some(name) => msg.push_str(name),
+            None => write!(msg, "func[{}]", frame.func_index()).ok()?,
+        }
+    }
+    if frames.len() > BACKTRACE_FRAMES {
+        msg.push_str(" <- ...");
+    }
+    intern(msg)
+}
+
+/// Leaks `msg` once so it fits `Error::Engine`; repeats reuse the first copy.
+fn intern(msg: String) -> Option<&'static str> {
+    static MESSAGES: OnceLock<Mutex<HashSet<&'static str>>> = OnceLock::new();
+    let mut messages = MESSAGES.get_or_init(Default::default).lock().ok()?;
+    if let Some(known) = messages.get(msg.as_str()) {
+        return Some(known);
+    }
+    if messages.len() >= MAX_TRAP_MESSAGES {
+        return None;
+    }
+    let leaked: &'static str = Box::leak(msg.into_boxed_str());
+    messages.insert(leaked);
+    Some(leaked)
+}
+
+fn trap_kind(err: &wasmtime::Error) -> TrapKind {
+    match err.downcast_ref::<Trap>() {
+        Some(Trap::UnreachableCodeReached) => TrapKind::Unreachable,
+        Some(Trap::MemoryOutOfBounds | Trap::HeapMisaligned) => TrapKind::MemoryOutOfBounds,
+        Some(Trap::StackOverflow) => TrapKind::StackOverflow,
+        Some(Trap::IntegerDivisionByZero) => TrapKind::DivisionByZero,
+        Some(Trap::IntegerOverflow) => TrapKind::IntegerOverflow,
+        Some(Trap::BadConversionToInteger) => TrapKind::BadConversion,
+        Some(Trap::TableOutOfBounds | Trap::IndirectCallToNull | Trap::BadSignature) => {
+            TrapKind::IndirectCall
+        }
+        Some(Trap::OutOfFuel) => TrapKind::OutOfFuel,
+        Some(Trap::Interrupt) => TrapKind::Interrupted,
+        Some(_) => TrapKind::Other,
+        // Anything that is not a wasm trap came from a host import.
+        None => TrapKind::HostError,
+    }
+}
+
+fn abi_type(ty: ValType) -> AbiType {
+    match ty {
+        ValType::I32 => AbiType::I32,
+        _ => AbiType::Other,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::abi::{GuestMemory, HostFn, HostImports, OK};
+
+    struct Recorder {
+        seen: std::rc::Rc<std::cell::RefCell<Vec<(i32, u8)>>>,
+    }
+
+    impl HostImports for Recorder {
+        fn functions(&self) -> &[HostFn] {
+            const FNS: &[HostFn] = &[HostFn::new("record", 2)];
+            FNS
+        }
+
+        fn call(&mut self, _name: &str, args: &[i32], memory: &mut dyn GuestMemory) -> Result<i32> {
+            let mut byte = [0u8; 1];
+            memory.read(args[1] as u32, &mut byte)?;
+            self.seen.borrow_mut().push((args[0], byte[0]));
+            Ok(OK)
+        }
+    }
+
+    #[test]
+    fn links_host_imports_with_memory_access() {
+        let wasm = wat::parse_str(
+            r#"(module
+                (import "env" "record" (func $record (param i32 i32) (result i32)))
+                (memory (export "memory") 1)
+                (data (i32.const 16) "\2a")
+                (func (export "main") (drop (call $record (i32.const 7) (i32.const 16)))))"#,
+        )
+        .unwrap();
+
+        let seen = std::rc::Rc::new(std::cell::RefCell::new(Vec::new()));
+        let mut engine = WasmtimeLiteEngine::new().unwrap();
+        engine.set_imports(Imports::new().with(Recorder { seen: seen.clone() }));
+
+        let handle = engine.load(1, &wasm).unwrap();
+        engine.invoke(handle, "main", &mut ()).unwrap();
+        engine.invoke(handle, "main", &mut ()).unwrap();
+        assert_eq!(*seen.borrow(), vec![(7, 0x2a), (7, 0x2a)]);
+    }
+
+    #[test]
+    fn invoke_status_reads_i32_results() {
+        let wasm = wat::parse_str(
+            r#"(module
+                (func (export "health") (result i32) (i32.const 3))
+                (func (export "main")))"#,
+        )
+        .unwrap();
+
+        let mut engine = WasmtimeLiteEngine::new().unwrap();
+        let handle = engine.load(1, &wasm).unwrap();
+        assert_eq!(engine.invoke_status(handle, "health", &mut ()), Ok(3));
+        assert_eq!(engine.invoke_status(handle, "main", &mut ()), Ok(0));
+        assert_eq!(
+            engine.invoke_status(handle, "missing", &mut ()),
+            Err(Error::EntryNotFound)
+        );
+        engine.unload(1);
+        assert_eq!(
+            engine.invoke(handle, "main", &mut ()),
+            Err(Error::ModuleNotFound)
+        );
+    }
+
+    #[test]
+    fn from_config_enforces_memory_and_stack_limits() {
+        let wasm = wat::parse_str(
+            r#"(module
+                (memory 2)
+                (func $deep (param i32)
+                    (br_if 0 (i32.eqz (local.get 0)))
+                    (call $deep (i32.sub (local.get 0) (i32.const 1))))
+                (func (export "shallow") (call $deep (i32.const 4)))
+                (func (export "deep") (call $deep (i32.const 100000))))"#,
+        )
+        .unwrap();
+
+        let config = EngineConfig {
+            max_memory_pages: Some(1),
+            ..EngineConfig::default()
+        };
+        let mut engine = WasmtimeLiteEngine::from_config(&config, Imports::new()).unwrap();
+        let handle = engine.load(1, &wasm).unwrap();
+        assert_eq!(
+            engine.invoke(handle, "shallow", &mut ()),
+            Err(Error::Engine("wasmtime instantiate"))
+        );
+
+        let config = EngineConfig {
+            max_memory_pages: Some(2),
+            ..EngineConfig::default()
+        };
+        let mut engine = WasmtimeLiteEngine::from_config(&config, Imports::new()).unwrap();
+        let handle = engine.load(1, &wasm).unwrap();
+        engine.invoke(handle, "shallow", &mut ()).unwrap();
+        assert_eq!(
+            engine.invoke(handle, "deep", &mut ()),
+            Err(Error::Engine(
+                "wasm trap: call stack exhausted in deep <- deep <- deep <- deep <- ..."
+            ))
+        );
+    }
+
+    #[test]
+    fn leaves_aot_payloads_to_other_engines() {
+        let mut engine = WasmtimeLiteEngine::<()>::new().unwrap();
+        assert_eq!(engine.load(1, b"\0aot\x03\0\0\0"), Err(Error::Unsupported));
+        assert!(!engine.capabilities().aot);
+    }
+
+    #[test]
+    fn meters_fuel_when_configured() {
+        let wasm = wat::parse_str(
+            r#"(module
+                (func (export "spin") (local i32)
+                    (loop (br_if 0 (i32.lt_u
+                        (local.tee 0 (i32.add (local.get 0) (i32.const 1)))
+                        (i32.const 100))))))"#,
+        )
+        .unwrap();
+
+        let mut engine = WasmtimeLiteEngine::new().unwrap();
+        let handle = engine.load(1, &wasm).unwrap();
+        engine.invoke(handle, "spin", &mut ()).unwrap();
+        assert_eq!(engine.last_fuel(), None);
+        assert!(!engine.capabilities().fuel);
+
+        let config = EngineConfig {
+            meter_fuel: true,
+            ..EngineConfig::default()
+        };
+        let mut engine = WasmtimeLiteEngine::from_config(&config, Imports::new()).unwrap();
+        let handle = engine.load(1, &wasm).unwrap();
+        engine.invoke(handle, "spin", &mut ()).unwrap();
+        assert!(engine.last_fuel().is_some_and(|fuel| fuel >= 100));
+        assert!(engine.capabilities().fuel);
+    }
+
+    #[test]
+    fn interrupter_stops_the_running_call_only() {
+        let wasm = wat::parse_str(
+            r#"(module
+                (func (export "forever") (loop (br 0)))
+                (func (export "main")))"#,
+        )
+        .unwrap();
+
+        let mut engine = WasmtimeLiteEngine::new().unwrap();
+        engine.set_crash_capture(Some(CrashCapture {
+            memory_offset: 0,
+            memory_len: 0,
+            stack_slots: 0,
+        }));
+        let handle = engine.load(1, &wasm).unwrap();
+        let interrupter = engine.interrupter().unwrap();
+        // Ticks between calls do not carry over.
+        interrupter.interrupt();
+        engine.invoke(handle, "main", &mut ()).unwrap();
+
+        let ticker = std::thread::spawn(move || {
+            std::thread::sleep(std::time::Duration::from_millis(50));
+            interrupter.interrupt();
+        });
+        assert!(engine.invoke(handle, "forever", &mut ()).is_err());
+        ticker.join().unwrap();
+        assert_eq!(engine.take_crash().unwrap().trap, TrapKind::Interrupted);
+        engine.invoke(handle, "main", &mut ()).unwrap();
+    }
+
+    #[test]
+    fn deadlines_and_tokens_stop_runaway_calls() {
+        let wasm = wat::parse_str(
+            r#"(module
+                (func (export "forever") (loop (br 0)))
+                (func (export "main")))"#,
+        )
+        .unwrap();
+        let mut store = crate::MemoryStore::new();
+        store.upsert(1, wasm);
+        let mut runtime = Runtime::new(WasmtimeLiteEngine::<()>::new().unwrap(), store);
+
+        let soon = Instant::now() + std::time::Duration::from_millis(50);
+        assert_eq!(
+            runtime.execute_with_deadline(1, "forever", &mut (), soon),
+            Err(DEADLINE_EXCEEDED)
+        );
+        // The bounds apply to that call only.
+        runtime.execute(1, "main", &mut ()).unwrap();
+
+        let token = CancellationToken::new();
+        let canceller = {
+            let token = token.clone();
+            thread::spawn(move || {
+                thread::sleep(std::time::Duration::from_millis(50));
+                token.cancel();
+            })
+        };
+        let later = Instant::now() + std::time::Duration::from_secs(60);
+        assert_eq!(
+            runtime.execute_cancellable(1, "forever", &mut (), &token, Some(later)),
+            Err(CANCELLED)
+        );
+        canceller.join().unwrap();
+        assert!(token.0.engines.lock().unwrap().is_empty());
+        assert_eq!(
+            runtime.execute_cancellable(1, "main", &mut (), &token, None),
+            Err(CANCELLED)
+        );
+        runtime
+            .execute_cancellable(1, "main", &mut (), &CancellationToken::new(), None)
+            .unwrap();
+    }
+
+    #[test]
+    fn trap_errors_name_frames_from_name_section() {
+        let wasm = wat::parse_str(
+            r#"(module
+                (import "env" "record" (func $record (param i32 i32) (result i32)))
+                (memory 1)
+                (func $fail (unreachable))
+                (func $bad_read (drop (call $record (i32.const 0) (i32.const 70000))))
+                (func (export "main") (call $fail))
+                (func (export "host") (call $bad_read)))"#,
+        )
+        .unwrap();
+
+        let mut engine = WasmtimeLiteEngine::new().unwrap();
+        engine.set_imports(Imports::new().with(Recorder {
+            seen: Default::default(),
+        }));
+        let handle = engine.load(1, &wasm).unwrap();
+        assert_eq!(
+            engine.invoke(handle, "main", &mut ()),
+            Err(Error::Engine(
+                "wasm trap: wasm `unreachable` instruction executed in fail <- func[3]"
+            ))
+        );
+        assert_eq!(
+            engine.invoke(handle, "host", &mut ()),
+            Err(Error::Engine(
+                "wasm trap: host import failed in bad_read <- func[4]"
+            ))
+        );
+
+        let unnamed = wat::parse_str(r#"(module (func (export "main") unreachable))"#).unwrap();
+        let handle = engine.load(2, &unnamed).unwrap();
+        assert_eq!(
+            engine.invoke(handle, "main", &mut ()),
+            Err(Error::Engine("wasmtime call"))
+        );
+    }
+
+    #[test]
+    fn stack_stats_track_memory_high_water_mark() {
+        let wasm = wat::parse_str(
+            r#"(module
+                (memory (export "memory") 1)
+                (func (export "grow") (drop (memory.grow (i32.const 2))))
+                (func (export "main")))"#,
+        )
+        .unwrap();
+
+        let mut engine = WasmtimeLiteEngine::new().unwrap();
+        let handle = engine.load(1, &wasm).unwrap();
+        assert_eq!(engine.stack_stats(handle), None);
+        engine.invoke(handle, "grow", &mut ()).unwrap();
+        engine.invoke(handle, "main", &mut ()).unwrap();
+        assert_eq!(
+            engine.stack_stats(handle),
+            Some(StackStats {
+                max_stack_slots: None,
+                memory_pages: 3,
+                last_memory_pages: 3,
+            })
+        );
+        engine.unload(1);
+        assert_eq!(engine.stack_stats(handle), None);
+    }
+
+    /// Counts calls across workers.
+    struct Counter(Arc<std::sync::atomic::AtomicU32>);
+
+    impl HostImports for Counter {
+        fn functions(&self) -> &[HostFn] {
+            const FNS: &[HostFn] = &[HostFn::new("count", 0)];
+            FNS
+        }
+
+        fn call(
+            &mut self,
+            _name: &str,
+            _args: &[i32],
+            _memory: &mut dyn GuestMemory,
+        ) -> Result<i32> {
+            self.0.fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+            Ok(OK)
+        }
+    }
+
+    #[test]
+    fn execute_async_runs_on_the_worker_pool() {
+        let wasm = wat::parse_str(
+            r#"(module
+                (import "env" "count" (func $count (result i32)))
+                (func (export "main") (drop (call $count))))"#,
+        )
+        .unwrap();
+        let mut store = crate::MemoryStore::new();
+        store.upsert(1, wasm);
+        let mut runtime = Runtime::new(WasmtimeLiteEngine::<()>::new().unwrap(), store);
+        assert_eq!(
+            runtime.execute_async(1, "main").err(),
+            Some(Error::Unsupported)
+        );
+
+        let calls = Arc::new(std::sync::atomic::AtomicU32::new(0));
+        let counter = calls.clone();
+        runtime
+            .engine()
+            .start_workers(3, move || Imports::new().with(Counter(counter.clone())))
+            .unwrap();
+        assert_eq!(runtime.engine().worker_count(), 3);
+        let pending: Vec<_> = (0..8)
+            .map(|_| runtime.execute_async(1, "main").unwrap())
+            .collect();
+        let missing = runtime.execute_async(1, "nope").unwrap();
+        for call in pending {
+            call.join().unwrap();
+        }
+        assert_eq!(calls.load(std::sync::atomic::Ordering::Relaxed), 8);
+
+        // Polled as a future.
+        let mut missing = core::pin::pin!(missing);
+        let mut cx = Context::from_waker(Waker::noop());
+        let result = loop {
+            match missing.as_mut().poll(&mut cx) {
+                Poll::Ready(result) => break result,
+                Poll::Pending => thread::yield_now(),
+            }
+        };
+        assert_eq!(result, Err(Error::EntryNotFound));
+        assert_eq!(
+            runtime.execute_async(2, "main").err(),
+            Some(Error::ModuleNotFound)
+        );
+    }
 }
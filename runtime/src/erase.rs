@@ -0,0 +1,116 @@
+//! Secure erase of modules (`secure-erase` feature).
+//!
+//! Removing a module normally only drops it from an index or a map: the
+//! bytes stay in flash until the space is reused and in freed heap until it
+//! is handed out again. Modules that embed secrets or proprietary algorithms
+//! need them gone. `Runtime::secure_erase` has the engine forget the module,
+//! overwriting the copies it keeps (`Engine::secure_unload`), then has the
+//! store overwrite every copy it holds (`SecureErase`) and drops the module
+//! from the registry. RAM copies are cleared with `zeroize`, so the writes
+//! are not optimized away.
+//!
+//! Flash stores overwrite the module's images and every erase block no live
+//! image occupies, since earlier updates, `gc` and compaction leave old
+//! copies there. That costs one erase per free block; an interrupted erase
+//! leaves the module unreachable but possibly readable, so call it again.
+//!
+//! Engines that compile modules keep code derived from them; those copies
+//! are freed, not overwritten.
+
+use crate::{Engine, MemoryStore, ModuleId, ModuleSource, Result, Runtime, Staging};
+use zeroize::Zeroize;
+
+/// A store that can overwrite every copy of a module it holds.
+pub trait SecureErase {
+    /// Removes `id` and overwrites its bytes (and any unfinished write of
+    /// it); returns whether it was stored.
+    fn secure_erase(&mut self, id: ModuleId) -> Result<bool>;
+}
+
+impl Staging {
+    /// Drops the image in progress after overwriting it.
+    pub fn wipe(&mut self) {
+        if let Some((_, _, bytes)) = &mut self.pending {
+            bytes.zeroize();
+        }
+        self.pending = None;
+    }
+
+    /// `wipe`s the image in progress when it is for `id`.
+    pub(crate) fn wipe_for(&mut self, id: ModuleId) {
+        if self.id() == Some(id) {
+            self.wipe();
+        }
+    }
+}
+
+impl SecureErase for MemoryStore {
+    fn secure_erase(&mut self, id: ModuleId) -> Result<bool> {
+        self.staging.wipe_for(id);
+        let erased = self.modules.remove(id);
+        Ok(erased.map(|mut bytes| bytes.zeroize()).is_some())
+    }
+}
+
+impl<E: Engine, S: ModuleSource + SecureErase> Runtime<E, S> {
+    /// Uninstalls a module for good: the engine and the store overwrite their
+    /// copies and the registry forgets it. Returns whether the store held it.
+    pub fn secure_erase(&mut self, module_id: ModuleId) -> Result<bool> {
+        self.engine.secure_unload(module_id);
+        let erased = self.source.secure_erase(module_id)?;
+        self.registry.remove(module_id);
+        self.memory.forget(module_id);
+        Ok(erased)
+    }
+}
+
+#[cfg(all(test, feature = "std"))]
+mod tests {
+    use super::*;
+    use crate::storage::bank::DualBankWriter;
+    use crate::storage::store::IndexedStore;
+    use crate::storage::{FlashIo, MemoryFlash};
+    use crate::write_module;
+
+    const BLOCK: u32 = 256;
+
+    fn holds(io: &MemoryFlash, byte: u8) -> bool {
+        let mut all = alloc::vec![0; io.capacity()];
+        io.read(0, &mut all).unwrap();
+        all.windows(16).any(|w| w.iter().all(|b| *b == byte))
+    }
+
+    #[test]
+    fn erased_modules_leave_no_copy_behind() {
+        let mut store = MemoryStore::new();
+        store.upsert(1, [0x11; 32]);
+        assert_eq!(store.secure_erase(1), Ok(true));
+        assert_eq!(store.secure_erase(1), Ok(false));
+        assert!(store.fetch(1).is_none());
+
+        // Images superseded and collected earlier are overwritten too.
+        let mut indexed =
+            IndexedStore::open(MemoryFlash::new(9 * BLOCK as usize), 8 * BLOCK, BLOCK).unwrap();
+        write_module(&mut indexed, 1, &[0x11; 100]).unwrap();
+        write_module(&mut indexed, 1, &[0x12; 100]).unwrap();
+        write_module(&mut indexed, 2, &[0x22; 100]).unwrap();
+        indexed.gc(&[1, 2]).unwrap();
+        assert!(holds(indexed.io(), 0x11));
+        assert_eq!(indexed.secure_erase(1), Ok(true));
+        assert!(indexed.live(1).is_none());
+        assert!(!holds(indexed.io(), 0x11) && !holds(indexed.io(), 0x12));
+        assert!(holds(indexed.io(), 0x22));
+
+        // The previous image in the other bank goes too.
+        let flash = MemoryFlash::new(2 * 4 * BLOCK as usize + 2 * BLOCK as usize);
+        let mut banks = DualBankWriter::open(flash, 4 * BLOCK, BLOCK, 2).unwrap();
+        banks.install(1, 1, &[0x11; 100]).unwrap();
+        banks.install(1, 2, &[0x12; 100]).unwrap();
+        banks.install(2, 1, &[0x22; 100]).unwrap();
+        assert!(holds(banks.io(), 0x11));
+        assert_eq!(banks.secure_erase(1), Ok(true));
+        assert!(banks.live(1).is_none());
+        assert!(!holds(banks.io(), 0x11) && !holds(banks.io(), 0x12));
+        assert!(holds(banks.io(), 0x22));
+    }
+}
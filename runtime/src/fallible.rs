@@ -0,0 +1,58 @@
+//! Allocation that reports failure instead of aborting.
+//!
+//! Image buffers on the OTA path grow with whatever a transport announces,
+//! and on a small heap the global alloc error handler would take the whole
+//! firmware down. These helpers reserve first and turn a failed reservation
+//! into `Error::OutOfMemory`, leaving the buffer as it was.
+
+use crate::{Error, Result};
+use alloc::vec::Vec;
+
+/// Room for `additional` more elements.
+pub(crate) fn reserve<T>(vec: &mut Vec<T>, additional: usize) -> Result<()> {
+    vec.try_reserve(additional).map_err(|_| Error::OutOfMemory)
+}
+
+/// Appends `bytes`.
+pub(crate) fn extend(vec: &mut Vec<u8>, bytes: &[u8]) -> Result<()> {
+    reserve(vec, bytes.len())?;
+    vec.extend_from_slice(bytes);
+    Ok(())
+}
+
+/// Resizes to `len`, filling new bytes with `byte`.
+pub(crate) fn resize(vec: &mut Vec<u8>, len: usize, byte: u8) -> Result<()> {
+    reserve(vec, len.saturating_sub(vec.len()))?;
+    vec.resize(len, byte);
+    Ok(())
+}
+
+/// `len` copies of `byte`.
+pub(crate) fn filled(len: usize, byte: u8) -> Result<Vec<u8>> {
+    let mut vec = Vec::new();
+    resize(&mut vec, len, byte)?;
+    Ok(vec)
+}
+
+#[cfg(all(test, feature = "std"))]
+mod tests {
+    use super::*;
+    use crate::{write_module, MemoryStore, ModuleSink, ModuleSource};
+
+    #[test]
+    fn oversized_images_fail_instead_of_aborting() {
+        let mut buf = b"keep".to_vec();
+        assert_eq!(extend(&mut buf, b"!"), Ok(()));
+        assert_eq!(resize(&mut buf, usize::MAX, 0), Err(Error::OutOfMemory));
+        assert_eq!(buf, b"keep!");
+
+        let mut store = MemoryStore::new();
+        write_module(&mut store, 1, &[1; 8]).unwrap();
+        assert_eq!(store.begin(2, usize::MAX), Err(Error::OutOfMemory));
+        assert_eq!(
+            store.write(&[2]),
+            Err(Error::Engine("sink: no write in progress"))
+        );
+        assert_eq!(store.fetch(1), Some(&[1; 8][..]));
+    }
+}
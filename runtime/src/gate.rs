@@ -0,0 +1,152 @@
+//! Device-state execution gate.
+//!
+//! Firmware reports what the device is doing right now (normal, charging,
+//! maintenance, low battery, ...) as a small `DeviceState` number, and each
+//! module may be restricted to a bitmask of states it is allowed to run in
+//! (bit n = state n). Masks usually come from the manifest `EXT_ALLOWED_STATES`
+//! extension. `Runtime::execute` checks the gate before fetching the module, so
+//! a denied module never reaches the engine; denials are counted and reported
+//! to `StateGate::on_denied` for logging.
+
+use crate::ModuleId;
+#[cfg(feature = "alloc")]
+use crate::{manifest::Manifest, Error, Result};
+#[cfg(feature = "alloc")]
+use alloc::{boxed::Box, vec::Vec};
+
+/// Device state reported by the firmware (0..32).
+pub type DeviceState = u8;
+
+/// Mask allowing every state.
+pub const ANY_STATE: u32 = u32::MAX;
+
+/// Builds an allowed-states mask from a list of states; states >= 32 are ignored.
+pub const fn state_mask(states: &[DeviceState]) -> u32 {
+    let mut mask = 0u32;
+    let mut i = 0;
+    while i < states.len() {
+        if states[i] < 32 {
+            mask |= 1 << states[i];
+        }
+        i += 1;
+    }
+    mask
+}
+
+/// True when `state` is part of `mask`.
+pub const fn is_allowed(mask: u32, state: DeviceState) -> bool {
+    state < 32 && mask & (1 << state) != 0
+}
+
+/// Source of the current device state.
+pub trait StateGate {
+    /// State the device is in right now.
+    fn current_state(&self) -> DeviceState;
+
+    /// Called for every denied invocation; firmware logs here. Default is a no-op.
+    ///
+    /// `correlation` is the trace id of the denied call (0 when tracing is off).
+    fn on_denied(
+        &mut self,
+        _module_id: ModuleId,
+        _entry: &str,
+        _state: DeviceState,
+        _correlation: u64,
+    ) {
+    }
+}
+
+/// Per-module allowed-state masks plus the gate that reports the current state.
+///
+/// Modules without a mask run in any state; with no gate installed every check passes.
+#[cfg(feature = "alloc")]
+pub struct StatePolicy {
+    gate: Option<Box<dyn StateGate>>,
+    masks: Vec<(ModuleId, u32)>,
+    denied: u32,
+}
+
+#[cfg(feature = "alloc")]
+impl StatePolicy {
+    /// Creates a policy with no gate and no restrictions.
+    pub const fn new() -> Self {
+        Self {
+            gate: None,
+            masks: Vec::new(),
+            denied: 0,
+        }
+    }
+
+    /// Installs the gate reporting the device state.
+    pub fn set_gate(&mut self, gate: impl StateGate + 'static) {
+        self.gate = Some(Box::new(gate));
+    }
+
+    /// Restricts `module_id` to the states in `mask`; `ANY_STATE` lifts the restriction.
+    pub fn restrict(&mut self, module_id: ModuleId, mask: u32) {
+        let pos = self.masks.iter().position(|(id, _)| *id == module_id);
+        match (pos, mask) {
+            (Some(pos), ANY_STATE) => {
+                self.masks.swap_remove(pos);
+            }
+            (Some(pos), _) => self.masks[pos].1 = mask,
+            (None, ANY_STATE) => {}
+            (None, _) => self.masks.push((module_id, mask)),
+        }
+    }
+
+    /// Applies the manifest's allowed-states extension (no-op when absent).
+    pub fn apply_manifest(&mut self, manifest: &Manifest<'_>) {
+        if let Some(mask) = manifest.allowed_states() {
+            self.restrict(manifest.module_id, mask);
+        }
+    }
+
+    /// Mask for `module_id` (`ANY_STATE` when unrestricted).
+    pub fn allowed(&self, module_id: ModuleId) -> u32 {
+        self.masks
+            .iter()
+            .find(|(id, _)| *id == module_id)
+            .map_or(ANY_STATE, |(_, mask)| *mask)
+    }
+
+    /// Number of invocations denied so far.
+    pub fn denied_count(&self) -> u32 {
+        self.denied
+    }
+
+    /// Checks whether `module_id` may run now; denials are counted and reported to the gate.
+    pub fn check(&mut self, module_id: ModuleId, entry: &str, correlation: u64) -> Result<()> {
+        let mask = self.allowed(module_id);
+        let Some(gate) = self.gate.as_mut() else {
+            return Ok(());
+        };
+        let state = gate.current_state();
+        if is_allowed(mask, state) {
+            return Ok(());
+        }
+        self.denied = self.denied.saturating_add(1);
+        gate.on_denied(module_id, entry, state, correlation);
+        Err(Error::StateDenied)
+    }
+}
+
+#[cfg(feature = "alloc")]
+impl Default for StatePolicy {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(all(test, feature = "std"))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn masks_from_state_lists() {
+        assert_eq!(state_mask(&[0, 2, 40]), 0b101);
+        assert!(is_allowed(0b101, 2));
+        assert!(!is_allowed(0b101, 1));
+        assert!(!is_allowed(ANY_STATE, 32));
+    }
+}
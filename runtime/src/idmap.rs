@@ -0,0 +1,167 @@
+//! Module-id keyed map shared by stores, caches and engines.
+//!
+//! Backed by a `HashMap` with `std` (O(1) lookups) and a `BTreeMap` without
+//! it (O(log n)), so fleets with dozens of modules do not pay a linear scan on
+//! every invocation. Iteration is in ascending id order in both builds; with
+//! `std` that sorts the ids on each walk, which is fine for the listings that
+//! need it. An optional capacity bound makes inserts of new ids fail once
+//! full.
+
+use crate::{Error, ModuleId, Result};
+#[cfg(feature = "std")]
+use alloc::vec::Vec;
+
+#[cfg(feature = "std")]
+type Map<V> = std::collections::HashMap<ModuleId, V>;
+#[cfg(not(feature = "std"))]
+type Map<V> = alloc::collections::BTreeMap<ModuleId, V>;
+
+/// Map from module id to `V`.
+#[derive(Debug, Clone)]
+pub struct IdMap<V> {
+    entries: Map<V>,
+    limit: Option<usize>,
+}
+
+impl<V> Default for IdMap<V> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<V> IdMap<V> {
+    /// Creates an unbounded map.
+    pub fn new() -> Self {
+        Self {
+            entries: Map::new(),
+            limit: None,
+        }
+    }
+
+    /// Creates a map holding at most `max_entries` ids.
+    pub fn with_capacity_limit(max_entries: usize) -> Self {
+        Self {
+            limit: Some(max_entries),
+            ..Self::new()
+        }
+    }
+
+    /// Number of entries.
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    /// True when empty.
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// True when `id` has an entry.
+    pub fn contains(&self, id: ModuleId) -> bool {
+        self.entries.contains_key(&id)
+    }
+
+    /// Value for `id`.
+    pub fn get(&self, id: ModuleId) -> Option<&V> {
+        self.entries.get(&id)
+    }
+
+    /// Mutable value for `id`.
+    pub fn get_mut(&mut self, id: ModuleId) -> Option<&mut V> {
+        self.entries.get_mut(&id)
+    }
+
+    /// Inserts or replaces; returns the replaced value. Fails only for a new
+    /// id when the capacity limit is reached.
+    pub fn insert(&mut self, id: ModuleId, value: V) -> Result<Option<V>> {
+        let full = self.limit.is_some_and(|limit| self.len() >= limit);
+        if full && !self.contains(id) {
+            return Err(Error::Engine("id map full"));
+        }
+        Ok(self.entries.insert(id, value))
+    }
+
+    /// Makes room for `additional` new ids, so the inserts that follow do
+    /// not allocate; `Error::OutOfMemory` if the heap cannot provide it.
+    /// Without `std` the `BTreeMap` allocates a node per insert instead, so
+    /// there is nothing to reserve up front.
+    pub fn try_reserve(&mut self, additional: usize) -> Result<()> {
+        #[cfg(feature = "std")]
+        return self
+            .entries
+            .try_reserve(additional)
+            .map_err(|_| Error::OutOfMemory);
+        #[cfg(not(feature = "std"))]
+        {
+            let _ = additional;
+            Ok(())
+        }
+    }
+
+    /// Removes and returns the value for `id`.
+    pub fn remove(&mut self, id: ModuleId) -> Option<V> {
+        self.entries.remove(&id)
+    }
+
+    /// Keeps only the entries for which `keep` returns true.
+    pub fn retain(&mut self, mut keep: impl FnMut(ModuleId, &V) -> bool) {
+        self.entries.retain(|id, value| keep(*id, value));
+    }
+
+    /// Removes everything.
+    pub fn clear(&mut self) {
+        self.entries.clear();
+    }
+
+    /// The `index`-th id in ascending order.
+    pub fn id_at(&self, index: usize) -> Option<ModuleId> {
+        #[cfg(feature = "std")]
+        {
+            let mut ids: Vec<ModuleId> = self.entries.keys().copied().collect();
+            (index < ids.len()).then(|| *ids.select_nth_unstable(index).1)
+        }
+        #[cfg(not(feature = "std"))]
+        self.entries.keys().nth(index).copied()
+    }
+
+    /// Entries in ascending id order.
+    pub fn iter(&self) -> impl Iterator<Item = (ModuleId, &V)> {
+        #[cfg(feature = "std")]
+        {
+            let mut ids: Vec<ModuleId> = self.entries.keys().copied().collect();
+            ids.sort_unstable();
+            ids.into_iter().map(|id| (id, &self.entries[&id]))
+        }
+        #[cfg(not(feature = "std"))]
+        self.entries.iter().map(|(id, value)| (*id, value))
+    }
+}
+
+#[cfg(all(test, feature = "std"))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn keeps_ids_sorted_and_respects_limit() {
+        let mut map = IdMap::with_capacity_limit(3);
+        assert_eq!(map.insert(9, "nine"), Ok(None));
+        assert_eq!(map.insert(2, "two"), Ok(None));
+        assert_eq!(map.insert(5, "five"), Ok(None));
+        assert_eq!(map.insert(7, "seven"), Err(Error::Engine("id map full")));
+        assert_eq!(map.insert(2, "deux"), Ok(Some("two")));
+
+        assert_eq!(
+            map.iter().collect::<Vec<_>>(),
+            [(2, &"deux"), (5, &"five"), (9, &"nine")]
+        );
+        assert_eq!(map.id_at(1), Some(5));
+        assert_eq!(map.remove(5), Some("five"));
+        assert!(!map.contains(5));
+
+        map.retain(|id, _| id > 3);
+        assert_eq!(map.iter().collect::<Vec<_>>(), [(9, &"nine")]);
+        assert_eq!(map.insert(7, "seven"), Ok(None));
+        *map.get_mut(7).unwrap() = "sept";
+        assert_eq!(map.get(7), Some(&"sept"));
+    }
+}
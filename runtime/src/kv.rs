@@ -0,0 +1,315 @@
+//! Small key-value state for guests (calibration, counters).
+//!
+//! Values live in a pluggable `KvStore` (RAM, NVS, littlefs, ...) and are
+//! namespaced by module id rather than by module bytes, so state survives both
+//! across invocations and across OTA updates of the same module.
+//!
+//! Guest imports (module `env`, all `i32`):
+//! - `kv_get(key_ptr, key_len, buf_ptr, buf_cap) -> len | status` – returns the
+//!   value's full length and copies it when it fits in `buf_cap`; `E_EMPTY` when missing.
+//! - `kv_set(key_ptr, key_len, val_ptr, val_len) -> status`
+//!
+//! The top namespaces (`RESERVED_NAMESPACES` and up) hold the runtime's own
+//! records in a store it shares with guests: the update log, the signature
+//! cache, switches, trust marks and the revocation list. A module whose id
+//! falls there gets `E_DENIED` from both calls.
+
+use crate::abi::{
+    guest_bytes, GuestMemory, HostFn, HostImports, E_DENIED, E_DEVICE, E_EMPTY, E_INVALID, OK,
+};
+use crate::{Error, ModuleId, Result};
+#[cfg(feature = "alloc")]
+use alloc::{rc::Rc, vec::Vec};
+#[cfg(feature = "alloc")]
+use core::cell::RefCell;
+
+/// Longest key in bytes.
+pub const MAX_KEY_LEN: usize = 32;
+/// Largest value in bytes.
+pub const MAX_VALUE_LEN: usize = 256;
+
+/// First namespace reserved for the runtime's own records; guests cannot
+/// address it or any above it.
+pub const RESERVED_NAMESPACES: ModuleId = ModuleId::MAX - 15;
+
+/// True for namespaces only the runtime writes.
+pub const fn is_reserved(namespace: ModuleId) -> bool {
+    namespace >= RESERVED_NAMESPACES
+}
+
+/// Host functions provided by `KvImports`.
+pub const FUNCTIONS: &[HostFn] = &[HostFn::new("kv_get", 4), HostFn::new("kv_set", 4)];
+
+/// Persistent storage for small values, namespaced per module.
+pub trait KvStore {
+    /// Copies the value into `buf` when it fits and returns its full length;
+    /// `None` when the key is missing.
+    fn get(&self, namespace: ModuleId, key: &[u8], buf: &mut [u8]) -> Result<Option<usize>>;
+
+    /// Inserts or replaces a value.
+    fn set(&mut self, namespace: ModuleId, key: &[u8], value: &[u8]) -> Result<()>;
+
+    /// Removes a value; returns whether it existed.
+    fn remove(&mut self, namespace: ModuleId, key: &[u8]) -> Result<bool>;
+}
+
+/// Shared stores: keep one clone for firmware and hand another to `KvImports`.
+#[cfg(feature = "alloc")]
+impl<K: KvStore> KvStore for Rc<RefCell<K>> {
+    fn get(&self, namespace: ModuleId, key: &[u8], buf: &mut [u8]) -> Result<Option<usize>> {
+        self.borrow().get(namespace, key, buf)
+    }
+
+    fn set(&mut self, namespace: ModuleId, key: &[u8], value: &[u8]) -> Result<()> {
+        self.borrow_mut().set(namespace, key, value)
+    }
+
+    fn remove(&mut self, namespace: ModuleId, key: &[u8]) -> Result<bool> {
+        self.borrow_mut().remove(namespace, key)
+    }
+}
+
+/// RAM-backed store (lost on reset); optional cap on the number of entries.
+#[cfg(feature = "alloc")]
+#[derive(Debug, Default, Clone)]
+pub struct MemoryKv {
+    entries: Vec<(ModuleId, Vec<u8>, Vec<u8>)>,
+    max_entries: Option<usize>,
+}
+
+#[cfg(feature = "alloc")]
+impl MemoryKv {
+    /// Creates an unbounded store.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Creates a store holding at most `max_entries` values.
+    pub fn with_capacity_limit(max_entries: usize) -> Self {
+        Self {
+            entries: Vec::new(),
+            max_entries: Some(max_entries),
+        }
+    }
+
+    /// Number of stored values.
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    /// True when nothing is stored.
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    fn position(&self, namespace: ModuleId, key: &[u8]) -> Option<usize> {
+        self.entries
+            .iter()
+            .position(|(ns, k, _)| *ns == namespace && k == key)
+    }
+}
+
+#[cfg(feature = "alloc")]
+impl KvStore for MemoryKv {
+    fn get(&self, namespace: ModuleId, key: &[u8], buf: &mut [u8]) -> Result<Option<usize>> {
+        Ok(self.position(namespace, key).map(|pos| {
+            let value = &self.entries[pos].2;
+            if let Some(dst) = buf.get_mut(..value.len()) {
+                dst.copy_from_slice(value);
+            }
+            value.len()
+        }))
+    }
+
+    fn set(&mut self, namespace: ModuleId, key: &[u8], value: &[u8]) -> Result<()> {
+        match self.position(namespace, key) {
+            Some(pos) => self.entries[pos].2 = value.to_vec(),
+            None => {
+                if self
+                    .max_entries
+                    .is_some_and(|max| self.entries.len() >= max)
+                {
+                    return Err(Error::Engine("kv: store full"));
+                }
+                self.entries.push((namespace, key.to_vec(), value.to_vec()));
+            }
+        }
+        Ok(())
+    }
+
+    fn remove(&mut self, namespace: ModuleId, key: &[u8]) -> Result<bool> {
+        Ok(self
+            .position(namespace, key)
+            .map(|pos| self.entries.swap_remove(pos))
+            .is_some())
+    }
+}
+
+/// `kv_get` / `kv_set` imports over a `KvStore`, scoped to the running module.
+pub struct KvImports<K> {
+    store: K,
+    active: Option<ModuleId>,
+}
+
+impl<K: KvStore> KvImports<K> {
+    /// Creates the import set over `store`.
+    pub fn new(store: K) -> Self {
+        Self {
+            store,
+            active: None,
+        }
+    }
+
+    fn active(&self) -> Result<ModuleId> {
+        self.active.ok_or(Error::Engine("kv: no active module"))
+    }
+
+    /// Namespace of the running module; `None` when it is reserved.
+    fn namespace(&self) -> Result<Option<ModuleId>> {
+        let module_id = self.active()?;
+        Ok(Some(module_id).filter(|id| !is_reserved(*id)))
+    }
+
+    fn kv_get(&mut self, args: [i32; 4], memory: &mut dyn GuestMemory) -> Result<i32> {
+        let [key_ptr, key_len, buf_ptr, buf_cap] = args;
+        let Some(namespace) = self.namespace()? else {
+            return Ok(E_DENIED);
+        };
+        let mut key = [0u8; MAX_KEY_LEN];
+        let key = guest_bytes(memory, key_ptr, key_len, &mut key);
+        let (Some(key), Ok(buf_cap)) =
+            (key.filter(|key| !key.is_empty()), usize::try_from(buf_cap))
+        else {
+            return Ok(E_INVALID);
+        };
+        let mut value = [0u8; MAX_VALUE_LEN];
+        let len = match self.store.get(namespace, key, &mut value) {
+            Ok(Some(len)) => len,
+            Ok(None) => return Ok(E_EMPTY),
+            Err(_) => return Ok(E_DEVICE),
+        };
+        if len <= buf_cap
+            && len <= MAX_VALUE_LEN
+            && memory.write(buf_ptr as u32, &value[..len]).is_err()
+        {
+            return Ok(E_INVALID);
+        }
+        Ok(len as i32)
+    }
+
+    fn kv_set(&mut self, args: [i32; 4], memory: &dyn GuestMemory) -> Result<i32> {
+        let [key_ptr, key_len, val_ptr, val_len] = args;
+        let Some(namespace) = self.namespace()? else {
+            return Ok(E_DENIED);
+        };
+        let mut key = [0u8; MAX_KEY_LEN];
+        let key = guest_bytes(memory, key_ptr, key_len, &mut key);
+        let Some(key) = key.filter(|key| !key.is_empty()) else {
+            return Ok(E_INVALID);
+        };
+        let mut value = [0u8; MAX_VALUE_LEN];
+        let Some(value) = guest_bytes(memory, val_ptr, val_len, &mut value) else {
+            return Ok(E_INVALID);
+        };
+        match self.store.set(namespace, key, value) {
+            Ok(()) => Ok(OK),
+            Err(_) => Ok(E_DEVICE),
+        }
+    }
+}
+
+impl<K: KvStore> HostImports for KvImports<K> {
+    fn functions(&self) -> &[HostFn] {
+        FUNCTIONS
+    }
+
+    fn call(&mut self, name: &str, args: &[i32], memory: &mut dyn GuestMemory) -> Result<i32> {
+        match (name, args) {
+            ("kv_get", &[a, b, c, d]) => self.kv_get([a, b, c, d], memory),
+            ("kv_set", &[a, b, c, d]) => self.kv_set([a, b, c, d], memory),
+            _ => Err(Error::Engine("kv: bad host call")),
+        }
+    }
+
+    fn enter(&mut self, module_id: ModuleId) {
+        self.active = Some(module_id);
+    }
+}
+
+#[cfg(all(test, feature = "std"))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn values_are_namespaced_per_module() {
+        let store = Rc::new(RefCell::new(MemoryKv::new()));
+        let mut imports = KvImports::new(store.clone());
+        let mut memory = [0u8; 32];
+        memory[..3].copy_from_slice(b"cal");
+        memory[4..6].copy_from_slice(&[0x12, 0x34]);
+
+        imports.enter(1);
+        assert_eq!(
+            imports.call("kv_set", &[0, 3, 4, 2], &mut memory).unwrap(),
+            OK
+        );
+        assert_eq!(
+            imports.call("kv_get", &[0, 3, 8, 1], &mut memory).unwrap(),
+            2
+        );
+        assert_eq!(memory[8], 0);
+        assert_eq!(
+            imports.call("kv_get", &[0, 3, 8, 4], &mut memory).unwrap(),
+            2
+        );
+        assert_eq!(&memory[8..10], &[0x12, 0x34]);
+
+        imports.enter(2);
+        assert_eq!(
+            imports.call("kv_get", &[0, 3, 8, 4], &mut memory).unwrap(),
+            E_EMPTY
+        );
+        assert_eq!(
+            imports.call("kv_set", &[0, 0, 4, 2], &mut memory).unwrap(),
+            E_INVALID
+        );
+
+        // Firmware sees the same store.
+        let mut buf = [0u8; 2];
+        assert_eq!(store.get(1, b"cal", &mut buf).unwrap(), Some(2));
+        assert!(store.borrow_mut().remove(1, b"cal").unwrap());
+        assert!(store.borrow().is_empty());
+    }
+
+    #[test]
+    fn runtime_namespaces_are_out_of_reach() {
+        let store = Rc::new(RefCell::new(MemoryKv::new()));
+        let mut imports = KvImports::new(store.clone());
+        let mut memory = [0u8; 32];
+        memory[..3].copy_from_slice(b"sig");
+
+        // A module installed under the signature cache's namespace.
+        imports.enter(ModuleId::MAX - 1);
+        assert_eq!(
+            imports.call("kv_set", &[0, 3, 4, 2], &mut memory),
+            Ok(E_DENIED)
+        );
+        assert_eq!(
+            imports.call("kv_get", &[0, 3, 8, 4], &mut memory),
+            Ok(E_DENIED)
+        );
+        assert!(store.borrow().is_empty());
+
+        imports.enter(RESERVED_NAMESPACES - 1);
+        assert_eq!(imports.call("kv_set", &[0, 3, 4, 2], &mut memory), Ok(OK));
+    }
+
+    #[test]
+    fn capacity_limit_is_enforced() {
+        let mut kv = MemoryKv::with_capacity_limit(1);
+        kv.set(1, b"a", b"1").unwrap();
+        kv.set(1, b"a", b"2").unwrap();
+        assert!(kv.set(1, b"b", b"3").is_err());
+        assert_eq!(kv.len(), 1);
+    }
+}
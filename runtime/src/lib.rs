@@ -1,7 +1,19 @@
 #![cfg_attr(not(feature = "std"), no_std)]
-
 // Minimal runtime harness for OTA-delivered WebAssembly modules.
 
+//! # Stability
+//!
+//! The stable core is what `prelude` re-exports (`Runtime`, `Engine`,
+//! `ModuleSource`, `ModuleCatalog`, `Error`, the host ABI traits, `Manifest`,
+//! `MemoryStore`, `CachedEngine`) plus the other public modules below, except:
+//!
+//! - `audit`, `diff`, `trust`, `update` – unstable; compiled only with the `unstable`
+//!   feature and free to change in any minor release.
+//!
+//! `Error` is `#[non_exhaustive]`, so new failure cases are not breaking;
+//! match with a wildcard arm. Renamed APIs keep a `#[deprecated]` shim for at
+//! least one minor release.
+
 #[cfg(feature = "alloc")]
 extern crate alloc;
 
@@ -15,6 +27,7 @@ pub type Result<T> = core::result::Result<T, Error>;
 
 /// Common error cases for the runtime and engines.
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[non_exhaustive]
 pub enum Error {
     /// The requested module is not present in the current store.
     ModuleNotFound,
@@ -24,22 +37,244 @@ pub enum Error {
     Engine(&'static str),
     /// The operation is not supported by the current configuration.
     Unsupported,
+    /// The module is not allowed to run in the current device state.
+    StateDenied,
+    /// `module` depends on a module that is not installed.
+    DependencyMissing { module: ModuleId },
+    /// `module` needs at least version `required` of `dependency`, which is at `found`.
+    DependencyTooOld {
+        module: ModuleId,
+        dependency: ModuleId,
+        required: u32,
+        found: u32,
+    },
+    /// `module` is part of a dependency cycle.
+    DependencyCycle { module: ModuleId },
+    /// The module used up its invocation or fuel quota for the current window.
+    QuotaExceeded,
+    /// A host function tried to run a module nested deeper than allowed, or
+    /// through a runtime that is already running (see `nest`).
+    Reentrancy,
+    /// An image of `needed` bytes does not fit the `available` bytes left by
+    /// the store or its quota.
+    StorageFull { needed: usize, available: usize },
+    /// The module is installed but switched off (see `switch`).
+    ModuleDisabled,
+    /// A buffer could not be allocated; nothing was changed.
+    OutOfMemory,
+    /// The entry point returned this negative status: a failure the module
+    /// reported itself, as opposed to a trap (see `ExitCode`).
+    GuestError(i32),
+    /// The module was built for `guest`, an ABI version this host (at
+    /// `host`) does not speak (see `abi::version`).
+    AbiMismatch { host: u16, guest: u16 },
+    /// The module's digest or its signer is on the revocation list (see
+    /// `revoke`).
+    Revoked,
+    /// The runtime is suspended; nothing loads or runs until
+    /// `Runtime::resume` (see `suspend`).
+    Suspended,
 }
 
-impl fmt::Display for Error {
-    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+impl Error {
+    /// Flag set in `code` for `Error::Engine`; the low 15 bits hash the
+    /// message.
+    pub const ENGINE_CODE: u16 = 0x8000;
+
+    /// Compact numeric form for logs and telemetry: a stable number per kind
+    /// in declaration order (1 = `ModuleNotFound` … 17 = `Suspended`), and
+    /// for `Engine` the `ENGINE_CODE` flag plus a hash of the message, so a
+    /// host can map it back with a table of the known messages. Fields are
+    /// dropped.
+    pub fn code(&self) -> u16 {
         match self {
-            Error::ModuleNotFound => f.write_str("module not found"),
-            Error::EntryNotFound => f.write_str("entry not found"),
-            Error::Engine(msg) => f.write_str(msg),
-            Error::Unsupported => f.write_str("operation not supported"),
+            Error::ModuleNotFound => 1,
+            Error::EntryNotFound => 2,
+            Error::Engine(msg) => {
+                // FNV-1a, folded to 15 bits.
+                let hash = msg.bytes().fold(0x811c_9dc5u32, |hash, byte| {
+                    (hash ^ u32::from(byte)).wrapping_mul(0x0100_0193)
+                });
+                Self::ENGINE_CODE | ((hash ^ (hash >> 15)) as u16 & 0x7fff)
+            }
+            Error::Unsupported => 4,
+            Error::StateDenied => 5,
+            Error::DependencyMissing { .. } => 6,
+            Error::DependencyTooOld { .. } => 7,
+            Error::DependencyCycle { .. } => 8,
+            Error::QuotaExceeded => 9,
+            Error::Reentrancy => 10,
+            Error::StorageFull { .. } => 11,
+            Error::ModuleDisabled => 12,
+            Error::OutOfMemory => 13,
+            Error::GuestError(_) => 14,
+            Error::AbiMismatch { .. } => 15,
+            Error::Revoked => 16,
+            Error::Suspended => 17,
+        }
+    }
+
+    /// Writes the message `Display` shows, with plain `write_str` calls, so
+    /// no_std builds can log it without pulling in integer formatting.
+    pub fn write_to(&self, out: &mut impl fmt::Write) -> fmt::Result {
+        let mut parts = |parts: &[Part<'_>]| {
+            parts.iter().try_for_each(|part| match *part {
+                Part::Text(text) => out.write_str(text),
+                Part::Number(n) => write_decimal(out, n),
+            })
+        };
+        use Part::{Number as N, Text as T};
+        match *self {
+            Error::ModuleNotFound => parts(&[T("module not found")]),
+            Error::EntryNotFound => parts(&[T("entry not found")]),
+            Error::Engine(msg) => parts(&[T(msg)]),
+            Error::Unsupported => parts(&[T("operation not supported")]),
+            Error::StateDenied => parts(&[T("module not allowed in current device state")]),
+            Error::DependencyMissing { module } => parts(&[
+                T("module "),
+                N(module.into()),
+                T(" has a missing dependency"),
+            ]),
+            Error::DependencyTooOld {
+                module,
+                dependency,
+                required,
+                found,
+            } => parts(&[
+                T("module "),
+                N(module.into()),
+                T(" needs module "),
+                N(dependency.into()),
+                T(" >= v"),
+                N(required.into()),
+                T(", found v"),
+                N(found.into()),
+            ]),
+            Error::DependencyCycle { module } => parts(&[
+                T("module "),
+                N(module.into()),
+                T(" is part of a dependency cycle"),
+            ]),
+            Error::QuotaExceeded => parts(&[T("module quota exceeded")]),
+            Error::Reentrancy => parts(&[T("nested invocation refused")]),
+            Error::StorageFull { needed, available } => parts(&[
+                T("storage full: "),
+                N(needed as u64),
+                T(" bytes needed, "),
+                N(available as u64),
+                T(" available"),
+            ]),
+            Error::ModuleDisabled => parts(&[T("module disabled")]),
+            Error::OutOfMemory => parts(&[T("out of memory")]),
+            Error::GuestError(code) => {
+                parts(&[T("module reported error -"), N(code.unsigned_abs().into())])
+            }
+            Error::AbiMismatch { host, guest } => parts(&[
+                T("module built for ABI v"),
+                N(guest.into()),
+                T(", host speaks v"),
+                N(host.into()),
+            ]),
+            Error::Revoked => parts(&[T("module revoked")]),
+            Error::Suspended => parts(&[T("runtime suspended")]),
+        }
+    }
+
+    /// Writes the message into `buf`, truncated at a character boundary if
+    /// it does not fit, and returns the part written.
+    pub fn render<'b>(&self, buf: &'b mut [u8]) -> &'b str {
+        let mut out = Truncate { buf, len: 0 };
+        // Fails only when cut short.
+        let _ = self.write_to(&mut out);
+        let Truncate { buf, len } = out;
+        // Only whole characters were copied.
+        core::str::from_utf8(&buf[..len]).unwrap_or("")
+    }
+}
+
+/// Piece of an error message.
+enum Part<'a> {
+    Text(&'a str),
+    Number(u64),
+}
+
+fn write_decimal(out: &mut impl fmt::Write, mut n: u64) -> fmt::Result {
+    let mut digits = [0u8; 20];
+    let mut at = digits.len();
+    loop {
+        at -= 1;
+        digits[at] = b'0' + (n % 10) as u8;
+        n /= 10;
+        if n == 0 {
+            break;
+        }
+    }
+    // ASCII digits only.
+    out.write_str(core::str::from_utf8(&digits[at..]).unwrap_or(""))
+}
+
+/// `fmt::Write` into a byte buffer; fails once something did not fit, so
+/// nothing is appended after a cut.
+struct Truncate<'b> {
+    buf: &'b mut [u8],
+    len: usize,
+}
+
+impl fmt::Write for Truncate<'_> {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        let room = self.buf.len() - self.len;
+        let mut take = s.len().min(room);
+        while !s.is_char_boundary(take) {
+            take -= 1;
         }
+        self.buf[self.len..self.len + take].copy_from_slice(&s.as_bytes()[..take]);
+        self.len += take;
+        if take < s.len() {
+            return Err(fmt::Error);
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.write_to(f)
     }
 }
 
 #[cfg(feature = "std")]
 impl std::error::Error for Error {}
 
+/// Status of an entry point that completed normally.
+///
+/// Entry points returning an `i32` follow one convention: 0 or a positive
+/// value means success (`ExitCode`), a negative value a recoverable failure
+/// the module chose to report (`Error::GuestError`), and a trap stays an
+/// engine error. guest-wasm's `status` module produces such values.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord)]
+pub struct ExitCode(i32);
+
+impl ExitCode {
+    pub const SUCCESS: ExitCode = ExitCode(0);
+
+    /// Splits a raw status along the convention.
+    pub fn from_status(status: i32) -> Result<Self> {
+        match status {
+            0.. => Ok(ExitCode(status)),
+            _ => Err(Error::GuestError(status)),
+        }
+    }
+
+    /// The status, never negative.
+    pub fn get(self) -> i32 {
+        self.0
+    }
+
+    pub fn is_success(self) -> bool {
+        self == Self::SUCCESS
+    }
+}
+
 /// Source of WASM bytecode.
 pub trait ModuleSource {
     /// Fetches raw bytes for a module id. Returned slice must stay valid for the
@@ -47,6 +282,198 @@ pub trait ModuleSource {
     fn fetch(&self, id: ModuleId) -> Option<&[u8]>;
 }
 
+/// Writable counterpart of `ModuleSource`: streams one module image in, so
+/// OTA transports and installers can target any backend the same way.
+///
+/// A write is `begin`, any number of `write` calls, then `commit` or
+/// `abort`. Until `commit` succeeds the previous image (if any) stays visible.
+pub trait ModuleSink {
+    /// Starts writing a `len`-byte image for `id`, dropping any unfinished write.
+    fn begin(&mut self, id: ModuleId, len: usize) -> Result<()>;
+
+    /// Appends the next chunk; fails if it runs past the announced length.
+    fn write(&mut self, chunk: &[u8]) -> Result<()>;
+
+    /// Publishes the image; fails unless exactly the announced length arrived.
+    fn commit(&mut self) -> Result<()>;
+
+    /// Drops the unfinished write.
+    fn abort(&mut self);
+}
+
+/// Writes a whole image through a sink, aborting on failure.
+pub fn write_module<K: ModuleSink + ?Sized>(
+    sink: &mut K,
+    id: ModuleId,
+    bytes: &[u8],
+) -> Result<()> {
+    sink.begin(id, bytes.len())?;
+    let written = sink.write(bytes).and_then(|()| sink.commit());
+    if written.is_err() {
+        sink.abort();
+    }
+    written
+}
+
+/// Image being received by a RAM-staged `ModuleSink`.
+#[cfg(feature = "alloc")]
+#[derive(Debug, Default)]
+pub struct Staging {
+    pending: Option<(ModuleId, usize, Vec<u8>)>,
+}
+
+#[cfg(feature = "alloc")]
+impl Staging {
+    /// Starts a new image, replacing any unfinished one; the whole image is
+    /// allocated up front, so a heap too small for it fails here with
+    /// `Error::OutOfMemory`.
+    pub fn begin(&mut self, id: ModuleId, len: usize) -> Result<()> {
+        self.pending = None;
+        let mut bytes = Vec::new();
+        fallible::reserve(&mut bytes, len)?;
+        self.pending = Some((id, len, bytes));
+        Ok(())
+    }
+
+    /// Appends a chunk within the announced length.
+    pub fn write(&mut self, chunk: &[u8]) -> Result<()> {
+        let (_, len, bytes) = self
+            .pending
+            .as_mut()
+            .ok_or(Error::Engine("sink: no write in progress"))?;
+        if bytes.len() + chunk.len() > *len {
+            return Err(Error::Engine("sink: more bytes than announced"));
+        }
+        fallible::extend(bytes, chunk)
+    }
+
+    /// Id of the image in progress.
+    pub fn id(&self) -> Option<ModuleId> {
+        self.pending.as_ref().map(|(id, _, _)| *id)
+    }
+
+    /// Hands over a complete image; an incomplete one stays pending.
+    pub fn finish(&mut self) -> Result<(ModuleId, Vec<u8>)> {
+        match self.pending.take() {
+            Some((id, len, bytes)) if bytes.len() == len => Ok((id, bytes)),
+            Some(pending) => {
+                self.pending = Some(pending);
+                Err(Error::Engine("sink: image incomplete"))
+            }
+            None => Err(Error::Engine("sink: no write in progress")),
+        }
+    }
+
+    /// Drops the image in progress.
+    pub fn abort(&mut self) {
+        self.pending = None;
+    }
+}
+
+/// A `ModuleSource` that can list what it stores, so the runtime, audit and
+/// scheduler can discover modules without out-of-band knowledge.
+///
+/// Listing is index based to stay allocation free; `iter_ids` wraps it.
+pub trait ModuleCatalog: ModuleSource {
+    /// Number of stored modules.
+    fn module_count(&self) -> usize;
+
+    /// Id of the `index`-th stored module (`index < module_count()`).
+    fn module_id_at(&self, index: usize) -> Option<ModuleId>;
+
+    /// Ids of all stored modules, in storage order.
+    fn iter_ids(&self) -> CatalogIds<'_, Self>
+    where
+        Self: Sized,
+    {
+        CatalogIds {
+            catalog: self,
+            next: 0,
+        }
+    }
+}
+
+/// Iterator returned by `ModuleCatalog::iter_ids`.
+pub struct CatalogIds<'a, C> {
+    catalog: &'a C,
+    next: usize,
+}
+
+impl<C: ModuleCatalog> Iterator for CatalogIds<'_, C> {
+    type Item = ModuleId;
+
+    fn next(&mut self) -> Option<ModuleId> {
+        while self.next < self.catalog.module_count() {
+            self.next += 1;
+            if let Some(id) = self.catalog.module_id_at(self.next - 1) {
+                return Some(id);
+            }
+        }
+        None
+    }
+}
+
+/// High-water marks for one module across its invocations.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct StackStats {
+    /// Most value-stack slots in use at once; `None` when the engine cannot
+    /// measure it.
+    pub max_stack_slots: Option<u32>,
+    /// Largest linear memory seen after an invocation, in 64 KiB pages.
+    pub memory_pages: u32,
+    /// Linear memory after the latest invocation, in 64 KiB pages.
+    pub last_memory_pages: u32,
+}
+
+impl StackStats {
+    /// Folds in one invocation's measurements.
+    pub fn record(&mut self, stack_slots: Option<u32>, memory_pages: u32) {
+        self.max_stack_slots = match (self.max_stack_slots, stack_slots) {
+            (Some(max), Some(used)) => Some(max.max(used)),
+            (max, used) => max.or(used),
+        };
+        self.memory_pages = self.memory_pages.max(memory_pages);
+        self.last_memory_pages = memory_pages;
+    }
+}
+
+/// What an engine supports, so callers can pick a code path up front instead
+/// of meeting `Error::Unsupported` (or a silent fallback) mid-flight.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct EngineCaps {
+    /// `invoke_status` reads `i32` results instead of reporting 0.
+    pub typed_calls: bool,
+    /// Host functions (`abi::Imports`) can be linked.
+    pub imports: bool,
+    /// Host functions and crash capture can read guest linear memory.
+    pub memory_access: bool,
+    /// Calls can be metered (`last_fuel`, fuel quotas).
+    pub fuel: bool,
+    /// Modules execute in place from the source's bytes, without a RAM copy.
+    pub xip: bool,
+    /// Several modules can stay loaded at once.
+    pub multiple_instances: bool,
+    /// Loads WAMR AOT artifacts (`manifest::PayloadKind::Aot`) besides wasm.
+    pub aot: bool,
+    /// Modules keep their state between calls, and `snapshot`/`restore` work
+    /// (`EngineConfig::keep_state`).
+    pub snapshots: bool,
+}
+
+impl EngineCaps {
+    /// Supports none of the above; the default for engines that do not say.
+    pub const NONE: Self = Self {
+        typed_calls: false,
+        imports: false,
+        memory_access: false,
+        fuel: false,
+        xip: false,
+        multiple_instances: false,
+        aot: false,
+        snapshots: false,
+    };
+}
+
 /// Execution engine abstraction so the runtime can swap wasm3 / WAMR / etc.
 pub trait Engine {
     /// Handle to a loaded module inside the engine.
@@ -65,19 +492,218 @@ pub trait Engine {
         ctx: &mut Self::Context,
     ) -> Result<()>;
 
+    /// Invokes an entry that may return an `i32` status; entries without a
+    /// result report 0. The default only supports the latter.
+    fn invoke_status(
+        &mut self,
+        handle: Self::ModuleHandle,
+        entry: &str,
+        ctx: &mut Self::Context,
+    ) -> Result<i32> {
+        self.invoke(handle, entry, ctx).map(|()| 0)
+    }
+
+    /// What this engine supports; default `EngineCaps::NONE`.
+    fn capabilities(&self) -> EngineCaps {
+        EngineCaps::NONE
+    }
+
+    /// Fuel the last `invoke`/`invoke_status` consumed, for engines that
+    /// meter execution; default `None`.
+    fn last_fuel(&self) -> Option<u64> {
+        None
+    }
+
+    /// Stack and memory high-water marks of `handle`'s invocations so far,
+    /// for sizing stacks and memory limits from field data; default `None`.
+    fn stack_stats(&self, _handle: Self::ModuleHandle) -> Option<StackStats> {
+        None
+    }
+
+    /// What to snapshot when a call traps (`None` disables capture); engines
+    /// that cannot capture ignore it.
+    #[cfg(feature = "alloc")]
+    fn set_crash_capture(&mut self, _capture: Option<crash::CrashCapture>) {}
+
+    /// Snapshot of the last trapped call, if captured; default `None`.
+    #[cfg(feature = "alloc")]
+    fn take_crash(&mut self) -> Option<crash::Crash> {
+        None
+    }
+
+    /// Handle stopping the running call from elsewhere, for engines that
+    /// can be interrupted (see `schedule::PreemptHint`); default `None`.
+    #[cfg(feature = "alloc")]
+    fn interrupter(&self) -> Option<schedule::Interrupter> {
+        None
+    }
+
+    /// Encoded state `handle`'s next call starts from (see `snapshot`);
+    /// default `Error::Unsupported`.
+    #[cfg(feature = "alloc")]
+    fn snapshot(&mut self, _handle: Self::ModuleHandle) -> Result<Vec<u8>> {
+        Err(Error::Unsupported)
+    }
+
+    /// Makes `handle`'s next call start from a state `snapshot` returned;
+    /// default `Error::Unsupported`.
+    #[cfg(feature = "alloc")]
+    fn restore(&mut self, _handle: Self::ModuleHandle, _state: &[u8]) -> Result<()> {
+        Err(Error::Unsupported)
+    }
+
     /// Optional cleanup hook; default is a no-op.
     fn drop_module(&mut self, _handle: Self::ModuleHandle) {}
+
+    /// Forgets anything loaded for `id` (e.g. after its bytes were replaced);
+    /// default is a no-op.
+    fn unload(&mut self, _id: ModuleId) {}
+
+    /// Like `unload`, but overwrites any copy of the module's bytes the engine
+    /// keeps (see `erase`); default is `unload`.
+    #[cfg(feature = "secure-erase")]
+    fn secure_unload(&mut self, id: ModuleId) {
+        self.unload(id);
+    }
+
+    /// Frees what can be rebuilt from module bytes (compiled modules,
+    /// instances, caches) before a low-power sleep; kept state and stats
+    /// stay. Earlier handles are stale afterwards, so load again before the
+    /// next call. Default is a no-op.
+    fn suspend(&mut self) {}
 }
 
 /// Minimal runtime that orchestrates loading and invoking modules.
 pub struct Runtime<E, S> {
     engine: E,
     source: S,
+    #[cfg(feature = "alloc")]
+    states: gate::StatePolicy,
+    #[cfg(feature = "alloc")]
+    trace: Option<trace::TraceContext>,
+    #[cfg(feature = "alloc")]
+    registry: deps::Registry,
+    #[cfg(feature = "alloc")]
+    quotas: quota::Quotas,
+    #[cfg(feature = "alloc")]
+    last_crash: Option<crash::CrashRecord>,
+    #[cfg(feature = "alloc")]
+    nesting: Option<nest::Nesting>,
+    #[cfg(feature = "alloc")]
+    telemetry: Option<telemetry::Telemetry>,
+    #[cfg(feature = "alloc")]
+    switches: switch::Switches,
+    #[cfg(feature = "alloc")]
+    quarantine: quarantine::Quarantine,
+    #[cfg(feature = "alloc")]
+    memory: metrics::MemoryAccounts,
+    #[cfg(feature = "alloc")]
+    suspension: suspend::Suspension,
+    #[cfg(all(feature = "alloc", feature = "unstable"))]
+    keyring: audit::Keyring,
+    #[cfg(all(feature = "alloc", feature = "unstable"))]
+    verification: trust::VerificationPolicy,
+    #[cfg(all(feature = "alloc", feature = "unstable"))]
+    trust_marks: trust::TrustMarks,
+    #[cfg(feature = "revocation")]
+    revocations: revoke::Revocations,
 }
 
+pub mod abi;
+#[cfg(target_has_atomic = "ptr")]
+pub mod arena;
+#[cfg(all(feature = "alloc", feature = "unstable"))]
+pub mod audit;
+#[cfg(feature = "ble-ota")]
+pub mod ble_ota;
+#[cfg(feature = "alloc")]
+pub mod boot;
+#[cfg(feature = "alloc")]
+pub mod builder;
+#[cfg(feature = "alloc")]
+pub mod bus;
+#[cfg(feature = "slimmy-capi")]
+pub mod capi;
+#[cfg(feature = "cert-chain")]
+pub mod cert;
+#[cfg(feature = "codec")]
+pub mod codec;
+#[cfg(all(test, feature = "std", feature = "unstable"))]
+mod conformance;
+#[cfg(feature = "alloc")]
+pub mod crash;
+#[cfg(feature = "alloc")]
+pub mod deps;
+#[cfg(feature = "alloc")]
+pub mod describe;
+#[cfg(feature = "dfu")]
+pub mod dfu;
+#[cfg(all(feature = "alloc", feature = "unstable"))]
+pub mod diff;
 pub mod engines;
+#[cfg(feature = "secure-erase")]
+pub mod erase;
+#[cfg(feature = "alloc")]
+mod fallible;
+pub mod gate;
+#[cfg(feature = "alloc")]
+pub mod idmap;
+pub mod kv;
 pub mod manifest;
+#[cfg(feature = "alloc")]
+pub mod metrics;
+#[cfg(feature = "alloc")]
+pub mod nest;
+pub mod prelude;
+#[cfg(all(test, feature = "std"))]
+mod props;
+#[cfg(feature = "alloc")]
+pub mod quarantine;
+#[cfg(feature = "alloc")]
+pub mod quota;
+#[cfg(feature = "alloc")]
+pub mod remote;
+#[cfg(feature = "alloc")]
+pub mod replay;
+#[cfg(feature = "revocation")]
+pub mod revoke;
+#[cfg(feature = "alloc")]
+pub mod route;
+#[cfg(feature = "rpc")]
+pub mod rpc;
+pub mod schedule;
+#[cfg(feature = "secure-element")]
+pub mod secure_element;
+#[cfg(feature = "serial-loader")]
+pub mod serial_loader;
+#[cfg(feature = "std")]
+pub mod shared;
+#[cfg(all(feature = "verify-ed25519", feature = "verify-blake3"))]
+pub mod sigcache;
+#[cfg(feature = "alloc")]
+pub mod snapshot;
 pub mod storage;
+#[cfg(feature = "verify-blake3")]
+pub mod stream;
+#[cfg(feature = "manifest-suit")]
+pub mod suit;
+#[cfg(feature = "alloc")]
+pub mod suspend;
+#[cfg(all(feature = "alloc", feature = "unstable"))]
+pub mod swap;
+#[cfg(feature = "alloc")]
+pub mod switch;
+#[cfg(feature = "alloc")]
+pub mod telemetry;
+#[cfg(feature = "alloc")]
+pub mod trace;
+#[cfg(all(feature = "alloc", feature = "unstable"))]
+pub mod trust;
+#[cfg(all(feature = "alloc", feature = "unstable"))]
+pub mod update;
+#[cfg(feature = "usb-dfu")]
+pub mod usb_dfu;
+pub mod verify;
 
 impl<E, S> Runtime<E, S>
 where
@@ -86,19 +712,241 @@ where
 {
     /// Creates a runtime from an engine and a module source.
     pub const fn new(engine: E, source: S) -> Self {
-        Self { engine, source }
+        Self {
+            engine,
+            source,
+            #[cfg(feature = "alloc")]
+            states: gate::StatePolicy::new(),
+            #[cfg(feature = "alloc")]
+            trace: None,
+            #[cfg(feature = "alloc")]
+            registry: deps::Registry::new(),
+            #[cfg(feature = "alloc")]
+            quotas: quota::Quotas::new(),
+            #[cfg(feature = "alloc")]
+            last_crash: None,
+            #[cfg(feature = "alloc")]
+            nesting: None,
+            #[cfg(feature = "alloc")]
+            telemetry: None,
+            #[cfg(feature = "alloc")]
+            switches: switch::Switches::new(),
+            #[cfg(feature = "alloc")]
+            quarantine: quarantine::Quarantine::new(),
+            #[cfg(feature = "alloc")]
+            memory: metrics::MemoryAccounts::new(),
+            #[cfg(feature = "alloc")]
+            suspension: suspend::Suspension::new(),
+            #[cfg(all(feature = "alloc", feature = "unstable"))]
+            keyring: audit::Keyring::new(),
+            #[cfg(all(feature = "alloc", feature = "unstable"))]
+            verification: trust::VerificationPolicy::Never,
+            #[cfg(all(feature = "alloc", feature = "unstable"))]
+            trust_marks: trust::TrustMarks::new(),
+            #[cfg(feature = "revocation")]
+            revocations: revoke::Revocations::new(),
+        }
     }
 
     /// Loads and runs a module entry point.
+    ///
+    /// With alloc, the device-state gate is checked first and denied calls
+    /// return `Error::StateDenied` without touching the engine. When tracing is
+    /// enabled (`trace()`), the call runs under a fresh correlation id.
     pub fn execute(
         &mut self,
         module_id: ModuleId,
         entry: &str,
         ctx: &mut E::Context,
     ) -> Result<()> {
+        #[cfg(feature = "alloc")]
+        {
+            self.execute_for(
+                module_id,
+                entry,
+                ctx,
+                trace::Reason::Direct,
+                trace::NO_CORRELATION,
+            )
+        }
+        #[cfg(not(feature = "alloc"))]
+        {
+            self.run(module_id, entry, ctx, E::invoke)
+        }
+    }
+
+    /// Like `execute`, but records why the module runs and under which
+    /// correlation id (e.g. the remote command's; `NO_CORRELATION` = fresh).
+    #[cfg(feature = "alloc")]
+    pub fn execute_for(
+        &mut self,
+        module_id: ModuleId,
+        entry: &str,
+        ctx: &mut E::Context,
+        reason: trace::Reason,
+        correlation: trace::CorrelationId,
+    ) -> Result<()> {
+        let _scope = self.enter_trace(module_id, reason, correlation);
+        self.run(module_id, entry, ctx, E::invoke)
+    }
+
+    /// Runs a module by the name its manifest registered (`EXT_NAME`, see
+    /// `deps`); `Error::ModuleNotFound` for an unknown name.
+    #[cfg(feature = "alloc")]
+    pub fn execute_by_name(&mut self, name: &str, entry: &str, ctx: &mut E::Context) -> Result<()> {
+        let module_id = self
+            .registry
+            .find(name)
+            .ok_or(Error::ModuleNotFound)?
+            .module_id;
+        self.execute(module_id, entry, ctx)
+    }
+
+    /// Runs under the caller's correlation id.
+    #[cfg(feature = "alloc")]
+    #[deprecated(
+        since = "0.1.0",
+        note = "use `execute_for` with `trace::Reason::Direct`"
+    )]
+    pub fn execute_correlated(
+        &mut self,
+        module_id: ModuleId,
+        entry: &str,
+        ctx: &mut E::Context,
+        correlation: trace::CorrelationId,
+    ) -> Result<()> {
+        self.execute_for(module_id, entry, ctx, trace::Reason::Direct, correlation)
+    }
+
+    /// Like `execute`, but for entries returning an `i32` status (0 when the
+    /// entry returns nothing), e.g. a `health` export.
+    pub fn execute_status(
+        &mut self,
+        module_id: ModuleId,
+        entry: &str,
+        ctx: &mut E::Context,
+    ) -> Result<i32> {
+        #[cfg(feature = "alloc")]
+        let _scope = self.enter_trace(module_id, trace::Reason::Direct, trace::NO_CORRELATION);
+        self.run(module_id, entry, ctx, E::invoke_status)
+    }
+
+    /// Like `execute_status`, but maps the status by the `ExitCode`
+    /// convention: negative ones become `Error::GuestError`. These do not
+    /// count toward quarantine or raise `InvokeTrapped`, since the module
+    /// handled them.
+    pub fn execute_exit(
+        &mut self,
+        module_id: ModuleId,
+        entry: &str,
+        ctx: &mut E::Context,
+    ) -> Result<ExitCode> {
+        ExitCode::from_status(self.execute_status(module_id, entry, ctx)?)
+    }
+
+    #[cfg(feature = "alloc")]
+    fn enter_trace(
+        &self,
+        module_id: ModuleId,
+        reason: trace::Reason,
+        correlation: trace::CorrelationId,
+    ) -> Option<trace::TraceScope> {
+        let invocation = trace::Invocation {
+            module_id,
+            version: self.module_version(module_id),
+            reason,
+            correlation,
+        };
+        self.trace.as_ref().map(|trace| trace.enter(invocation))
+    }
+
+    /// Checks suspension, switches, quarantine, the state gate and quotas
+    /// before a call.
+    #[cfg(feature = "alloc")]
+    pub(crate) fn admit(&mut self, module_id: ModuleId, entry: &str) -> Result<()> {
+        self.admit_module(module_id)?;
+        let correlation = self
+            .trace
+            .as_ref()
+            .map_or(trace::NO_CORRELATION, |trace| trace.current());
+        self.states.check(module_id, entry, correlation)?;
+        self.quotas.admit(module_id)
+    }
+
+    /// Checks suspension, switches and quarantine: the part of `admit` that
+    /// does not depend on an entry, for moving a module's state too.
+    #[cfg(feature = "alloc")]
+    fn admit_module(&self, module_id: ModuleId) -> Result<()> {
+        self.ensure_awake()?;
+        self.switches.check(module_id)?;
+        self.quarantine.check(module_id)
+    }
+
+    fn run<T>(
+        &mut self,
+        module_id: ModuleId,
+        entry: &str,
+        ctx: &mut E::Context,
+        invoke: fn(&mut E, E::ModuleHandle, &str, &mut E::Context) -> Result<T>,
+    ) -> Result<T> {
+        #[cfg(feature = "alloc")]
+        let _nest = self
+            .nesting
+            .as_ref()
+            .map(nest::Nesting::enter)
+            .transpose()?;
+        #[cfg(feature = "alloc")]
+        self.admit(module_id, entry)?;
+        #[cfg(all(feature = "alloc", feature = "unstable"))]
+        self.verify_stored(module_id)?;
         let module_bytes = self.source.fetch(module_id).ok_or(Error::ModuleNotFound)?;
-        let handle = self.engine.load(module_id, module_bytes)?;
-        self.engine.invoke(handle, entry, ctx)
+        #[cfg(feature = "revocation")]
+        self.revocations.check(module_id, module_bytes)?;
+        #[cfg(feature = "alloc")]
+        let heap_before = self.memory.heap_used();
+        let handle = load_module(&mut self.engine, module_id, module_bytes)?;
+        let result = invoke(&mut self.engine, handle, entry, ctx);
+        #[cfg(feature = "alloc")]
+        {
+            self.memory
+                .record(module_id, heap_before, self.engine.stack_stats(handle));
+            let fuel = self.engine.last_fuel();
+            if let Some(fuel) = fuel {
+                self.quotas.charge_fuel(module_id, fuel);
+            }
+            let crash = match result {
+                Err(_) => self.engine.take_crash(),
+                Ok(_) => None,
+            };
+            if let Some(crash) = crash {
+                self.last_crash = Some(crash::CrashRecord {
+                    module_id,
+                    version: self.module_version(module_id),
+                    entry: entry.into(),
+                    fuel,
+                    crash,
+                });
+            }
+            match result {
+                Ok(_) => self.quarantine.record_success(module_id),
+                Err(Error::EntryNotFound) => {}
+                Err(error) => {
+                    self.emit(telemetry::Event::InvokeTrapped {
+                        module_id,
+                        version: self.module_version(module_id),
+                        entry,
+                        error,
+                    });
+                    self.record_failure(module_id, error);
+                }
+            }
+        }
+        result
+    }
+
+    /// What the engine supports (see `EngineCaps`).
+    pub fn capabilities(&self) -> EngineCaps {
+        self.engine.capabilities()
     }
 
     /// Mutable access to the engine for fine-grained control (e.g., configuring imports).
@@ -111,19 +959,352 @@ where
         &self.source
     }
 
+    /// Mutable access to the module source (e.g., installing modules).
+    pub fn source_mut(&mut self) -> &mut S {
+        &mut self.source
+    }
+
+    /// Installs the gate reporting the current device state.
+    #[cfg(feature = "alloc")]
+    pub fn set_state_gate(&mut self, gate: impl gate::StateGate + 'static) {
+        self.states.set_gate(gate);
+    }
+
+    /// Restricts a module to the device states in `mask` (see `gate::state_mask`).
+    #[cfg(feature = "alloc")]
+    pub fn restrict_states(&mut self, module_id: ModuleId, mask: u32) {
+        self.states.restrict(module_id, mask);
+    }
+
+    /// Records a module's version, reported to guests via `invocation_info`.
+    #[cfg(feature = "alloc")]
+    pub fn set_module_version(&mut self, module_id: ModuleId, version: u32) {
+        self.registry.set_version(module_id, version);
+    }
+
+    /// Version recorded for a module (0 when unknown).
+    #[cfg(feature = "alloc")]
+    pub fn module_version(&self, module_id: ModuleId) -> u32 {
+        self.registry.version(module_id)
+    }
+
+    /// Applies an installed module's manifest: its sequence becomes the module
+    /// version, its name and dependencies are registered for `start_all`, and
+    /// its allowed-states extension restricts execution.
+    #[cfg(feature = "alloc")]
+    pub fn apply_manifest(&mut self, manifest: &manifest::Manifest<'_>) -> Result<()> {
+        self.registry.register_manifest(manifest)?;
+        self.states.apply_manifest(manifest);
+        Ok(())
+    }
+
+    /// Installed modules, versions and dependencies.
+    #[cfg(feature = "alloc")]
+    pub fn registry(&mut self) -> &mut deps::Registry {
+        &mut self.registry
+    }
+
+    /// Fetches and loads modules ahead of their first call, so a
+    /// `CachedEngine` already holds their handles (parsed and, with a
+    /// verifying source, checked). Stored bytes pass the same verification
+    /// policy as an invocation's (`trust`). Disabled and quarantined modules
+    /// are skipped. Every module is tried; returns the first error.
+    pub fn preload(&mut self, ids: &[ModuleId]) -> Result<()> {
+        #[cfg(feature = "alloc")]
+        self.ensure_awake()?;
+        let mut first = Ok(());
+        for &module_id in ids {
+            #[cfg(feature = "alloc")]
+            if !self.is_enabled(module_id) {
+                continue;
+            }
+            #[cfg(feature = "alloc")]
+            let heap_before = self.memory.heap_used();
+            #[cfg(all(feature = "alloc", feature = "unstable"))]
+            let verified = self.verify_stored(module_id);
+            #[cfg(not(all(feature = "alloc", feature = "unstable")))]
+            let verified = Ok(());
+            let loaded = verified
+                .and_then(|()| self.source.fetch(module_id).ok_or(Error::ModuleNotFound))
+                .and_then(|bytes| {
+                    #[cfg(feature = "revocation")]
+                    self.revocations.check(module_id, bytes)?;
+                    load_module(&mut self.engine, module_id, bytes)
+                });
+            #[cfg(feature = "alloc")]
+            if loaded.is_ok() {
+                self.memory.record(module_id, heap_before, None);
+            }
+            if let (Err(error), Ok(())) = (loaded, &first) {
+                first = Err(error);
+            }
+        }
+        first
+    }
+
+    /// Runs every registered module's entry, dependencies first.
+    ///
+    /// The order is resolved up front, so a missing, too-old or cyclic
+    /// dependency fails before anything runs. Modules without an entry are
+    /// skipped; the first failing entry stops startup. Returns the order used.
+    #[cfg(feature = "alloc")]
+    pub fn start_all(&mut self, ctx: &mut E::Context) -> Result<Vec<ModuleId>> {
+        let order = self.registry.start_order()?;
+        for &module_id in &order {
+            let Some(entry) = self
+                .registry
+                .get(module_id)
+                .and_then(|info| info.entry.clone())
+            else {
+                continue;
+            };
+            self.execute(module_id, &entry, ctx)?;
+        }
+        Ok(order)
+    }
+
+    /// Runs the start entry of every enabled module in a boot descriptor, in
+    /// its order. A failing module is reported and the others still start.
+    #[cfg(feature = "alloc")]
+    pub fn boot(
+        &mut self,
+        descriptor: &boot::BootDescriptor,
+        ctx: &mut E::Context,
+    ) -> boot::BootReport {
+        let mut report = boot::BootReport::default();
+        for e in descriptor.entries().iter().filter(|e| e.enabled) {
+            let Some(entry) = &e.entry else {
+                continue;
+            };
+            match self.execute(e.module_id, entry, ctx) {
+                Ok(()) => report.started.push(e.module_id),
+                Err(err) => report.failed.push((e.module_id, err)),
+            }
+        }
+        report
+    }
+
+    /// Shared correlation-id context; enables tracing on first use.
+    ///
+    /// Hand clones to `trace::TraceImports`, `bus::Bus::set_trace`, etc.
+    #[cfg(feature = "alloc")]
+    pub fn trace(&mut self) -> trace::TraceContext {
+        self.trace
+            .get_or_insert_with(trace::TraceContext::new)
+            .clone()
+    }
+
+    /// Replaces the correlation-id context (e.g. one seeded with a boot counter).
+    #[cfg(feature = "alloc")]
+    pub fn set_trace(&mut self, trace: trace::TraceContext) {
+        self.trace = Some(trace);
+    }
+
+    /// Nesting count shared with other runtimes (see `nest`); created on
+    /// first use with `NestingPolicy::Deny`.
+    #[cfg(feature = "alloc")]
+    pub fn nesting(&mut self) -> nest::Nesting {
+        self.nesting
+            .get_or_insert_with(nest::Nesting::default)
+            .clone()
+    }
+
+    /// Counts this runtime's invocations in `nesting`, so host functions that
+    /// run modules on other runtimes sharing it are bounded by its policy.
+    #[cfg(feature = "alloc")]
+    pub fn set_nesting(&mut self, nesting: nest::Nesting) {
+        self.nesting = Some(nesting);
+    }
+
+    /// Limits `module_id`'s invocation rate and fuel (see `quota`).
+    #[cfg(feature = "alloc")]
+    pub fn set_quota(&mut self, module_id: ModuleId, quota: quota::Quota) {
+        self.quotas.set(module_id, quota);
+    }
+
+    /// Clock quota windows are measured with.
+    #[cfg(feature = "alloc")]
+    pub fn set_quota_clock(&mut self, clock: impl schedule::Clock + 'static) {
+        self.quotas.set_clock(clock);
+    }
+
+    /// Quotas, usage and the refusal count.
+    #[cfg(feature = "alloc")]
+    pub fn quotas(&mut self) -> &mut quota::Quotas {
+        &mut self.quotas
+    }
+
+    /// Turns a module on or off without uninstalling it (see `switch`);
+    /// returns whether that changed anything. Turning it on also releases it
+    /// from quarantine.
+    #[cfg(feature = "alloc")]
+    pub fn set_enabled(&mut self, module_id: ModuleId, enabled: bool) -> Result<bool> {
+        let changed = self.switches.set_enabled(module_id, enabled)?;
+        Ok((enabled && self.quarantine.release(module_id)) || changed)
+    }
+
+    /// Whether the module is neither switched off nor quarantined.
+    #[cfg(feature = "alloc")]
+    pub fn is_enabled(&self, module_id: ModuleId) -> bool {
+        self.switches.is_enabled(module_id) && !self.quarantine.is_quarantined(module_id)
+    }
+
+    /// Enable switches, e.g. to persist them (`Switches::persist_to`).
+    #[cfg(feature = "alloc")]
+    pub fn switches(&mut self) -> &mut switch::Switches {
+        &mut self.switches
+    }
+
+    /// Failure counts and quarantined modules, e.g. to set the threshold
+    /// (see `quarantine`).
+    #[cfg(feature = "alloc")]
+    pub fn quarantine(&mut self) -> &mut quarantine::Quarantine {
+        &mut self.quarantine
+    }
+
+    /// Counts a failure of `module_id` toward quarantine, e.g. an image a
+    /// transport failed to verify; reports `Event::Quarantined` when it
+    /// reaches the threshold.
+    #[cfg(feature = "alloc")]
+    pub fn record_failure(&mut self, module_id: ModuleId, error: Error) {
+        if let Some(failures) = self.quarantine.record_failure(module_id) {
+            self.emit(telemetry::Event::Quarantined {
+                module_id,
+                failures,
+                error,
+            });
+        }
+    }
+
+    /// Captures a `crash::CrashRecord` whenever an invocation traps (`None`
+    /// turns capture off); see `crash`.
+    #[cfg(feature = "alloc")]
+    pub fn set_crash_capture(&mut self, capture: Option<crash::CrashCapture>) {
+        self.engine.set_crash_capture(capture);
+    }
+
+    /// Takes the record of the most recent trap, if one was captured.
+    #[cfg(feature = "alloc")]
+    pub fn take_crash(&mut self) -> Option<crash::CrashRecord> {
+        self.last_crash.take()
+    }
+
+    /// Measures modules' heap with `gauge` (see `metrics`).
+    #[cfg(feature = "alloc")]
+    pub fn set_metrics(&mut self, gauge: impl metrics::Metrics + 'static) {
+        self.memory.set_gauge(gauge);
+    }
+
+    /// Heap and linear memory held by each module, current and peak, by id.
+    #[cfg(feature = "alloc")]
+    pub fn memory_report(&self) -> Vec<(ModuleId, metrics::MemoryUsage)> {
+        self.memory.report()
+    }
+
+    /// Memory held by one module, if any was recorded.
+    #[cfg(feature = "alloc")]
+    pub fn memory_usage(&self, module_id: ModuleId) -> Option<metrics::MemoryUsage> {
+        self.memory.usage(module_id)
+    }
+
+    /// Per-module memory accounting.
+    #[cfg(feature = "alloc")]
+    pub fn memory_accounts(&mut self) -> &mut metrics::MemoryAccounts {
+        &mut self.memory
+    }
+
+    /// Reports OTA and execution events to `sink`, stamped with `clock`
+    /// (see `telemetry`).
+    #[cfg(feature = "alloc")]
+    pub fn set_event_sink(
+        &mut self,
+        sink: impl telemetry::EventSink + 'static,
+        clock: impl schedule::Clock + 'static,
+    ) {
+        self.telemetry = Some(telemetry::Telemetry::new(sink, clock));
+    }
+
+    /// Sends `event` to the event sink, if one is set.
+    #[cfg(feature = "alloc")]
+    pub fn emit(&mut self, event: telemetry::Event<'_>) {
+        let correlation = self
+            .trace
+            .as_ref()
+            .map_or(trace::NO_CORRELATION, |trace| trace.current());
+        if let Some(telemetry) = self.telemetry.as_mut() {
+            telemetry.emit(correlation, event);
+        }
+    }
+
+    /// Encoded state a module's next call starts from, e.g. to keep in flash
+    /// across deep sleep; see `snapshot`. Like a call, fails with
+    /// `Error::ModuleDisabled` for a module that is switched off or
+    /// quarantined.
+    #[cfg(feature = "alloc")]
+    pub fn snapshot(&mut self, module_id: ModuleId) -> Result<Vec<u8>> {
+        let handle = self.load(module_id)?;
+        self.engine.snapshot(handle)
+    }
+
+    /// Resumes a module from a state `snapshot` returned, unless it is
+    /// switched off or quarantined (`Error::ModuleDisabled`).
+    #[cfg(feature = "alloc")]
+    pub fn restore(&mut self, module_id: ModuleId, state: &[u8]) -> Result<()> {
+        let handle = self.load(module_id)?;
+        self.engine.restore(handle, state)
+    }
+
+    #[cfg(feature = "alloc")]
+    fn load(&mut self, module_id: ModuleId) -> Result<E::ModuleHandle> {
+        self.admit_module(module_id)?;
+        #[cfg(feature = "unstable")]
+        self.verify_stored(module_id)?;
+        let module_bytes = self.source.fetch(module_id).ok_or(Error::ModuleNotFound)?;
+        #[cfg(feature = "revocation")]
+        self.revocations.check(module_id, module_bytes)?;
+        load_module(&mut self.engine, module_id, module_bytes)
+    }
+
+    /// Device-state policy (restrictions and denial count).
+    #[cfg(feature = "alloc")]
+    pub fn state_policy(&mut self) -> &mut gate::StatePolicy {
+        &mut self.states
+    }
+
     /// Consumes the runtime and returns its parts.
     pub fn into_parts(self) -> (E, S) {
         (self.engine, self.source)
     }
 }
 
+/// Loads `bytes` into `engine` once the module's ABI version checks out
+/// (`abi::version::check`).
+pub(crate) fn load_module<E: Engine>(
+    engine: &mut E,
+    module_id: ModuleId,
+    bytes: &[u8],
+) -> Result<E::ModuleHandle> {
+    abi::version::check(bytes)?;
+    engine.load(module_id, bytes)
+}
+
 #[cfg(feature = "alloc")]
 use alloc::vec::Vec;
 
+impl<E: Engine, S: ModuleCatalog> Runtime<E, S> {
+    /// Ids of every module in the source.
+    pub fn module_ids(&self) -> CatalogIds<'_, S> {
+        self.source.iter_ids()
+    }
+}
+
 /// Simple in-memory module store for devices that have alloc support.
 #[cfg(feature = "alloc")]
 pub struct MemoryStore {
-    modules: Vec<(ModuleId, Vec<u8>)>,
+    modules: idmap::IdMap<Vec<u8>>,
+    staging: Staging,
+    /// Images kept in an arena instead (see `in_arena`).
+    arena: Option<arena::Images>,
 }
 
 #[cfg(feature = "alloc")]
@@ -131,37 +1312,136 @@ impl MemoryStore {
     /// Creates an empty store.
     pub fn new() -> Self {
         Self {
-            modules: Vec::new(),
+            modules: idmap::IdMap::new(),
+            staging: Staging::default(),
+            arena: None,
         }
     }
 
+    /// Creates an empty store holding up to `max_modules` images of
+    /// `capacity` bytes in total, claimed from `arena` now. Streamed images
+    /// are staged in the same space, so one can only replace an image if
+    /// both fit at once.
+    pub fn in_arena<const N: usize>(
+        arena: &'static arena::Arena<N>,
+        max_modules: usize,
+        capacity: usize,
+    ) -> Result<Self> {
+        Ok(Self {
+            arena: Some(arena::Images::in_arena(arena, max_modules, capacity)?),
+            ..Self::new()
+        })
+    }
+
     /// Inserts or replaces a module.
+    ///
+    /// Panics when a store made with `in_arena` has no room for it; use
+    /// `try_upsert` there.
     pub fn upsert(&mut self, id: ModuleId, bytes: impl Into<Vec<u8>>) {
-        let bytes = bytes.into();
-        if let Some((_, existing)) = self
-            .modules
-            .iter_mut()
-            .find(|(stored_id, _)| *stored_id == id)
-        {
-            *existing = bytes;
-        } else {
-            self.modules.push((id, bytes));
+        match &mut self.arena {
+            Some(images) => images.insert(id, &bytes.into()).unwrap(),
+            // Unbounded map: inserting cannot fail.
+            None => drop(self.modules.insert(id, bytes.into())),
+        }
+    }
+
+    /// Inserts or replaces a module; `Error::StorageFull` when an arena
+    /// store has no room for it, `Error::OutOfMemory` when the heap has none.
+    /// In an arena store this drops an image being streamed in.
+    pub fn try_upsert(&mut self, id: ModuleId, bytes: &[u8]) -> Result<()> {
+        match &mut self.arena {
+            Some(images) => images.insert(id, bytes),
+            None => {
+                let mut image = Vec::new();
+                fallible::extend(&mut image, bytes)?;
+                self.modules.try_reserve(1)?;
+                self.modules.insert(id, image).map(drop)
+            }
+        }
+    }
+
+    /// Removes a module; returns whether it was stored.
+    pub fn remove(&mut self, id: ModuleId) -> bool {
+        match &mut self.arena {
+            Some(images) => images.remove(id),
+            None => self.modules.remove(id).is_some(),
         }
     }
 
     /// Clears all modules, useful when reclaiming RAM.
     pub fn clear(&mut self) {
-        self.modules.clear();
+        match &mut self.arena {
+            Some(images) => images.clear(),
+            None => self.modules.clear(),
+        }
+    }
+}
+
+#[cfg(feature = "alloc")]
+impl Default for MemoryStore {
+    fn default() -> Self {
+        Self::new()
     }
 }
 
 #[cfg(feature = "alloc")]
 impl ModuleSource for MemoryStore {
     fn fetch(&self, id: ModuleId) -> Option<&[u8]> {
-        self.modules
-            .iter()
-            .find(|(stored_id, _)| *stored_id == id)
-            .map(|(_, bytes)| bytes.as_slice())
+        match &self.arena {
+            Some(images) => images.get(id),
+            None => self.modules.get(id).map(Vec::as_slice),
+        }
+    }
+}
+
+#[cfg(feature = "alloc")]
+impl ModuleSink for MemoryStore {
+    fn begin(&mut self, id: ModuleId, len: usize) -> Result<()> {
+        match &mut self.arena {
+            Some(images) => images.begin(id, len),
+            None => self.staging.begin(id, len),
+        }
+    }
+
+    fn write(&mut self, chunk: &[u8]) -> Result<()> {
+        match &mut self.arena {
+            Some(images) => images.write(chunk),
+            None => self.staging.write(chunk),
+        }
+    }
+
+    fn commit(&mut self) -> Result<()> {
+        if let Some(images) = &mut self.arena {
+            return images.commit();
+        }
+        let (id, bytes) = self.staging.finish()?;
+        self.modules.try_reserve(1)?;
+        self.upsert(id, bytes);
+        Ok(())
+    }
+
+    fn abort(&mut self) {
+        match &mut self.arena {
+            Some(images) => images.abort(),
+            None => self.staging.abort(),
+        }
+    }
+}
+
+#[cfg(feature = "alloc")]
+impl ModuleCatalog for MemoryStore {
+    fn module_count(&self) -> usize {
+        match &self.arena {
+            Some(images) => images.index().len(),
+            None => self.modules.len(),
+        }
+    }
+
+    fn module_id_at(&self, index: usize) -> Option<ModuleId> {
+        match &self.arena {
+            Some(images) => images.index().id_at(index),
+            None => self.modules.id_at(index),
+        }
     }
 }
 
@@ -173,7 +1453,53 @@ where
     E::ModuleHandle: PartialEq,
 {
     inner: E,
-    cache: Vec<(ModuleId, E::ModuleHandle)>,
+    cache: HandleCache<E::ModuleHandle>,
+}
+
+/// Where `CachedEngine` keeps its handles.
+#[cfg(feature = "alloc")]
+enum HandleCache<H> {
+    Heap(idmap::IdMap<H>),
+    Arena(arena::Slots<H>),
+}
+
+#[cfg(feature = "alloc")]
+impl<H: Copy> HandleCache<H> {
+    fn get(&self, id: ModuleId) -> Option<H> {
+        match self {
+            Self::Heap(map) => map.get(id).copied(),
+            Self::Arena(slots) => slots.get(id),
+        }
+    }
+
+    fn insert(&mut self, id: ModuleId, handle: H) -> Result<Option<H>> {
+        match self {
+            Self::Heap(map) => map.insert(id, handle),
+            Self::Arena(slots) => slots.insert(id, handle),
+        }
+    }
+
+    fn retain(&mut self, keep: impl FnMut(ModuleId, &H) -> bool) {
+        match self {
+            Self::Heap(map) => map.retain(keep),
+            Self::Arena(slots) => slots.retain(keep),
+        }
+    }
+
+    fn len(&self) -> usize {
+        match self {
+            Self::Heap(map) => map.len(),
+            Self::Arena(slots) => slots.len(),
+        }
+    }
+
+    /// Handle of the lowest cached id.
+    fn first(&self) -> Option<H> {
+        match self {
+            Self::Heap(map) => map.iter().next().map(|(_, handle)| *handle),
+            Self::Arena(slots) => slots.iter().next().map(|(_, handle)| handle),
+        }
+    }
 }
 
 #[cfg(feature = "alloc")]
@@ -186,25 +1512,62 @@ where
     pub fn new(inner: E) -> Self {
         Self {
             inner,
-            cache: Vec::new(),
+            cache: HandleCache::Heap(idmap::IdMap::new()),
+        }
+    }
+
+    /// Wraps an engine, caching at most `max_modules` handles; further
+    /// modules are loaded on every call instead of cached.
+    pub fn with_capacity_limit(inner: E, max_modules: usize) -> Self {
+        Self {
+            inner,
+            cache: HandleCache::Heap(idmap::IdMap::with_capacity_limit(max_modules)),
         }
     }
 
+    /// Like `with_capacity_limit`, with the `max_modules` handle slots
+    /// claimed from `arena` now.
+    pub fn in_arena<const N: usize>(
+        inner: E,
+        arena: &'static arena::Arena<N>,
+        max_modules: usize,
+    ) -> Result<Self>
+    where
+        E::ModuleHandle: 'static,
+    {
+        Ok(Self {
+            inner,
+            cache: HandleCache::Arena(arena::Slots::in_arena(arena, max_modules)?),
+        })
+    }
+
     fn cached_handle(&self, id: ModuleId) -> Option<E::ModuleHandle> {
-        self.cache
-            .iter()
-            .find(|(cached_id, _)| *cached_id == id)
-            .map(|(_, handle)| *handle)
+        self.cache.get(id)
     }
 
     /// Drops the cached handle if present and forwards to the inner engine.
     pub fn drop_cached(&mut self, handle: E::ModuleHandle) {
-        if let Some(pos) = self.cache.iter().position(|(_, h)| *h == handle) {
-            self.cache.swap_remove(pos);
-        }
+        self.cache.retain(|_, cached| *cached != handle);
         self.inner.drop_module(handle);
     }
 
+    /// Drops the cached handle for `id` (e.g. after its bytes were replaced);
+    /// returns whether one was cached.
+    pub fn evict(&mut self, id: ModuleId) -> bool {
+        match self.cached_handle(id) {
+            Some(handle) => {
+                self.drop_cached(handle);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Number of cached handles.
+    pub fn cached_len(&self) -> usize {
+        self.cache.len()
+    }
+
     /// Returns the wrapped engine, discarding the cache.
     pub fn into_inner(self) -> E {
         self.inner
@@ -226,7 +1589,8 @@ where
         }
 
         let handle = self.inner.load(id, module)?;
-        self.cache.push((id, handle));
+        // A full cache only costs a reload next time.
+        let _ = self.cache.insert(id, handle);
         Ok(handle)
     }
 
@@ -239,9 +1603,68 @@ where
         self.inner.invoke(handle, entry, ctx)
     }
 
+    fn invoke_status(
+        &mut self,
+        handle: Self::ModuleHandle,
+        entry: &str,
+        ctx: &mut Self::Context,
+    ) -> Result<i32> {
+        self.inner.invoke_status(handle, entry, ctx)
+    }
+
+    fn capabilities(&self) -> EngineCaps {
+        self.inner.capabilities()
+    }
+
+    fn last_fuel(&self) -> Option<u64> {
+        self.inner.last_fuel()
+    }
+
+    fn stack_stats(&self, handle: Self::ModuleHandle) -> Option<StackStats> {
+        self.inner.stack_stats(handle)
+    }
+
+    fn set_crash_capture(&mut self, capture: Option<crash::CrashCapture>) {
+        self.inner.set_crash_capture(capture);
+    }
+
+    fn take_crash(&mut self) -> Option<crash::Crash> {
+        self.inner.take_crash()
+    }
+
+    fn interrupter(&self) -> Option<schedule::Interrupter> {
+        self.inner.interrupter()
+    }
+
+    fn snapshot(&mut self, handle: Self::ModuleHandle) -> Result<Vec<u8>> {
+        self.inner.snapshot(handle)
+    }
+
+    fn restore(&mut self, handle: Self::ModuleHandle, state: &[u8]) -> Result<()> {
+        self.inner.restore(handle, state)
+    }
+
     fn drop_module(&mut self, handle: Self::ModuleHandle) {
         self.drop_cached(handle);
     }
+
+    fn unload(&mut self, id: ModuleId) {
+        self.evict(id);
+        self.inner.unload(id);
+    }
+
+    #[cfg(feature = "secure-erase")]
+    fn secure_unload(&mut self, id: ModuleId) {
+        self.evict(id);
+        self.inner.secure_unload(id);
+    }
+
+    fn suspend(&mut self) {
+        while let Some(handle) = self.cache.first() {
+            self.drop_cached(handle);
+        }
+        self.inner.suspend();
+    }
 }
 
 #[cfg(all(test, feature = "std"))]
@@ -314,17 +1737,453 @@ mod tests {
 
         runtime.execute(7, "start", &mut ()).unwrap();
         runtime.execute(7, "start", &mut ()).unwrap();
+        assert_eq!(runtime.engine().cached_len(), 1);
+
+        runtime.source_mut().upsert(7, vec![0xDD]);
+        assert!(runtime.engine().evict(7));
+        assert!(!runtime.engine().evict(7));
+        runtime.execute(7, "start", &mut ()).unwrap();
+
+        let (engine, _) = runtime.into_parts();
+        let engine = engine.into_inner();
+        assert_eq!(engine.loaded.get(&7), Some(&2));
+        assert_eq!(engine.invoked.len(), 3);
+
+        let mut bounded = CachedEngine::with_capacity_limit(MockEngine::default(), 1);
+        bounded.load(1, &[0]).unwrap();
+        bounded.load(2, &[0]).unwrap();
+        bounded.load(2, &[0]).unwrap();
+        assert_eq!(bounded.cached_len(), 1);
+        assert_eq!(bounded.into_inner().loaded.get(&2), Some(&2));
+    }
+
+    #[test]
+    fn arena_backed_runtime_runs_from_fixed_slices() {
+        static ARENA: arena::Arena<1024> = arena::Arena::new();
+        let mut store = MemoryStore::in_arena(&ARENA, 2, 8).unwrap();
+        store.upsert(1, vec![1, 2, 3]);
+        assert!(store.try_upsert(2, &[0; 6]).is_err());
+        store.try_upsert(2, &[4; 5]).unwrap();
+        assert!(store.try_upsert(3, &[]).is_err());
+        let engine = CachedEngine::in_arena(MockEngine::default(), &ARENA, 1).unwrap();
+        let mut runtime = Runtime::new(engine, store);
+
+        runtime.execute(1, "main", &mut ()).unwrap();
+        runtime.execute(2, "main", &mut ()).unwrap();
+        runtime.execute(2, "main", &mut ()).unwrap();
+        assert_eq!(runtime.engine().cached_len(), 1);
+        assert_eq!(runtime.module_ids().collect::<Vec<_>>(), vec![1, 2]);
+
+        let (engine, store) = runtime.into_parts();
+        // Module 2 did not fit the cache, so it was loaded for each call.
+        let engine = engine.into_inner();
+        assert_eq!(
+            (engine.loaded.get(&1), engine.loaded.get(&2)),
+            (Some(&1), Some(&2))
+        );
+        assert_eq!(store.fetch(2), Some(&[4; 5][..]));
+    }
+
+    #[test]
+    fn preload_fills_the_cache_before_the_first_call() {
+        let mut store = MemoryStore::new();
+        store.upsert(1, vec![1]);
+        store.upsert(2, vec![2]);
+        store.upsert(3, vec![]);
+        let mut runtime = Runtime::new(CachedEngine::new(MockEngine::default()), store);
+        runtime.set_enabled(2, false).unwrap();
+
+        assert_eq!(runtime.preload(&[4, 1, 2, 3]), Err(Error::ModuleNotFound));
+        assert_eq!(runtime.engine().cached_len(), 1);
+        runtime.execute(1, "main", &mut ()).unwrap();
 
         let (engine, _) = runtime.into_parts();
         let engine = engine.into_inner();
-        assert_eq!(engine.loaded.get(&7), Some(&1));
+        assert_eq!(engine.loaded.get(&1), Some(&1));
+        assert_eq!(engine.loaded.get(&2), None);
+    }
+
+    #[test]
+    #[allow(deprecated)]
+    fn prelude_covers_core_and_shims_forward() {
+        use crate::prelude::*;
+
+        fn run<E: Engine<Context = ()>, S: ModuleCatalog>(
+            runtime: &mut Runtime<E, S>,
+        ) -> Result<()> {
+            let ids: Vec<ModuleId> = runtime.module_ids().collect();
+            for id in ids {
+                runtime.execute_correlated(id, "main", &mut (), 42)?;
+            }
+            Ok(())
+        }
+
+        let mut store = MemoryStore::new();
+        store.upsert(1, vec![0]);
+        let mut runtime = Runtime::new(MockEngine::default(), store);
+        run(&mut runtime).unwrap();
+        assert_eq!(runtime.engine().invoked, [(1, "main".to_string())]);
+    }
+
+    #[test]
+    fn memory_store_sink_replaces_on_commit_only() {
+        let mut store = MemoryStore::new();
+        write_module(&mut store, 1, &[1, 2, 3]).unwrap();
+
+        store.begin(1, 2).unwrap();
+        store.write(&[9]).unwrap();
+        assert_eq!(
+            store.write(&[9, 9]),
+            Err(Error::Engine("sink: more bytes than announced"))
+        );
+        assert_eq!(store.fetch(1), Some(&[1, 2, 3][..]));
+        store.abort();
+        assert_eq!(
+            store.commit(),
+            Err(Error::Engine("sink: no write in progress"))
+        );
+
+        store.begin(1, 2).unwrap();
+        store.write(&[7]).unwrap();
+        store.write(&[8]).unwrap();
+        store.commit().unwrap();
+        assert_eq!(store.fetch(1), Some(&[7, 8][..]));
+    }
+
+    #[test]
+    fn catalogs_list_stored_modules() {
+        use crate::storage::{IndexEntry, IndexedSliceSource, PartitionSliceSource};
+
+        let mut store = MemoryStore::new();
+        store.upsert(4, vec![1]);
+        store.upsert(2, vec![2]);
+        store.upsert(4, vec![3]);
+        let runtime = Runtime::new(MockEngine::default(), store);
+        assert_eq!(runtime.module_ids().collect::<Vec<_>>(), [2, 4]);
+
+        let region = [0u8; 8];
+        let entries = [
+            IndexEntry {
+                id: 9,
+                offset: 0,
+                len: 4,
+            },
+            IndexEntry {
+                id: 3,
+                offset: 4,
+                len: 4,
+            },
+        ];
+        let indexed = IndexedSliceSource::new(&region, &entries);
+        assert_eq!(indexed.iter_ids().collect::<Vec<_>>(), [9, 3]);
+        let single = PartitionSliceSource::new(&region, 5);
+        assert_eq!(single.iter_ids().collect::<Vec<_>>(), [5]);
+    }
+
+    #[test]
+    fn state_gate_denies_outside_allowed_states() {
+        use crate::gate::{state_mask, DeviceState, StateGate};
+        use std::cell::{Cell, RefCell};
+        use std::rc::Rc;
+
+        type Denial = (ModuleId, String, DeviceState, u64);
+
+        struct Board {
+            state: Rc<Cell<DeviceState>>,
+            log: Rc<RefCell<Vec<Denial>>>,
+        }
+
+        impl StateGate for Board {
+            fn current_state(&self) -> DeviceState {
+                self.state.get()
+            }
+
+            fn on_denied(
+                &mut self,
+                module_id: ModuleId,
+                entry: &str,
+                state: DeviceState,
+                correlation: u64,
+            ) {
+                self.log
+                    .borrow_mut()
+                    .push((module_id, entry.to_string(), state, correlation));
+            }
+        }
+
+        let mut modules = HashMap::new();
+        modules.insert(1, vec![1, 2, 3]);
+        let state = Rc::new(Cell::new(0));
+        let log = Rc::new(RefCell::new(Vec::new()));
+
+        let mut runtime = Runtime::new(MockEngine::default(), modules);
+        runtime.set_state_gate(Board {
+            state: state.clone(),
+            log: log.clone(),
+        });
+        runtime.restrict_states(1, state_mask(&[0, 2]));
+
+        runtime.execute(1, "tick", &mut ()).unwrap();
+        state.set(1);
+        runtime.trace();
+        let err = runtime
+            .execute_for(1, "tick", &mut (), trace::Reason::RemoteCommand, 77)
+            .unwrap_err();
+        assert_eq!(err, Error::StateDenied);
+        state.set(2);
+        runtime.execute(1, "tick", &mut ()).unwrap();
+
+        assert_eq!(runtime.state_policy().denied_count(), 1);
+        assert_eq!(*log.borrow(), vec![(1, "tick".to_string(), 1, 77)]);
+        let (engine, _) = runtime.into_parts();
         assert_eq!(engine.invoked.len(), 2);
     }
 
     #[test]
-    fn missing_module_returns_error() {
+    fn quota_refuses_calls_after_fuel_is_spent() {
+        struct Metered(MockEngine);
+
+        impl Engine for Metered {
+            type ModuleHandle = ModuleId;
+            type Context = ();
+
+            fn load(&mut self, id: ModuleId, module: &[u8]) -> Result<ModuleId> {
+                self.0.load(id, module)
+            }
+
+            fn invoke(&mut self, handle: ModuleId, entry: &str, ctx: &mut ()) -> Result<()> {
+                self.0.invoke(handle, entry, ctx)
+            }
+
+            fn last_fuel(&self) -> Option<u64> {
+                Some(40)
+            }
+        }
+
+        let mut modules = HashMap::new();
+        modules.insert(1, vec![1]);
+        modules.insert(2, vec![2]);
+        let mut runtime = Runtime::new(Metered(MockEngine::default()), modules);
+        runtime.set_quota(1, quota::Quota::unlimited().fuel_per_window(100, 1_000));
+
+        for _ in 0..3 {
+            runtime.execute(1, "tick", &mut ()).unwrap();
+        }
+        assert_eq!(
+            runtime.execute(1, "tick", &mut ()),
+            Err(Error::QuotaExceeded)
+        );
+        runtime.execute(2, "tick", &mut ()).unwrap();
+        assert_eq!(runtime.quotas().usage(1).fuel, 120);
+        assert_eq!(runtime.quotas().exceeded_count(), 1);
+        let (engine, _) = runtime.into_parts();
+        assert_eq!(engine.0.invoked.len(), 4);
+    }
+
+    #[test]
+    fn invocation_context_visible_during_invoke() {
+        use crate::trace::{Invocation, Reason, TraceContext};
+
+        struct Probe(TraceContext, Vec<Invocation>);
+
+        impl Engine for Probe {
+            type ModuleHandle = ModuleId;
+            type Context = ();
+
+            fn load(&mut self, id: ModuleId, _module: &[u8]) -> Result<ModuleId> {
+                Ok(id)
+            }
+
+            fn invoke(&mut self, _handle: ModuleId, _entry: &str, _ctx: &mut ()) -> Result<()> {
+                self.1.push(self.0.invocation());
+                Ok(())
+            }
+        }
+
+        let mut modules = HashMap::new();
+        modules.insert(3, vec![1]);
+        let trace = TraceContext::new();
+        let mut runtime = Runtime::new(Probe(trace.clone(), Vec::new()), modules);
+        runtime.set_trace(trace.clone());
+        runtime.set_module_version(3, 12);
+
+        runtime.execute(3, "main", &mut ()).unwrap();
+        runtime
+            .execute_for(3, "main", &mut (), Reason::Event, 500)
+            .unwrap();
+
+        let (probe, _) = runtime.into_parts();
+        assert_eq!(
+            probe.1,
+            vec![
+                Invocation {
+                    module_id: 3,
+                    version: 12,
+                    reason: Reason::Direct,
+                    correlation: 1
+                },
+                Invocation {
+                    module_id: 3,
+                    version: 12,
+                    reason: Reason::Event,
+                    correlation: 500
+                },
+            ]
+        );
+        assert_eq!(trace.invocation(), Invocation::default());
+    }
+
+    #[test]
+    fn start_all_runs_dependencies_first() {
+        use crate::deps::{ModuleInfo, Requirement, Target};
+
+        let mut modules = HashMap::new();
+        modules.insert(1, vec![1]);
+        modules.insert(2, vec![2]);
+        let mut runtime = Runtime::new(MockEngine::default(), modules);
+        runtime.registry().register(ModuleInfo {
+            module_id: 1,
+            version: 1,
+            entry: Some("app_start".to_string()),
+            dependencies: vec![Requirement {
+                target: Target::Id(2),
+                min_version: 3,
+            }],
+            ..ModuleInfo::default()
+        });
+        runtime.registry().register(ModuleInfo {
+            module_id: 2,
+            version: 2,
+            entry: Some("net_start".to_string()),
+            ..ModuleInfo::default()
+        });
+
+        let err = runtime.start_all(&mut ()).unwrap_err();
+        assert_eq!(
+            err,
+            Error::DependencyTooOld {
+                module: 1,
+                dependency: 2,
+                required: 3,
+                found: 2
+            }
+        );
+        assert_eq!(err.to_string(), "module 1 needs module 2 >= v3, found v2");
+
+        runtime.set_module_version(2, 3);
+        assert_eq!(runtime.start_all(&mut ()).unwrap(), vec![2, 1]);
+        let (engine, _) = runtime.into_parts();
+        assert_eq!(
+            engine.invoked,
+            vec![(2, "net_start".to_string()), (1, "app_start".to_string())]
+        );
+    }
+
+    /// Entries are their own status, e.g. `"-3"`.
+    struct Statuses;
+
+    impl Engine for Statuses {
+        type ModuleHandle = ();
+        type Context = ();
+
+        fn load(&mut self, _id: ModuleId, _module: &[u8]) -> Result<()> {
+            Ok(())
+        }
+
+        fn invoke(&mut self, handle: (), entry: &str, ctx: &mut ()) -> Result<()> {
+            self.invoke_status(handle, entry, ctx).map(drop)
+        }
+
+        fn invoke_status(&mut self, _handle: (), entry: &str, _ctx: &mut ()) -> Result<i32> {
+            entry.parse().map_err(|_| Error::Engine("trap"))
+        }
+    }
+
+    #[test]
+    fn entry_statuses_split_into_exit_codes_and_guest_errors() {
+        let mut runtime = Runtime::new(Statuses, HashMap::from([(1, vec![0])]));
+        runtime.quarantine().set_threshold(Some(1));
+        assert_eq!(runtime.execute_exit(1, "0", &mut ()), Ok(ExitCode::SUCCESS));
+        let code = runtime.execute_exit(1, "3", &mut ()).unwrap();
+        assert_eq!((code.get(), code.is_success()), (3, false));
+        assert_eq!(
+            runtime.execute_exit(1, "-3", &mut ()),
+            Err(Error::GuestError(-3))
+        );
+        // Reported failures are not traps: the module stays in service.
+        assert!(!runtime.quarantine().is_quarantined(1));
+        assert_eq!(
+            runtime.execute_exit(1, "x", &mut ()),
+            Err(Error::Engine("trap"))
+        );
+        assert!(runtime.quarantine().is_quarantined(1));
+    }
+
+    #[test]
+    fn quarantined_modules_are_not_restored() {
+        let mut runtime = Runtime::new(Statuses, HashMap::from([(1, vec![0])]));
+        runtime.quarantine().set_threshold(Some(1));
+        assert!(runtime.execute_exit(1, "x", &mut ()).is_err());
+
+        // The crashed module is not resumed from (or saved as) a state...
+        assert_eq!(runtime.restore(1, b"state"), Err(Error::ModuleDisabled));
+        assert_eq!(runtime.snapshot(1), Err(Error::ModuleDisabled));
+        // ...until it is released; this engine keeps no state.
+        runtime.quarantine().release(1);
+        assert_eq!(runtime.restore(1, b"state"), Err(Error::Unsupported));
+        runtime.set_enabled(1, false).unwrap();
+        assert_eq!(runtime.restore(1, b"state"), Err(Error::ModuleDisabled));
+    }
+
+    #[test]
+    fn missing_or_incompatible_modules_fail_to_load() {
         let mut runtime = Runtime::new(MockEngine::default(), HashMap::<ModuleId, Vec<u8>>::new());
         let err = runtime.execute(42, "entry", &mut ()).unwrap_err();
         assert_eq!(err, Error::ModuleNotFound);
+
+        // A module declaring a newer ABI never reaches the engine.
+        let mut future = b"\0asm\x01\0\0\0\0\x0d\x0aslimmy.abi".to_vec();
+        future.extend((abi::ABI_VERSION + 1).to_le_bytes());
+        runtime.source_mut().insert(7, future);
+        assert_eq!(
+            runtime.execute(7, "entry", &mut ()),
+            Err(Error::AbiMismatch {
+                host: abi::ABI_VERSION,
+                guest: abi::ABI_VERSION + 1
+            })
+        );
+        assert!(runtime.engine().loaded.is_empty());
+    }
+
+    #[test]
+    fn errors_render_into_fixed_buffers() {
+        let err = Error::DependencyTooOld {
+            module: 3,
+            dependency: 10,
+            required: 7,
+            found: 0,
+        };
+        let mut buf = [0u8; 64];
+        assert_eq!(err.render(&mut buf), err.to_string());
+        assert_eq!(err.render(&mut buf[..12]), "module 3 nee");
+        // A cut never leaves half a character or later text behind.
+        assert_eq!(Error::Engine("é bad").render(&mut buf[..1]), "");
+
+        assert_eq!(Error::OutOfMemory.code(), 13);
+        assert_eq!(Error::GuestError(-7).code(), 14);
+        assert_eq!(Error::Revoked.code(), 16);
+        assert_eq!(Error::Suspended.code(), 17);
+        assert_eq!(
+            Error::AbiMismatch { host: 1, guest: 2 }.render(&mut buf),
+            "module built for ABI v2, host speaks v1"
+        );
+        assert_eq!(
+            Error::GuestError(i32::MIN).render(&mut buf),
+            "module reported error -2147483648"
+        );
+        let engine = Error::Engine("flash read failed").code();
+        assert_eq!(engine & Error::ENGINE_CODE, Error::ENGINE_CODE);
+        assert_eq!(engine, Error::Engine("flash read failed").code());
+        assert_ne!(engine, Error::Engine("flash slot too small").code());
     }
 }
@@ -1,4 +1,4 @@
-//! Minimal manifest format and optional Ed25519 verification.
+//! Minimal manifest format and optional Ed25519 or ECDSA P-256 verification.
 //!
 //! Layout v1 (little endian):
 //! - magic: 4 bytes = b"SMNY"
@@ -14,15 +14,38 @@
 //! - version: u8 = 2
 //! - module_id: u32
 //! - module_len: u32
-//! - flags: u8 (bit0=require signature, bit1=rollback-protected)
+//! - flags: u8 (bit0=require signature, bit1=rollback-protected, bit2=AOT payload,
+//!   bit3=signed)
 //! - sequence: u32 (monotonic, used with rollback flag)
 //! - entry_len: u8
 //! - entry: [u8; entry_len] (UTF-8)
 //! - signature: [u8; 64] (optional; required if flags bit0 set)
 //!
+//! Layout v3 (v2 + extensions; emitted only when extensions are present):
+//! - v2 header up to and including `entry`
+//! - ext_len: u16
+//! - ext: [u8; ext_len] as TLV records (tag: u8, len: u8, value: [u8; len])
+//! - signature: [u8; 64] (optional; required if flags bit0 set)
+//!
+//! Unknown extension tags are skipped so older runtimes accept newer manifests.
+//!
+//! The module must be exactly `module_len` bytes; blobs with bytes missing or
+//! trailing are rejected. Whether a signature follows the header is stated by
+//! the flags (`FLAG_SIGNED`, which `encode` and `signing_preimage` set, or
+//! `FLAG_REQUIRE_SIGNATURE`). For v1 and for v2/v3 blobs written before
+//! `FLAG_SIGNED`, a signature is present only when exactly `SIGNATURE_LEN`
+//! bytes more than `module_len` follow, so an unsigned module of any size is
+//! never taken for a signed one.
+//!
 //! The signed message is the manifest bytes up to (but not including) the signature,
 //! concatenated with the module bytes.
+//!
+//! Signatures are Ed25519 unless an `EXT_SIGNATURE_ALG` record names another
+//! algorithm. The record sits in the signed header, so it cannot be swapped
+//! without breaking the signature; older runtimes skip it and fail the
+//! Ed25519 check. A P-256 signature is `r || s`, the same 64 bytes.
 
+use crate::verify::{Algorithm, PublicKey, Verifier, P256_PUBLIC_KEY_LEN};
 use crate::{Error, ModuleId, Result};
 
 /// Manifest magic marker.
@@ -31,18 +54,119 @@ pub const MANIFEST_MAGIC: &[u8; 4] = b"SMNY";
 pub const MANIFEST_VERSION: u8 = 2;
 /// Manifest version 1 (legacy).
 pub const MANIFEST_VERSION_V1: u8 = 1;
-/// Length of a full Ed25519 signature.
+/// Manifest version 3 (v2 + extension block).
+pub const MANIFEST_VERSION_V3: u8 = 3;
+/// Length of a full signature (Ed25519, or P-256 `r || s`).
 pub const SIGNATURE_LEN: usize = 64;
 
 /// Flags bits (v2).
 pub const FLAG_REQUIRE_SIGNATURE: u8 = 0b0000_0001;
 pub const FLAG_ROLLBACK_PROTECTED: u8 = 0b0000_0010;
+/// The module is a WAMR AOT artifact (`wamrc` output) rather than wasm.
+pub const FLAG_AOT: u8 = 0b0000_0100;
+/// A signature follows the header.
+pub const FLAG_SIGNED: u8 = 0b0000_1000;
+
+/// First bytes of a wasm module.
+pub const WASM_MAGIC: &[u8; 4] = b"\0asm";
+/// First bytes of a WAMR AOT artifact.
+pub const AOT_MAGIC: &[u8; 4] = b"\0aot";
+
+/// What a manifest's module bytes are.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PayloadKind {
+    Wasm,
+    /// WAMR AOT artifact; only engines with `EngineCaps::aot` run it.
+    Aot,
+}
+
+impl PayloadKind {
+    /// Kind of `module`, from its magic.
+    pub fn detect(module: &[u8]) -> Option<Self> {
+        if module.starts_with(WASM_MAGIC) {
+            Some(Self::Wasm)
+        } else if module.starts_with(AOT_MAGIC) {
+            Some(Self::Aot)
+        } else {
+            None
+        }
+    }
+}
+
+/// Extension tags (v3).
+/// Device states the module may run in: u32 bitmask, bit n = state n.
+pub const EXT_ALLOWED_STATES: u8 = 0x01;
+/// Schedule text for `schedule::Trigger::parse` (UTF-8), e.g. `0 2 * * * report`.
+pub const EXT_SCHEDULE: u8 = 0x02;
+/// Module name (UTF-8) other manifests can depend on.
+pub const EXT_NAME: u8 = 0x03;
+/// Dependency by id: module_id u32 + min_version u32 (repeatable).
+pub const EXT_DEPENDS_ID: u8 = 0x04;
+/// Dependency by name: min_version u32 + name (UTF-8) (repeatable).
+pub const EXT_DEPENDS_NAME: u8 = 0x05;
+/// Signature algorithm: u8, `SIG_ALG_ED25519` (the default when absent) or
+/// `SIG_ALG_P256`.
+pub const EXT_SIGNATURE_ALG: u8 = 0x06;
+/// Signer certificate (`cert::Certificate`), leaf first then its issuers
+/// (repeatable).
+pub const EXT_SIGNER_CERT: u8 = 0x07;
+/// SHA-256 of a signer certificate the device already holds, in chain order
+/// after the `EXT_SIGNER_CERT` records (repeatable).
+pub const EXT_SIGNER_CERT_HASH: u8 = 0x08;
+
+/// `EXT_SIGNATURE_ALG` values.
+pub const SIG_ALG_ED25519: u8 = 0x00;
+pub const SIG_ALG_P256: u8 = 0x01;
+
+/// Module a manifest depends on.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DependencyRef<'a> {
+    Id(ModuleId),
+    Name(&'a str),
+}
 
 const HEADER_FIXED_V1: usize = 4 + 1 + 4 + 4 + 1;
 const HEADER_FIXED_V2: usize = 4 + 1 + 4 + 4 + 1 + 4 + 1;
 
+/// Wire format of a manifest blob.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ManifestFormat {
+    /// Compact `SMNY` header (this module).
+    #[default]
+    Smny,
+    /// CBOR SUIT envelope with COSE signatures (`suit`, `manifest-suit` feature).
+    Suit,
+}
+
+impl ManifestFormat {
+    /// Format of `bytes`, from the `SMNY` magic or the SUIT envelope tag.
+    pub fn detect(bytes: &[u8]) -> Option<Self> {
+        if bytes.starts_with(MANIFEST_MAGIC) {
+            Some(Self::Smny)
+        } else if bytes.starts_with(&[0xD8, 0x6B]) {
+            Some(Self::Suit)
+        } else {
+            None
+        }
+    }
+}
+
+impl core::str::FromStr for ManifestFormat {
+    type Err = Error;
+
+    /// `smny` or `suit`.
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "smny" => Ok(Self::Smny),
+            "suit" => Ok(Self::Suit),
+            _ => Err(Error::Engine("manifest format unknown (smny, suit)")),
+        }
+    }
+}
+
 /// Parsed view into a manifest.
 pub struct Manifest<'a> {
+    /// Header version; 0 for a view of a SUIT envelope (`suit::SuitManifest::manifest`).
     pub version: u8,
     pub module_id: ModuleId,
     pub module_len: u32,
@@ -50,6 +174,8 @@ pub struct Manifest<'a> {
     pub flags: u8,
     pub sequence: u32,
     pub signature: Option<&'a [u8; SIGNATURE_LEN]>,
+    /// Raw TLV extension block (empty before v3).
+    pub extensions: &'a [u8],
     raw_without_sig: &'a [u8],
 }
 
@@ -66,11 +192,71 @@ impl<'a> Manifest<'a> {
         let version = bytes[4];
         match version {
             MANIFEST_VERSION_V1 => Self::parse_v1(bytes),
-            MANIFEST_VERSION => Self::parse_v2(bytes),
+            MANIFEST_VERSION | MANIFEST_VERSION_V3 => Self::parse_v2(bytes, version, false),
             _ => Err(Error::Engine("manifest version unsupported")),
         }
     }
 
+    /// Parses a manifest embedded at `offset` of a larger region (e.g.
+    /// memory-mapped flash) and returns it with exactly its module bytes.
+    ///
+    /// Bytes after the blob belong to whatever follows, so the signature's
+    /// presence comes from the flags alone (`FLAG_SIGNED` or
+    /// `FLAG_REQUIRE_SIGNATURE`); v1 manifests carry no flags and are
+    /// rejected. Nothing is copied or allocated.
+    pub fn parse_at(region: &'a [u8], offset: usize) -> Result<(Self, &'a [u8])> {
+        let bytes = region
+            .get(offset..)
+            .ok_or(Error::Engine("manifest offset out of bounds"))?;
+        if bytes.len() < HEADER_FIXED_V1 || &bytes[0..4] != MANIFEST_MAGIC {
+            return Err(Error::Engine("manifest magic mismatch"));
+        }
+        let (manifest, rest) = match bytes[4] {
+            MANIFEST_VERSION | MANIFEST_VERSION_V3 => Self::parse_v2(bytes, bytes[4], true)?,
+            MANIFEST_VERSION_V1 => {
+                return Err(Error::Engine("manifest v1 cannot be parsed in place"))
+            }
+            _ => return Err(Error::Engine("manifest version unsupported")),
+        };
+        let module = rest
+            .get(..manifest.module_len as usize)
+            .ok_or(Error::Engine("manifest module out of bounds"))?;
+        Ok((manifest, module))
+    }
+
+    /// View of a SUIT envelope's fields; it carries no SMNY signature.
+    #[cfg(feature = "manifest-suit")]
+    pub(crate) fn from_suit(
+        module_id: ModuleId,
+        module_len: u32,
+        entry: &'a str,
+        flags: u8,
+        sequence: u32,
+    ) -> Self {
+        Self {
+            version: 0,
+            module_id,
+            module_len,
+            entry,
+            flags,
+            sequence,
+            signature: None,
+            extensions: &[],
+            raw_without_sig: &[],
+        }
+    }
+
+    /// Bytes the whole blob occupies: header, signature and module.
+    pub fn blob_len(&self) -> usize {
+        let signature = self.signature.map_or(0, |_| SIGNATURE_LEN);
+        self.raw_without_sig.len() + signature + self.module_len as usize
+    }
+
+    /// Header bytes the signature covers (everything before it).
+    pub fn signed_header(&self) -> &'a [u8] {
+        self.raw_without_sig
+    }
+
     fn parse_v1(bytes: &'a [u8]) -> Result<(Self, &'a [u8])> {
         let module_id = u32::from_le_bytes(bytes[5..9].try_into().unwrap());
         let module_len = u32::from_le_bytes(bytes[9..13].try_into().unwrap());
@@ -87,16 +273,7 @@ impl<'a> Manifest<'a> {
         let entry = core::str::from_utf8(entry_bytes)
             .map_err(|_| Error::Engine("manifest entry not utf-8"))?;
 
-        let remaining = &bytes[entry_end..];
-        let (signature, module_bytes) = if remaining.len() >= SIGNATURE_LEN {
-            let (sig, module) = remaining.split_at(SIGNATURE_LEN);
-            let sig = sig
-                .try_into()
-                .map_err(|_| Error::Engine("manifest signature malformed"))?;
-            (Some(sig), module)
-        } else {
-            (None, remaining)
-        };
+        let (signature, module_bytes) = split_signature(&bytes[entry_end..], 0, module_len)?;
 
         let raw_without_sig = &bytes[..entry_end];
         Ok((
@@ -108,13 +285,14 @@ impl<'a> Manifest<'a> {
                 flags: 0,
                 sequence: 0,
                 signature,
+                extensions: &[],
                 raw_without_sig,
             },
             module_bytes,
         ))
     }
 
-    fn parse_v2(bytes: &'a [u8]) -> Result<(Self, &'a [u8])> {
+    fn parse_v2(bytes: &'a [u8], version: u8, in_place: bool) -> Result<(Self, &'a [u8])> {
         if bytes.len() < HEADER_FIXED_V2 {
             return Err(Error::Engine("manifest too small"));
         }
@@ -136,37 +314,123 @@ impl<'a> Manifest<'a> {
         let entry = core::str::from_utf8(entry_bytes)
             .map_err(|_| Error::Engine("manifest entry not utf-8"))?;
 
-        let remaining = &bytes[entry_end..];
-        let (signature, module_bytes) = if remaining.len() >= SIGNATURE_LEN {
-            let (sig, module) = remaining.split_at(SIGNATURE_LEN);
-            let sig = sig
-                .try_into()
-                .map_err(|_| Error::Engine("manifest signature malformed"))?;
+        let (extensions, header_end) = if version == MANIFEST_VERSION_V3 {
+            let len_end = entry_end + 2;
+            let len_bytes = bytes
+                .get(entry_end..len_end)
+                .ok_or(Error::Engine("manifest extensions out of bounds"))?;
+            let ext_len = u16::from_le_bytes([len_bytes[0], len_bytes[1]]) as usize;
+            let ext = bytes
+                .get(len_end..len_end + ext_len)
+                .ok_or(Error::Engine("manifest extensions out of bounds"))?;
+            if Extensions::new(ext).any(|record| record.is_none()) {
+                return Err(Error::Engine("manifest extension malformed"));
+            }
+            (ext, len_end + ext_len)
+        } else {
+            (&bytes[..0], entry_end)
+        };
+
+        let remaining = &bytes[header_end..];
+        let (signature, module_bytes) = if !in_place {
+            split_signature(remaining, flags, module_len)?
+        } else if flags & (FLAG_SIGNED | FLAG_REQUIRE_SIGNATURE) != 0 {
+            let (sig, module) = remaining
+                .split_first_chunk()
+                .ok_or(Error::Engine("manifest requires signature"))?;
             (Some(sig), module)
         } else {
             (None, remaining)
         };
 
-        if (flags & FLAG_REQUIRE_SIGNATURE) != 0 && signature.is_none() {
-            return Err(Error::Engine("manifest requires signature"));
-        }
-
-        let raw_without_sig = &bytes[..entry_end];
+        let raw_without_sig = &bytes[..header_end];
         Ok((
             Manifest {
-                version: MANIFEST_VERSION,
+                version,
                 module_id,
                 module_len,
                 entry,
                 flags,
                 sequence,
                 signature,
+                extensions,
                 raw_without_sig,
             },
             module_bytes,
         ))
     }
 
+    /// Iterates `(tag, value)` extension records (already validated by `parse`).
+    pub fn extensions(&self) -> impl Iterator<Item = (u8, &'a [u8])> {
+        Extensions::new(self.extensions).flatten()
+    }
+
+    /// Payload kind the manifest declares (`FLAG_AOT`).
+    pub fn payload(&self) -> PayloadKind {
+        match self.flags & FLAG_AOT {
+            0 => PayloadKind::Wasm,
+            _ => PayloadKind::Aot,
+        }
+    }
+
+    /// Returns the first extension record with `tag`.
+    pub fn extension(&self, tag: u8) -> Option<&'a [u8]> {
+        self.extensions()
+            .find(|(record_tag, _)| *record_tag == tag)
+            .map(|(_, value)| value)
+    }
+
+    /// Device-state bitmask from `EXT_ALLOWED_STATES` (`None` = any state).
+    pub fn allowed_states(&self) -> Option<u32> {
+        let value = self.extension(EXT_ALLOWED_STATES)?;
+        value.try_into().ok().map(u32::from_le_bytes)
+    }
+
+    /// Schedule text from `EXT_SCHEDULE` (`None` = not scheduled by the manifest).
+    pub fn schedule(&self) -> Option<Result<&'a str>> {
+        let value = self.extension(EXT_SCHEDULE)?;
+        Some(core::str::from_utf8(value).map_err(|_| Error::Engine("manifest schedule not utf-8")))
+    }
+
+    /// Module name from `EXT_NAME`, if any.
+    pub fn name(&self) -> Option<Result<&'a str>> {
+        let value = self.extension(EXT_NAME)?;
+        Some(core::str::from_utf8(value).map_err(|_| Error::Engine("manifest name not utf-8")))
+    }
+
+    /// Algorithm the signature was made with, from `EXT_SIGNATURE_ALG`.
+    pub fn signature_algorithm(&self) -> Result<Algorithm> {
+        match self.extension(EXT_SIGNATURE_ALG) {
+            None => Ok(Algorithm::Ed25519),
+            Some(&[id]) => {
+                Algorithm::from_id(id).ok_or(Error::Engine("manifest signature algorithm unknown"))
+            }
+            Some(_) => Err(Error::Engine("manifest signature algorithm unknown")),
+        }
+    }
+
+    /// Declared dependencies with their minimum versions (manifest sequence).
+    pub fn dependencies(&self) -> impl Iterator<Item = Result<(DependencyRef<'a>, u32)>> {
+        self.extensions().filter_map(|(tag, value)| {
+            let min_version = |bytes: &[u8]| u32::from_le_bytes(bytes.try_into().unwrap());
+            match (tag, value.len()) {
+                (EXT_DEPENDS_ID, 8) => Some(Ok((
+                    DependencyRef::Id(min_version(&value[..4])),
+                    min_version(&value[4..]),
+                ))),
+                (EXT_DEPENDS_NAME, 5..) => Some(
+                    core::str::from_utf8(&value[4..])
+                        .map(|name| (DependencyRef::Name(name), min_version(&value[..4])))
+                        .map_err(|_| Error::Engine("manifest dependency malformed")),
+                ),
+                (EXT_DEPENDS_ID | EXT_DEPENDS_NAME, _) => {
+                    Some(Err(Error::Engine("manifest dependency malformed")))
+                }
+                _ => None,
+            }
+        })
+    }
+
     /// Size of the signing preimage when a signature is present.
     pub fn signing_preimage_len(&self, module_len: usize) -> Option<usize> {
         if self.signature.is_some() {
@@ -177,31 +441,156 @@ impl<'a> Manifest<'a> {
     }
 }
 
-#[cfg(feature = "verify-ed25519")]
-/// Verifies the manifest signature against the module bytes using Ed25519.
-pub fn verify_ed25519(manifest: &Manifest<'_>, module: &[u8], pubkey: &[u8; 32]) -> Result<()> {
-    use ed25519_dalek::{Signature, VerifyingKey};
+/// Splits the bytes after the header into signature and module, which must
+/// be exactly `module_len` bytes (see the module docs).
+fn split_signature(
+    remaining: &[u8],
+    flags: u8,
+    module_len: u32,
+) -> Result<(Option<&[u8; SIGNATURE_LEN]>, &[u8])> {
+    let module_len = module_len as usize;
+    let flagged = flags & (FLAG_SIGNED | FLAG_REQUIRE_SIGNATURE) != 0;
+    if flagged && remaining.len() == module_len {
+        return Err(Error::Engine("manifest requires signature"));
+    }
+    let signed = flagged || remaining.len().checked_sub(SIGNATURE_LEN) == Some(module_len);
+    let (signature, module) = match remaining.split_first_chunk() {
+        Some((sig, module)) if signed => (Some(sig), module),
+        _ => (None, remaining),
+    };
+    match module.len().cmp(&module_len) {
+        core::cmp::Ordering::Equal => Ok((signature, module)),
+        core::cmp::Ordering::Greater => Err(Error::Engine("manifest has trailing bytes")),
+        core::cmp::Ordering::Less => Err(Error::Engine("manifest module truncated")),
+    }
+}
 
-    let sig_bytes = manifest
+/// Compares digests, tags or keys in time independent of where they differ.
+/// Only the lengths may leak.
+pub fn ct_eq(a: &[u8], b: &[u8]) -> bool {
+    if a.len() != b.len() {
+        return false;
+    }
+    let diff = a.iter().zip(b).fold(0u8, |diff, (x, y)| diff | (x ^ y));
+    core::hint::black_box(diff) == 0
+}
+
+/// TLV walker; yields `None` once on a truncated record.
+struct Extensions<'a> {
+    rest: &'a [u8],
+}
+
+impl<'a> Extensions<'a> {
+    fn new(block: &'a [u8]) -> Self {
+        Self { rest: block }
+    }
+}
+
+impl<'a> Iterator for Extensions<'a> {
+    type Item = Option<(u8, &'a [u8])>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let (&tag, rest) = self.rest.split_first()?;
+        let record = rest.split_first().and_then(|(&len, rest)| {
+            let value = rest.get(..len as usize)?;
+            self.rest = &rest[len as usize..];
+            Some((tag, value))
+        });
+        if record.is_none() {
+            self.rest = &[];
+        }
+        Some(record)
+    }
+}
+
+#[cfg(feature = "alloc")]
+/// Appends one TLV record to an extension block for `encode_ext`.
+pub fn push_extension(block: &mut alloc::vec::Vec<u8>, tag: u8, value: &[u8]) -> Result<()> {
+    if value.len() > u8::MAX as usize {
+        return Err(Error::Engine("manifest extension too long"));
+    }
+    if block.len() + 2 + value.len() > u16::MAX as usize {
+        return Err(Error::Engine("manifest extensions too long"));
+    }
+    block.push(tag);
+    block.push(value.len() as u8);
+    block.extend_from_slice(value);
+    Ok(())
+}
+
+/// Verifies the manifest's Ed25519 signature against the module bytes with
+/// `verifier`.
+pub fn verify_with(
+    verifier: &dyn Verifier,
+    manifest: &Manifest<'_>,
+    module: &[u8],
+    pubkey: &[u8; 32],
+) -> Result<()> {
+    let signature = signature_for(manifest, module, Algorithm::Ed25519)?;
+    verifier.verify_ed25519(pubkey, &[manifest.raw_without_sig, module], signature)
+}
+
+/// Verifies the manifest's ECDSA P-256 signature against the module bytes
+/// with `verifier`; `pubkey` is a SEC1 compressed point.
+pub fn verify_p256_with(
+    verifier: &dyn Verifier,
+    manifest: &Manifest<'_>,
+    module: &[u8],
+    pubkey: &[u8; P256_PUBLIC_KEY_LEN],
+) -> Result<()> {
+    let signature = signature_for(manifest, module, Algorithm::P256)?;
+    verifier.verify_p256(pubkey, &[manifest.raw_without_sig, module], signature)
+}
+
+/// Verifies the manifest signature with `verifier` against `key`, of
+/// either algorithm.
+pub fn verify_key(
+    verifier: &dyn Verifier,
+    manifest: &Manifest<'_>,
+    module: &[u8],
+    key: &PublicKey,
+) -> Result<()> {
+    match key {
+        PublicKey::Ed25519(key) => verify_with(verifier, manifest, module, key),
+        PublicKey::P256(key) => verify_p256_with(verifier, manifest, module, key),
+    }
+}
+
+/// The signature, once `module` and the declared algorithm match.
+fn signature_for<'a>(
+    manifest: &Manifest<'a>,
+    module: &[u8],
+    algorithm: Algorithm,
+) -> Result<&'a [u8; SIGNATURE_LEN]> {
+    let signature = manifest
         .signature
         .ok_or(Error::Engine("manifest missing signature"))?;
 
     if manifest.module_len as usize != module.len() {
         return Err(Error::Engine("manifest module_len mismatch"));
     }
+    if manifest.signature_algorithm()? != algorithm {
+        return Err(Error::Engine("manifest signed with another algorithm"));
+    }
+    Ok(signature)
+}
 
-    let mut preimage = alloc::vec::Vec::with_capacity(
-        manifest
-            .signing_preimage_len(module.len())
-            .unwrap_or_default(),
-    );
-    preimage.extend_from_slice(manifest.raw_without_sig);
-    preimage.extend_from_slice(module);
+#[cfg(feature = "verify-ed25519")]
+/// Verifies the manifest signature against the module bytes using Ed25519
+/// in software (`verify::SoftwareVerifier`).
+pub fn verify_ed25519(manifest: &Manifest<'_>, module: &[u8], pubkey: &[u8; 32]) -> Result<()> {
+    verify_with(&crate::verify::SoftwareVerifier, manifest, module, pubkey)
+}
 
-    let vk = VerifyingKey::from_bytes(pubkey).map_err(|_| Error::Engine("bad pubkey"))?;
-    let sig = Signature::try_from(sig_bytes).map_err(|_| Error::Engine("bad signature bytes"))?;
-    vk.verify_strict(&preimage, &sig)
-        .map_err(|_| Error::Engine("signature verify failed"))
+#[cfg(feature = "verify-p256")]
+/// Verifies the manifest signature against the module bytes using ECDSA
+/// P-256 in software (`verify::SoftwareVerifier`).
+pub fn verify_p256(
+    manifest: &Manifest<'_>,
+    module: &[u8],
+    pubkey: &[u8; P256_PUBLIC_KEY_LEN],
+) -> Result<()> {
+    verify_p256_with(&crate::verify::SoftwareVerifier, manifest, module, pubkey)
 }
 
 #[cfg(feature = "alloc")]
@@ -214,7 +603,25 @@ pub fn encode(
     sequence: u32,
     signature: Option<[u8; SIGNATURE_LEN]>,
 ) -> Result<alloc::vec::Vec<u8>> {
-    let header = build_header(module_id, entry, module.len(), flags, sequence)?;
+    encode_ext(module_id, entry, module, flags, sequence, &[], signature)
+}
+
+#[cfg(feature = "alloc")]
+/// Like `encode`, but emits a v3 manifest carrying `extensions` when non-empty.
+pub fn encode_ext(
+    module_id: ModuleId,
+    entry: &str,
+    module: &[u8],
+    flags: u8,
+    sequence: u32,
+    extensions: &[u8],
+    signature: Option<[u8; SIGNATURE_LEN]>,
+) -> Result<alloc::vec::Vec<u8>> {
+    let flags = match signature {
+        Some(_) => flags | FLAG_SIGNED,
+        None => flags & !FLAG_SIGNED,
+    };
+    let header = build_header(module_id, entry, module.len(), flags, sequence, extensions)?;
 
     let mut out = alloc::vec::Vec::with_capacity(
         header.len() + signature.map(|_| SIGNATURE_LEN).unwrap_or(0) + module.len(),
@@ -228,7 +635,9 @@ pub fn encode(
 }
 
 #[cfg(feature = "alloc")]
-/// Builds the signing preimage (header + module bytes) for Ed25519 signatures.
+/// Builds the signing preimage (header + module bytes) signatures cover;
+/// the header gets `FLAG_SIGNED`, as in `encode` with a
+/// signature.
 pub fn signing_preimage(
     module_id: ModuleId,
     entry: &str,
@@ -236,7 +645,21 @@ pub fn signing_preimage(
     flags: u8,
     sequence: u32,
 ) -> Result<alloc::vec::Vec<u8>> {
-    let header = build_header(module_id, entry, module.len(), flags, sequence)?;
+    signing_preimage_ext(module_id, entry, module, flags, sequence, &[])
+}
+
+#[cfg(feature = "alloc")]
+/// Signing preimage for a manifest built with `encode_ext`.
+pub fn signing_preimage_ext(
+    module_id: ModuleId,
+    entry: &str,
+    module: &[u8],
+    flags: u8,
+    sequence: u32,
+    extensions: &[u8],
+) -> Result<alloc::vec::Vec<u8>> {
+    let flags = flags | FLAG_SIGNED;
+    let header = build_header(module_id, entry, module.len(), flags, sequence, extensions)?;
     let mut preimage = header;
     preimage.extend_from_slice(module);
     Ok(preimage)
@@ -249,6 +672,7 @@ fn build_header(
     module_len: usize,
     flags: u8,
     sequence: u32,
+    extensions: &[u8],
 ) -> Result<alloc::vec::Vec<u8>> {
     if module_len > u32::MAX as usize {
         return Err(Error::Engine("module too large"));
@@ -259,15 +683,32 @@ fn build_header(
         return Err(Error::Engine("entry name too long"));
     }
 
-    let mut buf = alloc::vec::Vec::with_capacity(HEADER_FIXED_V2 + entry_bytes.len());
+    if extensions.len() > u16::MAX as usize {
+        return Err(Error::Engine("manifest extensions too long"));
+    }
+    if Extensions::new(extensions).any(|record| record.is_none()) {
+        return Err(Error::Engine("manifest extension malformed"));
+    }
+
+    let version = if extensions.is_empty() {
+        MANIFEST_VERSION
+    } else {
+        MANIFEST_VERSION_V3
+    };
+    let mut buf =
+        alloc::vec::Vec::with_capacity(HEADER_FIXED_V2 + entry_bytes.len() + 2 + extensions.len());
     buf.extend_from_slice(MANIFEST_MAGIC);
-    buf.push(MANIFEST_VERSION);
+    buf.push(version);
     buf.extend_from_slice(&module_id.to_le_bytes());
     buf.extend_from_slice(&(module_len as u32).to_le_bytes());
     buf.push(flags);
     buf.extend_from_slice(&sequence.to_le_bytes());
     buf.push(entry_bytes.len() as u8);
     buf.extend_from_slice(entry_bytes);
+    if !extensions.is_empty() {
+        buf.extend_from_slice(&(extensions.len() as u16).to_le_bytes());
+        buf.extend_from_slice(extensions);
+    }
     Ok(buf)
 }
 
@@ -337,4 +778,121 @@ mod tests {
 
         assert!(Manifest::parse(&buf).is_err());
     }
+
+    #[test]
+    fn signature_presence_does_not_depend_on_module_size() {
+        // Unsigned modules of 64 bytes and more used to lose their first 64
+        // bytes to a "signature".
+        let module = [0x5Au8; 100];
+        let plain = encode(1, "main", &module, 0, 0, None).unwrap();
+        let (manifest, module_bytes) = Manifest::parse(&plain).unwrap();
+        assert!(manifest.signature.is_none());
+        assert_eq!(module_bytes, &module);
+
+        let signing = ed25519_dalek::SigningKey::from_bytes(&[5u8; 32]);
+        let preimage = signing_preimage(1, "main", &module, 0, 0).unwrap();
+        let sig = signing.sign(&preimage).to_bytes();
+        let signed = encode(1, "main", &module, 0, 0, Some(sig)).unwrap();
+        let (manifest, module_bytes) = Manifest::parse(&signed).unwrap();
+        assert_eq!(manifest.flags, FLAG_SIGNED);
+        verify_ed25519(&manifest, module_bytes, &signing.verifying_key().to_bytes()).unwrap();
+
+        // Blobs signed before `FLAG_SIGNED` are told apart by their length.
+        let mut legacy = plain[..HEADER_FIXED_V2 + 4].to_vec();
+        legacy.extend_from_slice(&[0u8; SIGNATURE_LEN]);
+        legacy.extend_from_slice(&module);
+        assert!(Manifest::parse(&legacy).unwrap().0.signature.is_some());
+
+        let mut trailing = plain.clone();
+        trailing.push(0);
+        assert_eq!(
+            Manifest::parse(&trailing).err(),
+            Some(Error::Engine("manifest has trailing bytes"))
+        );
+        assert_eq!(
+            Manifest::parse(&plain[..plain.len() - 1]).err(),
+            Some(Error::Engine("manifest module truncated"))
+        );
+        assert_eq!(
+            Manifest::parse(&signed[..signed.len() - 1]).err(),
+            Some(Error::Engine("manifest module truncated"))
+        );
+
+        assert!(ct_eq(b"digest", b"digest"));
+        assert!(!ct_eq(b"digest", b"digesT"));
+        assert!(!ct_eq(b"digest", b"diges"));
+    }
+
+    #[test]
+    fn v3_extensions_round_trip_and_are_signed() {
+        let signing = ed25519_dalek::SigningKey::from_bytes(&[9u8; 32]);
+        let module = [0xAAu8; 5];
+        let mut ext = alloc::vec::Vec::new();
+        push_extension(&mut ext, 0x7f, b"ignored").unwrap();
+        push_extension(&mut ext, EXT_ALLOWED_STATES, &0b101u32.to_le_bytes()).unwrap();
+
+        let preimage = signing_preimage_ext(1, "main", &module, 0, 0, &ext).unwrap();
+        let sig = signing.sign(&preimage).to_bytes();
+        let blob = encode_ext(1, "main", &module, 0, 0, &ext, Some(sig)).unwrap();
+
+        let (manifest, module_bytes) = Manifest::parse(&blob).unwrap();
+        assert_eq!(manifest.version, MANIFEST_VERSION_V3);
+        assert_eq!(manifest.allowed_states(), Some(0b101));
+        assert_eq!(manifest.extensions().count(), 2);
+        assert_eq!(module_bytes, &module);
+        verify_ed25519(&manifest, module_bytes, &signing.verifying_key().to_bytes()).unwrap();
+
+        let plain = encode(1, "main", &module, 0, 0, None).unwrap();
+        let (manifest, _) = Manifest::parse(&plain).unwrap();
+        assert_eq!(manifest.version, MANIFEST_VERSION);
+        assert_eq!(manifest.allowed_states(), None);
+
+        let mut truncated = ext.clone();
+        truncated.pop();
+        assert!(encode_ext(1, "main", &module, 0, 0, &truncated, None).is_err());
+    }
+
+    #[cfg(feature = "verify-p256")]
+    #[test]
+    fn p256_signatures_name_their_algorithm() {
+        use p256::ecdsa::{signature::Signer as _, Signature, SigningKey};
+
+        let signing = SigningKey::from_slice(&[3u8; 32]).unwrap();
+        let pubkey: [u8; P256_PUBLIC_KEY_LEN] = signing
+            .verifying_key()
+            .to_encoded_point(true)
+            .as_bytes()
+            .try_into()
+            .unwrap();
+        let module = [0x55u8; 4];
+        let mut ext = alloc::vec::Vec::new();
+        push_extension(&mut ext, EXT_SIGNATURE_ALG, &[SIG_ALG_P256]).unwrap();
+
+        let preimage = signing_preimage_ext(1, "main", &module, 0, 0, &ext).unwrap();
+        let sig: Signature = signing.sign(&preimage);
+        let blob = encode_ext(1, "main", &module, 0, 0, &ext, Some(sig.to_bytes().into())).unwrap();
+
+        let (manifest, module_bytes) = Manifest::parse(&blob).unwrap();
+        assert_eq!(manifest.signature_algorithm(), Ok(Algorithm::P256));
+        verify_p256(&manifest, module_bytes, &pubkey).unwrap();
+        assert_eq!(
+            verify_ed25519(&manifest, module_bytes, &[0u8; 32]),
+            Err(Error::Engine("manifest signed with another algorithm"))
+        );
+
+        // The identifier is signed: dropping it breaks the signature.
+        let unnamed = encode(1, "main", &module, 0, 0, Some(sig.to_bytes().into())).unwrap();
+        let (manifest, module_bytes) = Manifest::parse(&unnamed).unwrap();
+        assert_eq!(manifest.signature_algorithm(), Ok(Algorithm::Ed25519));
+        assert!(verify_p256(&manifest, module_bytes, &pubkey).is_err());
+
+        let mut unknown = alloc::vec::Vec::new();
+        push_extension(&mut unknown, EXT_SIGNATURE_ALG, &[0x7f]).unwrap();
+        let blob = encode_ext(1, "main", &module, 0, 0, &unknown, None).unwrap();
+        assert!(Manifest::parse(&blob)
+            .unwrap()
+            .0
+            .signature_algorithm()
+            .is_err());
+    }
 }
@@ -0,0 +1,255 @@
+//! Per-module memory accounting.
+//!
+//! RAM pressure on a device that runs several OTA modules is hard to pin on
+//! one of them. The runtime keeps, per module, the linear memory its engine
+//! reports after each invocation (`StackStats`) and the heap its loads and
+//! invocations left allocated, each with its peak; `Runtime::memory_report`
+//! lists them.
+//!
+//! Heap is measured through a `Metrics` gauge the firmware installs with
+//! `Runtime::set_metrics`, typically its global allocator:
+//!
+//! ```ignore
+//! #[global_allocator]
+//! static HEAP: runtime::arena::Arena<{ 64 * 1024 }> = runtime::arena::Arena::new();
+//!
+//! runtime.set_metrics(&HEAP);
+//! for (module_id, usage) in runtime.memory_report() {
+//!     log!("{module_id}: heap {} (peak {})", usage.heap_bytes, usage.peak_heap_bytes);
+//! }
+//! ```
+//!
+//! The gauge is read before a module is loaded and after its invocation
+//! returns, so a module is charged with what stays allocated (engine
+//! instances, kept state, caches the call filled) and credited with what it
+//! frees; allocations made and freed within one call do not show. Without a
+//! gauge only linear memory is tracked.
+
+use crate::{ModuleId, StackStats};
+use alloc::boxed::Box;
+use alloc::vec::Vec;
+
+/// Linear memory page size in bytes.
+const PAGE_SIZE: usize = 64 * 1024;
+
+/// Heap gauge the runtime samples around loads and invocations.
+pub trait Metrics {
+    /// Bytes allocated right now.
+    fn heap_used(&self) -> usize;
+}
+
+impl<M: Metrics + ?Sized> Metrics for &M {
+    fn heap_used(&self) -> usize {
+        (**self).heap_used()
+    }
+}
+
+#[cfg(target_has_atomic = "ptr")]
+impl<const N: usize> Metrics for crate::arena::Arena<N> {
+    fn heap_used(&self) -> usize {
+        self.used()
+    }
+}
+
+/// Memory held by one module, current and peak.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct MemoryUsage {
+    /// Heap its loads and invocations left allocated (0 without a gauge).
+    pub heap_bytes: usize,
+    pub peak_heap_bytes: usize,
+    /// Linear memory after its latest invocation.
+    pub linear_bytes: usize,
+    pub peak_linear_bytes: usize,
+}
+
+impl MemoryUsage {
+    /// Heap plus linear memory.
+    pub fn total_bytes(&self) -> usize {
+        self.heap_bytes.saturating_add(self.linear_bytes)
+    }
+}
+
+/// Usage per module plus the gauge it is measured with.
+#[derive(Default)]
+pub struct MemoryAccounts {
+    gauge: Option<Box<dyn Metrics>>,
+    usage: Vec<(ModuleId, MemoryUsage)>,
+}
+
+impl core::fmt::Debug for MemoryAccounts {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.debug_struct("MemoryAccounts")
+            .field("gauge", &self.gauge.is_some())
+            .field("usage", &self.usage)
+            .finish()
+    }
+}
+
+impl MemoryAccounts {
+    /// Tracks linear memory only.
+    pub const fn new() -> Self {
+        Self {
+            gauge: None,
+            usage: Vec::new(),
+        }
+    }
+
+    /// Measures heap with `gauge` from now on.
+    pub fn set_gauge(&mut self, gauge: impl Metrics + 'static) {
+        self.gauge = Some(Box::new(gauge));
+    }
+
+    /// Usage recorded for a module.
+    pub fn usage(&self, module_id: ModuleId) -> Option<MemoryUsage> {
+        self.usage
+            .iter()
+            .find(|(id, _)| *id == module_id)
+            .map(|(_, usage)| *usage)
+    }
+
+    /// Every module with recorded usage, by id.
+    pub fn report(&self) -> Vec<(ModuleId, MemoryUsage)> {
+        let mut report = self.usage.clone();
+        report.sort_unstable_by_key(|(id, _)| *id);
+        report
+    }
+
+    /// Drops a module's usage (e.g. once it is uninstalled).
+    pub fn forget(&mut self, module_id: ModuleId) {
+        self.usage.retain(|(id, _)| *id != module_id);
+    }
+
+    /// Zeroes every module's heap once the engine freed it (peaks stay).
+    pub(crate) fn release_heap(&mut self) {
+        for (_, usage) in &mut self.usage {
+            usage.heap_bytes = 0;
+        }
+    }
+
+    /// Gauge reading to pass back to `record`.
+    pub(crate) fn heap_used(&self) -> Option<usize> {
+        self.gauge.as_ref().map(|gauge| gauge.heap_used())
+    }
+
+    /// Charges `module_id` with the heap allocated since `heap_before` and
+    /// takes its linear memory from `stats`.
+    pub(crate) fn record(
+        &mut self,
+        module_id: ModuleId,
+        heap_before: Option<usize>,
+        stats: Option<StackStats>,
+    ) {
+        let heap_after = self.heap_used();
+        if heap_after.is_none() && stats.is_none() {
+            return;
+        }
+        let index = match self.usage.iter().position(|(id, _)| *id == module_id) {
+            Some(index) => index,
+            None => {
+                self.usage.push((module_id, MemoryUsage::default()));
+                self.usage.len() - 1
+            }
+        };
+        let usage = &mut self.usage[index].1;
+        if let (Some(before), Some(after)) = (heap_before, heap_after) {
+            usage.heap_bytes = match after.checked_sub(before) {
+                Some(grown) => usage.heap_bytes.saturating_add(grown),
+                None => usage.heap_bytes.saturating_sub(before - after),
+            };
+            usage.peak_heap_bytes = usage.peak_heap_bytes.max(usage.heap_bytes);
+        }
+        if let Some(stats) = stats {
+            usage.linear_bytes = (stats.last_memory_pages as usize).saturating_mul(PAGE_SIZE);
+            usage.peak_linear_bytes = (stats.memory_pages as usize).saturating_mul(PAGE_SIZE);
+        }
+    }
+}
+
+#[cfg(all(test, feature = "std"))]
+mod tests {
+    use super::*;
+    use crate::{Engine, MemoryStore, Result, Runtime};
+    use alloc::rc::Rc;
+    use core::cell::Cell;
+
+    #[derive(Clone, Default)]
+    struct Heap(Rc<Cell<usize>>);
+
+    impl Metrics for Heap {
+        fn heap_used(&self) -> usize {
+            self.0.get()
+        }
+    }
+
+    /// Loading allocates 100 bytes; `grow` keeps 50 more and a page of
+    /// linear memory, `shrink` frees 130 and the page.
+    struct Growing {
+        heap: Heap,
+        stats: StackStats,
+    }
+
+    impl Engine for Growing {
+        type ModuleHandle = ();
+        type Context = ();
+
+        fn load(&mut self, _id: ModuleId, _module: &[u8]) -> Result<()> {
+            self.heap.0.set(self.heap.0.get() + 100);
+            Ok(())
+        }
+
+        fn invoke(&mut self, _handle: (), entry: &str, _ctx: &mut ()) -> Result<()> {
+            let (heap, pages) = match entry {
+                "grow" => (self.heap.0.get() + 50, 2),
+                _ => (self.heap.0.get() - 130, 1),
+            };
+            self.heap.0.set(heap);
+            self.stats.record(None, pages);
+            Ok(())
+        }
+
+        fn stack_stats(&self, _handle: ()) -> Option<StackStats> {
+            Some(self.stats)
+        }
+    }
+
+    #[test]
+    fn charges_modules_with_the_memory_they_keep() {
+        let heap = Heap::default();
+        let mut store = MemoryStore::new();
+        store.upsert(1, b"\0asm".to_vec());
+        let engine = Growing {
+            heap: heap.clone(),
+            stats: StackStats::default(),
+        };
+        let mut runtime = Runtime::new(engine, store);
+        runtime.execute(1, "grow", &mut ()).unwrap();
+        // No gauge yet: linear memory only.
+        assert_eq!(
+            runtime.memory_usage(1),
+            Some(MemoryUsage {
+                linear_bytes: 2 * PAGE_SIZE,
+                peak_linear_bytes: 2 * PAGE_SIZE,
+                ..MemoryUsage::default()
+            })
+        );
+
+        runtime.set_metrics(heap);
+        runtime.execute(1, "grow", &mut ()).unwrap();
+        runtime.execute(1, "shrink", &mut ()).unwrap();
+        assert_eq!(
+            runtime.memory_report(),
+            [(
+                1,
+                MemoryUsage {
+                    heap_bytes: 120,
+                    peak_heap_bytes: 150,
+                    linear_bytes: PAGE_SIZE,
+                    peak_linear_bytes: 2 * PAGE_SIZE,
+                }
+            )]
+        );
+
+        runtime.memory_accounts().forget(1);
+        assert!(runtime.memory_report().is_empty());
+    }
+}
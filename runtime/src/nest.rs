@@ -0,0 +1,225 @@
+//! Nested invocations: a host function running a module synchronously while
+//! another module's call is still on the stack.
+//!
+//! An engine is never re-entered: `Runtime` holds it mutably for the whole
+//! call, so a nested invocation always goes through another runtime reached
+//! from an import set. Runtimes sharing one `Nesting` count their invocations
+//! together and refuse any past the policy with `Error::Reentrancy` before
+//! the engine is touched; by default nothing may nest. The calling guest sees
+//! the refusal as its host function's error (a trap) unless the import set
+//! turns it into a status.
+//!
+//! A runtime kept in a `RefCell` for its import sets is reached with
+//! `borrow_mut`, which reports a runtime that is already running as
+//! `Error::Reentrancy` rather than panicking.
+//!
+//! ```ignore
+//! let nesting = Nesting::new(NestingPolicy::MaxDepth(2));
+//! outer.set_nesting(nesting.clone());
+//! inner.borrow_mut().set_nesting(nesting);
+//! ```
+
+use crate::{Error, Result};
+use alloc::rc::Rc;
+use core::cell::{Cell, RefCell, RefMut};
+
+/// How deep invocations may nest.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum NestingPolicy {
+    /// Any nested invocation fails with `Error::Reentrancy`.
+    #[default]
+    Deny,
+    /// Up to this many invocations may run nested inside the outermost one.
+    MaxDepth(u8),
+}
+
+struct State {
+    policy: Cell<NestingPolicy>,
+    depth: Cell<u8>,
+}
+
+/// Shared count of the invocations in progress.
+#[derive(Clone)]
+pub struct Nesting {
+    state: Rc<State>,
+}
+
+impl Default for Nesting {
+    fn default() -> Self {
+        Self::new(NestingPolicy::Deny)
+    }
+}
+
+impl Nesting {
+    pub fn new(policy: NestingPolicy) -> Self {
+        Self {
+            state: Rc::new(State {
+                policy: Cell::new(policy),
+                depth: Cell::new(0),
+            }),
+        }
+    }
+
+    pub fn policy(&self) -> NestingPolicy {
+        self.state.policy.get()
+    }
+
+    /// Applies to invocations entered from now on.
+    pub fn set_policy(&self, policy: NestingPolicy) {
+        self.state.policy.set(policy);
+    }
+
+    /// Invocations in progress; 0 when idle, 1 inside the outermost one.
+    pub fn depth(&self) -> u8 {
+        self.state.depth.get()
+    }
+
+    /// Counts an invocation until the returned guard drops;
+    /// `Error::Reentrancy` when it would nest deeper than the policy allows.
+    pub fn enter(&self) -> Result<NestScope> {
+        let depth = self.depth();
+        let max_nested = match self.policy() {
+            NestingPolicy::Deny => 0,
+            NestingPolicy::MaxDepth(max) => max,
+        };
+        if depth > max_nested {
+            return Err(Error::Reentrancy);
+        }
+        self.state.depth.set(depth + 1);
+        Ok(NestScope {
+            nesting: self.clone(),
+        })
+    }
+}
+
+/// Ends an invocation counted by `Nesting::enter` on drop.
+pub struct NestScope {
+    nesting: Nesting,
+}
+
+impl Drop for NestScope {
+    fn drop(&mut self) {
+        let depth = &self.nesting.state.depth;
+        depth.set(depth.get() - 1);
+    }
+}
+
+/// Borrows a shared runtime (or engine); `Error::Reentrancy` while it is
+/// already borrowed, i.e. when a host function reaches the one running it.
+pub fn borrow_mut<T>(cell: &RefCell<T>) -> Result<RefMut<'_, T>> {
+    cell.try_borrow_mut().map_err(|_| Error::Reentrancy)
+}
+
+#[cfg(all(test, feature = "std"))]
+mod tests {
+    use super::*;
+    use crate::abi::{GuestMemory, HostFn, HostImports, Imports};
+    use crate::{Engine, MemoryStore, ModuleId, Runtime};
+    use alloc::vec::Vec;
+
+    /// `nest(id)` runs module `id` on `inner` and logs the result.
+    struct Nest {
+        inner: Rc<RefCell<Runtime<Fake, MemoryStore>>>,
+        results: Rc<RefCell<Vec<Result<()>>>>,
+    }
+
+    impl HostImports for Nest {
+        fn functions(&self) -> &[HostFn] {
+            const FNS: &[HostFn] = &[HostFn::new("nest", 1)];
+            FNS
+        }
+
+        fn call(
+            &mut self,
+            _name: &str,
+            args: &[i32],
+            _memory: &mut dyn GuestMemory,
+        ) -> Result<i32> {
+            let result = borrow_mut(&self.inner)
+                .and_then(|mut runtime| runtime.execute(args[0] as ModuleId, "main", &mut ()));
+            self.results.borrow_mut().push(result);
+            Ok(0)
+        }
+    }
+
+    /// Module `n` calls `nest(n - 1)`; module 0 calls nothing.
+    struct Fake {
+        imports: Imports,
+    }
+
+    impl Engine for Fake {
+        type ModuleHandle = ModuleId;
+        type Context = ();
+
+        fn load(&mut self, id: ModuleId, _module: &[u8]) -> Result<ModuleId> {
+            Ok(id)
+        }
+
+        fn invoke(&mut self, id: ModuleId, _entry: &str, _ctx: &mut ()) -> Result<()> {
+            if id > 0 && !self.imports.is_empty() {
+                self.imports.call("nest", &[id as i32 - 1], &mut [0u8; 0])?;
+            }
+            Ok(())
+        }
+    }
+
+    fn store() -> MemoryStore {
+        let mut store = MemoryStore::new();
+        for id in 0..4 {
+            store.upsert(id, b"\0asm".to_vec());
+        }
+        store
+    }
+
+    #[test]
+    fn bounds_nested_invocations() {
+        let inner = Rc::new(RefCell::new(Runtime::new(
+            Fake {
+                imports: Imports::new(),
+            },
+            store(),
+        )));
+        let results = Rc::new(RefCell::new(Vec::new()));
+        let imports = Imports::new().with(Nest {
+            inner: inner.clone(),
+            results: results.clone(),
+        });
+        let mut outer = Runtime::new(Fake { imports }, store());
+        outer.execute(1, "main", &mut ()).unwrap();
+        // Unshared runtimes do not see each other.
+        assert_eq!(results.borrow_mut().pop(), Some(Ok(())));
+
+        let nesting = Nesting::default();
+        outer.set_nesting(nesting.clone());
+        inner.borrow_mut().set_nesting(nesting.clone());
+        outer.execute(1, "main", &mut ()).unwrap();
+        assert_eq!(results.borrow_mut().pop(), Some(Err(Error::Reentrancy)));
+
+        nesting.set_policy(NestingPolicy::MaxDepth(1));
+        outer.execute(1, "main", &mut ()).unwrap();
+        assert_eq!(results.borrow_mut().pop(), Some(Ok(())));
+        assert_eq!(nesting.depth(), 0);
+    }
+
+    #[test]
+    fn refuses_a_runtime_that_is_already_running() {
+        let shared = Rc::new(RefCell::new(Runtime::new(
+            Fake {
+                imports: Imports::new(),
+            },
+            store(),
+        )));
+        let results = Rc::new(RefCell::new(Vec::new()));
+        shared.borrow_mut().engine().imports = Imports::new().with(Nest {
+            inner: shared.clone(),
+            results: results.clone(),
+        });
+
+        borrow_mut(&shared)
+            .unwrap()
+            .execute(2, "main", &mut ())
+            .unwrap();
+        assert_eq!(*results.borrow(), [Err(Error::Reentrancy)]);
+        assert!(borrow_mut(&shared).is_ok());
+    }
+}
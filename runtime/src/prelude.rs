@@ -0,0 +1,21 @@
+//! Stable core, for `use runtime::prelude::*;`.
+//!
+//! Everything re-exported here follows semver: it only changes in a breaking
+//! way on a major release, and renamed items keep a `#[deprecated]` shim for at
+//! least one minor release. Modules outside the prelude are stable unless
+//! listed as unstable in the crate docs; those sit behind the `unstable`
+//! feature and may change in any minor release.
+
+pub use crate::abi::{GuestMemory, HostFn, HostImports};
+pub use crate::manifest::Manifest;
+pub use crate::{
+    Engine, EngineCaps, Error, ExitCode, ModuleCatalog, ModuleId, ModuleSink, ModuleSource, Result,
+    Runtime,
+};
+
+#[cfg(feature = "alloc")]
+pub use crate::abi::Imports;
+#[cfg(feature = "alloc")]
+pub use crate::builder::{InstanceMode, RuntimeBuilder};
+#[cfg(feature = "alloc")]
+pub use crate::{CachedEngine, MemoryStore};
@@ -0,0 +1,357 @@
+//! Property tests for the store, cache and scheduler state machines.
+//!
+//! Each property runs random operation sequences (`proptest`) against a
+//! simple model and checks the invariant after every step; a failure is
+//! shrunk to a minimal sequence and names the step it broke at.
+
+use crate::schedule::{Clock, Scheduler, Trigger};
+use crate::{CachedEngine, Engine, Error, MemoryStore, ModuleId, ModuleSink, ModuleSource};
+use crate::{Result, Runtime};
+use proptest::prelude::*;
+use proptest::sample::Index;
+use std::cell::{Cell, RefCell};
+use std::collections::{BTreeMap, HashSet};
+use std::rc::Rc;
+use std::vec::Vec;
+
+/// Sequences per property.
+const CASES: u32 = 256;
+/// Most operations per sequence.
+const STEPS: usize = 64;
+/// Ids are drawn from a small range so operations collide.
+const IDS: u32 = 6;
+
+fn id() -> impl Strategy<Value = ModuleId> {
+    1..=IDS
+}
+
+fn bytes() -> impl Strategy<Value = Vec<u8>> {
+    prop::collection::vec(any::<u8>(), 0..9)
+}
+
+#[derive(Debug, Clone)]
+enum StoreOp {
+    Upsert(ModuleId, Vec<u8>),
+    Remove(ModuleId),
+    /// A streamed write of `bytes`, checked after `split` of them and
+    /// aborted there or committed.
+    Stream {
+        id: ModuleId,
+        bytes: Vec<u8>,
+        split: Index,
+        abort: bool,
+    },
+}
+
+fn store_op() -> impl Strategy<Value = StoreOp> {
+    prop_oneof![
+        1 => (id(), bytes()).prop_map(|(id, bytes)| StoreOp::Upsert(id, bytes)),
+        1 => id().prop_map(StoreOp::Remove),
+        2 => (id(), bytes(), any::<Index>(), prop::bool::weighted(0.25)).prop_map(
+            |(id, bytes, split, abort)| StoreOp::Stream {
+                id,
+                bytes,
+                split,
+                abort,
+            }
+        ),
+    ]
+}
+
+proptest! {
+    #![proptest_config(ProptestConfig::with_cases(CASES))]
+
+    #[test]
+    fn store_fetch_returns_latest_committed_bytes(
+        ops in prop::collection::vec(store_op(), 1..=STEPS),
+    ) {
+        let mut store = MemoryStore::new();
+        let mut model = BTreeMap::<ModuleId, Vec<u8>>::new();
+        for (step, op) in ops.into_iter().enumerate() {
+            match op {
+                StoreOp::Upsert(id, bytes) => {
+                    store.upsert(id, bytes.clone());
+                    model.insert(id, bytes);
+                }
+                StoreOp::Remove(id) => {
+                    prop_assert_eq!(store.remove(id), model.remove(&id).is_some(), "step {}", step);
+                }
+                // Streamed writes land on commit only.
+                StoreOp::Stream { id, bytes, split, abort } => {
+                    store.begin(id, bytes.len()).unwrap();
+                    let split = split.index(bytes.len() + 1);
+                    store.write(&bytes[..split]).unwrap();
+                    let committed = model.get(&id).map(Vec::as_slice);
+                    prop_assert_eq!(store.fetch(id), committed, "step {}", step);
+                    if abort {
+                        store.abort();
+                    } else {
+                        store.write(&bytes[split..]).unwrap();
+                        store.commit().unwrap();
+                        model.insert(id, bytes);
+                    }
+                }
+            }
+            for id in 1..=IDS {
+                prop_assert_eq!(
+                    store.fetch(id),
+                    model.get(&id).map(Vec::as_slice),
+                    "step {} id {}",
+                    step,
+                    id
+                );
+            }
+            let runtime = Runtime::new(Handles::default(), store);
+            let ids: Vec<_> = runtime.module_ids().collect();
+            prop_assert!(ids.iter().eq(model.keys()), "step {}: {:?}", step, ids);
+            store = runtime.into_parts().1;
+        }
+    }
+}
+
+/// Hands out a fresh handle per load and remembers dropped ones.
+#[derive(Default)]
+struct Handles {
+    next: u32,
+    live: Vec<(u32, ModuleId)>,
+    dropped: HashSet<u32>,
+}
+
+impl Engine for Handles {
+    type ModuleHandle = u32;
+    type Context = ();
+
+    fn load(&mut self, id: ModuleId, _module: &[u8]) -> Result<u32> {
+        self.next += 1;
+        self.live.push((self.next, id));
+        Ok(self.next)
+    }
+
+    fn invoke(&mut self, handle: u32, _entry: &str, _ctx: &mut ()) -> Result<()> {
+        if self.dropped.contains(&handle) {
+            return Err(Error::Engine("dropped handle"));
+        }
+        Ok(())
+    }
+
+    fn drop_module(&mut self, handle: u32) {
+        self.live.retain(|(live, _)| *live != handle);
+        self.dropped.insert(handle);
+    }
+
+    fn unload(&mut self, id: ModuleId) {
+        let dropped: Vec<u32> = self
+            .live
+            .iter()
+            .filter(|(_, module)| *module == id)
+            .map(|(handle, _)| *handle)
+            .collect();
+        for handle in dropped {
+            self.drop_module(handle);
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+enum CacheOp {
+    Load(ModuleId),
+    Evict(ModuleId),
+    DropCached(ModuleId),
+    Unload(ModuleId),
+}
+
+fn cache_op() -> impl Strategy<Value = CacheOp> {
+    prop_oneof![
+        2 => id().prop_map(CacheOp::Load),
+        1 => id().prop_map(CacheOp::Evict),
+        1 => id().prop_map(CacheOp::DropCached),
+        1 => id().prop_map(CacheOp::Unload),
+    ]
+}
+
+proptest! {
+    #![proptest_config(ProptestConfig::with_cases(CASES))]
+
+    #[test]
+    fn cache_never_returns_an_evicted_handle(
+        limit in 0..4usize,
+        ops in prop::collection::vec(cache_op(), 1..=STEPS),
+    ) {
+        let mut engine = CachedEngine::with_capacity_limit(Handles::default(), limit);
+        // Handle the cache may return per module, if any.
+        let mut cached = BTreeMap::<ModuleId, u32>::new();
+        for (step, op) in ops.into_iter().enumerate() {
+            match op {
+                CacheOp::Load(id) => {
+                    let handle = engine.load(id, &[0]).unwrap();
+                    prop_assert!(
+                        !engine.inner.dropped.contains(&handle),
+                        "step {}: dropped handle {} for {}",
+                        step,
+                        handle,
+                        id
+                    );
+                    match cached.get(&id) {
+                        Some(&expected) => prop_assert_eq!(handle, expected, "step {}", step),
+                        // A miss loads afresh, even right after an evict.
+                        None => {
+                            prop_assert_eq!(handle, engine.inner.next, "step {}", step);
+                            if cached.len() < limit {
+                                cached.insert(id, handle);
+                            }
+                        }
+                    }
+                    engine.invoke(handle, "main", &mut ()).unwrap();
+                }
+                CacheOp::Evict(id) => {
+                    let was_cached = cached.remove(&id).is_some();
+                    prop_assert_eq!(engine.evict(id), was_cached, "step {}", step);
+                }
+                CacheOp::DropCached(id) => {
+                    if let Some(handle) = cached.remove(&id) {
+                        engine.drop_cached(handle);
+                    }
+                }
+                CacheOp::Unload(id) => {
+                    engine.unload(id);
+                    cached.remove(&id);
+                }
+            }
+            prop_assert_eq!(engine.cached_len(), cached.len(), "step {}", step);
+            prop_assert!(engine.cached_len() <= limit, "step {}", step);
+        }
+    }
+}
+
+/// Fails modules listed in `failing`; logs every call.
+struct Flaky {
+    failing: Rc<RefCell<HashSet<ModuleId>>>,
+}
+
+impl Engine for Flaky {
+    type ModuleHandle = ModuleId;
+    type Context = Vec<(ModuleId, bool)>;
+
+    fn load(&mut self, id: ModuleId, _module: &[u8]) -> Result<ModuleId> {
+        Ok(id)
+    }
+
+    fn invoke(&mut self, handle: ModuleId, _entry: &str, ctx: &mut Self::Context) -> Result<()> {
+        let fails = self.failing.borrow().contains(&handle);
+        ctx.push((handle, !fails));
+        if fails {
+            Err(Error::Engine("trap"))
+        } else {
+            Ok(())
+        }
+    }
+}
+
+#[derive(Clone)]
+struct Manual(Rc<Cell<u64>>);
+
+impl Clock for Manual {
+    fn now_ms(&self) -> u64 {
+        self.0.get()
+    }
+}
+
+#[derive(Debug, Clone)]
+enum SchedulerOp {
+    SetEnabled(ModuleId, bool),
+    ToggleFailing(ModuleId),
+    MakeDue(ModuleId),
+    /// Advances the clock by this many ms and ticks.
+    Tick(u64),
+}
+
+fn scheduler_op() -> impl Strategy<Value = SchedulerOp> {
+    prop_oneof![
+        1 => (id(), any::<bool>()).prop_map(|(id, enabled)| SchedulerOp::SetEnabled(id, enabled)),
+        1 => id().prop_map(SchedulerOp::ToggleFailing),
+        1 => id().prop_map(SchedulerOp::MakeDue),
+        2 => (0..3u64).prop_map(SchedulerOp::Tick),
+    ]
+}
+
+const THRESHOLD: u32 = 2;
+
+proptest! {
+    #![proptest_config(ProptestConfig::with_cases(CASES))]
+
+    #[test]
+    fn scheduler_never_runs_a_disabled_module(
+        // Periods of each module's jobs.
+        jobs in prop::collection::vec(prop::collection::vec(1..=3u64, 1..=2), IDS as usize),
+        ops in prop::collection::vec(scheduler_op(), 1..=STEPS),
+    ) {
+        let mut store = MemoryStore::new();
+        for id in 1..=IDS {
+            store.upsert(id, vec![0]);
+        }
+        let failing = Rc::new(RefCell::new(HashSet::new()));
+        let mut runtime = Runtime::new(
+            Flaky {
+                failing: failing.clone(),
+            },
+            store,
+        );
+        runtime.quarantine().set_threshold(Some(THRESHOLD));
+        let now = Manual(Rc::new(Cell::new(0)));
+        let mut scheduler = Scheduler::new(now.clone());
+        for (id, periods) in (1..=IDS).zip(jobs) {
+            for period in periods {
+                scheduler.add(id, "main", Trigger::Every(period));
+            }
+        }
+
+        // Model: switched off, consecutive failures, quarantined.
+        let mut off = HashSet::new();
+        let mut failures = BTreeMap::<ModuleId, u32>::new();
+        let mut quarantined = HashSet::new();
+        for (step, op) in ops.into_iter().enumerate() {
+            match op {
+                SchedulerOp::SetEnabled(id, enabled) => {
+                    runtime.set_enabled(id, enabled).unwrap();
+                    if enabled {
+                        off.remove(&id);
+                        quarantined.remove(&id);
+                        failures.remove(&id);
+                    } else {
+                        off.insert(id);
+                    }
+                }
+                SchedulerOp::ToggleFailing(id) => {
+                    let mut failing = failing.borrow_mut();
+                    if !failing.remove(&id) {
+                        failing.insert(id);
+                    }
+                }
+                SchedulerOp::MakeDue(id) => scheduler.make_due(id),
+                SchedulerOp::Tick(ms) => {
+                    now.0.set(now.0.get() + ms);
+                    let mut calls = Vec::new();
+                    let ran = scheduler.tick(&mut runtime, &mut calls);
+                    prop_assert_eq!(ran, calls.len(), "step {}", step);
+                    for (module_id, ok) in calls {
+                        prop_assert!(
+                            !off.contains(&module_id) && !quarantined.contains(&module_id),
+                            "step {}: disabled module {} ran",
+                            step,
+                            module_id
+                        );
+                        let count = failures.entry(module_id).or_insert(0);
+                        *count = if ok { 0 } else { *count + 1 };
+                        if *count >= THRESHOLD {
+                            quarantined.insert(module_id);
+                        }
+                    }
+                }
+            }
+            for id in 1..=IDS {
+                let enabled = !off.contains(&id) && !quarantined.contains(&id);
+                prop_assert_eq!(runtime.is_enabled(id), enabled, "step {} id {}", step, id);
+            }
+        }
+    }
+}
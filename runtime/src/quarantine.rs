@@ -0,0 +1,221 @@
+//! Quarantine for modules that keep failing.
+//!
+//! The runtime counts consecutive failures per module: invocations the engine
+//! trapped (the ones reported as `InvokeTrapped`), images `Runtime::swap`
+//! rejected, and anything the firmware reports via `Runtime::record_failure`.
+//! A success resets the count. Once a module reaches the threshold it is
+//! quarantined: every invocation is refused with `Error::ModuleDisabled`
+//! before the module is fetched, the scheduler skips its jobs, and the event
+//! sink gets `Event::Quarantined`. A crash loop therefore costs at most
+//! `threshold` wake-ups instead of draining the battery.
+//!
+//! Quarantine lasts until `Runtime::set_enabled(id, true)` (also reachable as
+//! remote `SetEnabled`) or `Quarantine::release`; it is not persisted, so a
+//! module that should stay off across reboots is switched off (`switch`), and
+//! one whose bytes must not stay readable is removed with
+//! `Runtime::secure_erase` (`erase`).
+
+use crate::{Error, ModuleId, Result};
+use alloc::vec::Vec;
+
+/// Failure counts and the modules quarantined for them.
+#[derive(Debug, Clone, Default)]
+pub struct Quarantine {
+    threshold: Option<u32>,
+    /// Consecutive failures of modules that failed since their last success.
+    failures: Vec<(ModuleId, u32)>,
+    /// Ascending.
+    quarantined: Vec<ModuleId>,
+}
+
+impl Quarantine {
+    /// Counts failures but never quarantines.
+    pub const fn new() -> Self {
+        Self {
+            threshold: None,
+            failures: Vec::new(),
+            quarantined: Vec::new(),
+        }
+    }
+
+    /// Quarantines modules after `failures` consecutive failures (`None`
+    /// turns quarantine off; already quarantined modules stay so).
+    pub fn set_threshold(&mut self, failures: Option<u32>) {
+        self.threshold = failures.map(|n| n.max(1));
+    }
+
+    pub fn threshold(&self) -> Option<u32> {
+        self.threshold
+    }
+
+    /// Consecutive failures since the module last succeeded.
+    pub fn failures(&self, module_id: ModuleId) -> u32 {
+        self.failures
+            .iter()
+            .find(|(id, _)| *id == module_id)
+            .map_or(0, |(_, n)| *n)
+    }
+
+    pub fn is_quarantined(&self, module_id: ModuleId) -> bool {
+        self.quarantined.binary_search(&module_id).is_ok()
+    }
+
+    /// Quarantined modules, ascending.
+    pub fn quarantined(&self) -> &[ModuleId] {
+        &self.quarantined
+    }
+
+    /// Counts a failure; returns the count if it just put the module in
+    /// quarantine.
+    pub fn record_failure(&mut self, module_id: ModuleId) -> Option<u32> {
+        let count = match self.failures.iter_mut().find(|(id, _)| *id == module_id) {
+            Some((_, n)) => {
+                *n = n.saturating_add(1);
+                *n
+            }
+            None => {
+                self.failures.push((module_id, 1));
+                1
+            }
+        };
+        let threshold = self.threshold?;
+        match self.quarantined.binary_search(&module_id) {
+            Err(at) if count >= threshold => {
+                self.quarantined.insert(at, module_id);
+                Some(count)
+            }
+            _ => None,
+        }
+    }
+
+    /// Resets the module's failure count.
+    pub fn record_success(&mut self, module_id: ModuleId) {
+        self.failures.retain(|(id, _)| *id != module_id);
+    }
+
+    /// Lets the module run again with a clean count; returns whether it was
+    /// quarantined.
+    pub fn release(&mut self, module_id: ModuleId) -> bool {
+        self.record_success(module_id);
+        match self.quarantined.binary_search(&module_id) {
+            Ok(at) => {
+                self.quarantined.remove(at);
+                true
+            }
+            Err(_) => false,
+        }
+    }
+
+    /// `Error::ModuleDisabled` for a quarantined module.
+    pub fn check(&self, module_id: ModuleId) -> Result<()> {
+        if self.is_quarantined(module_id) {
+            Err(Error::ModuleDisabled)
+        } else {
+            Ok(())
+        }
+    }
+}
+
+#[cfg(all(test, feature = "std"))]
+mod tests {
+    use super::*;
+    use crate::schedule::{Clock, Scheduler, Trigger};
+    use crate::telemetry::{Event, EventSink, Record};
+    use crate::{Engine, MemoryStore, Runtime};
+    use alloc::rc::Rc;
+    use core::cell::RefCell;
+
+    /// Traps while the context says so; counts the calls that got through.
+    struct Flaky;
+
+    impl Engine for Flaky {
+        type ModuleHandle = ModuleId;
+        type Context = (bool, u32);
+
+        fn load(&mut self, id: ModuleId, _module: &[u8]) -> Result<ModuleId> {
+            Ok(id)
+        }
+
+        fn invoke(
+            &mut self,
+            _handle: ModuleId,
+            entry: &str,
+            ctx: &mut Self::Context,
+        ) -> Result<()> {
+            if entry != "main" {
+                return Err(Error::EntryNotFound);
+            }
+            ctx.1 += 1;
+            if ctx.0 {
+                Err(Error::Engine("trap"))
+            } else {
+                Ok(())
+            }
+        }
+    }
+
+    struct Epoch;
+
+    impl Clock for Epoch {
+        fn now_ms(&self) -> u64 {
+            0
+        }
+    }
+
+    struct Quarantines(Rc<RefCell<Vec<(ModuleId, u32)>>>);
+
+    impl EventSink for Quarantines {
+        fn event(&mut self, record: &Record<'_>) {
+            if let Event::Quarantined {
+                module_id,
+                failures,
+                ..
+            } = record.event
+            {
+                self.0.borrow_mut().push((module_id, failures));
+            }
+        }
+    }
+
+    #[test]
+    fn crash_loops_end_in_quarantine_until_released() {
+        let mut store = MemoryStore::new();
+        store.upsert(1, vec![0]);
+        let mut runtime = Runtime::new(Flaky, store);
+        let events = Rc::new(RefCell::new(Vec::new()));
+        runtime.set_event_sink(Quarantines(events.clone()), Epoch);
+        runtime.quarantine().set_threshold(Some(3));
+
+        let mut ctx = (true, 0);
+        assert!(runtime.execute(1, "main", &mut ctx).is_err());
+        assert!(runtime.execute(1, "main", &mut ctx).is_err());
+        // Successes reset the count; missing entries do not count.
+        ctx.0 = false;
+        runtime.execute(1, "main", &mut ctx).unwrap();
+        assert_eq!(
+            runtime.execute(1, "other", &mut ctx),
+            Err(Error::EntryNotFound)
+        );
+        assert_eq!(runtime.quarantine().failures(1), 0);
+
+        ctx.0 = true;
+        let mut scheduler = Scheduler::new(Epoch);
+        scheduler.add(1, "main", Trigger::Every(1));
+        for _ in 0..5 {
+            scheduler.make_due(1);
+            scheduler.tick(&mut runtime, &mut ctx);
+        }
+        assert_eq!(ctx.1, 6);
+        assert_eq!(events.borrow().as_slice(), [(1, 3)]);
+        assert!(!runtime.is_enabled(1));
+        assert_eq!(
+            runtime.execute(1, "main", &mut ctx),
+            Err(Error::ModuleDisabled)
+        );
+
+        ctx.0 = false;
+        assert_eq!(runtime.set_enabled(1, true), Ok(true));
+        runtime.execute(1, "main", &mut ctx).unwrap();
+        assert_eq!(ctx.1, 7);
+    }
+}
@@ -0,0 +1,237 @@
+//! Per-module execution quotas.
+//!
+//! A `Quota` caps how often a module may be invoked per second and how much
+//! fuel it may burn per window, so one chatty module cannot monopolize a
+//! shared device. `Runtime` checks the quota before loading the module and
+//! returns `Error::QuotaExceeded` once a limit is reached; the call is not
+//! counted and the engine is not touched.
+//!
+//! Fuel is only known after a call finishes (`Engine::last_fuel`), so the call
+//! that crosses the fuel limit completes and later calls in the same window
+//! are refused. Engines that do not meter fuel leave fuel limits unenforced.
+//!
+//! Windows are fixed and start with the first call after the previous one
+//! expired, measured with the `schedule::Clock` installed via
+//! `Runtime::set_quota_clock`. Without a clock windows never expire, so the
+//! limits become lifetime totals.
+
+use crate::schedule::Clock;
+use crate::{Error, ModuleId, Result};
+use alloc::boxed::Box;
+use alloc::vec::Vec;
+
+const MS_PER_SECOND: u64 = 1_000;
+
+/// Limits for one module; `None` leaves that dimension unlimited.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Quota {
+    /// Invocations allowed per second.
+    pub max_invocations_per_sec: Option<u32>,
+    /// Fuel allowed per `fuel_window_ms`.
+    pub max_fuel: Option<u64>,
+    pub fuel_window_ms: u64,
+}
+
+impl Quota {
+    /// No limits.
+    pub const fn unlimited() -> Self {
+        Self {
+            max_invocations_per_sec: None,
+            max_fuel: None,
+            fuel_window_ms: 0,
+        }
+    }
+
+    /// At most `max` invocations per second.
+    pub const fn invocations_per_sec(mut self, max: u32) -> Self {
+        self.max_invocations_per_sec = Some(max);
+        self
+    }
+
+    /// At most `max` fuel per `window_ms` milliseconds.
+    pub const fn fuel_per_window(mut self, max: u64, window_ms: u64) -> Self {
+        self.max_fuel = Some(max);
+        self.fuel_window_ms = window_ms;
+        self
+    }
+}
+
+/// Usage in the current windows.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Usage {
+    pub invocations: u32,
+    pub fuel: u64,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Entry {
+    quota: Quota,
+    usage: Usage,
+    invocations_since: u64,
+    fuel_since: u64,
+}
+
+/// Quotas for every restricted module plus the clock that ages them.
+#[derive(Default)]
+pub struct Quotas {
+    clock: Option<Box<dyn Clock>>,
+    entries: Vec<(ModuleId, Entry)>,
+    exceeded: u32,
+}
+
+impl Quotas {
+    /// No clock, no quotas.
+    pub const fn new() -> Self {
+        Self {
+            clock: None,
+            entries: Vec::new(),
+            exceeded: 0,
+        }
+    }
+
+    /// Installs the clock windows are measured with.
+    pub fn set_clock(&mut self, clock: impl Clock + 'static) {
+        self.clock = Some(Box::new(clock));
+    }
+
+    /// Sets `module_id`'s quota and resets its usage; `Quota::unlimited()`
+    /// removes it.
+    pub fn set(&mut self, module_id: ModuleId, quota: Quota) {
+        self.entries.retain(|(id, _)| *id != module_id);
+        if quota != Quota::unlimited() {
+            let entry = Entry {
+                quota,
+                usage: Usage::default(),
+                invocations_since: 0,
+                fuel_since: 0,
+            };
+            self.entries.push((module_id, entry));
+        }
+    }
+
+    /// Quota for `module_id` (`Quota::unlimited()` when none is set).
+    pub fn get(&self, module_id: ModuleId) -> Quota {
+        self.entry(module_id)
+            .map_or(Quota::unlimited(), |entry| entry.quota)
+    }
+
+    /// Usage of `module_id` in its current windows.
+    pub fn usage(&self, module_id: ModuleId) -> Usage {
+        self.entry(module_id)
+            .map_or(Usage::default(), |entry| entry.usage)
+    }
+
+    /// Number of invocations refused so far.
+    pub fn exceeded_count(&self) -> u32 {
+        self.exceeded
+    }
+
+    fn entry(&self, module_id: ModuleId) -> Option<&Entry> {
+        self.entries
+            .iter()
+            .find(|(id, _)| *id == module_id)
+            .map(|(_, entry)| entry)
+    }
+
+    fn now_ms(&self) -> u64 {
+        self.clock.as_ref().map_or(0, |clock| clock.now_ms())
+    }
+
+    /// Counts an invocation of `module_id`, or refuses it with
+    /// `Error::QuotaExceeded` when a limit is already reached.
+    pub fn admit(&mut self, module_id: ModuleId) -> Result<()> {
+        let now = self.now_ms();
+        let Some((_, entry)) = self.entries.iter_mut().find(|(id, _)| *id == module_id) else {
+            return Ok(());
+        };
+        entry.roll(now);
+        let quota = entry.quota;
+        let over_rate = quota
+            .max_invocations_per_sec
+            .is_some_and(|max| entry.usage.invocations >= max);
+        let over_fuel = quota.max_fuel.is_some_and(|max| entry.usage.fuel >= max);
+        if over_rate || over_fuel {
+            self.exceeded = self.exceeded.saturating_add(1);
+            return Err(Error::QuotaExceeded);
+        }
+        entry.usage.invocations += 1;
+        Ok(())
+    }
+
+    /// Charges fuel burnt by an invocation of `module_id`.
+    pub fn charge_fuel(&mut self, module_id: ModuleId, fuel: u64) {
+        let now = self.now_ms();
+        if let Some((_, entry)) = self.entries.iter_mut().find(|(id, _)| *id == module_id) {
+            entry.roll(now);
+            entry.usage.fuel = entry.usage.fuel.saturating_add(fuel);
+        }
+    }
+}
+
+impl Entry {
+    /// Starts new windows for those that expired by `now`.
+    fn roll(&mut self, now: u64) {
+        if now.saturating_sub(self.invocations_since) >= MS_PER_SECOND {
+            self.invocations_since = now;
+            self.usage.invocations = 0;
+        }
+        if now.saturating_sub(self.fuel_since) >= self.quota.fuel_window_ms {
+            self.fuel_since = now;
+            self.usage.fuel = 0;
+        }
+    }
+}
+
+#[cfg(all(test, feature = "std"))]
+mod tests {
+    use super::*;
+    use std::cell::Cell;
+    use std::rc::Rc;
+
+    struct Manual(Rc<Cell<u64>>);
+
+    impl Clock for Manual {
+        fn now_ms(&self) -> u64 {
+            self.0.get()
+        }
+    }
+
+    #[test]
+    fn limits_rate_and_fuel_per_window() {
+        let now = Rc::new(Cell::new(5_000));
+        let mut quotas = Quotas::new();
+        quotas.set_clock(Manual(now.clone()));
+        quotas.set(1, Quota::unlimited().invocations_per_sec(2));
+        quotas.set(2, Quota::unlimited().fuel_per_window(100, 10_000));
+
+        assert_eq!(quotas.admit(1), Ok(()));
+        assert_eq!(quotas.admit(1), Ok(()));
+        assert_eq!(quotas.admit(1), Err(Error::QuotaExceeded));
+        assert_eq!(quotas.usage(1).invocations, 2);
+        now.set(5_999);
+        assert_eq!(quotas.admit(1), Err(Error::QuotaExceeded));
+        now.set(6_000);
+        assert_eq!(quotas.admit(1), Ok(()));
+
+        // The call that crosses the fuel limit completes; the next is refused.
+        assert_eq!(quotas.admit(2), Ok(()));
+        quotas.charge_fuel(2, 60);
+        assert_eq!(quotas.admit(2), Ok(()));
+        quotas.charge_fuel(2, 60);
+        assert_eq!(quotas.admit(2), Err(Error::QuotaExceeded));
+        now.set(16_000);
+        assert_eq!(quotas.admit(2), Ok(()));
+        assert_eq!(
+            quotas.usage(2),
+            Usage {
+                invocations: 1,
+                fuel: 0
+            }
+        );
+
+        assert_eq!(quotas.admit(3), Ok(()));
+        assert_eq!(quotas.exceeded_count(), 3);
+        quotas.set(1, Quota::unlimited());
+        assert_eq!(quotas.get(1), Quota::unlimited());
+    }
+}
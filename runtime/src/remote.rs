@@ -0,0 +1,674 @@
+//! Framed command protocol for managing a device over any link.
+//!
+//! A `Transport` moves whole frames (serial with SLIP/COBS, a BLE
+//! characteristic, a TCP stream with length prefixes); `RemoteServer` answers
+//! the `Command`s it receives against a `Runtime`, one per `poll`.
+//!
+//! Frames (little-endian, at most `MAX_FRAME_LEN` bytes):
+//!
+//! | frame    | layout                                  |
+//! |----------|-----------------------------------------|
+//! | request  | op u8, tag u8, payload                  |
+//! | response | status u8, tag u8, payload              |
+//!
+//! The tag is echoed so hosts can match responses to requests. Payloads:
+//!
+//! | op | command        | request payload                    | response payload |
+//! |----|----------------|------------------------------------|------------------|
+//! | 1  | `ListModules`  | –                                  | `(id u32, version u32)`… up to the frame size |
+//! | 2  | `InstallBegin` | id u32, len u32, version u32       | –                |
+//! | 3  | `InstallData`  | next chunk of the image            | –                |
+//! | 4  | `InstallCommit`| –                                  | –                |
+//! | 5  | `InstallAbort` | –                                  | –                |
+//! | 6  | `Execute`      | id u32, correlation u64, entry     | –                |
+//! | 7  | `GetMetrics`   | –                                  | `Metrics`: modules, state denials, quota refusals, all u32 |
+//! | 8  | `SetEnabled`   | id u32, enabled u8 (0 or 1)        | –                |
+//! | 9  | `Call`         | id u32, correlation u64, entry len u8, entry, request | the export's response |
+//!
+//! Installs stream raw module bytes through the source's `ModuleSink`, so the
+//! image is never buffered whole; a committed image replaces the module's
+//! cached handle and records its version. Nothing is verified here: accept
+//! installs only over authenticated links, or disable them with
+//! `RemoteServer::read_only` and deliver signed manifests through `swap` or
+//! `dfu` instead. `Execute` runs with `Reason::RemoteCommand` under the given
+//! correlation id (0 = fresh).
+//!
+//! `Call` (`rpc` feature) runs a `#[slimmy_export]` function through the
+//! `Rpc` set given to `RemoteServer::with_rpc` (see `rpc`); request and
+//! response are its postcard payloads (`codec::postcard`), relayed as is.
+//! Responses must fit the frame. Without an `Rpc` set, calls are refused.
+
+#[cfg(feature = "rpc")]
+use crate::rpc::Rpc;
+use crate::telemetry::Event;
+use crate::trace::Reason;
+use crate::{Engine, Error, ModuleCatalog, ModuleId, ModuleSink, ModuleSource, Result, Runtime};
+use core::str;
+
+/// Largest request or response frame in bytes.
+pub const MAX_FRAME_LEN: usize = 256;
+
+/// Response status: the command succeeded.
+pub const STATUS_OK: u8 = 0;
+/// The request frame could not be decoded.
+pub const STATUS_BAD_FRAME: u8 = 1;
+/// The op is not known.
+pub const STATUS_UNKNOWN_OP: u8 = 2;
+/// Installs are disabled, or data/commit arrived without `InstallBegin`.
+pub const STATUS_REFUSED: u8 = 3;
+pub const STATUS_MODULE_NOT_FOUND: u8 = 4;
+pub const STATUS_ENTRY_NOT_FOUND: u8 = 5;
+/// Any other runtime error.
+pub const STATUS_FAILED: u8 = 6;
+/// The module is disabled (`SetEnabled`).
+pub const STATUS_DISABLED: u8 = 7;
+
+/// Moves whole frames between the device and a host.
+pub trait Transport {
+    /// Receives the next frame into `buf`; `Ok(None)` when none is pending.
+    /// Frames longer than `buf` are an error.
+    fn recv(&mut self, buf: &mut [u8]) -> Result<Option<usize>>;
+
+    fn send(&mut self, frame: &[u8]) -> Result<()>;
+}
+
+/// A request, as sent by the host.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Command<'a> {
+    ListModules,
+    InstallBegin {
+        module_id: ModuleId,
+        len: u32,
+        version: u32,
+    },
+    InstallData(&'a [u8]),
+    InstallCommit,
+    InstallAbort,
+    Execute {
+        module_id: ModuleId,
+        correlation: u64,
+        entry: &'a str,
+    },
+    GetMetrics,
+    /// Turns a module on or off without uninstalling it (see `switch`).
+    SetEnabled {
+        module_id: ModuleId,
+        enabled: bool,
+    },
+    /// Runs a typed export with an encoded request (`rpc` feature).
+    #[cfg(feature = "rpc")]
+    Call {
+        module_id: ModuleId,
+        correlation: u64,
+        entry: &'a str,
+        request: &'a [u8],
+    },
+}
+
+impl<'a> Command<'a> {
+    fn op(&self) -> u8 {
+        match self {
+            Command::ListModules => 1,
+            Command::InstallBegin { .. } => 2,
+            Command::InstallData(_) => 3,
+            Command::InstallCommit => 4,
+            Command::InstallAbort => 5,
+            Command::Execute { .. } => 6,
+            Command::GetMetrics => 7,
+            Command::SetEnabled { .. } => 8,
+            #[cfg(feature = "rpc")]
+            Command::Call { .. } => 9,
+        }
+    }
+
+    /// Writes the request frame into `buf`; returns its length, or `None`
+    /// when it does not fit.
+    pub fn encode(&self, tag: u8, buf: &mut [u8]) -> Option<usize> {
+        let mut out = Writer { buf, len: 0 };
+        out.put(&[self.op(), tag])?;
+        match *self {
+            Command::InstallBegin {
+                module_id,
+                len,
+                version,
+            } => {
+                out.put(&module_id.to_le_bytes())?;
+                out.put(&len.to_le_bytes())?;
+                out.put(&version.to_le_bytes())?;
+            }
+            Command::InstallData(chunk) => out.put(chunk)?,
+            Command::Execute {
+                module_id,
+                correlation,
+                entry,
+            } => {
+                out.put(&module_id.to_le_bytes())?;
+                out.put(&correlation.to_le_bytes())?;
+                out.put(entry.as_bytes())?;
+            }
+            Command::SetEnabled { module_id, enabled } => {
+                out.put(&module_id.to_le_bytes())?;
+                out.put(&[enabled as u8])?;
+            }
+            #[cfg(feature = "rpc")]
+            Command::Call {
+                module_id,
+                correlation,
+                entry,
+                request,
+            } => {
+                out.put(&module_id.to_le_bytes())?;
+                out.put(&correlation.to_le_bytes())?;
+                out.put(&[u8::try_from(entry.len()).ok()?])?;
+                out.put(entry.as_bytes())?;
+                out.put(request)?;
+            }
+            _ => {}
+        }
+        Some(out.len)
+    }
+
+    /// Parses a request frame into its tag and command.
+    pub fn decode(frame: &'a [u8]) -> core::result::Result<(u8, Self), u8> {
+        let [op, tag, payload @ ..] = frame else {
+            return Err(STATUS_BAD_FRAME);
+        };
+        let command = match (op, payload.len()) {
+            (1, 0) => Command::ListModules,
+            (2, 12) => Command::InstallBegin {
+                module_id: u32_at(payload, 0),
+                len: u32_at(payload, 4),
+                version: u32_at(payload, 8),
+            },
+            (3, _) => Command::InstallData(payload),
+            (4, 0) => Command::InstallCommit,
+            (5, 0) => Command::InstallAbort,
+            (6, 13..) => Command::Execute {
+                module_id: u32_at(payload, 0),
+                correlation: u64::from_le_bytes(payload[4..12].try_into().unwrap()),
+                entry: str::from_utf8(&payload[12..]).map_err(|_| STATUS_BAD_FRAME)?,
+            },
+            (7, 0) => Command::GetMetrics,
+            (8, 5) if payload[4] <= 1 => Command::SetEnabled {
+                module_id: u32_at(payload, 0),
+                enabled: payload[4] == 1,
+            },
+            #[cfg(feature = "rpc")]
+            (9, 13..) => {
+                let (entry, request) = payload[13..]
+                    .split_at_checked(usize::from(payload[12]))
+                    .ok_or(STATUS_BAD_FRAME)?;
+                Command::Call {
+                    module_id: u32_at(payload, 0),
+                    correlation: u64::from_le_bytes(payload[4..12].try_into().unwrap()),
+                    entry: str::from_utf8(entry).map_err(|_| STATUS_BAD_FRAME)?,
+                    request,
+                }
+            }
+            #[cfg(feature = "rpc")]
+            (9, _) => return Err(STATUS_BAD_FRAME),
+            (1..=8, _) => return Err(STATUS_BAD_FRAME),
+            _ => return Err(STATUS_UNKNOWN_OP),
+        };
+        Ok((*tag, command))
+    }
+}
+
+/// Counters reported by `GetMetrics`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Metrics {
+    /// Modules in the source.
+    pub modules: u32,
+    /// Calls refused by the device-state gate.
+    pub state_denials: u32,
+    /// Calls refused by quotas.
+    pub quota_refusals: u32,
+}
+
+impl Metrics {
+    pub fn decode(payload: &[u8]) -> Option<Self> {
+        (payload.len() == 12).then(|| Self {
+            modules: u32_at(payload, 0),
+            state_denials: u32_at(payload, 4),
+            quota_refusals: u32_at(payload, 8),
+        })
+    }
+}
+
+fn u32_at(bytes: &[u8], at: usize) -> u32 {
+    u32::from_le_bytes(bytes[at..at + 4].try_into().unwrap())
+}
+
+struct Writer<'b> {
+    buf: &'b mut [u8],
+    len: usize,
+}
+
+impl Writer<'_> {
+    fn put(&mut self, bytes: &[u8]) -> Option<()> {
+        let end = self.len.checked_add(bytes.len())?;
+        self.buf.get_mut(self.len..end)?.copy_from_slice(bytes);
+        self.len = end;
+        Some(())
+    }
+}
+
+/// Install in progress.
+#[derive(Debug, Clone, Copy)]
+struct Pending {
+    module_id: ModuleId,
+    version: u32,
+}
+
+/// Device side of the protocol.
+pub struct RemoteServer<T> {
+    transport: T,
+    read_only: bool,
+    pending: Option<Pending>,
+    #[cfg(feature = "rpc")]
+    rpc: Option<Rpc>,
+}
+
+impl<T: Transport> RemoteServer<T> {
+    /// Serves every command received over `transport`.
+    pub fn new(transport: T) -> Self {
+        Self {
+            transport,
+            read_only: false,
+            pending: None,
+            #[cfg(feature = "rpc")]
+            rpc: None,
+        }
+    }
+
+    /// Refuses installs (builder style).
+    pub fn read_only(mut self) -> Self {
+        self.read_only = true;
+        self
+    }
+
+    /// Serves `Call` through `rpc`, which must be registered with the
+    /// runtime's engine (builder style).
+    #[cfg(feature = "rpc")]
+    pub fn with_rpc(mut self, rpc: Rpc) -> Self {
+        self.rpc = Some(rpc);
+        self
+    }
+
+    pub fn transport(&mut self) -> &mut T {
+        &mut self.transport
+    }
+
+    pub fn into_inner(self) -> T {
+        self.transport
+    }
+
+    /// Answers the next pending frame, if any; returns whether one was
+    /// handled. Errors are transport errors; command failures are reported
+    /// to the host.
+    pub fn poll<E, S>(&mut self, runtime: &mut Runtime<E, S>, ctx: &mut E::Context) -> Result<bool>
+    where
+        E: Engine,
+        S: ModuleSource + ModuleSink + ModuleCatalog,
+    {
+        let mut request = [0u8; MAX_FRAME_LEN];
+        let Some(len) = self.transport.recv(&mut request)? else {
+            return Ok(false);
+        };
+        let mut response = [0u8; MAX_FRAME_LEN];
+        let mut out = Writer {
+            buf: &mut response,
+            len: 2,
+        };
+        let (tag, status) = match Command::decode(&request[..len]) {
+            Ok((tag, command)) => (tag, self.handle(command, runtime, ctx, &mut out)),
+            Err(status) => (request.get(1).copied().unwrap_or(0), status),
+        };
+        if status != STATUS_OK {
+            out.len = 2;
+        }
+        out.buf[..2].copy_from_slice(&[status, tag]);
+        let len = out.len;
+        self.transport.send(&response[..len])?;
+        Ok(true)
+    }
+
+    fn handle<E, S>(
+        &mut self,
+        command: Command<'_>,
+        runtime: &mut Runtime<E, S>,
+        ctx: &mut E::Context,
+        out: &mut Writer<'_>,
+    ) -> u8
+    where
+        E: Engine,
+        S: ModuleSource + ModuleSink + ModuleCatalog,
+    {
+        let result = match command {
+            Command::ListModules => {
+                for module_id in runtime.module_ids() {
+                    let version = runtime.module_version(module_id);
+                    let entry = [module_id.to_le_bytes(), version.to_le_bytes()].concat();
+                    if out.put(&entry).is_none() {
+                        break;
+                    }
+                }
+                Ok(())
+            }
+            Command::InstallBegin {
+                module_id,
+                len,
+                version,
+            } => {
+                if self.read_only {
+                    return STATUS_REFUSED;
+                }
+                self.pending = None;
+                runtime.emit(Event::UpdateReceived {
+                    module_id,
+                    version,
+                    len: len as usize,
+                });
+                let begun = runtime.source_mut().begin(module_id, len as usize);
+                if begun.is_ok() {
+                    self.pending = Some(Pending { module_id, version });
+                }
+                begun
+            }
+            Command::InstallData(chunk) => {
+                if self.pending.is_none() {
+                    return STATUS_REFUSED;
+                }
+                let written = runtime.source_mut().write(chunk);
+                if written.is_err() {
+                    self.abort(runtime);
+                }
+                written
+            }
+            Command::InstallCommit => {
+                let Some(Pending { module_id, version }) = self.pending.take() else {
+                    return STATUS_REFUSED;
+                };
+                let from = runtime.module_version(module_id);
+                let committed = runtime.source_mut().commit();
+                if committed.is_ok() {
+                    runtime.engine().unload(module_id);
+                    runtime.set_module_version(module_id, version);
+                    runtime.emit(Event::ModuleActivated {
+                        module_id,
+                        from,
+                        to: version,
+                    });
+                } else {
+                    runtime.source_mut().abort();
+                }
+                committed
+            }
+            Command::InstallAbort => {
+                self.abort(runtime);
+                Ok(())
+            }
+            Command::Execute {
+                module_id,
+                correlation,
+                entry,
+            } => runtime.execute_for(module_id, entry, ctx, Reason::RemoteCommand, correlation),
+            Command::GetMetrics => {
+                let modules = runtime.source().module_count() as u32;
+                let state_denials = runtime.state_policy().denied_count();
+                let quota_refusals = runtime.quotas().exceeded_count();
+                for value in [modules, state_denials, quota_refusals] {
+                    out.put(&value.to_le_bytes());
+                }
+                Ok(())
+            }
+            Command::SetEnabled { module_id, enabled } => {
+                if runtime.source().fetch(module_id).is_none() {
+                    return STATUS_MODULE_NOT_FOUND;
+                }
+                runtime.set_enabled(module_id, enabled).map(drop)
+            }
+            #[cfg(feature = "rpc")]
+            Command::Call {
+                module_id,
+                correlation,
+                entry,
+                request,
+            } => {
+                let Some(rpc) = &self.rpc else {
+                    return STATUS_REFUSED;
+                };
+                let origin = (Reason::RemoteCommand, correlation);
+                runtime
+                    .call_encoded_for(rpc, module_id, entry, request, ctx, origin)
+                    .and_then(|response| {
+                        out.put(&response)
+                            .ok_or(Error::Engine("remote: response exceeds the frame"))
+                    })
+            }
+        };
+        match result {
+            Ok(()) => STATUS_OK,
+            Err(Error::ModuleNotFound) => STATUS_MODULE_NOT_FOUND,
+            Err(Error::EntryNotFound) => STATUS_ENTRY_NOT_FOUND,
+            Err(Error::ModuleDisabled) => STATUS_DISABLED,
+            Err(_) => STATUS_FAILED,
+        }
+    }
+
+    fn abort<E: Engine, S: ModuleSource + ModuleSink>(&mut self, runtime: &mut Runtime<E, S>) {
+        if self.pending.take().is_some() {
+            runtime.source_mut().abort();
+        }
+    }
+}
+
+#[cfg(all(test, feature = "std"))]
+mod tests {
+    use super::*;
+    use crate::MemoryStore;
+    use alloc::collections::VecDeque;
+    use alloc::vec::Vec;
+
+    /// Frames queued by the test; responses collected.
+    #[derive(Default)]
+    struct Loopback {
+        requests: VecDeque<Vec<u8>>,
+        responses: Vec<Vec<u8>>,
+    }
+
+    impl Loopback {
+        fn push(&mut self, tag: u8, command: Command<'_>) {
+            let mut buf = [0u8; MAX_FRAME_LEN];
+            let len = command.encode(tag, &mut buf).unwrap();
+            self.requests.push_back(buf[..len].to_vec());
+        }
+    }
+
+    impl Transport for Loopback {
+        fn recv(&mut self, buf: &mut [u8]) -> Result<Option<usize>> {
+            Ok(self.requests.pop_front().map(|frame| {
+                buf[..frame.len()].copy_from_slice(&frame);
+                frame.len()
+            }))
+        }
+
+        fn send(&mut self, frame: &[u8]) -> Result<()> {
+            self.responses.push(frame.to_vec());
+            Ok(())
+        }
+    }
+
+    /// Every module has only `main`.
+    struct Mains;
+
+    impl Engine for Mains {
+        type ModuleHandle = ();
+        type Context = u32;
+
+        fn load(&mut self, _id: ModuleId, _module: &[u8]) -> Result<()> {
+            Ok(())
+        }
+
+        fn invoke(&mut self, _handle: (), entry: &str, runs: &mut u32) -> Result<()> {
+            if entry != "main" {
+                return Err(Error::EntryNotFound);
+            }
+            *runs += 1;
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn installs_lists_and_executes_over_frames() {
+        let mut runtime = Runtime::new(Mains, MemoryStore::new());
+        let mut server = RemoteServer::new(Loopback::default());
+        let link = server.transport();
+        link.push(
+            1,
+            Command::InstallBegin {
+                module_id: 9,
+                len: 6,
+                version: 4,
+            },
+        );
+        link.push(2, Command::InstallData(b"\0asm"));
+        link.push(3, Command::InstallData(b"\x01\0"));
+        link.push(4, Command::InstallCommit);
+        link.push(5, Command::ListModules);
+        let execute = |entry| Command::Execute {
+            module_id: 9,
+            correlation: 0,
+            entry,
+        };
+        link.push(6, execute("main"));
+        link.push(7, execute("other"));
+        link.push(8, Command::GetMetrics);
+        link.push(9, Command::InstallCommit);
+        link.requests.push_back(vec![42, 10]);
+        let switch = |module_id, enabled| Command::SetEnabled { module_id, enabled };
+        link.push(11, switch(9, false));
+        link.push(12, execute("main"));
+        link.push(13, switch(5, false));
+
+        let mut runs = 0;
+        while server.poll(&mut runtime, &mut runs).unwrap() {}
+        assert_eq!(runs, 1);
+        assert_eq!(runtime.source().fetch(9), Some(&b"\0asm\x01\0"[..]));
+
+        let responses = &server.transport().responses;
+        let ok = |tag| vec![STATUS_OK, tag];
+        assert_eq!(responses[..4], [ok(1), ok(2), ok(3), ok(4)]);
+        assert_eq!(responses[4], [0, 5, 9, 0, 0, 0, 4, 0, 0, 0]);
+        assert_eq!(responses[5..7], [ok(6), vec![STATUS_ENTRY_NOT_FOUND, 7]]);
+        assert_eq!(&responses[7][..2], ok(8));
+        let metrics = Metrics::decode(&responses[7][2..]).unwrap();
+        assert_eq!(metrics.modules, 1);
+        assert_eq!(responses[8], [STATUS_REFUSED, 9]);
+        assert_eq!(responses[9], [STATUS_UNKNOWN_OP, 10]);
+        assert_eq!(
+            responses[10..],
+            [
+                ok(11),
+                vec![STATUS_DISABLED, 12],
+                vec![STATUS_MODULE_NOT_FOUND, 13]
+            ]
+        );
+        assert!(!runtime.is_enabled(9));
+    }
+
+    #[test]
+    fn read_only_servers_refuse_installs() {
+        let mut runtime = Runtime::new(Mains, MemoryStore::new());
+        let mut server = RemoteServer::new(Loopback::default()).read_only();
+        let begin = Command::InstallBegin {
+            module_id: 1,
+            len: 1,
+            version: 1,
+        };
+        server.transport().push(0, begin);
+        server.transport().requests.push_back(vec![2, 1, 0]);
+        while server.poll(&mut runtime, &mut 0).unwrap() {}
+        assert_eq!(
+            server.transport().responses,
+            [vec![STATUS_REFUSED, 0], vec![STATUS_BAD_FRAME, 1]]
+        );
+    }
+
+    /// `add` answers a postcard `(u32, u32)` with its sum, as the
+    /// `#[slimmy_export]` glue would.
+    #[cfg(feature = "rpc")]
+    struct Adder(crate::abi::Imports);
+
+    #[cfg(feature = "rpc")]
+    impl Engine for Adder {
+        type ModuleHandle = ();
+        type Context = ();
+
+        fn load(&mut self, _id: ModuleId, _module: &[u8]) -> Result<()> {
+            Ok(())
+        }
+
+        fn invoke(&mut self, handle: (), entry: &str, ctx: &mut ()) -> Result<()> {
+            self.invoke_status(handle, entry, ctx).map(drop)
+        }
+
+        fn invoke_status(&mut self, _handle: (), entry: &str, _ctx: &mut ()) -> Result<i32> {
+            use crate::codec::postcard;
+
+            if entry != "add" {
+                return Err(Error::EntryNotFound);
+            }
+            let mut memory = [0u8; 64];
+            let len = self.0.call("rpc_request", &[0, 32], &mut memory)?;
+            let (a, b): (u32, u32) = postcard::from_bytes(&memory[..len as usize])?;
+            let len = postcard::to_slice(&(u64::from(a) + u64::from(b)), &mut memory[32..])?.len();
+            self.0.call("rpc_response", &[32, len as i32], &mut memory)
+        }
+    }
+
+    #[cfg(feature = "rpc")]
+    #[test]
+    fn calls_relay_postcard_payloads() {
+        use crate::codec::postcard;
+        use crate::rpc::Rpc;
+
+        let rpc = Rpc::new();
+        let engine = Adder(crate::abi::Imports::new().with(rpc.clone()));
+        let mut store = MemoryStore::new();
+        store.upsert(2, b"\0asm".to_vec());
+        let mut runtime = Runtime::new(engine, store);
+        let mut server = RemoteServer::new(Loopback::default()).with_rpc(rpc);
+        let request = postcard::to_vec(&(7u32, u32::MAX)).unwrap();
+        let call = |entry, request| Command::Call {
+            module_id: 2,
+            correlation: 11,
+            entry,
+            request,
+        };
+        server.transport().push(1, call("add", &request));
+        server.transport().push(2, call("sub", &request));
+        server
+            .transport()
+            .requests
+            .push_back(vec![9, 3, 2, 0, 0, 0]);
+        while server.poll(&mut runtime, &mut ()).unwrap() {}
+
+        let responses = &server.transport().responses;
+        assert_eq!(&responses[0][..2], [STATUS_OK, 1]);
+        assert_eq!(
+            postcard::from_bytes(&responses[0][2..]),
+            Ok(u64::from(u32::MAX) + 7)
+        );
+        assert_eq!(
+            responses[1..],
+            [vec![STATUS_ENTRY_NOT_FOUND, 2], vec![STATUS_BAD_FRAME, 3]]
+        );
+
+        let mut runtime = Runtime::new(Mains, MemoryStore::new());
+        let mut server = RemoteServer::new(Loopback::default());
+        server.transport().push(4, call("add", &request));
+        while server.poll(&mut runtime, &mut 0).unwrap() {}
+        assert_eq!(server.transport().responses, [vec![STATUS_REFUSED, 4]]);
+    }
+}
@@ -0,0 +1,411 @@
+//! Record/replay of host calls for reproducing field failures.
+//!
+//! Wrap the import sets whose answers depend on the outside world (time,
+//! entropy, sensors, KV state) in a `Recorder`. Every call it forwards is
+//! appended to a shared `Recording`: the arguments, the result and every
+//! byte the host wrote into guest memory. After a failing invocation, take the
+//! `Tape`, `encode` it and ship it off the device.
+//!
+//! On a developer machine, link a `Replayer` for the same function
+//! signatures into `WasmtimeLiteEngine` and run the same module bytes: each
+//! call is answered from the tape instead of the device, so the guest sees
+//! exactly what it saw in the field. A call whose name or arguments differ
+//! from the tape traps with `Error::Engine("replay diverged")`.
+//!
+//! ```ignore
+//! // device
+//! let recording = Recording::new();
+//! engine.set_imports(Imports::new().with(Recorder::new(sys, recording.clone())));
+//! if runtime.execute(id, "main", &mut ()).is_err() {
+//!     upload(&recording.take().encode());
+//! }
+//! // host
+//! let replayer = Replayer::new(abi::sys::FUNCTIONS, Tape::decode(&bytes)?);
+//! engine.set_imports(Imports::new().with(replayer.clone()));
+//! ```
+
+use crate::abi::{GuestMemory, HostContext, HostFn, HostImports};
+use crate::{Error, ModuleId, Result};
+use alloc::rc::Rc;
+use alloc::string::{String, ToString};
+use alloc::vec::Vec;
+use core::cell::RefCell;
+
+/// Leading bytes of an encoded `Tape`.
+pub const TAPE_MAGIC: &[u8; 4] = b"SRPL";
+/// Encoding version written after the magic.
+pub const TAPE_VERSION: u8 = 1;
+
+const FLAG_TRAPPED: u8 = 1;
+
+/// One recorded host call.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Call {
+    /// Module that made the call.
+    pub module_id: ModuleId,
+    pub name: String,
+    pub args: Vec<i32>,
+    /// Returned status, or `None` when the host call trapped.
+    pub result: Option<i32>,
+    /// Bytes the host wrote into guest memory, as `(ptr, bytes)` in order.
+    pub writes: Vec<(u32, Vec<u8>)>,
+}
+
+/// Recorded calls in order, with a compact little-endian encoding.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Tape {
+    pub calls: Vec<Call>,
+}
+
+impl Tape {
+    pub fn encode(&self) -> Vec<u8> {
+        let mut out = Vec::new();
+        out.extend_from_slice(TAPE_MAGIC);
+        out.push(TAPE_VERSION);
+        out.extend_from_slice(&(self.calls.len() as u32).to_le_bytes());
+        for call in &self.calls {
+            out.extend_from_slice(&call.module_id.to_le_bytes());
+            out.push(call.name.len() as u8);
+            out.extend_from_slice(call.name.as_bytes());
+            out.push(if call.result.is_none() {
+                FLAG_TRAPPED
+            } else {
+                0
+            });
+            out.extend_from_slice(&call.result.unwrap_or(0).to_le_bytes());
+            out.push(call.args.len() as u8);
+            for arg in &call.args {
+                out.extend_from_slice(&arg.to_le_bytes());
+            }
+            out.extend_from_slice(&(call.writes.len() as u32).to_le_bytes());
+            for (ptr, bytes) in &call.writes {
+                out.extend_from_slice(&ptr.to_le_bytes());
+                out.extend_from_slice(&(bytes.len() as u32).to_le_bytes());
+                out.extend_from_slice(bytes);
+            }
+        }
+        out
+    }
+
+    pub fn decode(bytes: &[u8]) -> Result<Self> {
+        let mut cursor = Cursor(bytes);
+        if cursor.take(4)? != TAPE_MAGIC {
+            return Err(Error::Engine("replay: bad tape magic"));
+        }
+        if cursor.u8()? != TAPE_VERSION {
+            return Err(Error::Engine("replay: unsupported tape version"));
+        }
+        let count = cursor.u32()?;
+        let mut calls = Vec::new();
+        for _ in 0..count {
+            let module_id = cursor.u32()?;
+            let name_len = cursor.u8()? as usize;
+            let name = core::str::from_utf8(cursor.take(name_len)?)
+                .map_err(|_| Error::Engine("replay: name not utf-8"))?
+                .to_string();
+            let trapped = cursor.u8()? & FLAG_TRAPPED != 0;
+            let result = cursor.u32()? as i32;
+            let args = (0..cursor.u8()?)
+                .map(|_| cursor.u32().map(|arg| arg as i32))
+                .collect::<Result<_>>()?;
+            let writes = (0..cursor.u32()?)
+                .map(|_| {
+                    let ptr = cursor.u32()?;
+                    let len = cursor.u32()? as usize;
+                    Ok((ptr, cursor.take(len)?.to_vec()))
+                })
+                .collect::<Result<_>>()?;
+            calls.push(Call {
+                module_id,
+                name,
+                args,
+                result: (!trapped).then_some(result),
+                writes,
+            });
+        }
+        if !cursor.0.is_empty() {
+            return Err(Error::Engine("replay: trailing bytes"));
+        }
+        Ok(Self { calls })
+    }
+}
+
+struct Cursor<'a>(&'a [u8]);
+
+impl<'a> Cursor<'a> {
+    fn take(&mut self, len: usize) -> Result<&'a [u8]> {
+        if self.0.len() < len {
+            return Err(Error::Engine("replay: truncated tape"));
+        }
+        let (head, rest) = self.0.split_at(len);
+        self.0 = rest;
+        Ok(head)
+    }
+
+    fn u8(&mut self) -> Result<u8> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn u32(&mut self) -> Result<u32> {
+        Ok(u32::from_le_bytes(self.take(4)?.try_into().unwrap()))
+    }
+}
+
+/// Shared handle to the calls a `Recorder` captured.
+#[derive(Clone, Default)]
+pub struct Recording {
+    calls: Rc<RefCell<Vec<Call>>>,
+}
+
+impl Recording {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Number of calls recorded so far.
+    pub fn len(&self) -> usize {
+        self.calls.borrow().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Removes everything recorded so far (e.g. before each invocation).
+    pub fn clear(&self) {
+        self.calls.borrow_mut().clear();
+    }
+
+    /// Returns the recorded calls and starts over.
+    pub fn take(&self) -> Tape {
+        Tape {
+            calls: core::mem::take(&mut *self.calls.borrow_mut()),
+        }
+    }
+}
+
+/// Forwards to `inner` and records every call into a `Recording`.
+pub struct Recorder<H> {
+    inner: H,
+    recording: Recording,
+    module_id: ModuleId,
+}
+
+impl<H: HostImports> Recorder<H> {
+    pub fn new(inner: H, recording: Recording) -> Self {
+        Self {
+            inner,
+            recording,
+            module_id: 0,
+        }
+    }
+
+    pub fn into_inner(self) -> H {
+        self.inner
+    }
+}
+
+/// Guest memory that remembers what the host wrote.
+struct Tap<'a> {
+    memory: &'a mut dyn GuestMemory,
+    writes: Vec<(u32, Vec<u8>)>,
+}
+
+impl GuestMemory for Tap<'_> {
+    fn read(&self, ptr: u32, buf: &mut [u8]) -> Result<()> {
+        self.memory.read(ptr, buf)
+    }
+
+    fn write(&mut self, ptr: u32, data: &[u8]) -> Result<()> {
+        self.memory.write(ptr, data)?;
+        self.writes.push((ptr, data.to_vec()));
+        Ok(())
+    }
+}
+
+impl<H: HostImports> HostImports for Recorder<H> {
+    fn functions(&self) -> &[HostFn] {
+        self.inner.functions()
+    }
+
+    fn call(&mut self, name: &str, args: &[i32], memory: &mut dyn GuestMemory) -> Result<i32> {
+        self.call_with(name, args, memory, &mut HostContext::none())
+    }
+
+    fn call_with(
+        &mut self,
+        name: &str,
+        args: &[i32],
+        memory: &mut dyn GuestMemory,
+        ctx: &mut HostContext<'_>,
+    ) -> Result<i32> {
+        let mut tap = Tap {
+            memory,
+            writes: Vec::new(),
+        };
+        let result = self.inner.call_with(name, args, &mut tap, ctx);
+        self.recording.calls.borrow_mut().push(Call {
+            module_id: self.module_id,
+            name: name.to_string(),
+            args: args.to_vec(),
+            result: result.ok(),
+            writes: tap.writes,
+        });
+        result
+    }
+
+    fn enter(&mut self, module_id: ModuleId) {
+        self.module_id = module_id;
+        self.inner.enter(module_id);
+    }
+
+    fn exit(&mut self) {
+        self.inner.exit();
+    }
+}
+
+struct Playback {
+    tape: Tape,
+    next: usize,
+}
+
+/// Answers host calls from a `Tape`; clones share the position.
+#[derive(Clone)]
+pub struct Replayer {
+    functions: &'static [HostFn],
+    playback: Rc<RefCell<Playback>>,
+}
+
+impl Replayer {
+    /// Replays `tape` for the functions in `functions` (the recorded set's
+    /// `FUNCTIONS`, e.g. `abi::sys::FUNCTIONS`).
+    pub fn new(functions: &'static [HostFn], tape: Tape) -> Self {
+        Self {
+            functions,
+            playback: Rc::new(RefCell::new(Playback { tape, next: 0 })),
+        }
+    }
+
+    /// Calls on the tape not yet replayed; non-zero after a run means the
+    /// guest stopped earlier than in the field.
+    pub fn remaining(&self) -> usize {
+        let playback = self.playback.borrow();
+        playback.tape.calls.len() - playback.next
+    }
+}
+
+impl HostImports for Replayer {
+    fn functions(&self) -> &[HostFn] {
+        self.functions
+    }
+
+    fn call(&mut self, name: &str, args: &[i32], memory: &mut dyn GuestMemory) -> Result<i32> {
+        let mut playback = self.playback.borrow_mut();
+        let next = playback.next;
+        let call = playback
+            .tape
+            .calls
+            .get(next)
+            .ok_or(Error::Engine("replay: tape exhausted"))?;
+        if call.name != name || call.args != args {
+            return Err(Error::Engine("replay diverged"));
+        }
+        for (ptr, bytes) in &call.writes {
+            memory.write(*ptr, bytes)?;
+        }
+        let result = call
+            .result
+            .ok_or(Error::Engine("replay: recorded host trap"));
+        playback.next += 1;
+        result
+    }
+}
+
+#[cfg(all(test, feature = "std"))]
+mod tests {
+    use super::*;
+    use crate::abi::sys::{SysImports, FUNCTIONS};
+    use crate::abi::OK;
+    use crate::schedule::Clock;
+    use std::cell::Cell;
+
+    struct Ticking(Cell<u64>);
+
+    impl Clock for Ticking {
+        fn now_ms(&self) -> u64 {
+            self.0.replace(self.0.get() + 1)
+        }
+    }
+
+    #[test]
+    fn records_and_replays_memory_writes() {
+        let recording = Recording::new();
+        let sys = SysImports::new(Ticking(Cell::new(1_000)), |buf: &mut [u8]| buf.fill(9));
+        let mut recorder = Recorder::new(sys, recording.clone());
+        recorder.enter(4);
+        let mut field = [0u8; 16];
+        assert_eq!(recorder.call("time_ms", &[0], &mut field), Ok(OK));
+        assert_eq!(recorder.call("random", &[8, 2], &mut field), Ok(OK));
+        assert!(recorder.call("nope", &[], &mut field).is_err());
+
+        let bytes = recording.take().encode();
+        assert!(recording.is_empty());
+        let tape = Tape::decode(&bytes).unwrap();
+        assert_eq!(tape.calls.len(), 3);
+        assert_eq!(tape.calls[1].module_id, 4);
+        assert_eq!(
+            Tape::decode(&bytes[..bytes.len() - 1]).err(),
+            Some(Error::Engine("replay: truncated tape"))
+        );
+
+        let replayer = Replayer::new(FUNCTIONS, tape);
+        let mut imports = crate::abi::Imports::new().with(replayer.clone());
+        let mut host = [0u8; 16];
+        assert_eq!(imports.call("time_ms", &[0], &mut host), Ok(OK));
+        assert_eq!(imports.call("random", &[8, 2], &mut host), Ok(OK));
+        assert_eq!(host, field);
+        assert_eq!(replayer.remaining(), 1);
+
+        let mut diverged = Replayer::new(FUNCTIONS, Tape::decode(&bytes).unwrap());
+        assert_eq!(
+            diverged.call("time_ms", &[4], &mut host),
+            Err(Error::Engine("replay diverged"))
+        );
+    }
+
+    #[cfg(feature = "engine-wasmtime-lite")]
+    #[test]
+    fn replays_field_invocation_on_wasmtime() {
+        use crate::abi::Imports;
+        use crate::engines::wasmtime_lite::WasmtimeLiteEngine;
+        use crate::Engine;
+
+        // Returns the low byte of the time plus the first random byte.
+        let wasm = wat::parse_str(
+            r#"(module
+                (import "env" "time_ms" (func $time (param i32) (result i32)))
+                (import "env" "random" (func $random (param i32 i32) (result i32)))
+                (memory (export "memory") 1)
+                (func (export "main") (result i32)
+                    (drop (call $time (i32.const 0)))
+                    (drop (call $random (i32.const 8) (i32.const 1)))
+                    (i32.add (i32.load8_u (i32.const 0)) (i32.load8_u (i32.const 8)))))"#,
+        )
+        .unwrap();
+
+        let recording = Recording::new();
+        let sys = SysImports::new(Ticking(Cell::new(40)), |buf: &mut [u8]| buf.fill(2));
+        let mut field = WasmtimeLiteEngine::new().unwrap();
+        field.set_imports(Imports::new().with(Recorder::new(sys, recording.clone())));
+        let handle = field.load(1, &wasm).unwrap();
+        assert_eq!(field.invoke_status(handle, "main", &mut ()), Ok(42));
+        let bytes = recording.take().encode();
+
+        let replayer = Replayer::new(FUNCTIONS, Tape::decode(&bytes).unwrap());
+        let mut host = WasmtimeLiteEngine::new().unwrap();
+        host.set_imports(Imports::new().with(replayer.clone()));
+        let handle = host.load(1, &wasm).unwrap();
+        assert_eq!(host.invoke_status(handle, "main", &mut ()), Ok(42));
+        assert_eq!(replayer.remaining(), 0);
+    }
+}
@@ -0,0 +1,630 @@
+//! Signed revocation lists (`revocation` feature).
+//!
+//! A revocation list names compromised modules by the BLAKE3 digest of their
+//! module bytes (what follows the manifest header) and compromised signing
+//! keys by `KeyId`. Lists arrive over OTA like modules; `Revocations::apply`
+//! checks the signature against the list signers the firmware trusts, refuses
+//! a sequence number that is not newer than the installed list (so an old
+//! list cannot be replayed to lift a revocation), persists it through a
+//! `RevocationStore` and installs it.
+//!
+//! The runtime refuses to load a revoked module (`Error::Revoked`), one whose
+//! manifest signature verifies under a revoked key among the module signers
+//! (`Revocations::set_module_signers`; `Runtime::set_keyring` fills them
+//! with the keyring's keys), and with `cert-chain` one whose manifest
+//! carries a revoked signer certificate. The verdict is cached per module
+//! under the BLAKE3 digest of its stored bytes until a newer list or other
+//! module signers arrive, so a module that runs again costs one hash rather
+//! than a signature check per revoked signer.
+//! Audits report revoked modules as `audit::Issue::Revoked` and no longer
+//! trust revoked keys (`audit::Keyring::with_revocations`; `Runtime::audit`
+//! uses the installed list when the keyring carries none).
+//!
+//! Layout (little-endian):
+//! - magic: 4 bytes = b"SMNR"
+//! - version: u8 = 1
+//! - sig_alg: u8 (`manifest::SIG_ALG_*`)
+//! - sequence: u32, increasing with every list issued
+//! - digest_count, key_count: u16 each
+//! - digests: [u8; 32] × digest_count
+//! - key ids: [u8; 8] × key_count
+//! - signature: [u8; 64] over the bytes before it
+
+use crate::kv::KvStore;
+use crate::manifest::{ct_eq, Manifest, MANIFEST_MAGIC};
+use crate::verify::{self, Algorithm, PublicKey, Verifier, SIGNATURE_LEN};
+use crate::{Engine, Error, ModuleId, ModuleSource, Result, Runtime};
+use alloc::boxed::Box;
+use alloc::rc::Rc;
+use alloc::vec::Vec;
+use core::fmt;
+
+/// Revocation list magic marker.
+pub const REVOCATION_MAGIC: &[u8; 4] = b"SMNR";
+/// Revocation list layout version.
+pub const REVOCATION_VERSION: u8 = 1;
+/// Namespace `KvRevocations` writes under; reserved (`kv::RESERVED_NAMESPACES`),
+/// so guests cannot reach it.
+pub const REVOCATION_NAMESPACE: ModuleId = ModuleId::MAX - 4;
+const _: () = assert!(crate::kv::is_reserved(REVOCATION_NAMESPACE));
+
+/// BLAKE3 digest of a module's bytes.
+pub type Digest = [u8; 32];
+/// First 8 bytes of the BLAKE3 digest of a public key's encoding, as
+/// `packer` prints it.
+pub type KeyId = [u8; 8];
+
+const HEADER_LEN: usize = 4 + 1 + 1 + 4 + 2 + 2;
+const KV_KEY: &[u8] = b"list";
+
+/// `KeyId` of `key`.
+pub fn key_id(key: &PublicKey) -> KeyId {
+    let digest = blake3::hash(key.as_bytes());
+    digest.as_bytes()[..8].try_into().unwrap()
+}
+
+/// A parsed revocation list; the signature is checked by `verify`.
+#[derive(Clone, PartialEq, Eq)]
+pub struct RevocationList {
+    bytes: Vec<u8>,
+    algorithm: Algorithm,
+    sequence: u32,
+    digests: usize,
+    keys: usize,
+}
+
+impl fmt::Debug for RevocationList {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("RevocationList")
+            .field("sequence", &self.sequence)
+            .field("digests", &self.digests)
+            .field("keys", &self.keys)
+            .finish()
+    }
+}
+
+impl RevocationList {
+    /// Parses a list of exactly `bytes`.
+    pub fn parse(bytes: Vec<u8>) -> Result<Self> {
+        let malformed = Error::Engine("revocation list malformed");
+        let header = bytes.get(..HEADER_LEN).ok_or(malformed)?;
+        if &header[..4] != REVOCATION_MAGIC {
+            return Err(Error::Engine("bad revocation list magic"));
+        }
+        if header[4] != REVOCATION_VERSION {
+            return Err(Error::Engine("unsupported revocation list version"));
+        }
+        let algorithm = Algorithm::from_id(header[5]).ok_or(malformed)?;
+        let sequence = u32::from_le_bytes(header[6..10].try_into().unwrap());
+        let digests = u16::from_le_bytes([header[10], header[11]]) as usize;
+        let keys = u16::from_le_bytes([header[12], header[13]]) as usize;
+        if bytes.len() != HEADER_LEN + digests * 32 + keys * 8 + SIGNATURE_LEN {
+            return Err(malformed);
+        }
+        Ok(Self {
+            bytes,
+            algorithm,
+            sequence,
+            digests,
+            keys,
+        })
+    }
+
+    /// Increases with every list issued.
+    pub fn sequence(&self) -> u32 {
+        self.sequence
+    }
+
+    pub fn algorithm(&self) -> Algorithm {
+        self.algorithm
+    }
+
+    /// The encoded list, as `parse` took it.
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.bytes
+    }
+
+    /// Revoked module digests.
+    pub fn digests(&self) -> impl Iterator<Item = &Digest> {
+        self.bytes[HEADER_LEN..self.keys_start()]
+            .chunks_exact(32)
+            .map(|digest| digest.try_into().unwrap())
+    }
+
+    /// Revoked signing keys.
+    pub fn key_ids(&self) -> impl Iterator<Item = &KeyId> {
+        self.bytes[self.keys_start()..self.signed_len()]
+            .chunks_exact(8)
+            .map(|id| id.try_into().unwrap())
+    }
+
+    /// Whether the module whose bytes hash to `digest` is revoked.
+    pub fn is_digest_revoked(&self, digest: &Digest) -> bool {
+        self.digests().any(|revoked| ct_eq(revoked, digest))
+    }
+
+    /// Whether `module` (wasm or AOT bytes, without a manifest) is revoked.
+    pub fn is_module_revoked(&self, module: &[u8]) -> bool {
+        self.digests != 0 && self.is_digest_revoked(blake3::hash(module).as_bytes())
+    }
+
+    /// Whether signatures by `key` are no longer trusted.
+    pub fn is_key_revoked(&self, key: &PublicKey) -> bool {
+        self.keys != 0 && {
+            let id = key_id(key);
+            self.key_ids().any(|revoked| ct_eq(revoked, &id))
+        }
+    }
+
+    /// Checks that one of `signers` signed the list.
+    pub fn verify(&self, verifier: &dyn Verifier, signers: &[PublicKey]) -> Result<()> {
+        let (signed, signature) = self.bytes.split_at(self.signed_len());
+        let signature = signature.try_into().unwrap();
+        let signed = signers
+            .iter()
+            .filter(|signer| signer.algorithm() == self.algorithm)
+            .any(|signer| signer.verify(verifier, &[signed], signature).is_ok());
+        if signed {
+            Ok(())
+        } else {
+            Err(Error::Engine("revocation list not from a trusted signer"))
+        }
+    }
+
+    fn keys_start(&self) -> usize {
+        HEADER_LEN + self.digests * 32
+    }
+
+    fn signed_len(&self) -> usize {
+        self.bytes.len() - SIGNATURE_LEN
+    }
+}
+
+/// Bytes a list signer signs; append the signature to get the list.
+pub fn signing_preimage(
+    sequence: u32,
+    algorithm: Algorithm,
+    digests: &[Digest],
+    key_ids: &[KeyId],
+) -> Result<Vec<u8>> {
+    let too_many = Error::Engine("revocation list too long");
+    let digest_count = u16::try_from(digests.len()).map_err(|_| too_many)?;
+    let key_count = u16::try_from(key_ids.len()).map_err(|_| too_many)?;
+    let mut out =
+        Vec::with_capacity(HEADER_LEN + digests.len() * 32 + key_ids.len() * 8 + SIGNATURE_LEN);
+    out.extend_from_slice(REVOCATION_MAGIC);
+    out.push(REVOCATION_VERSION);
+    out.push(algorithm.id());
+    out.extend_from_slice(&sequence.to_le_bytes());
+    out.extend_from_slice(&digest_count.to_le_bytes());
+    out.extend_from_slice(&key_count.to_le_bytes());
+    digests
+        .iter()
+        .for_each(|digest| out.extend_from_slice(digest));
+    key_ids.iter().for_each(|id| out.extend_from_slice(id));
+    Ok(out)
+}
+
+/// Keeps the latest list across reboots.
+pub trait RevocationStore {
+    /// The list last saved, if any.
+    fn load(&self) -> Result<Option<Vec<u8>>>;
+
+    /// Replaces the saved list.
+    fn save(&mut self, list: &[u8]) -> Result<()>;
+}
+
+/// List persisted in a `KvStore` (NVS, flash) under `REVOCATION_NAMESPACE`.
+#[derive(Debug, Default, Clone)]
+pub struct KvRevocations<K> {
+    store: K,
+}
+
+impl<K: KvStore> KvRevocations<K> {
+    /// Wraps a store.
+    pub fn new(store: K) -> Self {
+        Self { store }
+    }
+
+    /// Underlying store.
+    pub fn into_inner(self) -> K {
+        self.store
+    }
+}
+
+impl<K: KvStore> RevocationStore for KvRevocations<K> {
+    fn load(&self) -> Result<Option<Vec<u8>>> {
+        let Some(len) = self.store.get(REVOCATION_NAMESPACE, KV_KEY, &mut [])? else {
+            return Ok(None);
+        };
+        let mut list = alloc::vec![0; len];
+        self.store.get(REVOCATION_NAMESPACE, KV_KEY, &mut list)?;
+        Ok(Some(list))
+    }
+
+    fn save(&mut self, list: &[u8]) -> Result<()> {
+        self.store.set(REVOCATION_NAMESPACE, KV_KEY, list)
+    }
+}
+
+/// The installed list, the keys allowed to sign lists and where lists are
+/// persisted.
+#[derive(Default)]
+pub struct Revocations {
+    signers: Vec<PublicKey>,
+    current: Option<Rc<RevocationList>>,
+    /// Keys module manifests are signed with.
+    module_signers: Vec<PublicKey>,
+    store: Option<Box<dyn RevocationStore>>,
+    /// `None` uses `verify::default_verifier`.
+    verifier: Option<Rc<dyn Verifier>>,
+    /// `check` results under `current` and `module_signers`, ascending by id.
+    verdicts: Vec<(ModuleId, Verdict)>,
+}
+
+/// Whether the stored bytes hashing to `digest` are revoked.
+#[derive(Debug, Clone, Copy)]
+struct Verdict {
+    digest: Digest,
+    revoked: bool,
+}
+
+impl fmt::Debug for Revocations {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Revocations")
+            .field("signers", &self.signers)
+            .field("current", &self.current)
+            .field("module_signers", &self.module_signers)
+            .field("persisted", &self.store.is_some())
+            .field("verdicts", &self.verdicts.len())
+            .finish()
+    }
+}
+
+impl Revocations {
+    /// No list, no signers: every list is refused.
+    pub const fn new() -> Self {
+        Self {
+            signers: Vec::new(),
+            current: None,
+            module_signers: Vec::new(),
+            store: None,
+            verifier: None,
+            verdicts: Vec::new(),
+        }
+    }
+
+    /// Trusts lists signed by `key`.
+    pub fn add_signer(&mut self, key: PublicKey) {
+        self.signers.push(key);
+    }
+
+    /// Keys module manifests may be signed with; `check` refuses a manifest
+    /// whose signature verifies under one the list revokes.
+    pub fn set_module_signers(&mut self, keys: Vec<PublicKey>) {
+        self.module_signers = keys;
+        self.verdicts.clear();
+    }
+
+    /// Checks list signatures with `verifier` instead of the software default.
+    pub fn set_verifier(&mut self, verifier: impl Verifier + 'static) {
+        self.verifier = Some(Rc::new(verifier));
+    }
+
+    /// Persists every list applied from now on to `store`, and installs the
+    /// one it holds (call after `add_signer`). A saved list that no longer
+    /// verifies is reported; `store` is kept either way.
+    pub fn persist_to(&mut self, store: impl RevocationStore + 'static) -> Result<()> {
+        let saved = store.load();
+        self.store = Some(Box::new(store));
+        match saved? {
+            Some(list) => {
+                self.install(self.accept(list)?);
+                Ok(())
+            }
+            None => Ok(()),
+        }
+    }
+
+    /// Verifies a newly received list, persists it and installs it; returns
+    /// its sequence number. Lists not newer than the installed one are
+    /// refused, and a list that could not be saved is not installed, so the
+    /// next boot does not lose a revocation the runtime already applied.
+    pub fn apply(&mut self, list: Vec<u8>) -> Result<u32> {
+        let list = self.accept(list)?;
+        if let Some(store) = &mut self.store {
+            store.save(list.as_bytes())?;
+        }
+        let sequence = list.sequence();
+        self.install(list);
+        Ok(sequence)
+    }
+
+    /// The installed list.
+    pub fn current(&self) -> Option<&Rc<RevocationList>> {
+        self.current.as_ref()
+    }
+
+    /// Fails with `Error::Revoked` when `stored` (a raw module or a manifest
+    /// blob, the bytes of `module_id`) is revoked by digest, by a revoked
+    /// module signer whose key verifies its signature or, with `cert-chain`,
+    /// by the signer certificate its manifest carries.
+    pub fn check(&mut self, module_id: ModuleId, stored: &[u8]) -> Result<()> {
+        let Some(list) = &self.current else {
+            return Ok(());
+        };
+        let digest = *blake3::hash(stored).as_bytes();
+        let slot = self
+            .verdicts
+            .binary_search_by_key(&module_id, |(id, _)| *id);
+        let revoked = match slot {
+            Ok(at) if ct_eq(&self.verdicts[at].1.digest, &digest) => self.verdicts[at].1.revoked,
+            _ => {
+                let revoked = self.is_revoked(list, stored, &digest);
+                let verdict = Verdict { digest, revoked };
+                match slot {
+                    Ok(at) => self.verdicts[at].1 = verdict,
+                    Err(at) => self.verdicts.insert(at, (module_id, verdict)),
+                }
+                revoked
+            }
+        };
+        if revoked {
+            Err(Error::Revoked)
+        } else {
+            Ok(())
+        }
+    }
+
+    /// The uncached `check`; `digest` is the BLAKE3 digest of `stored`.
+    fn is_revoked(&self, list: &RevocationList, stored: &[u8], digest: &Digest) -> bool {
+        if !stored.starts_with(MANIFEST_MAGIC) {
+            return list.is_digest_revoked(digest);
+        }
+        // Unreadable manifests are refused by the load itself.
+        let Ok((manifest, module)) = Manifest::parse(stored) else {
+            return false;
+        };
+        if list.keys != 0 && self.signed_by_revoked_key(&manifest, module, list) {
+            return true;
+        }
+        #[cfg(feature = "cert-chain")]
+        if list.keys != 0 && revoked_signer(&manifest, list) {
+            return true;
+        }
+        list.is_module_revoked(module)
+    }
+
+    /// Whether the manifest's signature verifies under a module signer the
+    /// list revokes. Only revoked keys are tried, so a list without module
+    /// signers on it costs no verification.
+    fn signed_by_revoked_key(
+        &self,
+        manifest: &Manifest<'_>,
+        module: &[u8],
+        list: &RevocationList,
+    ) -> bool {
+        let Some(verifier) = self.verifier().filter(|_| manifest.signature.is_some()) else {
+            return false;
+        };
+        self.module_signers
+            .iter()
+            .filter(|key| list.is_key_revoked(key))
+            .any(|key| crate::manifest::verify_key(verifier, manifest, module, key).is_ok())
+    }
+
+    fn verifier(&self) -> Option<&dyn Verifier> {
+        match &self.verifier {
+            Some(verifier) => Some(&**verifier),
+            None => verify::default_verifier(),
+        }
+    }
+
+    /// Parses `bytes` and checks the list may replace the installed one.
+    fn accept(&self, bytes: Vec<u8>) -> Result<RevocationList> {
+        let list = RevocationList::parse(bytes)?;
+        let verifier = self.verifier().ok_or(Error::Unsupported)?;
+        list.verify(verifier, &self.signers)?;
+        if let Some(current) = &self.current {
+            if list.sequence() <= current.sequence() {
+                return Err(Error::Engine("revocation list is not newer"));
+            }
+        }
+        Ok(list)
+    }
+
+    fn install(&mut self, list: RevocationList) {
+        self.current = Some(Rc::new(list));
+        self.verdicts.clear();
+    }
+}
+
+/// Whether a signer certificate the manifest carries has a revoked subject.
+#[cfg(feature = "cert-chain")]
+fn revoked_signer(manifest: &Manifest<'_>, list: &RevocationList) -> bool {
+    manifest
+        .extensions()
+        .filter(|(tag, _)| *tag == crate::manifest::EXT_SIGNER_CERT)
+        .filter_map(|(_, cert)| crate::cert::Certificate::parse(cert).ok())
+        .any(|cert| list.is_key_revoked(&cert.subject))
+}
+
+impl<E: Engine, S: ModuleSource> Runtime<E, S> {
+    /// Revocation list checked before modules load, e.g. to apply one
+    /// received over OTA.
+    pub fn revocations(&mut self) -> &mut Revocations {
+        &mut self.revocations
+    }
+}
+
+#[cfg(all(test, feature = "std", feature = "verify-ed25519"))]
+mod tests {
+    use super::*;
+    use crate::kv::MemoryKv;
+    use crate::manifest;
+    use core::cell::RefCell;
+    use ed25519_dalek::{Signer, SigningKey};
+
+    const WASM: &[u8] = b"\0asm\x01\0\0\0";
+
+    fn list(signer: &SigningKey, sequence: u32, digests: &[Digest], keys: &[KeyId]) -> Vec<u8> {
+        let mut list = signing_preimage(sequence, Algorithm::Ed25519, digests, keys).unwrap();
+        list.extend_from_slice(&signer.sign(&list).to_bytes());
+        list
+    }
+
+    #[test]
+    fn signed_lists_revoke_modules_and_keys() {
+        let signer = SigningKey::from_bytes(&[1; 32]);
+        let module_key =
+            PublicKey::Ed25519(SigningKey::from_bytes(&[2; 32]).verifying_key().to_bytes());
+        let mut revocations = Revocations::new();
+        revocations.add_signer(PublicKey::Ed25519(signer.verifying_key().to_bytes()));
+        let kv = Rc::new(RefCell::new(MemoryKv::new()));
+        revocations
+            .persist_to(KvRevocations::new(kv.clone()))
+            .unwrap();
+
+        let blob = manifest::encode(1, "main", WASM, 0, 1, None).unwrap();
+        assert_eq!(revocations.check(1, &blob), Ok(()));
+        let digest = *blake3::hash(WASM).as_bytes();
+        assert_eq!(
+            revocations.apply(list(&signer, 2, &[digest], &[key_id(&module_key)])),
+            Ok(2)
+        );
+        assert_eq!(revocations.check(1, &blob), Err(Error::Revoked));
+        assert_eq!(revocations.check(1, WASM), Err(Error::Revoked));
+        assert!(revocations.current().unwrap().is_key_revoked(&module_key));
+
+        // So is a manifest whose signature verifies under a revoked module
+        // signer, once the signers are known.
+        let module = b"\0asm\x01\0\0\x02";
+        let preimage = manifest::signing_preimage(3, "main", module, 0, 1).unwrap();
+        let sig = SigningKey::from_bytes(&[2; 32]).sign(&preimage).to_bytes();
+        let by_revoked = manifest::encode(3, "main", module, 0, 1, Some(sig)).unwrap();
+        assert_eq!(revocations.check(3, &by_revoked), Ok(()));
+        let other_key =
+            PublicKey::Ed25519(SigningKey::from_bytes(&[4; 32]).verifying_key().to_bytes());
+        revocations.set_module_signers(alloc::vec![other_key, module_key]);
+        assert_eq!(revocations.check(3, &by_revoked), Err(Error::Revoked));
+        let preimage = manifest::signing_preimage(3, "main", module, 0, 1).unwrap();
+        let sig = SigningKey::from_bytes(&[4; 32]).sign(&preimage).to_bytes();
+        let by_other = manifest::encode(3, "main", module, 0, 1, Some(sig)).unwrap();
+        assert_eq!(revocations.check(3, &by_other), Ok(()));
+        #[cfg(feature = "unstable")]
+        {
+            use crate::audit::{check, Issue, Keyring};
+            let list = revocations.current().unwrap().clone();
+            let keyring = Keyring::new().with_revocations(list.clone());
+            assert_eq!(check(1, &blob, &keyring), Err(Issue::Revoked));
+
+            // Signatures by a revoked key are no longer trusted.
+            let module = b"\0asm\x01\0\0\x01";
+            let preimage = manifest::signing_preimage(2, "main", module, 0, 1).unwrap();
+            let sig = SigningKey::from_bytes(&[2; 32]).sign(&preimage).to_bytes();
+            let signed = manifest::encode(2, "main", module, 0, 1, Some(sig)).unwrap();
+            let keyring = Keyring::new()
+                .with_key(*module_key.as_bytes().first_chunk().unwrap())
+                .require_signatures();
+            assert_eq!(check(2, &signed, &keyring), Ok(()));
+            assert_eq!(
+                check(2, &signed, &keyring.with_revocations(list)),
+                Err(Issue::Untrusted)
+            );
+        }
+
+        // Replays, forgeries and damaged lists leave the installed one.
+        assert!(revocations.apply(list(&signer, 2, &[], &[])).is_err());
+        let forger = SigningKey::from_bytes(&[3; 32]);
+        assert!(revocations.apply(list(&forger, 3, &[], &[])).is_err());
+        let mut damaged = list(&signer, 3, &[], &[]);
+        damaged[6] ^= 1;
+        assert!(revocations.apply(damaged).is_err());
+        assert_eq!(revocations.current().unwrap().sequence(), 2);
+
+        // A reboot picks the saved list up again.
+        let mut rebooted = Revocations::new();
+        rebooted.add_signer(PublicKey::Ed25519(signer.verifying_key().to_bytes()));
+        rebooted.persist_to(KvRevocations::new(kv)).unwrap();
+        assert_eq!(rebooted.check(1, WASM), Err(Error::Revoked));
+        assert_eq!(rebooted.apply(list(&signer, 3, &[], &[])), Ok(3));
+        assert_eq!(rebooted.check(1, WASM), Ok(()));
+    }
+
+    /// Software verifier counting the checks it runs.
+    struct Counting(Rc<core::cell::Cell<u32>>);
+
+    impl Verifier for Counting {
+        fn verify_ed25519(
+            &self,
+            pubkey: &[u8; crate::verify::PUBLIC_KEY_LEN],
+            message: &[&[u8]],
+            signature: &[u8; SIGNATURE_LEN],
+        ) -> Result<()> {
+            self.0.set(self.0.get() + 1);
+            verify::SoftwareVerifier.verify_ed25519(pubkey, message, signature)
+        }
+    }
+
+    #[test]
+    fn verdicts_are_cached_until_the_list_or_signers_change() {
+        let signer = SigningKey::from_bytes(&[1; 32]);
+        let module_key = SigningKey::from_bytes(&[2; 32]);
+        let module_key = PublicKey::Ed25519(module_key.verifying_key().to_bytes());
+        let checks = Rc::new(core::cell::Cell::new(0));
+        let mut revocations = Revocations::new();
+        revocations.set_verifier(Counting(checks.clone()));
+        revocations.add_signer(PublicKey::Ed25519(signer.verifying_key().to_bytes()));
+        revocations.set_module_signers(alloc::vec![module_key]);
+        let revoke_key = list(&signer, 1, &[], &[key_id(&module_key)]);
+        revocations.apply(revoke_key).unwrap();
+
+        // Signed by another key: only tried against the revoked one, once.
+        let preimage = manifest::signing_preimage(3, "main", WASM, 0, 1).unwrap();
+        let sig = SigningKey::from_bytes(&[4; 32]).sign(&preimage).to_bytes();
+        let blob = manifest::encode(3, "main", WASM, 0, 1, Some(sig)).unwrap();
+        checks.set(0);
+        assert_eq!(revocations.check(3, &blob), Ok(()));
+        assert_eq!(revocations.check(3, &blob), Ok(()));
+        assert_eq!(checks.get(), 1);
+
+        // Other bytes, signers or a newer list are checked again.
+        let digest = *blake3::hash(WASM).as_bytes();
+        assert_eq!(revocations.check(3, WASM), Ok(()));
+        revocations.set_module_signers(alloc::vec![module_key]);
+        assert_eq!(revocations.check(3, &blob), Ok(()));
+        assert_eq!(checks.get(), 2);
+        let revoke_module = list(&signer, 2, &[digest], &[key_id(&module_key)]);
+        revocations.apply(revoke_module).unwrap();
+        assert_eq!(revocations.check(3, &blob), Err(Error::Revoked));
+        assert_eq!(revocations.check(3, &blob), Err(Error::Revoked));
+        assert_eq!(checks.get(), 4);
+    }
+
+    struct Full;
+
+    impl RevocationStore for Full {
+        fn load(&self) -> Result<Option<Vec<u8>>> {
+            Ok(None)
+        }
+
+        fn save(&mut self, _list: &[u8]) -> Result<()> {
+            Err(Error::Engine("store: write failed"))
+        }
+    }
+
+    #[test]
+    fn lists_that_cannot_be_saved_are_not_installed() {
+        let signer = SigningKey::from_bytes(&[1; 32]);
+        let mut revocations = Revocations::new();
+        revocations.add_signer(PublicKey::Ed25519(signer.verifying_key().to_bytes()));
+        revocations.persist_to(Full).unwrap();
+        let digest = *blake3::hash(WASM).as_bytes();
+        assert_eq!(
+            revocations.apply(list(&signer, 1, &[digest], &[])),
+            Err(Error::Engine("store: write failed"))
+        );
+        assert!(revocations.current().is_none());
+        assert_eq!(revocations.check(1, WASM), Ok(()));
+    }
+}
@@ -0,0 +1,348 @@
+//! Module-to-module calls routed by the host.
+//!
+//! A guest runs another module's export by name, so OTA modules compose
+//! without host plumbing for each pair. `Router` is a cheap handle over the
+//! runtime callees run on: register a clone as a `HostImports` set with the
+//! callers' engine and another with the callee runtime's engine, so callees
+//! can read their payload (and call on in turn).
+//!
+//! Guest imports (module `env`, all `i32`):
+//! - `call(name_ptr, name_len, entry_ptr, entry_len, data_ptr, data_len) -> status`
+//!   – runs `entry` of the module registered under manifest name `name` with
+//!   `data` as its payload and returns the entry's status (0 for entries
+//!   without a result).
+//! - `call_payload(ptr, cap) -> len | status` – in the callee: the payload's full
+//!   length, copied when it fits in `cap`; `E_EMPTY` outside a routed call.
+//!
+//! The caller needs a `Capability::Call { callee }` grant, otherwise `call`
+//! returns `E_DENIED`. An unknown name or bad argument is `E_INVALID`; a callee
+//! that fails (trap, missing entry, refused by its runtime's nesting policy or
+//! already running) is `E_DEVICE`.
+//!
+//! An engine is never re-entered, so callees run on another runtime than
+//! their callers (see `nest`); routing to the runtime that is running the
+//! caller always fails.
+
+use crate::abi::caps::{Capability, CapabilityPolicy};
+use crate::abi::{guest_bytes, guest_str, GuestMemory, HostContext, HostFn, HostImports};
+use crate::abi::{E_DENIED, E_DEVICE, E_EMPTY, E_INVALID};
+use crate::{nest, Engine, Error, ModuleId, ModuleSource, Result, Runtime};
+use alloc::rc::Rc;
+use alloc::vec::Vec;
+use core::cell::RefCell;
+use core::str;
+
+/// Longest module name or entry name in bytes.
+pub const MAX_NAME_LEN: usize = 32;
+/// Largest payload in bytes.
+pub const MAX_PAYLOAD_LEN: usize = 256;
+
+/// Host functions provided by `Router`.
+pub const FUNCTIONS: &[HostFn] = &[HostFn::new("call", 6), HostFn::new("call_payload", 2)];
+
+struct State {
+    policy: CapabilityPolicy,
+    /// Modules entered and not yet exited, innermost last.
+    active: Vec<ModuleId>,
+    /// Payloads of the routed calls in progress, innermost last.
+    payloads: Vec<Vec<u8>>,
+}
+
+/// Shared handle routing `call` to a callee runtime.
+pub struct Router<E: Engine, S: ModuleSource> {
+    runtime: Rc<RefCell<Runtime<E, S>>>,
+    state: Rc<RefCell<State>>,
+}
+
+impl<E: Engine, S: ModuleSource> Clone for Router<E, S> {
+    fn clone(&self) -> Self {
+        Self {
+            runtime: self.runtime.clone(),
+            state: self.state.clone(),
+        }
+    }
+}
+
+impl<E, S> Router<E, S>
+where
+    E: Engine,
+    E::Context: Default + 'static,
+    S: ModuleSource,
+{
+    /// Routes to modules on `runtime`; grants nothing.
+    pub fn new(runtime: Rc<RefCell<Runtime<E, S>>>) -> Self {
+        Self {
+            runtime,
+            state: Rc::new(RefCell::new(State {
+                policy: CapabilityPolicy::new(),
+                active: Vec::new(),
+                payloads: Vec::new(),
+            })),
+        }
+    }
+
+    /// Sets the capability policy gating calls (builder style).
+    pub fn with_policy(self, policy: CapabilityPolicy) -> Self {
+        self.state.borrow_mut().policy = policy;
+        self
+    }
+
+    /// Lets `caller` call `callee`.
+    pub fn grant(&self, caller: ModuleId, callee: ModuleId) {
+        let mut state = self.state.borrow_mut();
+        state.policy.grant(caller, Capability::Call { callee });
+    }
+
+    /// Removes every grant held by `caller`.
+    pub fn revoke_all(&self, caller: ModuleId) {
+        self.state.borrow_mut().policy.revoke_all(caller);
+    }
+
+    /// Runtime callees run on.
+    pub fn runtime(&self) -> &Rc<RefCell<Runtime<E, S>>> {
+        &self.runtime
+    }
+
+    /// Runs `entry` of `callee`, with `payload` readable through
+    /// `call_payload`.
+    fn route(
+        &self,
+        callee: ModuleId,
+        entry: &str,
+        payload: &[u8],
+        ctx: &mut E::Context,
+    ) -> Result<i32> {
+        let mut runtime = nest::borrow_mut(&self.runtime)?;
+        self.state.borrow_mut().payloads.push(payload.to_vec());
+        let result = runtime.execute_status(callee, entry, ctx);
+        self.state.borrow_mut().payloads.pop();
+        result
+    }
+
+    fn guest_call(
+        &self,
+        args: [i32; 6],
+        memory: &dyn GuestMemory,
+        ctx: &mut HostContext<'_>,
+    ) -> Result<i32> {
+        let [name_ptr, name_len, entry_ptr, entry_len, data_ptr, data_len] = args;
+        let caller = self
+            .state
+            .borrow()
+            .active
+            .last()
+            .copied()
+            .ok_or(Error::Engine("route: no active module"))?;
+        let mut name = [0u8; MAX_NAME_LEN];
+        let mut entry = [0u8; MAX_NAME_LEN];
+        let mut data = [0u8; MAX_PAYLOAD_LEN];
+        let (Some(name), Some(entry), Some(data)) = (
+            guest_str(memory, name_ptr, name_len, &mut name),
+            guest_str(memory, entry_ptr, entry_len, &mut entry),
+            guest_bytes(memory, data_ptr, data_len, &mut data),
+        ) else {
+            return Ok(E_INVALID);
+        };
+        let callee = match nest::borrow_mut(&self.runtime) {
+            Ok(mut runtime) => runtime.registry().find(name).map(|m| m.module_id),
+            Err(_) => return Ok(E_DEVICE),
+        };
+        let Some(callee) = callee else {
+            return Ok(E_INVALID);
+        };
+        if !self
+            .state
+            .borrow()
+            .policy
+            .allows(caller, Capability::Call { callee })
+        {
+            return Ok(E_DENIED);
+        }
+        let result = match ctx.get::<E::Context>() {
+            Some(ctx) => self.route(callee, entry, data, ctx),
+            None => self.route(callee, entry, data, &mut E::Context::default()),
+        };
+        Ok(result.unwrap_or(E_DEVICE))
+    }
+
+    fn guest_payload(&self, args: [i32; 2], memory: &mut dyn GuestMemory) -> i32 {
+        let [ptr, cap] = args;
+        let state = self.state.borrow();
+        let Some(payload) = state.payloads.last() else {
+            return E_EMPTY;
+        };
+        let Ok(cap) = usize::try_from(cap) else {
+            return E_INVALID;
+        };
+        if payload.len() <= cap && memory.write(ptr as u32, payload).is_err() {
+            return E_INVALID;
+        }
+        payload.len() as i32
+    }
+}
+
+impl<E, S> HostImports for Router<E, S>
+where
+    E: Engine,
+    E::Context: Default + 'static,
+    S: ModuleSource,
+{
+    fn functions(&self) -> &[HostFn] {
+        FUNCTIONS
+    }
+
+    fn call(&mut self, name: &str, args: &[i32], memory: &mut dyn GuestMemory) -> Result<i32> {
+        self.call_with(name, args, memory, &mut HostContext::none())
+    }
+
+    /// Callees run with the caller's context when it is an `E::Context`, and
+    /// with a default one otherwise.
+    fn call_with(
+        &mut self,
+        name: &str,
+        args: &[i32],
+        memory: &mut dyn GuestMemory,
+        ctx: &mut HostContext<'_>,
+    ) -> Result<i32> {
+        match (name, args) {
+            ("call", &[a, b, c, d, e, f]) => self.guest_call([a, b, c, d, e, f], memory, ctx),
+            ("call_payload", &[ptr, cap]) => Ok(self.guest_payload([ptr, cap], memory)),
+            _ => Err(Error::Engine("route: bad host call")),
+        }
+    }
+
+    fn enter(&mut self, module_id: ModuleId) {
+        self.state.borrow_mut().active.push(module_id);
+    }
+
+    fn exit(&mut self) {
+        self.state.borrow_mut().active.pop();
+    }
+}
+
+#[cfg(all(test, feature = "std"))]
+mod tests {
+    use super::*;
+    use crate::abi::Imports;
+    use crate::bus::Bus;
+    use crate::deps::ModuleInfo;
+    use crate::MemoryStore;
+    use alloc::string::ToString;
+
+    /// Callee engine: every entry returns its payload's length plus its
+    /// module id times 100; entry `fail` traps.
+    struct Fake {
+        imports: Imports,
+    }
+
+    impl Engine for Fake {
+        type ModuleHandle = ModuleId;
+        type Context = u32;
+
+        fn load(&mut self, id: ModuleId, _module: &[u8]) -> Result<ModuleId> {
+            Ok(id)
+        }
+
+        fn invoke(&mut self, id: ModuleId, entry: &str, ctx: &mut u32) -> Result<()> {
+            self.invoke_status(id, entry, ctx).map(drop)
+        }
+
+        fn invoke_status(&mut self, id: ModuleId, entry: &str, ctx: &mut u32) -> Result<i32> {
+            if entry == "fail" {
+                return Err(Error::Engine("trap"));
+            }
+            *ctx += 1;
+            self.imports.enter(id);
+            let len = self.imports.call("call_payload", &[0, 0], &mut [0u8; 0]);
+            self.imports.exit();
+            Ok(len? + id as i32 * 100)
+        }
+    }
+
+    fn callee_runtime() -> Runtime<Fake, MemoryStore> {
+        let mut store = MemoryStore::new();
+        store.upsert(7, b"\0asm".to_vec());
+        let mut runtime = Runtime::new(
+            Fake {
+                imports: Imports::new(),
+            },
+            store,
+        );
+        runtime.registry().register(ModuleInfo {
+            module_id: 7,
+            name: Some("filter".to_string()),
+            ..ModuleInfo::default()
+        });
+        runtime
+    }
+
+    #[test]
+    fn routes_granted_calls_by_name() {
+        let router = Router::new(Rc::new(RefCell::new(callee_runtime())));
+        router.runtime().borrow_mut().engine().imports = Imports::new().with(router.clone());
+        let mut imports = Imports::new().with(router.clone());
+        let mut memory = [0u8; 64];
+        memory[..6].copy_from_slice(b"filter");
+        memory[8..12].copy_from_slice(b"main");
+        memory[16..19].copy_from_slice(b"abc");
+        memory[24..28].copy_from_slice(b"fail");
+        let call = [0, 6, 8, 4, 16, 3];
+
+        imports.enter(1);
+        assert_eq!(imports.call("call", &call, &mut memory), Ok(E_DENIED));
+        router.grant(1, 7);
+        assert_eq!(imports.call("call", &call, &mut memory), Ok(703));
+        assert_eq!(
+            imports.call("call", &[0, 6, 24, 4, 16, 3], &mut memory),
+            Ok(E_DEVICE)
+        );
+        assert_eq!(
+            imports.call("call", &[0, 5, 8, 4, 16, 3], &mut memory),
+            Ok(E_INVALID)
+        );
+        assert_eq!(
+            imports.call("call_payload", &[0, 64], &mut memory),
+            Ok(E_EMPTY)
+        );
+
+        // The callee shares the caller's context.
+        let mut calls = 5u32;
+        let status = imports.call_with(
+            "call",
+            &call,
+            &mut memory,
+            &mut HostContext::new(&mut calls),
+        );
+        assert_eq!((status, calls), (Ok(703), 6));
+
+        router.revoke_all(1);
+        assert_eq!(imports.call("call", &call, &mut memory), Ok(E_DENIED));
+    }
+
+    #[test]
+    fn callers_are_active_again_once_the_callee_returns() {
+        let router = Router::new(Rc::new(RefCell::new(callee_runtime())));
+        router.grant(1, 7);
+        let bus = Bus::new();
+        router.runtime().borrow_mut().engine().imports =
+            Imports::new().with(router.clone()).with(bus.clone());
+        let mut imports = Imports::new().with(router.clone()).with(bus.clone());
+        let mut memory = [0u8; 64];
+        memory[..6].copy_from_slice(b"filter");
+        memory[8..12].copy_from_slice(b"main");
+        memory[16..20].copy_from_slice(b"temp");
+
+        imports.enter(1);
+        assert_eq!(
+            imports.call("call", &[0, 6, 8, 4, 16, 0], &mut memory),
+            Ok(700)
+        );
+        // The bus, shared with the callee's engine, attributes these to 1.
+        assert_eq!(imports.call("bus_subscribe", &[16, 4], &mut memory), Ok(0));
+        bus.publish(None, b"temp", b"21").unwrap();
+        assert_eq!((bus.pending(1), bus.pending(7)), (1, 0));
+        assert_eq!(imports.call("bus_recv", &[32, 32], &mut memory), Ok(7));
+        imports.exit();
+        assert!(imports.call("bus_recv", &[32, 32], &mut memory).is_err());
+    }
+}
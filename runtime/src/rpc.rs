@@ -0,0 +1,343 @@
+//! Typed calls into guest exports (`rpc` feature).
+//!
+//! A guest function marked `#[slimmy_export]` (guest-wasm `rpc` feature)
+//! becomes an `i32` entry point that takes its argument and hands back its
+//! result as postcard payloads (see `codec::postcard`), each delimited by
+//! the length passed alongside it. `Runtime::call_typed` encodes the
+//! request, runs the export with `Rpc` registered as a `HostImports` set
+//! and decodes the response, so neither side writes ABI glue by hand.
+//!
+//! Guest imports (module `env`, all `i32`):
+//! - `rpc_request(ptr, cap) -> len | status` – the request's full length,
+//!   copied when it fits in `cap`; `E_EMPTY` outside a typed call.
+//! - `rpc_response(ptr, len) -> status` – stores the response, replacing an
+//!   earlier one; `E_INVALID` past `MAX_PAYLOAD_LEN` or out of bounds.
+//!
+//! The export returns 0 once it responded and `E_INVALID` when the request
+//! does not decode as its argument type; any other status is its own.
+
+use crate::abi::{GuestMemory, HostFn, HostImports};
+use crate::abi::{E_EMPTY, E_INVALID, OK};
+use crate::codec::postcard;
+use crate::trace::{CorrelationId, Reason, NO_CORRELATION};
+use crate::{Engine, Error, ModuleId, ModuleSource, Result, Runtime};
+use alloc::rc::Rc;
+use alloc::vec::Vec;
+use core::cell::RefCell;
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+
+/// Largest request or response in bytes.
+pub const MAX_PAYLOAD_LEN: usize = 4096;
+
+/// Host functions provided by `Rpc`.
+pub const FUNCTIONS: &[HostFn] = &[
+    HostFn::new("rpc_request", 2),
+    HostFn::new("rpc_response", 2),
+];
+
+#[derive(Default)]
+struct State {
+    /// Encoded request of the call in progress.
+    request: Option<Vec<u8>>,
+    response: Option<Vec<u8>>,
+}
+
+/// Shared handle carrying payloads between `call_typed` and the guest.
+#[derive(Clone, Default)]
+pub struct Rpc {
+    state: Rc<RefCell<State>>,
+}
+
+impl Rpc {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    fn guest_request(&self, ptr: i32, cap: i32, memory: &mut dyn GuestMemory) -> i32 {
+        let state = self.state.borrow();
+        let Some(request) = &state.request else {
+            return E_EMPTY;
+        };
+        let Ok(cap) = usize::try_from(cap) else {
+            return E_INVALID;
+        };
+        if request.len() <= cap && memory.write(ptr as u32, request).is_err() {
+            return E_INVALID;
+        }
+        request.len() as i32
+    }
+
+    fn guest_response(&self, ptr: i32, len: i32, memory: &dyn GuestMemory) -> i32 {
+        let mut state = self.state.borrow_mut();
+        if state.request.is_none() {
+            return E_EMPTY;
+        }
+        let Some(len) = usize::try_from(len)
+            .ok()
+            .filter(|len| *len <= MAX_PAYLOAD_LEN)
+        else {
+            return E_INVALID;
+        };
+        let mut response = alloc::vec![0u8; len];
+        if memory.read(ptr as u32, &mut response).is_err() {
+            return E_INVALID;
+        }
+        state.response = Some(response);
+        OK
+    }
+}
+
+impl HostImports for Rpc {
+    fn functions(&self) -> &[HostFn] {
+        FUNCTIONS
+    }
+
+    fn call(&mut self, name: &str, args: &[i32], memory: &mut dyn GuestMemory) -> Result<i32> {
+        match (name, args) {
+            ("rpc_request", &[ptr, cap]) => Ok(self.guest_request(ptr, cap, memory)),
+            ("rpc_response", &[ptr, len]) => Ok(self.guest_response(ptr, len, memory)),
+            _ => Err(Error::Engine("rpc: bad host call")),
+        }
+    }
+}
+
+impl<E: Engine, S: ModuleSource> Runtime<E, S> {
+    /// Runs the `#[slimmy_export]` function `entry` of `module_id` with
+    /// `request` and decodes what it returned; `rpc` must be registered with
+    /// the engine. Typed calls do not nest (`Error::Reentrancy`).
+    pub fn call_typed<Req, Resp>(
+        &mut self,
+        rpc: &Rpc,
+        module_id: ModuleId,
+        entry: &str,
+        request: &Req,
+        ctx: &mut E::Context,
+    ) -> Result<Resp>
+    where
+        Req: Serialize + ?Sized,
+        Resp: DeserializeOwned,
+    {
+        let payload = postcard::to_vec(request)?;
+        let response = self.call_encoded(rpc, module_id, entry, &payload, ctx)?;
+        Ok(postcard::from_bytes(&response)?)
+    }
+
+    /// `call_typed` with the request already encoded; returns the encoded
+    /// response. For hosts that relay payloads they do not decode, such as
+    /// `remote`'s `Call` command.
+    pub fn call_encoded(
+        &mut self,
+        rpc: &Rpc,
+        module_id: ModuleId,
+        entry: &str,
+        request: &[u8],
+        ctx: &mut E::Context,
+    ) -> Result<Vec<u8>> {
+        let origin = (Reason::Direct, NO_CORRELATION);
+        self.call_encoded_for(rpc, module_id, entry, request, ctx, origin)
+    }
+
+    /// `call_encoded`, recording why the module runs and under which
+    /// correlation id (see `execute_for`).
+    pub(crate) fn call_encoded_for(
+        &mut self,
+        rpc: &Rpc,
+        module_id: ModuleId,
+        entry: &str,
+        request: &[u8],
+        ctx: &mut E::Context,
+        (reason, correlation): (Reason, CorrelationId),
+    ) -> Result<Vec<u8>> {
+        if request.len() > MAX_PAYLOAD_LEN {
+            return Err(Error::Engine("rpc: request too large"));
+        }
+        {
+            let mut state = rpc.state.borrow_mut();
+            if state.request.is_some() {
+                return Err(Error::Reentrancy);
+            }
+            state.request = Some(request.to_vec());
+            state.response = None;
+        }
+        let status = {
+            let _scope = self.enter_trace(module_id, reason, correlation);
+            self.run(module_id, entry, ctx, E::invoke_status)
+        };
+        let response = {
+            let mut state = rpc.state.borrow_mut();
+            state.request = None;
+            state.response.take()
+        };
+        match status? {
+            OK => response.ok_or(Error::Engine("rpc: export sent no response")),
+            E_INVALID => Err(Error::Engine("rpc: request not decoded by the export")),
+            _ => Err(Error::Engine("rpc: export returned an error status")),
+        }
+    }
+}
+
+#[cfg(all(test, feature = "std"))]
+mod tests {
+    use super::*;
+    use crate::abi::{Imports, E_DEVICE};
+    use crate::MemoryStore;
+    use alloc::string::String;
+    use serde::Deserialize;
+
+    #[derive(Debug, Serialize, Deserialize, PartialEq)]
+    struct Reading {
+        sensor: String,
+        samples: Vec<i16>,
+    }
+
+    #[derive(Debug, Serialize, Deserialize, PartialEq)]
+    enum Summary {
+        Empty,
+        Mean { sensor: String, mean: f32 },
+    }
+
+    /// Does what the `#[slimmy_export]` glue does for `summarize`; `bad`
+    /// fails to decode, `quiet` never responds and `busy` nests a call.
+    struct Guest {
+        imports: Imports,
+        memory: Vec<u8>,
+        rpc: Rpc,
+    }
+
+    impl Guest {
+        fn new(rpc: Rpc) -> Self {
+            Self {
+                imports: Imports::new().with(rpc.clone()),
+                memory: alloc::vec![0; 1024],
+                rpc,
+            }
+        }
+
+        fn summarize(&mut self) -> Result<i32> {
+            let len = self
+                .imports
+                .call("rpc_request", &[0, 0], &mut self.memory)?;
+            assert!(len > 0);
+            assert_eq!(
+                self.imports
+                    .call("rpc_request", &[16, len], &mut self.memory),
+                Ok(len)
+            );
+            let request = &self.memory[16..16 + len as usize];
+            let Ok(reading) = postcard::from_bytes::<Reading>(request) else {
+                return Ok(E_INVALID);
+            };
+            let summary = match reading.samples.len() {
+                0 => Summary::Empty,
+                n => Summary::Mean {
+                    sensor: reading.sensor,
+                    mean: reading.samples.iter().map(|s| f32::from(*s)).sum::<f32>() / n as f32,
+                },
+            };
+            let len = postcard::to_slice(&summary, &mut self.memory[512..])?.len() as i32;
+            self.imports
+                .call("rpc_response", &[512, len], &mut self.memory)
+        }
+    }
+
+    impl Engine for Guest {
+        type ModuleHandle = ModuleId;
+        type Context = ();
+
+        fn load(&mut self, id: ModuleId, _module: &[u8]) -> Result<ModuleId> {
+            Ok(id)
+        }
+
+        fn invoke(&mut self, id: ModuleId, entry: &str, ctx: &mut ()) -> Result<()> {
+            self.invoke_status(id, entry, ctx).map(drop)
+        }
+
+        fn invoke_status(&mut self, _id: ModuleId, entry: &str, _ctx: &mut ()) -> Result<i32> {
+            match entry {
+                "summarize" => self.summarize(),
+                "bad" => Ok(E_INVALID),
+                "quiet" => Ok(OK),
+                "busy" => {
+                    let mut nested = Runtime::new(Guest::new(self.rpc.clone()), store());
+                    let err =
+                        nested.call_typed::<_, Summary>(&self.rpc, 3, "summarize", &(), &mut ());
+                    assert_eq!(err, Err(Error::Reentrancy));
+                    Ok(E_DEVICE)
+                }
+                _ => Err(Error::EntryNotFound),
+            }
+        }
+    }
+
+    fn store() -> MemoryStore {
+        let mut store = MemoryStore::new();
+        store.upsert(3, b"\0asm".to_vec());
+        store
+    }
+
+    #[test]
+    fn typed_calls_round_trip_through_the_imports() {
+        let rpc = Rpc::new();
+        let mut runtime = Runtime::new(Guest::new(rpc.clone()), store());
+        let reading = Reading {
+            sensor: "t0".into(),
+            samples: alloc::vec![-4, 10, 24],
+        };
+        let summary: Summary = runtime
+            .call_typed(&rpc, 3, "summarize", &reading, &mut ())
+            .unwrap();
+        assert_eq!(
+            summary,
+            Summary::Mean {
+                sensor: "t0".into(),
+                mean: 10.0
+            }
+        );
+        let empty = Reading {
+            sensor: "t1".into(),
+            samples: Vec::new(),
+        };
+        assert_eq!(
+            runtime.call_typed(&rpc, 3, "summarize", &empty, &mut ()),
+            Ok(Summary::Empty)
+        );
+        assert_eq!(
+            runtime.call_typed::<_, Summary>(&rpc, 3, "summarize", &7u8, &mut ()),
+            Err(Error::Engine("rpc: request not decoded by the export"))
+        );
+        assert_eq!(
+            runtime.call_typed::<_, Summary>(&rpc, 3, "bad", &reading, &mut ()),
+            Err(Error::Engine("rpc: request not decoded by the export"))
+        );
+        assert_eq!(
+            runtime.call_typed::<_, Summary>(&rpc, 3, "quiet", &reading, &mut ()),
+            Err(Error::Engine("rpc: export sent no response"))
+        );
+        assert_eq!(
+            runtime.call_typed::<_, Summary>(&rpc, 3, "busy", &reading, &mut ()),
+            Err(Error::Engine("rpc: export returned an error status"))
+        );
+        assert_eq!(
+            runtime.call_typed::<_, Summary>(&rpc, 3, "missing", &reading, &mut ()),
+            Err(Error::EntryNotFound)
+        );
+        let huge = alloc::vec![0u8; MAX_PAYLOAD_LEN];
+        assert_eq!(
+            runtime.call_typed::<_, Summary>(&rpc, 3, "summarize", &huge, &mut ()),
+            Err(Error::Engine("rpc: request too large"))
+        );
+
+        // Outside a typed call there is nothing to read or answer.
+        let mut imports = Imports::new().with(rpc.clone());
+        let mut memory = [0u8; 16];
+        assert_eq!(
+            imports.call("rpc_request", &[0, 16], &mut memory),
+            Ok(E_EMPTY)
+        );
+        assert_eq!(
+            imports.call("rpc_response", &[0, 4], &mut memory),
+            Ok(E_EMPTY)
+        );
+    }
+}
@@ -0,0 +1,777 @@
+//! Periodic and time-of-day scheduling.
+//!
+//! A `Scheduler` owns a list of jobs (module id + entry + trigger) and runs the
+//! ones that are due through `Runtime::execute` whenever firmware calls `tick`.
+//! Wall-clock time and the local UTC offset come from the host via `Clock`.
+//!
+//! Triggers are written as compact text, either in code or in a module's
+//! signed manifest (`EXT_SCHEDULE` extension):
+//! - `@every 30s` / `@every 500ms` / `@every 5m` / `@every 1h` – fixed period.
+//! - five cron fields `minute hour day-of-month month day-of-week`, each `*`,
+//!   `n`, `a-b`, `*/step`, `a-b/step` or a comma list (`0 2 * * *` = 02:00 daily).
+//! - `@hourly`, `@daily`/`@midnight`, `@weekly`, `@monthly`, `@yearly`.
+//!
+//! An optional trailing word names the entry to call (`0 2 * * * report`).
+//! Cron fields follow classic cron: when both day fields are restricted a day
+//! matching either one fires.
+//!
+//! Jobs run most urgent first (`Scheduler::set_priority`; higher first, ties in
+//! the order they were added), so a safety module's tick is not held up behind
+//! analytics. A `PreemptHint` lets an interrupt handler or another thread report
+//! urgent work while a job runs: a job of lower priority is stopped through the
+//! engine's `Interrupter` (wasmtime epochs) and `tick` returns early, leaving it
+//! and the jobs after it due for the next tick.
+//!
+//! Firmware need not poll `tick`: `next_due` is the earliest time a job fires
+//! (in `Clock` milliseconds) and `sleep_ms` how long until then, so a
+//! power-conscious main loop sleeps exactly that long, ticks, and asks again.
+//! Anything that changes the jobs (`add`, `make_due`, `remove`, a new
+//! priority) can move it, so ask after each change.
+
+#[cfg(feature = "alloc")]
+use crate::trace::{Reason, NO_CORRELATION};
+#[cfg(feature = "alloc")]
+use crate::{manifest::Manifest, Engine, ModuleCatalog, ModuleId, ModuleSource, Runtime};
+use crate::{Error, Result};
+#[cfg(feature = "alloc")]
+use alloc::{string::String, sync::Arc, vec::Vec};
+#[cfg(feature = "alloc")]
+use core::cmp::Reverse;
+#[cfg(feature = "alloc")]
+use core::sync::atomic::{AtomicBool, AtomicU16, Ordering};
+
+const MS_PER_MINUTE: u64 = 60_000;
+const MINUTES_PER_DAY: i64 = 24 * 60;
+/// Cron searches give up after this many days (covers leap-day-only specs).
+const MAX_SEARCH_DAYS: i64 = 366 * 8 + 1;
+
+/// Host-provided wall clock.
+pub trait Clock {
+    /// Milliseconds since the Unix epoch, UTC.
+    fn now_ms(&self) -> u64;
+
+    /// Local offset from UTC in seconds (timezone + DST); default UTC.
+    fn utc_offset_secs(&self) -> i32 {
+        0
+    }
+}
+
+/// Parsed cron expression (minute resolution, local time).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CronSpec {
+    minutes: u64,
+    hours: u32,
+    days: u32,
+    months: u16,
+    weekdays: u8,
+    any_day: bool,
+    any_weekday: bool,
+}
+
+impl CronSpec {
+    /// Parses five cron fields or an `@hourly`-style shorthand.
+    pub fn parse(text: &str) -> Result<Self> {
+        let text = match text.trim() {
+            "@hourly" => "0 * * * *",
+            "@daily" | "@midnight" => "0 0 * * *",
+            "@weekly" => "0 0 * * 0",
+            "@monthly" => "0 0 1 * *",
+            "@yearly" | "@annually" => "0 0 1 1 *",
+            other => other,
+        };
+        let mut fields = text.split_ascii_whitespace();
+        let mut next = |min, max| parse_field(fields.next().ok_or(CRON_ERR)?, min, max);
+        let (minutes, _) = next(0, 59)?;
+        let (hours, _) = next(0, 23)?;
+        let (days, any_day) = next(1, 31)?;
+        let (months, _) = next(1, 12)?;
+        let (weekdays, any_weekday) = next(0, 7)?;
+        if fields.next().is_some() {
+            return Err(CRON_ERR);
+        }
+        // Day-of-week 7 is an alias for Sunday.
+        let weekdays = (weekdays | (weekdays >> 7)) as u8 & 0x7f;
+        Ok(Self {
+            minutes,
+            hours: hours as u32,
+            days: days as u32,
+            months: months as u16,
+            weekdays,
+            any_day,
+            any_weekday,
+        })
+    }
+
+    /// True when the spec fires at `minute` (minutes since the epoch, local time).
+    pub fn matches(&self, minute: i64) -> bool {
+        let days = minute.div_euclid(MINUTES_PER_DAY);
+        let of_day = minute.rem_euclid(MINUTES_PER_DAY);
+        self.day_matches(days)
+            && bit(self.hours as u64, of_day / 60)
+            && bit(self.minutes, of_day % 60)
+    }
+
+    /// First matching minute strictly after `minute` (local time), if any.
+    pub fn next_after(&self, minute: i64) -> Option<i64> {
+        let mut t = minute.checked_add(1)?;
+        let limit = t.checked_add(MAX_SEARCH_DAYS * MINUTES_PER_DAY)?;
+        while t < limit {
+            let days = t.div_euclid(MINUTES_PER_DAY);
+            let of_day = t.rem_euclid(MINUTES_PER_DAY);
+            if !self.day_matches(days) {
+                t = (days + 1) * MINUTES_PER_DAY;
+            } else if !bit(self.hours as u64, of_day / 60) {
+                t = days * MINUTES_PER_DAY + (of_day / 60 + 1) * 60;
+            } else if !bit(self.minutes, of_day % 60) {
+                t += 1;
+            } else {
+                return Some(t);
+            }
+        }
+        None
+    }
+
+    fn day_matches(&self, days: i64) -> bool {
+        let (_, month, day) = civil_from_days(days);
+        // 1970-01-01 was a Thursday (weekday 4, Sunday = 0).
+        let weekday = (days + 4).rem_euclid(7);
+        let dom = bit(self.days as u64, day as i64);
+        let dow = bit(self.weekdays as u64, weekday);
+        let day_ok = match (self.any_day, self.any_weekday) {
+            (true, true) => true,
+            (true, false) => dow,
+            (false, true) => dom,
+            (false, false) => dom || dow,
+        };
+        day_ok && bit(self.months as u64, month as i64)
+    }
+}
+
+const CRON_ERR: Error = Error::Engine("schedule: bad cron spec");
+
+fn bit(mask: u64, n: i64) -> bool {
+    (0..64).contains(&n) && mask & (1 << n) != 0
+}
+
+/// Parses one cron field into a bitmask; the flag reports a bare `*`.
+fn parse_field(field: &str, min: u32, max: u32) -> Result<(u64, bool)> {
+    let mut mask = 0u64;
+    for item in field.split(',') {
+        let (range, step) = match item.split_once('/') {
+            Some((range, step)) => (range, step.parse::<u32>().map_err(|_| CRON_ERR)?),
+            None => (item, 1),
+        };
+        if step == 0 {
+            return Err(CRON_ERR);
+        }
+        let (lo, hi) = match range {
+            "*" => (min, max),
+            _ => match range.split_once('-') {
+                Some((lo, hi)) => (parse_num(lo)?, parse_num(hi)?),
+                None => {
+                    let n = parse_num(range)?;
+                    (n, n)
+                }
+            },
+        };
+        if lo < min || hi > max || lo > hi {
+            return Err(CRON_ERR);
+        }
+        for n in (lo..=hi).step_by(step as usize) {
+            mask |= 1 << n;
+        }
+    }
+    Ok((mask, field == "*"))
+}
+
+fn parse_num(text: &str) -> Result<u32> {
+    text.parse().map_err(|_| CRON_ERR)
+}
+
+/// Days since the epoch to (year, month 1..=12, day 1..=31), proleptic Gregorian.
+fn civil_from_days(days: i64) -> (i64, u32, u32) {
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z.rem_euclid(146_097);
+    let yoe = (doe - doe / 1460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = (doy - (153 * mp + 2) / 5 + 1) as u32;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 } as u32;
+    let year = yoe + era * 400 + (month <= 2) as i64;
+    (year, month, day)
+}
+
+/// When a job fires.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Trigger {
+    /// Fixed period in milliseconds.
+    Every(u64),
+    /// Local time-of-day / calendar spec.
+    Cron(CronSpec),
+}
+
+impl Trigger {
+    /// Parses a trigger and an optional trailing entry name (see module docs).
+    pub fn parse(text: &str) -> Result<(Self, Option<&str>)> {
+        let text = text.trim();
+        let fields = if text.starts_with("@every") {
+            2
+        } else if text.starts_with('@') {
+            1
+        } else {
+            5
+        };
+        let (spec, entry) = split_fields(text, fields);
+        let entry = match entry {
+            "" => None,
+            entry if entry.contains(|c: char| c.is_ascii_whitespace()) => return Err(CRON_ERR),
+            entry => Some(entry),
+        };
+        let trigger = match spec.strip_prefix("@every") {
+            Some(period) => Trigger::Every(parse_period(period.trim())?),
+            None => Trigger::Cron(CronSpec::parse(spec)?),
+        };
+        Ok((trigger, entry))
+    }
+
+    /// Next firing time (UTC ms) strictly after `now_ms`.
+    pub fn next_after(&self, now_ms: u64, utc_offset_secs: i32) -> Option<u64> {
+        match self {
+            Trigger::Every(period) => now_ms.checked_add(*period),
+            Trigger::Cron(spec) => {
+                let offset_ms = utc_offset_secs as i64 * 1000;
+                let local = now_ms as i64 + offset_ms;
+                let next = spec.next_after(local.div_euclid(MS_PER_MINUTE as i64))?;
+                u64::try_from(next * MS_PER_MINUTE as i64 - offset_ms).ok()
+            }
+        }
+    }
+}
+
+/// Splits `text` after its first `n` whitespace-separated fields.
+fn split_fields(text: &str, n: usize) -> (&str, &str) {
+    let mut rest = text;
+    for _ in 0..n {
+        let end = rest
+            .find(|c: char| c.is_ascii_whitespace())
+            .unwrap_or(rest.len());
+        rest = rest[end..].trim_start();
+    }
+    (text[..text.len() - rest.len()].trim_end(), rest)
+}
+
+fn parse_period(text: &str) -> Result<u64> {
+    let (digits, scale) = if let Some(n) = text.strip_suffix("ms") {
+        (n, 1)
+    } else if let Some(n) = text.strip_suffix('s') {
+        (n, 1000)
+    } else if let Some(n) = text.strip_suffix('m') {
+        (n, MS_PER_MINUTE)
+    } else if let Some(n) = text.strip_suffix('h') {
+        (n, 60 * MS_PER_MINUTE)
+    } else {
+        return Err(CRON_ERR);
+    };
+    let n: u64 = digits.parse().map_err(|_| CRON_ERR)?;
+    match n.checked_mul(scale) {
+        Some(period) if period > 0 => Ok(period),
+        _ => Err(CRON_ERR),
+    }
+}
+
+/// One scheduled invocation.
+#[cfg(feature = "alloc")]
+#[derive(Debug, Clone)]
+pub struct Job {
+    pub module_id: ModuleId,
+    pub entry: String,
+    pub trigger: Trigger,
+    /// Next firing time (UTC ms); `None` when the trigger never fires again.
+    pub next_due: Option<u64>,
+    /// Error from the most recent run, if it failed.
+    pub last_error: Option<Error>,
+    /// Due jobs run highest first.
+    pub priority: u8,
+}
+
+/// Stops the call an engine is running, from any thread (see
+/// `Engine::interrupter`).
+#[cfg(feature = "alloc")]
+#[derive(Clone)]
+pub struct Interrupter(Arc<dyn Fn() + Send + Sync>);
+
+#[cfg(feature = "alloc")]
+impl Interrupter {
+    pub fn new(interrupt: impl Fn() + Send + Sync + 'static) -> Self {
+        Self(Arc::new(interrupt))
+    }
+
+    /// Makes the running call, if any, trap with `TrapKind::Interrupted` at
+    /// its next check; calls started later are unaffected.
+    pub fn interrupt(&self) {
+        (self.0)()
+    }
+}
+
+/// `Preemption::running` while no job runs.
+#[cfg(feature = "alloc")]
+const IDLE: u16 = u16::MAX;
+
+#[cfg(feature = "alloc")]
+struct Preemption {
+    /// Priority of the job running, or `IDLE`.
+    running: AtomicU16,
+    interrupted: AtomicBool,
+}
+
+/// Reports urgent work to a `Scheduler` from an interrupt handler or another
+/// thread; see the module docs.
+#[cfg(feature = "alloc")]
+#[derive(Clone)]
+pub struct PreemptHint {
+    preemption: Arc<Preemption>,
+    interrupter: Interrupter,
+}
+
+#[cfg(feature = "alloc")]
+impl PreemptHint {
+    /// Work of `priority` is waiting: interrupts the running job when its
+    /// priority is lower. Returns whether it did.
+    pub fn urgent(&self, priority: u8) -> bool {
+        let running = self.preemption.running.load(Ordering::Acquire);
+        if running == IDLE || running >= u16::from(priority) {
+            return false;
+        }
+        self.preemption.interrupted.store(true, Ordering::Release);
+        self.interrupter.interrupt();
+        true
+    }
+}
+
+/// Runs jobs when they are due; firmware calls `tick` from its main loop.
+#[cfg(feature = "alloc")]
+pub struct Scheduler<C> {
+    clock: C,
+    jobs: Vec<Job>,
+    priorities: Vec<(ModuleId, u8)>,
+    preemption: Arc<Preemption>,
+}
+
+#[cfg(feature = "alloc")]
+impl<C: Clock> Scheduler<C> {
+    /// Creates an empty scheduler reading time from `clock`.
+    pub fn new(clock: C) -> Self {
+        Self {
+            clock,
+            jobs: Vec::new(),
+            priorities: Vec::new(),
+            preemption: Arc::new(Preemption {
+                running: AtomicU16::new(IDLE),
+                interrupted: AtomicBool::new(false),
+            }),
+        }
+    }
+
+    /// Adds a job; the first run is the trigger's next firing after now.
+    pub fn add(&mut self, module_id: ModuleId, entry: &str, trigger: Trigger) {
+        let next_due = trigger.next_after(self.clock.now_ms(), self.clock.utc_offset_secs());
+        self.jobs.push(Job {
+            module_id,
+            entry: entry.into(),
+            trigger,
+            next_due,
+            last_error: None,
+            priority: self.priority(module_id),
+        });
+    }
+
+    /// Sets the priority of a module's jobs, present and future (default 0).
+    pub fn set_priority(&mut self, module_id: ModuleId, priority: u8) {
+        self.priorities.retain(|(id, _)| *id != module_id);
+        self.priorities.push((module_id, priority));
+        for job in self
+            .jobs
+            .iter_mut()
+            .filter(|job| job.module_id == module_id)
+        {
+            job.priority = priority;
+        }
+    }
+
+    /// Priority of a module's jobs.
+    pub fn priority(&self, module_id: ModuleId) -> u8 {
+        self.priorities
+            .iter()
+            .find(|(id, _)| *id == module_id)
+            .map_or(0, |(_, priority)| *priority)
+    }
+
+    /// Makes a module's jobs due now, e.g. after the event they handle.
+    pub fn make_due(&mut self, module_id: ModuleId) {
+        let now = self.clock.now_ms();
+        for job in self
+            .jobs
+            .iter_mut()
+            .filter(|job| job.module_id == module_id)
+        {
+            job.next_due = Some(now);
+        }
+    }
+
+    /// A hint that stops running jobs through `interrupter`, typically the
+    /// runtime engine's (`Engine::interrupter`).
+    pub fn preempt_hint(&self, interrupter: Interrupter) -> PreemptHint {
+        PreemptHint {
+            preemption: self.preemption.clone(),
+            interrupter,
+        }
+    }
+
+    /// Adds a job from trigger text; `default_entry` is used when the text names none.
+    pub fn add_spec(&mut self, module_id: ModuleId, default_entry: &str, spec: &str) -> Result<()> {
+        let (trigger, entry) = Trigger::parse(spec)?;
+        self.add(module_id, entry.unwrap_or(default_entry), trigger);
+        Ok(())
+    }
+
+    /// Replaces a module's jobs with the schedule carried by its manifest.
+    ///
+    /// Returns `false` (and leaves existing jobs alone) when the manifest has no
+    /// `EXT_SCHEDULE` extension.
+    pub fn apply_manifest(&mut self, manifest: &Manifest<'_>) -> Result<bool> {
+        let Some(spec) = manifest.schedule() else {
+            return Ok(false);
+        };
+        let (trigger, entry) = Trigger::parse(spec?)?;
+        self.remove(manifest.module_id);
+        self.add(manifest.module_id, entry.unwrap_or(manifest.entry), trigger);
+        Ok(true)
+    }
+
+    /// Drops every job for a module.
+    pub fn remove(&mut self, module_id: ModuleId) {
+        self.jobs.retain(|job| job.module_id != module_id);
+    }
+
+    /// Drops jobs whose module is no longer in `catalog`; returns how many.
+    pub fn prune<S: ModuleCatalog>(&mut self, catalog: &S) -> usize {
+        let before = self.jobs.len();
+        self.jobs
+            .retain(|job| catalog.iter_ids().any(|id| id == job.module_id));
+        before - self.jobs.len()
+    }
+
+    /// Scheduled jobs.
+    pub fn jobs(&self) -> &[Job] {
+        &self.jobs
+    }
+
+    /// Earliest firing time of any job (UTC ms, like `Job::next_due`);
+    /// `None` when no job fires again. In the past when a job is overdue.
+    pub fn next_due(&self) -> Option<u64> {
+        self.jobs.iter().filter_map(|job| job.next_due).min()
+    }
+
+    /// Milliseconds from now until `next_due`, 0 when a job is due already;
+    /// `None` when no job fires again.
+    pub fn sleep_ms(&self) -> Option<u64> {
+        self.next_due()
+            .map(|due| due.saturating_sub(self.clock.now_ms()))
+    }
+
+    /// Access to the clock.
+    pub fn clock(&self) -> &C {
+        &self.clock
+    }
+
+    /// Runs every due job once, highest priority first, and returns how many
+    /// ran. Jobs of disabled modules are skipped until their next firing.
+    ///
+    /// A failed run is recorded in `Job::last_error` and does not stop the
+    /// others. Missed periods are not replayed: the next firing is computed
+    /// from the current time. A job stopped by a `PreemptHint` stays due and
+    /// ends the tick. A suspended runtime (`Runtime::suspend`) runs nothing
+    /// and its due jobs stay due.
+    pub fn tick<E, S>(&mut self, runtime: &mut Runtime<E, S>, ctx: &mut E::Context) -> usize
+    where
+        E: Engine,
+        S: ModuleSource,
+    {
+        if runtime.is_suspended() {
+            return 0;
+        }
+        let now = self.clock.now_ms();
+        let offset = self.clock.utc_offset_secs();
+        let mut due: Vec<usize> = (0..self.jobs.len())
+            .filter(|&i| self.jobs[i].next_due.is_some_and(|due| due <= now))
+            .collect();
+        due.sort_by_key(|&i| Reverse(self.jobs[i].priority));
+        let preemption = &self.preemption;
+        let mut ran = 0;
+        for i in due {
+            let job = &mut self.jobs[i];
+            if !runtime.is_enabled(job.module_id) {
+                job.next_due = job.trigger.next_after(now, offset);
+                continue;
+            }
+            preemption.interrupted.store(false, Ordering::Relaxed);
+            preemption
+                .running
+                .store(u16::from(job.priority), Ordering::Release);
+            let result = runtime.execute_for(
+                job.module_id,
+                &job.entry,
+                ctx,
+                Reason::Scheduled,
+                NO_CORRELATION,
+            );
+            preemption.running.store(IDLE, Ordering::Release);
+            ran += 1;
+            let interrupted = preemption.interrupted.swap(false, Ordering::Acquire);
+            job.last_error = result.err();
+            if interrupted && job.last_error.is_some() {
+                break;
+            }
+            job.next_due = job.trigger.next_after(now, offset);
+        }
+        ran
+    }
+}
+
+#[cfg(all(test, feature = "std"))]
+mod tests {
+    use super::*;
+    use crate::MemoryStore;
+    use std::cell::Cell;
+    use std::rc::Rc;
+
+    // 2024-03-05 (Tuesday) 00:00 UTC, in minutes.
+    const TUE_MIDNIGHT: i64 = 19_787 * MINUTES_PER_DAY;
+
+    #[test]
+    fn parses_and_matches_cron_specs() {
+        let daily = CronSpec::parse("0 2 * * *").unwrap();
+        assert_eq!(daily.next_after(TUE_MIDNIGHT), Some(TUE_MIDNIGHT + 120));
+        assert_eq!(
+            daily.next_after(TUE_MIDNIGHT + 120),
+            Some(TUE_MIDNIGHT + MINUTES_PER_DAY + 120)
+        );
+        assert!(daily.matches(TUE_MIDNIGHT + 120));
+
+        let weekdays = CronSpec::parse("*/15 9-17 * * 1-5").unwrap();
+        assert!(weekdays.matches(TUE_MIDNIGHT + 9 * 60 + 45));
+        assert!(!weekdays.matches(TUE_MIDNIGHT + 9 * 60 + 40));
+        // Saturday 2024-03-09 is skipped through to Monday 09:00.
+        let saturday = TUE_MIDNIGHT + 4 * MINUTES_PER_DAY;
+        assert_eq!(
+            weekdays.next_after(saturday),
+            Some(saturday + 2 * MINUTES_PER_DAY + 9 * 60)
+        );
+
+        assert_eq!(
+            CronSpec::parse("@weekly").unwrap(),
+            CronSpec::parse("0 0 * * 7").unwrap()
+        );
+        assert_eq!(CronSpec::parse("0 0 30 2 *").unwrap().next_after(0), None);
+        assert!(CronSpec::parse("60 * * * *").is_err());
+        assert!(CronSpec::parse("* * * *").is_err());
+        assert_eq!(civil_from_days(19_787), (2024, 3, 5));
+    }
+
+    #[test]
+    fn parses_triggers_with_entries() {
+        let (trigger, entry) = Trigger::parse("0 2 * * * report").unwrap();
+        assert!(matches!(trigger, Trigger::Cron(_)));
+        assert_eq!(entry, Some("report"));
+        assert_eq!(
+            Trigger::parse("@every 500ms").unwrap(),
+            (Trigger::Every(500), None)
+        );
+        assert_eq!(Trigger::parse("@daily sync").unwrap().1, Some("sync"));
+        assert!(Trigger::parse("@every 0s").is_err());
+        assert!(Trigger::parse("0 2 * * * report extra").is_err());
+
+        // 02:00 local in UTC+2 is 00:00 UTC.
+        let (daily, _) = Trigger::parse("0 2 * * *").unwrap();
+        let now = TUE_MIDNIGHT as u64 * MS_PER_MINUTE - 1;
+        assert_eq!(
+            daily.next_after(now, 2 * 3600),
+            Some(TUE_MIDNIGHT as u64 * MS_PER_MINUTE)
+        );
+    }
+
+    struct TestClock(Rc<Cell<u64>>);
+
+    impl Clock for TestClock {
+        fn now_ms(&self) -> u64 {
+            self.0.get()
+        }
+    }
+
+    struct Counter;
+
+    impl Engine for Counter {
+        type ModuleHandle = ModuleId;
+        type Context = Vec<(ModuleId, String)>;
+
+        fn load(&mut self, id: ModuleId, _module: &[u8]) -> Result<ModuleId> {
+            Ok(id)
+        }
+
+        fn invoke(&mut self, handle: ModuleId, entry: &str, ctx: &mut Self::Context) -> Result<()> {
+            ctx.push((handle, entry.into()));
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn tick_runs_due_jobs() {
+        let now = Rc::new(Cell::new(TUE_MIDNIGHT as u64 * MS_PER_MINUTE));
+        let mut store = MemoryStore::new();
+        store.upsert(1, vec![0]);
+        store.upsert(2, vec![0]);
+        let mut runtime = Runtime::new(Counter, store);
+        let mut scheduler = Scheduler::new(TestClock(now.clone()));
+        scheduler.add(1, "sample", Trigger::Every(1000));
+        scheduler.add_spec(2, "main", "0 2 * * * report").unwrap();
+        let mut calls = Vec::new();
+
+        assert_eq!(scheduler.tick(&mut runtime, &mut calls), 0);
+        now.set(now.get() + 1000);
+        assert_eq!(scheduler.tick(&mut runtime, &mut calls), 1);
+        now.set(now.get() + 2 * 60 * MS_PER_MINUTE);
+        assert_eq!(scheduler.tick(&mut runtime, &mut calls), 2);
+        assert_eq!(
+            calls,
+            vec![
+                (1, "sample".to_string()),
+                (1, "sample".to_string()),
+                (2, "report".to_string())
+            ]
+        );
+
+        scheduler.add(3, "gone", Trigger::Every(1000));
+        assert_eq!(scheduler.prune(runtime.source()), 1);
+        assert_eq!(scheduler.jobs().len(), 2);
+    }
+
+    #[test]
+    fn next_due_is_the_earliest_firing() {
+        let start = TUE_MIDNIGHT as u64 * MS_PER_MINUTE;
+        let now = Rc::new(Cell::new(start));
+        let mut store = MemoryStore::new();
+        store.upsert(1, vec![0]);
+        let mut runtime = Runtime::new(Counter, store);
+        let mut scheduler = Scheduler::new(TestClock(now.clone()));
+        assert_eq!((scheduler.next_due(), scheduler.sleep_ms()), (None, None));
+
+        scheduler.add_spec(2, "main", "0 2 * * *").unwrap();
+        scheduler.add(1, "sample", Trigger::Every(30_000));
+        assert_eq!(scheduler.next_due(), Some(start + 30_000));
+        now.set(start + 10_000);
+        assert_eq!(scheduler.sleep_ms(), Some(20_000));
+
+        // Overdue jobs are due now; a tick moves the deadline on.
+        now.set(start + 45_000);
+        assert_eq!(scheduler.sleep_ms(), Some(0));
+        scheduler.tick(&mut runtime, &mut Vec::new());
+        assert_eq!(scheduler.next_due(), Some(start + 75_000));
+
+        scheduler.remove(1);
+        assert_eq!(scheduler.next_due(), Some(start + 2 * 60 * MS_PER_MINUTE));
+        scheduler.make_due(2);
+        assert_eq!(scheduler.sleep_ms(), Some(0));
+    }
+
+    /// Module 1 reports urgent work once while it runs, as an interrupt
+    /// handler would; an interrupted call fails.
+    struct Preemptible {
+        hint: Option<PreemptHint>,
+        stop: Arc<AtomicBool>,
+    }
+
+    impl Engine for Preemptible {
+        type ModuleHandle = ModuleId;
+        type Context = Vec<ModuleId>;
+
+        fn load(&mut self, id: ModuleId, _module: &[u8]) -> Result<ModuleId> {
+            Ok(id)
+        }
+
+        fn invoke(
+            &mut self,
+            handle: ModuleId,
+            _entry: &str,
+            ctx: &mut Self::Context,
+        ) -> Result<()> {
+            ctx.push(handle);
+            if handle == 1 {
+                if let Some(hint) = self.hint.take() {
+                    assert!(hint.urgent(9));
+                    assert!(!hint.urgent(0));
+                }
+            }
+            match self.stop.swap(false, Ordering::Relaxed) {
+                true => Err(Error::Engine("interrupted")),
+                false => Ok(()),
+            }
+        }
+    }
+
+    #[test]
+    fn urgent_work_preempts_lower_priorities() {
+        let now = Rc::new(Cell::new(0));
+        let mut store = MemoryStore::new();
+        for id in 1..=3 {
+            store.upsert(id, vec![0]);
+        }
+        let mut scheduler = Scheduler::new(TestClock(now.clone()));
+        scheduler.add(1, "analyze", Trigger::Every(1000));
+        scheduler.add(3, "report", Trigger::Every(1000));
+        scheduler.add(2, "safety", Trigger::Every(1000));
+        scheduler.set_priority(2, 9);
+        scheduler.set_priority(3, 1);
+        let stop = Arc::new(AtomicBool::new(false));
+        let flag = stop.clone();
+        let hint = scheduler.preempt_hint(Interrupter::new(move || {
+            flag.store(true, Ordering::Relaxed)
+        }));
+        assert!(!hint.urgent(9));
+        let engine = Preemptible {
+            hint: Some(hint),
+            stop,
+        };
+        let mut runtime = Runtime::new(engine, store);
+        let mut calls = Vec::new();
+
+        now.set(1000);
+        assert_eq!(scheduler.tick(&mut runtime, &mut calls), 3);
+        assert!(scheduler.jobs()[0].last_error.is_some());
+        assert_eq!(scheduler.jobs()[0].next_due, Some(1000));
+        scheduler.make_due(2);
+        assert_eq!(scheduler.tick(&mut runtime, &mut calls), 2);
+        assert_eq!(calls, [2, 3, 1, 2, 1]);
+        assert!(scheduler
+            .jobs()
+            .iter()
+            .all(|job| job.next_due == Some(2000)));
+    }
+
+    #[test]
+    fn manifest_schedule_replaces_jobs() {
+        use crate::manifest::{encode_ext, push_extension, EXT_SCHEDULE};
+
+        let mut ext = Vec::new();
+        push_extension(&mut ext, EXT_SCHEDULE, b"@daily").unwrap();
+        let blob = encode_ext(4, "main", &[0], 0, 0, &ext, None).unwrap();
+        let (manifest, _) = Manifest::parse(&blob).unwrap();
+
+        let mut scheduler = Scheduler::new(TestClock(Rc::new(Cell::new(0))));
+        scheduler.add(4, "old", Trigger::Every(10));
+        assert!(scheduler.apply_manifest(&manifest).unwrap());
+        assert_eq!(scheduler.jobs().len(), 1);
+        assert_eq!(scheduler.jobs()[0].entry, "main");
+        assert_eq!(scheduler.jobs()[0].next_due, Some(24 * 60 * MS_PER_MINUTE));
+    }
+}
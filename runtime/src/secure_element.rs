@@ -0,0 +1,400 @@
+//! Trust anchors and anti-rollback counters in a secure element
+//! (`secure-element` feature).
+//!
+//! Keys and counters kept in ordinary flash can be swapped by anyone who
+//! can write that flash. A secure element locks them in hardware instead:
+//! key slots that cannot be rewritten once the chip is locked, and
+//! counters that only go up.
+//!
+//! - `SecureElement` is what the runtime needs from the chip: read a
+//!   32-byte key slot, read a monotonic counter, increment it.
+//! - `Atecc608` implements it for Microchip's ATECC608 over any
+//!   embedded-hal `I2c`. Keys live in data slots (32-byte reads of block 0,
+//!   so the data zone must be locked and the slots readable); `Counter`
+//!   gives the chip's two monotonic counters (at most `ATECC_COUNTER_MAX`).
+//!   NXP's SE050 speaks APDUs over T=1; its vendor middleware implements
+//!   the trait in a few lines.
+//! - `load_keyring` (`unstable`) builds the `audit::Keyring` from slots at
+//!   boot, so the trust anchors never pass through mutable flash.
+//! - `AntiRollback` binds rollback-protected modules to a counter: a
+//!   manifest whose `sequence` is not above the counter is refused
+//!   (`check`), and installing it raises the counter to that sequence
+//!   (`commit`). An ATECC608 only has two counters, so modules share them
+//!   or one counter guards a whole release.
+
+use crate::manifest::{Manifest, FLAG_ROLLBACK_PROTECTED};
+use crate::{Error, ModuleId, Result};
+use alloc::vec::Vec;
+use embedded_hal::delay::DelayNs;
+use embedded_hal::i2c::I2c;
+
+/// Length of a key read from a slot.
+pub const KEY_LEN: usize = 32;
+/// Default 7-bit I2C address of an ATECC608.
+pub const ATECC_ADDRESS: u8 = 0x60;
+/// Largest value an ATECC608 counter reaches.
+pub const ATECC_COUNTER_MAX: u32 = 2_097_151;
+
+/// Key slots and monotonic counters held by a secure element.
+pub trait SecureElement {
+    /// Reads the 32 bytes stored in key slot `slot`.
+    fn read_key(&mut self, slot: u16) -> Result<[u8; KEY_LEN]>;
+    /// Current value of monotonic counter `counter`.
+    fn read_counter(&mut self, counter: u16) -> Result<u32>;
+    /// Adds one to `counter` and returns the new value.
+    fn increment_counter(&mut self, counter: u16) -> Result<u32>;
+}
+
+/// I2C word address preceding a command packet.
+const WORD_COMMAND: u8 = 0x03;
+/// I2C word address putting the chip to idle (keeps its volatile state).
+const WORD_IDLE: u8 = 0x02;
+/// What the chip answers after waking up.
+const WAKE_RESPONSE: [u8; 4] = [0x04, 0x11, 0x33, 0x43];
+const OP_READ: u8 = 0x02;
+const OP_COUNTER: u8 = 0x24;
+/// `Read` zone byte for a 32-byte read of the data zone.
+const ZONE_DATA_32: u8 = 0x82;
+/// Longest command execution (`Counter`), in microseconds.
+const EXEC_COUNTER_US: u32 = 20_000;
+/// `Read` execution time, in microseconds.
+const EXEC_READ_US: u32 = 1_000;
+/// Wake low time plus the time until the chip answers, in microseconds.
+const WAKE_US: u32 = 1_500;
+
+/// Microchip ATECC608 on an I2C bus.
+pub struct Atecc608<I, D> {
+    i2c: I,
+    delay: D,
+    address: u8,
+}
+
+impl<I: I2c, D: DelayNs> Atecc608<I, D> {
+    /// The chip at `ATECC_ADDRESS`.
+    pub fn new(i2c: I, delay: D) -> Self {
+        Self::with_address(i2c, delay, ATECC_ADDRESS)
+    }
+
+    pub fn with_address(i2c: I, delay: D, address: u8) -> Self {
+        Self {
+            i2c,
+            delay,
+            address,
+        }
+    }
+
+    pub fn release(self) -> (I, D) {
+        (self.i2c, self.delay)
+    }
+
+    fn counter(&mut self, mode: u8, counter: u16) -> Result<u32> {
+        if counter > 1 {
+            return Err(Error::Engine("se: no such counter"));
+        }
+        self.execute(OP_COUNTER, mode, counter, EXEC_COUNTER_US)
+            .map(u32::from_le_bytes)
+    }
+
+    /// Wakes the chip, runs one command, puts it back to idle and returns
+    /// the response data (`N` bytes).
+    fn execute<const N: usize>(
+        &mut self,
+        opcode: u8,
+        param1: u8,
+        param2: u16,
+        exec_us: u32,
+    ) -> Result<[u8; N]> {
+        self.wake()?;
+        let result = self.command(opcode, param1, param2, exec_us);
+        // An idle that fails only costs power until the watchdog sleeps it.
+        let _ = self.i2c.write(self.address, &[WORD_IDLE]);
+        result
+    }
+
+    fn wake(&mut self) -> Result<()> {
+        // Addressing 0x00 holds SDA low long enough at 100 kHz; nothing
+        // acknowledges, so the error is expected.
+        let _ = self.i2c.write(0x00, &[]);
+        self.delay.delay_us(WAKE_US);
+        let mut response = [0u8; 4];
+        self.i2c
+            .read(self.address, &mut response)
+            .map_err(|_| Error::Engine("se: no answer on the bus"))?;
+        if response != WAKE_RESPONSE {
+            return Err(Error::Engine("se: bad wake response"));
+        }
+        Ok(())
+    }
+
+    fn command<const N: usize>(
+        &mut self,
+        opcode: u8,
+        param1: u8,
+        param2: u16,
+        exec_us: u32,
+    ) -> Result<[u8; N]> {
+        let [lo, hi] = param2.to_le_bytes();
+        let mut packet = [WORD_COMMAND, 7, opcode, param1, lo, hi, 0, 0];
+        let crc = crc16(&packet[1..6]).to_le_bytes();
+        packet[6..].copy_from_slice(&crc);
+        self.i2c
+            .write(self.address, &packet)
+            .map_err(|_| Error::Engine("se: command not sent"))?;
+        self.delay.delay_us(exec_us);
+
+        // count, data, crc; status-only answers are 4 bytes.
+        let mut response = [0u8; 67];
+        let len = (N + 3).max(4);
+        self.i2c
+            .read(self.address, &mut response[..len])
+            .map_err(|_| Error::Engine("se: response not read"))?;
+        let count = usize::from(response[0]);
+        if !(4..=len).contains(&count) {
+            return Err(Error::Engine("se: bad response length"));
+        }
+        let (body, crc) = response[..count].split_at(count - 2);
+        if crc16(body).to_le_bytes() != crc {
+            return Err(Error::Engine("se: response CRC mismatch"));
+        }
+        if count != N + 3 {
+            // A 4-byte answer to a command with data is an error status.
+            return Err(Error::Engine(match (count, body[1]) {
+                (4, 0x01) => "se: check failed",
+                (4, 0x03) => "se: command rejected",
+                (4, 0x0f) => "se: execution error",
+                (4, _) => "se: device error",
+                _ => "se: bad response length",
+            }));
+        }
+        let mut data = [0u8; N];
+        data.copy_from_slice(&body[1..]);
+        Ok(data)
+    }
+}
+
+impl<I: I2c, D: DelayNs> SecureElement for Atecc608<I, D> {
+    fn read_key(&mut self, slot: u16) -> Result<[u8; KEY_LEN]> {
+        if slot > 15 {
+            return Err(Error::Engine("se: no such slot"));
+        }
+        self.execute(OP_READ, ZONE_DATA_32, slot << 3, EXEC_READ_US)
+    }
+
+    fn read_counter(&mut self, counter: u16) -> Result<u32> {
+        self.counter(0x00, counter)
+    }
+
+    fn increment_counter(&mut self, counter: u16) -> Result<u32> {
+        self.counter(0x01, counter)
+    }
+}
+
+/// The CRC-16 ATECC packets end with (polynomial 0x8005, bits in LSB
+/// first, no reflection of the result).
+fn crc16(data: &[u8]) -> u16 {
+    let mut crc = 0u16;
+    for byte in data {
+        for bit in 0..8 {
+            let data_bit = (byte >> bit) & 1 == 1;
+            let crc_bit = crc >> 15 == 1;
+            crc <<= 1;
+            if data_bit != crc_bit {
+                crc ^= 0x8005;
+            }
+        }
+    }
+    crc
+}
+
+/// Adds the keys in `slots` to `keyring`.
+#[cfg(feature = "unstable")]
+pub fn load_keyring(
+    se: &mut dyn SecureElement,
+    slots: &[u16],
+    keyring: crate::audit::Keyring,
+) -> Result<crate::audit::Keyring> {
+    slots.iter().try_fold(keyring, |keyring, &slot| {
+        Ok(keyring.with_key(se.read_key(slot)?))
+    })
+}
+
+/// Rollback protection backed by secure element counters.
+pub struct AntiRollback<S> {
+    se: S,
+    /// Counter guarding each module.
+    counters: Vec<(ModuleId, u16)>,
+}
+
+impl<S: SecureElement> AntiRollback<S> {
+    pub fn new(se: S) -> Self {
+        Self {
+            se,
+            counters: Vec::new(),
+        }
+    }
+
+    /// Guards `module_id` with `counter` (builder style).
+    pub fn with_counter(mut self, module_id: ModuleId, counter: u16) -> Self {
+        self.counters.retain(|(id, _)| *id != module_id);
+        self.counters.push((module_id, counter));
+        self
+    }
+
+    fn counter_for(&self, manifest: &Manifest<'_>) -> Option<u16> {
+        if manifest.flags & FLAG_ROLLBACK_PROTECTED == 0 {
+            return None;
+        }
+        self.counters
+            .iter()
+            .find(|(id, _)| *id == manifest.module_id)
+            .map(|(_, counter)| *counter)
+    }
+
+    /// Refuses a rollback-protected manifest whose sequence is not above
+    /// its module's counter. Unguarded modules pass.
+    pub fn check(&mut self, manifest: &Manifest<'_>) -> Result<()> {
+        let Some(counter) = self.counter_for(manifest) else {
+            return Ok(());
+        };
+        if manifest.sequence <= self.se.read_counter(counter)? {
+            return Err(Error::Engine("se: sequence not above the counter"));
+        }
+        Ok(())
+    }
+
+    /// Raises the module's counter to the manifest's sequence once it is
+    /// installed, so older images are refused from now on. Returns the
+    /// counter's value (unguarded modules: the sequence). Counters move one
+    /// step per command (20 ms on an ATECC608), so keep sequences dense.
+    pub fn commit(&mut self, manifest: &Manifest<'_>) -> Result<u32> {
+        let Some(counter) = self.counter_for(manifest) else {
+            return Ok(manifest.sequence);
+        };
+        let mut value = self.se.read_counter(counter)?;
+        while value < manifest.sequence {
+            value = self.se.increment_counter(counter)?;
+        }
+        Ok(value)
+    }
+
+    pub fn secure_element(&mut self) -> &mut S {
+        &mut self.se
+    }
+}
+
+#[cfg(all(test, feature = "std"))]
+mod tests {
+    use super::*;
+    use crate::manifest::encode;
+    use embedded_hal::i2c::{ErrorKind, ErrorType, Operation};
+
+    /// An ATECC608 answering `Read` from `slots` and `Counter` from `counters`.
+    #[derive(Default)]
+    struct Chip {
+        slots: [[u8; 32]; 16],
+        counters: [u32; 2],
+        pending: Vec<u8>,
+    }
+
+    impl Chip {
+        fn respond(&mut self, data: &[u8]) {
+            let mut frame = vec![data.len() as u8 + 3];
+            frame.extend(data);
+            frame.extend(crc16(&frame).to_le_bytes());
+            self.pending = frame;
+        }
+    }
+
+    impl ErrorType for Chip {
+        type Error = ErrorKind;
+    }
+
+    impl I2c for Chip {
+        fn transaction(
+            &mut self,
+            address: u8,
+            operations: &mut [Operation<'_>],
+        ) -> core::result::Result<(), ErrorKind> {
+            if address != ATECC_ADDRESS {
+                return Err(ErrorKind::Other);
+            }
+            for op in operations {
+                match op {
+                    Operation::Write([WORD_COMMAND, 7, opcode, p1, p2, 0, crc @ ..]) => {
+                        assert_eq!(crc16(&[7, *opcode, *p1, *p2, 0]).to_le_bytes(), *crc);
+                        match (*opcode, *p1) {
+                            (OP_READ, ZONE_DATA_32) => {
+                                let slot = self.slots[usize::from(*p2 >> 3)];
+                                self.respond(&slot);
+                            }
+                            (OP_COUNTER, mode) => {
+                                let value = &mut self.counters[usize::from(*p2)];
+                                *value += u32::from(mode);
+                                let value = *value;
+                                self.respond(&value.to_le_bytes());
+                            }
+                            _ => self.respond(&[0x03]),
+                        }
+                    }
+                    Operation::Write(_) => {}
+                    Operation::Read(buf) if self.pending.is_empty() => {
+                        buf.copy_from_slice(&WAKE_RESPONSE[..buf.len()]);
+                    }
+                    Operation::Read(buf) => {
+                        let len = self.pending.len().min(buf.len());
+                        buf[..len].copy_from_slice(&self.pending[..len]);
+                        self.pending.clear();
+                    }
+                }
+            }
+            Ok(())
+        }
+    }
+
+    struct NoDelay;
+
+    impl DelayNs for NoDelay {
+        fn delay_ns(&mut self, _ns: u32) {}
+    }
+
+    #[test]
+    fn atecc_keys_and_counters_guard_updates() {
+        // The wake response ends with the CRC of its first two bytes.
+        assert_eq!(crc16(&WAKE_RESPONSE[..2]).to_le_bytes(), WAKE_RESPONSE[2..]);
+        let mut chip = Chip::default();
+        chip.slots[9] = [0xab; 32];
+        chip.counters[1] = 3;
+        let mut se = Atecc608::new(chip, NoDelay);
+        assert_eq!(se.read_key(9), Ok([0xab; 32]));
+        assert_eq!(se.read_counter(1), Ok(3));
+        assert_eq!(
+            se.read_counter(2),
+            Err(Error::Engine("se: no such counter"))
+        );
+        #[cfg(feature = "unstable")]
+        assert_eq!(
+            load_keyring(&mut se, &[9], crate::audit::Keyring::new())
+                .unwrap()
+                .keys(),
+            [[0xab; 32]]
+        );
+
+        let blob = |sequence| encode(5, "main", b"\0asm", FLAG_ROLLBACK_PROTECTED, sequence, None);
+        let (old, new) = (blob(3).unwrap(), blob(6).unwrap());
+        let (old, new) = (
+            Manifest::parse(&old).unwrap().0,
+            Manifest::parse(&new).unwrap().0,
+        );
+        let mut guard = AntiRollback::new(se).with_counter(5, 1);
+        assert_eq!(
+            guard.check(&old),
+            Err(Error::Engine("se: sequence not above the counter"))
+        );
+        assert_eq!(guard.check(&new), Ok(()));
+        assert_eq!(guard.commit(&new), Ok(6));
+        assert!(guard.check(&new).is_err());
+        // Unguarded modules are left to the rest of the update path.
+        let other = encode(8, "main", b"\0asm", FLAG_ROLLBACK_PROTECTED, 1, None).unwrap();
+        assert_eq!(guard.check(&Manifest::parse(&other).unwrap().0), Ok(()));
+    }
+}
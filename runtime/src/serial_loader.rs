@@ -0,0 +1,277 @@
+//! Bring-up module loader over a UART (`serial-loader` feature).
+//!
+//! Speaks XMODEM-CRC (and XMODEM-1K), so `sx`, `lrzsz`, minicom or
+//! TeraTerm can push a `.smny` blob onto a dev board before any network OTA
+//! path exists. `SerialLoader` is byte-driven: feed it every received byte
+//! and write back what `feed` returns; call `tick` from the UART's receive
+//! timeout (about once a second) so it can ask the sender to start (`C`) or
+//! to resend a stalled block (`NAK`).
+//!
+//! After the sender's `EOT` the blob is trimmed of XMODEM's padding, parsed,
+//! checked with the verification hook and installed through a `ModuleSink`;
+//! the transfer is acknowledged only if that worked, and cancelled otherwise.
+//! The result is kept for `take_outcome`. Manifests must be v2 or later, as
+//! the padding leaves v1 headers ambiguous.
+
+use crate::manifest::Manifest;
+use crate::{write_module, Error, ModuleId, ModuleSink, Result};
+use alloc::vec::Vec;
+
+pub const SOH: u8 = 0x01;
+pub const STX: u8 = 0x02;
+pub const EOT: u8 = 0x04;
+pub const ACK: u8 = 0x06;
+pub const NAK: u8 = 0x15;
+pub const CAN: u8 = 0x18;
+/// Sent by the receiver to ask for a CRC transfer.
+pub const CRC_MODE: u8 = b'C';
+
+/// Module installed by a finished transfer.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Installed {
+    pub module_id: ModuleId,
+    pub sequence: u32,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum State {
+    /// No block received yet.
+    Waiting,
+    /// Between blocks.
+    Ready,
+    /// Collecting block number, its complement, `len` data bytes and the CRC.
+    Block { len: usize },
+}
+
+/// XMODEM-CRC receiver installing into a `ModuleSink`.
+pub struct SerialLoader<K, V> {
+    sink: K,
+    verify: V,
+    max_len: usize,
+    state: State,
+    next_block: u8,
+    block: Vec<u8>,
+    image: Vec<u8>,
+    outcome: Option<Result<Installed>>,
+}
+
+impl<K, V> SerialLoader<K, V>
+where
+    K: ModuleSink,
+    V: FnMut(&Manifest<'_>, &[u8]) -> Result<()>,
+{
+    /// Installs into `sink`, accepting blobs up to `max_len` bytes whose
+    /// manifest and module pass `verify`.
+    pub fn new(sink: K, max_len: usize, verify: V) -> Self {
+        Self {
+            sink,
+            verify,
+            max_len,
+            state: State::Waiting,
+            next_block: 1,
+            block: Vec::new(),
+            image: Vec::new(),
+            outcome: None,
+        }
+    }
+
+    pub fn sink(&self) -> &K {
+        &self.sink
+    }
+
+    pub fn sink_mut(&mut self) -> &mut K {
+        &mut self.sink
+    }
+
+    /// Result of the last finished or cancelled transfer, once.
+    pub fn take_outcome(&mut self) -> Option<Result<Installed>> {
+        self.outcome.take()
+    }
+
+    /// Receive timeout: bytes to send to the sender.
+    pub fn tick(&mut self) -> &'static [u8] {
+        match self.state {
+            State::Waiting => &[CRC_MODE],
+            State::Ready => &[],
+            // A block stalled halfway: drop it and ask again.
+            State::Block { .. } => {
+                self.state = State::Ready;
+                &[NAK]
+            }
+        }
+    }
+
+    /// Handles one received byte; returns the bytes to send back.
+    pub fn feed(&mut self, byte: u8) -> &'static [u8] {
+        match self.state {
+            State::Waiting | State::Ready => self.header(byte),
+            State::Block { len } => {
+                self.block.push(byte);
+                if self.block.len() < len + 4 {
+                    return &[];
+                }
+                self.state = State::Ready;
+                self.block_done(len)
+            }
+        }
+    }
+
+    fn header(&mut self, byte: u8) -> &'static [u8] {
+        let len = match byte {
+            SOH => 128,
+            STX => 1024,
+            EOT if self.state == State::Ready => return self.finish(),
+            CAN if self.state == State::Ready => {
+                self.fail(Error::Engine("serial: cancelled by sender"));
+                return &[];
+            }
+            // Line noise between blocks.
+            _ => return &[],
+        };
+        self.block.clear();
+        self.state = State::Block { len };
+        &[]
+    }
+
+    fn block_done(&mut self, len: usize) -> &'static [u8] {
+        let (number, complement) = (self.block[0], self.block[1]);
+        let (data, crc) = self.block[2..].split_at(len);
+        if number != !complement || crc16(data) != u16::from_be_bytes([crc[0], crc[1]]) {
+            return &[NAK];
+        }
+        if number == self.next_block.wrapping_sub(1) && !self.image.is_empty() {
+            // Our ACK was lost; the sender repeated the block.
+            return &[ACK];
+        }
+        if number != self.next_block {
+            self.fail(Error::Engine("serial: block out of sequence"));
+            return &[CAN, CAN];
+        }
+        if self.image.len() + len > self.max_len {
+            self.fail(Error::Engine("serial: image too large"));
+            return &[CAN, CAN];
+        }
+        if let Err(error) = crate::fallible::extend(&mut self.image, data) {
+            self.fail(error);
+            return &[CAN, CAN];
+        }
+        self.next_block = self.next_block.wrapping_add(1);
+        &[ACK]
+    }
+
+    fn finish(&mut self) -> &'static [u8] {
+        let result = self.install();
+        let reply: &'static [u8] = if result.is_ok() { &[ACK] } else { &[CAN, CAN] };
+        self.reset();
+        self.outcome = Some(result);
+        reply
+    }
+
+    fn install(&mut self) -> Result<Installed> {
+        let (manifest, module) = Manifest::parse_at(&self.image, 0)?;
+        (self.verify)(&manifest, module)?;
+        write_module(&mut self.sink, manifest.module_id, module)?;
+        Ok(Installed {
+            module_id: manifest.module_id,
+            sequence: manifest.sequence,
+        })
+    }
+
+    fn fail(&mut self, error: Error) {
+        self.reset();
+        self.outcome = Some(Err(error));
+    }
+
+    fn reset(&mut self) {
+        self.state = State::Waiting;
+        self.next_block = 1;
+        self.block = Vec::new();
+        self.image = Vec::new();
+    }
+}
+
+/// CRC-16/XMODEM (polynomial 0x1021, initial value 0).
+pub fn crc16(data: &[u8]) -> u16 {
+    let mut crc = 0u16;
+    for &byte in data {
+        crc ^= (byte as u16) << 8;
+        for _ in 0..8 {
+            crc = if crc & 0x8000 != 0 {
+                (crc << 1) ^ 0x1021
+            } else {
+                crc << 1
+            };
+        }
+    }
+    crc
+}
+
+#[cfg(all(test, feature = "std"))]
+mod tests {
+    use super::*;
+    use crate::manifest::encode;
+    use crate::{MemoryStore, ModuleSource};
+
+    const WASM: &[u8] = b"\0asm\x01\0\0\0";
+
+    fn block(number: u8, data: &[u8]) -> Vec<u8> {
+        let mut padded = [0x1a; 128];
+        padded[..data.len()].copy_from_slice(data);
+        let crc = crc16(&padded).to_be_bytes();
+        [&[SOH, number, !number][..], &padded, &crc].concat()
+    }
+
+    fn feed_all<K, V>(loader: &mut SerialLoader<K, V>, bytes: &[u8]) -> Vec<u8>
+    where
+        K: ModuleSink,
+        V: FnMut(&Manifest<'_>, &[u8]) -> Result<()>,
+    {
+        bytes
+            .iter()
+            .flat_map(|&b| loader.feed(b).to_vec())
+            .collect()
+    }
+
+    #[test]
+    fn receives_padded_blocks_and_installs() {
+        assert_eq!(crc16(b"123456789"), 0x31c3);
+
+        let module: Vec<u8> = WASM.iter().copied().cycle().take(200).collect();
+        let blob = encode(4, "main", &module, 0, 2, None).unwrap();
+        let mut loader = SerialLoader::new(
+            MemoryStore::new(),
+            4096,
+            |_: &Manifest<'_>, _: &[u8]| Ok(()),
+        );
+        assert_eq!(loader.tick(), [CRC_MODE]);
+
+        let first = block(1, &blob[..128]);
+        let mut corrupt = first.clone();
+        corrupt[10] ^= 1;
+        assert_eq!(feed_all(&mut loader, &corrupt), [NAK]);
+        assert_eq!(feed_all(&mut loader, &first), [ACK]);
+        // A repeat after a lost ACK is acknowledged but not appended.
+        assert_eq!(feed_all(&mut loader, &first), [ACK]);
+        // A stalled block is dropped on timeout.
+        feed_all(&mut loader, &[SOH, 2, !2, 0]);
+        assert_eq!(loader.tick(), [NAK]);
+        assert_eq!(feed_all(&mut loader, &block(2, &blob[128..])), [ACK]);
+        assert_eq!(loader.take_outcome(), None);
+        assert_eq!(loader.feed(EOT), [ACK]);
+
+        assert_eq!(
+            loader.take_outcome(),
+            Some(Ok(Installed {
+                module_id: 4,
+                sequence: 2
+            }))
+        );
+        assert_eq!(loader.sink().fetch(4), Some(&module[..]));
+
+        // Garbage is cancelled once the sender ends the transfer.
+        assert_eq!(feed_all(&mut loader, &block(1, b"junk")), [ACK]);
+        assert_eq!(loader.feed(EOT), [CAN, CAN]);
+        assert!(matches!(loader.take_outcome(), Some(Err(_))));
+        assert_eq!(feed_all(&mut loader, &block(3, &blob[..128])), [CAN, CAN]);
+    }
+}
@@ -0,0 +1,242 @@
+//! Thread-safe runtime handle for multi-threaded std hosts.
+//!
+//! `Runtime` is single-threaded by design (tracing and nesting share `Rc`
+//! state, sinks and stores are plain boxes), so it cannot sit behind a
+//! `Mutex` shared across threads. `SharedRuntime` instead gives every module
+//! its own lane: a thread that builds a `Runtime` with the factory on first
+//! use and runs that module's invocations one after another. Calls for the
+//! same module queue behind each other; calls for different modules run in
+//! parallel, so one long invocation does not hold up the rest. The handle
+//! itself is `Send + Sync` and cheap to clone.
+//!
+//! Each lane's runtime keeps its own engine, quotas, switches and sinks. The
+//! factory decides what lanes share, e.g. one `Arc<MemoryStore>` as the
+//! source. After replacing a module in that store, `retire` its lane so the
+//! next call starts from the new bytes.
+
+use crate::{Engine, Error, ModuleId, ModuleSource, Result, Runtime};
+use std::collections::HashMap;
+use std::string::String;
+use std::sync::mpsc::{self, Sender};
+use std::sync::{Arc, Mutex};
+use std::thread;
+
+type Job<E, S> = Box<dyn FnOnce(&mut Runtime<E, S>) + Send>;
+type Factory<E, S> = dyn Fn(ModuleId) -> Runtime<E, S> + Send + Sync;
+
+/// Per-module lanes over runtimes built by a factory.
+pub struct SharedRuntime<E, S> {
+    inner: Arc<Inner<E, S>>,
+}
+
+struct Inner<E, S> {
+    factory: Arc<Factory<E, S>>,
+    lanes: Mutex<HashMap<ModuleId, Sender<Job<E, S>>>>,
+}
+
+impl<E, S> Clone for SharedRuntime<E, S> {
+    fn clone(&self) -> Self {
+        Self {
+            inner: self.inner.clone(),
+        }
+    }
+}
+
+impl<E, S> SharedRuntime<E, S>
+where
+    E: Engine + 'static,
+    S: ModuleSource + 'static,
+{
+    /// `factory` builds the runtime of a module's lane, on the lane's thread.
+    pub fn new(factory: impl Fn(ModuleId) -> Runtime<E, S> + Send + Sync + 'static) -> Self {
+        Self {
+            inner: Arc::new(Inner {
+                factory: Arc::new(factory),
+                lanes: Mutex::new(HashMap::new()),
+            }),
+        }
+    }
+
+    /// Runs `job` on the module's runtime, after the calls queued before it,
+    /// and waits for its result. A job that panics stops the lane; the next
+    /// call starts a new one.
+    pub fn run<R: Send + 'static>(
+        &self,
+        module_id: ModuleId,
+        job: impl FnOnce(&mut Runtime<E, S>) -> R + Send + 'static,
+    ) -> Result<R> {
+        let (reply, result) = mpsc::sync_channel(1);
+        let mut job: Job<E, S> = Box::new(move |runtime| {
+            // The caller is blocked on `result` until this is sent.
+            let _ = reply.send(job(runtime));
+        });
+        // A lane found stopped is replaced once.
+        for _ in 0..2 {
+            match self.lane(module_id)?.send(job) {
+                Ok(()) => {
+                    return result.recv().map_err(|_| {
+                        self.retire(module_id);
+                        Error::Engine("shared: invocation panicked")
+                    })
+                }
+                Err(mpsc::SendError(unsent)) => {
+                    self.retire(module_id);
+                    job = unsent;
+                }
+            }
+        }
+        Err(Error::Engine("shared: lane stopped"))
+    }
+
+    /// `Runtime::execute` on the module's lane. The context is moved there
+    /// for the call and back; it is left at its default if the call panics.
+    pub fn execute(&self, module_id: ModuleId, entry: &str, ctx: &mut E::Context) -> Result<()>
+    where
+        E::Context: Default + Send + 'static,
+    {
+        let entry = String::from(entry);
+        let mut sent = core::mem::take(ctx);
+        let (result, returned) = self.run(module_id, move |runtime| {
+            let result = runtime.execute(module_id, &entry, &mut sent);
+            (result, sent)
+        })?;
+        *ctx = returned;
+        result
+    }
+
+    /// Stops the module's lane once its queued calls are done; the next call
+    /// builds a fresh runtime. Returns whether a lane was running.
+    pub fn retire(&self, module_id: ModuleId) -> bool {
+        self.lanes().remove(&module_id).is_some()
+    }
+
+    /// Modules with a running lane.
+    pub fn lane_count(&self) -> usize {
+        self.lanes().len()
+    }
+
+    fn lanes(&self) -> std::sync::MutexGuard<'_, HashMap<ModuleId, Sender<Job<E, S>>>> {
+        // A panic while holding the map cannot leave it half-updated.
+        self.inner
+            .lanes
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    fn lane(&self, module_id: ModuleId) -> Result<Sender<Job<E, S>>> {
+        let mut lanes = self.lanes();
+        if let Some(lane) = lanes.get(&module_id) {
+            return Ok(lane.clone());
+        }
+        let (lane, jobs) = mpsc::channel::<Job<E, S>>();
+        let factory = self.inner.factory.clone();
+        thread::Builder::new()
+            .name(std::format!("slimmy-module-{module_id}"))
+            .spawn(move || {
+                let mut runtime = factory(module_id);
+                for job in jobs {
+                    job(&mut runtime);
+                }
+            })
+            .map_err(|_| Error::Engine("shared: cannot spawn lane"))?;
+        lanes.insert(module_id, lane.clone());
+        Ok(lane)
+    }
+}
+
+/// Lets lanes share one store.
+impl<T: ModuleSource + ?Sized> ModuleSource for Arc<T> {
+    fn fetch(&self, id: ModuleId) -> Option<&[u8]> {
+        (**self).fetch(id)
+    }
+}
+
+#[cfg(all(test, feature = "std"))]
+mod tests {
+    use super::*;
+    use crate::MemoryStore;
+    use std::sync::mpsc::Receiver;
+    use std::time::Duration;
+
+    /// Module 1 waits for module 2's signal, which it only gets if the two
+    /// run at the same time.
+    struct Handshake;
+
+    #[derive(Default)]
+    struct Ctx {
+        wait: Option<Receiver<()>>,
+        signal: Option<Sender<()>>,
+        calls: u32,
+    }
+
+    impl Engine for Handshake {
+        type ModuleHandle = ModuleId;
+        type Context = Ctx;
+
+        fn load(&mut self, id: ModuleId, _module: &[u8]) -> Result<ModuleId> {
+            Ok(id)
+        }
+
+        fn invoke(&mut self, _handle: ModuleId, entry: &str, ctx: &mut Ctx) -> Result<()> {
+            if entry == "panic" {
+                panic!("guest bug");
+            }
+            ctx.calls += 1;
+            if let Some(signal) = &ctx.signal {
+                signal.send(()).unwrap();
+            }
+            match &ctx.wait {
+                Some(wait) => wait
+                    .recv_timeout(Duration::from_secs(10))
+                    .map_err(|_| Error::Engine("not concurrent")),
+                None => Ok(()),
+            }
+        }
+    }
+
+    #[test]
+    fn different_modules_run_concurrently() {
+        fn assert_send_sync<T: Send + Sync>() {}
+        assert_send_sync::<SharedRuntime<Handshake, Arc<MemoryStore>>>();
+
+        let mut store = MemoryStore::new();
+        store.upsert(1, vec![0]);
+        store.upsert(2, vec![0]);
+        let store = Arc::new(store);
+        let shared = SharedRuntime::new(move |_| Runtime::new(Handshake, store.clone()));
+
+        let (signal, wait) = mpsc::channel();
+        let slow = {
+            let shared = shared.clone();
+            thread::spawn(move || {
+                let mut ctx = Ctx {
+                    wait: Some(wait),
+                    ..Ctx::default()
+                };
+                let result = shared.execute(1, "main", &mut ctx);
+                (result, ctx.calls)
+            })
+        };
+        let mut ctx = Ctx {
+            signal: Some(signal),
+            ..Ctx::default()
+        };
+        shared.execute(2, "main", &mut ctx).unwrap();
+        assert_eq!(ctx.calls, 1);
+        assert_eq!(slow.join().unwrap(), (Ok(()), 1));
+        assert_eq!(shared.lane_count(), 2);
+
+        // A panicking call takes down only its own lane.
+        assert_eq!(
+            shared.execute(2, "panic", &mut Ctx::default()),
+            Err(Error::Engine("shared: invocation panicked"))
+        );
+        assert_eq!(shared.lane_count(), 1);
+        shared.execute(2, "main", &mut Ctx::default()).unwrap();
+        assert_eq!(
+            shared.run(3, |rt| rt.execute(3, "main", &mut Ctx::default())),
+            Ok(Err(Error::ModuleNotFound))
+        );
+        assert!(shared.retire(3));
+    }
+}
@@ -0,0 +1,250 @@
+//! Skips repeat Ed25519 checks for modules that have not changed.
+//!
+//! Verifying every module's signature on every boot is slow on small cores.
+//! `verify_cached` first hashes the signed material with BLAKE3 (much cheaper)
+//! and compares it with the digest remembered for that module in a
+//! `VerifiedDigests` store; only a mismatch runs the full Ed25519 check, and
+//! a successful check records the new digest. The digest covers the public
+//! key, manifest header, signature and module bytes, so any changed byte (or
+//! a different trusted key) forces full verification.
+//!
+//! A persisted cache lives where something other than the runtime might
+//! write it, so `KvDigests` stores each digest as a BLAKE3 MAC under a
+//! device secret: without the secret a forged record never matches.
+
+use crate::idmap::IdMap;
+use crate::kv::KvStore;
+use crate::manifest::{ct_eq, verify_ed25519, Manifest};
+use crate::{Error, ModuleId, Result};
+
+/// BLAKE3 digest of everything a signature check depends on.
+pub type Digest = [u8; 32];
+
+/// Namespace `KvDigests` writes under; reserved (`kv::RESERVED_NAMESPACES`),
+/// so guests cannot reach it.
+pub const SIGCACHE_NAMESPACE: ModuleId = ModuleId::MAX - 1;
+const _: () = assert!(crate::kv::is_reserved(SIGCACHE_NAMESPACE));
+
+/// Last digest verified per module; persist it to skip checks across boots.
+pub trait VerifiedDigests {
+    /// True when `digest` is the one recorded for a module.
+    fn contains(&self, module_id: ModuleId, digest: &Digest) -> Result<bool>;
+
+    /// Records a freshly verified digest.
+    fn set(&mut self, module_id: ModuleId, digest: Digest) -> Result<()>;
+
+    /// Forgets a module (e.g. when it is removed from storage).
+    fn forget(&mut self, module_id: ModuleId) -> Result<()>;
+}
+
+/// RAM-only cache (survives re-verification within one boot).
+#[derive(Debug, Default, Clone)]
+pub struct MemoryDigests {
+    digests: IdMap<Digest>,
+}
+
+impl MemoryDigests {
+    /// Creates an empty cache.
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+impl VerifiedDigests for MemoryDigests {
+    fn contains(&self, module_id: ModuleId, digest: &Digest) -> Result<bool> {
+        Ok(self
+            .digests
+            .get(module_id)
+            .is_some_and(|cached| ct_eq(cached, digest)))
+    }
+
+    fn set(&mut self, module_id: ModuleId, digest: Digest) -> Result<()> {
+        self.digests.insert(module_id, digest).map(|_| ())
+    }
+
+    fn forget(&mut self, module_id: ModuleId) -> Result<()> {
+        self.digests.remove(module_id);
+        Ok(())
+    }
+}
+
+/// Cache persisted in a `KvStore` (NVS, flash), one record per module.
+///
+/// Records are MACs of the digest under `secret`, a device-unique key (from
+/// a secure element, OTP or the key store) that never leaves the device.
+#[derive(Clone)]
+pub struct KvDigests<K> {
+    store: K,
+    secret: [u8; 32],
+}
+
+impl<K> core::fmt::Debug for KvDigests<K> {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.debug_struct("KvDigests").finish_non_exhaustive()
+    }
+}
+
+impl<K: KvStore> KvDigests<K> {
+    /// Wraps a store; records are authenticated with `secret`.
+    pub fn new(store: K, secret: [u8; 32]) -> Self {
+        Self { store, secret }
+    }
+
+    /// Underlying store.
+    pub fn into_inner(self) -> K {
+        self.store
+    }
+
+    fn tag(&self, module_id: ModuleId, digest: &Digest) -> Digest {
+        let mut mac = blake3::Hasher::new_keyed(&self.secret);
+        mac.update(&module_id.to_le_bytes());
+        mac.update(digest);
+        *mac.finalize().as_bytes()
+    }
+}
+
+impl<K: KvStore> VerifiedDigests for KvDigests<K> {
+    fn contains(&self, module_id: ModuleId, digest: &Digest) -> Result<bool> {
+        let mut tag = [0; 32];
+        match self
+            .store
+            .get(SIGCACHE_NAMESPACE, &module_id.to_le_bytes(), &mut tag)?
+        {
+            Some(32) => Ok(ct_eq(&tag, &self.tag(module_id, digest))),
+            Some(_) => Err(Error::Engine("sigcache: bad record length")),
+            None => Ok(false),
+        }
+    }
+
+    fn set(&mut self, module_id: ModuleId, digest: Digest) -> Result<()> {
+        let tag = self.tag(module_id, &digest);
+        self.store
+            .set(SIGCACHE_NAMESPACE, &module_id.to_le_bytes(), &tag)
+    }
+
+    fn forget(&mut self, module_id: ModuleId) -> Result<()> {
+        self.store
+            .remove(SIGCACHE_NAMESPACE, &module_id.to_le_bytes())
+            .map(|_| ())
+    }
+}
+
+/// How `verify_cached` accepted a module.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Verified {
+    /// Digest matched the cache; Ed25519 was skipped.
+    Cached,
+    /// Full Ed25519 check passed and the digest was recorded.
+    Checked,
+}
+
+/// Digest of the material a signature check covers.
+pub fn digest(manifest: &Manifest<'_>, module: &[u8], pubkey: &[u8; 32]) -> Result<Digest> {
+    let signature = manifest
+        .signature
+        .ok_or(Error::Engine("manifest missing signature"))?;
+    let mut hasher = blake3::Hasher::new();
+    hasher.update(pubkey);
+    hasher.update(manifest.signed_header());
+    hasher.update(signature);
+    hasher.update(module);
+    Ok(*hasher.finalize().as_bytes())
+}
+
+/// `verify_ed25519`, skipped when the module's digest is already cached.
+///
+/// A failed check leaves the cache untouched: the digest includes the key, so
+/// trying each key of a keyring in turn never evicts a good record.
+pub fn verify_cached(
+    cache: &mut impl VerifiedDigests,
+    manifest: &Manifest<'_>,
+    module: &[u8],
+    pubkey: &[u8; 32],
+) -> Result<Verified> {
+    let digest = digest(manifest, module, pubkey)?;
+    if cache.contains(manifest.module_id, &digest)? {
+        return Ok(Verified::Cached);
+    }
+    verify_ed25519(manifest, module, pubkey)?;
+    cache.set(manifest.module_id, digest)?;
+    Ok(Verified::Checked)
+}
+
+#[cfg(all(test, feature = "std"))]
+mod tests {
+    use super::*;
+    use crate::kv::MemoryKv;
+    use crate::manifest::{encode, signing_preimage, FLAG_REQUIRE_SIGNATURE};
+    use ed25519_dalek::{Signer, SigningKey};
+
+    const SECRET: [u8; 32] = [9; 32];
+
+    fn signed(module: &[u8], key: &SigningKey) -> Vec<u8> {
+        let preimage = signing_preimage(4, "main", module, FLAG_REQUIRE_SIGNATURE, 1).unwrap();
+        let sig = key.sign(&preimage).to_bytes();
+        encode(4, "main", module, FLAG_REQUIRE_SIGNATURE, 1, Some(sig)).unwrap()
+    }
+
+    #[test]
+    fn unchanged_modules_skip_verification() {
+        let key = SigningKey::from_bytes(&[3u8; 32]);
+        let pubkey = key.verifying_key().to_bytes();
+        let mut cache = KvDigests::new(MemoryKv::new(), SECRET);
+
+        let blob = signed(&[1, 2, 3], &key);
+        let (manifest, module) = Manifest::parse(&blob).unwrap();
+        assert_eq!(
+            verify_cached(&mut cache, &manifest, module, &pubkey),
+            Ok(Verified::Checked)
+        );
+        assert_eq!(
+            verify_cached(&mut cache, &manifest, module, &pubkey),
+            Ok(Verified::Cached)
+        );
+
+        // Another key never hits the cache; the good record survives a "reboot".
+        let other = SigningKey::from_bytes(&[4u8; 32])
+            .verifying_key()
+            .to_bytes();
+        assert!(verify_cached(&mut cache, &manifest, module, &other).is_err());
+        let mut cache = KvDigests::new(cache.into_inner(), SECRET);
+        assert_eq!(
+            verify_cached(&mut cache, &manifest, module, &pubkey),
+            Ok(Verified::Cached)
+        );
+        let good = digest(&manifest, module, &pubkey).unwrap();
+        cache.forget(4).unwrap();
+        assert_eq!(cache.contains(4, &good), Ok(false));
+
+        let mut tampered = blob.clone();
+        *tampered.last_mut().unwrap() ^= 1;
+        let (manifest, module) = Manifest::parse(&tampered).unwrap();
+        let mut cache = MemoryDigests::new();
+        cache.set(4, [0; 32]).unwrap();
+        assert!(verify_cached(&mut cache, &manifest, module, &pubkey).is_err());
+    }
+
+    #[test]
+    fn forged_records_do_not_skip_verification() {
+        let key = SigningKey::from_bytes(&[3u8; 32]);
+        let pubkey = key.verifying_key().to_bytes();
+        let mut forged = signed(&[1, 2, 3], &key);
+        *forged.last_mut().unwrap() ^= 1;
+        let (manifest, module) = Manifest::parse(&forged).unwrap();
+        let unkeyed = digest(&manifest, module, &pubkey).unwrap();
+
+        // Whoever writes the store records the forged image's digest...
+        let mut kv = MemoryKv::new();
+        kv.set(SIGCACHE_NAMESPACE, &4u32.to_le_bytes(), &unkeyed)
+            .unwrap();
+        let mut cache = KvDigests::new(kv, SECRET);
+        // ...but it is no MAC under the device secret.
+        assert!(verify_cached(&mut cache, &manifest, module, &pubkey).is_err());
+
+        // Nor does a record made under another device's secret match.
+        let mut other = KvDigests::new(MemoryKv::new(), [1; 32]);
+        other.set(4, unkeyed).unwrap();
+        let mut cache = KvDigests::new(other.into_inner(), SECRET);
+        assert!(verify_cached(&mut cache, &manifest, module, &pubkey).is_err());
+    }
+}
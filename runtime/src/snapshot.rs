@@ -0,0 +1,217 @@
+//! Instance state for hibernation: linear memory and mutable globals.
+//!
+//! Engines instantiate a module afresh for every call. With
+//! `EngineConfig::keep_state`, those that support it (wasmi, wasmtime-lite)
+//! instead start each call from the state the module's previous successful
+//! call left, as if it had been instantiated once. `Engine::snapshot` encodes
+//! that state and `Engine::restore` replaces it, so a device can write its
+//! modules' state to flash before deep sleep and resume them on wake without
+//! rerunning their initialization. Data segments and the start function still
+//! run on every instantiation; the kept state then overwrites what they set.
+//!
+//! Only exported globals are reachable through the backends' APIs, so a
+//! module that keeps state in globals must export them. A call that traps
+//! leaves the state of the call before it.
+//!
+//! Encoded layout (little-endian):
+//!
+//! | field                    | type                                   |
+//! |--------------------------|----------------------------------------|
+//! | magic `SSNP`             | 4 bytes                                |
+//! | version                  | u8 (= 1)                               |
+//! | global count `g`         | u16                                    |
+//! | globals                  | `g` × (name length u8, name, type u8, value u64) |
+//! | memory length `m`        | u32                                    |
+//! | memory                   | `m` bytes                              |
+
+use crate::{Error, Result};
+use alloc::string::String;
+use alloc::vec::Vec;
+
+/// Leading bytes of an encoded `InstanceState`.
+pub const SNAPSHOT_MAGIC: &[u8; 4] = b"SSNP";
+/// Encoding version.
+pub const SNAPSHOT_VERSION: u8 = 1;
+
+const BAD_SNAPSHOT: Error = Error::Engine("snapshot: bad encoding");
+
+/// Value of a mutable global; floats are kept as their bits.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum GlobalValue {
+    I32(i32),
+    I64(i64),
+    F32(u32),
+    F64(u64),
+}
+
+impl GlobalValue {
+    fn tag(&self) -> u8 {
+        match self {
+            GlobalValue::I32(_) => 0,
+            GlobalValue::I64(_) => 1,
+            GlobalValue::F32(_) => 2,
+            GlobalValue::F64(_) => 3,
+        }
+    }
+
+    fn bits(&self) -> u64 {
+        match *self {
+            GlobalValue::I32(v) => v as u32 as u64,
+            GlobalValue::I64(v) => v as u64,
+            GlobalValue::F32(v) => v as u64,
+            GlobalValue::F64(v) => v,
+        }
+    }
+
+    fn from_parts(tag: u8, bits: u64) -> Option<Self> {
+        Some(match tag {
+            0 => GlobalValue::I32(bits as u32 as i32),
+            1 => GlobalValue::I64(bits as i64),
+            2 => GlobalValue::F32(bits as u32),
+            3 => GlobalValue::F64(bits),
+            _ => return None,
+        })
+    }
+}
+
+/// State of one instance.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct InstanceState {
+    /// Exported `memory`; empty when the module exports none.
+    pub memory: Vec<u8>,
+    /// Exported mutable globals by name.
+    pub globals: Vec<(String, GlobalValue)>,
+}
+
+impl InstanceState {
+    pub fn encode(&self) -> Vec<u8> {
+        let mut out = Vec::with_capacity(11 + self.memory.len());
+        out.extend_from_slice(SNAPSHOT_MAGIC);
+        out.push(SNAPSHOT_VERSION);
+        out.extend_from_slice(&(self.globals.len() as u16).to_le_bytes());
+        for (name, value) in &self.globals {
+            out.push(name.len() as u8);
+            out.extend_from_slice(name.as_bytes());
+            out.push(value.tag());
+            out.extend_from_slice(&value.bits().to_le_bytes());
+        }
+        out.extend_from_slice(&(self.memory.len() as u32).to_le_bytes());
+        out.extend_from_slice(&self.memory);
+        out
+    }
+
+    pub fn decode(bytes: &[u8]) -> Result<Self> {
+        let mut input = Reader { bytes };
+        if input.take(4)? != SNAPSHOT_MAGIC || input.take(1)? != [SNAPSHOT_VERSION] {
+            return Err(BAD_SNAPSHOT);
+        }
+        let count = u16::from_le_bytes(input.array()?);
+        let mut globals = Vec::with_capacity(count as usize);
+        for _ in 0..count {
+            let len = input.take(1)?[0] as usize;
+            let name = core::str::from_utf8(input.take(len)?).map_err(|_| BAD_SNAPSHOT)?;
+            let tag = input.take(1)?[0];
+            let bits = u64::from_le_bytes(input.array()?);
+            let value = GlobalValue::from_parts(tag, bits).ok_or(BAD_SNAPSHOT)?;
+            globals.push((name.into(), value));
+        }
+        let len = u32::from_le_bytes(input.array()?) as usize;
+        let memory = input.take(len)?.to_vec();
+        if !input.bytes.is_empty() {
+            return Err(BAD_SNAPSHOT);
+        }
+        Ok(Self { memory, globals })
+    }
+}
+
+struct Reader<'a> {
+    bytes: &'a [u8],
+}
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, len: usize) -> Result<&'a [u8]> {
+        if self.bytes.len() < len {
+            return Err(BAD_SNAPSHOT);
+        }
+        let (head, rest) = self.bytes.split_at(len);
+        self.bytes = rest;
+        Ok(head)
+    }
+
+    fn array<const N: usize>(&mut self) -> Result<[u8; N]> {
+        let mut out = [0u8; N];
+        out.copy_from_slice(self.take(N)?);
+        Ok(out)
+    }
+}
+
+#[cfg(all(test, feature = "std"))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn round_trips_and_rejects_bad_encodings() {
+        let state = InstanceState {
+            memory: vec![1, 2, 3],
+            globals: vec![
+                ("count".into(), GlobalValue::I32(-5)),
+                ("gain".into(), GlobalValue::F64(1.5f64.to_bits())),
+            ],
+        };
+        let bytes = state.encode();
+        assert_eq!(&bytes[..7], b"SSNP\x01\x02\x00");
+        assert_eq!(InstanceState::decode(&bytes), Ok(state));
+
+        assert!(InstanceState::decode(&bytes[..bytes.len() - 1]).is_err());
+        assert!(InstanceState::decode(&[bytes.as_slice(), &[0]].concat()).is_err());
+        let mut bad_tag = bytes.clone();
+        bad_tag[7 + 1 + 5] = 9;
+        assert!(InstanceState::decode(&bad_tag).is_err());
+    }
+
+    #[cfg(feature = "engine-wasmtime-lite")]
+    #[test]
+    fn runtime_resumes_a_wasmtime_module() {
+        use crate::abi::Imports;
+        use crate::builder::{ConfigurableEngine, EngineConfig};
+        use crate::engines::wasmtime_lite::WasmtimeLiteEngine;
+        use crate::{CachedEngine, MemoryStore, Runtime};
+
+        let wasm = wat::parse_str(
+            r#"(module
+                (memory (export "memory") 1)
+                (global $gain (export "gain") (mut f64) (f64.const 1))
+                (func (export "step") (result i32)
+                    (global.set $gain (f64.mul (global.get $gain) (f64.const 2)))
+                    (i32.store (i32.const 8) (i32.add (i32.load (i32.const 8)) (i32.const 1)))
+                    (i32.add (i32.load (i32.const 8)) (i32.trunc_f64_s (global.get $gain)))))"#,
+        )
+        .unwrap();
+        let config = EngineConfig {
+            keep_state: true,
+            ..EngineConfig::default()
+        };
+        let runtime = || {
+            let mut store = MemoryStore::new();
+            store.upsert(1, wasm.clone());
+            let engine = WasmtimeLiteEngine::from_config(&config, Imports::new()).unwrap();
+            Runtime::new(CachedEngine::new(engine), store)
+        };
+
+        let mut before = runtime();
+        assert_eq!(before.execute_status(1, "step", &mut ()), Ok(3));
+        assert_eq!(before.execute_status(1, "step", &mut ()), Ok(6));
+        let state = before.snapshot(1).unwrap();
+        let decoded = InstanceState::decode(&state).unwrap();
+        assert_eq!(decoded.memory.len(), 65536);
+        assert_eq!(
+            decoded.globals,
+            [("gain".into(), GlobalValue::F64(4f64.to_bits()))]
+        );
+
+        let mut after = runtime();
+        after.restore(1, &state).unwrap();
+        assert_eq!(after.execute_status(1, "step", &mut ()), Ok(11));
+        assert_eq!(after.snapshot(2), Err(Error::ModuleNotFound));
+    }
+}
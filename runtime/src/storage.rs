@@ -5,10 +5,18 @@
 //! - `IndexedSliceSource`: map multiple modules inside one region using offset/length.
 //! - `FlashBufferedSource`: simple flash-backed store that copies into RAM when fetched.
 //!
+//! - `index`: on-flash index describing where modules live in a region (layout + wear).
+//! - `fat`: verified modules read from files on an SD card (`storage-fat` feature).
+//! - `mmap`: verified modules served from memory-mapped files (`storage-mmap`, unix).
+//! - `store`: flash store writing modules through an `index`, with gc and compaction.
+//! - `quota`: per-module and total size limits enforced on install.
+//! - `bank`: dual-bank writer for raw NOR flash with a journaled index.
+//!
 //! The platform-specific glue (NVS/partition reads, STM32 QSPI, etc.) should
 //! create a slice over the flash region and feed it into one of these structs.
 
-use crate::{Error, ModuleId, ModuleSource, Result};
+use crate::manifest::Manifest;
+use crate::{Error, ModuleCatalog, ModuleId, ModuleSource, Result};
 #[cfg(feature = "std")]
 use std::fs::OpenOptions;
 #[cfg(feature = "std")]
@@ -20,6 +28,18 @@ compile_error!("Feature `esp-idf-storage` requires target_os=\"espidf\".");
 #[cfg(all(feature = "stm32-storage", target_os = "espidf"))]
 compile_error!("Feature `stm32-storage` is not compatible with espidf target.");
 
+#[cfg(feature = "alloc")]
+pub mod bank;
+#[cfg(feature = "storage-fat")]
+pub mod fat;
+pub mod index;
+#[cfg(all(feature = "storage-mmap", unix))]
+pub mod mmap;
+#[cfg(feature = "alloc")]
+pub mod quota;
+#[cfg(feature = "alloc")]
+pub mod store;
+
 /// Treats a single contiguous slice as one module with a fixed id.
 pub struct PartitionSliceSource<'a> {
     region: &'a [u8],
@@ -43,6 +63,16 @@ impl<'a> ModuleSource for PartitionSliceSource<'a> {
     }
 }
 
+impl ModuleCatalog for PartitionSliceSource<'_> {
+    fn module_count(&self) -> usize {
+        1
+    }
+
+    fn module_id_at(&self, index: usize) -> Option<ModuleId> {
+        (index == 0).then_some(self.id)
+    }
+}
+
 /// Maps multiple modules within a single backing slice.
 ///
 /// Offsets and lengths should respect the erase/program boundaries of the target
@@ -53,7 +83,7 @@ pub struct IndexedSliceSource<'a> {
 }
 
 /// Simple offset/len entry for modules in a region.
-#[derive(Clone, Copy)]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
 pub struct IndexEntry {
     pub id: ModuleId,
     pub offset: usize,
@@ -75,6 +105,98 @@ impl<'a> ModuleSource for IndexedSliceSource<'a> {
     }
 }
 
+impl ModuleCatalog for IndexedSliceSource<'_> {
+    fn module_count(&self) -> usize {
+        self.entries.len()
+    }
+
+    fn module_id_at(&self, index: usize) -> Option<ModuleId> {
+        self.entries.get(index).map(|e| e.id)
+    }
+}
+
+/// Rounds `len` up to a multiple of `align` (unchanged when `align` is 0).
+pub const fn align_up(len: usize, align: usize) -> usize {
+    if align == 0 {
+        len
+    } else {
+        len.div_ceil(align) * align
+    }
+}
+
+/// True when `offset` is a multiple of `align` (always when `align` is 0).
+pub const fn is_aligned(offset: usize, align: usize) -> bool {
+    align == 0 || offset.is_multiple_of(align)
+}
+
+/// Manifest blobs (`manifest::encode` output) laid out back to back in one
+/// region, each starting on an erase-block boundary, parsed in place.
+///
+/// Only modules that pass the caller's verification at construction are
+/// exposed; their entries point at the module bytes inside the region.
+/// Scanning stops at the first offset that does not hold a manifest (erased
+/// flash, or a blob too damaged to know its length).
+pub struct ManifestSliceSource<'a> {
+    inner: IndexedSliceSource<'a>,
+    rejected: usize,
+}
+
+impl<'a> ManifestSliceSource<'a> {
+    /// Scans `region`, recording verified modules into `slots`.
+    pub fn new(
+        region: &'a [u8],
+        erase_block: usize,
+        slots: &'a mut [IndexEntry],
+        mut verify: impl FnMut(&Manifest<'_>, &[u8]) -> Result<()>,
+    ) -> Result<Self> {
+        let mut found = 0;
+        let mut rejected = 0;
+        let mut offset = 0;
+        while let Ok((manifest, module)) = Manifest::parse_at(region, offset) {
+            if verify(&manifest, module).is_ok() {
+                let slot = slots
+                    .get_mut(found)
+                    .ok_or(Error::Engine("manifest slots full"))?;
+                *slot = IndexEntry {
+                    id: manifest.module_id,
+                    offset: offset + manifest.blob_len() - module.len(),
+                    len: module.len(),
+                };
+                found += 1;
+            } else {
+                rejected += 1;
+            }
+            offset = align_up(offset + manifest.blob_len(), erase_block.max(1));
+        }
+        let slots: &'a [IndexEntry] = slots;
+        Ok(Self {
+            inner: IndexedSliceSource::new(region, &slots[..found]),
+            rejected,
+        })
+    }
+
+    /// Manifests that failed verification.
+    pub fn rejected(&self) -> usize {
+        self.rejected
+    }
+}
+
+impl ModuleSource for ManifestSliceSource<'_> {
+    fn fetch(&self, id: ModuleId) -> Option<&[u8]> {
+        self.inner.fetch(id)
+    }
+}
+
+impl ModuleCatalog for ManifestSliceSource<'_> {
+    fn module_count(&self) -> usize {
+        self.inner.module_count()
+    }
+
+    fn module_id_at(&self, index: usize) -> Option<ModuleId> {
+        self.inner.module_id_at(index)
+    }
+}
+
 /// ESP-IDF note:
 /// Use `unsafe { core::slice::from_raw_parts(base_ptr, len) }` where `base_ptr`
 /// points at an OTA/NVS partition mapped into the address space, then wrap it
@@ -306,7 +428,7 @@ pub mod stm32 {
             if self.erase_block == 0 {
                 len
             } else {
-                ((len + self.erase_block - 1) / self.erase_block) * self.erase_block
+                super::align_up(len, self.erase_block)
             }
         }
     }
@@ -320,10 +442,10 @@ pub mod stm32 {
                 return Err(Error::Engine("write out of bounds"));
             }
             if self.erase_block != 0 {
-                if offset % self.erase_block != 0 {
+                if !offset.is_multiple_of(self.erase_block) {
                     return Err(Error::Engine("erase offset not aligned"));
                 }
-                if data.len() % self.erase_block != 0 {
+                if !data.len().is_multiple_of(self.erase_block) {
                     return Err(Error::Engine("erase len not aligned"));
                 }
             }
@@ -392,6 +514,7 @@ impl FileFlash {
             .read(true)
             .write(true)
             .create(true)
+            .truncate(false)
             .open(&path)
             .map_err(|_| Error::Engine("open flash file"))?;
         file.set_len(capacity as u64)
@@ -469,6 +592,7 @@ pub struct FlashBufferedSource<IO: FlashIo> {
     len: usize,
     module_id: ModuleId,
     cache: alloc::vec::Vec<u8>,
+    staging: crate::Staging,
 }
 
 #[cfg(feature = "alloc")]
@@ -480,6 +604,7 @@ impl<IO: FlashIo> FlashBufferedSource<IO> {
             len,
             module_id,
             cache: alloc::vec::Vec::new(),
+            staging: crate::Staging::default(),
         }
     }
 
@@ -511,7 +636,7 @@ impl<IO: FlashIo> ModuleSource for FlashBufferedSource<IO> {
 impl<IO: FlashIo> FlashBufferedSource<IO> {
     /// Loads from flash into the cache buffer and returns it.
     pub fn fetch_into_cache(&mut self) -> Result<&[u8]> {
-        self.cache.resize(self.len, 0);
+        crate::fallible::resize(&mut self.cache, self.len, 0)?;
         self.io
             .read(self.base_offset, &mut self.cache)
             .map_err(|_| Error::Engine("flash read failed"))?;
@@ -536,6 +661,7 @@ pub struct FlashOnDemandSource<IO: FlashIo> {
     len: usize,
     module_id: ModuleId,
     scratch: alloc::vec::Vec<u8>,
+    staging: crate::Staging,
 }
 
 #[cfg(feature = "alloc")]
@@ -547,6 +673,7 @@ impl<IO: FlashIo> FlashOnDemandSource<IO> {
             len,
             module_id,
             scratch: alloc::vec::Vec::new(),
+            staging: crate::Staging::default(),
         }
     }
 }
@@ -582,7 +709,7 @@ impl<IO: FlashIo> FlashOnDemandSource<IO> {
 
     /// Reads the module into the internal scratch buffer and returns it.
     pub fn fetch_into_scratch(&mut self) -> Result<&[u8]> {
-        self.scratch.resize(self.len, 0);
+        crate::fallible::resize(&mut self.scratch, self.len, 0)?;
         self.io
             .read(self.base_offset, self.scratch.as_mut_slice())
             .map_err(|_| Error::Engine("flash read failed"))?;
@@ -590,6 +717,97 @@ impl<IO: FlashIo> FlashOnDemandSource<IO> {
     }
 }
 
+/// Stages the image in RAM and programs the slot once on `commit`, so a
+/// failed transfer never leaves a half-written module behind.
+#[cfg(feature = "alloc")]
+impl<IO: FlashIo> crate::ModuleSink for FlashBufferedSource<IO> {
+    fn begin(&mut self, id: ModuleId, len: usize) -> Result<()> {
+        if id != self.module_id {
+            return Err(Error::ModuleNotFound);
+        }
+        if len > self.len {
+            return Err(Error::Engine("flash slot too small"));
+        }
+        self.staging.begin(id, len)
+    }
+
+    fn write(&mut self, chunk: &[u8]) -> Result<()> {
+        self.staging.write(chunk)
+    }
+
+    fn commit(&mut self) -> Result<()> {
+        let (_, bytes) = self.staging.finish()?;
+        self.io.erase_write(self.base_offset, &bytes)?;
+        self.cache.clear();
+        Ok(())
+    }
+
+    fn abort(&mut self) {
+        self.staging.abort();
+    }
+}
+
+/// Staged like `FlashBufferedSource`.
+#[cfg(feature = "alloc")]
+impl<IO: FlashIo> crate::ModuleSink for FlashOnDemandSource<IO> {
+    fn begin(&mut self, id: ModuleId, len: usize) -> Result<()> {
+        if id != self.module_id {
+            return Err(Error::ModuleNotFound);
+        }
+        if len > self.len {
+            return Err(Error::Engine("flash slot too small"));
+        }
+        self.staging.begin(id, len)
+    }
+
+    fn write(&mut self, chunk: &[u8]) -> Result<()> {
+        self.staging.write(chunk)
+    }
+
+    fn commit(&mut self) -> Result<()> {
+        let (_, bytes) = self.staging.finish()?;
+        self.io.erase_write(self.base_offset, &bytes)?;
+        self.scratch.clear();
+        Ok(())
+    }
+
+    fn abort(&mut self) {
+        self.staging.abort();
+    }
+}
+
+/// Overwrites the whole slot and the cached copy.
+#[cfg(feature = "secure-erase")]
+impl<IO: FlashIo> crate::erase::SecureErase for FlashBufferedSource<IO> {
+    fn secure_erase(&mut self, id: ModuleId) -> Result<bool> {
+        use zeroize::Zeroize;
+        if id != self.module_id {
+            return Ok(false);
+        }
+        self.staging.wipe();
+        self.cache.zeroize();
+        self.io
+            .erase_write(self.base_offset, &crate::fallible::filled(self.len, 0)?)?;
+        Ok(true)
+    }
+}
+
+/// Overwrites the whole slot and the scratch copy.
+#[cfg(feature = "secure-erase")]
+impl<IO: FlashIo> crate::erase::SecureErase for FlashOnDemandSource<IO> {
+    fn secure_erase(&mut self, id: ModuleId) -> Result<bool> {
+        use zeroize::Zeroize;
+        if id != self.module_id {
+            return Ok(false);
+        }
+        self.staging.wipe();
+        self.scratch.zeroize();
+        self.io
+            .erase_write(self.base_offset, &crate::fallible::filled(self.len, 0)?)?;
+        Ok(true)
+    }
+}
+
 /// In-memory flash implementation (useful for tests or RAM-only targets).
 #[cfg(feature = "alloc")]
 pub struct MemoryFlash {
@@ -718,6 +936,77 @@ mod tests {
         let bytes = source.fetch_into_scratch().unwrap();
         assert_eq!(bytes, &[5, 6, 7, 8]);
     }
+
+    #[test]
+    fn manifest_slice_source_exposes_verified_modules_in_place() {
+        use crate::manifest::{encode, FLAG_REQUIRE_SIGNATURE};
+
+        let big = [0xAB; 70];
+        let blobs = [
+            encode(1, "main", &big, 0, 1, None).unwrap(),
+            encode(
+                2,
+                "main",
+                &[1, 2, 3],
+                FLAG_REQUIRE_SIGNATURE,
+                1,
+                Some([0; 64]),
+            )
+            .unwrap(),
+            encode(3, "bad", &[4], 0, 1, None).unwrap(),
+        ];
+        let mut region = alloc::vec![0xFF; 512];
+        let mut offset = 0;
+        for blob in &blobs {
+            assert!(is_aligned(offset, 64));
+            region[offset..offset + blob.len()].copy_from_slice(blob);
+            offset = align_up(offset + blob.len(), 64);
+        }
+
+        let (manifest, module) = Manifest::parse_at(&region, 0).unwrap();
+        assert_eq!((manifest.signature, module), (None, &big[..]));
+        assert_eq!(manifest.blob_len(), blobs[0].len());
+        assert!(Manifest::parse_at(&region, 600).is_err());
+
+        let mut slots = [IndexEntry::default(); 4];
+        let source = ManifestSliceSource::new(&region, 64, &mut slots, |manifest, _| {
+            if manifest.entry == "main" {
+                Ok(())
+            } else {
+                Err(Error::Engine("untrusted"))
+            }
+        })
+        .unwrap();
+        assert_eq!(source.iter_ids().collect::<Vec<_>>(), [1, 2]);
+        assert_eq!(source.fetch(2), Some(&[1, 2, 3][..]));
+        assert_eq!(source.fetch(3), None);
+        assert_eq!(source.rejected(), 1);
+
+        let mut one = [IndexEntry::default(); 1];
+        assert!(ManifestSliceSource::new(&region, 64, &mut one, |_, _| Ok(())).is_err());
+    }
+
+    #[cfg(feature = "alloc")]
+    #[test]
+    fn flash_sink_programs_slot_on_commit() {
+        use crate::ModuleSink;
+
+        let mut source = FlashOnDemandSource::new(MemoryFlash::new(8), 0, 4, 3);
+        assert_eq!(source.begin(4, 4), Err(Error::ModuleNotFound));
+        assert!(source.begin(3, 5).is_err());
+
+        source.begin(3, 4).unwrap();
+        source.write(&[1, 2]).unwrap();
+        source.abort();
+        assert_eq!(source.fetch_into_scratch().unwrap(), &[0xFF; 4]);
+
+        source.begin(3, 4).unwrap();
+        source.write(&[1, 2]).unwrap();
+        assert!(source.commit().is_err());
+        source.write(&[3, 4]).unwrap();
+        source.commit().unwrap();
+        assert_eq!(source.fetch_into_scratch().unwrap(), &[1, 2, 3, 4]);
+    }
 }
 
 // Extra coverage for stm32 feature (alignment + bounds).
@@ -746,7 +1035,7 @@ mod stm32_tests {
     }
 
     fn mock_read(offset: usize, buf: &mut [u8]) -> Result<()> {
-        let mut backing = backing().lock().unwrap();
+        let backing = backing().lock().unwrap();
         let end = offset + buf.len();
         if end > CAP {
             return Err(Error::Engine("read oob"));
@@ -0,0 +1,403 @@
+//! Dual-bank module writer for raw NOR flash.
+//!
+//! The region is split into two equal banks, followed by a journal of erase
+//! blocks. Each update of a module goes to the bank that does not hold its
+//! live image, at the free place whose erase blocks were erased least, so a
+//! module updated daily wears both banks evenly instead of the same sectors.
+//!
+//! The index (`index` layout, erase counters included) is never rewritten in
+//! place: every change writes a full copy, stamped with a sequence number and
+//! a CRC-32, into the next journal block in turn, and `open` takes the newest
+//! copy that checks out. Images are written before the journal entry that
+//! points at them, so an interrupted update leaves the previous index, and
+//! the previous image, in effect.
+
+use super::index::{self, FlashIndex, IndexRecord, SlotState};
+use super::{align_up, FlashIo, IndexEntry};
+use crate::{fallible, Error, ModuleId, ModuleSink, Result, Staging};
+use alloc::vec::Vec;
+
+/// Journal entry marker.
+pub const JOURNAL_MAGIC: &[u8; 4] = b"SMJR";
+/// Bytes before the index in a journal entry: magic, sequence, index length
+/// and CRC-32 of the index (little endian).
+pub const JOURNAL_HEADER_LEN: usize = 16;
+
+/// One half of the module area.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Bank {
+    A,
+    B,
+}
+
+/// Modules alternating between two banks, indexed through a journal.
+pub struct DualBankWriter<IO: FlashIo> {
+    io: IO,
+    bank_len: u32,
+    erase_block: u32,
+    journal_blocks: u32,
+    records: Vec<IndexRecord>,
+    erase_counts: Vec<u16>,
+    sequence: u32,
+    next_slot: u32,
+    staging: Staging,
+}
+
+impl<IO: FlashIo> DualBankWriter<IO> {
+    /// Opens the writer on `io`: two banks of `bank_len` bytes, then
+    /// `journal_blocks` (at least two) erase blocks of journal. A journal
+    /// with no valid entry opens empty.
+    pub fn open(io: IO, bank_len: u32, erase_block: u32, journal_blocks: u32) -> Result<Self> {
+        if erase_block == 0 || bank_len == 0 || !bank_len.is_multiple_of(erase_block) {
+            return Err(Error::Engine("bank: banks not erase-block aligned"));
+        }
+        if journal_blocks < 2 {
+            return Err(Error::Engine("bank: journal needs two blocks"));
+        }
+        let needed = 2 * bank_len as u64 + journal_blocks as u64 * erase_block as u64;
+        if (io.capacity() as u64) < needed {
+            return Err(Error::Engine("bank: flash too small"));
+        }
+        let mut writer = Self {
+            io,
+            bank_len,
+            erase_block,
+            journal_blocks,
+            records: Vec::new(),
+            erase_counts: alloc::vec![0; (2 * bank_len / erase_block) as usize],
+            sequence: 0,
+            next_slot: 0,
+            staging: Staging::default(),
+        };
+
+        let mut block = fallible::filled(erase_block as usize, 0)?;
+        let mut newest: Option<(u32, u32, Vec<u8>)> = None;
+        for slot in 0..journal_blocks {
+            writer.io.read(writer.journal_offset(slot), &mut block)?;
+            if let Some((sequence, bytes)) = journal_entry(&block) {
+                if newest.as_ref().is_none_or(|(best, _, _)| sequence > *best) {
+                    newest = Some((sequence, slot, bytes.to_vec()));
+                }
+            }
+        }
+        if let Some((sequence, slot, bytes)) = newest {
+            let index = FlashIndex::parse(&bytes)?;
+            if index.region_len != 2 * bank_len || index.erase_block != erase_block {
+                return Err(Error::Engine("bank: index geometry mismatch"));
+            }
+            writer.records = index.entries().collect();
+            for (count, stored) in writer.erase_counts.iter_mut().zip(index.erase_counts()) {
+                *count = stored;
+            }
+            writer.sequence = sequence;
+            writer.next_slot = (slot + 1) % journal_blocks;
+        }
+        Ok(writer)
+    }
+
+    /// Live image of a module.
+    pub fn live(&self, id: ModuleId) -> Option<IndexRecord> {
+        self.records.iter().copied().find(|r| r.module_id == id)
+    }
+
+    /// Bank an image lives in.
+    pub fn bank_of(&self, record: &IndexRecord) -> Bank {
+        if record.offset < self.bank_len {
+            Bank::A
+        } else {
+            Bank::B
+        }
+    }
+
+    /// Live images as entries for an `IndexedSliceSource` over both banks.
+    pub fn live_entries(&self) -> Vec<IndexEntry> {
+        self.records.iter().map(IndexRecord::index_entry).collect()
+    }
+
+    /// Erase counter per erase block of both banks.
+    pub fn erase_counts(&self) -> &[u16] {
+        &self.erase_counts
+    }
+
+    /// Sequence number of the newest journal entry (0 before the first).
+    pub fn sequence(&self) -> u32 {
+        self.sequence
+    }
+
+    /// Reads a module's live image into `out`.
+    pub fn read_module(&self, id: ModuleId, out: &mut Vec<u8>) -> Result<()> {
+        let record = self.live(id).ok_or(Error::ModuleNotFound)?;
+        out.clear();
+        fallible::resize(out, record.len as usize, 0)?;
+        self.io.read(record.offset as usize, out)
+    }
+
+    /// Writes `bytes` as the live image of `id` at manifest sequence
+    /// `version`, in the bank its current image is not in.
+    pub fn install(&mut self, id: ModuleId, version: u32, bytes: &[u8]) -> Result<IndexRecord> {
+        if bytes.is_empty() {
+            return Err(Error::Engine("bank: empty image"));
+        }
+        let offset = self.place(id, bytes.len())?;
+        self.io.erase_write(offset as usize, bytes)?;
+        let first = (offset / self.erase_block) as usize;
+        let blocks = self.blocks(bytes.len());
+        for count in &mut self.erase_counts[first..first + blocks] {
+            *count = count.saturating_add(1);
+        }
+
+        let record = IndexRecord {
+            module_id: id,
+            offset,
+            len: bytes.len() as u32,
+            version,
+            state: SlotState::Live,
+        };
+        let previous = self.records.iter().position(|r| r.module_id == id);
+        let replaced = match previous {
+            Some(i) => core::mem::replace(&mut self.records[i], record),
+            None => {
+                self.records.push(record);
+                record
+            }
+        };
+        if let Err(err) = self.journal() {
+            // The journal still points at the previous image.
+            match previous {
+                Some(i) => self.records[i] = replaced,
+                None => {
+                    self.records.pop();
+                }
+            }
+            return Err(err);
+        }
+        Ok(record)
+    }
+
+    /// Drops a module from the index; returns whether it was stored.
+    pub fn remove(&mut self, id: ModuleId) -> Result<bool> {
+        let Some(i) = self.records.iter().position(|r| r.module_id == id) else {
+            return Ok(false);
+        };
+        let record = self.records.remove(i);
+        if let Err(err) = self.journal() {
+            self.records.insert(i, record);
+            return Err(err);
+        }
+        Ok(true)
+    }
+
+    pub fn io(&self) -> &IO {
+        &self.io
+    }
+
+    pub fn into_inner(self) -> IO {
+        self.io
+    }
+
+    fn blocks(&self, len: usize) -> usize {
+        align_up(len, self.erase_block as usize) / self.erase_block as usize
+    }
+
+    fn journal_offset(&self, slot: u32) -> usize {
+        (2 * self.bank_len + slot * self.erase_block) as usize
+    }
+
+    /// Whether erase block `block` holds no live image other than `id`'s.
+    fn block_free(&self, block: usize, id: ModuleId) -> bool {
+        let start = block as u32 * self.erase_block;
+        self.records.iter().all(|r| {
+            r.module_id == id || r.offset >= start + self.erase_block || r.offset + r.len <= start
+        })
+    }
+
+    /// Least-worn free place for a `len`-byte image of `id`: in the other
+    /// bank for an update, in either bank for a new module.
+    fn place(&self, id: ModuleId, len: usize) -> Result<u32> {
+        let bank_blocks = (self.bank_len / self.erase_block) as usize;
+        let banks: &[usize] = match self.live(id).map(|r| self.bank_of(&r)) {
+            Some(Bank::A) => &[1],
+            Some(Bank::B) => &[0],
+            None => &[0, 1],
+        };
+        let needed = self.blocks(len);
+        let mut best: Option<(u32, usize)> = None;
+        let mut longest_run = 0;
+        for &bank in banks {
+            let blocks = bank * bank_blocks..(bank + 1) * bank_blocks;
+            let mut run = 0;
+            for block in blocks.clone() {
+                run = if self.block_free(block, id) {
+                    run + 1
+                } else {
+                    0
+                };
+                longest_run = longest_run.max(run);
+                if run >= needed {
+                    let first = block + 1 - needed;
+                    let wear: u32 = self.erase_counts[first..=block]
+                        .iter()
+                        .map(|c| *c as u32)
+                        .sum();
+                    if best.is_none_or(|(least, _)| wear < least) {
+                        best = Some((wear, first));
+                    }
+                }
+            }
+        }
+        match best {
+            Some((_, first)) => Ok(first as u32 * self.erase_block),
+            None => Err(Error::StorageFull {
+                needed: len,
+                available: longest_run * self.erase_block as usize,
+            }),
+        }
+    }
+
+    /// Writes the index into the next journal block.
+    fn journal(&mut self) -> Result<()> {
+        let index = index::encode(
+            2 * self.bank_len,
+            self.erase_block,
+            &self.records,
+            &self.erase_counts,
+        )?;
+        if JOURNAL_HEADER_LEN + index.len() > self.erase_block as usize {
+            return Err(Error::Engine("bank: index larger than a journal block"));
+        }
+        let sequence = self.sequence.wrapping_add(1);
+        let mut entry = Vec::with_capacity(JOURNAL_HEADER_LEN + index.len());
+        entry.extend_from_slice(JOURNAL_MAGIC);
+        entry.extend_from_slice(&sequence.to_le_bytes());
+        entry.extend_from_slice(&(index.len() as u32).to_le_bytes());
+        entry.extend_from_slice(&crc32(&index).to_le_bytes());
+        entry.extend_from_slice(&index);
+        self.io
+            .erase_write(self.journal_offset(self.next_slot), &entry)?;
+        self.sequence = sequence;
+        self.next_slot = (self.next_slot + 1) % self.journal_blocks;
+        Ok(())
+    }
+}
+
+/// Drops the module from the journal, then overwrites every erase block no
+/// live image occupies, in both banks (its previous image included).
+#[cfg(feature = "secure-erase")]
+impl<IO: FlashIo> crate::erase::SecureErase for DualBankWriter<IO> {
+    fn secure_erase(&mut self, id: ModuleId) -> Result<bool> {
+        self.staging.wipe_for(id);
+        let removed = self.remove(id)?;
+        let zeros = fallible::filled(self.erase_block as usize, 0)?;
+        for block in 0..self.erase_counts.len() {
+            if self.block_free(block, id) {
+                self.io
+                    .erase_write(block * self.erase_block as usize, &zeros)?;
+                self.erase_counts[block] = self.erase_counts[block].saturating_add(1);
+            }
+        }
+        // Records the wear of the overwrite.
+        self.journal()?;
+        Ok(removed)
+    }
+}
+
+/// Sequence and index bytes of a valid journal entry.
+fn journal_entry(block: &[u8]) -> Option<(u32, &[u8])> {
+    let header = block.get(..JOURNAL_HEADER_LEN)?;
+    if &header[..4] != JOURNAL_MAGIC {
+        return None;
+    }
+    let field = |at: usize| u32::from_le_bytes(header[at..at + 4].try_into().unwrap());
+    let index = block.get(JOURNAL_HEADER_LEN..JOURNAL_HEADER_LEN + field(8) as usize)?;
+    (crc32(index) == field(12)).then_some((field(4), index))
+}
+
+/// CRC-32 (IEEE 802.3).
+fn crc32(data: &[u8]) -> u32 {
+    let mut crc = !0u32;
+    for &byte in data {
+        crc ^= byte as u32;
+        for _ in 0..8 {
+            crc = if crc & 1 != 0 {
+                (crc >> 1) ^ 0xedb8_8320
+            } else {
+                crc >> 1
+            };
+        }
+    }
+    !crc
+}
+
+/// Staged in RAM and installed on `commit` at the live version plus one; use
+/// `install` to record the manifest sequence instead.
+impl<IO: FlashIo> ModuleSink for DualBankWriter<IO> {
+    fn begin(&mut self, id: ModuleId, len: usize) -> Result<()> {
+        self.place(id, len)?;
+        self.staging.begin(id, len)
+    }
+
+    fn write(&mut self, chunk: &[u8]) -> Result<()> {
+        self.staging.write(chunk)
+    }
+
+    fn commit(&mut self) -> Result<()> {
+        let (id, bytes) = self.staging.finish()?;
+        let version = self.live(id).map_or(1, |r| r.version.wrapping_add(1));
+        self.install(id, version, &bytes).map(drop)
+    }
+
+    fn abort(&mut self) {
+        self.staging.abort();
+    }
+}
+
+#[cfg(all(test, feature = "std"))]
+mod tests {
+    use super::*;
+    use crate::storage::MemoryFlash;
+    use crate::write_module;
+
+    const BLOCK: u32 = 256;
+    const BANK: u32 = 4 * BLOCK;
+
+    fn open(flash: MemoryFlash) -> DualBankWriter<MemoryFlash> {
+        DualBankWriter::open(flash, BANK, BLOCK, 2).unwrap()
+    }
+
+    #[test]
+    fn alternates_banks_and_recovers_from_a_torn_journal() {
+        assert_eq!(crc32(b"123456789"), 0xcbf4_3926);
+
+        let mut writer = open(MemoryFlash::new((2 * BANK + 2 * BLOCK) as usize));
+        let offsets: Vec<u32> = (1..=3)
+            .map(|version| writer.install(1, version, &[version as u8; 300]).unwrap())
+            .map(|r| r.offset)
+            .collect();
+        // A, then B, then the least worn place in A.
+        assert_eq!(offsets, [0, BANK, 2 * BLOCK]);
+        write_module(&mut writer, 2, &[0x22; 100]).unwrap();
+        assert_eq!(writer.live(2).map(|r| writer.bank_of(&r)), Some(Bank::B));
+        assert_eq!(writer.erase_counts(), [1, 1, 1, 1, 1, 1, 1, 0]);
+        assert_eq!(
+            writer.install(3, 1, &[3; 1100]),
+            Err(Error::StorageFull {
+                needed: 1100,
+                available: 512
+            })
+        );
+
+        let mut writer = open(writer.into_inner());
+        assert_eq!(writer.sequence(), 4);
+        let mut module = Vec::new();
+        writer.read_module(1, &mut module).unwrap();
+        assert_eq!(module, [3; 300]);
+
+        // Tear the newest entry: the one before it takes over.
+        let newest = writer.journal_offset(1) + JOURNAL_HEADER_LEN;
+        writer.io.erase_write(newest, &[0]).unwrap();
+        let writer = open(writer.into_inner());
+        assert_eq!(writer.sequence(), 3);
+        assert_eq!(writer.live(2), None);
+        assert_eq!(writer.live(1).unwrap().offset, 2 * BLOCK);
+    }
+}
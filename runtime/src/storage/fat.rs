@@ -0,0 +1,234 @@
+//! Modules from files on a FAT volume, e.g. an SD card (`storage-fat` feature).
+//!
+//! For data loggers whose logic is updated by swapping cards: `FatSource`
+//! reads `.smny` blobs (named `.smn` to fit 8.3 names) from one directory of
+//! the card, verifies them and keeps
+//! the module bytes in RAM. Files are found in one of two ways:
+//! - by id: every `<id>.smn` in the directory, `<id>` in decimal; the
+//!   manifest must name the same module;
+//! - through an index: when the directory holds `INDEX_FILE`, only the files it
+//!   lists (one 8.3 name per line, `#` starts a comment) are read, and each
+//!   manifest names its module.
+//!
+//! The filesystem itself stays with the firmware: `FatVolume` is the two
+//! calls needed from it, a few lines over `fatfs::Dir` (`iter`, `open_file` +
+//! `read_to_end`) or `embedded-sdmmc`'s `iterate_dir` / `read`.
+//! Call `FatSource::load` again after a card swap.
+
+use crate::idmap::IdMap;
+use crate::manifest::Manifest;
+use crate::{Error, ModuleCatalog, ModuleId, ModuleSource, Result};
+use alloc::string::String;
+use alloc::vec::Vec;
+
+/// Index file listing the module files to load.
+pub const INDEX_FILE: &str = "MODULES.TXT";
+/// Extension of module files found by id.
+pub const MODULE_EXT: &str = "smn";
+
+/// The directory modules are read from.
+pub trait FatVolume {
+    /// Calls `f` with the name of every file in the directory.
+    fn for_each_file(&mut self, f: &mut dyn FnMut(&str)) -> Result<()>;
+
+    /// Replaces `out` with the contents of file `name`; `Ok(false)` when it
+    /// does not exist.
+    fn read_file(&mut self, name: &str, out: &mut Vec<u8>) -> Result<bool>;
+}
+
+/// Module loaded from a file.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Loaded {
+    pub module_id: ModuleId,
+    /// Manifest sequence.
+    pub version: u32,
+    pub file: String,
+}
+
+/// Verified modules read from a `FatVolume`.
+pub struct FatSource {
+    modules: IdMap<Vec<u8>>,
+    loaded: Vec<Loaded>,
+    rejected: usize,
+}
+
+impl Default for FatSource {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl FatSource {
+    /// No modules until `load`.
+    pub fn new() -> Self {
+        Self {
+            modules: IdMap::new(),
+            loaded: Vec::new(),
+            rejected: 0,
+        }
+    }
+
+    /// Replaces the modules with those on `volume` whose manifest and module
+    /// pass `verify`; files up to `max_len` bytes are read. Files that are
+    /// missing, too large, damaged or rejected are counted in `rejected`.
+    pub fn load(
+        &mut self,
+        volume: &mut impl FatVolume,
+        max_len: usize,
+        mut verify: impl FnMut(&Manifest<'_>, &[u8]) -> Result<()>,
+    ) -> Result<()> {
+        let mut files: Vec<(String, Option<ModuleId>)> = Vec::new();
+        let mut index = Vec::new();
+        if volume.read_file(INDEX_FILE, &mut index)? {
+            let index = core::str::from_utf8(&index)
+                .map_err(|_| Error::Engine("fat: index is not utf-8"))?;
+            for line in index.lines() {
+                let name = line.split('#').next().unwrap_or("").trim();
+                if !name.is_empty() {
+                    files.push((name.into(), None));
+                }
+            }
+        } else {
+            volume.for_each_file(&mut |name| {
+                if let Some(id) = module_id_of(name) {
+                    files.push((name.into(), Some(id)));
+                }
+            })?;
+        }
+
+        self.modules.clear();
+        self.loaded.clear();
+        self.rejected = 0;
+        let mut blob = Vec::new();
+        for (file, named) in files {
+            if !volume.read_file(&file, &mut blob)? || blob.len() > max_len {
+                self.rejected += 1;
+                continue;
+            }
+            match Manifest::parse(&blob) {
+                Ok((manifest, module))
+                    if manifest.module_len as usize == module.len()
+                        && named.is_none_or(|id| id == manifest.module_id)
+                        && verify(&manifest, module).is_ok() =>
+                {
+                    self.accept(&manifest, module, file)
+                }
+                _ => self.rejected += 1,
+            }
+        }
+        Ok(())
+    }
+
+    fn accept(&mut self, manifest: &Manifest<'_>, module: &[u8], file: String) {
+        // Unbounded map: inserting cannot fail. A later file for the same
+        // module replaces the earlier one.
+        let _ = self.modules.insert(manifest.module_id, module.to_vec());
+        self.loaded.retain(|l| l.module_id != manifest.module_id);
+        self.loaded.push(Loaded {
+            module_id: manifest.module_id,
+            version: manifest.sequence,
+            file,
+        });
+    }
+
+    /// Modules read by the last `load`, e.g. to record their versions with
+    /// `Runtime::set_module_version`.
+    pub fn loaded(&self) -> &[Loaded] {
+        &self.loaded
+    }
+
+    /// Files the last `load` skipped.
+    pub fn rejected(&self) -> usize {
+        self.rejected
+    }
+}
+
+/// Id of a module file found by id (`<id>.smn`, any case).
+fn module_id_of(name: &str) -> Option<ModuleId> {
+    let (stem, ext) = name.rsplit_once('.')?;
+    if !ext.eq_ignore_ascii_case(MODULE_EXT) {
+        return None;
+    }
+    stem.parse().ok()
+}
+
+impl ModuleSource for FatSource {
+    fn fetch(&self, id: ModuleId) -> Option<&[u8]> {
+        self.modules.get(id).map(Vec::as_slice)
+    }
+}
+
+impl ModuleCatalog for FatSource {
+    fn module_count(&self) -> usize {
+        self.modules.len()
+    }
+
+    fn module_id_at(&self, index: usize) -> Option<ModuleId> {
+        self.modules.id_at(index)
+    }
+}
+
+#[cfg(all(test, feature = "std"))]
+mod tests {
+    use super::*;
+    use crate::manifest::encode;
+
+    const WASM: &[u8] = b"\0asm\x01\0\0\0";
+
+    /// Files in a directory, by name.
+    struct Card(Vec<(&'static str, Vec<u8>)>);
+
+    impl FatVolume for Card {
+        fn for_each_file(&mut self, f: &mut dyn FnMut(&str)) -> Result<()> {
+            self.0.iter().for_each(|(name, _)| f(name));
+            Ok(())
+        }
+
+        fn read_file(&mut self, name: &str, out: &mut Vec<u8>) -> Result<bool> {
+            let file = self.0.iter().find(|(n, _)| n.eq_ignore_ascii_case(name));
+            out.clear();
+            out.extend(file.map_or(&[][..], |(_, bytes)| bytes));
+            Ok(file.is_some())
+        }
+    }
+
+    fn blob(id: ModuleId, sequence: u32) -> Vec<u8> {
+        encode(id, "main", WASM, 0, sequence, None).unwrap()
+    }
+
+    #[test]
+    fn loads_by_id_or_through_the_index() {
+        let accept_all = |_: &Manifest<'_>, _: &[u8]| Ok(());
+        let mut card = Card(vec![
+            ("3.SMN", blob(3, 2)),
+            ("4.smn", blob(5, 1)),
+            ("LOG.CSV", b"t,v".to_vec()),
+            ("LOGIC.BIN", blob(9, 7)),
+        ]);
+        let mut source = FatSource::new();
+        source.load(&mut card, 1024, accept_all).unwrap();
+        assert_eq!(source.fetch(3), Some(WASM));
+        assert_eq!((source.module_count(), source.rejected()), (1, 1));
+        assert_eq!(source.loaded()[0].version, 2);
+
+        card.0.push((
+            INDEX_FILE,
+            b"# field build\nLOGIC.BIN\n4.smn\nGONE.BIN\n".to_vec(),
+        ));
+        source
+            .load(&mut card, 1024, |m: &Manifest<'_>, _: &[u8]| {
+                if m.module_id == 5 {
+                    Err(Error::Engine("revoked"))
+                } else {
+                    Ok(())
+                }
+            })
+            .unwrap();
+        assert_eq!(source.iter_ids().collect::<Vec<_>>(), [9]);
+        assert_eq!(source.rejected(), 2);
+        assert_eq!(source.loaded()[0].file, "LOGIC.BIN");
+
+        source.load(&mut card, 16, accept_all).unwrap();
+        assert_eq!(source.module_count(), 0);
+    }
+}
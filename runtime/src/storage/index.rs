@@ -0,0 +1,330 @@
+//! On-flash module index: where each module lives in a region, plus per-erase-block
+//! wear counters.
+//!
+//! Layout (little endian):
+//! - magic: 4 bytes = b"SMIX"
+//! - version: u8 = 1
+//! - reserved: u8 = 0
+//! - entry_count: u16
+//! - region_len: u32
+//! - erase_block: u32
+//! - entries: entry_count × 20 bytes
+//!   - module_id: u32, offset: u32, len: u32, version: u32 (manifest sequence)
+//!   - state: u8 (1 = live, 2 = superseded), reserved: [u8; 3]
+//! - block_count: u32
+//! - erase_counts: block_count × u16 (saturating)
+//!
+//! Offsets are relative to the start of the region. Parsing borrows the bytes,
+//! so an index can be read in place from memory-mapped flash or from a dump.
+
+use super::IndexEntry;
+use crate::{Error, ModuleId, Result};
+
+/// Index magic marker.
+pub const INDEX_MAGIC: &[u8; 4] = b"SMIX";
+/// Index layout version.
+pub const INDEX_VERSION: u8 = 1;
+/// Bytes before the first entry.
+pub const HEADER_LEN: usize = 16;
+/// Bytes per entry.
+pub const ENTRY_LEN: usize = 20;
+
+/// Whether a slot holds the module's current image.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SlotState {
+    Live,
+    /// Replaced by a newer version; reclaimable.
+    Superseded,
+}
+
+impl SlotState {
+    fn from_byte(byte: u8) -> Result<Self> {
+        match byte {
+            1 => Ok(SlotState::Live),
+            2 => Ok(SlotState::Superseded),
+            _ => Err(Error::Engine("index: bad slot state")),
+        }
+    }
+
+    #[cfg(feature = "alloc")]
+    fn to_byte(self) -> u8 {
+        match self {
+            SlotState::Live => 1,
+            SlotState::Superseded => 2,
+        }
+    }
+}
+
+/// One module image in the region.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct IndexRecord {
+    pub module_id: ModuleId,
+    pub offset: u32,
+    pub len: u32,
+    pub version: u32,
+    pub state: SlotState,
+}
+
+impl IndexRecord {
+    /// Offset one past the last byte.
+    pub fn end(&self) -> u64 {
+        self.offset as u64 + self.len as u64
+    }
+
+    /// Entry for `IndexedSliceSource`.
+    pub fn index_entry(&self) -> IndexEntry {
+        IndexEntry {
+            id: self.module_id,
+            offset: self.offset as usize,
+            len: self.len as usize,
+        }
+    }
+}
+
+/// Parsed view of an index.
+#[derive(Debug, Clone, Copy)]
+pub struct FlashIndex<'a> {
+    pub region_len: u32,
+    pub erase_block: u32,
+    entries: &'a [u8],
+    erase_counts: &'a [u8],
+}
+
+fn u32_at(bytes: &[u8], at: usize) -> u32 {
+    u32::from_le_bytes(bytes[at..at + 4].try_into().unwrap())
+}
+
+impl<'a> FlashIndex<'a> {
+    /// Parses and validates an index (entries in bounds, states known).
+    pub fn parse(bytes: &'a [u8]) -> Result<Self> {
+        if bytes.len() < HEADER_LEN {
+            return Err(Error::Engine("index too small"));
+        }
+        if &bytes[0..4] != INDEX_MAGIC {
+            return Err(Error::Engine("index magic mismatch"));
+        }
+        if bytes[4] != INDEX_VERSION {
+            return Err(Error::Engine("index version unsupported"));
+        }
+        let count = u16::from_le_bytes([bytes[6], bytes[7]]) as usize;
+        let region_len = u32_at(bytes, 8);
+        let erase_block = u32_at(bytes, 12);
+        if erase_block == 0 {
+            return Err(Error::Engine("index erase block is zero"));
+        }
+
+        let entries_end = HEADER_LEN + count * ENTRY_LEN;
+        let counts_start = entries_end + 4;
+        if bytes.len() < counts_start {
+            return Err(Error::Engine("index entries out of bounds"));
+        }
+        let block_count = u32_at(bytes, entries_end) as usize;
+        let counts_end = block_count
+            .checked_mul(2)
+            .and_then(|len| len.checked_add(counts_start))
+            .filter(|end| *end <= bytes.len())
+            .ok_or(Error::Engine("index erase counts out of bounds"))?;
+
+        let index = Self {
+            region_len,
+            erase_block,
+            entries: &bytes[HEADER_LEN..entries_end],
+            erase_counts: &bytes[counts_start..counts_end],
+        };
+        for record in index.records() {
+            if record?.end() > region_len as u64 {
+                return Err(Error::Engine("index entry outside region"));
+            }
+        }
+        Ok(index)
+    }
+
+    /// Bytes `parse` consumes for an index with `entries` records and
+    /// `blocks` erase counters (useful when streaming one in).
+    pub const fn encoded_len(entries: usize, blocks: usize) -> usize {
+        HEADER_LEN + entries * ENTRY_LEN + 4 + blocks * 2
+    }
+
+    fn records(&self) -> impl Iterator<Item = Result<IndexRecord>> + 'a {
+        self.entries.chunks_exact(ENTRY_LEN).map(|entry| {
+            Ok(IndexRecord {
+                module_id: u32_at(entry, 0),
+                offset: u32_at(entry, 4),
+                len: u32_at(entry, 8),
+                version: u32_at(entry, 12),
+                state: SlotState::from_byte(entry[16])?,
+            })
+        })
+    }
+
+    /// Number of entries.
+    pub fn len(&self) -> usize {
+        self.entries.len() / ENTRY_LEN
+    }
+
+    /// True when no modules are indexed.
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Entries in index order (validated by `parse`).
+    pub fn entries(&self) -> impl Iterator<Item = IndexRecord> + 'a {
+        self.records().filter_map(Result::ok)
+    }
+
+    /// Live entry for a module.
+    pub fn live(&self, module_id: ModuleId) -> Option<IndexRecord> {
+        self.entries()
+            .find(|r| r.module_id == module_id && r.state == SlotState::Live)
+    }
+
+    /// Erase counter per erase block, from the start of the region.
+    pub fn erase_counts(&self) -> impl Iterator<Item = u16> + 'a {
+        self.erase_counts
+            .chunks_exact(2)
+            .map(|c| u16::from_le_bytes([c[0], c[1]]))
+    }
+
+    /// Number of erase blocks the region spans.
+    pub fn block_count(&self) -> u32 {
+        self.region_len.div_ceil(self.erase_block)
+    }
+}
+
+/// A span of the region: a module image or free space.
+#[cfg(feature = "alloc")]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Extent {
+    pub offset: u32,
+    pub len: u32,
+    /// `None` for free space.
+    pub record: Option<IndexRecord>,
+}
+
+#[cfg(feature = "alloc")]
+impl FlashIndex<'_> {
+    /// The whole region as sorted extents, free gaps included.
+    pub fn extents(&self) -> alloc::vec::Vec<Extent> {
+        let mut records: alloc::vec::Vec<_> = self.entries().collect();
+        records.sort_by_key(|r| r.offset);
+        let mut extents = alloc::vec::Vec::with_capacity(records.len() * 2 + 1);
+        let mut cursor = 0u32;
+        for record in records {
+            if record.offset > cursor {
+                extents.push(Extent {
+                    offset: cursor,
+                    len: record.offset - cursor,
+                    record: None,
+                });
+            }
+            extents.push(Extent {
+                offset: record.offset,
+                len: record.len,
+                record: Some(record),
+            });
+            cursor = cursor.max(record.offset + record.len);
+        }
+        if cursor < self.region_len {
+            extents.push(Extent {
+                offset: cursor,
+                len: self.region_len - cursor,
+                record: None,
+            });
+        }
+        extents
+    }
+}
+
+/// Serializes an index in the layout `FlashIndex::parse` reads.
+#[cfg(feature = "alloc")]
+pub fn encode(
+    region_len: u32,
+    erase_block: u32,
+    records: &[IndexRecord],
+    erase_counts: &[u16],
+) -> Result<alloc::vec::Vec<u8>> {
+    let count =
+        u16::try_from(records.len()).map_err(|_| Error::Engine("index: too many entries"))?;
+    let mut out =
+        alloc::vec::Vec::with_capacity(FlashIndex::encoded_len(records.len(), erase_counts.len()));
+    out.extend_from_slice(INDEX_MAGIC);
+    out.push(INDEX_VERSION);
+    out.push(0);
+    out.extend_from_slice(&count.to_le_bytes());
+    out.extend_from_slice(&region_len.to_le_bytes());
+    out.extend_from_slice(&erase_block.to_le_bytes());
+    for record in records {
+        out.extend_from_slice(&record.module_id.to_le_bytes());
+        out.extend_from_slice(&record.offset.to_le_bytes());
+        out.extend_from_slice(&record.len.to_le_bytes());
+        out.extend_from_slice(&record.version.to_le_bytes());
+        out.extend_from_slice(&[record.state.to_byte(), 0, 0, 0]);
+    }
+    out.extend_from_slice(&(erase_counts.len() as u32).to_le_bytes());
+    for count in erase_counts {
+        out.extend_from_slice(&count.to_le_bytes());
+    }
+    Ok(out)
+}
+
+#[cfg(all(test, feature = "std"))]
+mod tests {
+    use super::*;
+
+    fn record(module_id: ModuleId, offset: u32, len: u32, state: SlotState) -> IndexRecord {
+        IndexRecord {
+            module_id,
+            offset,
+            len,
+            version: 1,
+            state,
+        }
+    }
+
+    #[test]
+    fn round_trips_and_maps_extents() {
+        let records = [
+            record(2, 8192, 1000, SlotState::Live),
+            record(1, 0, 4096, SlotState::Superseded),
+            record(1, 12288, 4096, SlotState::Live),
+        ];
+        let bytes = encode(32768, 4096, &records, &[3, 0, 7, 9]).unwrap();
+        assert_eq!(bytes.len(), FlashIndex::encoded_len(3, 4));
+
+        let index = FlashIndex::parse(&bytes).unwrap();
+        assert_eq!(index.len(), 3);
+        assert_eq!(index.block_count(), 8);
+        assert_eq!(index.live(1), Some(records[2]));
+        assert_eq!(index.live(1).unwrap().index_entry().offset, 12288);
+        assert_eq!(index.erase_counts().collect::<Vec<_>>(), [3, 0, 7, 9]);
+
+        let extents: Vec<_> = index
+            .extents()
+            .iter()
+            .map(|e| (e.offset, e.len, e.record.map(|r| r.module_id)))
+            .collect();
+        assert_eq!(
+            extents,
+            [
+                (0, 4096, Some(1)),
+                (4096, 4096, None),
+                (8192, 1000, Some(2)),
+                (9192, 3096, None),
+                (12288, 4096, Some(1)),
+                (16384, 16384, None),
+            ]
+        );
+    }
+
+    #[test]
+    fn rejects_out_of_region_entries_and_truncation() {
+        let bytes = encode(4096, 4096, &[record(1, 4000, 200, SlotState::Live)], &[]).unwrap();
+        assert!(FlashIndex::parse(&bytes).is_err());
+
+        let bytes = encode(4096, 4096, &[record(1, 0, 200, SlotState::Live)], &[1]).unwrap();
+        assert!(FlashIndex::parse(&bytes[..bytes.len() - 1]).is_err());
+        let mut bad_state = bytes.clone();
+        bad_state[HEADER_LEN + 16] = 9;
+        assert!(FlashIndex::parse(&bad_state).is_err());
+    }
+}
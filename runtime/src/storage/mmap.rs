@@ -0,0 +1,243 @@
+//! Memory-mapped module files for Linux-class gateways (`storage-mmap`
+//! feature, unix).
+//!
+//! `MmapSource` maps every `.smny` file of a directory read-only and serves
+//! the verified module bytes straight from the mappings, so hundreds of
+//! modules cost address space rather than heap: the kernel pages them in on
+//! use and drops the clean pages under memory pressure. Verification reads
+//! each file once when the directory is opened.
+//!
+//! Replace module files by writing a new file and renaming it over the old
+//! one, then call `reload`; the previous mapping keeps the old contents
+//! until then. Truncating or rewriting a mapped file in place is not safe.
+
+use crate::idmap::IdMap;
+use crate::manifest::Manifest;
+use crate::{Error, ModuleCatalog, ModuleId, ModuleSource, Result};
+use std::fs::{self, File};
+use std::os::unix::io::AsRawFd;
+use std::path::{Path, PathBuf};
+
+/// Extension of the files mapped.
+pub const MODULE_EXT: &str = "smny";
+
+/// Read-only mapping of a whole file.
+struct Mapping {
+    ptr: *mut libc::c_void,
+    len: usize,
+}
+
+impl Mapping {
+    fn map(file: &File) -> Result<Self> {
+        let len = file
+            .metadata()
+            .map_err(|_| Error::Engine("mmap: cannot stat file"))?
+            .len() as usize;
+        if len == 0 {
+            return Err(Error::Engine("mmap: empty file"));
+        }
+        // SAFETY: a fresh private read-only mapping of `len` bytes of an open
+        // file; the kernel picks the address.
+        let ptr = unsafe {
+            libc::mmap(
+                core::ptr::null_mut(),
+                len,
+                libc::PROT_READ,
+                libc::MAP_PRIVATE,
+                file.as_raw_fd(),
+                0,
+            )
+        };
+        if ptr == libc::MAP_FAILED {
+            return Err(Error::Engine("mmap: mapping failed"));
+        }
+        Ok(Self { ptr, len })
+    }
+
+    fn bytes(&self) -> &[u8] {
+        // SAFETY: the mapping is `len` readable bytes and lives as long as
+        // `self`; files are replaced by rename, not modified in place.
+        unsafe { core::slice::from_raw_parts(self.ptr as *const u8, self.len) }
+    }
+}
+
+// SAFETY: the mapping is read-only and owned; nothing writes through `ptr`.
+unsafe impl Send for Mapping {}
+unsafe impl Sync for Mapping {}
+
+impl Drop for Mapping {
+    fn drop(&mut self) {
+        // SAFETY: unmaps exactly what `map` mapped; no slices outlive `self`.
+        unsafe {
+            libc::munmap(self.ptr, self.len);
+        }
+    }
+}
+
+/// Module bytes inside a mapping.
+struct Entry {
+    mapping: Mapping,
+    offset: usize,
+    len: usize,
+    version: u32,
+}
+
+/// Verified modules served from memory-mapped `.smny` files.
+pub struct MmapSource {
+    dir: PathBuf,
+    max_len: usize,
+    modules: IdMap<Entry>,
+    rejected: usize,
+}
+
+impl MmapSource {
+    /// Maps the `.smny` files in `dir` up to `max_len` bytes whose manifest
+    /// and module pass `verify`; see `reload`.
+    pub fn open(
+        dir: impl AsRef<Path>,
+        max_len: usize,
+        verify: impl FnMut(&Manifest<'_>, &[u8]) -> Result<()>,
+    ) -> Result<Self> {
+        let mut source = Self {
+            dir: dir.as_ref().to_path_buf(),
+            max_len,
+            modules: IdMap::new(),
+            rejected: 0,
+        };
+        source.reload(verify)?;
+        Ok(source)
+    }
+
+    /// Maps the directory again, replacing every module. Files that cannot be
+    /// mapped, are too large, damaged or rejected by `verify` are counted in
+    /// `rejected`; when two files name the same module, the higher sequence
+    /// wins.
+    pub fn reload(
+        &mut self,
+        mut verify: impl FnMut(&Manifest<'_>, &[u8]) -> Result<()>,
+    ) -> Result<()> {
+        let entries =
+            fs::read_dir(&self.dir).map_err(|_| Error::Engine("mmap: cannot read dir"))?;
+        let mut modules = IdMap::new();
+        let mut rejected = 0;
+        for entry in entries {
+            let path = entry
+                .map_err(|_| Error::Engine("mmap: cannot read dir"))?
+                .path();
+            if path.extension().is_none_or(|ext| ext != MODULE_EXT) {
+                continue;
+            }
+            match self.map(&path, &mut verify) {
+                Some((module_id, entry)) => {
+                    let current = modules.get(module_id).map(|e: &Entry| e.version);
+                    if current.is_none_or(|version| version < entry.version) {
+                        // Unbounded map: inserting cannot fail.
+                        let _ = modules.insert(module_id, entry);
+                    }
+                }
+                None => rejected += 1,
+            }
+        }
+        self.modules = modules;
+        self.rejected = rejected;
+        Ok(())
+    }
+
+    fn map(
+        &self,
+        path: &Path,
+        verify: &mut impl FnMut(&Manifest<'_>, &[u8]) -> Result<()>,
+    ) -> Option<(ModuleId, Entry)> {
+        let file = File::open(path).ok()?;
+        let mapping = Mapping::map(&file).ok().filter(|m| m.len <= self.max_len)?;
+        let bytes = mapping.bytes();
+        let (manifest, module) = Manifest::parse(bytes).ok()?;
+        if manifest.module_len as usize != module.len() || verify(&manifest, module).is_err() {
+            return None;
+        }
+        let (module_id, version) = (manifest.module_id, manifest.sequence);
+        let (offset, len) = (bytes.len() - module.len(), module.len());
+        Some((
+            module_id,
+            Entry {
+                mapping,
+                offset,
+                len,
+                version,
+            },
+        ))
+    }
+
+    /// Manifest sequence of a mapped module.
+    pub fn version(&self, id: ModuleId) -> Option<u32> {
+        self.modules.get(id).map(|entry| entry.version)
+    }
+
+    /// Files the last `reload` skipped.
+    pub fn rejected(&self) -> usize {
+        self.rejected
+    }
+}
+
+impl ModuleSource for MmapSource {
+    fn fetch(&self, id: ModuleId) -> Option<&[u8]> {
+        let entry = self.modules.get(id)?;
+        Some(&entry.mapping.bytes()[entry.offset..entry.offset + entry.len])
+    }
+}
+
+impl ModuleCatalog for MmapSource {
+    fn module_count(&self) -> usize {
+        self.modules.len()
+    }
+
+    fn module_id_at(&self, index: usize) -> Option<ModuleId> {
+        self.modules.id_at(index)
+    }
+}
+
+#[cfg(all(test, feature = "std"))]
+mod tests {
+    use super::*;
+    use crate::manifest::encode;
+    use std::env;
+
+    const WASM: &[u8] = b"\0asm\x01\0\0\0";
+
+    #[test]
+    fn serves_verified_modules_from_mappings() {
+        let dir = env::temp_dir().join(format!("slimmy_mmap_{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let write = |name: &str, bytes: &[u8]| fs::write(dir.join(name), bytes).unwrap();
+        write("a.smny", &encode(1, "main", WASM, 0, 1, None).unwrap());
+        write(
+            "b.smny",
+            &encode(1, "main", &[WASM, b"\0"].concat(), 0, 2, None).unwrap(),
+        );
+        write("c.smny", &encode(2, "main", WASM, 0, 1, None).unwrap());
+        write("broken.smny", b"SMNY");
+        write("notes.txt", b"ignored");
+
+        let reject_2 = |m: &Manifest<'_>, _: &[u8]| {
+            if m.module_id == 2 {
+                Err(Error::Engine("untrusted"))
+            } else {
+                Ok(())
+            }
+        };
+        let mut source = MmapSource::open(&dir, 1 << 20, reject_2).unwrap();
+        assert_eq!(source.fetch(1), Some(&[WASM, b"\0"].concat()[..]));
+        assert_eq!((source.version(1), source.fetch(2)), (Some(2), None));
+        assert_eq!((source.module_count(), source.rejected()), (1, 2));
+
+        // Replacing by rename, then reloading, serves the new file.
+        let next = dir.join("b.tmp");
+        fs::write(&next, encode(1, "main", WASM, 0, 3, None).unwrap()).unwrap();
+        fs::rename(&next, dir.join("b.smny")).unwrap();
+        source.reload(|_: &Manifest<'_>, _: &[u8]| Ok(())).unwrap();
+        assert_eq!((source.version(1), source.fetch(1)), (Some(3), Some(WASM)));
+        assert_eq!(source.module_count(), 2);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,203 @@
+//! Storage quotas checked when a module is installed.
+//!
+//! A `StorageQuota` caps the bytes one module may take (a default for all,
+//! overridable per module) and the bytes all modules take together. An image
+//! that would exceed either is refused with `Error::StorageFull` before
+//! anything is written, so one oversized artifact cannot push the others out
+//! or leave no room for the next update. A module's current image does not
+//! count against its own replacement.
+//!
+//! `QuotaStore` enforces a quota in front of any store that can list what it
+//! holds; `IndexedStore::set_quota` does the same for flash.
+
+use crate::idmap::IdMap;
+use crate::{Error, ModuleCatalog, ModuleId, ModuleSink, ModuleSource, Result};
+
+/// Size limits for installed modules; `None` leaves a limit off.
+#[derive(Debug, Clone, Default)]
+pub struct StorageQuota {
+    /// Bytes all modules may take together.
+    pub total: Option<usize>,
+    /// Bytes any one module may take, unless overridden.
+    pub per_module: Option<usize>,
+    overrides: IdMap<usize>,
+}
+
+impl StorageQuota {
+    /// No limits.
+    pub fn unlimited() -> Self {
+        Self::default()
+    }
+
+    pub fn with_total(mut self, bytes: usize) -> Self {
+        self.total = Some(bytes);
+        self
+    }
+
+    pub fn with_per_module(mut self, bytes: usize) -> Self {
+        self.per_module = Some(bytes);
+        self
+    }
+
+    /// Overrides the per-module limit for `id`.
+    pub fn set_module_limit(&mut self, id: ModuleId, bytes: usize) {
+        // Unbounded map: inserting cannot fail.
+        let _ = self.overrides.insert(id, bytes);
+    }
+
+    /// Limit for one module.
+    pub fn module_limit(&self, id: ModuleId) -> Option<usize> {
+        self.overrides.get(id).copied().or(self.per_module)
+    }
+
+    /// Checks that a `len`-byte image for `id` fits, given the size of every
+    /// module stored now.
+    pub fn check(
+        &self,
+        id: ModuleId,
+        len: usize,
+        stored: impl IntoIterator<Item = (ModuleId, usize)>,
+    ) -> Result<()> {
+        if let Some(limit) = self.module_limit(id) {
+            if len > limit {
+                return Err(Error::StorageFull {
+                    needed: len,
+                    available: limit,
+                });
+            }
+        }
+        if let Some(total) = self.total {
+            let others: usize = stored
+                .into_iter()
+                .filter(|(other, _)| *other != id)
+                .map(|(_, len)| len)
+                .sum();
+            let available = total.saturating_sub(others);
+            if len > available {
+                return Err(Error::StorageFull {
+                    needed: len,
+                    available,
+                });
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Store wrapper refusing installs past a `StorageQuota`.
+pub struct QuotaStore<S> {
+    inner: S,
+    quota: StorageQuota,
+}
+
+impl<S> QuotaStore<S> {
+    pub fn new(inner: S, quota: StorageQuota) -> Self {
+        Self { inner, quota }
+    }
+
+    pub fn quota(&self) -> &StorageQuota {
+        &self.quota
+    }
+
+    pub fn quota_mut(&mut self) -> &mut StorageQuota {
+        &mut self.quota
+    }
+
+    pub fn inner(&self) -> &S {
+        &self.inner
+    }
+
+    pub fn inner_mut(&mut self) -> &mut S {
+        &mut self.inner
+    }
+
+    pub fn into_inner(self) -> S {
+        self.inner
+    }
+}
+
+impl<S: ModuleSource + ModuleCatalog> QuotaStore<S> {
+    /// Bytes taken by all stored modules.
+    pub fn used(&self) -> usize {
+        self.stored().map(|(_, len)| len).sum()
+    }
+
+    fn stored(&self) -> impl Iterator<Item = (ModuleId, usize)> + '_ {
+        self.inner
+            .iter_ids()
+            .filter_map(|id| Some((id, self.inner.fetch(id)?.len())))
+    }
+}
+
+impl<S: ModuleSource> ModuleSource for QuotaStore<S> {
+    fn fetch(&self, id: ModuleId) -> Option<&[u8]> {
+        self.inner.fetch(id)
+    }
+}
+
+impl<S: ModuleCatalog> ModuleCatalog for QuotaStore<S> {
+    fn module_count(&self) -> usize {
+        self.inner.module_count()
+    }
+
+    fn module_id_at(&self, index: usize) -> Option<ModuleId> {
+        self.inner.module_id_at(index)
+    }
+}
+
+/// Checks the announced length on `begin`; the inner sink enforces that no
+/// more arrives.
+impl<S: ModuleSink + ModuleSource + ModuleCatalog> ModuleSink for QuotaStore<S> {
+    fn begin(&mut self, id: ModuleId, len: usize) -> Result<()> {
+        self.quota.check(id, len, self.stored())?;
+        self.inner.begin(id, len)
+    }
+
+    fn write(&mut self, chunk: &[u8]) -> Result<()> {
+        self.inner.write(chunk)
+    }
+
+    fn commit(&mut self) -> Result<()> {
+        self.inner.commit()
+    }
+
+    fn abort(&mut self) {
+        self.inner.abort();
+    }
+}
+
+#[cfg(all(test, feature = "std"))]
+mod tests {
+    use super::*;
+    use crate::{write_module, MemoryStore};
+
+    #[test]
+    fn refuses_images_past_module_and_total_limits() {
+        let mut quota = StorageQuota::unlimited()
+            .with_total(1000)
+            .with_per_module(400);
+        quota.set_module_limit(9, 600);
+        let mut store = QuotaStore::new(MemoryStore::new(), quota);
+
+        write_module(&mut store, 1, &[1; 400]).unwrap();
+        assert_eq!(
+            write_module(&mut store, 2, &[2; 401]),
+            Err(Error::StorageFull {
+                needed: 401,
+                available: 400
+            })
+        );
+        write_module(&mut store, 9, &[9; 550]).unwrap();
+        assert_eq!(
+            write_module(&mut store, 2, &[2; 100]),
+            Err(Error::StorageFull {
+                needed: 100,
+                available: 50
+            })
+        );
+        // Replacing a module frees its old image first.
+        write_module(&mut store, 9, &[9; 600]).unwrap();
+        assert_eq!(store.used(), 1000);
+        assert_eq!(store.fetch(2), None);
+    }
+}
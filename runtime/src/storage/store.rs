@@ -0,0 +1,384 @@
+//! Flash store that keeps its modules where an on-flash `index` says they are.
+//!
+//! `IndexedStore` writes each image into free space of the region, starting on
+//! an erase-block boundary, and then rewrites the index kept in the erase
+//! blocks after the region. Images are always written before the index that
+//! points at them, so an interrupted write or move leaves the previous copy in
+//! use. A replaced version stays in flash, marked superseded, until `gc` drops
+//! it; `compact` then moves the remaining images towards the start of the
+//! region so the free space becomes one span again.
+//!
+//! Read modules with `read_module`, or map the region and hand `live_entries`
+//! to an `IndexedSliceSource`.
+
+use super::index::{self, FlashIndex, IndexRecord, SlotState, INDEX_MAGIC};
+use super::quota::StorageQuota;
+use super::{align_up, FlashIo, IndexEntry};
+use crate::{fallible, Error, ModuleId, ModuleSink, Result, Staging};
+use alloc::vec::Vec;
+
+/// Modules in a flash region, located through an index stored after it.
+pub struct IndexedStore<IO: FlashIo> {
+    io: IO,
+    region_len: u32,
+    erase_block: u32,
+    records: Vec<IndexRecord>,
+    erase_counts: Vec<u16>,
+    quota: StorageQuota,
+    staging: Staging,
+}
+
+impl<IO: FlashIo> IndexedStore<IO> {
+    /// Opens the store on `io`: modules in the first `region_len` bytes, the
+    /// index in the rest. Erased flash opens an empty store; an index that is
+    /// damaged or describes another geometry is an error.
+    pub fn open(io: IO, region_len: u32, erase_block: u32) -> Result<Self> {
+        if erase_block == 0 || !region_len.is_multiple_of(erase_block) {
+            return Err(Error::Engine("store: region not erase-block aligned"));
+        }
+        let index_len = io
+            .capacity()
+            .checked_sub(region_len as usize)
+            .filter(|len| *len > 0)
+            .ok_or(Error::Engine("store: no room for the index"))?;
+        let mut bytes = fallible::filled(index_len, 0)?;
+        io.read(region_len as usize, &mut bytes)?;
+
+        let blocks = (region_len / erase_block) as usize;
+        let mut store = Self {
+            io,
+            region_len,
+            erase_block,
+            records: Vec::new(),
+            erase_counts: alloc::vec![0; blocks],
+            quota: StorageQuota::unlimited(),
+            staging: Staging::default(),
+        };
+        if !bytes.starts_with(INDEX_MAGIC) {
+            return Ok(store);
+        }
+        let index = FlashIndex::parse(&bytes).map_err(|_| Error::Engine("store: index damaged"))?;
+        if index.region_len != region_len || index.erase_block != erase_block {
+            return Err(Error::Engine("store: index geometry mismatch"));
+        }
+        store.records = index.entries().collect();
+        for (slot, count) in store.erase_counts.iter_mut().zip(index.erase_counts()) {
+            *slot = count;
+        }
+        Ok(store)
+    }
+
+    /// Limits the live images; superseded ones only take region space.
+    pub fn set_quota(&mut self, quota: StorageQuota) {
+        self.quota = quota;
+    }
+
+    /// Every image in the region, superseded ones included.
+    pub fn records(&self) -> &[IndexRecord] {
+        &self.records
+    }
+
+    /// Live image of a module.
+    pub fn live(&self, id: ModuleId) -> Option<IndexRecord> {
+        self.records
+            .iter()
+            .copied()
+            .find(|r| r.module_id == id && r.state == SlotState::Live)
+    }
+
+    /// Live images as entries for an `IndexedSliceSource` over the region.
+    pub fn live_entries(&self) -> Vec<IndexEntry> {
+        self.records
+            .iter()
+            .filter(|r| r.state == SlotState::Live)
+            .map(IndexRecord::index_entry)
+            .collect()
+    }
+
+    /// Erase counter per erase block of the region.
+    pub fn erase_counts(&self) -> &[u16] {
+        &self.erase_counts
+    }
+
+    /// Bytes not taken by any image, in erase blocks.
+    pub fn free_bytes(&self) -> u32 {
+        let used: u32 = self.records.iter().map(|r| self.span(r.len)).sum();
+        self.region_len - used
+    }
+
+    /// Reads a module's live image into `out`.
+    pub fn read_module(&self, id: ModuleId, out: &mut Vec<u8>) -> Result<()> {
+        let record = self.live(id).ok_or(Error::ModuleNotFound)?;
+        out.clear();
+        fallible::resize(out, record.len as usize, 0)?;
+        self.io.read(record.offset as usize, out)
+    }
+
+    /// Writes `bytes` as the live image of `id` at manifest sequence
+    /// `version`; the previous image is kept, superseded. Fails with
+    /// `Error::StorageFull` past the quota or when no free span is large
+    /// enough (`gc` and `compact` may make room).
+    pub fn install(&mut self, id: ModuleId, version: u32, bytes: &[u8]) -> Result<IndexRecord> {
+        if bytes.is_empty() {
+            return Err(Error::Engine("store: empty image"));
+        }
+        let offset = self.reserve(id, bytes.len())?;
+        self.program(offset, bytes)?;
+        for record in &mut self.records {
+            if record.module_id == id && record.state == SlotState::Live {
+                record.state = SlotState::Superseded;
+            }
+        }
+        let record = IndexRecord {
+            module_id: id,
+            offset,
+            len: bytes.len() as u32,
+            version,
+            state: SlotState::Live,
+        };
+        self.records.push(record);
+        self.persist()?;
+        Ok(record)
+    }
+
+    /// Drops superseded images and the modules not in `retain` from the
+    /// index; returns the bytes freed. The images stay in flash until their
+    /// space is reused.
+    pub fn gc(&mut self, retain: &[ModuleId]) -> Result<u32> {
+        let before = self.free_bytes();
+        self.records
+            .retain(|r| r.state == SlotState::Live && retain.contains(&r.module_id));
+        let freed = self.free_bytes() - before;
+        if freed > 0 {
+            self.persist()?;
+        }
+        Ok(freed)
+    }
+
+    /// Moves images towards the start of the region, in order, so the free
+    /// space ends up as one span at the end; returns how many moved.
+    ///
+    /// An image whose new place overlaps its current one is first copied to
+    /// free space further on. When there is none, compaction stops early,
+    /// leaving every image intact.
+    pub fn compact(&mut self) -> Result<usize> {
+        let mut order: Vec<usize> = (0..self.records.len()).collect();
+        order.sort_by_key(|&i| self.records[i].offset);
+        let mut moved = 0;
+        let mut cursor = 0;
+        for i in order {
+            let record = self.records[i];
+            let span = self.span(record.len);
+            if record.offset > cursor {
+                if cursor + span > record.offset {
+                    let Some(spare) = self.find_free(span) else {
+                        break;
+                    };
+                    self.relocate(i, spare)?;
+                }
+                self.relocate(i, cursor)?;
+                moved += 1;
+            }
+            cursor += span;
+        }
+        Ok(moved)
+    }
+
+    pub fn io(&self) -> &IO {
+        &self.io
+    }
+
+    pub fn into_inner(self) -> IO {
+        self.io
+    }
+
+    /// Bytes an image of `len` bytes occupies.
+    fn span(&self, len: u32) -> u32 {
+        align_up(len as usize, self.erase_block as usize) as u32
+    }
+
+    /// Free spans of the region as `(offset, len)`, in order.
+    fn gaps(&self) -> Vec<(u32, u32)> {
+        let mut taken: Vec<(u32, u32)> = self
+            .records
+            .iter()
+            .map(|r| (r.offset, r.offset + self.span(r.len)))
+            .collect();
+        taken.sort_unstable();
+        let mut gaps = Vec::new();
+        let mut cursor = 0;
+        for (start, end) in taken {
+            if start > cursor {
+                gaps.push((cursor, start - cursor));
+            }
+            cursor = cursor.max(end);
+        }
+        if self.region_len > cursor {
+            gaps.push((cursor, self.region_len - cursor));
+        }
+        gaps
+    }
+
+    /// Lowest offset with `span` free bytes.
+    fn find_free(&self, span: u32) -> Option<u32> {
+        self.gaps()
+            .into_iter()
+            .find(|(_, len)| *len >= span)
+            .map(|(offset, _)| offset)
+    }
+
+    /// Where a `len`-byte image for `id` would go, if the quota allows it.
+    fn reserve(&self, id: ModuleId, len: usize) -> Result<u32> {
+        let live = self
+            .records
+            .iter()
+            .filter(|r| r.state == SlotState::Live)
+            .map(|r| (r.module_id, r.len as usize));
+        self.quota.check(id, len, live)?;
+        let span = u32::try_from(align_up(len, self.erase_block as usize)).ok();
+        span.and_then(|span| self.find_free(span))
+            .ok_or_else(|| Error::StorageFull {
+                needed: len,
+                available: self
+                    .gaps()
+                    .iter()
+                    .map(|(_, len)| *len as usize)
+                    .max()
+                    .unwrap_or(0),
+            })
+    }
+
+    fn relocate(&mut self, i: usize, to: u32) -> Result<()> {
+        let record = self.records[i];
+        let mut bytes = fallible::filled(record.len as usize, 0)?;
+        self.io.read(record.offset as usize, &mut bytes)?;
+        self.program(to, &bytes)?;
+        self.records[i].offset = to;
+        self.persist()
+    }
+
+    fn program(&mut self, offset: u32, bytes: &[u8]) -> Result<()> {
+        self.io.erase_write(offset as usize, bytes)?;
+        let first = offset / self.erase_block;
+        let blocks = self.span(bytes.len() as u32) / self.erase_block;
+        for count in &mut self.erase_counts[first as usize..(first + blocks) as usize] {
+            *count = count.saturating_add(1);
+        }
+        Ok(())
+    }
+
+    fn persist(&mut self) -> Result<()> {
+        let bytes = index::encode(
+            self.region_len,
+            self.erase_block,
+            &self.records,
+            &self.erase_counts,
+        )?;
+        if bytes.len() > self.io.capacity() - self.region_len as usize {
+            return Err(Error::Engine("store: index area full"));
+        }
+        self.io.erase_write(self.region_len as usize, &bytes)
+    }
+}
+
+/// Drops every image of the module from the index, then overwrites all free
+/// space of the region, where superseded, collected and moved copies remain.
+#[cfg(feature = "secure-erase")]
+impl<IO: FlashIo> crate::erase::SecureErase for IndexedStore<IO> {
+    fn secure_erase(&mut self, id: ModuleId) -> Result<bool> {
+        self.staging.wipe_for(id);
+        let before = self.records.len();
+        self.records.retain(|r| r.module_id != id);
+        let removed = self.records.len() != before;
+        if removed {
+            self.persist()?;
+        }
+        let zeros = fallible::filled(self.erase_block as usize, 0)?;
+        for (offset, len) in self.gaps() {
+            for block in (offset..offset + len).step_by(self.erase_block as usize) {
+                self.program(block, &zeros)?;
+            }
+        }
+        // Records the wear of the overwrite.
+        self.persist()?;
+        Ok(removed)
+    }
+}
+
+/// Staged in RAM and installed on `commit` at the live version plus one; use
+/// `install` to record the manifest sequence instead.
+impl<IO: FlashIo> ModuleSink for IndexedStore<IO> {
+    fn begin(&mut self, id: ModuleId, len: usize) -> Result<()> {
+        self.reserve(id, len)?;
+        self.staging.begin(id, len)
+    }
+
+    fn write(&mut self, chunk: &[u8]) -> Result<()> {
+        self.staging.write(chunk)
+    }
+
+    fn commit(&mut self) -> Result<()> {
+        let (id, bytes) = self.staging.finish()?;
+        let version = self.live(id).map_or(1, |r| r.version.wrapping_add(1));
+        self.install(id, version, &bytes).map(drop)
+    }
+
+    fn abort(&mut self) {
+        self.staging.abort();
+    }
+}
+
+#[cfg(all(test, feature = "std"))]
+mod tests {
+    use super::*;
+    use crate::storage::MemoryFlash;
+    use crate::write_module;
+
+    const BLOCK: u32 = 256;
+    const REGION: u32 = 8 * BLOCK;
+
+    fn open(flash: MemoryFlash) -> IndexedStore<MemoryFlash> {
+        IndexedStore::open(flash, REGION, BLOCK).unwrap()
+    }
+
+    #[test]
+    fn gc_and_compaction_reclaim_superseded_space() {
+        let mut store = open(MemoryFlash::new((REGION + BLOCK) as usize));
+        write_module(&mut store, 1, &[0x11; 100]).unwrap();
+        write_module(&mut store, 1, &[0x12; 100]).unwrap();
+        store.install(3, 7, &[0x33; 400]).unwrap();
+        assert_eq!(store.live(1).map(|r| (r.offset, r.version)), Some((256, 2)));
+        assert_eq!(store.records().len(), 3);
+        // Blocks 4..8 are free but too short.
+        assert_eq!(
+            store.install(4, 1, &[0x44; 1280]),
+            Err(Error::StorageFull {
+                needed: 1280,
+                available: 1024
+            })
+        );
+
+        assert_eq!(store.gc(&[1, 3]).unwrap(), BLOCK);
+        // Module 3 overlaps its new place, so it hops through free space.
+        assert_eq!(store.compact().unwrap(), 2);
+        assert_eq!(store.live(3).unwrap().offset, BLOCK);
+        assert_eq!(store.erase_counts(), [2, 2, 2, 1, 1, 1, 0, 0]);
+        assert_eq!(store.install(4, 1, &[0x44; 1280]).unwrap().offset, 768);
+        assert_eq!(store.free_bytes(), 0);
+        store.set_quota(StorageQuota::unlimited().with_per_module(200));
+        assert!(matches!(
+            write_module(&mut store, 1, &[0x13; 201]),
+            Err(Error::StorageFull { available: 200, .. })
+        ));
+
+        let mut store = open(store.into_inner());
+        let mut module = Vec::new();
+        store.read_module(3, &mut module).unwrap();
+        assert_eq!(module, [0x33; 400]);
+        assert_eq!(store.live(1).unwrap().offset, 0);
+        assert_eq!(store.erase_counts()[7], 1);
+        assert_eq!(store.gc(&[4]).unwrap(), 768);
+        assert!(store.read_module(1, &mut module).is_err());
+        assert!(IndexedStore::open(store.into_inner(), REGION, 2 * BLOCK).is_err());
+    }
+}
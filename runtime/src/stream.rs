@@ -0,0 +1,455 @@
+//! BLAKE3 verified streaming for large assets (bao outboard tree).
+//!
+//! The root is the plain BLAKE3 hash of the asset (what `b3sum` prints), and
+//! the tree is BLAKE3's own: assets are split into `CHUNK_LEN` chunks, each a
+//! group of four 1 KiB BLAKE3 chunks whose chaining value is computed at its
+//! offset, and the left subtree of every parent holds the largest
+//! power-of-two number of chunks strictly below its total. The outboard
+//! encoding stores each parent node (left chaining value || right chaining
+//! value, 64 bytes) in pre-order, as bao does with 4 KiB chunk groups and
+//! without its 8-byte length header:
+//! - `StreamVerifier` walks it front to back while chunks arrive, so every chunk
+//!   is checked against the root before the caller writes it anywhere.
+//! - `verify_chunk` jumps straight to the path for a single chunk, so gateways can
+//!   serve verified partial reads without holding (or re-hashing) the whole asset.
+//!
+//! As in bao, the total length is authenticated by the last chunk: a
+//! stream of a truncated or extended asset never verifies, and a partial read
+//! with a wrong length only verifies chunks that are really the asset's.
+//! Verification itself is allocation-free.
+
+use crate::{Error, Result};
+use blake3::hazmat::{merge_subtrees_non_root, merge_subtrees_root, HasherExt, Mode};
+
+/// Chunk size in bytes (matches the common 4 KiB flash erase block).
+pub const CHUNK_LEN: usize = 4096;
+/// Size of one encoded parent node (left || right chaining value).
+pub const PARENT_LEN: usize = 64;
+/// Length of a BLAKE3 hash.
+pub const HASH_LEN: usize = 32;
+/// Deepest tree the verifier tracks (2^40 chunks, far beyond any real asset).
+pub const MAX_DEPTH: usize = 40;
+
+/// Raw BLAKE3 hash bytes.
+pub type Hash = [u8; HASH_LEN];
+
+/// Root hash of a lone chunk, chaining value of chunk `index` otherwise.
+fn leaf_hash(index: u64, chunk: &[u8], is_root: bool) -> blake3::Hash {
+    if is_root {
+        return blake3::hash(chunk);
+    }
+    let mut hasher = blake3::Hasher::new();
+    hasher.set_input_offset(index * CHUNK_LEN as u64);
+    hasher.update(chunk);
+    hasher.finalize_non_root().into()
+}
+
+/// Root hash of the top parent, chaining value of the others.
+fn parent_hash(node: &[u8; PARENT_LEN], is_root: bool) -> blake3::Hash {
+    let (left, right) = node.split_at(HASH_LEN);
+    let (left, right) = (left.try_into().unwrap(), right.try_into().unwrap());
+    if is_root {
+        merge_subtrees_root(left, right, Mode::Hash)
+    } else {
+        merge_subtrees_non_root(left, right, Mode::Hash).into()
+    }
+}
+
+/// Number of chunks for an asset (an empty asset is a single empty chunk).
+pub const fn chunk_count(total_len: u64) -> u64 {
+    if total_len == 0 {
+        1
+    } else {
+        total_len.div_ceil(CHUNK_LEN as u64)
+    }
+}
+
+/// Size of the outboard tree for an asset of `total_len` bytes.
+pub const fn outboard_len(total_len: u64) -> u64 {
+    (chunk_count(total_len) - 1) * PARENT_LEN as u64
+}
+
+/// Chunks held by the left subtree of a parent covering `count` (> 1) chunks.
+const fn left_chunks(count: u64) -> u64 {
+    1 << (63 - (count - 1).leading_zeros())
+}
+
+const fn tree_depth(count: u64) -> usize {
+    if count <= 1 {
+        0
+    } else {
+        (64 - (count - 1).leading_zeros()) as usize
+    }
+}
+
+fn chunk_len_at(total_len: u64, index: u64) -> usize {
+    let start = index * CHUNK_LEN as u64;
+    core::cmp::min(CHUNK_LEN as u64, total_len.saturating_sub(start)) as usize
+}
+
+/// Hashes an asset and builds its outboard tree. Returns `(root, outboard)`;
+/// `root` is `blake3::hash(data)`.
+#[cfg(feature = "alloc")]
+pub fn outboard(data: &[u8]) -> (Hash, alloc::vec::Vec<u8>) {
+    let total_len = data.len() as u64;
+    let count = chunk_count(total_len);
+    let mut out = alloc::vec::Vec::with_capacity(outboard_len(total_len) as usize);
+    let root = encode_subtree(data, 0, count, true, &mut out);
+    (*root.as_bytes(), out)
+}
+
+#[cfg(feature = "alloc")]
+fn encode_subtree(
+    data: &[u8],
+    start: u64,
+    count: u64,
+    is_root: bool,
+    out: &mut alloc::vec::Vec<u8>,
+) -> blake3::Hash {
+    if count == 1 {
+        let begin = (start as usize) * CHUNK_LEN;
+        let end = core::cmp::min(begin + CHUNK_LEN, data.len());
+        return leaf_hash(start, &data[begin..end], is_root);
+    }
+
+    let pos = out.len();
+    out.extend_from_slice(&[0u8; PARENT_LEN]);
+    let left = left_chunks(count);
+    let l = encode_subtree(data, start, left, false, out);
+    let r = encode_subtree(data, start + left, count - left, false, out);
+
+    let mut node = [0u8; PARENT_LEN];
+    node[..HASH_LEN].copy_from_slice(l.as_bytes());
+    node[HASH_LEN..].copy_from_slice(r.as_bytes());
+    out[pos..pos + PARENT_LEN].copy_from_slice(&node);
+    parent_hash(&node, is_root)
+}
+
+/// Verifies one chunk against the root using only the outboard tree.
+///
+/// `outboard` may be a memory-mapped flash region or file view; only the
+/// parents on the chunk's path are read.
+pub fn verify_chunk(
+    root: &Hash,
+    total_len: u64,
+    outboard: &[u8],
+    index: u64,
+    chunk: &[u8],
+) -> Result<()> {
+    let mut count = chunk_count(total_len);
+    if index >= count {
+        return Err(Error::Engine("asset chunk out of range"));
+    }
+    if tree_depth(count) > MAX_DEPTH {
+        return Err(Error::Engine("asset too large"));
+    }
+    if outboard.len() as u64 != outboard_len(total_len) {
+        return Err(Error::Engine("asset outboard len mismatch"));
+    }
+    if chunk.len() != chunk_len_at(total_len, index) {
+        return Err(Error::Engine("asset chunk len mismatch"));
+    }
+
+    let mut start = 0u64;
+    let mut pos = 0usize;
+    let mut expected = blake3::Hash::from(*root);
+    let mut is_root = true;
+    while count > 1 {
+        let node = read_parent(outboard, pos)?;
+        check(&expected, &parent_hash(&node, is_root))?;
+
+        let left = left_chunks(count);
+        let mut half = [0u8; HASH_LEN];
+        if index < start + left {
+            half.copy_from_slice(&node[..HASH_LEN]);
+            pos += 1;
+            count = left;
+        } else {
+            half.copy_from_slice(&node[HASH_LEN..]);
+            pos += left as usize;
+            start += left;
+            count -= left;
+        }
+        expected = blake3::Hash::from(half);
+        is_root = false;
+    }
+
+    check(&expected, &leaf_hash(index, chunk, is_root))
+}
+
+fn read_parent(outboard: &[u8], pos: usize) -> Result<[u8; PARENT_LEN]> {
+    let begin = pos
+        .checked_mul(PARENT_LEN)
+        .ok_or(Error::Engine("asset outboard overflow"))?;
+    outboard
+        .get(begin..begin + PARENT_LEN)
+        .and_then(|node| node.try_into().ok())
+        .ok_or(Error::Engine("asset outboard truncated"))
+}
+
+/// Compares against the root or the chaining value the parent provided.
+fn check(expected: &blake3::Hash, computed: &blake3::Hash) -> Result<()> {
+    // blake3::Hash equality is constant-time.
+    if expected == computed {
+        Ok(())
+    } else {
+        Err(Error::Engine("asset hash mismatch"))
+    }
+}
+
+/// What the verifier needs next from the transport.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Next {
+    /// The next 64-byte outboard parent node.
+    Parent,
+    /// The next chunk of asset data.
+    Chunk { index: u64, offset: u64, len: usize },
+    /// Every chunk has been verified.
+    Done,
+}
+
+#[derive(Clone, Copy)]
+struct Pending {
+    hash: [u8; HASH_LEN],
+    is_root: bool,
+    start: u64,
+    count: u64,
+}
+
+const EMPTY: Pending = Pending {
+    hash: [0; HASH_LEN],
+    is_root: false,
+    start: 0,
+    count: 0,
+};
+
+/// Incremental verifier for an asset streamed in order.
+///
+/// Ask `next()` what to read, then feed it with `push_parent` / `push_chunk`.
+/// Parents arrive in outboard order, so either a separate outboard stream or a
+/// combined (interleaved) stream works. A chunk is only handed back once it has
+/// been checked, so callers can write it straight to flash.
+pub struct StreamVerifier {
+    total_len: u64,
+    stack: [Pending; MAX_DEPTH + 1],
+    depth: usize,
+}
+
+impl StreamVerifier {
+    /// Starts verifying an asset of `total_len` bytes against `root`.
+    pub fn new(root: Hash, total_len: u64) -> Result<Self> {
+        let count = chunk_count(total_len);
+        if tree_depth(count) > MAX_DEPTH {
+            return Err(Error::Engine("asset too large"));
+        }
+        let mut stack = [EMPTY; MAX_DEPTH + 1];
+        stack[0] = Pending {
+            hash: root,
+            is_root: true,
+            start: 0,
+            count,
+        };
+        Ok(Self {
+            total_len,
+            stack,
+            depth: 1,
+        })
+    }
+
+    /// Total asset length being verified.
+    pub fn total_len(&self) -> u64 {
+        self.total_len
+    }
+
+    /// Returns the next item the verifier expects.
+    pub fn next(&self) -> Next {
+        match self.top() {
+            None => Next::Done,
+            Some(p) if p.count > 1 => Next::Parent,
+            Some(p) => Next::Chunk {
+                index: p.start,
+                offset: p.start * CHUNK_LEN as u64,
+                len: chunk_len_at(self.total_len, p.start),
+            },
+        }
+    }
+
+    /// Feeds the next outboard parent node.
+    pub fn push_parent(&mut self, node: &[u8; PARENT_LEN]) -> Result<()> {
+        let pending = match self.top() {
+            Some(p) if p.count > 1 => *p,
+            _ => return Err(Error::Engine("asset stream out of order")),
+        };
+        self.verify(&pending, &parent_hash(node, pending.is_root))?;
+
+        let left = left_chunks(pending.count);
+        let mut right = Pending {
+            hash: [0; HASH_LEN],
+            is_root: false,
+            start: pending.start + left,
+            count: pending.count - left,
+        };
+        right.hash.copy_from_slice(&node[HASH_LEN..]);
+        let mut left_entry = Pending {
+            hash: [0; HASH_LEN],
+            is_root: false,
+            start: pending.start,
+            count: left,
+        };
+        left_entry.hash.copy_from_slice(&node[..HASH_LEN]);
+
+        // Replace the parent with its children; left is popped first.
+        self.stack[self.depth - 1] = right;
+        self.stack[self.depth] = left_entry;
+        self.depth += 1;
+        Ok(())
+    }
+
+    /// Feeds the next chunk and returns it once verified.
+    pub fn push_chunk<'c>(&mut self, chunk: &'c [u8]) -> Result<&'c [u8]> {
+        let pending = match self.top() {
+            Some(p) if p.count == 1 => *p,
+            _ => return Err(Error::Engine("asset stream out of order")),
+        };
+        if chunk.len() != chunk_len_at(self.total_len, pending.start) {
+            return Err(Error::Engine("asset chunk len mismatch"));
+        }
+        self.verify(&pending, &leaf_hash(pending.start, chunk, pending.is_root))?;
+        self.depth -= 1;
+        Ok(chunk)
+    }
+
+    /// Confirms every chunk has been verified.
+    pub fn finish(&self) -> Result<()> {
+        if self.depth == 0 {
+            Ok(())
+        } else {
+            Err(Error::Engine("asset stream incomplete"))
+        }
+    }
+
+    fn top(&self) -> Option<&Pending> {
+        self.depth.checked_sub(1).map(|i| &self.stack[i])
+    }
+
+    fn verify(&self, pending: &Pending, computed: &blake3::Hash) -> Result<()> {
+        check(&blake3::Hash::from(pending.hash), computed)
+    }
+}
+
+#[cfg(all(test, feature = "std"))]
+mod tests {
+    use super::*;
+
+    fn sample(len: usize) -> Vec<u8> {
+        (0..len).map(|i| (i * 31 % 251) as u8).collect()
+    }
+
+    fn stream_all(root: Hash, data: &[u8], outboard: &[u8]) -> Result<Vec<u8>> {
+        let mut verifier = StreamVerifier::new(root, data.len() as u64)?;
+        let mut parents = outboard.chunks_exact(PARENT_LEN);
+        let mut out = Vec::new();
+        loop {
+            match verifier.next() {
+                Next::Parent => {
+                    let node = parents.next().ok_or(Error::Engine("short outboard"))?;
+                    verifier.push_parent(node.try_into().unwrap())?;
+                }
+                Next::Chunk { offset, len, .. } => {
+                    let start = offset as usize;
+                    out.extend_from_slice(verifier.push_chunk(&data[start..start + len])?);
+                }
+                Next::Done => break,
+            }
+        }
+        verifier.finish()?;
+        Ok(out)
+    }
+
+    #[test]
+    fn streams_and_verifies_multi_chunk_asset() {
+        for len in [0, 1, CHUNK_LEN, CHUNK_LEN + 1, 5 * CHUNK_LEN + 17] {
+            let data = sample(len);
+            let (root, outboard) = outboard(&data);
+            assert_eq!(root, *blake3::hash(&data).as_bytes());
+            assert_eq!(outboard.len() as u64, outboard_len(len as u64));
+            assert_eq!(stream_all(root, &data, &outboard).unwrap(), data);
+        }
+    }
+
+    #[test]
+    fn stream_rejects_tampered_chunk() {
+        let mut data = sample(3 * CHUNK_LEN + 5);
+        let (root, outboard) = outboard(&data);
+        data[2 * CHUNK_LEN + 1] ^= 0xFF;
+        assert_eq!(
+            stream_all(root, &data, &outboard),
+            Err(Error::Engine("asset hash mismatch"))
+        );
+    }
+
+    #[test]
+    fn partial_reads_verify_single_chunks() {
+        let data = sample(7 * CHUNK_LEN + 100);
+        let (root, outboard) = outboard(&data);
+        let total = data.len() as u64;
+
+        for index in 0..chunk_count(total) {
+            let start = index as usize * CHUNK_LEN;
+            let end = core::cmp::min(start + CHUNK_LEN, data.len());
+            verify_chunk(&root, total, &outboard, index, &data[start..end]).unwrap();
+        }
+
+        let mut bad = data[CHUNK_LEN..2 * CHUNK_LEN].to_vec();
+        bad[0] ^= 1;
+        assert!(verify_chunk(&root, total, &outboard, 1, &bad).is_err());
+        // Right bytes, wrong position.
+        assert!(verify_chunk(&root, total, &outboard, 2, &data[..CHUNK_LEN]).is_err());
+    }
+
+    #[test]
+    fn last_chunk_commits_to_length() {
+        let data = sample(2 * CHUNK_LEN);
+        let (root, outboard) = outboard(&data);
+        let total = data.len() as u64;
+        assert!(stream_all(root, &data[..data.len() - 1], &outboard).is_err());
+        assert!(verify_chunk(
+            &root,
+            total - 1,
+            &outboard,
+            1,
+            &data[CHUNK_LEN..total as usize - 1]
+        )
+        .is_err());
+        let mut extended = data.clone();
+        extended.push(0);
+        let ext_outboard = super::outboard(&extended).1;
+        assert!(verify_chunk(&root, total + 1, &ext_outboard, 2, &[0]).is_err());
+    }
+
+    #[test]
+    fn trees_match_bao_chunk_groups() {
+        // Parents of a three-chunk asset, computed from BLAKE3's public
+        // subtree hashing: chunks 0 and 1 form the left subtree.
+        let data = sample(2 * CHUNK_LEN + 1);
+        let (root, outboard) = outboard(&data);
+        let subtree = |offset: usize, len: usize| {
+            let mut hasher = blake3::Hasher::new();
+            hasher.set_input_offset(offset as u64);
+            hasher.update(&data[offset..offset + len]);
+            hasher.finalize_non_root()
+        };
+        let left = subtree(0, 2 * CHUNK_LEN);
+        let right = subtree(2 * CHUNK_LEN, 1);
+        assert_eq!(outboard[..HASH_LEN], left);
+        assert_eq!(outboard[HASH_LEN..PARENT_LEN], right);
+        assert_eq!(
+            outboard[PARENT_LEN..PARENT_LEN + HASH_LEN],
+            subtree(0, CHUNK_LEN)
+        );
+        assert_eq!(
+            root,
+            *merge_subtrees_root(&left, &right, Mode::Hash).as_bytes()
+        );
+    }
+}
@@ -0,0 +1,537 @@
+//! SUIT/COSE manifest envelopes (`manifest-suit` feature).
+//!
+//! An alternative to the compact `SMNY` header for deployments that have
+//! standardized on IETF SUIT tooling (RFC 9019 architecture,
+//! draft-ietf-suit-manifest). `SMNY` stays the default;
+//! `manifest::ManifestFormat::detect` tells the two apart. slimmy reads and
+//! writes this subset:
+//!
+//! ```text
+//! SUIT_Envelope = #6.107({
+//!   2: bstr .cbor [                  ; authentication wrapper
+//!        bstr .cbor SUIT_Digest,     ; SHA-256 of the manifest bstr
+//!        * bstr .cbor COSE_Sign1,    ; EdDSA; detached payload = the digest bstr
+//!      ],
+//!   3: bstr .cbor SUIT_Manifest,
+//!   "#module": bstr,                 ; integrated payload: the wasm module
+//! })
+//! SUIT_Manifest = {
+//!   1: 1,                            ; manifest version
+//!   2: uint,                         ; sequence number (slimmy `sequence`)
+//!   3: bstr .cbor {                  ; common
+//!        2: [[ bstr ]],              ; one component: module id, u32 big endian
+//!        4: bstr .cbor [ 20, {       ; shared sequence: override parameters
+//!             3: bstr .cbor SUIT_Digest,  ; image digest (module)
+//!             14: uint,              ; image size
+//!             -257: tstr,            ; entry point
+//!             -258: uint,            ; FLAG_* bits
+//!           } ],
+//!      },
+//! }
+//! SUIT_Digest = [ -16, bstr ]        ; SHA-256
+//! ```
+//!
+//! Parsing checks both digests, so a parsed envelope is intact; signatures are
+//! checked separately (`SuitManifest::verify_ed25519`). Unknown keys are
+//! skipped, and bytes after the envelope (e.g. flash padding) are ignored.
+//! Manifest extensions (states, schedule, dependencies) have no SUIT mapping
+//! yet.
+
+mod cbor;
+
+use crate::manifest::{ct_eq, Manifest, FLAG_REQUIRE_SIGNATURE};
+#[cfg(feature = "alloc")]
+use crate::verify::Verifier;
+use crate::{Error, ModuleId, Result};
+use cbor::{Key, Reader};
+use sha2::{Digest as _, Sha256};
+
+/// CBOR tag of a SUIT envelope.
+pub const ENVELOPE_TAG: u64 = 107;
+/// Envelope key of the integrated module payload.
+pub const PAYLOAD_KEY: &str = "#module";
+
+const ENVELOPE_AUTH: i64 = 2;
+const ENVELOPE_MANIFEST: i64 = 3;
+const MANIFEST_VERSION: i64 = 1;
+const MANIFEST_SEQUENCE: i64 = 2;
+const MANIFEST_COMMON: i64 = 3;
+const COMMON_COMPONENTS: i64 = 2;
+const COMMON_SHARED_SEQUENCE: i64 = 4;
+const DIRECTIVE_OVERRIDE_PARAMETERS: u64 = 20;
+const PARAM_IMAGE_DIGEST: i64 = 3;
+const PARAM_IMAGE_SIZE: i64 = 14;
+const PARAM_ENTRY: i64 = -257;
+const PARAM_FLAGS: i64 = -258;
+const DIGEST_SHA256: i64 = -16;
+const COSE_SIGN1_TAG: u64 = 18;
+/// COSE protected header `{1: -8}` (alg: EdDSA).
+#[cfg(feature = "alloc")]
+const PROTECTED_EDDSA: [u8; 3] = [0xA1, 0x01, 0x27];
+
+/// Parsed view into a SUIT envelope.
+#[derive(Debug, Clone, Copy)]
+pub struct SuitManifest<'a> {
+    pub module_id: ModuleId,
+    pub entry: &'a str,
+    pub flags: u8,
+    pub sequence: u32,
+    /// SHA-256 of the module, as carried in the manifest.
+    pub image_digest: [u8; 32],
+    module_len: u32,
+    /// Encoded manifest `SUIT_Digest`: the payload every signature covers.
+    digest: &'a [u8],
+    /// Authentication wrapper items after the digest.
+    signatures: &'a [u8],
+    signature_count: usize,
+}
+
+impl<'a> SuitManifest<'a> {
+    /// Parses an envelope and returns the view plus the module bytes.
+    pub fn parse(bytes: &'a [u8]) -> Result<(Self, &'a [u8])> {
+        let mut envelope = Reader::new(bytes);
+        if !envelope.optional_tag(ENVELOPE_TAG)? {
+            return Err(Error::Engine("suit envelope tag missing"));
+        }
+        let (mut auth, mut manifest, mut module) = (None, None, None);
+        for _ in 0..envelope.map()? {
+            match envelope.key()? {
+                Key::Int(ENVELOPE_AUTH) => auth = Some(envelope.bytes()?),
+                Key::Int(ENVELOPE_MANIFEST) => manifest = Some(envelope.bytes()?),
+                Key::Text(PAYLOAD_KEY) => module = Some(envelope.bytes()?),
+                _ => envelope.skip()?,
+            }
+        }
+        let auth = auth.ok_or(Error::Engine("suit authentication wrapper missing"))?;
+        let manifest = manifest.ok_or(Error::Engine("suit manifest missing"))?;
+        let module = module.ok_or(Error::Engine("suit module payload missing"))?;
+
+        let mut wrapper = Reader::new(auth);
+        let items = wrapper.array()?;
+        if items == 0 {
+            return Err(Error::Engine("suit manifest digest missing"));
+        }
+        let digest = wrapper.bytes()?;
+        if !ct_eq(&read_digest(digest)?, &sha256(manifest)) {
+            return Err(Error::Engine("suit manifest digest mismatch"));
+        }
+
+        let fields = Fields::parse(manifest)?;
+        let image_digest = fields
+            .image_digest
+            .ok_or(Error::Engine("suit image digest missing"))?;
+        let module_len = fields
+            .image_size
+            .ok_or(Error::Engine("suit image size missing"))?;
+        if module.len() != module_len as usize {
+            return Err(Error::Engine("suit image size mismatch"));
+        }
+        if !ct_eq(&sha256(module), &image_digest) {
+            return Err(Error::Engine("suit image digest mismatch"));
+        }
+        let signature_count = items - 1;
+        if fields.flags & FLAG_REQUIRE_SIGNATURE != 0 && signature_count == 0 {
+            return Err(Error::Engine("manifest requires signature"));
+        }
+
+        Ok((
+            Self {
+                module_id: fields
+                    .module_id
+                    .ok_or(Error::Engine("suit component missing"))?,
+                entry: fields.entry.ok_or(Error::Engine("suit entry missing"))?,
+                flags: fields.flags,
+                sequence: fields.sequence,
+                image_digest,
+                module_len,
+                digest,
+                signatures: wrapper.rest(),
+                signature_count,
+            },
+            module,
+        ))
+    }
+
+    /// Number of COSE_Sign1 signatures in the authentication wrapper.
+    pub fn signature_count(&self) -> usize {
+        self.signature_count
+    }
+
+    /// EdDSA signatures in the authentication wrapper, in order; malformed
+    /// or non-EdDSA COSE_Sign1 items are skipped.
+    pub fn signatures(&self) -> impl Iterator<Item = [u8; 64]> + 'a {
+        let digest = self.digest;
+        self.sign1_items()
+            .filter_map(move |item| read_sign1(item, digest).ok().map(|(_, sig)| sig))
+    }
+
+    fn sign1_items(&self) -> impl Iterator<Item = &'a [u8]> + 'a {
+        let mut items = Reader::new(self.signatures);
+        (0..self.signature_count).map_while(move |_| items.bytes().ok())
+    }
+
+    /// The same fields as a `Manifest` (version 0, no signature or
+    /// extensions), e.g. for `Runtime::apply_manifest`.
+    pub fn manifest(&self) -> Manifest<'a> {
+        Manifest::from_suit(
+            self.module_id,
+            self.module_len,
+            self.entry,
+            self.flags,
+            self.sequence,
+        )
+    }
+
+    /// Accepts the envelope when any of its signatures is a valid EdDSA
+    /// signature by `pubkey`, checked with `verifier`.
+    #[cfg(feature = "alloc")]
+    pub fn verify_with(&self, verifier: &dyn Verifier, pubkey: &[u8; 32]) -> Result<()> {
+        if self.signature_count == 0 {
+            return Err(Error::Engine("manifest missing signature"));
+        }
+        for item in self.sign1_items() {
+            let (protected, signature) = read_sign1(item, self.digest)?;
+            let preimage = sig_structure(protected, self.digest);
+            if verifier
+                .verify_ed25519(pubkey, &[&preimage], &signature)
+                .is_ok()
+            {
+                return Ok(());
+            }
+        }
+        Err(Error::Engine("signature verify failed"))
+    }
+
+    /// `verify_with` ed25519-dalek in software.
+    #[cfg(feature = "verify-ed25519")]
+    pub fn verify_ed25519(&self, pubkey: &[u8; 32]) -> Result<()> {
+        self.verify_with(&crate::verify::SoftwareVerifier, pubkey)
+    }
+}
+
+/// Manifest fields gathered while walking the nested bstrs.
+#[derive(Default)]
+struct Fields<'a> {
+    sequence: u32,
+    module_id: Option<ModuleId>,
+    image_digest: Option<[u8; 32]>,
+    image_size: Option<u32>,
+    entry: Option<&'a str>,
+    flags: u8,
+}
+
+impl<'a> Fields<'a> {
+    fn parse(manifest: &'a [u8]) -> Result<Self> {
+        let mut fields = Self::default();
+        let mut common = None;
+        let mut r = Reader::new(manifest);
+        for _ in 0..r.map()? {
+            match r.key()? {
+                Key::Int(MANIFEST_VERSION) => {
+                    if r.uint()? != 1 {
+                        return Err(Error::Engine("suit manifest version unsupported"));
+                    }
+                }
+                Key::Int(MANIFEST_SEQUENCE) => {
+                    fields.sequence = u32::try_from(r.uint()?)
+                        .map_err(|_| Error::Engine("suit sequence number too large"))?;
+                }
+                Key::Int(MANIFEST_COMMON) => common = Some(r.bytes()?),
+                _ => r.skip()?,
+            }
+        }
+        let mut r = Reader::new(common.ok_or(Error::Engine("suit common missing"))?);
+        for _ in 0..r.map()? {
+            match r.key()? {
+                Key::Int(COMMON_COMPONENTS) => fields.module_id = Some(read_component(&mut r)?),
+                Key::Int(COMMON_SHARED_SEQUENCE) => fields.shared_sequence(r.bytes()?)?,
+                _ => r.skip()?,
+            }
+        }
+        Ok(fields)
+    }
+
+    fn shared_sequence(&mut self, sequence: &'a [u8]) -> Result<()> {
+        let mut r = Reader::new(sequence);
+        let len = r.array()?;
+        if len % 2 != 0 {
+            return Err(Error::Engine("suit command sequence malformed"));
+        }
+        for _ in 0..len / 2 {
+            if r.uint()? != DIRECTIVE_OVERRIDE_PARAMETERS {
+                r.skip()?;
+                continue;
+            }
+            for _ in 0..r.map()? {
+                match r.key()? {
+                    Key::Int(PARAM_IMAGE_DIGEST) => {
+                        self.image_digest = Some(read_digest(r.bytes()?)?)
+                    }
+                    Key::Int(PARAM_IMAGE_SIZE) => {
+                        self.image_size = Some(
+                            u32::try_from(r.uint()?)
+                                .map_err(|_| Error::Engine("suit image too large"))?,
+                        )
+                    }
+                    Key::Int(PARAM_ENTRY) => self.entry = Some(r.text()?),
+                    Key::Int(PARAM_FLAGS) => {
+                        self.flags = u8::try_from(r.uint()?)
+                            .map_err(|_| Error::Engine("suit flags out of range"))?
+                    }
+                    _ => r.skip()?,
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// `[[ bstr ]]` naming one component by its big-endian module id.
+fn read_component(r: &mut Reader<'_>) -> Result<ModuleId> {
+    if r.array()? != 1 || r.array()? != 1 {
+        return Err(Error::Engine("suit expects one component"));
+    }
+    let id: [u8; 4] = r
+        .bytes()?
+        .try_into()
+        .map_err(|_| Error::Engine("suit component id malformed"))?;
+    Ok(ModuleId::from_be_bytes(id))
+}
+
+/// Decodes a SHA-256 `SUIT_Digest`.
+fn read_digest(encoded: &[u8]) -> Result<[u8; 32]> {
+    let mut r = Reader::new(encoded);
+    if r.array()? != 2 {
+        return Err(Error::Engine("suit digest malformed"));
+    }
+    if r.int()? != DIGEST_SHA256 {
+        return Err(Error::Engine("suit digest algorithm unsupported"));
+    }
+    r.bytes()?
+        .try_into()
+        .map_err(|_| Error::Engine("suit digest malformed"))
+}
+
+/// Protected header and signature of an EdDSA COSE_Sign1 over `payload`.
+fn read_sign1<'a>(encoded: &'a [u8], payload: &[u8]) -> Result<(&'a [u8], [u8; 64])> {
+    let mut r = Reader::new(encoded);
+    r.optional_tag(COSE_SIGN1_TAG)?;
+    if r.array()? != 4 {
+        return Err(Error::Engine("cose sign1 malformed"));
+    }
+    let protected = r.bytes()?;
+    let mut header = Reader::new(protected);
+    let mut eddsa = false;
+    for _ in 0..header.map()? {
+        match header.key()? {
+            Key::Int(1) => eddsa = header.int()? == -8,
+            _ => header.skip()?,
+        }
+    }
+    if !eddsa {
+        return Err(Error::Engine("cose algorithm unsupported"));
+    }
+    r.skip()?; // unprotected header
+    if !r.optional_null() && r.bytes()? != payload {
+        return Err(Error::Engine("cose payload mismatch"));
+    }
+    let signature = r
+        .bytes()?
+        .try_into()
+        .map_err(|_| Error::Engine("cose signature malformed"))?;
+    Ok((protected, signature))
+}
+
+/// COSE `Sig_structure` for a Sign1 with detached `payload`.
+#[cfg(feature = "alloc")]
+fn sig_structure(protected: &[u8], payload: &[u8]) -> alloc::vec::Vec<u8> {
+    let mut w = cbor::Writer::default();
+    w.array(4)
+        .text("Signature1")
+        .bytes(protected)
+        .bytes(&[])
+        .bytes(payload);
+    w.out
+}
+
+fn sha256(data: &[u8]) -> [u8; 32] {
+    Sha256::digest(data).into()
+}
+
+#[cfg(feature = "alloc")]
+fn encode_digest(data: &[u8]) -> alloc::vec::Vec<u8> {
+    let mut w = cbor::Writer::default();
+    w.array(2).int(DIGEST_SHA256).bytes(&sha256(data));
+    w.out
+}
+
+#[cfg(feature = "alloc")]
+fn encode_manifest(
+    module_id: ModuleId,
+    entry: &str,
+    module: &[u8],
+    flags: u8,
+    sequence: u32,
+) -> Result<alloc::vec::Vec<u8>> {
+    let image_size =
+        u32::try_from(module.len()).map_err(|_| Error::Engine("suit image too large"))?;
+    let mut params = cbor::Writer::default();
+    params
+        .array(2)
+        .uint(DIRECTIVE_OVERRIDE_PARAMETERS)
+        .map(4)
+        .int(PARAM_IMAGE_DIGEST)
+        .bytes(&encode_digest(module))
+        .int(PARAM_IMAGE_SIZE)
+        .uint(image_size as u64)
+        .int(PARAM_ENTRY)
+        .text(entry)
+        .int(PARAM_FLAGS)
+        .uint(flags as u64);
+
+    let mut common = cbor::Writer::default();
+    common
+        .map(2)
+        .int(COMMON_COMPONENTS)
+        .array(1)
+        .array(1)
+        .bytes(&module_id.to_be_bytes())
+        .int(COMMON_SHARED_SEQUENCE)
+        .bytes(&params.out);
+
+    let mut manifest = cbor::Writer::default();
+    manifest
+        .map(3)
+        .int(MANIFEST_VERSION)
+        .uint(1)
+        .int(MANIFEST_SEQUENCE)
+        .uint(sequence as u64)
+        .int(MANIFEST_COMMON)
+        .bytes(&common.out);
+    Ok(manifest.out)
+}
+
+#[cfg(feature = "alloc")]
+/// Builds the COSE `Sig_structure` to sign with Ed25519 for `encode`.
+pub fn signing_preimage(
+    module_id: ModuleId,
+    entry: &str,
+    module: &[u8],
+    flags: u8,
+    sequence: u32,
+) -> Result<alloc::vec::Vec<u8>> {
+    let manifest = encode_manifest(module_id, entry, module, flags, sequence)?;
+    Ok(sig_structure(&PROTECTED_EDDSA, &encode_digest(&manifest)))
+}
+
+#[cfg(feature = "alloc")]
+/// Builds a SUIT envelope (manifest, optional EdDSA COSE_Sign1, module).
+pub fn encode(
+    module_id: ModuleId,
+    entry: &str,
+    module: &[u8],
+    flags: u8,
+    sequence: u32,
+    signature: Option<[u8; 64]>,
+) -> Result<alloc::vec::Vec<u8>> {
+    let manifest = encode_manifest(module_id, entry, module, flags, sequence)?;
+
+    let mut auth = cbor::Writer::default();
+    auth.array(1 + signature.is_some() as usize)
+        .bytes(&encode_digest(&manifest));
+    if let Some(signature) = signature {
+        let mut sign1 = cbor::Writer::default();
+        sign1
+            .tag(COSE_SIGN1_TAG)
+            .array(4)
+            .bytes(&PROTECTED_EDDSA)
+            .map(0)
+            .null()
+            .bytes(&signature);
+        auth.bytes(&sign1.out);
+    }
+
+    let mut envelope = cbor::Writer::default();
+    envelope
+        .tag(ENVELOPE_TAG)
+        .map(3)
+        .int(ENVELOPE_AUTH)
+        .bytes(&auth.out)
+        .int(ENVELOPE_MANIFEST)
+        .bytes(&manifest)
+        .text(PAYLOAD_KEY)
+        .bytes(module);
+    Ok(envelope.out)
+}
+
+#[cfg(all(test, feature = "std"))]
+mod tests {
+    use super::*;
+    use crate::manifest::ManifestFormat;
+
+    const WASM: &[u8] = b"\0asm\x01\0\0\0";
+
+    #[test]
+    fn round_trips_unsigned_envelopes() {
+        let blob = encode(0x0102_0304, "main", WASM, 0, 9, None).unwrap();
+        assert_eq!(ManifestFormat::detect(&blob), Some(ManifestFormat::Suit));
+        assert_eq!(&blob[..2], [0xD8, 0x6B]);
+
+        let mut padded = blob.clone();
+        padded.extend_from_slice(&[0xFF; 16]);
+        let (suit, module) = SuitManifest::parse(&padded).unwrap();
+        assert_eq!(
+            (suit.module_id, suit.entry, suit.sequence, suit.flags),
+            (0x0102_0304, "main", 9, 0)
+        );
+        assert_eq!(module, WASM);
+        assert_eq!(suit.signature_count(), 0);
+        assert_eq!(suit.manifest().module_len, WASM.len() as u32);
+
+        // Flip one module byte: the image digest no longer matches.
+        let mut tampered = blob.clone();
+        let at = tampered.len() - 1;
+        tampered[at] ^= 1;
+        assert_eq!(
+            SuitManifest::parse(&tampered).err(),
+            Some(Error::Engine("suit image digest mismatch"))
+        );
+        assert!(SuitManifest::parse(&blob[..blob.len() - 3]).is_err());
+
+        let required = encode(1, "main", WASM, FLAG_REQUIRE_SIGNATURE, 0, None).unwrap();
+        assert_eq!(
+            SuitManifest::parse(&required).err(),
+            Some(Error::Engine("manifest requires signature"))
+        );
+    }
+
+    #[cfg(feature = "verify-ed25519")]
+    #[test]
+    fn cose_signatures_verify() {
+        use ed25519_dalek::{Signer, SigningKey};
+
+        let key = SigningKey::from_bytes(&[7u8; 32]);
+        let preimage = signing_preimage(3, "run", WASM, FLAG_REQUIRE_SIGNATURE, 2).unwrap();
+        let sig = key.sign(&preimage).to_bytes();
+        let blob = encode(3, "run", WASM, FLAG_REQUIRE_SIGNATURE, 2, Some(sig)).unwrap();
+
+        let (suit, _) = SuitManifest::parse(&blob).unwrap();
+        assert_eq!(suit.signature_count(), 1);
+        assert_eq!(suit.signatures().collect::<Vec<_>>(), [sig]);
+        assert_eq!(suit.verify_ed25519(&key.verifying_key().to_bytes()), Ok(()));
+        let other = SigningKey::from_bytes(&[8u8; 32])
+            .verifying_key()
+            .to_bytes();
+        assert_eq!(
+            suit.verify_ed25519(&other),
+            Err(Error::Engine("signature verify failed"))
+        );
+
+        // A different sequence number is a different manifest digest.
+        let replayed = encode(3, "run", WASM, FLAG_REQUIRE_SIGNATURE, 3, Some(sig)).unwrap();
+        let (suit, _) = SuitManifest::parse(&replayed).unwrap();
+        assert!(suit
+            .verify_ed25519(&key.verifying_key().to_bytes())
+            .is_err());
+    }
+}
@@ -0,0 +1,243 @@
+//! The CBOR subset SUIT envelopes need: definite-length items of major types
+//! 0–6 plus `null`. Floats, indefinite lengths and other simple values are
+//! rejected, which also keeps encodings canonical enough to hash.
+
+use crate::{Error, Result};
+
+const UINT: u8 = 0;
+const NINT: u8 = 1;
+const BYTES: u8 = 2;
+const TEXT: u8 = 3;
+const ARRAY: u8 = 4;
+const MAP: u8 = 5;
+const TAG: u8 = 6;
+const NULL: u8 = 0xF6;
+
+/// Nesting `skip` follows before giving up.
+const MAX_DEPTH: usize = 16;
+
+/// Map key: SUIT uses integer keys, plus text for integrated payloads.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Key<'a> {
+    Int(i64),
+    Text(&'a str),
+}
+
+/// Zero-copy cursor over encoded items.
+pub(crate) struct Reader<'a> {
+    bytes: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Reader<'a> {
+    pub(crate) fn new(bytes: &'a [u8]) -> Self {
+        Self { bytes, pos: 0 }
+    }
+
+    /// Bytes not yet consumed.
+    pub(crate) fn rest(&self) -> &'a [u8] {
+        &self.bytes[self.pos..]
+    }
+
+    fn take(&mut self, len: usize) -> Result<&'a [u8]> {
+        let end = self
+            .pos
+            .checked_add(len)
+            .filter(|end| *end <= self.bytes.len())
+            .ok_or(Error::Engine("cbor truncated"))?;
+        let out = &self.bytes[self.pos..end];
+        self.pos = end;
+        Ok(out)
+    }
+
+    fn peek(&self) -> Option<u8> {
+        self.bytes.get(self.pos).copied()
+    }
+
+    /// Major type and argument of the next item.
+    fn head(&mut self) -> Result<(u8, u64)> {
+        let initial = self.take(1)?[0];
+        let major = initial >> 5;
+        let arg = match initial & 0x1F {
+            small @ 0..=23 => small as u64,
+            24 => self.take(1)?[0] as u64,
+            25 => u16::from_be_bytes(self.take(2)?.try_into().unwrap()) as u64,
+            26 => u32::from_be_bytes(self.take(4)?.try_into().unwrap()) as u64,
+            27 => u64::from_be_bytes(self.take(8)?.try_into().unwrap()),
+            _ => return Err(Error::Engine("cbor item unsupported")),
+        };
+        if major == 7 {
+            return Err(Error::Engine("cbor item unsupported"));
+        }
+        Ok((major, arg))
+    }
+
+    fn expect(&mut self, major: u8) -> Result<u64> {
+        match self.head()? {
+            (found, arg) if found == major => Ok(arg),
+            _ => Err(Error::Engine("cbor unexpected type")),
+        }
+    }
+
+    fn len(&mut self, major: u8) -> Result<usize> {
+        usize::try_from(self.expect(major)?).map_err(|_| Error::Engine("cbor truncated"))
+    }
+
+    pub(crate) fn uint(&mut self) -> Result<u64> {
+        self.expect(UINT)
+    }
+
+    pub(crate) fn int(&mut self) -> Result<i64> {
+        match self.head()? {
+            (UINT, arg) => i64::try_from(arg).map_err(|_| Error::Engine("cbor int overflow")),
+            (NINT, arg) => i64::try_from(arg)
+                .map(|n| -1 - n)
+                .map_err(|_| Error::Engine("cbor int overflow")),
+            _ => Err(Error::Engine("cbor unexpected type")),
+        }
+    }
+
+    pub(crate) fn bytes(&mut self) -> Result<&'a [u8]> {
+        let len = self.len(BYTES)?;
+        self.take(len)
+    }
+
+    pub(crate) fn text(&mut self) -> Result<&'a str> {
+        let len = self.len(TEXT)?;
+        core::str::from_utf8(self.take(len)?).map_err(|_| Error::Engine("cbor text not utf-8"))
+    }
+
+    pub(crate) fn array(&mut self) -> Result<usize> {
+        self.len(ARRAY)
+    }
+
+    pub(crate) fn map(&mut self) -> Result<usize> {
+        self.len(MAP)
+    }
+
+    pub(crate) fn key(&mut self) -> Result<Key<'a>> {
+        match self.peek().map(|b| b >> 5) {
+            Some(TEXT) => self.text().map(Key::Text),
+            _ => self.int().map(Key::Int),
+        }
+    }
+
+    /// Consumes `tag` if it comes next; other tags are an error.
+    pub(crate) fn optional_tag(&mut self, tag: u64) -> Result<bool> {
+        if self.peek().map(|b| b >> 5) != Some(TAG) {
+            return Ok(false);
+        }
+        match self.expect(TAG)? {
+            found if found == tag => Ok(true),
+            _ => Err(Error::Engine("cbor unexpected tag")),
+        }
+    }
+
+    /// Consumes `null` if it comes next.
+    pub(crate) fn optional_null(&mut self) -> bool {
+        if self.peek() == Some(NULL) {
+            self.pos += 1;
+            true
+        } else {
+            false
+        }
+    }
+
+    /// Skips one item, including everything nested in it.
+    pub(crate) fn skip(&mut self) -> Result<()> {
+        self.skip_nested(0)
+    }
+
+    fn skip_nested(&mut self, depth: usize) -> Result<()> {
+        if depth > MAX_DEPTH {
+            return Err(Error::Engine("cbor nested too deep"));
+        }
+        if self.optional_null() {
+            return Ok(());
+        }
+        let (major, arg) = self.head()?;
+        let len = usize::try_from(arg).map_err(|_| Error::Engine("cbor truncated"))?;
+        match major {
+            BYTES | TEXT => self.take(len).map(|_| ()),
+            ARRAY => (0..len).try_for_each(|_| self.skip_nested(depth + 1)),
+            MAP => (0..len).try_for_each(|_| {
+                self.skip_nested(depth + 1)?;
+                self.skip_nested(depth + 1)
+            }),
+            TAG => self.skip_nested(depth + 1),
+            _ => Ok(()),
+        }
+    }
+}
+
+/// Canonical (shortest-form) encoder.
+#[cfg(feature = "alloc")]
+#[derive(Default)]
+pub(crate) struct Writer {
+    pub(crate) out: alloc::vec::Vec<u8>,
+}
+
+#[cfg(feature = "alloc")]
+impl Writer {
+    fn head(&mut self, major: u8, arg: u64) -> &mut Self {
+        let major = major << 5;
+        match arg {
+            0..=23 => self.out.push(major | arg as u8),
+            24..=0xFF => self.out.extend_from_slice(&[major | 24, arg as u8]),
+            0x100..=0xFFFF => {
+                self.out.push(major | 25);
+                self.out.extend_from_slice(&(arg as u16).to_be_bytes());
+            }
+            0x1_0000..=0xFFFF_FFFF => {
+                self.out.push(major | 26);
+                self.out.extend_from_slice(&(arg as u32).to_be_bytes());
+            }
+            _ => {
+                self.out.push(major | 27);
+                self.out.extend_from_slice(&arg.to_be_bytes());
+            }
+        }
+        self
+    }
+
+    pub(crate) fn uint(&mut self, value: u64) -> &mut Self {
+        self.head(UINT, value)
+    }
+
+    pub(crate) fn int(&mut self, value: i64) -> &mut Self {
+        if value < 0 {
+            self.head(NINT, (-1 - value) as u64)
+        } else {
+            self.head(UINT, value as u64)
+        }
+    }
+
+    pub(crate) fn bytes(&mut self, value: &[u8]) -> &mut Self {
+        self.head(BYTES, value.len() as u64);
+        self.out.extend_from_slice(value);
+        self
+    }
+
+    pub(crate) fn text(&mut self, value: &str) -> &mut Self {
+        self.head(TEXT, value.len() as u64);
+        self.out.extend_from_slice(value.as_bytes());
+        self
+    }
+
+    pub(crate) fn array(&mut self, len: usize) -> &mut Self {
+        self.head(ARRAY, len as u64)
+    }
+
+    pub(crate) fn map(&mut self, len: usize) -> &mut Self {
+        self.head(MAP, len as u64)
+    }
+
+    pub(crate) fn tag(&mut self, tag: u64) -> &mut Self {
+        self.head(TAG, tag)
+    }
+
+    pub(crate) fn null(&mut self) -> &mut Self {
+        self.out.push(NULL);
+        self
+    }
+}
@@ -0,0 +1,241 @@
+//! Suspend and resume around low-power sleep.
+//!
+//! Battery devices spend most of their life asleep, and RAM that stays
+//! powered through deep sleep is scarce. `Runtime::suspend` has the engine
+//! free everything it can rebuild from module bytes (`Engine::suspend`:
+//! compiled modules, instances, `CachedEngine` handles); the next invocation
+//! after `Runtime::resume` loads its module again. Module state kept with
+//! `EngineConfig::keep_state`, stats, quotas and the rest of the runtime stay.
+//!
+//! `SuspendHook`s registered with `Runtime::add_suspend_hook` run before the
+//! engine lets go (in order) and after resume (in reverse), e.g. to persist
+//! what a sleep that loses RAM would drop or to power peripherals down.
+//! While the runtime is suspended `schedule::Scheduler::tick` runs nothing and
+//! leaves due jobs due, so pending work runs on the first tick after
+//! `resume` instead of rebuilding engine state mid-sleep. Anything else that
+//! would load a module (`execute` and the other invocations, `preload`,
+//! `describe`, `restore`) fails with `Error::Suspended` rather than resuming
+//! implicitly: the resume hooks may power up what the module needs, and the
+//! firmware decides when that happens.
+//!
+//! ```ignore
+//! runtime.suspend()?;
+//! deep_sleep(scheduler.sleep_ms().unwrap_or(MAX_SLEEP_MS));
+//! runtime.resume()?;
+//! scheduler.tick(&mut runtime, &mut ctx);
+//! ```
+
+use crate::{Engine, ModuleSource, Result, Runtime};
+use alloc::boxed::Box;
+use alloc::vec::Vec;
+
+/// Called around a suspend; both default to doing nothing.
+pub trait SuspendHook {
+    /// Before the engine frees its state; an error cancels the suspend.
+    fn suspend(&mut self) -> Result<()> {
+        Ok(())
+    }
+
+    /// After the runtime is back.
+    fn resume(&mut self) -> Result<()> {
+        Ok(())
+    }
+}
+
+/// Hooks plus whether the runtime is suspended.
+#[derive(Default)]
+pub(crate) struct Suspension {
+    hooks: Vec<Box<dyn SuspendHook>>,
+    suspended: bool,
+}
+
+impl Suspension {
+    pub(crate) const fn new() -> Self {
+        Self {
+            hooks: Vec::new(),
+            suspended: false,
+        }
+    }
+}
+
+impl<E: Engine, S: ModuleSource> Runtime<E, S> {
+    /// Runs `hook` around every suspend and resume.
+    pub fn add_suspend_hook(&mut self, hook: impl SuspendHook + 'static) {
+        self.suspension.hooks.push(Box::new(hook));
+    }
+
+    /// Runs the suspend hooks, then frees the engine's transient state.
+    ///
+    /// A failing hook cancels the suspend: the hooks that already ran are
+    /// resumed and its error is returned. Suspending twice does nothing.
+    pub fn suspend(&mut self) -> Result<()> {
+        if self.suspension.suspended {
+            return Ok(());
+        }
+        let hooks = &mut self.suspension.hooks;
+        for i in 0..hooks.len() {
+            if let Err(error) = hooks[i].suspend() {
+                for hook in hooks[..i].iter_mut().rev() {
+                    let _ = hook.resume();
+                }
+                return Err(error);
+            }
+        }
+        self.engine.suspend();
+        // What the engine freed is charged again as it is rebuilt.
+        self.memory.release_heap();
+        self.suspension.suspended = true;
+        Ok(())
+    }
+
+    /// Runs the resume hooks, last registered first; engine state is
+    /// rebuilt by the next invocation of each module. Every hook runs; the
+    /// first error is returned. Resuming a running runtime does nothing.
+    pub fn resume(&mut self) -> Result<()> {
+        if !self.suspension.suspended {
+            return Ok(());
+        }
+        self.suspension.suspended = false;
+        let mut first = Ok(());
+        for hook in self.suspension.hooks.iter_mut().rev() {
+            if let (Err(error), Ok(())) = (hook.resume(), &first) {
+                first = Err(error);
+            }
+        }
+        first
+    }
+
+    /// True between `suspend` and `resume`.
+    pub fn is_suspended(&self) -> bool {
+        self.suspension.suspended
+    }
+
+    /// `Error::Suspended` between `suspend` and `resume`.
+    pub(crate) fn ensure_awake(&self) -> Result<()> {
+        match self.suspension.suspended {
+            true => Err(crate::Error::Suspended),
+            false => Ok(()),
+        }
+    }
+}
+
+#[cfg(all(test, feature = "std"))]
+mod tests {
+    use super::*;
+    use crate::schedule::{Clock, Scheduler, Trigger};
+    use crate::{CachedEngine, Error, MemoryStore, ModuleId};
+    use alloc::rc::Rc;
+    use alloc::string::String;
+    use core::cell::RefCell;
+
+    type Calls = Rc<RefCell<Vec<String>>>;
+
+    /// Records loads, calls and suspends.
+    struct Recorder(Calls);
+
+    impl Engine for Recorder {
+        type ModuleHandle = ModuleId;
+        type Context = ();
+
+        fn load(&mut self, id: ModuleId, _module: &[u8]) -> Result<ModuleId> {
+            self.0.borrow_mut().push(alloc::format!("load {id}"));
+            Ok(id)
+        }
+
+        fn invoke(&mut self, id: ModuleId, entry: &str, _ctx: &mut ()) -> Result<()> {
+            self.0.borrow_mut().push(alloc::format!("{entry} {id}"));
+            Ok(())
+        }
+
+        fn suspend(&mut self) {
+            self.0.borrow_mut().push("engine suspend".into());
+        }
+    }
+
+    struct Hook(&'static str, Calls, bool);
+
+    impl SuspendHook for Hook {
+        fn suspend(&mut self) -> Result<()> {
+            self.1
+                .borrow_mut()
+                .push(alloc::format!("{} suspend", self.0));
+            match self.2 {
+                true => Err(Error::Engine("hook failed")),
+                false => Ok(()),
+            }
+        }
+
+        fn resume(&mut self) -> Result<()> {
+            self.1
+                .borrow_mut()
+                .push(alloc::format!("{} resume", self.0));
+            Ok(())
+        }
+    }
+
+    struct Fixed(u64);
+
+    impl Clock for Fixed {
+        fn now_ms(&self) -> u64 {
+            self.0
+        }
+    }
+
+    fn drain(calls: &Calls) -> Vec<String> {
+        calls.borrow_mut().drain(..).collect()
+    }
+
+    #[test]
+    fn suspend_frees_engine_state_and_resume_rebuilds_it_lazily() {
+        let calls = Calls::default();
+        let mut store = MemoryStore::new();
+        store.upsert(1, b"\0asm".to_vec());
+        let mut runtime = Runtime::new(CachedEngine::new(Recorder(calls.clone())), store);
+        runtime.add_suspend_hook(Hook("a", calls.clone(), false));
+        runtime.add_suspend_hook(Hook("b", calls.clone(), false));
+
+        runtime.execute(1, "main", &mut ()).unwrap();
+        runtime.execute(1, "main", &mut ()).unwrap();
+        assert_eq!(drain(&calls), ["load 1", "main 1", "main 1"]);
+
+        runtime.suspend().unwrap();
+        runtime.suspend().unwrap();
+        assert!(runtime.is_suspended());
+        assert_eq!(runtime.engine().cached_len(), 0);
+        assert_eq!(drain(&calls), ["a suspend", "b suspend", "engine suspend"]);
+
+        // Due jobs wait for the resume.
+        let mut scheduler = Scheduler::new(Fixed(0));
+        scheduler.add(1, "tick", Trigger::parse("@every 1s").unwrap().0);
+        scheduler.make_due(1);
+        assert_eq!(scheduler.tick(&mut runtime, &mut ()), 0);
+        assert!(drain(&calls).is_empty());
+
+        // Direct calls are refused rather than rebuilding engine state.
+        assert_eq!(runtime.execute(1, "main", &mut ()), Err(Error::Suspended));
+        assert_eq!(runtime.preload(&[1]), Err(Error::Suspended));
+        assert!(runtime.is_suspended());
+        assert_eq!(runtime.engine().cached_len(), 0);
+        assert!(drain(&calls).is_empty());
+
+        runtime.resume().unwrap();
+        assert!(!runtime.is_suspended());
+        assert_eq!(scheduler.tick(&mut runtime, &mut ()), 1);
+        assert_eq!(drain(&calls), ["b resume", "a resume", "load 1", "tick 1"]);
+
+        // A failing hook cancels the suspend.
+        runtime.add_suspend_hook(Hook("c", calls.clone(), true));
+        assert!(runtime.suspend().is_err());
+        assert!(!runtime.is_suspended());
+        assert_eq!(
+            drain(&calls),
+            [
+                "a suspend",
+                "b suspend",
+                "c suspend",
+                "b resume",
+                "a resume"
+            ]
+        );
+    }
+}
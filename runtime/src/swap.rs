@@ -0,0 +1,468 @@
+//! Replacing a module while the runtime is live.
+//!
+//! `Runtime::swap` takes a manifest blob and does, in order, what an OTA
+//! installer otherwise coordinates by hand: checks it against the runtime's
+//! `Keyring` (see `audit::check`) and the installed version, stages the
+//! module in the source, commits it, drops whatever the engine cached for the
+//! id, marks it verified for `trust::VerificationPolicy::OnInstall` and
+//! applies the manifest. Invocations before the commit run the old
+//! image, invocations after it the new one; a failure before the commit
+//! leaves the old image in place.
+//!
+//! KV state is namespaced by module id (see `kv`), so it carries over as is.
+//! `swap_migrating` runs a migration between staging and commit for images
+//! whose state layout changed.
+//!
+//! State a module keeps elsewhere is handed over by the module itself with
+//! `swap_carrying_state`: before the commit the old image's `export_state`
+//! entry saves a blob, and after it the new image's `import_state` entry
+//! reads it back. Both talk to a `StateHandoff` registered with the engine
+//! (module `env`, all `i32`):
+//! - `state_put(ptr, len) -> status` – in `export_state`: saves `len` bytes,
+//!   replacing any saved before; `E_INVALID` elsewhere or past `MAX_STATE_LEN`.
+//! - `state_get(ptr, cap) -> len | status` – in `import_state`: the blob's full
+//!   length, copied when it fits in `cap`; `E_EMPTY` elsewhere.
+//!
+//! Either entry is optional: without `export_state` nothing is handed over,
+//! and without `import_state` the blob is dropped.
+
+use crate::abi::{GuestMemory, HostFn, HostImports, E_EMPTY, E_INVALID, OK};
+use crate::audit::{self, Issue};
+use crate::manifest::{Manifest, FLAG_ROLLBACK_PROTECTED, MANIFEST_MAGIC};
+use crate::telemetry::Event;
+use crate::{Engine, Error, ModuleId, ModuleSink, ModuleSource, Result, Runtime};
+use alloc::rc::Rc;
+use alloc::vec::Vec;
+use core::cell::RefCell;
+
+/// Entry the old image saves its state from.
+pub const EXPORT_ENTRY: &str = "export_state";
+/// Entry the new image restores its state in.
+pub const IMPORT_ENTRY: &str = "import_state";
+/// Largest state blob in bytes.
+pub const MAX_STATE_LEN: usize = 4096;
+
+/// Host functions provided by `StateHandoff`.
+pub const FUNCTIONS: &[HostFn] = &[HostFn::new("state_put", 2), HostFn::new("state_get", 2)];
+
+/// A completed swap.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Swapped {
+    pub module_id: ModuleId,
+    /// Version before the swap (0 for a module the runtime did not know).
+    pub from: u32,
+    /// Manifest sequence now active.
+    pub to: u32,
+    /// Whether the new image imported the old one's state
+    /// (`swap_carrying_state`).
+    pub carried: bool,
+}
+
+impl<E: Engine, S: ModuleSource + ModuleSink> Runtime<E, S> {
+    /// Verifies the manifest blob `blob` for module `id` and makes it the
+    /// module's active image.
+    pub fn swap(&mut self, id: ModuleId, blob: &[u8]) -> Result<Swapped> {
+        self.swap_migrating(id, blob, |_, _| Ok(()))
+    }
+
+    /// Like `swap`, but calls `migrate(from, to)` once the image is verified
+    /// and staged, before it is committed, e.g. to rewrite the module's
+    /// `KvStore` namespace. An error from `migrate` aborts the swap.
+    pub fn swap_migrating(
+        &mut self,
+        id: ModuleId,
+        blob: &[u8],
+        migrate: impl FnOnce(u32, u32) -> Result<()>,
+    ) -> Result<Swapped> {
+        self.install(id, blob, |_, from, to| migrate(from, to))
+    }
+
+    /// Like `swap`, but hands the module's state from the old image to the
+    /// new one through `handoff` (see the module docs).
+    ///
+    /// An error from `export_state` aborts the swap. An error from
+    /// `import_state` is returned with the new image already active; the
+    /// state is then lost.
+    pub fn swap_carrying_state(
+        &mut self,
+        id: ModuleId,
+        blob: &[u8],
+        handoff: &StateHandoff,
+        ctx: &mut E::Context,
+    ) -> Result<Swapped> {
+        handoff.reset();
+        let mut swapped = self.install(id, blob, |runtime, _, _| {
+            handoff
+                .run(runtime, id, EXPORT_ENTRY, Phase::Export, ctx)
+                .map(drop)
+        });
+        if swapped.is_ok() && handoff.state.borrow().blob.is_some() {
+            let imported = handoff.run(self, id, IMPORT_ENTRY, Phase::Import, ctx);
+            if let Ok(swapped) = swapped.as_mut() {
+                swapped.carried = imported?;
+            }
+        }
+        handoff.reset();
+        swapped
+    }
+
+    fn install(
+        &mut self,
+        id: ModuleId,
+        blob: &[u8],
+        migrate: impl FnOnce(&mut Self, u32, u32) -> Result<()>,
+    ) -> Result<Swapped> {
+        let version = Manifest::parse(blob).map_or(0, |(manifest, _)| manifest.sequence);
+        self.emit(Event::UpdateReceived {
+            module_id: id,
+            version,
+            len: blob.len(),
+        });
+        let from = self.registry.get(id).map_or(0, |info| info.version);
+        let (manifest, module) = match self.verify(id, blob, from) {
+            Ok(verified) => verified,
+            Err(error) => {
+                self.emit(Event::VerifyFailed {
+                    module_id: id,
+                    error,
+                });
+                self.record_failure(id, error);
+                return Err(error);
+            }
+        };
+
+        let source = self.source_mut();
+        source.begin(id, module.len())?;
+        let staged = source
+            .write(module)
+            .and_then(|()| migrate(self, from, manifest.sequence))
+            .and_then(|()| self.source_mut().commit());
+        if let Err(err) = staged {
+            self.source_mut().abort();
+            return Err(err);
+        }
+
+        self.engine.unload(id);
+        // A mark that could not be persisted only costs a check.
+        self.refresh_trust_revision();
+        let _ = self.trust_marks.mark(id, module);
+        self.apply_manifest(&manifest)?;
+        self.emit(Event::ModuleActivated {
+            module_id: id,
+            from,
+            to: manifest.sequence,
+        });
+        Ok(Swapped {
+            module_id: id,
+            from,
+            to: manifest.sequence,
+            carried: false,
+        })
+    }
+
+    /// Checks `blob` against the keyring and the installed version `from`.
+    fn verify<'a>(
+        &self,
+        id: ModuleId,
+        blob: &'a [u8],
+        from: u32,
+    ) -> Result<(Manifest<'a>, &'a [u8])> {
+        if !blob.starts_with(MANIFEST_MAGIC) {
+            return Err(Error::Engine("swap: blob is not a manifest"));
+        }
+        audit::check(id, blob, &self.keyring).map_err(rejected)?;
+        let (manifest, module) = Manifest::parse(blob)?;
+        if manifest.flags & FLAG_ROLLBACK_PROTECTED != 0 && manifest.sequence <= from {
+            return Err(Error::Engine("swap: sequence not newer than installed"));
+        }
+        Ok((manifest, module))
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Phase {
+    Idle,
+    Export,
+    Import,
+}
+
+struct HandoffState {
+    phase: Phase,
+    blob: Option<Vec<u8>>,
+}
+
+/// Carries a module's state blob across `Runtime::swap_carrying_state`;
+/// register a clone as a `HostImports` set and pass another to the swap.
+#[derive(Clone)]
+pub struct StateHandoff {
+    state: Rc<RefCell<HandoffState>>,
+}
+
+impl Default for StateHandoff {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl StateHandoff {
+    pub fn new() -> Self {
+        Self {
+            state: Rc::new(RefCell::new(HandoffState {
+                phase: Phase::Idle,
+                blob: None,
+            })),
+        }
+    }
+
+    fn reset(&self) {
+        let mut state = self.state.borrow_mut();
+        state.phase = Phase::Idle;
+        state.blob = None;
+    }
+
+    /// Runs `entry` of module `id` in `phase`; `Ok(false)` when the module
+    /// has no such entry (or, when exporting, no installed image).
+    fn run<E: Engine, S: ModuleSource>(
+        &self,
+        runtime: &mut Runtime<E, S>,
+        id: ModuleId,
+        entry: &str,
+        phase: Phase,
+        ctx: &mut E::Context,
+    ) -> Result<bool> {
+        self.state.borrow_mut().phase = phase;
+        let result = runtime.execute(id, entry, ctx);
+        self.state.borrow_mut().phase = Phase::Idle;
+        match result {
+            Ok(()) => Ok(true),
+            Err(Error::EntryNotFound) => Ok(false),
+            Err(Error::ModuleNotFound) if phase == Phase::Export => Ok(false),
+            Err(err) => Err(err),
+        }
+    }
+
+    fn state_put(&self, ptr: i32, len: i32, memory: &dyn GuestMemory) -> i32 {
+        let mut state = self.state.borrow_mut();
+        let Some(len) = usize::try_from(len)
+            .ok()
+            .filter(|len| *len <= MAX_STATE_LEN && state.phase == Phase::Export)
+        else {
+            return E_INVALID;
+        };
+        let mut blob = alloc::vec![0u8; len];
+        if memory.read(ptr as u32, &mut blob).is_err() {
+            return E_INVALID;
+        }
+        state.blob = Some(blob);
+        OK
+    }
+
+    fn state_get(&self, ptr: i32, cap: i32, memory: &mut dyn GuestMemory) -> i32 {
+        let state = self.state.borrow();
+        let Some(blob) = state.blob.as_ref().filter(|_| state.phase == Phase::Import) else {
+            return E_EMPTY;
+        };
+        let Ok(cap) = usize::try_from(cap) else {
+            return E_INVALID;
+        };
+        if blob.len() <= cap && memory.write(ptr as u32, blob).is_err() {
+            return E_INVALID;
+        }
+        blob.len() as i32
+    }
+}
+
+impl HostImports for StateHandoff {
+    fn functions(&self) -> &[HostFn] {
+        FUNCTIONS
+    }
+
+    fn call(&mut self, name: &str, args: &[i32], memory: &mut dyn GuestMemory) -> Result<i32> {
+        match (name, args) {
+            ("state_put", &[ptr, len]) => Ok(self.state_put(ptr, len, memory)),
+            ("state_get", &[ptr, cap]) => Ok(self.state_get(ptr, cap, memory)),
+            _ => Err(Error::Engine("swap: bad host call")),
+        }
+    }
+}
+
+fn rejected(issue: Issue) -> Error {
+    let reason = match issue {
+        Issue::Missing => "swap: module missing",
+        Issue::Corrupted(reason) => reason,
+        Issue::Unsigned => "swap: signature required but absent",
+        Issue::Untrusted => "swap: signature not from a trusted key",
+        Issue::DigestMismatch => "swap: digest does not match pin",
+        Issue::Revoked => return Error::Revoked,
+    };
+    Error::Engine(reason)
+}
+
+#[cfg(all(test, feature = "std"))]
+mod tests {
+    use super::*;
+    use crate::audit::Keyring;
+    use crate::kv::{KvStore, MemoryKv};
+    use crate::manifest;
+    use crate::{CachedEngine, MemoryStore};
+    use alloc::vec::Vec;
+    use core::cell::RefCell;
+
+    /// Counts loads, so tests can tell a cached handle from a fresh load.
+    #[derive(Default)]
+    struct Loads(Vec<Vec<u8>>);
+
+    impl Engine for Loads {
+        type ModuleHandle = usize;
+        type Context = ();
+
+        fn load(&mut self, _id: ModuleId, module: &[u8]) -> Result<usize> {
+            self.0.push(module.to_vec());
+            Ok(self.0.len() - 1)
+        }
+
+        fn invoke(&mut self, _handle: usize, _entry: &str, _ctx: &mut ()) -> Result<()> {
+            Ok(())
+        }
+    }
+
+    const V1: &[u8] = b"\0asm\x01\0\0\0";
+    const V2: &[u8] = b"\0asm\x01\0\0\0\0";
+
+    fn blob(module: &[u8], sequence: u32) -> Vec<u8> {
+        manifest::encode(1, "main", module, FLAG_ROLLBACK_PROTECTED, sequence, None).unwrap()
+    }
+
+    #[test]
+    fn swaps_verified_newer_images_and_drops_cached_handles() {
+        let mut runtime = Runtime::new(CachedEngine::new(Loads::default()), MemoryStore::new());
+        assert_eq!(
+            runtime.swap(1, &blob(V1, 1)).unwrap(),
+            Swapped {
+                module_id: 1,
+                from: 0,
+                to: 1,
+                carried: false
+            }
+        );
+        runtime.execute(1, "main", &mut ()).unwrap();
+        runtime.execute(1, "main", &mut ()).unwrap();
+        assert_eq!(runtime.engine().cached_len(), 1);
+
+        assert_eq!(runtime.swap(1, &blob(V2, 2)).unwrap().from, 1);
+        assert_eq!(runtime.engine().cached_len(), 0);
+        runtime.execute(1, "main", &mut ()).unwrap();
+        assert_eq!(runtime.module_version(1), 2);
+
+        // Rejected swaps leave version 2 running.
+        let rollback = runtime.swap(1, &blob(V1, 2)).unwrap_err();
+        assert_eq!(
+            rollback,
+            Error::Engine("swap: sequence not newer than installed")
+        );
+        assert!(runtime.swap(2, &blob(V1, 3)).is_err());
+        assert!(runtime.swap(1, V1).is_err());
+        runtime.set_keyring(Keyring::new().require_signatures());
+        assert_eq!(
+            runtime.swap(1, &blob(V1, 3)).unwrap_err(),
+            Error::Engine("swap: signature required but absent")
+        );
+        assert_eq!(runtime.source().fetch(1), Some(V2));
+        assert_eq!(runtime.module_version(1), 2);
+        assert_eq!(runtime.into_parts().0.into_inner().0, [V1, V2]);
+    }
+
+    #[test]
+    fn migration_runs_before_commit_and_can_abort() {
+        let kv = RefCell::new(MemoryKv::new());
+        kv.borrow_mut().set(1, b"cal", &[7]).unwrap();
+        let mut runtime = Runtime::new(Loads::default(), MemoryStore::new());
+        runtime.swap(1, &blob(V1, 1)).unwrap();
+
+        let failed = runtime.swap_migrating(1, &blob(V2, 2), |_, _| {
+            Err(Error::Engine("migration failed"))
+        });
+        assert_eq!(failed, Err(Error::Engine("migration failed")));
+        assert_eq!(runtime.source().fetch(1), Some(V1));
+
+        let swapped = runtime.swap_migrating(1, &blob(V2, 2), |from, to| {
+            let mut kv = kv.borrow_mut();
+            let mut buf = [0u8; 1];
+            kv.get(1, b"cal", &mut buf)?;
+            kv.set(1, b"cal", &[buf[0] * 2, (from * 10 + to) as u8])
+        });
+        assert_eq!(swapped.unwrap().to, 2);
+        let mut buf = [0u8; 2];
+        assert_eq!(kv.borrow().get(1, b"cal", &mut buf).unwrap(), Some(2));
+        assert_eq!(buf, [14, 12]);
+        assert_eq!(runtime.source().fetch(1), Some(V2));
+    }
+
+    /// V1 exports `[1, 2, 3]`; V2 imports into its memory. Other entries are
+    /// missing.
+    struct Carries {
+        imports: crate::abi::Imports,
+        modules: Vec<Vec<u8>>,
+        memory: [u8; 8],
+    }
+
+    impl Engine for Carries {
+        type ModuleHandle = usize;
+        type Context = ();
+
+        fn load(&mut self, _id: ModuleId, module: &[u8]) -> Result<usize> {
+            self.modules.push(module.to_vec());
+            Ok(self.modules.len() - 1)
+        }
+
+        fn invoke(&mut self, handle: usize, entry: &str, _ctx: &mut ()) -> Result<()> {
+            let memory = &mut self.memory;
+            match (self.modules[handle].as_slice(), entry) {
+                (V1, EXPORT_ENTRY) => {
+                    memory[..3].copy_from_slice(&[1, 2, 3]);
+                    self.imports.call("state_put", &[0, 3], memory)?;
+                }
+                (V2, IMPORT_ENTRY) => {
+                    memory.fill(0);
+                    let len = self.imports.call("state_get", &[4, 4], memory)?;
+                    memory[0] = len as u8;
+                }
+                _ => return Err(Error::EntryNotFound),
+            }
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn carries_exported_state_into_the_new_image() {
+        let handoff = StateHandoff::new();
+        let engine = Carries {
+            imports: crate::abi::Imports::new().with(handoff.clone()),
+            modules: Vec::new(),
+            memory: [0; 8],
+        };
+        let mut runtime = Runtime::new(engine, MemoryStore::new());
+        runtime.swap(1, &blob(V1, 1)).unwrap();
+
+        let swapped = runtime
+            .swap_carrying_state(1, &blob(V2, 2), &handoff, &mut ())
+            .unwrap();
+        assert!(swapped.carried);
+        assert_eq!(runtime.engine().memory, [3, 0, 0, 0, 1, 2, 3, 0]);
+
+        // V2 exports nothing, so there is nothing to carry back.
+        let swapped = runtime
+            .swap_carrying_state(1, &blob(V1, 3), &handoff, &mut ())
+            .unwrap();
+        assert!(!swapped.carried);
+
+        // Outside a swap both calls are refused.
+        let mut imports = crate::abi::Imports::new().with(handoff);
+        let mut memory = [0u8; 8];
+        assert_eq!(
+            imports.call("state_put", &[0, 3], &mut memory),
+            Ok(E_INVALID)
+        );
+        assert_eq!(imports.call("state_get", &[0, 8], &mut memory), Ok(E_EMPTY));
+    }
+}
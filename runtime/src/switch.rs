@@ -0,0 +1,182 @@
+//! Per-module enable switches.
+//!
+//! Disabling a module keeps its bytes installed but makes every invocation
+//! fail with `Error::ModuleDisabled` before the module is fetched: direct
+//! calls, `execute_by_name`, remote `Execute` commands and scheduler jobs
+//! (which skip it rather than record a failure). Operators can turn a
+//! misbehaving module off remotely and back on without reinstalling it.
+//!
+//! With a `KvStore` attached (`Switches::persist_to`), the disabled set is
+//! written on every change under `SWITCH_NAMESPACE` and read back at startup,
+//! so a module stays off across reboots.
+
+use crate::kv::KvStore;
+use crate::{Error, ModuleId, Result};
+use alloc::boxed::Box;
+use alloc::vec::Vec;
+
+/// Namespace the disabled set is stored under; reserved
+/// (`kv::RESERVED_NAMESPACES`), so guests cannot reach it.
+pub const SWITCH_NAMESPACE: ModuleId = ModuleId::MAX - 2;
+const _: () = assert!(crate::kv::is_reserved(SWITCH_NAMESPACE));
+/// Key of the disabled set: module ids as little-endian `u32`s.
+pub const DISABLED_KEY: &[u8] = b"disabled";
+
+/// Which modules are disabled.
+#[derive(Default)]
+pub struct Switches {
+    /// Ascending.
+    disabled: Vec<ModuleId>,
+    store: Option<Box<dyn KvStore>>,
+}
+
+impl Switches {
+    /// Every module enabled, nothing persisted.
+    pub const fn new() -> Self {
+        Self {
+            disabled: Vec::new(),
+            store: None,
+        }
+    }
+
+    /// Loads the disabled set from `store` and persists every later change
+    /// there (share it via `Rc<RefCell<_>>` to also serve guests).
+    pub fn persist_to(&mut self, store: impl KvStore + 'static) -> Result<()> {
+        let mut buf = Vec::new();
+        let len = loop {
+            match store.get(SWITCH_NAMESPACE, DISABLED_KEY, &mut buf)? {
+                Some(len) if len > buf.len() => buf.resize(len, 0),
+                Some(len) => break len,
+                None => break 0,
+            }
+        };
+        if len % 4 != 0 {
+            return Err(Error::Engine("switches: bad record length"));
+        }
+        self.disabled = buf[..len]
+            .chunks_exact(4)
+            .map(|id| u32::from_le_bytes(id.try_into().unwrap()))
+            .collect();
+        self.disabled.sort_unstable();
+        self.disabled.dedup();
+        self.store = Some(Box::new(store));
+        Ok(())
+    }
+
+    pub fn is_enabled(&self, module_id: ModuleId) -> bool {
+        self.disabled.binary_search(&module_id).is_err()
+    }
+
+    /// Turns a module on or off; returns whether that changed anything. The
+    /// switch only flips once it is persisted.
+    pub fn set_enabled(&mut self, module_id: ModuleId, enabled: bool) -> Result<bool> {
+        let mut disabled = self.disabled.clone();
+        match (disabled.binary_search(&module_id), enabled) {
+            (Ok(at), true) => {
+                disabled.remove(at);
+            }
+            (Err(at), false) => disabled.insert(at, module_id),
+            _ => return Ok(false),
+        }
+        if let Some(store) = &mut self.store {
+            let bytes: Vec<u8> = disabled.iter().flat_map(|id| id.to_le_bytes()).collect();
+            store.set(SWITCH_NAMESPACE, DISABLED_KEY, &bytes)?;
+        }
+        self.disabled = disabled;
+        Ok(true)
+    }
+
+    /// Disabled modules, ascending.
+    pub fn disabled(&self) -> &[ModuleId] {
+        &self.disabled
+    }
+
+    /// `Error::ModuleDisabled` for a disabled module.
+    pub fn check(&self, module_id: ModuleId) -> Result<()> {
+        if self.is_enabled(module_id) {
+            Ok(())
+        } else {
+            Err(Error::ModuleDisabled)
+        }
+    }
+}
+
+#[cfg(all(test, feature = "std"))]
+mod tests {
+    use super::*;
+    use crate::deps::ModuleInfo;
+    use crate::kv::MemoryKv;
+    use crate::schedule::{Clock, Scheduler, Trigger};
+    use crate::{Engine, MemoryStore, Runtime};
+    use alloc::rc::Rc;
+    use core::cell::RefCell;
+
+    struct Calls;
+
+    impl Engine for Calls {
+        type ModuleHandle = ModuleId;
+        type Context = Vec<ModuleId>;
+
+        fn load(&mut self, id: ModuleId, _module: &[u8]) -> Result<ModuleId> {
+            Ok(id)
+        }
+
+        fn invoke(
+            &mut self,
+            handle: ModuleId,
+            _entry: &str,
+            ctx: &mut Self::Context,
+        ) -> Result<()> {
+            ctx.push(handle);
+            Ok(())
+        }
+    }
+
+    struct Epoch;
+
+    impl Clock for Epoch {
+        fn now_ms(&self) -> u64 {
+            0
+        }
+    }
+
+    #[test]
+    fn disabled_modules_stay_installed_but_do_not_run() {
+        let kv = Rc::new(RefCell::new(MemoryKv::default()));
+        let mut store = MemoryStore::new();
+        store.upsert(1, vec![0]);
+        store.upsert(2, vec![0]);
+        let mut runtime = Runtime::new(Calls, store);
+        runtime.switches().persist_to(kv.clone()).unwrap();
+        assert_eq!(runtime.set_enabled(2, false), Ok(true));
+        assert_eq!(runtime.set_enabled(2, false), Ok(false));
+
+        runtime.registry().register(ModuleInfo {
+            module_id: 2,
+            name: Some("net".into()),
+            ..ModuleInfo::default()
+        });
+        let mut calls = Vec::new();
+        assert_eq!(
+            runtime.execute_by_name("net", "main", &mut calls),
+            Err(Error::ModuleDisabled)
+        );
+        let mut scheduler = Scheduler::new(Epoch);
+        scheduler.add(1, "main", Trigger::Every(1));
+        scheduler.add(2, "main", Trigger::Every(1));
+        scheduler.make_due(1);
+        scheduler.make_due(2);
+        assert_eq!(scheduler.tick(&mut runtime, &mut calls), 1);
+        assert!(scheduler.jobs().iter().all(|job| job.last_error.is_none()));
+        assert_eq!(calls, [1]);
+
+        // The switch survives a restart through the shared store.
+        let mut switches = Switches::new();
+        switches.persist_to(kv.clone()).unwrap();
+        assert_eq!(switches.disabled(), [2]);
+        switches.set_enabled(2, true).unwrap();
+        runtime.switches().persist_to(kv).unwrap();
+        runtime.execute_by_name("net", "main", &mut calls).unwrap();
+        assert_eq!(calls, [1, 2]);
+    }
+}
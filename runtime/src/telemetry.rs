@@ -0,0 +1,239 @@
+//! Device-side OTA and execution telemetry.
+//!
+//! A runtime with an `EventSink` (`Runtime::set_event_sink`) reports what
+//! happens to its modules as structured `Event`s, each stamped with the time
+//! from the sink's `Clock` and the correlation id of the invocation or command
+//! in progress. The sink decides the transport (MQTT, CoAP, a ring buffer
+//! flushed on the next connection), so fleets get the same events from every
+//! device.
+//!
+//! Reported by the runtime:
+//! - `UpdateReceived`, `VerifyFailed`, `ModuleActivated` – `Runtime::swap`
+//!   (`VerifyFailed` also for invocations refused by the verification policy,
+//!   see `trust`);
+//!   the A/B flows in `update` report `UpdateReceived` and `ModuleActivated`.
+//! - `RolledBack` – failed health checks and unconfirmed trials (`update`).
+//! - `InvokeTrapped` – any invocation the engine failed, except for missing
+//!   entries; calls refused by the state gate or quotas never reach it.
+//! - `Quarantined` – a module reached the failure threshold (`quarantine`).
+//!
+//! Transports that verify images themselves (e.g. `dfu`) report through
+//! `Runtime::emit`.
+
+use crate::schedule::Clock;
+use crate::trace::CorrelationId;
+use crate::{Error, ModuleId};
+use alloc::boxed::Box;
+
+/// Something that happened to a module.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Event<'a> {
+    /// An image for `module_id` arrived and is about to be verified.
+    UpdateReceived {
+        module_id: ModuleId,
+        /// Manifest sequence the image claims.
+        version: u32,
+        /// Image length in bytes.
+        len: usize,
+    },
+    /// The image was rejected before it was installed.
+    VerifyFailed { module_id: ModuleId, error: Error },
+    /// Version `to` replaced `from` (0 = not installed before).
+    ModuleActivated {
+        module_id: ModuleId,
+        from: u32,
+        to: u32,
+    },
+    /// An invocation of `entry` failed in the engine.
+    InvokeTrapped {
+        module_id: ModuleId,
+        version: u32,
+        entry: &'a str,
+        error: Error,
+    },
+    /// Version `failed` was discarded and `restored` is active again.
+    RolledBack {
+        module_id: ModuleId,
+        failed: u32,
+        restored: u32,
+    },
+    /// The module failed `failures` times in a row and no longer runs;
+    /// `error` is the last failure.
+    Quarantined {
+        module_id: ModuleId,
+        failures: u32,
+        error: Error,
+    },
+}
+
+impl Event<'_> {
+    /// Module the event is about.
+    pub fn module_id(&self) -> ModuleId {
+        match *self {
+            Event::UpdateReceived { module_id, .. }
+            | Event::VerifyFailed { module_id, .. }
+            | Event::ModuleActivated { module_id, .. }
+            | Event::InvokeTrapped { module_id, .. }
+            | Event::RolledBack { module_id, .. }
+            | Event::Quarantined { module_id, .. } => module_id,
+        }
+    }
+
+    /// Stable wire code of the event kind, for compact transports.
+    pub fn code(&self) -> u8 {
+        match self {
+            Event::UpdateReceived { .. } => 1,
+            Event::VerifyFailed { .. } => 2,
+            Event::ModuleActivated { .. } => 3,
+            Event::InvokeTrapped { .. } => 4,
+            Event::RolledBack { .. } => 5,
+            Event::Quarantined { .. } => 6,
+        }
+    }
+}
+
+/// One event as delivered to an `EventSink`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Record<'a> {
+    /// UTC ms from the sink's clock.
+    pub at_ms: u64,
+    /// Correlation id in progress (`NO_CORRELATION` when not traced).
+    pub correlation: CorrelationId,
+    pub event: Event<'a>,
+}
+
+/// Receives the runtime's telemetry events.
+pub trait EventSink {
+    fn event(&mut self, record: &Record<'_>);
+}
+
+/// Sink plus the clock its records are stamped with.
+pub(crate) struct Telemetry {
+    sink: Box<dyn EventSink>,
+    clock: Box<dyn Clock>,
+}
+
+impl Telemetry {
+    pub(crate) fn new(sink: impl EventSink + 'static, clock: impl Clock + 'static) -> Self {
+        Self {
+            sink: Box::new(sink),
+            clock: Box::new(clock),
+        }
+    }
+
+    pub(crate) fn emit(&mut self, correlation: CorrelationId, event: Event<'_>) {
+        self.sink.event(&Record {
+            at_ms: self.clock.now_ms(),
+            correlation,
+            event,
+        });
+    }
+}
+
+#[cfg(all(test, feature = "std"))]
+mod tests {
+    use super::*;
+    use crate::{Engine, MemoryStore, Result, Runtime};
+    use alloc::rc::Rc;
+    use alloc::string::{String, ToString};
+    use alloc::vec::Vec;
+    use core::cell::{Cell, RefCell};
+
+    struct Fixed(Rc<Cell<u64>>);
+
+    impl Clock for Fixed {
+        fn now_ms(&self) -> u64 {
+            self.0.get()
+        }
+    }
+
+    /// `(at_ms, code, module, entry)` of an event.
+    type Seen = (u64, u8, ModuleId, String);
+
+    #[derive(Clone, Default)]
+    struct Log(Rc<RefCell<Vec<Seen>>>);
+
+    impl EventSink for Log {
+        fn event(&mut self, record: &Record<'_>) {
+            let entry = match record.event {
+                Event::InvokeTrapped { entry, .. } => entry.to_string(),
+                _ => String::new(),
+            };
+            let event = &record.event;
+            self.0
+                .borrow_mut()
+                .push((record.at_ms, event.code(), event.module_id(), entry));
+        }
+    }
+
+    /// Entry `trap` fails; every other entry is missing except `main`.
+    struct Traps;
+
+    impl Engine for Traps {
+        type ModuleHandle = ();
+        type Context = ();
+
+        fn load(&mut self, _id: ModuleId, _module: &[u8]) -> Result<()> {
+            Ok(())
+        }
+
+        fn invoke(&mut self, _handle: (), entry: &str, _ctx: &mut ()) -> Result<()> {
+            match entry {
+                "main" => Ok(()),
+                "trap" => Err(Error::Engine("unreachable")),
+                _ => Err(Error::EntryNotFound),
+            }
+        }
+    }
+
+    #[test]
+    fn reports_trapped_invocations_with_timestamps() {
+        let mut store = MemoryStore::new();
+        store.upsert(3, b"\0asm".to_vec());
+        let mut runtime = Runtime::new(Traps, store);
+        let now = Rc::new(Cell::new(1_000));
+        let log = Log::default();
+        runtime.set_event_sink(log.clone(), Fixed(now.clone()));
+
+        runtime.execute(3, "main", &mut ()).unwrap();
+        assert!(runtime.execute(3, "missing", &mut ()).is_err());
+        now.set(2_500);
+        assert!(runtime.execute(3, "trap", &mut ()).is_err());
+        assert!(runtime.execute(4, "trap", &mut ()).is_err());
+        assert_eq!(*log.0.borrow(), [(2_500, 4, 3, "trap".to_string())]);
+    }
+
+    #[cfg(feature = "unstable")]
+    #[test]
+    fn reports_swaps_and_rejected_images() {
+        use crate::manifest::{self, FLAG_ROLLBACK_PROTECTED};
+
+        let blob = |sequence| {
+            let module = b"\0asm\x01\0\0\0";
+            manifest::encode(1, "main", module, FLAG_ROLLBACK_PROTECTED, sequence, None).unwrap()
+        };
+        let mut runtime = Runtime::new(Traps, MemoryStore::new());
+        let log = Log::default();
+        runtime.set_event_sink(log.clone(), Fixed(Rc::new(Cell::new(7))));
+        let trace = runtime.trace();
+
+        runtime.swap(1, &blob(2)).unwrap();
+        assert!(runtime.swap(1, &blob(1)).is_err());
+        let codes: Vec<u8> = log.0.borrow().iter().map(|e| e.1).collect();
+        assert_eq!(codes, [1, 3, 1, 2]);
+
+        // Records carry the correlation id of the command that caused them.
+        let sink = Rc::new(RefCell::new(Vec::new()));
+        struct Correlations(Rc<RefCell<Vec<CorrelationId>>>);
+        impl EventSink for Correlations {
+            fn event(&mut self, record: &Record<'_>) {
+                self.0.borrow_mut().push(record.correlation);
+            }
+        }
+        runtime.set_event_sink(Correlations(sink.clone()), Fixed(Rc::new(Cell::new(0))));
+        let scope = trace.scope(42);
+        runtime.swap(1, &blob(3)).unwrap();
+        drop(scope);
+        assert_eq!(*sink.borrow(), [42, 42]);
+    }
+}
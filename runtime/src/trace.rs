@@ -0,0 +1,265 @@
+//! Correlation ids for tracing an invocation end to end.
+//!
+//! Every `Runtime::execute` runs under a correlation id: a fresh one by default,
+//! or the id of the remote command / event that caused it when the caller uses
+//! `Runtime::execute_for`. The id, together with the rest of the `Invocation`
+//! (module, version, reason), is published through a shared `TraceContext`, so
+//! host import sets can stamp it on what they emit: guests read it with
+//! `trace_id` or `invocation_info`, `log` records carry it to a `LogSink`, bus
+//! messages remember it, and state-gate denials report it.
+//!
+//! Guest imports (module `env`, all `i32`):
+//! - `trace_id(ptr) -> status` – writes the current id as a little-endian `u64`.
+//! - `log(level, ptr, len) -> status` – forwards `len` bytes to the `LogSink`.
+
+use crate::abi::{guest_bytes, GuestMemory, HostFn, HostImports, E_INVALID, OK};
+use crate::{Error, ModuleId, Result};
+use alloc::rc::Rc;
+use core::cell::Cell;
+
+/// Identifier shared by every record caused by one command/event; 0 = none.
+pub type CorrelationId = u64;
+
+/// Correlation id meaning "not traced".
+pub const NO_CORRELATION: CorrelationId = 0;
+/// Longest message accepted by the `log` import.
+pub const MAX_LOG_LEN: usize = 128;
+
+/// Host functions provided by `TraceImports`.
+pub const FUNCTIONS: &[HostFn] = &[HostFn::new("trace_id", 1), HostFn::new("log", 3)];
+
+/// Why a module is being invoked.
+#[repr(u8)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Reason {
+    /// Called directly by firmware (`Runtime::execute`).
+    #[default]
+    Direct = 0,
+    /// Fired by the scheduler.
+    Scheduled = 1,
+    /// Triggered by an event (bus message, interrupt, sensor threshold).
+    Event = 2,
+    /// Requested by a remote command from the backend.
+    RemoteCommand = 3,
+}
+
+/// The invocation in progress, as seen by host imports.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Invocation {
+    pub module_id: ModuleId,
+    /// Module version (manifest sequence; 0 when unknown).
+    pub version: u32,
+    pub reason: Reason,
+    pub correlation: CorrelationId,
+}
+
+struct State {
+    current: Cell<Invocation>,
+    next: Cell<u64>,
+}
+
+/// Shared handle to the correlation id of the invocation in progress.
+#[derive(Clone)]
+pub struct TraceContext {
+    state: Rc<State>,
+}
+
+impl Default for TraceContext {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl TraceContext {
+    /// Creates a context whose fresh ids start at 1.
+    pub fn new() -> Self {
+        Self::with_seed(0)
+    }
+
+    /// Creates a context whose fresh ids continue from `seed` (e.g. boot counter << 32),
+    /// so ids stay unique across reboots.
+    pub fn with_seed(seed: u64) -> Self {
+        Self {
+            state: Rc::new(State {
+                current: Cell::new(Invocation::default()),
+                next: Cell::new(seed),
+            }),
+        }
+    }
+
+    /// Allocates a new, non-zero correlation id.
+    pub fn fresh(&self) -> CorrelationId {
+        let mut id = self.state.next.get().wrapping_add(1);
+        if id == NO_CORRELATION {
+            id = 1;
+        }
+        self.state.next.set(id);
+        id
+    }
+
+    /// Correlation id of the invocation in progress (`NO_CORRELATION` when idle).
+    pub fn current(&self) -> CorrelationId {
+        self.state.current.get().correlation
+    }
+
+    /// The invocation in progress (`Invocation::default()` when idle).
+    pub fn invocation(&self) -> Invocation {
+        self.state.current.get()
+    }
+
+    /// Makes `id` current until the returned guard drops; `NO_CORRELATION` allocates a fresh id.
+    pub fn scope(&self, id: CorrelationId) -> TraceScope {
+        self.enter(Invocation {
+            correlation: id,
+            ..self.invocation()
+        })
+    }
+
+    /// Makes `invocation` current until the returned guard drops; a
+    /// `NO_CORRELATION` id is replaced with a fresh one.
+    pub fn enter(&self, mut invocation: Invocation) -> TraceScope {
+        if invocation.correlation == NO_CORRELATION {
+            invocation.correlation = self.fresh();
+        }
+        TraceScope {
+            previous: self.state.current.replace(invocation),
+            trace: self.clone(),
+        }
+    }
+}
+
+/// Restores the previous correlation id on drop (see `TraceContext::scope`).
+pub struct TraceScope {
+    trace: TraceContext,
+    previous: Invocation,
+}
+
+impl TraceScope {
+    /// Correlation id active inside this scope.
+    pub fn id(&self) -> CorrelationId {
+        self.trace.current()
+    }
+}
+
+impl Drop for TraceScope {
+    fn drop(&mut self) {
+        self.trace.state.current.set(self.previous);
+    }
+}
+
+/// Receives guest log lines stamped with module and correlation id.
+pub trait LogSink {
+    fn record(&mut self, record: &LogRecord<'_>);
+}
+
+/// One guest log line.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LogRecord<'a> {
+    pub module_id: ModuleId,
+    pub correlation: CorrelationId,
+    pub level: u8,
+    pub message: &'a [u8],
+}
+
+/// `trace_id` and `log` imports over a shared `TraceContext`.
+pub struct TraceImports<L> {
+    trace: TraceContext,
+    sink: L,
+    active: Option<ModuleId>,
+}
+
+impl<L: LogSink> TraceImports<L> {
+    /// Creates the import set; pass `Runtime::trace()` so ids match the runtime's.
+    pub fn new(trace: TraceContext, sink: L) -> Self {
+        Self {
+            trace,
+            sink,
+            active: None,
+        }
+    }
+
+    fn log(&mut self, level: i32, ptr: i32, len: i32, memory: &dyn GuestMemory) -> Result<i32> {
+        let mut buf = [0u8; MAX_LOG_LEN];
+        let (Ok(level), Some(message)) =
+            (u8::try_from(level), guest_bytes(memory, ptr, len, &mut buf))
+        else {
+            return Ok(E_INVALID);
+        };
+        let module_id = self
+            .active
+            .ok_or(Error::Engine("trace: no active module"))?;
+        self.sink.record(&LogRecord {
+            module_id,
+            correlation: self.trace.current(),
+            level,
+            message,
+        });
+        Ok(OK)
+    }
+}
+
+impl<L: LogSink> HostImports for TraceImports<L> {
+    fn functions(&self) -> &[HostFn] {
+        FUNCTIONS
+    }
+
+    fn call(&mut self, name: &str, args: &[i32], memory: &mut dyn GuestMemory) -> Result<i32> {
+        match (name, args) {
+            ("trace_id", &[ptr]) => {
+                let id = self.trace.current().to_le_bytes();
+                Ok(memory.write(ptr as u32, &id).map_or(E_INVALID, |_| OK))
+            }
+            ("log", &[level, ptr, len]) => self.log(level, ptr, len, memory),
+            _ => Err(Error::Engine("trace: bad host call")),
+        }
+    }
+
+    fn enter(&mut self, module_id: ModuleId) {
+        self.active = Some(module_id);
+    }
+}
+
+#[cfg(all(test, feature = "std"))]
+mod tests {
+    use super::*;
+    use std::vec::Vec;
+
+    #[derive(Default)]
+    struct Collect(Vec<(ModuleId, CorrelationId, u8, Vec<u8>)>);
+
+    impl LogSink for Collect {
+        fn record(&mut self, record: &LogRecord<'_>) {
+            self.0.push((
+                record.module_id,
+                record.correlation,
+                record.level,
+                record.message.to_vec(),
+            ));
+        }
+    }
+
+    #[test]
+    fn guest_sees_and_logs_current_id() {
+        let trace = TraceContext::with_seed(41);
+        let mut imports = TraceImports::new(trace.clone(), Collect::default());
+        let mut memory = [0u8; 16];
+        memory[8..10].copy_from_slice(b"hi");
+
+        let scope = trace.scope(NO_CORRELATION);
+        assert_eq!(scope.id(), 42);
+        imports.enter(5);
+        assert_eq!(imports.call("trace_id", &[0], &mut memory).unwrap(), OK);
+        assert_eq!(imports.call("log", &[2, 8, 2], &mut memory).unwrap(), OK);
+        assert_eq!(
+            imports
+                .call("log", &[2, 8, MAX_LOG_LEN as i32 + 1], &mut memory)
+                .unwrap(),
+            E_INVALID
+        );
+        drop(scope);
+
+        assert_eq!(u64::from_le_bytes(memory[..8].try_into().unwrap()), 42);
+        assert_eq!(imports.sink.0, vec![(5, 42, 2, b"hi".to_vec())]);
+        assert_eq!(trace.current(), NO_CORRELATION);
+    }
+}
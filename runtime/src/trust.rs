@@ -0,0 +1,441 @@
+//! Verifying stored modules before they run.
+//!
+//! `Runtime::set_verification_policy` decides whether invocations re-check a
+//! module's stored bytes against the runtime's `Keyring` (`audit::check`)
+//! before the engine loads them:
+//! - `Never` (default): the source is trusted as it is.
+//! - `Always`: every invocation checks, so a signed blob costs an Ed25519
+//!   verification per call.
+//! - `OnInstall`: a module is checked once and marked trusted under the
+//!   BLAKE3 digest of its stored bytes; later calls only hash and compare.
+//!   `Runtime::swap` marks the images it installs, other modules are checked
+//!   and marked on their first call, and bytes that changed since no longer
+//!   match their mark.
+//!
+//! A mark only holds for the keyring it was made under: the digest also
+//! covers `Keyring::fingerprint`, so after `Runtime::set_keyring` removes a
+//! key or starts requiring signatures every marked image is checked again.
+//! With `revocation` it covers the sequence of the runtime's revocation list
+//! too, so installing a newer list does the same.
+//!
+//! Without `verify-blake3` there is nothing to key marks by, and `OnInstall`
+//! checks every call like `Always`.
+//!
+//! With a `KvStore` attached (`TrustMarks::persist_to`), marks are written
+//! under `TRUST_NAMESPACE`, which guests cannot address, and survive a
+//! reboot. The store may still be written by something other than the
+//! runtime, so marks are BLAKE3 MACs under a device secret, as in
+//! `sigcache::KvDigests`: a mark forged without the secret never matches.
+//! A module that fails the check is refused before it is loaded, reported
+//! as `Event::VerifyFailed` and counted toward quarantine.
+
+use crate::audit::{self, Issue, Keyring};
+use crate::kv::KvStore;
+use crate::manifest::ct_eq;
+use crate::{Engine, Error, ModuleId, ModuleSource, Result, Runtime};
+use alloc::boxed::Box;
+use alloc::vec::Vec;
+
+/// Namespace marks are stored under; reserved (`kv::RESERVED_NAMESPACES`),
+/// so guests cannot reach it.
+pub const TRUST_NAMESPACE: ModuleId = ModuleId::MAX - 3;
+const _: () = assert!(crate::kv::is_reserved(TRUST_NAMESPACE));
+
+/// BLAKE3 MAC of a module's stored bytes and the keyring that accepted
+/// them.
+pub type Digest = [u8; 32];
+
+/// When invocations verify the module they run.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum VerificationPolicy {
+    /// Before every invocation.
+    Always,
+    /// Once per stored image (see the module docs).
+    OnInstall,
+    /// Not at all.
+    #[default]
+    Never,
+}
+
+/// Modules verified since their bytes last changed.
+#[derive(Default)]
+pub struct TrustMarks {
+    /// Ascending by id.
+    marks: Vec<(ModuleId, Digest)>,
+    store: Option<Box<dyn KvStore>>,
+    /// Fingerprint of what marks are made under (see `set_context`).
+    context: Digest,
+    /// Revocation list sequence marks are made under (see `set_revision`).
+    revision: u32,
+    /// Key of the mark MAC (see `persist_to`).
+    secret: [u8; 32],
+}
+
+impl TrustMarks {
+    /// No marks, nothing persisted.
+    pub const fn new() -> Self {
+        Self {
+            marks: Vec::new(),
+            store: None,
+            context: [0; 32],
+            revision: 0,
+            secret: [0; 32],
+        }
+    }
+
+    /// Makes marks from now on hold only under `context` (a keyring
+    /// fingerprint); marks made under another context stop matching.
+    pub fn set_context(&mut self, context: Digest) {
+        if self.context != context {
+            self.context = context;
+            self.marks.clear();
+        }
+    }
+
+    /// Makes marks from now on hold only while revocation list `sequence`
+    /// (0 for none) is installed.
+    pub fn set_revision(&mut self, sequence: u32) {
+        if self.revision != sequence {
+            self.revision = sequence;
+            self.marks.clear();
+        }
+    }
+
+    /// Reads marks from `store` when they are looked up and writes every new
+    /// one there, authenticated with `secret`: a device-unique key (from a
+    /// secure element, OTP or the key store) that never leaves the device.
+    /// Marks already held in memory were made under another key and are
+    /// dropped.
+    pub fn persist_to(&mut self, store: impl KvStore + 'static, secret: [u8; 32]) {
+        self.store = Some(Box::new(store));
+        self.secret = secret;
+        self.marks.clear();
+    }
+
+    /// Whether `stored` is the image last marked for the module.
+    pub fn is_trusted(&mut self, module_id: ModuleId, stored: &[u8]) -> bool {
+        let Some(digest) = self.digest(module_id, stored) else {
+            return false;
+        };
+        if let Ok(at) = self.find(module_id) {
+            return ct_eq(&self.marks[at].1, &digest);
+        }
+        let mut persisted = [0; 32];
+        let found = self
+            .store
+            .as_ref()
+            .map(|store| store.get(TRUST_NAMESPACE, &module_id.to_le_bytes(), &mut persisted));
+        if found != Some(Ok(Some(32))) {
+            return false;
+        }
+        // Cached, so the store is read once per module.
+        if let Err(at) = self.find(module_id) {
+            self.marks.insert(at, (module_id, persisted));
+        }
+        ct_eq(&persisted, &digest)
+    }
+
+    /// Marks `stored` as the module's verified image. Without
+    /// `verify-blake3` this does nothing.
+    pub fn mark(&mut self, module_id: ModuleId, stored: &[u8]) -> Result<()> {
+        let Some(digest) = self.digest(module_id, stored) else {
+            return Ok(());
+        };
+        if let Some(store) = &mut self.store {
+            store.set(TRUST_NAMESPACE, &module_id.to_le_bytes(), &digest)?;
+        }
+        match self.find(module_id) {
+            Ok(at) => self.marks[at].1 = digest,
+            Err(at) => self.marks.insert(at, (module_id, digest)),
+        }
+        Ok(())
+    }
+
+    /// Drops the module's mark; its next check runs in full.
+    pub fn forget(&mut self, module_id: ModuleId) -> Result<()> {
+        if let Some(store) = &mut self.store {
+            store.remove(TRUST_NAMESPACE, &module_id.to_le_bytes())?;
+        }
+        if let Ok(at) = self.find(module_id) {
+            self.marks.remove(at);
+        }
+        Ok(())
+    }
+
+    /// Checks `stored` as `policy` asks.
+    pub(crate) fn check(
+        &mut self,
+        policy: VerificationPolicy,
+        keyring: &Keyring,
+        module_id: ModuleId,
+        stored: &[u8],
+    ) -> Result<()> {
+        match policy {
+            VerificationPolicy::Never => Ok(()),
+            VerificationPolicy::Always => audit::check(module_id, stored, keyring).map_err(refused),
+            VerificationPolicy::OnInstall if self.is_trusted(module_id, stored) => Ok(()),
+            VerificationPolicy::OnInstall => {
+                audit::check(module_id, stored, keyring).map_err(refused)?;
+                // A mark that could not be persisted only costs a check.
+                let _ = self.mark(module_id, stored);
+                Ok(())
+            }
+        }
+    }
+
+    fn find(&self, module_id: ModuleId) -> core::result::Result<usize, usize> {
+        self.marks.binary_search_by_key(&module_id, |(id, _)| *id)
+    }
+
+    #[cfg(feature = "verify-blake3")]
+    fn digest(&self, module_id: ModuleId, stored: &[u8]) -> Option<Digest> {
+        let mut mac = blake3::Hasher::new_keyed(&self.secret);
+        mac.update(&self.context);
+        mac.update(&self.revision.to_le_bytes());
+        mac.update(&module_id.to_le_bytes());
+        mac.update(stored);
+        Some(*mac.finalize().as_bytes())
+    }
+
+    #[cfg(not(feature = "verify-blake3"))]
+    fn digest(&self, _module_id: ModuleId, _stored: &[u8]) -> Option<Digest> {
+        None
+    }
+}
+
+fn refused(issue: Issue) -> Error {
+    let reason = match issue {
+        Issue::Missing => "verify: module missing",
+        Issue::Corrupted(reason) => reason,
+        Issue::Unsigned => "verify: signature required but absent",
+        Issue::Untrusted => "verify: signature not from a trusted key",
+        Issue::DigestMismatch => "verify: digest does not match pin",
+        Issue::Revoked => return Error::Revoked,
+    };
+    Error::Engine(reason)
+}
+
+impl<E: Engine, S: ModuleSource> Runtime<E, S> {
+    pub fn verification_policy(&self) -> VerificationPolicy {
+        self.verification
+    }
+
+    /// When invocations verify stored modules against the keyring.
+    pub fn set_verification_policy(&mut self, policy: VerificationPolicy) {
+        self.verification = policy;
+    }
+
+    /// Modules `OnInstall` treats as verified, e.g. to persist them.
+    pub fn trust_marks(&mut self) -> &mut TrustMarks {
+        &mut self.trust_marks
+    }
+
+    /// Checks the stored bytes of `module_id` under the verification policy
+    /// before they are loaded; a refusal is reported as `VerifyFailed` and
+    /// counted toward quarantine.
+    pub(crate) fn verify_stored(&mut self, module_id: ModuleId) -> Result<()> {
+        self.refresh_trust_revision();
+        let stored = self.source.fetch(module_id).ok_or(Error::ModuleNotFound)?;
+        let Err(error) =
+            self.trust_marks
+                .check(self.verification, &self.keyring, module_id, stored)
+        else {
+            return Ok(());
+        };
+        self.emit(crate::telemetry::Event::VerifyFailed { module_id, error });
+        self.record_failure(module_id, error);
+        Err(error)
+    }
+
+    /// Ties trust marks to the current keyring.
+    pub(crate) fn refresh_trust_context(&mut self) {
+        #[cfg(feature = "verify-blake3")]
+        self.trust_marks.set_context(self.keyring.fingerprint());
+    }
+
+    /// Ties trust marks to the installed revocation list, which
+    /// `Runtime::revocations` may have replaced since the last call.
+    pub(crate) fn refresh_trust_revision(&mut self) {
+        #[cfg(feature = "revocation")]
+        self.trust_marks
+            .set_revision(self.revocations.current().map_or(0, |list| list.sequence()));
+    }
+}
+
+#[cfg(all(test, feature = "std"))]
+mod tests {
+    use super::*;
+    use crate::kv::MemoryKv;
+    use crate::MemoryStore;
+    use alloc::rc::Rc;
+    use core::cell::RefCell;
+
+    struct Loads;
+
+    impl Engine for Loads {
+        type ModuleHandle = ModuleId;
+        type Context = u32;
+
+        fn load(&mut self, id: ModuleId, _module: &[u8]) -> Result<ModuleId> {
+            Ok(id)
+        }
+
+        fn invoke(&mut self, _handle: ModuleId, _entry: &str, ctx: &mut u32) -> Result<()> {
+            *ctx += 1;
+            Ok(())
+        }
+    }
+
+    const WASM: &[u8] = b"\0asm\x01\0\0\0";
+    const SECRET: [u8; 32] = [9; 32];
+
+    #[test]
+    fn policies_decide_when_stored_modules_are_checked() {
+        let mut store = MemoryStore::new();
+        store.upsert(1, WASM);
+        store.upsert(2, b"not wasm".to_vec());
+        let mut runtime = Runtime::new(Loads, store);
+        let mut calls = 0;
+        runtime.execute(2, "main", &mut calls).unwrap();
+
+        runtime.set_verification_policy(VerificationPolicy::Always);
+        assert_eq!(
+            runtime.execute(2, "main", &mut calls),
+            Err(Error::Engine("not a wasm module"))
+        );
+        runtime.execute(1, "main", &mut calls).unwrap();
+        assert_eq!(calls, 2);
+        // Preloading goes through the same check.
+        assert_eq!(
+            runtime.preload(&[1, 2]),
+            Err(Error::Engine("not a wasm module"))
+        );
+
+        let kv = Rc::new(RefCell::new(MemoryKv::default()));
+        runtime.trust_marks().persist_to(kv.clone(), SECRET);
+        runtime.set_verification_policy(VerificationPolicy::OnInstall);
+        runtime.execute(1, "main", &mut calls).unwrap();
+        #[cfg(feature = "verify-blake3")]
+        {
+            // Marked on the first call; a fresh set of marks reads it back.
+            let mut marks = TrustMarks::new();
+            marks.persist_to(kv.clone(), SECRET);
+            assert!(marks.is_trusted(1, WASM));
+            assert!(!marks.is_trusted(1, b"\0asm\x01\0\0\0\0"));
+
+            // A mark stops holding when the image's bytes change...
+            runtime.execute(1, "main", &mut calls).unwrap();
+            runtime.source_mut().upsert(1, b"not wasm".to_vec());
+            assert_eq!(
+                runtime.execute(1, "main", &mut calls),
+                Err(Error::Engine("not a wasm module"))
+            );
+
+            // ...or the keyring changes.
+            runtime.source_mut().upsert(1, WASM);
+            runtime.execute(1, "main", &mut calls).unwrap();
+            runtime.set_keyring(Keyring::new().require_signatures());
+            assert_eq!(
+                runtime.execute(1, "main", &mut calls),
+                Err(Error::Engine("verify: signature required but absent"))
+            );
+            let mut marks = TrustMarks::new();
+            marks.persist_to(kv, SECRET);
+            marks.set_context(runtime.keyring().fingerprint());
+            assert!(!marks.is_trusted(1, WASM));
+        }
+    }
+
+    #[cfg(feature = "verify-blake3")]
+    #[test]
+    fn forged_marks_are_rejected() {
+        let kv = Rc::new(RefCell::new(MemoryKv::default()));
+        // What an unkeyed mark of the image would look like.
+        let mut forged = blake3::Hasher::new();
+        forged.update(&[0; 32]);
+        forged.update(&0u32.to_le_bytes());
+        forged.update(&1u32.to_le_bytes());
+        forged.update(WASM);
+        kv.borrow_mut()
+            .set(
+                TRUST_NAMESPACE,
+                &1u32.to_le_bytes(),
+                forged.finalize().as_bytes(),
+            )
+            .unwrap();
+        let mut marks = TrustMarks::new();
+        marks.persist_to(kv.clone(), SECRET);
+        assert!(!marks.is_trusted(1, WASM));
+
+        // Nor does a mark made under another device's secret match.
+        let mut other = TrustMarks::new();
+        other.persist_to(kv.clone(), [7; 32]);
+        other.mark(1, WASM).unwrap();
+        let mut marks = TrustMarks::new();
+        marks.persist_to(kv.clone(), SECRET);
+        assert!(!marks.is_trusted(1, WASM));
+
+        // Or a mark copied over from another module.
+        marks.mark(2, WASM).unwrap();
+        let mut copied = [0; 32];
+        kv.borrow()
+            .get(TRUST_NAMESPACE, &2u32.to_le_bytes(), &mut copied)
+            .unwrap();
+        kv.borrow_mut()
+            .set(TRUST_NAMESPACE, &1u32.to_le_bytes(), &copied)
+            .unwrap();
+        let mut marks = TrustMarks::new();
+        marks.persist_to(kv, SECRET);
+        assert!(!marks.is_trusted(1, WASM));
+        assert!(marks.is_trusted(2, WASM));
+    }
+
+    #[cfg(all(
+        feature = "revocation",
+        feature = "verify-ed25519",
+        feature = "verify-blake3"
+    ))]
+    #[test]
+    fn revocation_lists_void_marks_and_revoke_keyring_signers() {
+        use crate::revoke::signing_preimage;
+        use crate::verify::{Algorithm, PublicKey};
+        use ed25519_dalek::{Signer, SigningKey};
+
+        let mut store = MemoryStore::new();
+        store.upsert(1, WASM);
+        store.upsert(2, b"not wasm".to_vec());
+        let mut runtime = Runtime::new(Loads, store);
+        runtime.set_verification_policy(VerificationPolicy::OnInstall);
+        let mut calls = 0;
+        runtime.execute(1, "main", &mut calls).unwrap();
+        assert!(runtime.trust_marks().is_trusted(1, WASM));
+
+        let signer = SigningKey::from_bytes(&[1; 32]);
+        let mut list = signing_preimage(1, Algorithm::Ed25519, &[], &[]).unwrap();
+        list.extend_from_slice(&signer.sign(&list).to_bytes());
+        let revocations = runtime.revocations();
+        revocations.add_signer(PublicKey::Ed25519(signer.verifying_key().to_bytes()));
+        revocations.apply(list).unwrap();
+
+        // The next invocation, of any module, sees the list.
+        assert!(runtime.execute(2, "main", &mut calls).is_err());
+        assert!(!runtime.trust_marks().is_trusted(1, WASM));
+        runtime.execute(1, "main", &mut calls).unwrap();
+        assert!(runtime.trust_marks().is_trusted(1, WASM));
+
+        // Keys of the runtime's keyring are checked against the list.
+        let module_signer = SigningKey::from_bytes(&[2; 32]);
+        let preimage = crate::manifest::signing_preimage(3, "main", WASM, 0, 1).unwrap();
+        let sig = module_signer.sign(&preimage).to_bytes();
+        let blob = crate::manifest::encode(3, "main", WASM, 0, 1, Some(sig)).unwrap();
+        runtime.source_mut().upsert(3, blob);
+        let module_key = module_signer.verifying_key().to_bytes();
+        runtime.set_keyring(Keyring::new().with_key(module_key).require_signatures());
+        runtime.execute(3, "main", &mut calls).unwrap();
+        let key_id = crate::revoke::key_id(&PublicKey::Ed25519(module_key));
+        let mut list = signing_preimage(2, Algorithm::Ed25519, &[], &[key_id]).unwrap();
+        list.extend_from_slice(&signer.sign(&list).to_bytes());
+        runtime.revocations().apply(list).unwrap();
+        assert_eq!(runtime.execute(3, "main", &mut calls), Err(Error::Revoked));
+    }
+}
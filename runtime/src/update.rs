@@ -0,0 +1,814 @@
+//! A/B module images and post-update health checks.
+//!
+//! `AbStore` keeps each module's active image plus the one it replaced. After
+//! an OTA install, `Runtime::install_update` calls the module's optional
+//! `health` export (`() -> i32`, or `() -> ()` meaning healthy): a trap or a
+//! nonzero status is a failed attempt, and once `attempts` tries have failed
+//! the previous image is restored. Modules without a `health` export are
+//! accepted as installed.
+//!
+//! `UpdateStateMachine` is the explicit alternative: a new version stays on
+//! trial until the application calls `confirm`, and a trial that survives a
+//! reboot is rolled back by `boot`. Its state lives in an `UpdateLog`
+//! (`KvLog` adapts any `KvStore`).
+//!
+//! Either flow can be preceded by a `DryRun`: the last N inputs the live
+//! module received are replayed against the candidate on a shadow engine, and
+//! the update is rejected unless every one succeeds.
+
+use crate::diff::{Probe, DIFF_MODULE_ID};
+use crate::kv::KvStore;
+use crate::telemetry::Event;
+use crate::{
+    Engine, Error, ModuleCatalog, ModuleId, ModuleSink, ModuleSource, Result, Runtime, Staging,
+};
+use alloc::collections::VecDeque;
+use alloc::string::{String, ToString};
+use alloc::vec::Vec;
+
+/// Export called after an update to decide whether it stays.
+pub const HEALTH_EXPORT: &str = "health";
+/// Health attempts before rolling back, for callers without a policy of their own.
+pub const DEFAULT_HEALTH_ATTEMPTS: u8 = 3;
+
+/// One stored module image.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Image {
+    /// Manifest sequence of the image.
+    pub version: u32,
+    pub bytes: Vec<u8>,
+}
+
+struct Slots {
+    module_id: ModuleId,
+    active: Image,
+    previous: Option<Image>,
+}
+
+/// Module store with an active and a previous image per module.
+#[derive(Default)]
+pub struct AbStore {
+    modules: Vec<Slots>,
+    staging: Staging,
+}
+
+impl AbStore {
+    /// Creates an empty store.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    fn slots(&self, module_id: ModuleId) -> Option<&Slots> {
+        self.modules.iter().find(|s| s.module_id == module_id)
+    }
+
+    /// Makes `bytes` the active image; the image it replaces becomes the
+    /// previous one (an older previous image is discarded).
+    pub fn install(&mut self, module_id: ModuleId, version: u32, bytes: impl Into<Vec<u8>>) {
+        let image = Image {
+            version,
+            bytes: bytes.into(),
+        };
+        match self.modules.iter_mut().find(|s| s.module_id == module_id) {
+            Some(slots) => slots.previous = Some(core::mem::replace(&mut slots.active, image)),
+            None => self.modules.push(Slots {
+                module_id,
+                active: image,
+                previous: None,
+            }),
+        }
+    }
+
+    /// Active image of a module.
+    pub fn active(&self, module_id: ModuleId) -> Option<&Image> {
+        self.slots(module_id).map(|s| &s.active)
+    }
+
+    /// Image the active one replaced, if still kept.
+    pub fn previous(&self, module_id: ModuleId) -> Option<&Image> {
+        self.slots(module_id).and_then(|s| s.previous.as_ref())
+    }
+
+    /// Restores the previous image, discarding the active one; returns the
+    /// restored version.
+    pub fn rollback(&mut self, module_id: ModuleId) -> Result<u32> {
+        let slots = self
+            .modules
+            .iter_mut()
+            .find(|s| s.module_id == module_id)
+            .ok_or(Error::ModuleNotFound)?;
+        let previous = slots
+            .previous
+            .take()
+            .ok_or(Error::Engine("ab: no previous image"))?;
+        slots.active = previous;
+        Ok(slots.active.version)
+    }
+
+    /// Removes both images of a module; returns whether it was stored.
+    pub fn remove(&mut self, module_id: ModuleId) -> bool {
+        let before = self.modules.len();
+        self.modules.retain(|s| s.module_id != module_id);
+        self.modules.len() != before
+    }
+}
+
+impl ModuleSource for AbStore {
+    fn fetch(&self, id: ModuleId) -> Option<&[u8]> {
+        self.active(id).map(|image| image.bytes.as_slice())
+    }
+}
+
+/// Streamed images are installed like `install`, as the next version after
+/// the active one (1 for a new module); use `install` to choose the version.
+impl ModuleSink for AbStore {
+    fn begin(&mut self, id: ModuleId, len: usize) -> Result<()> {
+        self.staging.begin(id, len)
+    }
+
+    fn write(&mut self, chunk: &[u8]) -> Result<()> {
+        self.staging.write(chunk)
+    }
+
+    fn commit(&mut self) -> Result<()> {
+        let (id, bytes) = self.staging.finish()?;
+        let version = self
+            .active(id)
+            .map_or(1, |image| image.version.wrapping_add(1));
+        self.install(id, version, bytes);
+        Ok(())
+    }
+
+    fn abort(&mut self) {
+        self.staging.abort();
+    }
+}
+
+impl ModuleCatalog for AbStore {
+    fn module_count(&self) -> usize {
+        self.modules.len()
+    }
+
+    fn module_id_at(&self, index: usize) -> Option<ModuleId> {
+        self.modules.get(index).map(|s| s.module_id)
+    }
+}
+
+/// What a health check decided.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HealthOutcome {
+    /// The module has no `health` export; the update stands.
+    Unchecked,
+    /// `health` succeeded on attempt `attempts`.
+    Healthy { attempts: u8 },
+    /// Every attempt failed and `restored` is active again.
+    RolledBack { failed: u32, restored: u32 },
+    /// Every attempt failed but there was no previous image to restore.
+    Unhealthy { version: u32 },
+    /// The dry run failed; the image was not installed.
+    Rejected(Rejection),
+}
+
+impl<E: Engine> Runtime<E, AbStore> {
+    /// Installs an update, then runs its health check (see `check_health`).
+    pub fn install_update(
+        &mut self,
+        module_id: ModuleId,
+        version: u32,
+        bytes: impl Into<Vec<u8>>,
+        attempts: u8,
+        ctx: &mut E::Context,
+    ) -> Result<HealthOutcome> {
+        activate(self, module_id, version, bytes.into());
+        self.check_health(module_id, attempts, ctx)
+    }
+
+    /// Like `install_update`, but first rehearses the image with `dry_run` and
+    /// leaves the store untouched when that fails.
+    pub fn install_rehearsed<S: Engine<Context = ()>>(
+        &mut self,
+        dry_run: &mut DryRun<S>,
+        module_id: ModuleId,
+        version: u32,
+        bytes: impl Into<Vec<u8>>,
+        attempts: u8,
+        ctx: &mut E::Context,
+    ) -> Result<HealthOutcome> {
+        let bytes = bytes.into();
+        if let Err(rejection) = dry_run.rehearse(&bytes) {
+            return Ok(HealthOutcome::Rejected(rejection));
+        }
+        self.install_update(module_id, version, bytes, attempts, ctx)
+    }
+
+    /// Calls the active image's `health` export up to `attempts` times (at
+    /// least once) and rolls back to the previous image if none succeeds.
+    ///
+    /// A state-gate denial is returned as an error without counting as a
+    /// failed attempt.
+    pub fn check_health(
+        &mut self,
+        module_id: ModuleId,
+        attempts: u8,
+        ctx: &mut E::Context,
+    ) -> Result<HealthOutcome> {
+        let version = self
+            .source()
+            .active(module_id)
+            .ok_or(Error::ModuleNotFound)?
+            .version;
+        for attempt in 1..=attempts.max(1) {
+            match self.execute_status(module_id, HEALTH_EXPORT, ctx) {
+                Ok(0) => return Ok(HealthOutcome::Healthy { attempts: attempt }),
+                Err(Error::EntryNotFound) => return Ok(HealthOutcome::Unchecked),
+                Err(Error::StateDenied) => return Err(Error::StateDenied),
+                Ok(_) | Err(_) => {}
+            }
+        }
+
+        if self.source().previous(module_id).is_none() {
+            return Ok(HealthOutcome::Unhealthy { version });
+        }
+        let restored = self.source_mut().rollback(module_id)?;
+        self.engine().unload(module_id);
+        self.set_module_version(module_id, restored);
+        self.emit(Event::RolledBack {
+            module_id,
+            failed: version,
+            restored,
+        });
+        Ok(HealthOutcome::RolledBack {
+            failed: version,
+            restored,
+        })
+    }
+}
+
+/// Last `depth` inputs fed to a live module, kept to rehearse its updates.
+#[derive(Debug, Clone)]
+pub struct InputJournal {
+    depth: usize,
+    inputs: VecDeque<Vec<u8>>,
+}
+
+impl InputJournal {
+    /// Keeps at most `depth` inputs (oldest dropped first).
+    pub fn new(depth: usize) -> Self {
+        Self {
+            depth,
+            inputs: VecDeque::with_capacity(depth),
+        }
+    }
+
+    /// Records an input as it is delivered to the live module.
+    pub fn record(&mut self, input: &[u8]) {
+        if self.depth == 0 {
+            return;
+        }
+        if self.inputs.len() == self.depth {
+            self.inputs.pop_front();
+        }
+        self.inputs.push_back(input.to_vec());
+    }
+
+    /// Recorded inputs, oldest first.
+    pub fn inputs(&self) -> impl Iterator<Item = &[u8]> {
+        self.inputs.iter().map(Vec::as_slice)
+    }
+
+    /// Number of recorded inputs.
+    pub fn len(&self) -> usize {
+        self.inputs.len()
+    }
+
+    /// True when nothing was recorded.
+    pub fn is_empty(&self) -> bool {
+        self.inputs.is_empty()
+    }
+}
+
+/// Why a candidate failed its dry run.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Rejection {
+    /// The shadow engine could not load the image.
+    Load(Error),
+    /// Replaying journal input `input` trapped or returned a nonzero status.
+    Input { input: usize, result: Result<i32> },
+}
+
+/// Optional installer stage: replays the journal against a candidate image on
+/// a shadow engine before it is activated.
+///
+/// The shadow engine must have `probe.imports()` in its import set; each
+/// recorded input is served through `msg_input` (see `diff`) and `entry` must
+/// return status 0 (or nothing) for every one. An empty journal passes.
+pub struct DryRun<S> {
+    shadow: S,
+    probe: Probe,
+    entry: String,
+    journal: InputJournal,
+}
+
+impl<S: Engine<Context = ()>> DryRun<S> {
+    /// Rehearses `entry` over the last `depth` recorded inputs.
+    pub fn new(shadow: S, probe: Probe, entry: &str, depth: usize) -> Self {
+        Self {
+            shadow,
+            probe,
+            entry: entry.to_string(),
+            journal: InputJournal::new(depth),
+        }
+    }
+
+    /// Records an input delivered to the live module.
+    pub fn record(&mut self, input: &[u8]) {
+        self.journal.record(input);
+    }
+
+    /// Recorded inputs.
+    pub fn journal(&self) -> &InputJournal {
+        &self.journal
+    }
+
+    /// Replays every recorded input against `module`; returns how many ran.
+    pub fn rehearse(&mut self, module: &[u8]) -> core::result::Result<usize, Rejection> {
+        let handle = self
+            .shadow
+            .load(DIFF_MODULE_ID, module)
+            .map_err(Rejection::Load)?;
+        let mut verdict = Ok(self.journal.len());
+        for (input, bytes) in self.journal.inputs().enumerate() {
+            self.probe.start(bytes);
+            let result = self.shadow.invoke_status(handle, &self.entry, &mut ());
+            self.probe.finish();
+            if result != Ok(0) {
+                verdict = Err(Rejection::Input { input, result });
+                break;
+            }
+        }
+        self.shadow.drop_module(handle);
+        self.shadow.unload(DIFF_MODULE_ID);
+        verdict
+    }
+}
+
+/// Where a module's last update stands.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Lifecycle {
+    /// `version` was confirmed (or restored) and stays across reboots.
+    Confirmed { version: u32 },
+    /// `version` is installed but unconfirmed; the next boot restores `previous`.
+    Trial { version: u32, previous: Option<u32> },
+}
+
+impl Lifecycle {
+    /// Version currently installed.
+    pub fn version(&self) -> u32 {
+        match *self {
+            Lifecycle::Confirmed { version } | Lifecycle::Trial { version, .. } => version,
+        }
+    }
+
+    const LEN: usize = 9;
+    const NO_PREVIOUS: u32 = u32::MAX;
+
+    fn encode(&self) -> [u8; Self::LEN] {
+        let (tag, version, previous) = match *self {
+            Lifecycle::Confirmed { version } => (1, version, Self::NO_PREVIOUS),
+            Lifecycle::Trial { version, previous } => {
+                (2, version, previous.unwrap_or(Self::NO_PREVIOUS))
+            }
+        };
+        let mut out = [0; Self::LEN];
+        out[0] = tag;
+        out[1..5].copy_from_slice(&version.to_le_bytes());
+        out[5..9].copy_from_slice(&previous.to_le_bytes());
+        out
+    }
+
+    fn decode(bytes: &[u8]) -> Result<Self> {
+        if bytes.len() != Self::LEN {
+            return Err(Error::Engine("update log: bad record length"));
+        }
+        let version = u32::from_le_bytes(bytes[1..5].try_into().unwrap());
+        let previous = u32::from_le_bytes(bytes[5..9].try_into().unwrap());
+        match bytes[0] {
+            1 => Ok(Lifecycle::Confirmed { version }),
+            2 => Ok(Lifecycle::Trial {
+                version,
+                previous: (previous != Self::NO_PREVIOUS).then_some(previous),
+            }),
+            _ => Err(Error::Engine("update log: bad record tag")),
+        }
+    }
+}
+
+/// Persistent record of each module's `Lifecycle` (NVS, flash page, ...).
+pub trait UpdateLog {
+    /// Last stored state; `None` for modules never updated through the machine.
+    fn load(&self, module_id: ModuleId) -> Result<Option<Lifecycle>>;
+
+    /// Replaces the stored state; must be durable before returning.
+    fn store(&mut self, module_id: ModuleId, state: Lifecycle) -> Result<()>;
+}
+
+/// Namespace `KvLog` writes under; reserved (`kv::RESERVED_NAMESPACES`), so
+/// guests cannot reach it.
+pub const UPDATE_LOG_NAMESPACE: ModuleId = ModuleId::MAX;
+const _: () = assert!(crate::kv::is_reserved(UPDATE_LOG_NAMESPACE));
+
+/// `UpdateLog` kept in a `KvStore`, one record per module.
+#[derive(Debug, Default, Clone)]
+pub struct KvLog<K> {
+    store: K,
+}
+
+impl<K: KvStore> KvLog<K> {
+    /// Wraps a store (share it via `Rc<RefCell<_>>` to also serve guests).
+    pub fn new(store: K) -> Self {
+        Self { store }
+    }
+
+    /// Underlying store.
+    pub fn into_inner(self) -> K {
+        self.store
+    }
+}
+
+impl<K: KvStore> UpdateLog for KvLog<K> {
+    fn load(&self, module_id: ModuleId) -> Result<Option<Lifecycle>> {
+        let mut buf = [0; Lifecycle::LEN];
+        match self
+            .store
+            .get(UPDATE_LOG_NAMESPACE, &module_id.to_le_bytes(), &mut buf)?
+        {
+            Some(len) if len <= buf.len() => Lifecycle::decode(&buf[..len]).map(Some),
+            Some(_) => Err(Error::Engine("update log: bad record length")),
+            None => Ok(None),
+        }
+    }
+
+    fn store(&mut self, module_id: ModuleId, state: Lifecycle) -> Result<()> {
+        self.store.set(
+            UPDATE_LOG_NAMESPACE,
+            &module_id.to_le_bytes(),
+            &state.encode(),
+        )
+    }
+}
+
+/// What `UpdateStateMachine::boot` did for a module.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BootOutcome {
+    /// Nothing pending; `version` runs.
+    Confirmed { version: u32 },
+    /// An unconfirmed update was discarded and `restored` is active again.
+    RolledBack { failed: u32, restored: u32 },
+    /// An unconfirmed update had no previous version to return to; it stays
+    /// on trial.
+    NoPrevious { version: u32 },
+}
+
+/// Explicit-confirmation update flow on top of `AbStore`:
+/// `begin` installs a version on trial, the application calls `confirm` once
+/// it has verified the new version works, and `boot` (run for each module
+/// before starting it) restores the previous image if a trial survived a reboot.
+///
+/// Images must persist in the store across reboots for the rollback to have
+/// something to restore; the machine itself only persists `Lifecycle`.
+pub struct UpdateStateMachine<L> {
+    log: L,
+}
+
+impl<L: UpdateLog> UpdateStateMachine<L> {
+    /// Creates a machine over a persistent log.
+    pub fn new(log: L) -> Self {
+        Self { log }
+    }
+
+    /// Persistent log.
+    pub fn log(&self) -> &L {
+        &self.log
+    }
+
+    /// Stored lifecycle of a module.
+    pub fn state(&self, module_id: ModuleId) -> Result<Option<Lifecycle>> {
+        self.log.load(module_id)
+    }
+
+    /// Installs `version` on trial. Fails while an earlier update of the same
+    /// module awaits confirmation.
+    pub fn begin<E: Engine>(
+        &mut self,
+        runtime: &mut Runtime<E, AbStore>,
+        module_id: ModuleId,
+        version: u32,
+        bytes: impl Into<Vec<u8>>,
+    ) -> Result<()> {
+        if let Some(Lifecycle::Trial { .. }) = self.log.load(module_id)? {
+            return Err(Error::Engine("update: previous update not confirmed"));
+        }
+        let previous = runtime
+            .source()
+            .active(module_id)
+            .map(|image| image.version);
+        // Record the trial first: a reset between the two writes then rolls
+        // back to the image that is still active.
+        self.log
+            .store(module_id, Lifecycle::Trial { version, previous })?;
+        activate(runtime, module_id, version, bytes.into());
+        Ok(())
+    }
+
+    /// Marks the trial version as good; it will survive the next reboot.
+    pub fn confirm(&mut self, module_id: ModuleId) -> Result<u32> {
+        match self.log.load(module_id)? {
+            Some(Lifecycle::Trial { version, .. }) => {
+                self.log
+                    .store(module_id, Lifecycle::Confirmed { version })?;
+                Ok(version)
+            }
+            _ => Err(Error::Engine("update: nothing to confirm")),
+        }
+    }
+
+    /// Resolves a module's state at startup: an unconfirmed trial is rolled
+    /// back to the previous image.
+    pub fn boot<E: Engine>(
+        &mut self,
+        runtime: &mut Runtime<E, AbStore>,
+        module_id: ModuleId,
+    ) -> Result<BootOutcome> {
+        let (failed, previous) = match self.log.load(module_id)? {
+            Some(Lifecycle::Trial { version, previous }) => (version, previous),
+            Some(Lifecycle::Confirmed { version }) => {
+                return Ok(BootOutcome::Confirmed { version })
+            }
+            None => {
+                let active = runtime.source().active(module_id);
+                let version = active.ok_or(Error::ModuleNotFound)?.version;
+                return Ok(BootOutcome::Confirmed { version });
+            }
+        };
+
+        let active = runtime.source().active(module_id).map(|i| i.version);
+        let restored = match (previous, active) {
+            (None, _) => return Ok(BootOutcome::NoPrevious { version: failed }),
+            // Reset before the trial image was written: the old one is active.
+            (Some(previous), Some(active)) if active != failed => previous,
+            (Some(_), _) => runtime.source_mut().rollback(module_id)?,
+        };
+        self.log
+            .store(module_id, Lifecycle::Confirmed { version: restored })?;
+        runtime.engine().unload(module_id);
+        runtime.set_module_version(module_id, restored);
+        runtime.emit(Event::RolledBack {
+            module_id,
+            failed,
+            restored,
+        });
+        Ok(BootOutcome::RolledBack { failed, restored })
+    }
+}
+
+/// Installs `bytes` as the active image and reports it.
+fn activate<E: Engine>(
+    runtime: &mut Runtime<E, AbStore>,
+    module_id: ModuleId,
+    version: u32,
+    bytes: Vec<u8>,
+) {
+    runtime.emit(Event::UpdateReceived {
+        module_id,
+        version,
+        len: bytes.len(),
+    });
+    let from = runtime
+        .source()
+        .active(module_id)
+        .map_or(0, |image| image.version);
+    runtime.source_mut().install(module_id, version, bytes);
+    runtime.engine().unload(module_id);
+    runtime.set_module_version(module_id, version);
+    runtime.emit(Event::ModuleActivated {
+        module_id,
+        from,
+        to: version,
+    });
+}
+
+#[cfg(all(test, feature = "std"))]
+mod tests {
+    use super::*;
+    use crate::kv::MemoryKv;
+    use std::vec;
+
+    /// The first image byte scripts `health`: 0 = missing export, 1 = healthy,
+    /// 2 = nonzero status, 3 = trap, 4 = fails once then healthy.
+    #[derive(Default)]
+    struct Scripted {
+        loaded: Vec<u8>,
+        calls: u32,
+        unloads: u32,
+        flaked: bool,
+    }
+
+    impl Engine for Scripted {
+        type ModuleHandle = ModuleId;
+        type Context = ();
+
+        fn load(&mut self, id: ModuleId, module: &[u8]) -> Result<ModuleId> {
+            self.loaded = module.to_vec();
+            Ok(id)
+        }
+
+        fn invoke(&mut self, _handle: ModuleId, _entry: &str, _ctx: &mut ()) -> Result<()> {
+            Ok(())
+        }
+
+        fn invoke_status(&mut self, _handle: ModuleId, entry: &str, _ctx: &mut ()) -> Result<i32> {
+            assert_eq!(entry, HEALTH_EXPORT);
+            self.calls += 1;
+            match self.loaded[0] {
+                0 => Err(Error::EntryNotFound),
+                1 => Ok(0),
+                2 => Ok(-5),
+                3 => Err(Error::Engine("trap")),
+                _ => Ok(i32::from(!core::mem::replace(&mut self.flaked, true))),
+            }
+        }
+
+        fn unload(&mut self, _id: ModuleId) {
+            self.unloads += 1;
+        }
+    }
+
+    #[test]
+    fn failed_health_rolls_back_to_previous_image() {
+        let mut runtime = Runtime::new(Scripted::default(), AbStore::new());
+
+        let outcome = runtime.install_update(1, 1, vec![1], 3, &mut ()).unwrap();
+        assert_eq!(outcome, HealthOutcome::Healthy { attempts: 1 });
+
+        let outcome = runtime.install_update(1, 2, vec![2], 3, &mut ()).unwrap();
+        assert_eq!(
+            outcome,
+            HealthOutcome::RolledBack {
+                failed: 2,
+                restored: 1
+            }
+        );
+        assert_eq!(runtime.engine().calls, 4);
+        assert_eq!(runtime.source().active(1).unwrap().bytes, vec![1]);
+        assert_eq!(runtime.source().previous(1), None);
+        assert_eq!(runtime.module_version(1), 1);
+
+        let outcome = runtime.install_update(1, 3, vec![3], 2, &mut ()).unwrap();
+        assert!(matches!(
+            outcome,
+            HealthOutcome::RolledBack { failed: 3, .. }
+        ));
+
+        let outcome = runtime.install_update(1, 4, vec![4], 2, &mut ()).unwrap();
+        assert_eq!(outcome, HealthOutcome::Healthy { attempts: 2 });
+        assert_eq!(runtime.engine().unloads, 6);
+    }
+
+    #[test]
+    fn missing_health_export_and_first_install() {
+        let mut runtime = Runtime::new(Scripted::default(), AbStore::new());
+        assert_eq!(
+            runtime.install_update(2, 1, vec![2], 2, &mut ()).unwrap(),
+            HealthOutcome::Unhealthy { version: 1 }
+        );
+        assert_eq!(
+            runtime.install_update(2, 2, vec![0], 2, &mut ()).unwrap(),
+            HealthOutcome::Unchecked
+        );
+        assert_eq!(runtime.source().previous(2).unwrap().version, 1);
+        assert_eq!(
+            runtime.check_health(9, 1, &mut ()),
+            Err(Error::ModuleNotFound)
+        );
+        assert!(runtime.source_mut().remove(2));
+
+        crate::write_module(runtime.source_mut(), 3, &[1, 1]).unwrap();
+        crate::write_module(runtime.source_mut(), 3, &[2, 2]).unwrap();
+        assert_eq!(runtime.source().active(3).unwrap().version, 2);
+        assert_eq!(runtime.source().previous(3).unwrap().bytes, vec![1, 1]);
+    }
+
+    #[test]
+    fn unconfirmed_update_rolls_back_on_next_boot() {
+        let mut runtime = Runtime::new(Scripted::default(), AbStore::new());
+        let mut machine = UpdateStateMachine::new(KvLog::new(MemoryKv::new()));
+
+        machine.begin(&mut runtime, 1, 1, vec![1]).unwrap();
+        assert_eq!(
+            machine.begin(&mut runtime, 1, 2, vec![1]),
+            Err(Error::Engine("update: previous update not confirmed"))
+        );
+        assert_eq!(machine.confirm(1), Ok(1));
+        assert_eq!(
+            machine.boot(&mut runtime, 1),
+            Ok(BootOutcome::Confirmed { version: 1 })
+        );
+
+        machine.begin(&mut runtime, 1, 2, vec![2]).unwrap();
+        assert_eq!(
+            machine.state(1),
+            Ok(Some(Lifecycle::Trial {
+                version: 2,
+                previous: Some(1)
+            }))
+        );
+
+        // Reboot: only the log and the stored images survive.
+        let log = machine.log().clone();
+        let mut machine = UpdateStateMachine::new(log);
+        assert_eq!(
+            machine.boot(&mut runtime, 1),
+            Ok(BootOutcome::RolledBack {
+                failed: 2,
+                restored: 1
+            })
+        );
+        assert_eq!(runtime.source().active(1).unwrap().bytes, vec![1]);
+        assert_eq!(runtime.module_version(1), 1);
+        assert_eq!(
+            machine.confirm(1),
+            Err(Error::Engine("update: nothing to confirm"))
+        );
+
+        machine.begin(&mut runtime, 2, 1, vec![1]).unwrap();
+        assert_eq!(
+            machine.boot(&mut runtime, 2),
+            Ok(BootOutcome::NoPrevious { version: 1 })
+        );
+    }
+
+    /// Shadow engine: the first image byte is a value the candidate chokes on
+    /// (nonzero status) when it appears in an input.
+    struct Picky {
+        imports: crate::abi::Imports,
+        poison: u8,
+    }
+
+    impl Engine for Picky {
+        type ModuleHandle = ModuleId;
+        type Context = ();
+
+        fn load(&mut self, id: ModuleId, module: &[u8]) -> Result<ModuleId> {
+            self.poison = *module.first().ok_or(Error::Engine("empty"))?;
+            Ok(id)
+        }
+
+        fn invoke(&mut self, _handle: ModuleId, _entry: &str, _ctx: &mut ()) -> Result<()> {
+            Ok(())
+        }
+
+        fn invoke_status(&mut self, _handle: ModuleId, _entry: &str, _ctx: &mut ()) -> Result<i32> {
+            let mut memory = [0u8; 16];
+            let len = self.imports.call("msg_input", &[0, 16], &mut memory)?;
+            Ok(i32::from(memory[..len as usize].contains(&self.poison)))
+        }
+    }
+
+    #[test]
+    fn dry_run_gates_installation() {
+        let probe = Probe::new();
+        let shadow = Picky {
+            imports: crate::abi::Imports::new().with(probe.imports()),
+            poison: 0,
+        };
+        let mut dry_run = DryRun::new(shadow, probe, "main", 2);
+        let mut runtime = Runtime::new(Scripted::default(), AbStore::new());
+        assert_eq!(
+            runtime.install_rehearsed(&mut dry_run, 1, 1, vec![1], 1, &mut ()),
+            Ok(HealthOutcome::Healthy { attempts: 1 })
+        );
+
+        for input in [[1u8, 1], [9, 9], [5, 5]] {
+            dry_run.record(&input);
+        }
+        assert_eq!(dry_run.journal().len(), 2);
+        assert_eq!(
+            dry_run.rehearse(&[]),
+            Err(Rejection::Load(Error::Engine("empty")))
+        );
+
+        let outcome = runtime.install_rehearsed(&mut dry_run, 1, 2, vec![5], 1, &mut ());
+        assert_eq!(
+            outcome,
+            Ok(HealthOutcome::Rejected(Rejection::Input {
+                input: 1,
+                result: Ok(1)
+            }))
+        );
+        assert_eq!(runtime.source().active(1).unwrap().version, 1);
+
+        // Poison 1 only appeared in an input that has aged out of the journal.
+        let outcome = runtime.install_rehearsed(&mut dry_run, 1, 3, vec![1], 1, &mut ());
+        assert_eq!(outcome, Ok(HealthOutcome::Healthy { attempts: 1 }));
+    }
+}
@@ -0,0 +1,309 @@
+//! USB DFU 1.1 class for module uploads (`usb-dfu` feature).
+//!
+//! Exposes the module store as a DFU-mode interface, so stock `dfu-util -D
+//! module.smny` installs modules with no custom host tooling. `UsbDfu` is the
+//! class's request state machine with no USB stack dependency: a
+//! `usb-device` `UsbClass` (or any other stack) writes `INTERFACE_CLASS`,
+//! `INTERFACE_SUBCLASS`, `INTERFACE_PROTOCOL` and `functional_descriptor`
+//! into the configuration descriptor and forwards class requests addressed
+//! to the interface to `control_out` / `control_in`; an error means "stall".
+//!
+//! Downloads are written synchronously, so the status never asks the host to
+//! wait. The blob is parsed, checked with the verification hook and installed
+//! through a `ModuleSink` when the host ends the download; the device is
+//! manifestation tolerant and returns to `dfuIDLE` afterwards, ready for the
+//! next module. Uploads are not supported.
+
+use crate::manifest::Manifest;
+use crate::{write_module, Error, ModuleId, ModuleSink, Result};
+use alloc::vec::Vec;
+
+/// Application-specific interface class.
+pub const INTERFACE_CLASS: u8 = 0xfe;
+/// Device firmware upgrade subclass.
+pub const INTERFACE_SUBCLASS: u8 = 0x01;
+/// DFU mode protocol.
+pub const INTERFACE_PROTOCOL: u8 = 0x02;
+/// Descriptor type of the DFU functional descriptor.
+pub const DFU_FUNCTIONAL: u8 = 0x21;
+
+pub const DFU_DETACH: u8 = 0;
+pub const DFU_DNLOAD: u8 = 1;
+pub const DFU_UPLOAD: u8 = 2;
+pub const DFU_GETSTATUS: u8 = 3;
+pub const DFU_CLRSTATUS: u8 = 4;
+pub const DFU_GETSTATE: u8 = 5;
+pub const DFU_ABORT: u8 = 6;
+
+/// Device states (`bState`).
+#[repr(u8)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DfuState {
+    Idle = 2,
+    DownloadSync = 3,
+    DownloadIdle = 5,
+    ManifestSync = 6,
+    Error = 10,
+}
+
+/// Status codes (`bStatus`).
+#[repr(u8)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DfuStatus {
+    Ok = 0x00,
+    /// The image is not a manifest.
+    ErrTarget = 0x01,
+    /// The sink failed to store the module.
+    ErrWrite = 0x03,
+    /// The image failed verification.
+    ErrVerify = 0x07,
+    /// The image is larger than the device accepts or can buffer.
+    ErrAddress = 0x08,
+    /// A request was not expected in the current state.
+    ErrStalledPkt = 0x0f,
+}
+
+/// DFU functional descriptor: download only, manifestation tolerant,
+/// `transfer_size` bytes per `DFU_DNLOAD`.
+pub fn functional_descriptor(transfer_size: u16) -> [u8; 9] {
+    let [size_lo, size_hi] = transfer_size.to_le_bytes();
+    // bitCanDnload | bitManifestationTolerant; detach timeout 255 ms; DFU 1.1.
+    [
+        9,
+        DFU_FUNCTIONAL,
+        0x05,
+        255,
+        0,
+        size_lo,
+        size_hi,
+        0x10,
+        0x01,
+    ]
+}
+
+/// Module installed by the last completed download.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Installed {
+    pub module_id: ModuleId,
+    pub sequence: u32,
+}
+
+/// DFU-mode class installing downloaded blobs into a `ModuleSink`.
+pub struct UsbDfu<K, V> {
+    sink: K,
+    verify: V,
+    max_len: usize,
+    state: DfuState,
+    status: DfuStatus,
+    next_block: u16,
+    image: Vec<u8>,
+    installed: Option<Installed>,
+}
+
+impl<K, V> UsbDfu<K, V>
+where
+    K: ModuleSink,
+    V: FnMut(&Manifest<'_>, &[u8]) -> Result<()>,
+{
+    /// Installs into `sink`, accepting blobs up to `max_len` bytes whose
+    /// manifest and module pass `verify`.
+    pub fn new(sink: K, max_len: usize, verify: V) -> Self {
+        Self {
+            sink,
+            verify,
+            max_len,
+            state: DfuState::Idle,
+            status: DfuStatus::Ok,
+            next_block: 0,
+            image: Vec::new(),
+            installed: None,
+        }
+    }
+
+    pub fn state(&self) -> DfuState {
+        self.state
+    }
+
+    /// Module installed by the last completed download.
+    pub fn installed(&self) -> Option<Installed> {
+        self.installed
+    }
+
+    pub fn sink(&self) -> &K {
+        &self.sink
+    }
+
+    pub fn sink_mut(&mut self) -> &mut K {
+        &mut self.sink
+    }
+
+    /// Host-to-device class request; `Err` stalls it.
+    pub fn control_out(&mut self, request: u8, value: u16, data: &[u8]) -> Result<()> {
+        match (request, self.state) {
+            (DFU_DETACH, _) => Ok(()),
+            (DFU_DNLOAD, DfuState::Idle | DfuState::DownloadIdle) if !data.is_empty() => {
+                self.download(value, data)
+            }
+            (DFU_DNLOAD, DfuState::DownloadIdle) => {
+                self.state = DfuState::ManifestSync;
+                Ok(())
+            }
+            (DFU_CLRSTATUS, DfuState::Error)
+            | (DFU_ABORT, DfuState::Idle | DfuState::DownloadIdle) => {
+                self.reset();
+                Ok(())
+            }
+            _ => self.stall(),
+        }
+    }
+
+    /// Device-to-host class request; returns the bytes written to `buf`, and
+    /// `Err` stalls it.
+    pub fn control_in(&mut self, request: u8, buf: &mut [u8]) -> Result<usize> {
+        match request {
+            DFU_GETSTATUS if buf.len() >= 6 => {
+                match self.state {
+                    DfuState::DownloadSync => self.state = DfuState::DownloadIdle,
+                    DfuState::ManifestSync => self.manifest(),
+                    _ => {}
+                }
+                // bStatus, bwPollTimeout (0 ms), bState, iString.
+                buf[..6].copy_from_slice(&[self.status as u8, 0, 0, 0, self.state as u8, 0]);
+                Ok(6)
+            }
+            DFU_GETSTATE if !buf.is_empty() => {
+                buf[0] = self.state as u8;
+                Ok(1)
+            }
+            _ => self.stall().map(|()| 0),
+        }
+    }
+
+    fn download(&mut self, block: u16, data: &[u8]) -> Result<()> {
+        if block != self.next_block {
+            return self.stall();
+        }
+        if self.image.len() + data.len() > self.max_len {
+            self.fail(DfuStatus::ErrAddress);
+            return Err(Error::Engine("usb dfu: image too large"));
+        }
+        if let Err(error) = crate::fallible::extend(&mut self.image, data) {
+            self.fail(DfuStatus::ErrAddress);
+            return Err(error);
+        }
+        self.next_block = self.next_block.wrapping_add(1);
+        self.state = DfuState::DownloadSync;
+        Ok(())
+    }
+
+    fn manifest(&mut self) {
+        match self.install() {
+            Ok(installed) => {
+                self.installed = Some(installed);
+                self.reset();
+            }
+            Err(status) => self.fail(status),
+        }
+    }
+
+    fn install(&mut self) -> core::result::Result<Installed, DfuStatus> {
+        let (manifest, module) = Manifest::parse(&self.image).map_err(|_| DfuStatus::ErrTarget)?;
+        if manifest.module_len as usize != module.len() {
+            return Err(DfuStatus::ErrTarget);
+        }
+        (self.verify)(&manifest, module).map_err(|_| DfuStatus::ErrVerify)?;
+        write_module(&mut self.sink, manifest.module_id, module)
+            .map_err(|_| DfuStatus::ErrWrite)?;
+        Ok(Installed {
+            module_id: manifest.module_id,
+            sequence: manifest.sequence,
+        })
+    }
+
+    fn stall(&mut self) -> Result<()> {
+        self.fail(DfuStatus::ErrStalledPkt);
+        Err(Error::Engine("usb dfu: unexpected request"))
+    }
+
+    fn fail(&mut self, status: DfuStatus) {
+        self.image = Vec::new();
+        self.state = DfuState::Error;
+        self.status = status;
+    }
+
+    fn reset(&mut self) {
+        self.image = Vec::new();
+        self.next_block = 0;
+        self.state = DfuState::Idle;
+        self.status = DfuStatus::Ok;
+    }
+}
+
+#[cfg(all(test, feature = "std"))]
+mod tests {
+    use super::*;
+    use crate::manifest::encode;
+    use crate::{MemoryStore, ModuleSource};
+
+    const WASM: &[u8] = b"\0asm\x01\0\0\0";
+
+    /// What `dfu-util -D` does: download blocks, polling the status after
+    /// each, then an empty block and a final status poll.
+    fn dfu_util<K, V>(dfu: &mut UsbDfu<K, V>, blob: &[u8]) -> [u8; 6]
+    where
+        K: ModuleSink,
+        V: FnMut(&Manifest<'_>, &[u8]) -> Result<()>,
+    {
+        let mut status = [0u8; 6];
+        for (block, chunk) in blob.chunks(16).enumerate() {
+            dfu.control_out(DFU_DNLOAD, block as u16, chunk).unwrap();
+            dfu.control_in(DFU_GETSTATUS, &mut status).unwrap();
+            assert_eq!(status[4], DfuState::DownloadIdle as u8);
+        }
+        let end = blob.len().div_ceil(16) as u16;
+        dfu.control_out(DFU_DNLOAD, end, &[]).unwrap();
+        dfu.control_in(DFU_GETSTATUS, &mut status).unwrap();
+        status
+    }
+
+    #[test]
+    fn downloads_install_like_dfu_util() {
+        assert_eq!(functional_descriptor(64)[5..7], [64, 0]);
+
+        let blob = encode(5, "main", WASM, 0, 9, None).unwrap();
+        let mut dfu = UsbDfu::new(MemoryStore::new(), 1024, |m: &Manifest<'_>, _: &[u8]| {
+            if m.module_id == 5 {
+                Ok(())
+            } else {
+                Err(Error::Engine("unknown module"))
+            }
+        });
+        assert_eq!(
+            dfu_util(&mut dfu, &blob),
+            [0, 0, 0, 0, DfuState::Idle as u8, 0]
+        );
+        assert_eq!(dfu.sink().fetch(5), Some(WASM));
+        assert_eq!(
+            dfu.installed(),
+            Some(Installed {
+                module_id: 5,
+                sequence: 9
+            })
+        );
+
+        let other = encode(6, "main", WASM, 0, 1, None).unwrap();
+        let status = dfu_util(&mut dfu, &other);
+        assert_eq!(status[0], DfuStatus::ErrVerify as u8);
+        assert_eq!(status[4], DfuState::Error as u8);
+        assert!(dfu.control_out(DFU_DNLOAD, 0, &other).is_err());
+        dfu.control_out(DFU_CLRSTATUS, 0, &[]).unwrap();
+
+        // Out-of-order blocks and uploads stall.
+        dfu.control_out(DFU_DNLOAD, 0, &blob[..16]).unwrap();
+        assert!(dfu.control_out(DFU_DNLOAD, 2, &blob[16..]).is_err());
+        assert_eq!(dfu.state(), DfuState::Error);
+        dfu.control_out(DFU_CLRSTATUS, 0, &[]).unwrap();
+        assert!(dfu.control_in(DFU_UPLOAD, &mut [0; 16]).is_err());
+        assert_eq!(dfu.sink().fetch(6), None);
+    }
+}
@@ -0,0 +1,326 @@
+//! Pluggable signature verification.
+//!
+//! Everything that checks a signature (`manifest::verify_with`,
+//! `SuitManifest::verify_with`, audits and `trust` through
+//! `Keyring::with_verifier`) goes through a `Verifier`, so targets with a
+//! crypto engine (nRF CryptoCell, ESP32 SHA DMA, a secure element) can plug
+//! in their driver instead of `SoftwareVerifier`, ed25519-dalek on the core,
+//! which takes over 100 ms on a Cortex-M4.
+//!
+//! Manifests are signed with Ed25519 unless they name ECDSA P-256 (see
+//! `manifest::EXT_SIGNATURE_ALG`), for PKIs that only issue P-256 keys;
+//! `SoftwareVerifier` checks those with `verify-p256`.
+//!
+//! Messages arrive in parts (a manifest header, then the module) so a
+//! hardware backend can stream them through its hash unit without the copy
+//! into one buffer software verification needs.
+
+use crate::{Error, Result};
+
+/// Length of an Ed25519 public key.
+pub const PUBLIC_KEY_LEN: usize = 32;
+/// Length of an Ed25519 signature, and of a P-256 one (`r || s`).
+pub const SIGNATURE_LEN: usize = 64;
+/// Length of a P-256 public key (SEC1 compressed point).
+pub const P256_PUBLIC_KEY_LEN: usize = 33;
+
+/// Signature algorithm of a manifest.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Algorithm {
+    #[default]
+    Ed25519,
+    /// ECDSA over NIST P-256 with SHA-256.
+    P256,
+}
+
+impl core::str::FromStr for Algorithm {
+    type Err = Error;
+
+    /// `ed25519` or `p256`.
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "ed25519" => Ok(Self::Ed25519),
+            "p256" => Ok(Self::P256),
+            _ => Err(Error::Engine("signature algorithm unknown (ed25519, p256)")),
+        }
+    }
+}
+
+impl Algorithm {
+    /// Wire id (`manifest::SIG_ALG_*`).
+    pub const fn id(self) -> u8 {
+        match self {
+            Self::Ed25519 => crate::manifest::SIG_ALG_ED25519,
+            Self::P256 => crate::manifest::SIG_ALG_P256,
+        }
+    }
+
+    /// Algorithm with wire id `id`.
+    pub const fn from_id(id: u8) -> Option<Self> {
+        match id {
+            crate::manifest::SIG_ALG_ED25519 => Some(Self::Ed25519),
+            crate::manifest::SIG_ALG_P256 => Some(Self::P256),
+            _ => None,
+        }
+    }
+
+    /// Length of this algorithm's public keys.
+    pub const fn public_key_len(self) -> usize {
+        match self {
+            Self::Ed25519 => PUBLIC_KEY_LEN,
+            Self::P256 => P256_PUBLIC_KEY_LEN,
+        }
+    }
+}
+
+/// A public key of either algorithm.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PublicKey {
+    Ed25519([u8; PUBLIC_KEY_LEN]),
+    /// SEC1 compressed point.
+    P256([u8; P256_PUBLIC_KEY_LEN]),
+}
+
+impl PublicKey {
+    /// `algorithm` key from its encoding; fails on a wrong length.
+    pub fn from_bytes(algorithm: Algorithm, bytes: &[u8]) -> Result<Self> {
+        let key = match algorithm {
+            Algorithm::Ed25519 => bytes.try_into().map(Self::Ed25519),
+            Algorithm::P256 => bytes.try_into().map(Self::P256),
+        };
+        key.map_err(|_| Error::Engine("bad pubkey"))
+    }
+
+    pub fn algorithm(&self) -> Algorithm {
+        match self {
+            Self::Ed25519(_) => Algorithm::Ed25519,
+            Self::P256(_) => Algorithm::P256,
+        }
+    }
+
+    pub fn as_bytes(&self) -> &[u8] {
+        match self {
+            Self::Ed25519(key) => key,
+            Self::P256(key) => key,
+        }
+    }
+
+    /// Checks `signature` by this key with `verifier`.
+    pub fn verify(
+        &self,
+        verifier: &dyn Verifier,
+        message: &[&[u8]],
+        signature: &[u8; SIGNATURE_LEN],
+    ) -> Result<()> {
+        match self {
+            Self::Ed25519(key) => verifier.verify_ed25519(key, message, signature),
+            Self::P256(key) => verifier.verify_p256(key, message, signature),
+        }
+    }
+}
+
+/// A signature implementation; backends without P-256 keep the default
+/// `verify_p256`, which fails with `Error::Unsupported`.
+pub trait Verifier {
+    /// Checks `signature` by `pubkey` over the concatenation of `message`,
+    /// with the strict rules of ed25519-dalek's `verify_strict` (no small
+    /// order keys, canonical `S`).
+    fn verify_ed25519(
+        &self,
+        pubkey: &[u8; PUBLIC_KEY_LEN],
+        message: &[&[u8]],
+        signature: &[u8; SIGNATURE_LEN],
+    ) -> Result<()>;
+
+    /// Checks the ECDSA P-256 `signature` (`r || s`) by `pubkey` over the
+    /// SHA-256 digest of the concatenation of `message`.
+    fn verify_p256(
+        &self,
+        pubkey: &[u8; P256_PUBLIC_KEY_LEN],
+        message: &[&[u8]],
+        signature: &[u8; SIGNATURE_LEN],
+    ) -> Result<()> {
+        let _ = (pubkey, message, signature);
+        Err(Error::Unsupported)
+    }
+}
+
+impl<V: Verifier + ?Sized> Verifier for &V {
+    fn verify_ed25519(
+        &self,
+        pubkey: &[u8; PUBLIC_KEY_LEN],
+        message: &[&[u8]],
+        signature: &[u8; SIGNATURE_LEN],
+    ) -> Result<()> {
+        (**self).verify_ed25519(pubkey, message, signature)
+    }
+
+    fn verify_p256(
+        &self,
+        pubkey: &[u8; P256_PUBLIC_KEY_LEN],
+        message: &[&[u8]],
+        signature: &[u8; SIGNATURE_LEN],
+    ) -> Result<()> {
+        (**self).verify_p256(pubkey, message, signature)
+    }
+}
+
+/// ed25519-dalek (`verify-ed25519`) and p256 (`verify-p256`) in software;
+/// an algorithm whose feature is off fails with `Error::Unsupported`.
+#[cfg(any(feature = "verify-ed25519", feature = "verify-p256"))]
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SoftwareVerifier;
+
+#[cfg(any(feature = "verify-ed25519", feature = "verify-p256"))]
+impl Verifier for SoftwareVerifier {
+    #[cfg(feature = "verify-ed25519")]
+    fn verify_ed25519(
+        &self,
+        pubkey: &[u8; PUBLIC_KEY_LEN],
+        message: &[&[u8]],
+        signature: &[u8; SIGNATURE_LEN],
+    ) -> Result<()> {
+        use ed25519_dalek::{Signature, VerifyingKey};
+
+        let vk = VerifyingKey::from_bytes(pubkey).map_err(|_| Error::Engine("bad pubkey"))?;
+        let signature = Signature::from_bytes(signature);
+        let verified = match message {
+            [whole] => vk.verify_strict(whole, &signature),
+            parts => vk.verify_strict(&parts.concat(), &signature),
+        };
+        verified.map_err(|_| Error::Engine("signature verify failed"))
+    }
+
+    #[cfg(not(feature = "verify-ed25519"))]
+    fn verify_ed25519(
+        &self,
+        _pubkey: &[u8; PUBLIC_KEY_LEN],
+        _message: &[&[u8]],
+        _signature: &[u8; SIGNATURE_LEN],
+    ) -> Result<()> {
+        Err(Error::Unsupported)
+    }
+
+    #[cfg(feature = "verify-p256")]
+    fn verify_p256(
+        &self,
+        pubkey: &[u8; P256_PUBLIC_KEY_LEN],
+        message: &[&[u8]],
+        signature: &[u8; SIGNATURE_LEN],
+    ) -> Result<()> {
+        use p256::ecdsa::signature::DigestVerifier;
+        use p256::ecdsa::{Signature, VerifyingKey};
+        use sha2::{Digest, Sha256};
+
+        let vk = VerifyingKey::from_sec1_bytes(pubkey).map_err(|_| Error::Engine("bad pubkey"))?;
+        let signature = Signature::from_slice(signature)
+            .map_err(|_| Error::Engine("signature verify failed"))?;
+        // The digest takes the parts as they are; no copy into one buffer.
+        let digest = message
+            .iter()
+            .fold(Sha256::new(), |digest, part| digest.chain_update(part));
+        vk.verify_digest(digest, &signature)
+            .map_err(|_| Error::Engine("signature verify failed"))
+    }
+}
+
+/// The verifier used when none is plugged in: `SoftwareVerifier` with
+/// `verify-ed25519` or `verify-p256`, otherwise none, and signatures cannot
+/// be checked.
+pub fn default_verifier() -> Option<&'static dyn Verifier> {
+    #[cfg(any(feature = "verify-ed25519", feature = "verify-p256"))]
+    return Some(&SoftwareVerifier);
+    #[cfg(not(any(feature = "verify-ed25519", feature = "verify-p256")))]
+    None
+}
+
+#[cfg(all(test, feature = "std"))]
+mod tests {
+    use super::*;
+    use crate::manifest::{self, Manifest};
+    use crate::Error;
+    use core::cell::Cell;
+
+    /// Stands in for a crypto engine: a signature is valid when it starts
+    /// with the key's first byte; records how the message was handed over.
+    #[derive(Default)]
+    struct Engine {
+        parts: Cell<usize>,
+        streamed: Cell<usize>,
+    }
+
+    impl Verifier for Engine {
+        fn verify_ed25519(
+            &self,
+            pubkey: &[u8; PUBLIC_KEY_LEN],
+            message: &[&[u8]],
+            signature: &[u8; SIGNATURE_LEN],
+        ) -> Result<()> {
+            self.parts.set(message.len());
+            self.streamed
+                .set(message.iter().map(|part| part.len()).sum());
+            if signature[0] == pubkey[0] {
+                Ok(())
+            } else {
+                Err(Error::Engine("signature verify failed"))
+            }
+        }
+
+        fn verify_p256(
+            &self,
+            pubkey: &[u8; P256_PUBLIC_KEY_LEN],
+            message: &[&[u8]],
+            signature: &[u8; SIGNATURE_LEN],
+        ) -> Result<()> {
+            self.verify_ed25519(pubkey[1..].try_into().unwrap(), message, signature)
+        }
+    }
+
+    #[test]
+    fn manifests_verify_through_a_plugged_in_backend() {
+        let module = b"\0asm\x01\0\0\0";
+        let blob = manifest::encode(4, "main", module, 0, 1, Some([7; 64])).unwrap();
+        let (parsed, _) = Manifest::parse(&blob).unwrap();
+        let engine = Engine::default();
+
+        assert_eq!(
+            manifest::verify_with(&engine, &parsed, module, &[7; 32]),
+            Ok(())
+        );
+        // Header and module arrive as they are stored, without a copy.
+        assert_eq!(engine.parts.get(), 2);
+        assert_eq!(engine.streamed.get(), blob.len() - SIGNATURE_LEN);
+        assert!(manifest::verify_with(&engine, &parsed, module, &[8; 32]).is_err());
+
+        #[cfg(feature = "unstable")]
+        {
+            use crate::audit::{check, Issue, Keyring};
+            let keyring = Keyring::new()
+                .with_key([7; 32])
+                .require_signatures()
+                .with_verifier(Engine::default());
+            assert_eq!(check(4, &blob, &keyring), Ok(()));
+            let other = Keyring::new()
+                .with_key([8; 32])
+                .with_verifier(Engine::default());
+            assert_eq!(check(4, &blob, &other), Err(Issue::Untrusted));
+
+            // P-256 manifests are checked against the P-256 keys only.
+            let mut ext = alloc::vec::Vec::new();
+            manifest::push_extension(
+                &mut ext,
+                manifest::EXT_SIGNATURE_ALG,
+                &[manifest::SIG_ALG_P256],
+            )
+            .unwrap();
+            let p256 = manifest::encode_ext(4, "main", module, 0, 1, &ext, Some([7; 64])).unwrap();
+            assert_eq!(check(4, &p256, &keyring), Err(Issue::Untrusted));
+            let mut key = [7; P256_PUBLIC_KEY_LEN];
+            key[0] = 0x02;
+            let keyring = Keyring::new()
+                .with_p256_key(key)
+                .with_verifier(Engine::default());
+            assert_eq!(check(4, &p256, &keyring), Ok(()));
+        }
+    }
+}